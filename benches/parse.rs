@@ -0,0 +1,55 @@
+//! Parser throughput on generated memo files of a few megabytes.
+//!
+//! `cargo bench --bench parse`
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use fmemo::incremental::IncrementalParser;
+use fmemo::parser::{ParseOptions, parse_document};
+
+/// A memo file of `sections` nested sections, each with text, a code block, a diagram and math
+fn generate(sections: usize) -> String {
+    let mut content = String::from("---\ncreated: 2024-01-01\n---\n# Notes\n");
+    for i in 0..sections {
+        content.push_str(&format!(
+            "## Section {i}\n<desc>About {i}</desc>\nSome *text* with [[link-{i}]] and `code`.\n\n\
+             - item\n  - nested\n\n```rust\nfn f{i}() {{}}\n```\n```mermaid\ngraph TD\n```\n\
+             $$\nx_{i}\n$$\n### Detail {i}\n<status>open</status>\nMore text.\n"
+        ));
+    }
+    content
+}
+
+fn parse(c: &mut Criterion) {
+    let options = ParseOptions::default();
+    let mut group = c.benchmark_group("parse_document");
+    group.sample_size(10);
+    for sections in [5_000, 20_000] {
+        let content = generate(sections);
+        group.throughput(Throughput::Bytes(content.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(content.len()),
+            &content,
+            |b, content| b.iter(|| parse_document(content, &options)),
+        );
+    }
+    group.finish();
+}
+
+/// Re-parsing after a one-line edit, as the watcher does on every save
+fn reparse(c: &mut Criterion) {
+    let content = generate(20_000);
+    let edited = content.replacen("Section 10000\n", "Section 10000 (edited)\n", 1);
+    let mut parser = IncrementalParser::new(ParseOptions::default());
+    let mut group = c.benchmark_group("incremental");
+    group.sample_size(10);
+    group.bench_function("edit", |b| {
+        b.iter(|| {
+            parser.parse(&content);
+            parser.parse(&edited)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse, reparse);
+criterion_main!(benches);
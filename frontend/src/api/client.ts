@@ -24,7 +24,8 @@ export class ApiClient {
   constructor(baseUrl: string = '') {
     // In development (Vite dev server), use relative URLs that get proxied
     // In production, use the provided baseUrl or default to current origin
-    this.baseUrl = import.meta.env.DEV ? '' : baseUrl || window.location.origin;
+    // (including the server's --base-path, taken from the <base href> it adds to the page)
+    this.baseUrl = import.meta.env.DEV ? '' : baseUrl || new URL('.', document.baseURI).href.replace(/\/$/, '');
   }
 
   async getDirectoryTree(path: string = ''): Promise<ApiResponse<ApiDirectoryTree>> {
@@ -6,6 +6,8 @@ interface WebSocketMessage {
   file_path?: string;
   html?: string;
   memos?: any[];
+  warnings?: any[];
+  issues?: any[];
   tree?: any;
 }
 
@@ -17,7 +19,11 @@ const defaultWsUrl = (() => {
   // In dev, default to backend port 3030
   if ((import.meta as any)?.env?.DEV) return 'ws://localhost:3030/ws';
   // In production (served by the same server), use current host
-  if (typeof window !== 'undefined') return `ws://${window.location.host}/ws`;
+  // (below the server's --base-path, taken from the <base href> it adds to the page)
+  if (typeof window !== 'undefined') {
+    const base = new URL('.', document.baseURI);
+    return `ws://${base.host}${base.pathname}ws`;
+  }
   return 'ws://localhost:3030/ws';
 })();
 
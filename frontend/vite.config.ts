@@ -11,6 +11,8 @@ const dirname = typeof __dirname !== 'undefined' ? __dirname : path.dirname(file
 
 // More info at: https://storybook.js.org/docs/next/writing-tests/integrations/vitest-addon
 export default defineConfig({
+  // Relative asset URLs, resolved against the <base href> the server adds for --base-path
+  base: './',
   plugins: [react()],
   server: {
     proxy: {
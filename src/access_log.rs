@@ -0,0 +1,184 @@
+//! Access log of the HTTP server, one line per request, in the Apache/nginx combined log
+//! format or as JSON, so the traffic can go into the usual log pipelines.
+//!
+//! Combined lines end with the request duration in seconds, like nginx's `$request_time`,
+//! and the request ID.
+//! The response size isn't known to the logger and is written as `-`.
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use serde_json::json;
+
+/// Line format of the access log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// `host - - [time] "request" status - "referer" "user agent" duration request-id`
+    #[default]
+    Combined,
+    /// One JSON object per line
+    Json,
+}
+
+/// What is logged about a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessEntry {
+    pub time: DateTime<Local>,
+    pub remote_addr: Option<SocketAddr>,
+    pub method: String,
+    pub path: String,
+    /// e.g. `HTTP/1.1`
+    pub version: String,
+    pub status: u16,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub duration: Duration,
+    /// See `crate::request_id`
+    pub request_id: Option<String>,
+}
+
+impl AccessEntry {
+    pub fn from_info(info: &warp::log::Info<'_>) -> Self {
+        Self {
+            time: Local::now(),
+            remote_addr: info.remote_addr(),
+            method: info.method().to_string(),
+            path: info.path().to_string(),
+            version: format!("{:?}", info.version()),
+            status: info.status().as_u16(),
+            referer: info.referer().map(str::to_string),
+            user_agent: info.user_agent().map(str::to_string),
+            duration: info.elapsed(),
+            request_id: None,
+        }
+    }
+
+    /// The entry as a line in `format`, without the line break
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        match format {
+            AccessLogFormat::Combined => {
+                let quoted = |value: &Option<String>| {
+                    value
+                        .as_deref()
+                        .map(|value| value.replace('\\', "\\\\").replace('"', "\\\""))
+                        .unwrap_or_else(|| "-".to_string())
+                };
+                format!(
+                    "{} - - [{}] \"{} {} {}\" {} - \"{}\" \"{}\" {:.3} {}",
+                    self.remote_addr
+                        .map(|addr| addr.ip().to_string())
+                        .unwrap_or_else(|| "-".to_string()),
+                    self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+                    self.method,
+                    self.path,
+                    self.version,
+                    self.status,
+                    quoted(&self.referer),
+                    quoted(&self.user_agent),
+                    self.duration.as_secs_f64(),
+                    self.request_id.as_deref().unwrap_or("-")
+                )
+            }
+            AccessLogFormat::Json => json!({
+                "time": self.time.to_rfc3339(),
+                "remote_addr": self.remote_addr.map(|addr| addr.ip().to_string()),
+                "method": self.method,
+                "path": self.path,
+                "version": self.version,
+                "status": self.status,
+                "referer": self.referer,
+                "user_agent": self.user_agent,
+                "duration_ms": self.duration.as_secs_f64() * 1000.0,
+                "request_id": self.request_id,
+            })
+            .to_string(),
+        }
+    }
+}
+
+/// Where and how requests are logged; clones write to the same place
+#[derive(Clone)]
+pub struct AccessLog {
+    format: AccessLogFormat,
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl std::fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessLog")
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+impl AccessLog {
+    /// Log to any writer, e.g. a buffer in tests
+    pub fn new<W: Write + Send + 'static>(out: W, format: AccessLogFormat) -> Self {
+        Self {
+            format,
+            out: Arc::new(Mutex::new(Box::new(out))),
+        }
+    }
+
+    pub fn stdout(format: AccessLogFormat) -> Self {
+        Self::new(std::io::stdout(), format)
+    }
+
+    /// Append to a file, creating it when missing
+    pub fn file<P: AsRef<Path>>(path: P, format: AccessLogFormat) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self::new(file, format))
+    }
+
+    pub fn record(&self, entry: &AccessEntry) {
+        let line = entry.format(self.format);
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        // A full disk or closed stdout shouldn't take requests down with it
+        let _ = writeln!(out, "{}", line).and_then(|_| out.flush());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessEntry, AccessLogFormat};
+    use chrono::TimeZone;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format() {
+        let entry = AccessEntry {
+            time: chrono::Local
+                .with_ymd_and_hms(2024, 3, 5, 14, 7, 9)
+                .unwrap(),
+            remote_addr: Some("192.168.1.20:51234".parse().unwrap()),
+            method: "GET".to_string(),
+            path: "/api/root".to_string(),
+            version: "HTTP/1.1".to_string(),
+            status: 200,
+            referer: None,
+            user_agent: Some("curl/8.0 \"quoted\"".to_string()),
+            duration: Duration::from_millis(3),
+            request_id: Some("abc123".to_string()),
+        };
+
+        let line = entry.format(AccessLogFormat::Combined);
+        assert!(line.starts_with("192.168.1.20 - - [05/Mar/2024:14:07:09 "));
+        assert!(line.ends_with(
+            "] \"GET /api/root HTTP/1.1\" 200 - \"-\" \"curl/8.0 \\\"quoted\\\"\" 0.003 abc123"
+        ));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&entry.format(AccessLogFormat::Json)).unwrap();
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["remote_addr"], "192.168.1.20");
+        assert_eq!(json["referer"], serde_json::Value::Null);
+        assert_eq!(json["duration_ms"], 3.0);
+    }
+}
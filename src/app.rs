@@ -0,0 +1,1511 @@
+//! Embeddable fmemo server.
+//!
+//! ```no_run
+//! # async fn run() -> std::io::Result<()> {
+//! fmemo::FmemoServer::new("./notes")
+//!     .port(8080)
+//!     .auth("secret-token")
+//!     .run()
+//!     .await
+//! # }
+//! ```
+
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use warp::Filter;
+use warp::filters::BoxedFilter;
+
+use crate::access_log::{AccessEntry, AccessLog};
+use crate::collab::Collab;
+use crate::config::Permission;
+use crate::indexer::Indexer;
+use crate::plugin::{Plugin, Plugins};
+use crate::presence::Presence;
+use crate::request_id;
+use crate::server::{
+    ApiOptions, WatcherOptions, WebSocketClients, WebSocketOptions, create_api_routes_with_options,
+    create_index_routes, create_sitemap_route, create_static_routes_with_base_path,
+    create_tag_routes, create_user_routes, create_websocket_route_with_options,
+    start_directory_watcher_with_options,
+};
+use crate::tags::TagIndex;
+use crate::users::Users;
+use crate::views::{self, ViewCounter};
+use crate::watcher::WatcherHealth;
+
+/// What the server hosts besides the API and WebSocket
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frontend {
+    /// API and WebSocket only
+    None,
+    /// A built frontend (`frontend/dist`) on disk
+    Dir(PathBuf),
+    /// The frontend compiled into the binary
+    #[cfg(feature = "embed_frontend")]
+    Embedded,
+}
+
+/// Builder for an fmemo server serving the memos below `root`
+#[derive(Debug, Clone)]
+pub struct FmemoServer {
+    root: PathBuf,
+    host: IpAddr,
+    port: u16,
+    frontend: Frontend,
+    /// Prefix every route is mounted under, e.g. `/notes`; empty for `/`
+    base_path: String,
+    auth_token: Option<String>,
+    watch: bool,
+    git_autocommit: bool,
+    mdns: bool,
+    access_log: Option<AccessLog>,
+    publish: bool,
+    plugins: Plugins,
+    clients: WebSocketClients,
+    tag_index: TagIndex,
+    views: ViewCounter,
+    indexer: Indexer,
+    collab: Collab,
+    presence: Presence,
+    watcher_health: WatcherHealth,
+}
+
+/// `/notes` for `notes`, `/notes/` or `//notes`; empty for `/`
+fn normalize_base_path(base_path: &str) -> String {
+    let segments: Vec<&str> = base_path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    segments
+        .iter()
+        .map(|segment| format!("/{}", segment))
+        .collect()
+}
+
+/// Rejection for requests without the configured bearer token or a session
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// Rejection for changes by a read-only account
+#[derive(Debug)]
+struct ReadOnly;
+
+impl warp::reject::Reject for ReadOnly {}
+
+/// Rejection for admin-only requests (API tokens, the audit log) by others
+#[derive(Debug)]
+struct AdminOnly;
+
+impl warp::reject::Reject for AdminOnly {}
+
+/// Rejection for changes to a server in publish mode
+#[derive(Debug)]
+struct Published;
+
+impl warp::reject::Reject for Published {}
+
+impl FmemoServer {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        let root = root.into();
+        let clients: WebSocketClients = Arc::new(Mutex::new(Vec::new()));
+        Self {
+            root: root.clone(),
+            host: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 3030,
+            frontend: Frontend::None,
+            base_path: String::new(),
+            auth_token: None,
+            watch: true,
+            git_autocommit: false,
+            mdns: false,
+            access_log: None,
+            publish: false,
+            plugins: Plugins::default(),
+            clients: clients.clone(),
+            tag_index: TagIndex::new(root.clone()),
+            views: ViewCounter::new(root.clone()),
+            indexer: Indexer::new(root.clone()).clients(clients),
+            collab: Collab::new(root.clone()),
+            presence: Presence::default(),
+            watcher_health: WatcherHealth::default(),
+        }
+    }
+
+    pub fn host(mut self, host: IpAddr) -> Self {
+        self.host = host;
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn frontend(mut self, frontend: Frontend) -> Self {
+        self.frontend = frontend;
+        self
+    }
+
+    /// Mount every route (API, WebSocket and frontend) under a path prefix such as `/notes`,
+    /// for a reverse proxy hosting several apps on one domain. Surrounding slashes don't matter.
+    pub fn base_path<S: AsRef<str>>(mut self, base_path: S) -> Self {
+        self.base_path = normalize_base_path(base_path.as_ref());
+        self
+    }
+
+    /// Require `Authorization: Bearer <token>` (or `?token=<token>` for WebSocket
+    /// clients and calendar subscriptions) on `/api`, `/ws` and `/calendar.ics`
+    pub fn auth<S: Into<String>>(mut self, token: S) -> Self {
+        self.auth_token = Some(token.into());
+        self
+    }
+
+    /// Push file changes to WebSocket clients (on by default)
+    pub fn watch(mut self, watch: bool) -> Self {
+        self.watch = watch;
+        self
+    }
+
+    /// Commit every memo change the watcher sees to the root's git repository.
+    /// The root has to be inside a git work tree.
+    pub fn git_autocommit(mut self, git_autocommit: bool) -> Self {
+        self.git_autocommit = git_autocommit;
+        self
+    }
+
+    /// Announce the server on the local network as `_fmemo._tcp` over mDNS. Only
+    /// useful with a host other devices can reach (e.g. `0.0.0.0`).
+    pub fn mdns(mut self, mdns: bool) -> Self {
+        self.mdns = mdns;
+        self
+    }
+
+    /// Log every request, e.g. `AccessLog::file("access.log", AccessLogFormat::Json)`.
+    /// Nothing is logged without one.
+    pub fn access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    /// Serve the memos as a public, read-only site: `<private>` regions are cut out of
+    /// every file read, and requests other than `GET` and `HEAD` (including WebSocket edits)
+    /// are refused
+    pub fn publish(mut self, publish: bool) -> Self {
+        self.publish = publish;
+        self.tag_index = self.tag_index.publish(publish);
+        self.indexer = self.indexer.publish(publish);
+        self
+    }
+
+    /// Register a plugin. Plugins apply in the order they're added; with the
+    /// `wasm-plugins` feature, those in `.fmemo/plugins` follow when the server binds.
+    pub fn plugin<P: Plugin + 'static>(mut self, plugin: P) -> Self {
+        self.plugins.add(Arc::new(plugin));
+        self
+    }
+
+    pub fn root(&self) -> &PathBuf {
+        &self.root
+    }
+
+    /// Connected WebSocket clients, e.g. to broadcast custom messages
+    pub fn clients(&self) -> WebSocketClients {
+        self.clients.clone()
+    }
+
+    /// All routes of the server, for mounting into a larger warp application
+    pub fn routes(&self) -> BoxedFilter<(Box<dyn warp::Reply>,)> {
+        // A broken config already fails `bind`
+        let users = Users::load(&self.root).unwrap_or_default();
+        let api = create_user_routes(users.clone())
+            .or(create_index_routes(self.indexer.clone()))
+            .or(create_api_routes_with_options(
+                self.root.clone(),
+                ApiOptions {
+                    plugins: self.plugins.clone(),
+                    users: users.clone(),
+                    views: Some(self.views.clone()),
+                    clients: Some(self.clients.clone()),
+                    watcher: Some(self.watcher_health.clone()),
+                    publish: self.publish,
+                },
+            ))
+            .or(create_tag_routes(self.tag_index.clone()))
+            .or(create_websocket_route_with_options(
+                self.clients.clone(),
+                WebSocketOptions {
+                    collab: (!self.publish).then(|| self.collab.clone()),
+                    presence: Some(self.presence.clone()),
+                    users: Some(users.clone()),
+                },
+            ))
+            .map(|reply| Box::new(reply) as Box<dyn warp::Reply>)
+            .boxed();
+        // Only a published site is meant to be indexed
+        let api = match self.publish {
+            true => api
+                .or(create_sitemap_route(
+                    self.root.clone(),
+                    self.base_path.clone(),
+                ))
+                .map(|reply| Box::new(reply) as Box<dyn warp::Reply>)
+                .boxed(),
+            false => api,
+        };
+        let api = match self.plugins.routes() {
+            Some(plugin_routes) => plugin_routes.or(api).unify().boxed(),
+            None => api,
+        };
+        let routes = match &self.frontend {
+            Frontend::None => api,
+            Frontend::Dir(dist_dir) => api
+                .or(create_static_routes_with_base_path(
+                    dist_dir.clone(),
+                    self.base_path.clone(),
+                ))
+                .map(|reply| Box::new(reply) as Box<dyn warp::Reply>)
+                .boxed(),
+            #[cfg(feature = "embed_frontend")]
+            Frontend::Embedded => api
+                .or(
+                    crate::server::embedded::create_embedded_static_routes_with_base_path(
+                        self.base_path.clone(),
+                    ),
+                )
+                .map(|reply| Box::new(reply) as Box<dyn warp::Reply>)
+                .boxed(),
+        };
+
+        let access_log = self.access_log.clone();
+        let prefix = self
+            .base_path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .fold(warp::any().boxed(), |prefix, segment| {
+                prefix.and(warp::path(segment.to_string())).boxed()
+            });
+        let routes = prefix
+            .and(require_auth(
+                self.auth_token.clone(),
+                users,
+                self.base_path.clone(),
+            ))
+            .and(read_only_when_published(self.publish))
+            .and(routes)
+            .map(warp::Reply::into_response)
+            .recover(handle_rejection)
+            .unify();
+        request_id::request_id()
+            .and(routes)
+            .then(|id, response| request_id::attach(response, id))
+            .with(warp::log::custom(move |info| {
+                let request_id = request_id::take_finished();
+                if let Some(access_log) = &access_log {
+                    let mut entry = AccessEntry::from_info(&info);
+                    entry.request_id = request_id;
+                    access_log.record(&entry);
+                }
+            }))
+            .map(|reply| Box::new(reply) as Box<dyn warp::Reply>)
+            .boxed()
+    }
+
+    /// Validate the root, then start the watcher and the first index pass
+    fn prepare(&mut self) -> std::io::Result<()> {
+        if !self.root.is_dir() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!(
+                    "Root directory '{}' does not exist or is not a directory",
+                    self.root.display()
+                ),
+            ));
+        }
+        if let Frontend::Dir(dist_dir) = &self.frontend
+            && !dist_dir.is_dir()
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!(
+                    "Frontend directory '{}' does not exist or is not a directory",
+                    dist_dir.display()
+                ),
+            ));
+        }
+        if self.git_autocommit && !crate::git::is_repository(&self.root) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Root directory '{}' is not inside a git repository",
+                    self.root.display()
+                ),
+            ));
+        }
+        self.plugins.load_installed(&self.root)?;
+        let config = crate::config::load_config(&self.root)?;
+        if let Some(embedder) = crate::embeddings::ApiEmbedder::new(config.embeddings) {
+            let embeddings = crate::embeddings::EmbeddingIndex::start(Arc::new(embedder));
+            self.indexer = self.indexer.clone().embeddings(embeddings);
+        }
+        let options = WatcherOptions {
+            git_autocommit: self.git_autocommit,
+            webhooks: config.webhooks,
+            chat: config.chat,
+            plugins: self.plugins.clone(),
+            hooks: config.hooks,
+            tag_index: Some(self.tag_index.clone()),
+            indexer: Some(self.indexer.clone()),
+            coalesce: std::time::Duration::from_millis(config.watch.coalesce_ms),
+            health: Some(self.watcher_health.clone()),
+            publish: self.publish,
+        };
+        let notifies = !options.webhooks.is_empty()
+            || !options.chat.is_empty()
+            || options.hooks != Default::default();
+        if (self.watch || self.git_autocommit || notifies)
+            && let Err(e) =
+                start_directory_watcher_with_options(&self.root, self.clients.clone(), options)
+        {
+            eprintln!("Warning: Failed to start directory watcher: {}", e);
+        }
+
+        self.indexer.spawn_reindex();
+        self.views.start_flushing(views::FLUSH_INTERVAL);
+        Ok(())
+    }
+
+    /// Validate the root, start the watcher and bind the listening socket.
+    /// Returns the bound address (useful with port 0) and the future that serves requests.
+    pub fn bind(mut self) -> std::io::Result<(SocketAddr, impl Future<Output = ()>)> {
+        self.prepare()?;
+        let (addr, server) = warp::serve(self.routes())
+            .try_bind_ephemeral((self.host, self.port))
+            .map_err(std::io::Error::other)?;
+        let advertisement = if !self.mdns {
+            None
+        } else if self.host.is_loopback() {
+            eprintln!(
+                "Warning: Not announcing over mDNS; {} can't be reached from other devices",
+                self.host
+            );
+            None
+        } else {
+            match crate::mdns::advertise(&self.root, addr) {
+                Ok(advertisement) => Some(advertisement),
+                Err(e) => {
+                    eprintln!("Warning: Failed to announce over mDNS: {}", e);
+                    None
+                }
+            }
+        };
+        Ok((addr, async move {
+            // Withdrawn when the server stops
+            let _advertisement = advertisement;
+            server.await
+        }))
+    }
+
+    /// Like `bind`, but listen on a Unix domain socket at `path` instead of a TCP port, e.g.
+    /// behind a reverse proxy on the same host. Access is up to the socket file's permissions
+    /// (the umask's). A socket file left at `path` by an earlier run is replaced; the file is
+    /// removed when the server stops.
+    #[cfg(unix)]
+    pub fn bind_unix<P: Into<PathBuf>>(
+        mut self,
+        path: P,
+    ) -> std::io::Result<impl Future<Output = ()>> {
+        use std::os::unix::fs::FileTypeExt;
+
+        let path = path.into();
+        if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+            if !metadata.file_type().is_socket() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    format!("'{}' exists and is not a socket", path.display()),
+                ));
+            }
+            if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AddrInUse,
+                    format!("'{}' is in use by another server", path.display()),
+                ));
+            }
+            std::fs::remove_file(&path)?;
+        }
+        self.prepare()?;
+
+        let listener = tokio::net::UnixListener::bind(&path)?;
+        let incoming = futures_util::stream::unfold(listener, |listener| async move {
+            let stream = listener.accept().await.map(|(stream, _)| stream);
+            Some((stream, listener))
+        });
+        let server = warp::serve(self.routes()).serve_incoming(incoming);
+        let socket = SocketFile(path);
+        Ok(async move {
+            let _socket = socket;
+            server.await
+        })
+    }
+
+    /// Serve until the process exits
+    pub async fn run(self) -> std::io::Result<()> {
+        let (_, server) = self.bind()?;
+        server.await;
+        Ok(())
+    }
+}
+
+/// Removes the socket file of `bind_unix` when the server stops
+#[cfg(unix)]
+struct SocketFile(PathBuf);
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Check the bearer token, an API token or, with local accounts, the session cookie on API
+/// and WebSocket requests; read-only accounts and tokens may only read (and comment), and only admins
+/// (and the `--token` holder) manage API tokens and read the audit log and the list of WebSocket
+/// clients. CORS preflights, logging in and frontend files stay public so the UI can load and ask
+/// for credentials.
+fn require_auth(
+    token: Option<String>,
+    users: Users,
+    base_path: String,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::path::full()
+        .and(warp::method())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::header::optional::<String>("cookie"))
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and_then(
+            move |path: warp::path::FullPath,
+                  method: warp::http::Method,
+                  header: Option<String>,
+                  cookie: Option<String>,
+                  query: String| {
+                let token = token.clone();
+                let users = users.clone();
+                let base_path = base_path.clone();
+                async move {
+                    if token.is_none() && !users.requires_login() {
+                        return Ok(());
+                    }
+                    let path = path
+                        .as_str()
+                        .strip_prefix(base_path.as_str())
+                        .unwrap_or(path.as_str());
+                    let protected = path.starts_with("/api")
+                        || path.starts_with("/ws")
+                        || path == "/calendar.ics";
+                    if !protected
+                        || method == warp::http::Method::OPTIONS
+                        || path == "/api/login"
+                        || path.starts_with("/api/auth/")
+                    {
+                        return Ok(());
+                    }
+                    let from_header = header
+                        .as_deref()
+                        .and_then(|value| value.strip_prefix("Bearer "));
+                    let from_query = query
+                        .split('&')
+                        .find_map(|pair| pair.strip_prefix("token="));
+                    let given = from_header.or(from_query);
+                    let permission = if let (Some(token), Some(given)) = (&token, given)
+                        && constant_time_eq(given.as_bytes(), token.as_bytes())
+                    {
+                        Some(Permission::Admin)
+                    } else {
+                        cookie
+                            .as_deref()
+                            .and_then(crate::users::session_id)
+                            .and_then(|id| users.session(id))
+                            .map(|session| session.permission)
+                            .or_else(|| given.and_then(|given| users.api_tokens().scope(given)))
+                    };
+                    match permission {
+                        None => Err(warp::reject::custom(Unauthorized)),
+                        Some(permission)
+                            if permission < Permission::Admin
+                                && (path == "/api/tokens"
+                                    || path.starts_with("/api/tokens/")
+                                    || path == "/api/audit"
+                                    || path == "/api/ws/clients") =>
+                        {
+                            Err(warp::reject::custom(AdminOnly))
+                        }
+                        Some(permission)
+                            if permission < Permission::Write
+                                && !matches!(
+                                    method,
+                                    warp::http::Method::GET | warp::http::Method::HEAD
+                                )
+                                && path != "/api/logout"
+                                // Reviewers may comment on what they can read
+                                && !(path.starts_with("/api/files/")
+                                    && path.ends_with("/comments")) =>
+                        {
+                            Err(warp::reject::custom(ReadOnly))
+                        }
+                        Some(_) => Ok(()),
+                    }
+                }
+            },
+        )
+        .untuple_one()
+}
+
+/// In publish mode, let only `GET` and `HEAD` requests through
+fn read_only_when_published(
+    publish: bool,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::method()
+        .and_then(move |method: warp::http::Method| async move {
+            if publish && !matches!(method, warp::http::Method::GET | warp::http::Method::HEAD) {
+                Err(warp::reject::custom(Published))
+            } else {
+                Ok(())
+            }
+        })
+        .untuple_one()
+}
+
+/// Every rejection as a JSON error, so it carries the request ID like other errors. The
+/// status is the one warp would give it: among several, anything beats 405 and 405 beats 404.
+async fn handle_rejection(
+    rejection: warp::Rejection,
+) -> Result<warp::reply::Response, std::convert::Infallible> {
+    use warp::http::StatusCode;
+    use warp::reject;
+
+    let (status, message) = if rejection.is_not_found() {
+        (StatusCode::NOT_FOUND, "Not found".to_string())
+    } else {
+        let found = [
+            rejection.find::<Unauthorized>().map(|_| {
+                (
+                    StatusCode::UNAUTHORIZED,
+                    "Missing or invalid token".to_string(),
+                )
+            }),
+            rejection
+                .find::<ReadOnly>()
+                .map(|_| (StatusCode::FORBIDDEN, "Read-only account".to_string())),
+            rejection
+                .find::<Published>()
+                .map(|_| (StatusCode::FORBIDDEN, "Published read-only".to_string())),
+            rejection.find::<AdminOnly>().map(|_| {
+                (
+                    StatusCode::FORBIDDEN,
+                    "Admin permission required".to_string(),
+                )
+            }),
+            rejection
+                .find::<reject::InvalidQuery>()
+                .map(|e| (StatusCode::BAD_REQUEST, e.to_string())),
+            rejection
+                .find::<reject::InvalidHeader>()
+                .map(|e| (StatusCode::BAD_REQUEST, e.to_string())),
+            rejection
+                .find::<reject::MissingHeader>()
+                .map(|e| (StatusCode::BAD_REQUEST, e.to_string())),
+            rejection
+                .find::<warp::filters::body::BodyDeserializeError>()
+                .map(|e| (StatusCode::BAD_REQUEST, e.to_string())),
+            rejection
+                .find::<reject::LengthRequired>()
+                .map(|e| (StatusCode::LENGTH_REQUIRED, e.to_string())),
+            rejection
+                .find::<reject::PayloadTooLarge>()
+                .map(|e| (StatusCode::PAYLOAD_TOO_LARGE, e.to_string())),
+            rejection
+                .find::<reject::UnsupportedMediaType>()
+                .map(|e| (StatusCode::UNSUPPORTED_MEDIA_TYPE, e.to_string())),
+            rejection
+                .find::<reject::MethodNotAllowed>()
+                .map(|e| (StatusCode::METHOD_NOT_ALLOWED, e.to_string())),
+        ];
+        let preferred = found
+            .into_iter()
+            .flatten()
+            .max_by_key(|(status, _)| (*status != StatusCode::METHOD_NOT_ALLOWED, status.as_u16()));
+        preferred.unwrap_or_else(|| {
+            eprintln!("Unhandled rejection: {:?}", rejection);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error".to_string(),
+            )
+        })
+    };
+    Ok(warp::Reply::into_response(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({"error": message})),
+        status,
+    )))
+}
+
+/// Compare without returning early, so response time doesn't leak the token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FmemoServer, Frontend};
+    use crate::access_log::{AccessLog, AccessLogFormat};
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn server(temp_dir: &TempDir) -> FmemoServer {
+        fs::write(temp_dir.path().join("notes.fmemo"), "# Notes").unwrap();
+        FmemoServer::new(temp_dir.path()).watch(false)
+    }
+
+    #[tokio::test]
+    async fn test_routes_without_auth() {
+        let temp_dir = TempDir::new().unwrap();
+        let routes = server(&temp_dir).routes();
+
+        let response = warp::test::request()
+            .path("/api/files/notes.fmemo")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_auth_token_is_required_for_api() {
+        let temp_dir = TempDir::new().unwrap();
+        let dist_dir = TempDir::new().unwrap();
+        fs::write(dist_dir.path().join("index.html"), "<html></html>").unwrap();
+        let routes = server(&temp_dir)
+            .auth("secret")
+            .frontend(Frontend::Dir(dist_dir.path().to_path_buf()))
+            .routes();
+
+        let response = warp::test::request()
+            .path("/api/files/notes.fmemo")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 401);
+
+        let response = warp::test::request()
+            .path("/api/files/notes.fmemo")
+            .header("authorization", "Bearer wrong")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 401);
+
+        let response = warp::test::request()
+            .path("/api/files/notes.fmemo")
+            .header("authorization", "Bearer secret")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+
+        let response = warp::test::request()
+            .path("/api/files/notes.fmemo?token=secret")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+
+        // Calendar apps can only pass the token in the URL
+        let response = warp::test::request()
+            .path("/calendar.ics")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 401);
+        let response = warp::test::request()
+            .path("/calendar.ics?token=secret")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+
+        // The frontend itself stays reachable
+        let response = warp::test::request().path("/").reply(&routes).await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_oidc_login_routes() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join(".fmemo")).unwrap();
+        fs::write(
+            temp_dir.path().join(".fmemo/config.toml"),
+            "[oidc]\nclient_id = \"fmemo\"\n\
+             redirect_url = \"https://notes.example.com/api/auth/oidc/callback\"\n\
+             authorization_url = \"https://id.example.com/authorize\"\n\
+             token_url = \"https://id.example.com/token\"\n\
+             userinfo_url = \"https://id.example.com/userinfo\"\n",
+        )
+        .unwrap();
+        let routes = server(&temp_dir).routes();
+
+        let response = warp::test::request()
+            .path("/api/files/notes.fmemo")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 401);
+        // The WebSocket upgrade needs a session too
+        let response = warp::test::request()
+            .path("/ws")
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 401);
+
+        let response = warp::test::request()
+            .path("/api/auth/oidc/login")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 302);
+        let location = response.headers()["location"].to_str().unwrap();
+        assert!(location.starts_with("https://id.example.com/authorize?response_type=code"));
+        let cookie = response.headers()["set-cookie"].to_str().unwrap();
+        assert!(cookie.starts_with("fmemo_oidc_state=") && cookie.contains("HttpOnly"));
+        let state = crate::oidc::state_from_cookie(cookie.split(';').next().unwrap()).unwrap();
+
+        let response = warp::test::request()
+            .path("/api/auth/oidc/callback?code=c&state=forged")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 403);
+        // A real state is refused without the cookie of the browser that started the login
+        let response = warp::test::request()
+            .path(&format!("/api/auth/oidc/callback?code=c&state={}", state))
+            .header("cookie", "fmemo_oidc_state=forged")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 403);
+        assert!(
+            response.headers()["set-cookie"]
+                .to_str()
+                .unwrap()
+                .starts_with("fmemo_oidc_state=;")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_user_sessions() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join(".fmemo")).unwrap();
+        let hash = |password| crate::users::hash_password_with(password, 10).unwrap();
+        fs::write(
+            temp_dir.path().join(".fmemo/config.toml"),
+            format!(
+                "[users.kai]\npassword = \"{}\"\npermission = \"write\"\n\n\
+                 [users.guest]\npassword = \"{}\"\n",
+                hash("kai-pass"),
+                hash("guest-pass")
+            ),
+        )
+        .unwrap();
+        let routes = server(&temp_dir).auth("secret").routes();
+        let login = |user: &str, password: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/api/login")
+                .json(&serde_json::json!({"username": user, "password": password}))
+        };
+        let cookie = |response: &warp::http::Response<warp::hyper::body::Bytes>| {
+            let header = response.headers()["set-cookie"].to_str().unwrap();
+            header.split(';').next().unwrap().to_string()
+        };
+        let save = |cookie: &str| {
+            warp::test::request()
+                .method("PUT")
+                .path("/api/file/notes.fmemo")
+                .header("cookie", cookie)
+                .json(&serde_json::json!({"content": "# Changed"}))
+        };
+
+        assert_eq!(
+            warp::test::request()
+                .path("/api/files/notes.fmemo")
+                .reply(&routes)
+                .await
+                .status(),
+            401
+        );
+        assert_eq!(login("kai", "wrong").reply(&routes).await.status(), 401);
+
+        let response = login("kai", "kai-pass").reply(&routes).await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"user": "kai", "permission": "write"})
+        );
+        let kai = cookie(&response);
+        assert_eq!(save(&kai).reply(&routes).await.status(), 200);
+
+        let guest = cookie(&login("guest", "guest-pass").reply(&routes).await);
+        let response = warp::test::request()
+            .path("/api/files/notes.fmemo")
+            .header("cookie", &guest)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+        let response = save(&guest).reply(&routes).await;
+        assert_eq!(response.status(), 403);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["error"], "Read-only account");
+        let response = warp::test::request()
+            .path("/api/me")
+            .header("cookie", &guest)
+            .reply(&routes)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["user"], "guest");
+
+        // The token still works alongside the accounts
+        let response = warp::test::request()
+            .path("/api/files/notes.fmemo?token=secret")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/logout")
+            .header("cookie", &kai)
+            .reply(&routes)
+            .await;
+        assert!(cookie(&response).ends_with('='));
+        assert_eq!(save(&kai).reply(&routes).await.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn test_api_tokens() {
+        let temp_dir = TempDir::new().unwrap();
+        let routes = server(&temp_dir).auth("secret").routes();
+        let create = |bearer: &str, scope: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/api/tokens")
+                .header("authorization", format!("Bearer {}", bearer))
+                .json(&serde_json::json!({"name": format!("{} script", scope), "scope": scope}))
+        };
+        let save = |bearer: &str| {
+            warp::test::request()
+                .method("PUT")
+                .path("/api/file/notes.fmemo")
+                .header("authorization", format!("Bearer {}", bearer))
+                .json(&serde_json::json!({"content": "# Changed"}))
+        };
+
+        let response = create("secret", "read").reply(&routes).await;
+        assert_eq!(response.status(), 201);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["scope"], "read");
+        assert!(body.get("hash").is_none());
+        let read = body["token"].as_str().unwrap().to_string();
+        let read_id = body["id"].as_str().unwrap().to_string();
+        let response = create("secret", "write").reply(&routes).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let write = body["token"].as_str().unwrap().to_string();
+
+        let response = warp::test::request()
+            .path(&format!("/api/files/notes.fmemo?token={}", read))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(save(&read).reply(&routes).await.status(), 403);
+        assert_eq!(save(&write).reply(&routes).await.status(), 200);
+        assert_eq!(save("fmemo_forged").reply(&routes).await.status(), 401);
+
+        // Only admins manage tokens
+        let response = create(&write, "admin").reply(&routes).await;
+        assert_eq!(response.status(), 403);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["error"], "Admin permission required");
+        let response = create("secret", "admin").reply(&routes).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let admin = body["token"].as_str().unwrap().to_string();
+        let response = warp::test::request()
+            .path("/api/tokens")
+            .header("authorization", format!("Bearer {}", admin))
+            .reply(&routes)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 3);
+        assert!(!String::from_utf8_lossy(response.body()).contains(&read));
+
+        let revoke = || {
+            warp::test::request()
+                .method("DELETE")
+                .path(&format!("/api/tokens/{}", read_id))
+                .header("authorization", format!("Bearer {}", admin))
+        };
+        assert_eq!(revoke().reply(&routes).await.status(), 200);
+        assert_eq!(revoke().reply(&routes).await.status(), 404);
+        let response = warp::test::request()
+            .path(&format!("/api/files/notes.fmemo?token={}", read))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 401);
+
+        // Tokens are kept across restarts
+        let routes = server(&temp_dir).auth("secret").routes();
+        assert_eq!(save(&write).reply(&routes).await.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let temp_dir = TempDir::new().unwrap();
+        let routes = server(&temp_dir).auth("secret").routes();
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/tokens")
+            .header("authorization", "Bearer secret")
+            .json(&serde_json::json!({"name": "sync", "scope": "write"}))
+            .reply(&routes)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let sync = format!("Bearer {}", body["token"].as_str().unwrap());
+
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/api/file/notes.fmemo")
+            .header("authorization", &sync)
+            .json(&serde_json::json!({"content": "# Notes\nMore\n## Child"}))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+        let response = warp::test::request()
+            .method("DELETE")
+            .path("/api/files/notes.fmemo?token=secret")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+
+        let audit = |authorization: &str| {
+            warp::test::request()
+                .path("/api/audit?path=notes.fmemo")
+                .header("authorization", authorization)
+        };
+        assert_eq!(audit(&sync).reply(&routes).await.status(), 403);
+        let response = audit("Bearer secret").reply(&routes).await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let entries = body["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["action"], "delete");
+        assert_eq!(entries[0]["actor"], "token");
+        assert_eq!(entries[1]["action"], "write");
+        assert_eq!(entries[1]["actor"], "token:sync");
+        assert_eq!(
+            entries[1]["diff"],
+            serde_json::json!({
+                "memos_added": 1, "memos_removed": 0, "memos_changed": 1,
+                "lines_added": 2, "lines_removed": 0
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ws_clients() {
+        let temp_dir = TempDir::new().unwrap();
+        let server = server(&temp_dir).auth("secret");
+        let clients = server.clients();
+        let routes = server.routes();
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut client = crate::server::WebSocketClient::new(tx);
+        client.dirs = vec!["projects".to_string()];
+        let id = client.id;
+        clients.lock().unwrap().push(client);
+        let (closed_tx, _) = tokio::sync::mpsc::unbounded_channel();
+        clients.lock().unwrap().push(closed_tx.into());
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/tokens")
+            .header("authorization", "Bearer secret")
+            .json(&serde_json::json!({"name": "sync", "scope": "write"}))
+            .reply(&routes)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let sync = format!("Bearer {}", body["token"].as_str().unwrap());
+        let list = |authorization: &str| {
+            warp::test::request()
+                .path("/api/ws/clients")
+                .header("authorization", authorization)
+        };
+        assert_eq!(list(&sync).reply(&routes).await.status(), 403);
+
+        let response = list("Bearer secret").reply(&routes).await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let listed = body["clients"].as_array().unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0]["id"], id);
+        assert_eq!(listed[0]["dirs"], serde_json::json!(["projects"]));
+        assert_eq!(listed[0]["format"], "json");
+        assert_eq!(listed[0]["compression"], "none");
+        assert_eq!(listed[0]["age_secs"], 0);
+        assert_eq!(listed[0]["open"], true);
+        assert_eq!(listed[1]["open"], false);
+    }
+
+    #[tokio::test]
+    async fn test_view_counts() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("other.fmemo"), "# Other").unwrap();
+        let routes = server(&temp_dir).routes();
+        let fetch = |path: &str| warp::test::request().path(path);
+        for path in [
+            "/api/files/notes.fmemo",
+            "/api/files/notes.fmemo?format=markdown",
+            "/api/files/other.fmemo",
+            "/api/files/missing.fmemo",
+        ] {
+            fetch(path).reply(&routes).await;
+        }
+
+        let response = fetch("/api/popular").reply(&routes).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            body["files"],
+            serde_json::json!([
+                {"path": "notes.fmemo", "views": 2},
+                {"path": "other.fmemo", "views": 1}
+            ])
+        );
+        let response = fetch("/api/popular?limit=x").reply(&routes).await;
+        assert_eq!(response.status(), 400);
+
+        let response = fetch("/api/files").reply(&routes).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let views: Vec<_> = body["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|file| (file["path"].clone(), file["views"].clone()))
+            .collect();
+        assert!(views.contains(&(serde_json::json!("notes.fmemo"), serde_json::json!(2))));
+    }
+
+    #[tokio::test]
+    async fn test_publish_mode() {
+        let temp_dir = TempDir::new().unwrap();
+        let personal = server(&temp_dir).routes();
+        fs::write(
+            temp_dir.path().join("notes.fmemo"),
+            "# Notes\nPublic\n<private>\nPhone: 555\n## Diary\nsecret\n</private>",
+        )
+        .unwrap();
+        let response = warp::test::request()
+            .path("/api/files/notes.fmemo?format=markdown")
+            .reply(&personal)
+            .await;
+        assert!(String::from_utf8_lossy(response.body()).contains("Phone: 555"));
+
+        let published = FmemoServer::new(temp_dir.path())
+            .watch(false)
+            .publish(true)
+            .routes();
+        for path in [
+            "/api/files/notes.fmemo",
+            "/api/files/notes.fmemo?format=markdown",
+            "/api/files/notes.fmemo/toc",
+            "/api/graph",
+            "/api/search?q=secret",
+        ] {
+            let response = warp::test::request().path(path).reply(&published).await;
+            assert_eq!(response.status(), 200);
+            let body = String::from_utf8_lossy(response.body());
+            assert!(!body.contains("555") && !body.contains("Diary"), "{}", body);
+        }
+        for path in ["/api/assets/notes.fmemo", "/api/assets/notes%2Efmemo"] {
+            let response = warp::test::request().path(path).reply(&published).await;
+            assert_eq!(response.status(), 404);
+        }
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/api/files/notes.fmemo")
+            .body("# Replaced")
+            .reply(&published)
+            .await;
+        assert_eq!(response.status(), 403);
+        assert!(
+            fs::read_to_string(temp_dir.path().join("notes.fmemo"))
+                .unwrap()
+                .contains("<private>")
+        );
+        // Publishing one server leaves the others on the same root as they were
+        let response = warp::test::request()
+            .path("/api/files/notes.fmemo/toc")
+            .reply(&personal)
+            .await;
+        assert!(String::from_utf8_lossy(response.body()).contains("Diary"));
+
+        let sitemap = |routes| {
+            warp::test::request()
+                .path("/sitemap.xml")
+                .header("host", "notes.example.com")
+                .reply(routes)
+        };
+        assert_eq!(sitemap(&personal).await.status(), 404);
+        let response = sitemap(&published).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/xml; charset=utf-8"
+        );
+        assert!(
+            String::from_utf8_lossy(response.body())
+                .contains("<loc>http://notes.example.com/api/files/notes.fmemo?format=html</loc>")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_base_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let dist_dir = TempDir::new().unwrap();
+        fs::write(
+            dist_dir.path().join("index.html"),
+            "<html><head><title>fmemo</title></head></html>",
+        )
+        .unwrap();
+        let routes = server(&temp_dir)
+            .auth("secret")
+            .base_path("notes/")
+            .frontend(Frontend::Dir(dist_dir.path().to_path_buf()))
+            .routes();
+
+        let response = warp::test::request()
+            .path("/notes/api/files/notes.fmemo")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 401);
+        let response = warp::test::request()
+            .path("/notes/api/files/notes.fmemo?token=secret")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+        let response = warp::test::request()
+            .path("/api/files/notes.fmemo?token=secret")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 404);
+
+        // The frontend resolves its URLs below the prefix, from any page
+        for path in ["/notes", "/notes/", "/notes/some/page"] {
+            let response = warp::test::request().path(path).reply(&routes).await;
+            assert_eq!(response.status(), 200);
+            let html = String::from_utf8_lossy(response.body()).to_string();
+            assert!(html.contains("<head>\n    <base href=\"/notes/\"><title>"));
+        }
+    }
+
+    /// Log lines written by the server
+    #[derive(Clone, Default)]
+    struct Lines(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_access_log() {
+        let temp_dir = TempDir::new().unwrap();
+        let lines = Lines::default();
+        let routes = server(&temp_dir)
+            .access_log(AccessLog::new(lines.clone(), AccessLogFormat::Json))
+            .routes();
+
+        warp::test::request()
+            .path("/api/files/notes.fmemo")
+            .header("user-agent", "test-agent")
+            .reply(&routes)
+            .await;
+        warp::test::request()
+            .path("/api/files/missing.fmemo")
+            .reply(&routes)
+            .await;
+
+        let log = String::from_utf8(lines.0.lock().unwrap().clone()).unwrap();
+        let entries: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["path"], "/api/files/notes.fmemo");
+        assert_eq!(entries[0]["status"], 200);
+        assert_eq!(entries[0]["user_agent"], "test-agent");
+        assert_eq!(entries[1]["status"], 404);
+    }
+
+    #[tokio::test]
+    async fn test_request_id() {
+        let temp_dir = TempDir::new().unwrap();
+        let lines = Lines::default();
+        let routes = server(&temp_dir)
+            .auth("secret")
+            .access_log(AccessLog::new(lines.clone(), AccessLogFormat::Json))
+            .routes();
+
+        let response = warp::test::request()
+            .path("/api/files/notes.fmemo")
+            .header("x-request-id", "from-client.1")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.headers()["x-request-id"], "from-client.1");
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["error"], "Missing or invalid token");
+        assert_eq!(body["request_id"], "from-client.1");
+
+        // Unusable IDs are replaced, and rejections become JSON errors too
+        let response = warp::test::request()
+            .path("/api/nothing-here?token=secret")
+            .header("x-request-id", "has spaces")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 404);
+        let id = response.headers()["x-request-id"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(id.len(), 16);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["request_id"], id.as_str());
+
+        let response = warp::test::request()
+            .path("/api/files/notes.fmemo?token=secret")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert!(response.headers().contains_key("x-request-id"));
+
+        let log = String::from_utf8(lines.0.lock().unwrap().clone()).unwrap();
+        let ids: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<serde_json::Value>(line).unwrap()["request_id"].clone()
+            })
+            .collect();
+        assert_eq!(ids[0], "from-client.1");
+        assert_eq!(ids[1], id.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_bind_serves_on_ephemeral_port() {
+        let temp_dir = TempDir::new().unwrap();
+        let (addr, serve) = server(&temp_dir).port(0).bind().unwrap();
+        tokio::spawn(serve);
+
+        let response = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await;
+        assert!(response.is_ok());
+    }
+
+    async fn recv_json(client: &mut warp::test::WsClient) -> serde_json::Value {
+        let message = client.recv().await.unwrap();
+        serde_json::from_str(message.to_str().unwrap()).unwrap()
+    }
+
+    /// Connect to `/ws` and read the hello and presence messages every client gets first
+    async fn connect(
+        routes: &warp::filters::BoxedFilter<(Box<dyn warp::Reply>,)>,
+    ) -> warp::test::WsClient {
+        let mut client = warp::test::ws()
+            .path("/ws")
+            .handshake(routes.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            recv_json(&mut client).await,
+            serde_json::json!({"type": "hello", "protocol": crate::schema::WS_PROTOCOL_VERSION})
+        );
+        assert_eq!(recv_json(&mut client).await["type"], "presence");
+        client
+    }
+
+    #[tokio::test]
+    async fn test_collaborative_editing_over_websocket() {
+        let temp_dir = TempDir::new().unwrap();
+        let routes = server(&temp_dir).routes();
+        let mut first = connect(&routes).await;
+        let mut second = connect(&routes).await;
+        let join = r#"{"type": "edit_join", "path": "notes.fmemo"}"#;
+        first.send_text(join).await;
+        first.recv().await.unwrap();
+        second.send_text(join).await;
+        second.recv().await.unwrap();
+
+        first
+            .send_text(r#"{"type": "edit", "path": "notes.fmemo", "revision": 0, "ops": [7, "!"]}"#)
+            .await;
+        let message = recv_json(&mut second).await;
+        assert_eq!(message["ops"], serde_json::json!([7, "!"]));
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("notes.fmemo")).unwrap(),
+            "# Notes!"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_websocket_messages() {
+        let temp_dir = TempDir::new().unwrap();
+        let routes = server(&temp_dir).routes();
+        let mut client = connect(&routes).await;
+        // Unknown types are ignored, known ones that don't parse are answered
+        client.send_text(r#"{"type": "from_the_future"}"#).await;
+        client
+            .send_text(r#"{"type": "edit", "path": "notes.fmemo"}"#)
+            .await;
+        let message = recv_json(&mut client).await;
+        assert_eq!(message["type"], "error");
+        assert!(message["error"].as_str().unwrap().contains("revision"));
+    }
+
+    #[tokio::test]
+    async fn test_binary_websocket_format() {
+        use crate::ws_format::WsFormat;
+
+        let temp_dir = TempDir::new().unwrap();
+        let routes = server(&temp_dir).routes();
+        let mut client = warp::test::ws()
+            .path("/ws?format=msgpack")
+            .handshake(routes.clone())
+            .await
+            .unwrap();
+        let hello = client.recv().await.unwrap();
+        assert!(hello.is_binary());
+        assert_eq!(
+            WsFormat::MessagePack
+                .decode::<serde_json::Value>(&hello)
+                .unwrap()["type"],
+            "hello"
+        );
+        client.recv().await.unwrap();
+
+        let subscribe = serde_json::json!({"type": "subscribe", "dir": "projects"});
+        client
+            .send(WsFormat::MessagePack.encode(&subscribe).unwrap())
+            .await;
+        let reply = WsFormat::MessagePack
+            .decode::<serde_json::Value>(&client.recv().await.unwrap())
+            .unwrap();
+        assert_eq!(
+            reply,
+            serde_json::json!({"type": "subscribed", "dirs": ["projects"]})
+        );
+
+        let response = warp::test::request()
+            .path("/ws?format=xml")
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_websocket() {
+        use std::io::Read;
+
+        let temp_dir = TempDir::new().unwrap();
+        let routes = server(&temp_dir).routes();
+        let mut client = warp::test::ws()
+            .path("/ws?compress=deflate")
+            .handshake(routes.clone())
+            .await
+            .unwrap();
+        let hello = client.recv().await.unwrap();
+        assert!(hello.is_binary());
+        let mut text = String::new();
+        flate2::read::ZlibDecoder::new(hello.as_bytes())
+            .read_to_string(&mut text)
+            .unwrap();
+        let hello: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(hello["type"], "hello");
+
+        let result = warp::test::ws()
+            .path("/ws?compress=gzip")
+            .handshake(routes.clone())
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_presence_over_websocket() {
+        let temp_dir = TempDir::new().unwrap();
+        let routes = server(&temp_dir).routes();
+        let mut first = connect(&routes).await;
+        first
+            .send_text(r#"{"type": "viewing", "path": "notes.fmemo", "name": "Kai"}"#)
+            .await;
+        let message = recv_json(&mut first).await;
+        assert_eq!(message["files"]["notes.fmemo"][0]["name"], "Kai");
+
+        // New clients see who is there, and everyone sees them leave
+        let mut second = warp::test::ws()
+            .path("/ws")
+            .handshake(routes.clone())
+            .await
+            .unwrap();
+        assert_eq!(recv_json(&mut second).await["type"], "hello");
+        let message = recv_json(&mut second).await;
+        assert_eq!(message["files"]["notes.fmemo"][0]["name"], "Kai");
+        drop(first);
+        let message = recv_json(&mut second).await;
+        assert_eq!(
+            message,
+            serde_json::json!({"type": "presence", "files": {}})
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_bind_unix_socket() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let temp_dir = TempDir::new().unwrap();
+        let socket_dir = TempDir::new().unwrap();
+        let path = socket_dir.path().join("fmemo.sock");
+        let serve = server(&temp_dir).bind_unix(path.clone()).unwrap();
+        let task = tokio::spawn(serve);
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET /api/files/notes.fmemo HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Notes"));
+
+        task.abort();
+        let _ = task.await;
+        assert!(!path.exists());
+        // Anything but a stale socket is left alone
+        fs::write(&path, "").unwrap();
+        assert!(server(&temp_dir).bind_unix(path).is_err());
+    }
+
+    #[test]
+    fn test_bind_rejects_missing_root() {
+        let result = FmemoServer::new("/definitely/not/here").bind();
+        assert!(result.is_err());
+    }
+}
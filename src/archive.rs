@@ -0,0 +1,236 @@
+//! Archiving stale memos: files not modified for a while move below the archive
+//! directory (`[archive] dir`, `archive` by default), keeping their relative path.
+//!
+//! Archived memos stay ordinary files. The tree, the file list and search leave the archive
+//! directory out unless they are asked for archived memos (`?archived=true`, `--archived`).
+
+use std::io;
+use std::path::Path;
+
+use chrono::{DateTime, Months, Utc};
+
+use crate::schema::DirectoryTree;
+use crate::server::list_memo_files;
+
+/// A file moved into the archive
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct ArchivedFile {
+    /// Path relative to the root before archiving
+    pub from: String,
+    /// Path relative to the root inside the archive
+    pub to: String,
+    /// RFC 3339 modification time
+    pub modified: String,
+}
+
+/// What an archive run did
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct ArchiveReport {
+    pub archived: Vec<ArchivedFile>,
+    /// Stale files left in place because the archive already has a file at their path
+    pub skipped: Vec<String>,
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.to_string())
+}
+
+/// The point in time before `now` that an age like `1y`, `6m`, `2w` or `30d` stands for
+pub fn cutoff(now: DateTime<Utc>, older_than: &str) -> io::Result<DateTime<Utc>> {
+    let older_than = older_than.trim();
+    let error = || invalid_input("Age must be a number with d, w, m or y, like 1y or 30d");
+    let unit = older_than.chars().last().ok_or_else(error)?;
+    let count: u32 = older_than[..older_than.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| error())?;
+    let cutoff = match unit {
+        'd' => now.checked_sub_signed(chrono::Duration::days(count.into())),
+        'w' => now.checked_sub_signed(chrono::Duration::weeks(count.into())),
+        'm' => now.checked_sub_months(Months::new(count)),
+        'y' => now.checked_sub_months(Months::new(count.saturating_mul(12))),
+        _ => return Err(error()),
+    };
+    cutoff.ok_or_else(error)
+}
+
+/// The archive directory as a relative path without surrounding slashes; it has to stay
+/// inside the root and be visible to the scanner
+fn archive_dir(dir: &str) -> io::Result<&str> {
+    let dir = dir.trim_matches('/');
+    if dir.is_empty()
+        || dir
+            .split('/')
+            .any(|segment| segment.is_empty() || segment.starts_with('.'))
+    {
+        return Err(invalid_input(
+            "Archive directory must be a relative path without hidden or empty segments",
+        ));
+    }
+    Ok(dir)
+}
+
+/// Whether a file (relative to the root) is in the archive directory `dir`
+pub fn is_archived(dir: &str, relative: &str) -> bool {
+    let dir = dir.trim_matches('/');
+    !dir.is_empty()
+        && relative
+            .strip_prefix(dir)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// The tree scanned from `root` without the archive directory `dir`
+pub fn without_archive(mut tree: DirectoryTree, root: &Path, dir: &str) -> DirectoryTree {
+    let archive = root.join(dir.trim_matches('/'));
+    tree.subdirectories = tree
+        .subdirectories
+        .into_iter()
+        .filter(|subdir| Path::new(&subdir.path) != archive)
+        .map(|subdir| without_archive(subdir, root, dir))
+        .collect();
+    tree
+}
+
+/// Memo files outside the archive directory `dir` last modified before `cutoff`, with
+/// their modification time
+pub fn stale_files(
+    root: &Path,
+    dir: &str,
+    cutoff: DateTime<Utc>,
+) -> io::Result<Vec<(String, DateTime<Utc>)>> {
+    let mut stale = Vec::new();
+    for file in list_memo_files(root)? {
+        if is_archived(dir, &file) {
+            continue;
+        }
+        let modified: DateTime<Utc> = std::fs::metadata(root.join(&file))?.modified()?.into();
+        if modified < cutoff {
+            stale.push((file, modified));
+        }
+    }
+    Ok(stale)
+}
+
+/// Move the memo files last modified before `cutoff` into the archive directory `dir`,
+/// along with their comments. With `dry_run` nothing is moved, only reported.
+pub fn archive(
+    root: &Path,
+    dir: &str,
+    cutoff: DateTime<Utc>,
+    dry_run: bool,
+) -> io::Result<ArchiveReport> {
+    let dir = archive_dir(dir)?;
+    let mut report = ArchiveReport::default();
+    for (file, modified) in stale_files(root, dir, cutoff)? {
+        let to = format!("{}/{}", dir, file);
+        let target = root.join(&to);
+        if target.exists() {
+            report.skipped.push(file);
+            continue;
+        }
+        if !dry_run {
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::rename(root.join(&file), &target)?;
+            let comments = crate::comments::comments_path(root, &file);
+            if comments.exists() {
+                let moved = crate::comments::comments_path(root, &to);
+                if let Some(parent) = moved.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::rename(comments, moved)?;
+            }
+        }
+        report.archived.push(ArchivedFile {
+            from: file,
+            to,
+            modified: modified.to_rfc3339(),
+        });
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::scan_directory;
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    fn touch(path: &Path, days_ago: u64) {
+        let time = SystemTime::now() - Duration::from_secs(days_ago * 24 * 60 * 60);
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_cutoff() {
+        let now = DateTime::parse_from_rfc3339("2024-05-31T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        assert_eq!(
+            cutoff(now, "1y").unwrap().to_rfc3339(),
+            "2023-05-31T12:00:00+00:00"
+        );
+        assert_eq!(
+            cutoff(now, "3m").unwrap().to_rfc3339(),
+            "2024-02-29T12:00:00+00:00"
+        );
+        assert_eq!(
+            cutoff(now, "2w").unwrap().to_rfc3339(),
+            "2024-05-17T12:00:00+00:00"
+        );
+        assert_eq!(
+            cutoff(now, "30d").unwrap().to_rfc3339(),
+            "2024-05-01T12:00:00+00:00"
+        );
+        assert!(cutoff(now, "y").is_err());
+        assert!(cutoff(now, "1h").is_err());
+    }
+
+    #[test]
+    fn test_archive() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("notes")).unwrap();
+        fs::write(root.join("notes/old.fmemo"), "# Old").unwrap();
+        fs::write(root.join("notes/new.fmemo"), "# New").unwrap();
+        fs::write(root.join("taken.fmemo"), "# Taken").unwrap();
+        fs::create_dir_all(root.join("archive")).unwrap();
+        fs::write(root.join("archive/taken.fmemo"), "# Archived before").unwrap();
+        for file in ["notes/old.fmemo", "taken.fmemo", "archive/taken.fmemo"] {
+            touch(&root.join(file), 400);
+        }
+        crate::comments::add_comment(root, "notes/old.fmemo", "old", None, "Still true?").unwrap();
+
+        let cutoff = cutoff(Utc::now(), "1y").unwrap();
+        let dry_run = archive(root, "archive/", cutoff, true).unwrap();
+        assert_eq!(dry_run.archived.len(), 1);
+        assert!(root.join("notes/old.fmemo").exists());
+
+        let report = archive(root, "archive", cutoff, false).unwrap();
+        assert_eq!(report.archived[0].from, "notes/old.fmemo");
+        assert_eq!(report.archived[0].to, "archive/notes/old.fmemo");
+        assert_eq!(report.skipped, ["taken.fmemo"]);
+        assert!(!root.join("notes/old.fmemo").exists());
+        assert!(root.join("archive/notes/old.fmemo").exists());
+        assert_eq!(
+            crate::comments::list_comments(root, "archive/notes/old.fmemo", "old")
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(archive(root, "../elsewhere", cutoff, false).is_err());
+
+        assert!(is_archived("archive", "archive/notes/old.fmemo"));
+        assert!(!is_archived("archive", "archived.fmemo"));
+        let tree = without_archive(scan_directory(root).unwrap(), root, "archive");
+        assert_eq!(tree.subdirectories.len(), 1);
+        assert_eq!(tree.files, ["taken.fmemo"]);
+    }
+}
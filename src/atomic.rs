@@ -0,0 +1,149 @@
+//! Writing memo files of at least `REPLACE_THRESHOLD` bytes through a rename: the new content
+//! goes to a temporary file next to the old one, which then takes its place. Writing a file
+//! that large in place takes long enough for the watcher, a request or an editor to read it
+//! half-written; a rename is seen all at once.
+//!
+//! The temporary file gets the old file's permissions (and owner on Unix). A file with other
+//! hard links, or one whose owner can't be kept, is still written in place, since a rename
+//! would split it from its links or hand it to the server's user.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::crypt::{random_bytes, to_hex};
+
+/// Files of at least this many bytes are replaced rather than overwritten
+pub const REPLACE_THRESHOLD: u64 = 4 * 1024 * 1024;
+
+/// Write a memo file, replacing it through a rename when it has at least
+/// `REPLACE_THRESHOLD` bytes
+pub fn write(path: &Path, data: &[u8]) -> io::Result<()> {
+    if path
+        .metadata()
+        .is_ok_and(|metadata| metadata.len() >= REPLACE_THRESHOLD)
+    {
+        return replace(path, data);
+    }
+    fs::write(path, data)
+}
+
+/// A new, uniquely named temporary file next to `path`
+fn create_temp(path: &Path) -> io::Result<(PathBuf, File)> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Path has no file name"))?;
+    loop {
+        let temp = path.with_file_name(format!(
+            ".{}.{}.tmp",
+            name.to_string_lossy(),
+            to_hex(&random_bytes::<8>()?)
+        ));
+        match OpenOptions::new().write(true).create_new(true).open(&temp) {
+            Ok(file) => return Ok((temp, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Give `temp` the owner of the file it replaces; `false` when that isn't allowed
+#[cfg(unix)]
+fn keep_owner(temp: &Path, metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    std::os::unix::fs::chown(temp, Some(metadata.uid()), Some(metadata.gid())).is_ok()
+}
+
+#[cfg(not(unix))]
+fn keep_owner(_temp: &Path, _metadata: &fs::Metadata) -> bool {
+    true
+}
+
+#[cfg(unix)]
+fn has_other_links(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink() > 1
+}
+
+#[cfg(not(unix))]
+fn has_other_links(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+/// Write `data` to `path` through a temporary file renamed over it, keeping the old file's
+/// permissions and owner
+pub fn replace(path: &Path, data: &[u8]) -> io::Result<()> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) if has_other_links(&metadata) => return fs::write(path, data),
+        Ok(metadata) => Some(metadata),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let (temp, mut file) = create_temp(path)?;
+    let result = (|| {
+        file.write_all(data)?;
+        file.sync_all()?;
+        if let Some(metadata) = &metadata {
+            if !keep_owner(&temp, metadata) {
+                return Ok(false);
+            }
+            fs::set_permissions(&temp, metadata.permissions())?;
+        }
+        fs::rename(&temp, path)?;
+        Ok(true)
+    })();
+    match result {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            let _ = fs::remove_file(&temp);
+            fs::write(path, data)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_replace() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("big.fmemo");
+        fs::write(&path, "# Big\nbody\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        replace(&path, b"# New\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "# New\n");
+        // No temporary file is left behind
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o640);
+
+            // Hard links stay linked
+            let link = temp_dir.path().join("link.fmemo");
+            fs::hard_link(&path, &link).unwrap();
+            replace(&path, b"# Linked\n").unwrap();
+            assert_eq!(fs::read_to_string(&link).unwrap(), "# Linked\n");
+        }
+
+        replace(&temp_dir.path().join("new.fmemo"), b"# Created\n").unwrap();
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("new.fmemo")).unwrap(),
+            "# Created\n"
+        );
+    }
+}
@@ -0,0 +1,250 @@
+//! Append-only log of the changes made through the API, in `.fmemo/audit.log`.
+//!
+//! Each line is a JSON object saying who changed which file, when, and how much: memos and
+//! lines added or removed, never the content itself, so encrypted memos don't leak into it.
+//! Entries are only ever appended; nothing in fmemo rewrites or truncates the file.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+
+use crate::config::FMEMO_DIR;
+use crate::diff::{MemoChangeKind, diff_memos};
+
+/// Entries `query` returns without a limit
+pub const DEFAULT_LIMIT: usize = 100;
+
+/// What was done to a file
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    /// Saved from the editor
+    Write,
+    /// Created from a template
+    Create,
+    /// Moved to the trash
+    Delete,
+    /// Brought back from a git revision
+    Restore,
+    /// Brought back from the trash
+    Untrash,
+    /// Written by a zip import
+    Import,
+    /// Moved into the archive directory
+    Archive,
+}
+
+/// How much a change touched
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+pub struct DiffSummary {
+    pub memos_added: usize,
+    pub memos_removed: usize,
+    pub memos_changed: usize,
+    pub lines_added: usize,
+    pub lines_removed: usize,
+}
+
+impl DiffSummary {
+    /// Summary of the change from `old` to `new`. Lines are compared as a multiset, so
+    /// moving a line counts as neither adding nor removing it.
+    pub fn between(old: &str, new: &str) -> Self {
+        let mut summary = Self::default();
+        for change in diff_memos(old, new) {
+            match change.kind {
+                MemoChangeKind::Added => summary.memos_added += 1,
+                MemoChangeKind::Removed => summary.memos_removed += 1,
+                MemoChangeKind::Changed => summary.memos_changed += 1,
+            }
+        }
+        let mut lines: HashMap<&str, isize> = HashMap::new();
+        for line in old.lines() {
+            *lines.entry(line).or_default() -= 1;
+        }
+        for line in new.lines() {
+            *lines.entry(line).or_default() += 1;
+        }
+        for count in lines.values() {
+            if *count > 0 {
+                summary.lines_added += count.unsigned_abs();
+            } else {
+                summary.lines_removed += count.unsigned_abs();
+            }
+        }
+        summary
+    }
+}
+
+/// One change
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct AuditEntry {
+    /// RFC 3339
+    pub time: String,
+    /// User name of the session, `token:<name>` for API tokens, `token` for the `--token`;
+    /// null when the server needs no credentials
+    pub actor: Option<String>,
+    pub action: AuditAction,
+    /// File path relative to the root
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff: Option<DiffSummary>,
+}
+
+impl AuditEntry {
+    pub fn new(actor: Option<String>, action: AuditAction, path: &str) -> Self {
+        Self {
+            time: Utc::now().to_rfc3339(),
+            actor,
+            action,
+            path: path.to_string(),
+            diff: None,
+        }
+    }
+
+    /// Also summarize what changed in the file
+    pub fn diff(mut self, old: &str, new: &str) -> Self {
+        self.diff = Some(DiffSummary::between(old, new));
+        self
+    }
+}
+
+/// Filters of `GET /api/audit`
+#[derive(Debug, Clone, Default, serde::Deserialize, PartialEq)]
+pub struct AuditQuery {
+    /// Only this file
+    pub path: Option<String>,
+    pub actor: Option<String>,
+    pub action: Option<AuditAction>,
+    /// RFC 3339; only entries at or after it
+    pub since: Option<String>,
+    pub limit: Option<usize>,
+}
+
+pub fn audit_path(root: &Path) -> PathBuf {
+    root.join(FMEMO_DIR).join("audit.log")
+}
+
+/// Append an entry to the root's log
+pub fn record(root: &Path, entry: &AuditEntry) -> io::Result<()> {
+    let path = audit_path(root);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    // One write per line, so concurrent appends don't interleave
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())
+}
+
+/// Like `record`, for changes that already happened: a failure is only reported
+pub fn log(root: &Path, entry: AuditEntry) {
+    if let Err(e) = record(root, &entry) {
+        eprintln!("Failed to write audit log entry for {}: {}", entry.path, e);
+    }
+}
+
+/// Entries matching the query, newest first
+pub fn query(root: &Path, query: &AuditQuery) -> io::Result<Vec<AuditEntry>> {
+    let since = query
+        .since
+        .as_deref()
+        .map(DateTime::parse_from_rfc3339)
+        .transpose()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "since must be RFC 3339"))?;
+    let file = match std::fs::File::open(audit_path(root)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut entries = Vec::new();
+    for line in io::BufReader::new(file).lines() {
+        // A line cut short by a crash is skipped
+        let Ok(entry) = serde_json::from_str::<AuditEntry>(&line?) else {
+            continue;
+        };
+        let matches = query.path.as_ref().is_none_or(|path| *path == entry.path)
+            && query
+                .actor
+                .as_ref()
+                .is_none_or(|actor| entry.actor.as_ref() == Some(actor))
+            && query.action.is_none_or(|action| action == entry.action)
+            && since.is_none_or(|since| {
+                DateTime::parse_from_rfc3339(&entry.time).is_ok_and(|time| time >= since)
+            });
+        if matches {
+            entries.push(entry);
+        }
+    }
+    entries.reverse();
+    entries.truncate(query.limit.unwrap_or(DEFAULT_LIMIT));
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_audit_log() {
+        let summary = DiffSummary::between("# A\none\n# B\ntwo", "# A\none\nmore\n# C\ntwo");
+        assert_eq!(
+            summary,
+            DiffSummary {
+                memos_added: 1,
+                memos_removed: 1,
+                memos_changed: 1,
+                lines_added: 2,
+                lines_removed: 1,
+            }
+        );
+
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        assert!(query(root, &AuditQuery::default()).unwrap().is_empty());
+        log(
+            root,
+            AuditEntry::new(Some("kai".to_string()), AuditAction::Write, "a.fmemo").diff("", "# A"),
+        );
+        log(root, AuditEntry::new(None, AuditAction::Delete, "b.fmemo"));
+        log(
+            root,
+            AuditEntry::new(Some("kai".to_string()), AuditAction::Delete, "a.fmemo"),
+        );
+
+        let all = query(root, &AuditQuery::default()).unwrap();
+        assert_eq!(
+            all.iter()
+                .map(|entry| entry.path.as_str())
+                .collect::<Vec<_>>(),
+            ["a.fmemo", "b.fmemo", "a.fmemo"]
+        );
+        assert_eq!(all[2].diff.unwrap().memos_added, 1);
+        let by_kai = AuditQuery {
+            actor: Some("kai".to_string()),
+            action: Some(AuditAction::Delete),
+            ..Default::default()
+        };
+        assert_eq!(query(root, &by_kai).unwrap().len(), 1);
+        let limited = AuditQuery {
+            limit: Some(1),
+            since: Some(all[2].time.clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            query(root, &limited).unwrap()[0].action,
+            AuditAction::Delete
+        );
+        let invalid = AuditQuery {
+            since: Some("yesterday".to_string()),
+            ..Default::default()
+        };
+        assert!(query(root, &invalid).is_err());
+    }
+}
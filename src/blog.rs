@@ -0,0 +1,361 @@
+//! Export to the content directory of a Hugo or Jekyll site (`fmemo export --hugo <dir>` or
+//! `--jekyll <dir>`), so memos can feed an existing blog pipeline.
+//!
+//! Every memo file becomes a Markdown page at the same path. Its front matter is written in
+//! the generator's terms: `title` (or the first heading), `date` (`date:`, else `created:`
+//! or `<created>`, else the file's modification time), the last modification, `tags` from
+//! the front matter and the memos' `<tag>`s, and an explicit permalink; other fields are kept.
+//! Permalinks are the file's path without the extension, each segment slugified
+//! (`/trips/kyoto/`), and a file named `index` is the page of its directory. Outside code,
+//! wiki-links, embeds and links to memo files are rewritten to those permalinks; unresolved
+//! wiki-links become their text. Like the static site, the export is public: encrypted memos
+//! and `<private>` regions are left out.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use chrono::SecondsFormat;
+
+use crate::graph::{resolve_link, wiki_name};
+use crate::parser::{front_matter_fields, parse_timestamp};
+use crate::plugin::Plugins;
+use crate::schema::{LinkKind, Timestamp};
+use crate::server::{list_memo_files, read_fmemo_file_with};
+use crate::template::slugify;
+
+/// Front matter fields the export writes itself
+const NORMALIZED_FIELDS: &[&str] = &[
+    "title",
+    "date",
+    "created",
+    "updated",
+    "tags",
+    "url",
+    "lastmod",
+    "permalink",
+    "last_modified_at",
+];
+
+/// Static site generator an export is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Generator {
+    Hugo,
+    Jekyll,
+}
+
+impl Generator {
+    /// Front matter keys of the permalink and of the last modification
+    fn keys(self) -> (&'static str, &'static str) {
+        match self {
+            Generator::Hugo => ("url", "lastmod"),
+            Generator::Jekyll => ("permalink", "last_modified_at"),
+        }
+    }
+
+    /// Path of the page for the memo file `file`, relative to the content directory.
+    /// Hugo takes `index.md` for a bundle hiding its directory's other pages, so an index
+    /// file is the section page `_index.md` instead.
+    pub fn page_path(self, file: &str) -> String {
+        let name = wiki_name(file);
+        match name.strip_suffix("index") {
+            Some(dir) if self == Generator::Hugo && (dir.is_empty() || dir.ends_with('/')) => {
+                format!("{}_index.md", dir)
+            }
+            _ => format!("{}.md", name),
+        }
+    }
+}
+
+/// Site-relative URL of the page for the memo file `file`
+pub fn permalink(file: &str) -> String {
+    let mut segments: Vec<String> = wiki_name(file).split('/').map(slugify).collect();
+    if segments.last().is_some_and(|segment| segment == "index") {
+        segments.pop();
+    }
+    match segments.is_empty() {
+        true => "/".to_string(),
+        false => format!("/{}/", segments.join("/")),
+    }
+}
+
+/// Permalink of the memo file a link resolves to, with the link's `#anchor` slugified
+fn link_target(files: &[String], file: &str, kind: LinkKind, url: &str) -> Option<String> {
+    let target = resolve_link(files, file, kind, url)?;
+    let mut href = permalink(&target);
+    if let Some((_, anchor)) = url.split_once('#') {
+        href.push('#');
+        href.push_str(&slugify(anchor));
+    }
+    Some(href)
+}
+
+/// One line of Markdown outside code blocks with its links rewritten
+fn rewrite_line(line: &str, file: &str, files: &[String], out: &mut String) {
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        if c == '`' {
+            // A code span runs to the next run of as many backticks
+            let ticks = rest.len() - rest.trim_start_matches('`').len();
+            let end = rest[ticks..]
+                .find(&rest[..ticks])
+                .map_or(rest.len(), |end| 2 * ticks + end);
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+        let open = match rest {
+            _ if rest.starts_with("![[") => 3,
+            _ if rest.starts_with("[[") => 2,
+            _ => 0,
+        };
+        if open > 0
+            && let Some(end) = rest.find("]]")
+        {
+            let inner = &rest[open..end];
+            let (target, label) = inner.split_once('|').unwrap_or((inner, inner));
+            match link_target(files, file, LinkKind::Wiki, target.trim()) {
+                Some(href) => out.push_str(&format!("[{}]({})", label.trim(), href)),
+                None => out.push_str(label.trim()),
+            }
+            rest = &rest[end + 2..];
+            continue;
+        }
+        if rest.starts_with("](")
+            && let Some(end) = rest.find(')')
+        {
+            let url = &rest[2..end];
+            let href = match LinkKind::classify(url) {
+                LinkKind::Internal => link_target(files, file, LinkKind::Internal, url),
+                _ => None,
+            };
+            out.push_str("](");
+            out.push_str(href.as_deref().unwrap_or(url));
+            out.push(')');
+            rest = &rest[end + 1..];
+            continue;
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+}
+
+/// `body`, the Markdown of the memo file `file`, with its links rewritten to permalinks
+pub fn rewrite_links(body: &str, file: &str, files: &[String]) -> String {
+    let mut out = String::with_capacity(body.len());
+    let mut fence: Option<&str> = None;
+    for line in body.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        match fence {
+            Some(marker) => {
+                if trimmed.starts_with(marker) {
+                    fence = None;
+                }
+                out.push_str(line);
+            }
+            None => match ["```", "~~~"]
+                .into_iter()
+                .find(|marker| trimmed.starts_with(marker))
+            {
+                Some(marker) => {
+                    fence = Some(marker);
+                    out.push_str(line);
+                }
+                None => rewrite_line(line, file, files, &mut out),
+            },
+        }
+    }
+    out
+}
+
+fn timestamp_value(timestamp: &Timestamp) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Values of a flat front matter list such as `[a, "b"]` or `a, b`
+fn list_values(value: &str) -> impl Iterator<Item = String> + '_ {
+    value
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .map(|item| {
+            item.trim()
+                .trim_matches(|c| c == '"' || c == '\'')
+                .to_string()
+        })
+        .filter(|item| !item.is_empty())
+}
+
+/// The page for the memo file `file` below `root`, front matter and all
+pub fn render_page(
+    root: &Path,
+    file: &str,
+    files: &[String],
+    generator: Generator,
+    plugins: &Plugins,
+) -> io::Result<String> {
+    let path = root.join(file);
+    let memos = read_fmemo_file_with(&path, plugins, true)?.memos;
+    let content = crate::publish::read_memo(&path, true)?;
+    let (fields, body) = front_matter_fields(&content);
+    let field = |key: &str| {
+        fields
+            .iter()
+            .find_map(|(k, v)| (k == key).then_some(v.as_str()))
+    };
+    let modified: Timestamp =
+        chrono::DateTime::<chrono::Utc>::from(fs::metadata(&path)?.modified()?).into();
+
+    let title = field("title")
+        .map(str::to_string)
+        .or_else(|| memos.first().map(|memo| memo.title().clone()))
+        .unwrap_or_else(|| {
+            wiki_name(file)
+                .rsplit('/')
+                .next()
+                .unwrap_or(file)
+                .to_string()
+        });
+    let date = field("date")
+        .and_then(parse_timestamp)
+        .or_else(|| memos.first().and_then(|memo| memo.created().copied()))
+        .unwrap_or(modified);
+    let updated = memos
+        .first()
+        .and_then(|memo| memo.updated().copied())
+        .unwrap_or(modified);
+    let mut tags: BTreeSet<String> = field("tags")
+        .map(|tags| list_values(tags).collect())
+        .unwrap_or_default();
+    crate::tags::collect_tags(&memos, &mut tags);
+
+    let (permalink_key, modified_key) = generator.keys();
+    let quote = |value: &str| serde_json::to_string(value).unwrap_or_default();
+    let mut page = String::from("---\n");
+    page.push_str(&format!("title: {}\n", quote(&title)));
+    page.push_str(&format!("date: {}\n", timestamp_value(&date)));
+    page.push_str(&format!(
+        "{}: {}\n",
+        modified_key,
+        timestamp_value(&updated)
+    ));
+    if !tags.is_empty() {
+        page.push_str(&format!("tags: {}\n", serde_json::to_string(&tags)?));
+    }
+    page.push_str(&format!("{}: {}\n", permalink_key, quote(&permalink(file))));
+    for (key, value) in &fields {
+        if !NORMALIZED_FIELDS.contains(&key.as_str()) {
+            page.push_str(&format!("{}: {}\n", key, quote(value)));
+        }
+    }
+    page.push_str("---\n");
+    page.push_str(&rewrite_links(body, file, files));
+    Ok(page)
+}
+
+/// Write the memo files below `root` as pages into the content directory `out`; returns
+/// the number of pages
+pub fn export_content(
+    root: &Path,
+    out: &Path,
+    generator: Generator,
+    plugins: &Plugins,
+) -> io::Result<usize> {
+    let files: Vec<String> = list_memo_files(root)?
+        .into_iter()
+        .filter(|file| !crate::crypt::is_encrypted(Path::new(file)))
+        .collect();
+    for file in &files {
+        let page = render_page(root, file, &files, generator, plugins)?;
+        let target = out.join(generator.page_path(file));
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(target, page)?;
+    }
+    Ok(files.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_permalinks() {
+        assert_eq!(permalink("Trips/Kyoto Nara.fmemo"), "/trips/kyoto-nara/");
+        assert_eq!(permalink("index.md"), "/");
+        assert_eq!(permalink("trips/index.fmemo"), "/trips/");
+        assert_eq!(
+            Generator::Hugo.page_path("trips/index.fmemo"),
+            "trips/_index.md"
+        );
+        assert_eq!(Generator::Hugo.page_path("index.fmemo"), "_index.md");
+        assert_eq!(
+            Generator::Jekyll.page_path("trips/index.fmemo"),
+            "trips/index.md"
+        );
+        assert_eq!(Generator::Jekyll.page_path("a.fmemo"), "a.md");
+    }
+
+    #[test]
+    fn test_rewrite_links() {
+        let files = vec!["plan.fmemo".to_string(), "trips/kyoto.fmemo".to_string()];
+        assert_eq!(
+            rewrite_links(
+                "See [[kyoto#Temples]], [[plan|the plan]] and [[nowhere]].\n\
+                 ![[plan]] and [kyoto](trips/kyoto.fmemo) but `[[plan]]`\n\
+                 ```\n[[plan]]\n```\n![map](img/map.png) [site](https://example.com)\n",
+                "index.fmemo",
+                &files
+            ),
+            "See [kyoto#Temples](/trips/kyoto/#temples), [the plan](/plan/) and nowhere.\n\
+             [plan](/plan/) and [kyoto](/trips/kyoto/) but `[[plan]]`\n\
+             ```\n[[plan]]\n```\n![map](img/map.png) [site](https://example.com)\n"
+        );
+    }
+
+    #[test]
+    fn test_export_content() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("vault");
+        let out = temp_dir.path().join("content");
+        fs::create_dir_all(root.join("trips")).unwrap();
+        fs::write(
+            root.join("trips/kyoto.fmemo"),
+            "---\ncreated: 2024-05-01\ntags: [travel, \"japan\"]\nauthor: kai\n---\n\
+             # Kyoto\n<tag>food</tag>\nBack to [[index]].<private> Hotel 4411</private>\n",
+        )
+        .unwrap();
+        fs::write(root.join("trips/index.fmemo"), "# Trips\n[[kyoto]]\n").unwrap();
+        fs::write(root.join("secret.fmemox"), "locked").unwrap();
+
+        assert_eq!(
+            export_content(&root, &out, Generator::Hugo, &Plugins::default()).unwrap(),
+            2
+        );
+        let kyoto = fs::read_to_string(out.join("trips/kyoto.md")).unwrap();
+        let (front_matter, body) = kyoto
+            .trim_start_matches("---\n")
+            .split_once("---\n")
+            .unwrap();
+        assert!(
+            front_matter.starts_with("title: \"Kyoto\"\ndate: 2024-05-01T00:00:00Z\nlastmod: ")
+        );
+        assert!(front_matter.ends_with(
+            "tags: [\"food\",\"japan\",\"travel\"]\nurl: \"/trips/kyoto/\"\nauthor: \"kai\"\n"
+        ));
+        assert_eq!(
+            body,
+            "# Kyoto\n<tag>food</tag>\nBack to [index](/trips/).\n"
+        );
+        assert!(out.join("trips/_index.md").is_file());
+        assert!(!out.join("secret.md").exists());
+
+        export_content(&root, &out, Generator::Jekyll, &Plugins::default()).unwrap();
+        let index = fs::read_to_string(out.join("trips/index.md")).unwrap();
+        assert!(index.contains("last_modified_at: "));
+        assert!(index.contains("permalink: \"/trips/\"\n---\n# Trips\n[kyoto](/trips/kyoto/)\n"));
+    }
+}
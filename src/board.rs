@@ -0,0 +1,131 @@
+//! Kanban board over existing memos, for `GET /api/board`.
+//!
+//! A memo is a card when it has a `<status>` (its last value, lowercased) or a heading
+//! starting with a checkbox: `[ ]` puts it in `todo`, `[x]` in `done`. Cards are grouped
+//! into columns in the requested order; statuses nobody asked for get columns of their own
+//! after those, in the order they first appear.
+
+use crate::indexer::Documents;
+use crate::schema::Memo;
+
+/// Columns when none are requested
+pub const DEFAULT_COLUMNS: [&str; 3] = ["todo", "doing", "done"];
+
+/// A memo on the board
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct Card {
+    /// File path relative to the root
+    pub file: String,
+    /// The memo's anchor, e.g. for `/api/files/{file}/memos/{slug}/markdown`
+    pub slug: String,
+    /// Heading without the checkbox
+    pub title: String,
+    pub line: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct Column {
+    pub status: String,
+    pub cards: Vec<Card>,
+}
+
+/// Status and title of a memo, if it is a card
+fn card_status(memo: &Memo) -> Option<(String, String)> {
+    let title = memo.title();
+    let checkbox = [("[ ] ", "todo"), ("[x] ", "done"), ("[X] ", "done")]
+        .into_iter()
+        .find_map(|(prefix, status)| Some((title.strip_prefix(prefix)?, status)));
+    let status = memo
+        .metadata()
+        .get("status")
+        .and_then(|values| values.last())
+        .map(|status| status.trim().to_lowercase())
+        .filter(|status| !status.is_empty());
+    match (status, checkbox) {
+        (Some(status), Some((title, _))) => Some((status, title.trim().to_string())),
+        (Some(status), None) => Some((status, title.clone())),
+        (None, Some((title, status))) => Some((status.to_string(), title.trim().to_string())),
+        (None, None) => None,
+    }
+}
+
+fn collect(memos: &[Memo], file: &str, columns: &mut Vec<Column>) {
+    for memo in memos {
+        if let Some((status, title)) = card_status(memo) {
+            let card = Card {
+                file: file.to_string(),
+                slug: memo.anchor().into_owned(),
+                title,
+                line: memo.span().map_or(0, |span| span.start_line),
+                due: memo
+                    .metadata()
+                    .get("due")
+                    .and_then(|values| values.first())
+                    .cloned(),
+            };
+            match columns.iter_mut().find(|column| column.status == status) {
+                Some(column) => column.cards.push(card),
+                None => columns.push(Column {
+                    status,
+                    cards: vec![card],
+                }),
+            }
+        }
+        collect(memo.children(), file, columns);
+    }
+}
+
+/// The board of every indexed file, with `columns` first (even when empty). Cards keep
+/// file and document order.
+pub fn board(documents: &Documents, columns: &[String]) -> Vec<Column> {
+    let mut board: Vec<Column> = columns
+        .iter()
+        .map(|status| Column {
+            status: status.trim().to_lowercase(),
+            cards: Vec::new(),
+        })
+        .collect();
+    for (file, memos) in documents {
+        collect(memos, file, &mut board);
+    }
+    board
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DEFAULT_COLUMNS, board};
+    use crate::indexer::Documents;
+    use crate::parser::{ParseOptions, parse_document};
+
+    #[test]
+    fn test_board() {
+        let mut documents = Documents::new();
+        for (file, content) in [
+            (
+                "a.fmemo",
+                "# Project\n## Docs\n<status>Doing</status>\n<due>2024-05-01</due>\n\
+                 ## [ ] Tests\n## [x] Release\n<status>blocked</status>\n## Notes\n",
+            ),
+            ("b.fmemo", "# [X] Setup\n"),
+        ] {
+            documents.insert(
+                file.to_string(),
+                parse_document(content, &ParseOptions::default()).memos,
+            );
+        }
+
+        let columns: Vec<String> = DEFAULT_COLUMNS.map(String::from).to_vec();
+        let board = board(&documents, &columns);
+        let statuses: Vec<&str> = board.iter().map(|column| column.status.as_str()).collect();
+        assert_eq!(statuses, ["todo", "doing", "done", "blocked"]);
+        assert_eq!(board[0].cards[0].title, "Tests");
+        assert_eq!(board[1].cards[0].slug, "docs");
+        assert_eq!(board[1].cards[0].due.as_deref(), Some("2024-05-01"));
+        assert_eq!(board[2].cards[0].file, "b.fmemo");
+        assert_eq!(board[2].cards[0].title, "Setup");
+        // The tag wins over the checkbox
+        assert_eq!(board[3].cards[0].title, "Release");
+    }
+}
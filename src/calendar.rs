@@ -0,0 +1,355 @@
+//! iCalendar feed of due dates, served as `/calendar.ics`, and the month view data of
+//! `GET /api/calendar`.
+//!
+//! Every `<due>` of a memo becomes an event, as does a `due:` field in a file's front
+//! matter (titled after the file's first memo). Plain dates are all-day events. The month
+//! view also shows files on their front matter `date:` and on the date their name starts
+//! with, like `2024-05-01.fmemo` or `2024-05-01-standup.md`.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use chrono::NaiveDate;
+
+use crate::parser::{front_matter_value, parse_timestamp};
+use crate::plugin::Plugins;
+use crate::schema::{FileContent, Memo, Timestamp};
+use crate::server::{list_memo_files, read_fmemo_file_with};
+
+/// A memo with a due date
+#[derive(Debug, Clone, PartialEq)]
+pub struct DueItem {
+    /// File path relative to the root
+    pub file: String,
+    /// Titles from the top-level memo down to this one
+    pub titles: Vec<String>,
+    /// Anchor of the memo; `None` for a front matter `due:`
+    pub slug: Option<String>,
+    pub line: usize,
+    pub due: Timestamp,
+    /// The due value was a date without a time
+    pub all_day: bool,
+}
+
+fn due_item(
+    file: &str,
+    titles: Vec<String>,
+    slug: Option<String>,
+    line: usize,
+    value: &str,
+) -> Option<DueItem> {
+    Some(DueItem {
+        file: file.to_string(),
+        titles,
+        slug,
+        line,
+        due: parse_timestamp(value)?,
+        all_day: chrono::NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").is_ok(),
+    })
+}
+
+fn collect(memos: &[Memo], file: &str, parents: &[String], items: &mut Vec<DueItem>) {
+    for memo in memos {
+        let mut titles = parents.to_vec();
+        titles.push(memo.title().clone());
+        for value in memo.metadata().get("due").into_iter().flatten() {
+            items.extend(due_item(
+                file,
+                titles.clone(),
+                Some(memo.anchor().into_owned()),
+                memo.span().map_or(0, |span| span.start_line),
+                value,
+            ));
+        }
+        collect(memo.children(), file, &titles, items);
+    }
+}
+
+/// Title of a file as a whole: its first memo's, or its path
+fn file_title(file: &str, parsed: &FileContent) -> String {
+    match parsed.memos.first() {
+        Some(memo) => memo.title().clone(),
+        None => file.to_string(),
+    }
+}
+
+/// Every memo file below `root` with its source and parsed memos, without their private
+/// regions with `publish`; unreadable files are skipped
+fn read_files(root: &Path, publish: bool) -> std::io::Result<Vec<(String, String, FileContent)>> {
+    let mut files = Vec::new();
+    for file in list_memo_files(root)? {
+        let path = root.join(&file);
+        if let (Ok(content), Ok(parsed)) = (
+            crate::publish::read_memo(&path, publish),
+            read_fmemo_file_with(&path, &Plugins::default(), publish),
+        ) {
+            files.push((file, content, parsed));
+        }
+    }
+    Ok(files)
+}
+
+fn file_due_items(file: &str, content: &str, parsed: &FileContent) -> Vec<DueItem> {
+    let mut items = Vec::new();
+    if let Some(value) = front_matter_value(content, "due") {
+        items.extend(due_item(
+            file,
+            vec![file_title(file, parsed)],
+            None,
+            1,
+            &value,
+        ));
+    }
+    collect(&parsed.memos, file, &[], &mut items);
+    items
+}
+
+/// Due items of every memo file below `root`, by file; unreadable files and values that
+/// aren't dates are skipped. With `publish`, private regions are left out.
+pub fn due_items(root: &Path, publish: bool) -> std::io::Result<Vec<DueItem>> {
+    Ok(read_files(root, publish)?
+        .iter()
+        .flat_map(|(file, content, parsed)| file_due_items(file, content, parsed))
+        .collect())
+}
+
+/// Where the date of a calendar entry comes from
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DateSource {
+    /// A memo's `<due>` or the front matter `due:`
+    Due,
+    /// The front matter `date:`
+    Date,
+    /// The date the file name starts with
+    FileName,
+}
+
+/// Something on a day of `GET /api/calendar`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct CalendarEntry {
+    /// File path relative to the root
+    pub file: String,
+    /// Anchor of the memo; `None` for the file as a whole
+    pub slug: Option<String>,
+    pub title: String,
+    pub source: DateSource,
+    /// `HH:MM` in the value's own offset; `None` for all-day dates
+    pub time: Option<String>,
+}
+
+/// The entries of one day
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct CalendarDay {
+    /// `YYYY-MM-DD`
+    pub date: String,
+    pub entries: Vec<CalendarEntry>,
+}
+
+/// The date a file name (without directories) starts with
+fn file_name_date(file: &str) -> Option<NaiveDate> {
+    let name = file.rsplit('/').next()?;
+    NaiveDate::parse_from_str(name.get(..10)?, "%Y-%m-%d").ok()
+}
+
+/// Days from `from` to `to` (both included) that have entries, in order; entries keep file
+/// and document order. With `publish`, private regions are left out.
+pub fn calendar_days(
+    root: &Path,
+    from: NaiveDate,
+    to: NaiveDate,
+    publish: bool,
+) -> std::io::Result<Vec<CalendarDay>> {
+    let mut days: BTreeMap<NaiveDate, Vec<CalendarEntry>> = BTreeMap::new();
+    let mut add = |date: NaiveDate, entry: CalendarEntry| {
+        if (from..=to).contains(&date) {
+            days.entry(date).or_default().push(entry);
+        }
+    };
+    for (file, content, parsed) in read_files(root, publish)? {
+        let whole_file = |source, time| CalendarEntry {
+            file: file.clone(),
+            slug: None,
+            title: file_title(&file, &parsed),
+            source,
+            time,
+        };
+        if let Some(date) = file_name_date(&file) {
+            add(date, whole_file(DateSource::FileName, None));
+        }
+        if let Some(value) = front_matter_value(&content, "date")
+            && let Some(item) = due_item(&file, Vec::new(), None, 1, &value)
+        {
+            let time = (!item.all_day).then(|| item.due.format("%H:%M").to_string());
+            add(item.due.date_naive(), whole_file(DateSource::Date, time));
+        }
+        for item in file_due_items(&file, &content, &parsed) {
+            add(
+                item.due.date_naive(),
+                CalendarEntry {
+                    file: item.file,
+                    slug: item.slug,
+                    title: item.titles.last().cloned().unwrap_or_default(),
+                    source: DateSource::Due,
+                    time: (!item.all_day).then(|| item.due.format("%H:%M").to_string()),
+                },
+            );
+        }
+    }
+    Ok(days
+        .into_iter()
+        .map(|(date, entries)| CalendarDay {
+            date: date.format("%Y-%m-%d").to_string(),
+            entries,
+        })
+        .collect())
+}
+
+/// Escape a TEXT value (RFC 5545 3.3.11)
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Fold a content line to 75 octets per line, without splitting characters
+fn fold(line: &str, out: &mut String) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+/// The items as a VCALENDAR, stamped with `now`
+pub fn to_ics(items: &[DueItem], now: Timestamp) -> String {
+    const UTC: &str = "%Y%m%dT%H%M%SZ";
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//fmemo//due dates//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "X-WR-CALNAME:fmemo".to_string(),
+    ];
+    let stamp = now.naive_utc().format(UTC);
+    for item in items {
+        let path = item.titles.join(" > ");
+        lines.push("BEGIN:VEVENT".to_string());
+        lines.push(format!(
+            "UID:{}",
+            escape(&format!("{}#{}@{}", item.file, path, item.due.to_rfc3339()))
+        ));
+        lines.push(format!("DTSTAMP:{}", stamp));
+        if item.all_day {
+            lines.push(format!("DTSTART;VALUE=DATE:{}", item.due.format("%Y%m%d")));
+        } else {
+            lines.push(format!("DTSTART:{}", item.due.naive_utc().format(UTC)));
+        }
+        lines.push(format!(
+            "SUMMARY:{}",
+            escape(item.titles.last().map_or("", String::as_str))
+        ));
+        lines.push(format!(
+            "DESCRIPTION:{}",
+            escape(&format!("{}:{}\n{}", item.file, item.line, path))
+        ));
+        lines.push("END:VEVENT".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    let mut ics = String::new();
+    for line in lines {
+        fold(&line, &mut ics);
+    }
+    ics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DateSource, calendar_days, due_items, fold, to_ics};
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_due_items_to_ics() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(
+            temp_dir.path().join("tasks.fmemo"),
+            "# Tasks\n## Taxes, finally\n<due>2024-04-15</due>\n## Call\n<due>2024-04-01 09:30</due>\n## Someday\n<due>soon</due>",
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.path().join("launch.md"),
+            "---\ndue: 2024-06-01T10:00:00+09:00\n---\n# Launch",
+        )
+        .unwrap();
+
+        let items = due_items(temp_dir.path(), false).unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].file, "launch.md");
+        assert_eq!(items[1].titles, ["Tasks", "Taxes, finally"]);
+        assert!(items[1].all_day);
+        assert_eq!(items[2].line, 4);
+        assert!(!items[2].all_day);
+
+        let now = chrono::DateTime::parse_from_rfc3339("2024-03-01T00:00:00Z").unwrap();
+        let ics = to_ics(&items, now);
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 3);
+        assert!(ics.contains("DTSTART:20240601T010000Z\r\nSUMMARY:Launch\r\n"));
+        assert!(ics.contains("DTSTART;VALUE=DATE:20240415\r\nSUMMARY:Taxes\\, finally\r\n"));
+        assert!(ics.contains("DTSTART:20240401T093000Z\r\n"));
+        assert!(ics.contains("DTSTAMP:20240301T000000Z\r\n"));
+    }
+
+    #[test]
+    fn test_calendar_days() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("daily")).unwrap();
+        fs::write(
+            temp_dir.path().join("daily/2024-05-01-standup.fmemo"),
+            "# Standup\n## Deploy\n<due>2024-05-02 14:00</due>",
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.path().join("retro.md"),
+            "---\ndate: 2024-05-01\n---\n# Retro",
+        )
+        .unwrap();
+        fs::write(temp_dir.path().join("2024-06-01.fmemo"), "# June").unwrap();
+
+        let date = |value| chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap();
+        let days = calendar_days(
+            temp_dir.path(),
+            date("2024-05-01"),
+            date("2024-05-31"),
+            false,
+        )
+        .unwrap();
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].date, "2024-05-01");
+        let sources: Vec<_> = days[0].entries.iter().map(|entry| entry.source).collect();
+        assert_eq!(sources, [DateSource::FileName, DateSource::Date]);
+        assert_eq!(days[0].entries[0].title, "Standup");
+        assert_eq!(days[0].entries[1].file, "retro.md");
+        assert_eq!(days[1].entries[0].slug.as_deref(), Some("deploy"));
+        assert_eq!(days[1].entries[0].time.as_deref(), Some("14:00"));
+    }
+
+    #[test]
+    fn test_fold() {
+        let mut out = String::new();
+        fold(&format!("SUMMARY:{}", "é".repeat(40)), &mut out);
+        let lines: Vec<&str> = out.trim_end().split("\r\n").collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line.len() <= 75));
+        assert!(lines[1].starts_with(' '));
+    }
+}
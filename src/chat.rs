@@ -0,0 +1,254 @@
+//! Chat notifications: a short message per changed memo file, posted to Slack or
+//! Discord incoming webhooks configured as `[[chat]]` in `.fmemo/config.toml`.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{RecvTimeoutError, Sender, channel};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::{ChatConfig, ChatService};
+use crate::diff::{MemoChange, MemoChangeKind, diff_memos};
+use crate::server::list_memo_files;
+
+/// How long a file has to stay unchanged before it's reported
+const QUIET_PERIOD: Duration = Duration::from_secs(2);
+
+/// Headings listed per kind of change before the rest is summarized as a count
+const MAX_HEADINGS: usize = 5;
+
+/// Message text for a change to `path`; `new` is `None` for a deleted file.
+/// Returns `None` when no memo changed (e.g. only whitespace). Changes to an encrypted memo
+/// are reported without its headings.
+pub fn summarize(path: &str, old: Option<&str>, new: Option<&str>) -> Option<String> {
+    if crate::crypt::is_encrypted(Path::new(path)) {
+        return match (old, new) {
+            (_, None) => Some(format!("`{}` deleted", path)),
+            (None, Some(_)) => Some(format!("`{}` created", path)),
+            (Some(old), Some(new)) => (old != new).then(|| format!("`{}` updated", path)),
+        };
+    }
+    let (Some(old), Some(new)) = (old, new) else {
+        return Some(match new {
+            Some(new) => format_changes(&format!("`{}` created", path), &diff_memos("", new)),
+            None => format!("`{}` deleted", path),
+        });
+    };
+    let changes = diff_memos(old, new);
+    if changes.is_empty() {
+        return None;
+    }
+    Some(format_changes(&format!("`{}` updated", path), &changes))
+}
+
+fn format_changes(header: &str, changes: &[MemoChange]) -> String {
+    let mut text = header.to_string();
+    for (kind, label) in [
+        (MemoChangeKind::Added, "added"),
+        (MemoChangeKind::Changed, "changed"),
+        (MemoChangeKind::Removed, "removed"),
+    ] {
+        let headings: Vec<String> = changes
+            .iter()
+            .filter(|change| change.kind == kind)
+            .map(|change| change.titles.join(" > "))
+            .collect();
+        if headings.is_empty() {
+            continue;
+        }
+        text.push_str(&format!(
+            "\n• {}: {}",
+            label,
+            headings[..headings.len().min(MAX_HEADINGS)].join(", ")
+        ));
+        if headings.len() > MAX_HEADINGS {
+            text.push_str(&format!(" and {} more", headings.len() - MAX_HEADINGS));
+        }
+    }
+    text
+}
+
+/// What is compared to tell how a file changed: its memo, or for an encrypted one only a
+/// hash of the ciphertext, so decrypted memos never reach the chat
+fn snapshot(root: &Path, path: &str) -> Option<String> {
+    use sha2::{Digest, Sha256};
+
+    let file = root.join(path);
+    if crate::crypt::is_encrypted(&file) {
+        let data = std::fs::read(&file).ok()?;
+        return Some(crate::crypt::to_hex(&Sha256::digest(data)));
+    }
+    crate::crypt::read_memo(&file).ok()
+}
+
+/// Request body for the service's incoming webhook
+pub fn message_body(service: ChatService, text: &str) -> serde_json::Value {
+    match service {
+        ChatService::Slack => serde_json::json!({ "text": text }),
+        ChatService::Discord => serde_json::json!({ "content": text }),
+    }
+}
+
+/// Whether a file (relative to the root) is below one of the configured directories
+fn matches_directories(config: &ChatConfig, path: &str) -> bool {
+    config.directories.is_empty()
+        || config.directories.iter().any(|dir| {
+            let dir = dir.trim_matches('/');
+            dir.is_empty()
+                || path
+                    .strip_prefix(dir)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+}
+
+/// Posts chat messages from a background thread. Changes are collected until files have
+/// been quiet for a moment, then each file is read and compared with the content seen
+/// last time (all memo files are read once at startup) to tell which headings changed.
+#[derive(Debug, Clone)]
+pub struct ChatNotifier {
+    sender: Sender<String>,
+}
+
+impl ChatNotifier {
+    /// Start the notifier for the memos below `root`; `None` without chat configs
+    pub fn start(root: PathBuf, configs: Vec<ChatConfig>) -> Option<Self> {
+        if configs.is_empty() {
+            return None;
+        }
+        let (sender, receiver) = channel::<String>();
+        thread::spawn(move || {
+            let mut contents: HashMap<String, String> = list_memo_files(&root)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|file| Some((file.clone(), snapshot(&root, &file)?)))
+                .collect();
+            let agent = ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(10))
+                .build();
+            let mut pending = BTreeSet::new();
+
+            loop {
+                match receiver.recv_timeout(QUIET_PERIOD) {
+                    Ok(path) => {
+                        pending.insert(path);
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                for path in std::mem::take(&mut pending) {
+                    let new = snapshot(&root, &path);
+                    let old = match &new {
+                        Some(new) => contents.insert(path.clone(), new.clone()),
+                        None => contents.remove(&path),
+                    };
+                    if old.is_none() && new.is_none() {
+                        continue;
+                    }
+                    let Some(text) = summarize(&path, old.as_deref(), new.as_deref()) else {
+                        continue;
+                    };
+                    for config in configs
+                        .iter()
+                        .filter(|config| matches_directories(config, &path))
+                    {
+                        let body = message_body(config.service, &text).to_string();
+                        if let Err(e) = agent
+                            .post(&config.url)
+                            .set("Content-Type", "application/json")
+                            .send_string(&body)
+                        {
+                            eprintln!("Chat notification to {} failed: {}", config.url, e);
+                        }
+                    }
+                }
+            }
+        });
+        Some(Self { sender })
+    }
+
+    /// A memo file (relative to the root) was written, created or deleted
+    pub fn file_changed(&self, path: &str) {
+        // Only fails once the notifier thread is gone, and then there's nobody to tell
+        let _ = self.sender.send(path.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{matches_directories, message_body, snapshot, summarize};
+    use crate::config::{ChatConfig, ChatService};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_summarize() {
+        let old = "# Plan\n## Mon\ngym\n## Tue\n";
+        let new = "# Plan\n## Mon\nrest\n## Wed\n";
+        assert_eq!(
+            summarize("team/plan.fmemo", Some(old), Some(new)).unwrap(),
+            "`team/plan.fmemo` updated\n• added: Plan > Wed\n• changed: Plan > Mon\n• removed: Plan > Tue"
+        );
+        assert_eq!(
+            summarize("a.fmemo", Some(old), Some(&format!("{}\n\n", old))),
+            None
+        );
+        assert_eq!(
+            summarize("a.fmemo", Some(old), None).unwrap(),
+            "`a.fmemo` deleted"
+        );
+
+        let many: String = (1..=7).map(|i| format!("# H{}\n", i)).collect();
+        assert_eq!(
+            summarize("a.fmemo", None, Some(&many)).unwrap(),
+            "`a.fmemo` created\n• added: H1, H2, H3, H4, H5 and 2 more"
+        );
+    }
+
+    #[test]
+    fn test_encrypted_memos_are_not_summarized() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        crate::crypt::Vault::create(root, b"passphrase", 10).unwrap();
+        crate::crypt::unlock(root, b"passphrase").unwrap();
+        crate::crypt::write_memo(&root.join("s.fmemox"), "# Secret plan\nbody").unwrap();
+
+        let old = snapshot(root, "s.fmemox").unwrap();
+        assert!(!old.contains("Secret"));
+        crate::crypt::write_memo(&root.join("s.fmemox"), "# Secret plan\n## Launch\n").unwrap();
+        let new = snapshot(root, "s.fmemox").unwrap();
+        crate::crypt::lock(root);
+        assert_eq!(
+            summarize("s.fmemox", Some(&old), Some(&new)).unwrap(),
+            "`s.fmemox` updated"
+        );
+        assert_eq!(summarize("s.fmemox", Some(&old), Some(&old)), None);
+        assert_eq!(
+            summarize("s.fmemox", None, Some("# Secret plan")).unwrap(),
+            "`s.fmemox` created"
+        );
+    }
+
+    #[test]
+    fn test_directory_filter_and_body() {
+        let mut config = ChatConfig {
+            service: ChatService::Discord,
+            url: "https://discord.example/hook".to_string(),
+            directories: Vec::new(),
+        };
+        assert!(matches_directories(&config, "a.fmemo"));
+        config.directories = vec!["team/".to_string(), "projects/x".to_string()];
+        assert!(matches_directories(&config, "team/a.fmemo"));
+        assert!(matches_directories(&config, "projects/x/deep/b.md"));
+        assert!(!matches_directories(&config, "teamwork/a.fmemo"));
+        assert!(!matches_directories(&config, "a.fmemo"));
+
+        assert_eq!(
+            message_body(ChatService::Discord, "hi"),
+            serde_json::json!({"content": "hi"})
+        );
+        assert_eq!(
+            message_body(ChatService::Slack, "hi"),
+            serde_json::json!({"text": "hi"})
+        );
+    }
+}
@@ -0,0 +1,558 @@
+//! Collaborative editing over the WebSocket, with operational transformation.
+//!
+//! The server holds the authoritative text of every file being edited. Clients send
+//! operations against the revision they last saw; operations that were applied since are
+//! transformed in, the result is written to disk, acknowledged to the sender and
+//! forwarded to the file's other editors. Changes made to the file outside the session
+//! are picked up as operations of their own.
+//!
+//! Client messages (`path` is relative to the root):
+//!
+//! - `{"type": "edit_join", "path": ...}` - answered with
+//!   `{"type": "edit_state", "path", "revision", "content"}`
+//! - `{"type": "edit", "path", "revision", "ops"}` - answered with
+//!   `{"type": "edit_ack", "path", "revision"}`; other editors get
+//!   `{"type": "edit", "path", "revision", "ops"}`
+//! - `{"type": "edit_leave", "path": ...}`
+//!
+//! Failures are answered with `{"type": "edit_error", "path", "error"}`; the client
+//! should join again to resynchronize.
+//!
+//! Operations are arrays as in ot.js: a positive number retains that many characters, a
+//! string inserts it and a negative number deletes that many characters. Lengths count
+//! Unicode scalar values.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use serde_json::{Value, json};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::schema::{WsClientMessage, WsServerMessage};
+use crate::server::{ClientId, INVALID_MEMO_PATH, resolve_memo_path};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Retain(usize),
+    Insert(String),
+    Delete(usize),
+}
+
+/// A sequence of operations covering a whole text
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "Vec<Value>", into = "Vec<Value>")]
+pub struct TextOperation {
+    ops: Vec<Op>,
+}
+
+impl TryFrom<Vec<Value>> for TextOperation {
+    type Error = String;
+
+    fn try_from(values: Vec<Value>) -> Result<Self, Self::Error> {
+        let mut operation = TextOperation::default();
+        for value in values {
+            match value {
+                Value::String(text) => operation.insert(&text),
+                Value::Number(n) => match n.as_i64() {
+                    Some(n) if n >= 0 => operation.retain(n as usize),
+                    Some(n) => operation.delete(n.unsigned_abs() as usize),
+                    None => return Err(format!("Invalid operation {}", n)),
+                },
+                other => return Err(format!("Invalid operation {}", other)),
+            }
+        }
+        Ok(operation)
+    }
+}
+
+impl From<TextOperation> for Vec<Value> {
+    fn from(operation: TextOperation) -> Self {
+        operation
+            .ops
+            .into_iter()
+            .map(|op| match op {
+                Op::Retain(n) => json!(n),
+                Op::Insert(text) => json!(text),
+                Op::Delete(n) => json!(-(n as i64)),
+            })
+            .collect()
+    }
+}
+
+impl TextOperation {
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    pub fn retain(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        match self.ops.last_mut() {
+            Some(Op::Retain(last)) => *last += n,
+            _ => self.ops.push(Op::Retain(n)),
+        }
+    }
+
+    /// Inserts go before a delete at the same position, so equal edits look the same
+    pub fn insert(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let len = self.ops.len();
+        match self.ops.as_mut_slice() {
+            [.., Op::Insert(last)] | [.., Op::Insert(last), Op::Delete(_)] => last.push_str(text),
+            [.., Op::Delete(_)] => self.ops.insert(len - 1, Op::Insert(text.to_string())),
+            _ => self.ops.push(Op::Insert(text.to_string())),
+        }
+    }
+
+    pub fn delete(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        match self.ops.last_mut() {
+            Some(Op::Delete(last)) => *last += n,
+            _ => self.ops.push(Op::Delete(n)),
+        }
+    }
+
+    /// Length of the text the operation applies to
+    pub fn base_len(&self) -> usize {
+        self.ops
+            .iter()
+            .map(|op| match op {
+                Op::Retain(n) | Op::Delete(n) => *n,
+                Op::Insert(_) => 0,
+            })
+            .sum()
+    }
+
+    /// The operation turning `old` into `new`: one replacement between their common
+    /// prefix and suffix
+    pub fn from_diff(old: &str, new: &str) -> Self {
+        let old: Vec<char> = old.chars().collect();
+        let new: Vec<char> = new.chars().collect();
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let mut operation = TextOperation::default();
+        operation.retain(prefix);
+        operation.delete(old.len() - prefix - suffix);
+        operation.insert(&new[prefix..new.len() - suffix].iter().collect::<String>());
+        operation.retain(suffix);
+        operation
+    }
+
+    pub fn apply(&self, text: &str) -> Result<String, String> {
+        let len = text.chars().count();
+        if self.base_len() != len {
+            return Err(format!(
+                "Operation is for a text of {} characters, not {}",
+                self.base_len(),
+                len
+            ));
+        }
+        let mut chars = text.chars();
+        let mut result = String::with_capacity(text.len());
+        for op in &self.ops {
+            match op {
+                Op::Retain(n) => result.extend(chars.by_ref().take(*n)),
+                Op::Insert(inserted) => result.push_str(inserted),
+                Op::Delete(n) => {
+                    chars.by_ref().take(*n).for_each(drop);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Transform two operations on the same text so that applying `a` then `b'` gives
+    /// the same as `b` then `a'`. Inserts of `a` go first when both insert at one place.
+    pub fn transform(a: &Self, b: &Self) -> Result<(Self, Self), String> {
+        if a.base_len() != b.base_len() {
+            return Err("Operations are for different texts".to_string());
+        }
+        let (mut a_prime, mut b_prime) = (Self::default(), Self::default());
+        let mut ops_a = a.ops.iter().cloned();
+        let mut ops_b = b.ops.iter().cloned();
+        let (mut op_a, mut op_b) = (ops_a.next(), ops_b.next());
+        loop {
+            match (op_a.take(), op_b.take()) {
+                (None, None) => break,
+                (Some(Op::Insert(text)), other) => {
+                    b_prime.retain(text.chars().count());
+                    a_prime.insert(&text);
+                    op_a = ops_a.next();
+                    op_b = other;
+                }
+                (other, Some(Op::Insert(text))) => {
+                    a_prime.retain(text.chars().count());
+                    b_prime.insert(&text);
+                    op_a = other;
+                    op_b = ops_b.next();
+                }
+                (Some(first), Some(second)) => {
+                    let len = |op: &Op| match op {
+                        Op::Retain(n) | Op::Delete(n) => *n,
+                        Op::Insert(_) => 0,
+                    };
+                    let n = len(&first).min(len(&second));
+                    match (&first, &second) {
+                        (Op::Retain(_), Op::Retain(_)) => {
+                            a_prime.retain(n);
+                            b_prime.retain(n);
+                        }
+                        (Op::Delete(_), Op::Retain(_)) => a_prime.delete(n),
+                        (Op::Retain(_), Op::Delete(_)) => b_prime.delete(n),
+                        // Both deleted the same text
+                        _ => {}
+                    }
+                    let rest = |op: Op| match op {
+                        Op::Retain(m) if m > n => Some(Op::Retain(m - n)),
+                        Op::Delete(m) if m > n => Some(Op::Delete(m - n)),
+                        _ => None,
+                    };
+                    op_a = rest(first).or_else(|| ops_a.next());
+                    op_b = rest(second).or_else(|| ops_b.next());
+                }
+                _ => return Err("Operations are for different texts".to_string()),
+            }
+        }
+        Ok((a_prime, b_prime))
+    }
+}
+
+type Sender = UnboundedSender<warp::ws::Message>;
+
+struct Session {
+    content: String,
+    /// Operation `i` turned revision `i` into revision `i + 1`
+    history: Vec<TextOperation>,
+    editors: Vec<(ClientId, Sender)>,
+}
+
+impl Session {
+    fn send_others(&self, client: Option<ClientId>, message: &WsServerMessage) {
+        let text = serde_json::to_string(message).unwrap_or_default();
+        for (id, sender) in &self.editors {
+            if Some(*id) != client {
+                let _ = sender.send(warp::ws::Message::text(text.clone()));
+            }
+        }
+    }
+}
+
+/// Editing sessions of the files below a root; clones share the sessions
+#[derive(Clone)]
+pub struct Collab {
+    root: PathBuf,
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+}
+
+impl std::fmt::Debug for Collab {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Collab").field("root", &self.root).finish()
+    }
+}
+
+fn send(sender: &Sender, message: WsServerMessage) {
+    if let Ok(text) = serde_json::to_string(&message) {
+        let _ = sender.send(warp::ws::Message::text(text));
+    }
+}
+
+impl Collab {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: root.into(),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Handle a message from a client; returns `false` for messages that aren't about
+    /// editing
+    pub fn handle_message(
+        &self,
+        client: ClientId,
+        sender: &Sender,
+        message: &WsClientMessage,
+    ) -> bool {
+        let (path, result) = match message {
+            WsClientMessage::EditJoin { path } => {
+                let path = path.trim_start_matches('/');
+                (path, self.join(client, sender, path))
+            }
+            WsClientMessage::Edit {
+                path,
+                revision,
+                ops,
+            } => {
+                let path = path.trim_start_matches('/');
+                (
+                    path,
+                    self.edit(client, sender, path, *revision, ops.clone()),
+                )
+            }
+            WsClientMessage::EditLeave { path } => {
+                let path = path.trim_start_matches('/');
+                self.leave(client, Some(path));
+                (path, Ok(()))
+            }
+            _ => return false,
+        };
+        if let Err(error) = result {
+            send(
+                sender,
+                WsServerMessage::EditError {
+                    path: path.to_string(),
+                    error,
+                },
+            );
+        }
+        true
+    }
+
+    /// Forget a closed connection
+    pub fn disconnect(&self, client: ClientId) {
+        self.leave(client, None);
+    }
+
+    fn leave(&self, client: ClientId, path: Option<&str>) {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.retain(|session_path, session| {
+            if path.is_none_or(|path| path == session_path) {
+                session.editors.retain(|(id, _)| *id != client);
+            }
+            !session.editors.is_empty()
+        });
+    }
+
+    /// Bring a session up to date with the file on disk
+    fn sync_from_disk(session: &mut Session, path: &str, disk: String) {
+        if disk == session.content {
+            return;
+        }
+        let operation = TextOperation::from_diff(&session.content, &disk);
+        session.content = disk;
+        session.history.push(operation.clone());
+        let message = WsServerMessage::Edit {
+            path: path.to_string(),
+            revision: session.history.len(),
+            ops: operation,
+        };
+        session.send_others(None, &message);
+    }
+
+    fn read(&self, path: &str) -> Result<String, String> {
+        let file_path = resolve_memo_path(&self.root, path).ok_or(INVALID_MEMO_PATH)?;
+        crate::crypt::read_memo(&file_path).map_err(|e| e.to_string())
+    }
+
+    fn join(&self, client: ClientId, sender: &Sender, path: &str) -> Result<(), String> {
+        let disk = self.read(path)?;
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let session = sessions.entry(path.to_string()).or_insert_with(|| Session {
+            content: disk.clone(),
+            history: Vec::new(),
+            editors: Vec::new(),
+        });
+        Self::sync_from_disk(session, path, disk);
+        session.editors.retain(|(id, _)| *id != client);
+        session.editors.push((client, sender.clone()));
+        send(
+            sender,
+            WsServerMessage::EditState {
+                path: path.to_string(),
+                revision: session.history.len(),
+                content: session.content.clone(),
+            },
+        );
+        Ok(())
+    }
+
+    fn edit(
+        &self,
+        client: ClientId,
+        sender: &Sender,
+        path: &str,
+        revision: usize,
+        mut operation: TextOperation,
+    ) -> Result<(), String> {
+        let disk = self.read(path)?;
+
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let session = sessions
+            .get_mut(path)
+            .filter(|session| session.editors.iter().any(|(id, _)| *id == client))
+            .ok_or("Join the file before editing it")?;
+        if revision > session.history.len() {
+            return Err(format!("Unknown revision {}", revision));
+        }
+        // Edits made outside the session are concurrent with this one too
+        Self::sync_from_disk(session, path, disk);
+
+        for concurrent in &session.history[revision..] {
+            operation = TextOperation::transform(&operation, concurrent)?.0;
+        }
+        let content = operation.apply(&session.content)?;
+        let file_path = resolve_memo_path(&self.root, path).ok_or(INVALID_MEMO_PATH)?;
+        crate::crypt::write_memo(&file_path, &content).map_err(|e| e.to_string())?;
+
+        session.content = content;
+        session.history.push(operation.clone());
+        let revision = session.history.len();
+        send(
+            sender,
+            WsServerMessage::EditAck {
+                path: path.to_string(),
+                revision,
+            },
+        );
+        session.send_others(
+            Some(client),
+            &WsServerMessage::Edit {
+                path: path.to_string(),
+                revision,
+                ops: operation,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Collab, TextOperation};
+    use crate::schema::WsClientMessage;
+    use serde_json::{Value, json};
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn op(value: Value) -> TextOperation {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_transform_converges() {
+        let text = "hello world";
+        let cases = [
+            (json!([5, " there", 6]), json!([6, -5, "rust"])),
+            (json!(["a", 11]), json!(["b", 11])),
+            (json!([2, -5, 4]), json!([4, -5, 2])),
+            (json!([-11]), json!([11, "!"])),
+        ];
+        for (a, b) in cases {
+            let (a, b) = (op(a), op(b));
+            let (a_prime, b_prime) = TextOperation::transform(&a, &b).unwrap();
+            let left = b_prime.apply(&a.apply(text).unwrap()).unwrap();
+            let right = a_prime.apply(&b.apply(text).unwrap()).unwrap();
+            assert_eq!(left, right);
+        }
+        let (a, b) = (op(json!(["a", 11])), op(json!(["b", 11])));
+        let (_, b_prime) = TextOperation::transform(&a, &b).unwrap();
+        assert_eq!(
+            b_prime.apply(&a.apply(text).unwrap()).unwrap(),
+            "abhello world"
+        );
+        assert!(op(json!([3])).apply(text).is_err());
+    }
+
+    #[test]
+    fn test_from_diff() {
+        let operation = TextOperation::from_diff("# Tïtle\nbody", "# Tïtle\nnew body");
+        assert_eq!(
+            serde_json::to_value(&operation).unwrap(),
+            json!([8, "new ", 4])
+        );
+        let operation = TextOperation::from_diff("aaa", "a");
+        assert_eq!(operation.apply("aaa").unwrap(), "a");
+    }
+
+    fn message(value: Value) -> WsClientMessage {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn receive(rx: &mut tokio::sync::mpsc::UnboundedReceiver<warp::ws::Message>) -> Value {
+        let message = rx.try_recv().unwrap();
+        serde_json::from_str(message.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_concurrent_edits() {
+        let temp_dir = TempDir::new().unwrap();
+        let file = temp_dir.path().join("a.fmemo");
+        fs::write(&file, "# A\nbody").unwrap();
+        let collab = Collab::new(temp_dir.path());
+        let (tx1, mut rx1) = tokio::sync::mpsc::unbounded_channel();
+        let (tx2, mut rx2) = tokio::sync::mpsc::unbounded_channel();
+        let (c1, c2) = (1, 2);
+
+        assert!(collab.handle_message(
+            c1,
+            &tx1,
+            &message(json!({"type": "edit_join", "path": "a.fmemo"}))
+        ));
+        assert!(collab.handle_message(
+            c2,
+            &tx2,
+            &message(json!({"type": "edit_join", "path": "a.fmemo"}))
+        ));
+        assert_eq!(receive(&mut rx1)["content"], "# A\nbody");
+        assert_eq!(receive(&mut rx2)["revision"], 0);
+
+        // Both edit revision 0
+        collab.handle_message(
+            c1,
+            &tx1,
+            &message(
+                json!({"type": "edit", "path": "a.fmemo", "revision": 0, "ops": [3, "BC", 5]}),
+            ),
+        );
+        collab.handle_message(
+            c2,
+            &tx2,
+            &message(json!({"type": "edit", "path": "a.fmemo", "revision": 0, "ops": [8, "!"]})),
+        );
+        assert_eq!(fs::read_to_string(&file).unwrap(), "# ABC\nbody!");
+        assert_eq!(
+            receive(&mut rx1),
+            json!({"type": "edit_ack", "path": "a.fmemo", "revision": 1})
+        );
+        assert_eq!(
+            receive(&mut rx1),
+            json!({"type": "edit", "path": "a.fmemo", "revision": 2, "ops": [10, "!"]})
+        );
+        assert_eq!(receive(&mut rx2)["ops"], json!([3, "BC", 5]));
+        assert_eq!(receive(&mut rx2)["type"], "edit_ack");
+
+        // A change on disk reaches the editors before the next edit applies
+        fs::write(&file, "# ABC\nbody!\nmore").unwrap();
+        collab.handle_message(
+            c1,
+            &tx1,
+            &message(json!({"type": "edit", "path": "a.fmemo", "revision": 2, "ops": [-2, 9]})),
+        );
+        assert_eq!(fs::read_to_string(&file).unwrap(), "ABC\nbody!\nmore");
+        assert_eq!(receive(&mut rx1)["ops"], json!([11, "\nmore"]));
+        assert_eq!(receive(&mut rx1)["revision"], 4);
+
+        collab.disconnect(c1);
+        collab.handle_message(
+            c1,
+            &tx1,
+            &message(json!({"type": "edit", "path": "a.fmemo", "revision": 4, "ops": [16]})),
+        );
+        assert_eq!(receive(&mut rx1)["type"], "edit_error");
+        assert!(!collab.handle_message(
+            c1,
+            &tx1,
+            &message(json!({"type": "viewing", "path": "a.fmemo"}))
+        ));
+    }
+}
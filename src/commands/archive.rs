@@ -0,0 +1,58 @@
+//! `fmemo archive` - move memos nobody touched for a while into the archive directory
+
+use clap::{Arg, ArgMatches, Command};
+use fmemo::archive::{archive, cutoff};
+use fmemo::config::load_config;
+
+use super::{CommandResult, root_arg, root_dir};
+
+pub fn command() -> Command {
+    Command::new("archive")
+        .about("Move memo files not modified for a while into the archive directory")
+        .arg(root_arg())
+        .arg(
+            Arg::new("older-than")
+                .long("older-than")
+                .value_name("AGE")
+                .help("Age like 1y, 6m, 2w or 30d [default: [archive] older_than, or 1y]"),
+        )
+        .arg(
+            Arg::new("dest").long("dest").value_name("DIR").help(
+                "Archive directory relative to the root [default: [archive] dir, or archive]",
+            ),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .help("Only list the files that would be moved")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> CommandResult {
+    let root = root_dir(matches);
+    let config = load_config(&root)?.archive;
+    let older_than = matches
+        .get_one::<String>("older-than")
+        .unwrap_or(&config.older_than);
+    let dest = matches.get_one::<String>("dest").unwrap_or(&config.dir);
+    let dry_run = matches.get_flag("dry-run");
+
+    let report = archive(
+        &root,
+        dest,
+        cutoff(chrono::Utc::now(), older_than)?,
+        dry_run,
+    )?;
+    for file in &report.archived {
+        let verb = if dry_run { "Would move" } else { "Moved" };
+        println!("{} {} -> {}", verb, file.from, file.to);
+    }
+    for file in &report.skipped {
+        eprintln!("Skipped {}: already in the archive", file);
+    }
+    if report.archived.is_empty() {
+        println!("No memo files older than {}", older_than);
+    }
+    Ok(())
+}
@@ -0,0 +1,211 @@
+//! Running the server in the background with `fmemo --daemon`
+//!
+//! `--daemon` starts the same command again as a detached process (without `--daemon`) and
+//! records its PID in `$XDG_RUNTIME_DIR/fmemo/fmemo-<port>.pid`, with its output going to
+//! `fmemo-<port>.log` next to it. Servers are told apart by port; `fmemo stop` and `fmemo status` find them
+//! through the PID file.
+//!
+//! The directory has to be private to the user (`0700` and their own), since whoever can write
+//! to it could point the log at another file or the PID file at another process. A PID is
+//! only taken for a server's when that process runs the fmemo binary.
+
+use clap::{Arg, ArgMatches};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use super::CommandResult;
+
+/// How long a new daemon has to stay up before `--daemon` reports it as started
+const STARTUP_CHECK: Duration = Duration::from_millis(500);
+
+/// `-p/--port` of the background server, for `stop` and `status`
+pub fn port_arg() -> Arg {
+    Arg::new("port")
+        .short('p')
+        .long("port")
+        .value_name("PORT")
+        .help("Port of the background server")
+        .default_value("3030")
+}
+
+pub fn port(matches: &ArgMatches) -> Result<u16, &'static str> {
+    matches
+        .get_one::<String>("port")
+        .unwrap()
+        .parse()
+        .map_err(|_| "Port must be a valid number")
+}
+
+/// `$XDG_RUNTIME_DIR/fmemo`, or `~/.cache/fmemo` without one
+fn runtime_dir() -> PathBuf {
+    let dir = |var: &str| std::env::var_os(var).filter(|dir| !dir.is_empty());
+    match dir("XDG_RUNTIME_DIR") {
+        Some(runtime) => PathBuf::from(runtime).join("fmemo"),
+        None => dir("HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir)
+            .join(".cache")
+            .join("fmemo"),
+    }
+}
+
+pub fn pid_file(port: u16) -> PathBuf {
+    runtime_dir().join(format!("fmemo-{}.pid", port))
+}
+
+pub fn log_file(port: u16) -> PathBuf {
+    runtime_dir().join(format!("fmemo-{}.log", port))
+}
+
+/// Refuse a runtime directory that isn't a directory of the current user, closed to others
+#[cfg(unix)]
+fn check_private(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let metadata = std::fs::symlink_metadata(dir)?;
+    // SAFETY: geteuid has no preconditions and can't fail
+    let uid = unsafe { libc::geteuid() };
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.permissions().mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} must be a directory of your own that only you can access (mode 0700)",
+                dir.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_private(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// The runtime directory, created private when it's missing
+#[cfg(unix)]
+fn create_runtime_dir() -> io::Result<PathBuf> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    let dir = runtime_dir();
+    if let Some(parent) = dir.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+    check_private(&dir)?;
+    Ok(dir)
+}
+
+fn is_alive(pid: u32) -> bool {
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Whether process `pid` runs the fmemo binary: its `/proc/<pid>/exe`, or the command `ps`
+/// reports where there's no `/proc`
+fn is_fmemo(pid: u32) -> bool {
+    let Some(name) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.file_name()?.to_string_lossy().into_owned()))
+    else {
+        return false;
+    };
+    if Path::new("/proc/self/exe").exists() {
+        // A binary replaced since the server started reads as "fmemo (deleted)"
+        return std::fs::read_link(format!("/proc/{}/exe", pid)).is_ok_and(|exe| {
+            exe.file_name()
+                .is_some_and(|exe| exe.to_string_lossy().trim_end_matches(" (deleted)") == name)
+        });
+    }
+    std::process::Command::new("ps")
+        .args(["-p", &pid.to_string(), "-o", "comm="])
+        .stderr(Stdio::null())
+        .output()
+        .is_ok_and(|output| {
+            let command = String::from_utf8_lossy(&output.stdout);
+            Path::new(command.trim())
+                .file_name()
+                .is_some_and(|comm| comm.to_string_lossy() == name)
+        })
+}
+
+/// PID of the running daemon on `port`; a PID file left by one that died, or naming a process
+/// that isn't fmemo, is removed
+pub fn running_pid(port: u16) -> io::Result<Option<u32>> {
+    let path = pid_file(port);
+    match check_private(&runtime_dir()) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        result => result?,
+    }
+    let pid = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|pid| pid.trim().parse().ok());
+    match pid {
+        Some(pid) if is_alive(pid) && is_fmemo(pid) => Ok(Some(pid)),
+        _ => {
+            let _ = std::fs::remove_file(&path);
+            Ok(None)
+        }
+    }
+}
+
+/// Start the command line again in the background without `--daemon`
+#[cfg(unix)]
+pub fn start(port: u16) -> CommandResult {
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::process::CommandExt;
+
+    create_runtime_dir()?;
+    if let Some(pid) = running_pid(port)? {
+        return Err(format!("fmemo is already running on port {} (pid {})", port, pid).into());
+    }
+    // Never through a symlink planted in place of the log
+    let log = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(log_file(port))?;
+    let args = std::env::args_os().skip(1).filter(|arg| arg != "--daemon");
+    let mut child = std::process::Command::new(std::env::current_exe()?)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        // A process group of its own, so the terminal's Ctrl-C and hangup don't reach it
+        .process_group(0)
+        .spawn()?;
+
+    std::thread::sleep(STARTUP_CHECK);
+    if let Some(status) = child.try_wait()? {
+        return Err(format!(
+            "fmemo exited right away ({}); see {}",
+            status,
+            log_file(port).display()
+        )
+        .into());
+    }
+    std::fs::write(pid_file(port), format!("{}\n", child.id()))?;
+    println!(
+        "fmemo is running in the background on port {} (pid {})",
+        port,
+        child.id()
+    );
+    println!("Log: {}", log_file(port).display());
+    println!("Stop it with: fmemo stop -p {}", port);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn start(_port: u16) -> CommandResult {
+    Err("--daemon is only supported on Unix".into())
+}
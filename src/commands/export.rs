@@ -0,0 +1,106 @@
+//! `fmemo export` - dump every memo below the root, or render it as a static site or as
+//! pages of a Hugo or Jekyll site
+
+use clap::{Arg, ArgMatches, Command};
+use fmemo::blog::{Generator, export_content};
+use fmemo::highlight::highlight_memos;
+use fmemo::plugin::Plugins;
+use fmemo::server::{list_memo_files, read_fmemo_file};
+use fmemo::static_site::export_site;
+use std::path::Path;
+
+use super::{CommandResult, root_arg, root_dir};
+
+pub fn command() -> Command {
+    Command::new("export")
+        .about("Export the parsed memos of every file below the root as JSON, a static site or blog pages")
+        .arg(root_arg())
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("FILE")
+                .help("Write to FILE instead of stdout")
+                .required(false),
+        )
+        .arg(
+            Arg::new("highlight")
+                .long("highlight")
+                .value_name("THEME")
+                .num_args(0..=1)
+                .default_missing_value(fmemo::highlight::DEFAULT_THEME)
+                .help("Add syntax highlighted HTML to code blocks, with a syntect theme")
+                .required(false),
+        )
+        .arg(
+            Arg::new("static")
+                .long("static")
+                .value_name("DIR")
+                .help("Render the memos as a static HTML site into DIR")
+                .conflicts_with_all(["output", "highlight"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("hugo")
+                .long("hugo")
+                .value_name("DIR")
+                .help("Write the memos as pages of a Hugo content directory DIR")
+                .conflicts_with_all(["output", "highlight", "static"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("jekyll")
+                .long("jekyll")
+                .value_name("DIR")
+                .help("Write the memos as pages of a Jekyll site in DIR")
+                .conflicts_with_all(["output", "highlight", "static", "hugo"])
+                .required(false),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> CommandResult {
+    let root = root_dir(matches);
+    if let Some(out) = matches.get_one::<String>("static") {
+        let mut plugins = Plugins::default();
+        plugins.load_installed(&root)?;
+        let summary = export_site(&root, Path::new(out), &plugins)?;
+        println!(
+            "Exported {} pages, {} indexes and {} assets to {}",
+            summary.pages, summary.indexes, summary.assets, out
+        );
+        return Ok(());
+    }
+    let content_dir = match (
+        matches.get_one::<String>("hugo"),
+        matches.get_one::<String>("jekyll"),
+    ) {
+        (Some(out), _) => Some((out, Generator::Hugo)),
+        (_, Some(out)) => Some((out, Generator::Jekyll)),
+        _ => None,
+    };
+    if let Some((out, generator)) = content_dir {
+        let mut plugins = Plugins::default();
+        plugins.load_installed(&root)?;
+        let pages = export_content(&root, Path::new(out), generator, &plugins)?;
+        println!("Exported {} pages to {}", pages, out);
+        return Ok(());
+    }
+    let mut files = Vec::new();
+    for path in list_memo_files(&root)? {
+        let mut content = read_fmemo_file(root.join(&path))?;
+        if let Some(theme) = matches.get_one::<String>("highlight") {
+            highlight_memos(&mut content.memos, theme)?;
+        }
+        files.push(serde_json::json!({
+            "path": path,
+            "memos": content.memos,
+        }));
+    }
+    let export = serde_json::to_string_pretty(&serde_json::json!({ "files": files }))?;
+
+    match matches.get_one::<String>("output") {
+        Some(output) => std::fs::write(output, export)?,
+        None => println!("{}", export),
+    }
+    Ok(())
+}
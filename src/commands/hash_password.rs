@@ -0,0 +1,22 @@
+//! `fmemo hash-password` - hash a password for `[users.<name>]` in `.fmemo/config.toml`
+
+use clap::{ArgMatches, Command};
+use std::io::BufRead;
+
+use super::CommandResult;
+
+pub fn command() -> Command {
+    Command::new("hash-password")
+        .about("Read a password from stdin and print its hash for [users.<name>] password")
+}
+
+pub fn run(_matches: &ArgMatches) -> CommandResult {
+    let mut password = String::new();
+    std::io::stdin().lock().read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        return Err("The password is empty".into());
+    }
+    println!("{}", fmemo::users::hash_password(password)?);
+    Ok(())
+}
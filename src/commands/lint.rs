@@ -0,0 +1,77 @@
+//! `fmemo lint` - memo hygiene checks for CI
+
+use clap::{Arg, ArgMatches, Command};
+use fmemo::lint::{fix_source, lint_file, spelling_issues};
+use fmemo::server::list_memo_files;
+use fmemo::spell::Spellchecker;
+
+use super::{CommandResult, root_arg, root_dir};
+
+pub fn command() -> Command {
+    Command::new("lint")
+        .about("Check memo files for unclosed blocks, bad tags, broken links, duplicate titles and, when enabled, spelling")
+        .arg(root_arg())
+        .arg(
+            Arg::new("files")
+                .value_name("FILE")
+                .help("Files to check, relative to the root [default: every memo file]")
+                .num_args(0..),
+        )
+        .arg(
+            Arg::new("fix")
+                .long("fix")
+                .help("Close unclosed <desc>, code fences and math blocks in place")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+/// Fails when any issue is left, so CI can gate on it
+pub fn run(matches: &ArgMatches) -> CommandResult {
+    let root = root_dir(matches);
+    let files = match matches.get_many::<String>("files") {
+        Some(files) => files.cloned().collect(),
+        None => list_memo_files(&root)?,
+    };
+
+    let speller = Spellchecker::for_root(&root)?;
+
+    let mut issue_count = 0;
+    for file in &files {
+        if matches.get_flag("fix") {
+            let path = root.join(file);
+            let content = std::fs::read_to_string(&path)?;
+            let fixed = fix_source(&content);
+            if fixed != content {
+                std::fs::write(&path, fixed)?;
+                println!("{}: fixed", file);
+            }
+        }
+        let mut issues = lint_file(&root, file)?;
+        if let Some(speller) = &speller {
+            issues.extend(spelling_issues(
+                &std::fs::read_to_string(root.join(file))?,
+                speller,
+            ));
+            issues.sort_by_key(|issue| issue.line);
+        }
+        for issue in issues {
+            let hint = if issue.rule.is_fixable() {
+                " (fixable with --fix)"
+            } else {
+                ""
+            };
+            println!("{}:{}: {}{}", file, issue.line, issue.message, hint);
+            issue_count += 1;
+        }
+    }
+
+    if issue_count > 0 {
+        return Err(format!(
+            "{} issue(s) in {} file(s) checked",
+            issue_count,
+            files.len()
+        )
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,19 @@
+//! `fmemo lsp` - language server for editors, over stdio
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command};
+
+use super::{CommandResult, root_arg, root_dir};
+
+pub fn command() -> Command {
+    Command::new("lsp")
+        .about("Run a language server for memo files over stdio")
+        .arg(root_arg().help("Root directory of the memos [default: the editor's workspace]"))
+}
+
+pub fn run(matches: &ArgMatches) -> CommandResult {
+    let root =
+        (matches.value_source("root") == Some(ValueSource::CommandLine)).then(|| root_dir(matches));
+    fmemo::lsp::run(root, std::io::stdin().lock(), std::io::stdout().lock())?;
+    Ok(())
+}
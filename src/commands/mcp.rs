@@ -0,0 +1,31 @@
+//! `fmemo mcp` - Model Context Protocol server for AI assistants, over stdio
+
+use clap::{Arg, ArgMatches, Command};
+
+use super::{CommandResult, root_arg, root_dir};
+
+pub fn command() -> Command {
+    Command::new("mcp")
+        .about("Let AI assistants list, search, read and write memos over MCP (stdio)")
+        .arg(root_arg())
+        .arg(
+            Arg::new("read-only")
+                .long("read-only")
+                .help("Don't offer the write_memo tool")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> CommandResult {
+    let root = root_dir(matches);
+    if !root.is_dir() {
+        return Err(format!("Root directory '{}' does not exist", root.display()).into());
+    }
+    fmemo::mcp::run(
+        root,
+        matches.get_flag("read-only"),
+        std::io::stdin().lock(),
+        std::io::stdout().lock(),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,55 @@
+//! Subcommands of the `fmemo` binary
+
+use clap::{Arg, ArgMatches, Command};
+use std::path::PathBuf;
+
+pub mod archive;
+pub mod daemon;
+pub mod export;
+pub mod hash_password;
+pub mod lint;
+pub mod lsp;
+pub mod mcp;
+pub mod new;
+pub mod parse;
+pub mod search;
+pub mod serve;
+pub mod status;
+pub mod stop;
+pub mod vault;
+
+pub type CommandResult = Result<(), Box<dyn std::error::Error>>;
+
+pub fn cli() -> Command {
+    serve::with_args(Command::new("fmemo"))
+        .version("0.1.0")
+        .about("Real-time Markdown memo server with React frontend")
+        .args_conflicts_with_subcommands(true)
+        .subcommand(serve::command())
+        .subcommand(parse::command())
+        .subcommand(search::command())
+        .subcommand(export::command())
+        .subcommand(lint::command())
+        .subcommand(new::command())
+        .subcommand(lsp::command())
+        .subcommand(mcp::command())
+        .subcommand(stop::command())
+        .subcommand(status::command())
+        .subcommand(hash_password::command())
+        .subcommand(archive::command())
+        .subcommand(vault::command())
+}
+
+/// `-r/--root`, the directory holding the memos
+pub fn root_arg() -> Arg {
+    Arg::new("root")
+        .short('r')
+        .long("root")
+        .value_name("ROOT_DIR")
+        .help("Root directory to serve .fmemo files from")
+        .default_value(".")
+}
+
+pub fn root_dir(matches: &ArgMatches) -> PathBuf {
+    PathBuf::from(matches.get_one::<String>("root").unwrap())
+}
@@ -0,0 +1,63 @@
+//! `fmemo new` - create a memo file from a template
+
+use clap::{Arg, ArgMatches, Command};
+use fmemo::schema::NewMemoRequest;
+use fmemo::template::create_from_template;
+use std::path::Path;
+
+use super::{CommandResult, root_arg, root_dir};
+
+pub fn command() -> Command {
+    Command::new("new")
+        .about("Create a new memo file below the root from a template")
+        .arg(root_arg())
+        .arg(
+            Arg::new("path")
+                .value_name("PATH")
+                .help("File to create, relative to the root (.fmemo is added if missing) [default: from the title]")
+                .required_unless_present("title"),
+        )
+        .arg(
+            Arg::new("title")
+                .short('t')
+                .long("title")
+                .value_name("TITLE")
+                .help("Title of the memo [default: file name]"),
+        )
+        .arg(
+            Arg::new("template")
+                .long("template")
+                .value_name("NAME")
+                .help("Template from .fmemo/templates [default: the directory's configured template, or default]"),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> CommandResult {
+    let path = matches.get_one::<String>("path").cloned();
+    let title = match (matches.get_one::<String>("title"), &path) {
+        (Some(title), _) => title.clone(),
+        (None, Some(path)) => Path::new(path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        (None, None) => unreachable!("clap requires a path or a title"),
+    };
+    let request = NewMemoRequest {
+        title,
+        template: matches.get_one::<String>("template").cloned(),
+        path,
+    };
+
+    let root = root_dir(matches);
+    let created = create_from_template(&root, &request)?;
+    match created.cursor {
+        Some(cursor) => println!(
+            "Created {}:{}:{}",
+            root.join(&created.path).display(),
+            cursor.line,
+            cursor.column
+        ),
+        None => println!("Created {}", root.join(&created.path).display()),
+    }
+    Ok(())
+}
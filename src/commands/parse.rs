@@ -0,0 +1,53 @@
+//! `fmemo parse` - print the memo tree of a file
+
+use clap::{Arg, ArgMatches, Command};
+use fmemo::parser::parse_memo;
+use std::io::{Read, Write};
+
+use super::CommandResult;
+
+pub fn command() -> Command {
+    Command::new("parse")
+        .about("Parse a memo file (or stdin) and print its memo tree")
+        .arg(
+            Arg::new("file")
+                .value_name("FILE")
+                .help("File to parse; reads stdin when omitted or '-'")
+                .required(false),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Output format")
+                .value_parser(["json", "yaml"])
+                .default_value("json"),
+        )
+        .arg(
+            Arg::new("compact")
+                .long("compact")
+                .help("Print JSON on a single line")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> CommandResult {
+    let content = match matches.get_one::<String>("file").map(String::as_str) {
+        Some(file) if file != "-" => std::fs::read_to_string(file)?,
+        _ => {
+            let mut content = String::new();
+            std::io::stdin().read_to_string(&mut content)?;
+            content
+        }
+    };
+    let memos = parse_memo(&content);
+
+    let output = match matches.get_one::<String>("format").unwrap().as_str() {
+        "yaml" => serde_yaml::to_string(&memos)?,
+        _ if matches.get_flag("compact") => serde_json::to_string(&memos)?,
+        _ => serde_json::to_string_pretty(&memos)?,
+    };
+    // Write instead of println! so `fmemo parse | head` ends quietly on a closed pipe
+    writeln!(std::io::stdout().lock(), "{}", output.trim_end())?;
+    Ok(())
+}
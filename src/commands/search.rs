@@ -0,0 +1,45 @@
+//! `fmemo search` - find memos from the command line
+
+use clap::{Arg, ArgMatches, Command};
+
+use super::{CommandResult, root_arg, root_dir};
+
+pub fn command() -> Command {
+    Command::new("search")
+        .about("Search memo titles, descriptions and content")
+        .arg(root_arg())
+        .arg(
+            Arg::new("query")
+                .value_name("QUERY")
+                .help("Words that must all appear in a memo (case-insensitive)")
+                .required(true)
+                .num_args(1..),
+        )
+        .arg(
+            Arg::new("archived")
+                .long("archived")
+                .help("Also search the archive directory")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> CommandResult {
+    let query: Vec<&str> = matches
+        .get_many::<String>("query")
+        .unwrap()
+        .map(String::as_str)
+        .collect();
+    let root = root_dir(matches);
+    let mut hits = fmemo::search::search(&root, &query.join(" "))?;
+    if !matches.get_flag("archived") {
+        let dir = fmemo::config::load_config(&root)?.archive.dir;
+        hits.retain(|hit| !fmemo::archive::is_archived(&dir, &hit.file));
+    }
+    for hit in &hits {
+        println!("{}:{}: {} - {}", hit.file, hit.line, hit.title, hit.snippet);
+    }
+    if hits.is_empty() {
+        return Err("No matching memos".into());
+    }
+    Ok(())
+}
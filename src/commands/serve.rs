@@ -0,0 +1,332 @@
+//! `fmemo serve` - the memo server (also what plain `fmemo` runs)
+
+use clap::{Arg, ArgMatches, Command};
+use fmemo::access_log::{AccessLog, AccessLogFormat};
+use fmemo::{FmemoServer, Frontend};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+use super::{CommandResult, root_arg, root_dir};
+
+pub fn command() -> Command {
+    with_args(Command::new("serve").about("Serve memos over HTTP and WebSocket (default)"))
+}
+
+/// Server options, shared with the top-level command so `fmemo -r dir -p 8080` keeps working
+pub fn with_args(command: Command) -> Command {
+    command
+        .arg(root_arg())
+        .arg(
+            Arg::new("port")
+                .short('p')
+                .long("port")
+                .value_name("PORT")
+                .help("Port to serve on")
+                .default_value("3030"),
+        )
+        .arg(
+            Arg::new("host")
+                .long("host")
+                .value_name("HOST")
+                .help("Address to listen on (0.0.0.0 for every interface)")
+                .default_value("127.0.0.1"),
+        )
+        .arg(
+            Arg::new("unix-socket")
+                .long("unix-socket")
+                .value_name("PATH")
+                .help("Listen on a Unix domain socket instead of a TCP port")
+                .conflicts_with_all(["port", "host", "mdns", "open"]),
+        )
+        .arg(
+            Arg::new("base-path")
+                .long("base-path")
+                .value_name("PATH")
+                .help(
+                    "Serve everything under this path prefix, e.g. /notes behind a reverse proxy",
+                ),
+        )
+        .arg(
+            Arg::new("frontend")
+                .short('f')
+                .long("frontend")
+                .value_name("FRONTEND_DIR")
+                .help("Frontend dist directory (optional)")
+                .required(false),
+        )
+        .arg(
+            Arg::new("api-only")
+                .long("api-only")
+                .help("Run API server only, without frontend hosting")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dev")
+                .long("dev")
+                .help(
+                    "Development mode - serve API only, frontend runs separately on different port",
+                )
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("token")
+                .long("token")
+                .value_name("TOKEN")
+                .help("Require this bearer token for API and WebSocket requests")
+                .required(false),
+        )
+        .arg(
+            Arg::new("key-file")
+                .long("key-file")
+                .value_name("FILE")
+                .help("Unlock the encrypted (.fmemox) memos with this key file at startup"),
+        )
+        .arg(
+            Arg::new("git-autocommit")
+                .long("git-autocommit")
+                .help("Commit every memo change to the root's git repository")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("access-log")
+                .long("access-log")
+                .value_name("FILE")
+                .help("Log every request to this file (- for stdout)"),
+        )
+        .arg(
+            Arg::new("access-log-format")
+                .long("access-log-format")
+                .value_name("FORMAT")
+                .help("Access log line format")
+                .value_parser(["combined", "json"])
+                .default_value("combined"),
+        )
+        .arg(
+            Arg::new("publish")
+                .long("publish")
+                .help("Serve read-only, without the <private> regions of the memos")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("mdns")
+                .long("mdns")
+                .help("Announce the server on the local network over mDNS (_fmemo._tcp)")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("open")
+                .long("open")
+                .help("Open the served URL in the default browser once the server is up")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("daemon")
+                .long("daemon")
+                .help("Run in the background; stop it with `fmemo stop`")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-qr")
+                .long("no-qr")
+                .help("Don't print a QR code of the network URL")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+pub async fn run(matches: &ArgMatches) -> CommandResult {
+    let root_dir = root_dir(matches);
+    let port: u16 = matches
+        .get_one::<String>("port")
+        .unwrap()
+        .parse()
+        .map_err(|_| "Port must be a valid number")?;
+    let host: std::net::IpAddr = matches
+        .get_one::<String>("host")
+        .unwrap()
+        .parse()
+        .map_err(|_| "Host must be an IP address")?;
+    if matches.get_flag("daemon") {
+        return super::daemon::start(port);
+    }
+    // `notes/` for `--base-path /notes`, appended to the printed URLs
+    let base_path = matches
+        .get_one::<String>("base-path")
+        .map(|base_path| base_path.trim_matches('/'))
+        .filter(|base_path| !base_path.is_empty())
+        .map(|base_path| format!("{}/", base_path))
+        .unwrap_or_default();
+    let frontend_dir = matches.get_one::<String>("frontend").map(PathBuf::from);
+    let api_only = matches.get_flag("api-only");
+    let dev_mode = matches.get_flag("dev");
+
+    let token = matches.get_one::<String>("token").cloned();
+
+    // Pick what to host besides the API
+    let frontend = if api_only || dev_mode {
+        let mode_str = if dev_mode {
+            "development API"
+        } else {
+            "API-only"
+        };
+        println!("Starting {} server...", mode_str);
+        Frontend::None
+    } else if let Some(frontend_path) = frontend_dir {
+        println!("Starting server with React frontend...");
+        println!("Frontend directory: {}", frontend_path.display());
+        Frontend::Dir(frontend_path)
+    } else {
+        default_frontend()
+    };
+    let has_frontend = frontend != Frontend::None;
+
+    if let Some(key_file) = matches.get_one::<String>("key-file") {
+        std::fs::read(key_file)
+            .and_then(|secret| fmemo::crypt::unlock(&root_dir, &secret))
+            .map_err(|e| format!("Failed to unlock with '{}': {}", key_file, e))?;
+    }
+
+    let mut server = FmemoServer::new(&root_dir)
+        .host(host)
+        .port(port)
+        .frontend(frontend)
+        .git_autocommit(matches.get_flag("git-autocommit"))
+        .mdns(matches.get_flag("mdns"))
+        .publish(matches.get_flag("publish"))
+        .base_path(&base_path);
+    if let Some(token) = token {
+        server = server.auth(token);
+    }
+    if let Some(path) = matches.get_one::<String>("access-log") {
+        let format = match matches
+            .get_one::<String>("access-log-format")
+            .unwrap()
+            .as_str()
+        {
+            "json" => AccessLogFormat::Json,
+            _ => AccessLogFormat::Combined,
+        };
+        let access_log = if path == "-" {
+            AccessLog::stdout(format)
+        } else {
+            AccessLog::file(path, format)
+                .map_err(|e| format!("Failed to open access log '{}': {}", path, e))?
+        };
+        server = server.access_log(access_log);
+    }
+    if let Some(socket) = matches.get_one::<String>("unix-socket") {
+        return serve_unix(server, &root_dir, PathBuf::from(socket)).await;
+    }
+    let (addr, serve) = server.bind()?;
+
+    println!("Root directory: {}", root_dir.display());
+    println!("Server running on http://localhost:{}/{}", port, base_path);
+    if has_frontend {
+        println!(
+            "Frontend available at: http://localhost:{}/{}",
+            port, base_path
+        );
+    }
+    if let Some(ip) = fmemo::network::lan_ip(host) {
+        let url = format!("{}{}", fmemo::network::url(ip, addr.port()), base_path);
+        println!("On your network: {}", url);
+        if !matches.get_flag("no-qr")
+            && std::io::stdout().is_terminal()
+            && let Some(qr) = fmemo::network::terminal_qr_code(&url)
+        {
+            println!("{}", qr);
+        }
+    }
+    println!("API endpoints:");
+    println!("  GET /api/root - Get directory tree");
+    println!("  GET /api/files/{{filename}} - Get file content");
+    println!("  GET /api/file/{{filename}} - Get file content (frontend compatible)");
+    println!("  PUT /api/file/{{filename}} - Save file content");
+    println!("  WebSocket /ws - Real-time updates");
+
+    if matches.get_flag("open") {
+        // The listener is bound, so the page loads even before `serve` is polled
+        let ip = if host.is_unspecified() || host.is_loopback() {
+            std::net::Ipv4Addr::LOCALHOST.into()
+        } else {
+            host
+        };
+        let url = format!("{}{}", fmemo::network::url(ip, addr.port()), base_path);
+        if let Err(e) = open_browser(&url) {
+            eprintln!("Warning: Failed to open {} in a browser: {}", url, e);
+        }
+    }
+
+    if dev_mode {
+        println!();
+        println!("🔧 Development mode:");
+        println!("   Run React dev server separately: cd frontend && npm run dev");
+        println!("   React dev server will proxy API calls to this server");
+        println!(
+            "   Configure Vite proxy in vite.config.ts to point to localhost:{}",
+            port
+        );
+    }
+
+    serve.await;
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn serve_unix(server: FmemoServer, root_dir: &Path, socket: PathBuf) -> CommandResult {
+    let serve = server.bind_unix(socket.clone())?;
+    println!("Root directory: {}", root_dir.display());
+    println!("Server listening on unix:{}", socket.display());
+    serve.await;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(_server: FmemoServer, _root_dir: &Path, _socket: PathBuf) -> CommandResult {
+    Err("--unix-socket is only supported on Unix".into())
+}
+
+/// Frontend to host when none was given on the command line
+fn default_frontend() -> Frontend {
+    // If compiled with embedded frontend, serve it from the binary
+    #[cfg(feature = "embed_frontend")]
+    {
+        println!("Serving embedded frontend (single binary mode)...");
+        Frontend::Embedded
+    }
+
+    // Try to auto-detect frontend directory
+    #[cfg(not(feature = "embed_frontend"))]
+    {
+        let auto_frontend = PathBuf::from("frontend/dist");
+        if auto_frontend.is_dir() {
+            println!(
+                "Auto-detected frontend directory: {}",
+                auto_frontend.display()
+            );
+            Frontend::Dir(auto_frontend)
+        } else {
+            println!("No frontend directory found, starting API-only server...");
+            Frontend::None
+        }
+    }
+}
+
+/// Open a URL with the platform's default handler, without waiting for the browser
+fn open_browser(url: &str) -> std::io::Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else if cfg!(windows) {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+    command
+        .arg(url)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map(|_| ())
+}
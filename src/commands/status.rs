@@ -0,0 +1,25 @@
+//! `fmemo status` - whether a server started with `--daemon` is running
+
+use clap::{ArgMatches, Command};
+
+use super::CommandResult;
+use super::daemon::{log_file, port, port_arg, running_pid};
+
+pub fn command() -> Command {
+    Command::new("status")
+        .about("Show whether a server started with --daemon is running")
+        .arg(port_arg())
+}
+
+/// Fails when the server isn't running, so scripts can check the exit code
+pub fn run(matches: &ArgMatches) -> CommandResult {
+    let port = port(matches)?;
+    match running_pid(port)? {
+        Some(pid) => {
+            println!("fmemo is running on port {} (pid {})", port, pid);
+            println!("Log: {}", log_file(port).display());
+            Ok(())
+        }
+        None => Err(format!("fmemo is not running on port {}", port).into()),
+    }
+}
@@ -0,0 +1,28 @@
+//! `fmemo stop` - stop a server started with `--daemon`
+
+use clap::{ArgMatches, Command};
+
+use super::CommandResult;
+use super::daemon::{pid_file, port, port_arg, running_pid};
+
+pub fn command() -> Command {
+    Command::new("stop")
+        .about("Stop a server started with --daemon")
+        .arg(port_arg())
+}
+
+pub fn run(matches: &ArgMatches) -> CommandResult {
+    let port = port(matches)?;
+    let Some(pid) = running_pid(port)? else {
+        return Err(format!("fmemo is not running on port {}", port).into());
+    };
+    let status = std::process::Command::new("kill")
+        .arg(pid.to_string())
+        .status()?;
+    if !status.success() {
+        return Err(format!("Failed to stop fmemo (pid {})", pid).into());
+    }
+    std::fs::remove_file(pid_file(port))?;
+    println!("Stopped fmemo on port {} (pid {})", port, pid);
+    Ok(())
+}
@@ -0,0 +1,36 @@
+//! `fmemo vault` - set up the vault that encrypted (`.fmemox`) memos are unlocked with
+
+use clap::{Arg, ArgMatches, Command};
+use std::io::BufRead;
+
+use super::{CommandResult, root_arg, root_dir};
+
+pub fn command() -> Command {
+    Command::new("vault")
+        .about("Set up encrypted memos with a passphrase read from stdin, or a key file")
+        .arg(root_arg())
+        .arg(
+            Arg::new("key-file")
+                .long("key-file")
+                .value_name("FILE")
+                .help("Use this key file instead of a passphrase"),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> CommandResult {
+    let root = root_dir(matches);
+    let secret = match matches.get_one::<String>("key-file") {
+        Some(key_file) => std::fs::read(key_file)?,
+        None => {
+            let mut passphrase = String::new();
+            std::io::stdin().lock().read_line(&mut passphrase)?;
+            passphrase
+                .trim_end_matches(['\r', '\n'])
+                .as_bytes()
+                .to_vec()
+        }
+    };
+    fmemo::crypt::init(&root, &secret)?;
+    println!("Created {}", fmemo::crypt::vault_path(&root).display());
+    Ok(())
+}
@@ -0,0 +1,133 @@
+//! Comments on memos, kept apart from the memo files in `.fmemo/comments/`.
+//!
+//! Each memo file has one sidecar with the comments on all of its memos, which are
+//! identified by their slug (the memo's `anchor`). Commenting never touches the memo
+//! file, so reviewers can discuss a memo without editing it.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::config::FMEMO_DIR;
+
+/// Longest comment body, in bytes
+pub const MAX_COMMENT_LEN: usize = 10_000;
+
+/// Serializes read-modify-write of the sidecars
+static SIDECARS: Mutex<()> = Mutex::new(());
+
+/// A comment on a memo
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct Comment {
+    pub id: String,
+    /// Slug of the memo commented on
+    pub slug: String,
+    /// Who wrote it; `None` when nobody is logged in and no name was given
+    pub author: Option<String>,
+    pub body: String,
+    /// RFC 3339
+    pub created_at: String,
+}
+
+/// Where the comments of a memo file (relative to the root) are stored. The `.json`
+/// extension keeps the watcher from treating it as a memo.
+pub fn comments_path(root: &Path, relative: &str) -> PathBuf {
+    root.join(FMEMO_DIR)
+        .join("comments")
+        .join(format!("{}.json", relative))
+}
+
+fn load(root: &Path, relative: &str) -> std::io::Result<Vec<Comment>> {
+    match std::fs::read_to_string(comments_path(root, relative)) {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Comments on a memo of a file, oldest first
+pub fn list_comments(root: &Path, relative: &str, slug: &str) -> std::io::Result<Vec<Comment>> {
+    let _guard = SIDECARS.lock().unwrap_or_else(|e| e.into_inner());
+    Ok(load(root, relative)?
+        .into_iter()
+        .filter(|comment| comment.slug == slug)
+        .collect())
+}
+
+/// Add a comment to a memo of a file. The caller checks that the memo exists.
+pub fn add_comment(
+    root: &Path,
+    relative: &str,
+    slug: &str,
+    author: Option<String>,
+    body: &str,
+) -> std::io::Result<Comment> {
+    if crate::crypt::is_encrypted(Path::new(relative)) {
+        // The comments would be stored in plaintext
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Encrypted memos have no comments",
+        ));
+    }
+    let body = body.trim();
+    if body.is_empty() || body.len() > MAX_COMMENT_LEN {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Comment must have 1 to {} bytes", MAX_COMMENT_LEN),
+        ));
+    }
+    let comment = Comment {
+        id: crate::request_id::generate(),
+        slug: slug.to_string(),
+        author,
+        body: body.to_string(),
+        created_at: chrono::Utc::now().to_rfc3339(),
+    };
+
+    let _guard = SIDECARS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut comments = load(root, relative)?;
+    comments.push(comment.clone());
+    let path = comments_path(root, relative);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(&comments)?)?;
+    Ok(comment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_comment, comments_path, list_comments};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_comments() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        assert!(
+            list_comments(root, "notes/a.fmemo", "intro")
+                .unwrap()
+                .is_empty()
+        );
+
+        let first = add_comment(
+            root,
+            "notes/a.fmemo",
+            "intro",
+            Some("kai".to_string()),
+            " Typo? ",
+        )
+        .unwrap();
+        assert_eq!(first.body, "Typo?");
+        add_comment(root, "notes/a.fmemo", "intro", None, "Fixed").unwrap();
+        add_comment(root, "notes/a.fmemo", "usage", None, "Needs an example").unwrap();
+        let comments = list_comments(root, "notes/a.fmemo", "intro").unwrap();
+        assert_eq!(comments.len(), 2);
+        assert_eq!(comments[0], first);
+        assert!(comments_path(root, "notes/a.fmemo").is_file());
+        assert!(!root.join("notes/a.fmemo").exists());
+
+        assert!(add_comment(root, "notes/a.fmemo", "intro", None, "  ").is_err());
+        assert!(add_comment(root, "secret.fmemox", "intro", None, "Hi").is_err());
+    }
+}
@@ -0,0 +1,565 @@
+//! Per-root settings from `.fmemo/config.toml`.
+//!
+//! ```toml
+//! [templates.journal]
+//! directory = "journal"
+//!
+//! [directories."work/meetings"]
+//! template = "meeting"
+//!
+//! [variables]
+//! author = "kai"
+//!
+//! [[webhooks]]
+//! url = "https://ci.example.com/hooks/notes"
+//! secret = "shared-secret"
+//! events = ["file_updated", "file_deleted"]
+//!
+//! [[chat]]
+//! service = "slack"
+//! url = "https://hooks.slack.com/services/..."
+//! directories = ["team"]
+//!
+//! [hooks]
+//! file_updated = ["make -C site"]
+//! debounce_ms = 500
+//!
+//! [watch]
+//! coalesce_ms = 500
+//!
+//! [highlight]
+//! enabled = true
+//! theme = "base16-ocean.dark"
+//!
+//! [import]
+//! max_size_mb = 50
+//! on_conflict = "skip"
+//!
+//! [spellcheck]
+//! enabled = true
+//! languages = ["en_US", "de_DE"]
+//! dictionaries = ["dicts"]
+//!
+//! [llm]
+//! url = "https://api.openai.com/v1"
+//! api_key = "sk-..."
+//! model = "gpt-4o-mini"
+//!
+//! [embeddings]
+//! url = "https://api.openai.com/v1"
+//! api_key = "sk-..."
+//! model = "text-embedding-3-small"
+//!
+//! [users.kai]
+//! password = "pbkdf2-sha256$600000$..."   # from `fmemo hash-password`
+//! permission = "write"
+//!
+//! [oidc]
+//! issuer = "https://accounts.google.com"
+//! client_id = "..."
+//! client_secret = "..."
+//! redirect_url = "https://notes.example.com/api/auth/oidc/callback"
+//! default_permission = "read"
+//!
+//! [oidc.users]
+//! "kai@example.com" = "write"
+//!
+//! [publish]
+//! url = "https://notes.example.com"
+//!
+//! [pandoc]
+//! path = "/opt/pandoc/bin/pandoc"
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Directory holding fmemo's own files inside a root; hidden, so it's never listed as memos
+pub const FMEMO_DIR: &str = ".fmemo";
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct Config {
+    /// Settings per template name (the template file stem)
+    #[serde(default)]
+    pub templates: BTreeMap<String, TemplateConfig>,
+    /// Settings per directory (relative to the root, `/` separated)
+    #[serde(default)]
+    pub directories: BTreeMap<String, DirectoryConfig>,
+    /// Extra `{{name}}` placeholders for templates
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+    /// Endpoints receiving the watcher's change events
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    /// Chat channels told about changed memos
+    #[serde(default)]
+    pub chat: Vec<ChatConfig>,
+    /// Shell commands run on memo file events
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    /// How the watcher turns file events into `file_updated` messages
+    #[serde(default)]
+    pub watch: WatchConfig,
+    /// Server-side highlighting of code blocks
+    #[serde(default)]
+    pub highlight: HighlightConfig,
+    /// Zip uploads to `POST /api/import`
+    #[serde(default)]
+    pub import: ImportConfig,
+    /// Spelling diagnostics in `fmemo lint` and the language server
+    #[serde(default)]
+    pub spellcheck: SpellcheckConfig,
+    /// OpenAI-compatible backend for `POST /api/files/{path}/summarize`
+    #[serde(default)]
+    pub llm: LlmConfig,
+    /// OpenAI-compatible embedding API for `GET /api/search?mode=semantic`
+    #[serde(default)]
+    pub embeddings: EmbeddingsConfig,
+    /// Local accounts by user name; logging in is required once there is one
+    #[serde(default)]
+    pub users: BTreeMap<String, UserConfig>,
+    /// Logging in through an OpenID Connect (or plain OAuth2) provider
+    #[serde(default)]
+    pub oidc: Option<OidcConfig>,
+    /// One memo file per day, for `/api/journal`
+    #[serde(default)]
+    pub journal: JournalConfig,
+    /// Where `fmemo archive` moves stale memos
+    #[serde(default)]
+    pub archive: ArchiveConfig,
+    /// The public site of `fmemo serve --publish`
+    #[serde(default)]
+    pub publish: PublishConfig,
+    /// DOCX/ODT conversion for `GET /api/files/{path}/export`
+    #[serde(default)]
+    pub pandoc: PandocConfig,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct TemplateConfig {
+    /// Directory (relative to the root) for memos created from this template
+    /// when no explicit path is given
+    pub directory: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct DirectoryConfig {
+    /// Template for new memos in this directory (and below) when none is named
+    pub template: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Sign each body with HMAC-SHA256 (`X-Fmemo-Signature: sha256=<hex>`)
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Event types to send (`file_updated`, `file_deleted`, `directory_updated`); all when empty
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatService {
+    Slack,
+    Discord,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct ChatConfig {
+    pub service: ChatService,
+    /// The service's incoming webhook URL
+    pub url: String,
+    /// Only report files below these directories (relative to the root); all when empty
+    #[serde(default)]
+    pub directories: Vec<String>,
+}
+
+/// Commands per event; see `crate::hooks` for their environment
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(default)]
+pub struct HooksConfig {
+    pub file_created: Vec<String>,
+    pub file_updated: Vec<String>,
+    pub file_deleted: Vec<String>,
+    /// How long a file has to stay unchanged before its hooks run
+    pub debounce_ms: u64,
+    /// Commands running at the same time; later ones wait
+    pub max_concurrent: usize,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            file_created: Vec::new(),
+            file_updated: Vec::new(),
+            file_deleted: Vec::new(),
+            debounce_ms: 1000,
+            max_concurrent: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(default)]
+pub struct WatchConfig {
+    /// How long the watcher has to be quiet before changed files are read and sent, so the
+    /// several writes an editor makes for one save become one message
+    pub coalesce_ms: u64,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self { coalesce_ms: 300 }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(default)]
+pub struct HighlightConfig {
+    /// Highlight code blocks in `GET /api/files/...` unless the request says `?highlight=false`
+    pub enabled: bool,
+    /// syntect theme; `crate::highlight::DEFAULT_THEME` when omitted
+    pub theme: Option<String>,
+}
+
+/// What an import does with a file whose path already exists
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Keep the existing file and leave the imported one out
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Import under a free name (`a-1.md`, `a-2.md`, ...)
+    #[default]
+    Rename,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ImportConfig {
+    /// Largest upload, and largest total size of the extracted files, in MiB
+    pub max_size_mb: u64,
+    /// Used when the request doesn't say `?on_conflict=...`
+    pub on_conflict: ConflictPolicy,
+}
+
+impl Default for ImportConfig {
+    fn default() -> Self {
+        Self {
+            max_size_mb: 100,
+            on_conflict: ConflictPolicy::Rename,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SpellcheckConfig {
+    /// Report unknown words as lint issues; `GET /api/files/{path}/spelling` works either way
+    pub enabled: bool,
+    /// Hunspell dictionary names (`<name>.dic` / `<name>.aff`); a word any of them knows is fine
+    pub languages: Vec<String>,
+    /// Extra directories (relative to the root) searched for dictionaries before
+    /// `.fmemo/dictionaries` and the system's hunspell directories
+    pub dictionaries: Vec<String>,
+}
+
+impl Default for SpellcheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            languages: vec!["en_US".to_string()],
+            dictionaries: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(default)]
+pub struct LlmConfig {
+    /// Base URL of the API (`/chat/completions` is appended); summarizing is off without one
+    pub url: Option<String>,
+    /// Sent as `Authorization: Bearer <key>`
+    pub api_key: Option<String>,
+    pub model: String,
+    /// Instructions sent ahead of the memo; `crate::llm::DEFAULT_PROMPT` when omitted
+    pub prompt: Option<String>,
+    /// Longer memos are cut to this many characters before they're sent
+    pub max_input_chars: usize,
+    pub timeout_secs: u64,
+}
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            api_key: None,
+            model: "gpt-4o-mini".to_string(),
+            prompt: None,
+            max_input_chars: 20_000,
+            timeout_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(default)]
+pub struct EmbeddingsConfig {
+    /// Base URL of the API (`/embeddings` is appended); semantic search is off without one
+    pub url: Option<String>,
+    /// Sent as `Authorization: Bearer <key>`
+    pub api_key: Option<String>,
+    pub model: String,
+    /// Texts sent per request
+    pub batch_size: usize,
+    /// Longer memos are cut to this many characters before they're embedded
+    pub max_input_chars: usize,
+    pub timeout_secs: u64,
+}
+
+impl Default for EmbeddingsConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            api_key: None,
+            model: "text-embedding-3-small".to_string(),
+            batch_size: 64,
+            max_input_chars: 8000,
+            timeout_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(default)]
+pub struct JournalConfig {
+    /// Path of a day's entry relative to the root, as a chrono format string
+    pub layout: String,
+    /// Template for new entries; `crate::journal::DEFAULT_TEMPLATE` when omitted
+    pub template: Option<String>,
+    /// Decides which day is today: `local` (the server's), `UTC` or an offset like `+09:00`
+    pub timezone: Option<String>,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            layout: "journal/%Y/%m/%Y-%m-%d.fmemo".to_string(),
+            template: None,
+            timezone: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct PublishConfig {
+    /// Public URL of the site, for absolute links such as those in `/sitemap.xml`; without
+    /// one they're built from the request's `Host`
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct PandocConfig {
+    /// The pandoc binary, e.g. one shipped next to fmemo; `pandoc` from `PATH` when omitted
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    /// Directory (relative to the root) left out of the tree, the file list and search
+    /// unless they are asked for archived memos
+    pub dir: String,
+    /// Default age for `fmemo archive` and `POST /api/archive`, like `1y`, `6m`, `2w` or `30d`
+    pub older_than: String,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            dir: "archive".to_string(),
+            older_than: "1y".to_string(),
+        }
+    }
+}
+
+/// What an account may do in the root
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    serde::Serialize,
+    serde::Deserialize,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    /// Every GET, no changes
+    #[default]
+    Read,
+    Write,
+    /// Writing, and managing API tokens
+    Admin,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct UserConfig {
+    /// Password hash from `fmemo hash-password`, never the password itself
+    pub password: String,
+    #[serde(default)]
+    pub permission: Permission,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct OidcConfig {
+    /// Where `/.well-known/openid-configuration` is found; not needed when the three
+    /// endpoints below are given (e.g. for GitHub, which has no discovery)
+    #[serde(default)]
+    pub issuer: Option<String>,
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    /// This server's `/api/auth/oidc/callback` as the provider sees it
+    pub redirect_url: String,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub authorization_url: Option<String>,
+    #[serde(default)]
+    pub token_url: Option<String>,
+    #[serde(default)]
+    pub userinfo_url: Option<String>,
+    /// Userinfo field naming the user (`email`, or `login` for GitHub)
+    #[serde(default = "default_oidc_user_claim")]
+    pub user_claim: String,
+    /// Permission of users not in `users`; they can't log in without one
+    #[serde(default)]
+    pub default_permission: Option<Permission>,
+    /// Permission by user name (the `user_claim` value)
+    #[serde(default)]
+    pub users: BTreeMap<String, Permission>,
+    #[serde(default = "default_oidc_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    ["openid", "email", "profile"].map(String::from).to_vec()
+}
+
+fn default_oidc_user_claim() -> String {
+    "email".to_string()
+}
+
+fn default_oidc_timeout_secs() -> u64 {
+    30
+}
+
+impl Config {
+    /// Default template for a directory: the closest configured ancestor wins
+    pub fn directory_template(&self, dir: &str) -> Option<&str> {
+        let mut dir = dir.trim_matches('/');
+        loop {
+            if let Some(template) = self
+                .directories
+                .get(dir)
+                .and_then(|config| config.template.as_deref())
+            {
+                return Some(template);
+            }
+            if dir.is_empty() {
+                return None;
+            }
+            dir = dir.rfind('/').map_or("", |idx| &dir[..idx]);
+        }
+    }
+}
+
+pub fn config_path(root: &Path) -> PathBuf {
+    root.join(FMEMO_DIR).join("config.toml")
+}
+
+/// Load the root's config; a missing file is the default config
+pub fn load_config(root: &Path) -> std::io::Result<Config> {
+    let content = match std::fs::read_to_string(config_path(root)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(e),
+    };
+    toml::from_str(&content)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChatService, Config, FMEMO_DIR, load_config};
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_load_config() {
+        let temp_dir = TempDir::new().unwrap();
+        assert_eq!(load_config(temp_dir.path()).unwrap(), Config::default());
+
+        fs::create_dir(temp_dir.path().join(FMEMO_DIR)).unwrap();
+        let path = temp_dir.path().join(FMEMO_DIR).join("config.toml");
+        fs::write(&path, "[templates.journal]\ndirectory = \"journal\"\n").unwrap();
+        let config = load_config(temp_dir.path()).unwrap();
+        assert_eq!(
+            config.templates["journal"].directory.as_deref(),
+            Some("journal")
+        );
+
+        fs::write(
+            &path,
+            "[directories.work]\ntemplate = \"meeting\"\n[directories.\"\"]\ntemplate = \"note\"\n",
+        )
+        .unwrap();
+        let config = load_config(temp_dir.path()).unwrap();
+        assert_eq!(config.directory_template("work/2024/q1"), Some("meeting"));
+        assert_eq!(config.directory_template("home"), Some("note"));
+
+        fs::write(
+            &path,
+            "[[webhooks]]\nurl = \"http://localhost/a\"\n[[webhooks]]\nurl = \"http://localhost/b\"\nsecret = \"s\"\nevents = [\"file_deleted\"]\n",
+        )
+        .unwrap();
+        let config = load_config(temp_dir.path()).unwrap();
+        assert_eq!(config.webhooks.len(), 2);
+        assert_eq!(config.webhooks[1].secret.as_deref(), Some("s"));
+        assert!(config.webhooks[0].events.is_empty());
+
+        fs::write(
+            &path,
+            "[[chat]]\nservice = \"discord\"\nurl = \"https://discord.example/hook\"\n",
+        )
+        .unwrap();
+        let config = load_config(temp_dir.path()).unwrap();
+        assert_eq!(config.chat[0].service, ChatService::Discord);
+        assert!(config.chat[0].directories.is_empty());
+
+        fs::write(
+            &path,
+            "[hooks]\nfile_updated = [\"make\"]\nmax_concurrent = 1\n",
+        )
+        .unwrap();
+        let config = load_config(temp_dir.path()).unwrap();
+        assert_eq!(config.hooks.file_updated, ["make"]);
+        assert!(config.hooks.file_deleted.is_empty());
+        assert_eq!(config.hooks.debounce_ms, 1000);
+        assert_eq!(config.hooks.max_concurrent, 1);
+        assert_eq!(config.watch.coalesce_ms, 300);
+
+        fs::write(&path, "[watch]\ncoalesce_ms = 0\n").unwrap();
+        assert_eq!(load_config(temp_dir.path()).unwrap().watch.coalesce_ms, 0);
+
+        fs::write(&path, "templates = 3").unwrap();
+        assert!(load_config(temp_dir.path()).is_err());
+    }
+}
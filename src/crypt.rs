@@ -0,0 +1,402 @@
+//! Encrypted memos: `.fmemox` files hold a memo encrypted with ChaCha20-Poly1305 (RFC 8439)
+//! under a key derived from a passphrase or key file with PBKDF2-HMAC-SHA256.
+//!
+//! Each root has one vault key. `.fmemo/vault.json` keeps its salt, iteration count and a
+//! check value that tells a wrong passphrase apart, and is created by `fmemo vault`.
+//! Unlocking keeps the key in memory; `read_memo` and `write_memo` then decrypt and encrypt
+//! `.fmemox` files on the fly, so their plaintext never touches the disk. While the root is
+//! locked they fail with `ErrorKind::PermissionDenied`.
+//!
+//! A file is `MAGIC`, a random 12 byte nonce, the ciphertext and the 16 byte tag.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use chacha20poly1305::ChaCha20Poly1305;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use sha2::Sha256;
+
+use crate::config::FMEMO_DIR;
+
+pub const EXTENSION: &str = "fmemox";
+/// Start of every encrypted file, also authenticated with it
+pub const MAGIC: &[u8; 8] = b"FMEMOX\0\x01";
+/// PBKDF2 rounds for new vaults
+pub const DEFAULT_ITERATIONS: u32 = 600_000;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const SALT_LEN: usize = 16;
+/// Plaintext of the vault's check value
+const CHECK_TEXT: &[u8] = b"fmemo vault";
+
+type Key = [u8; KEY_LEN];
+
+/// Keys of the unlocked roots, by canonical root path
+static KEYS: RwLock<Option<HashMap<PathBuf, Key>>> = RwLock::new(None);
+
+/// ChaCha20-Poly1305 encryption: the ciphertext followed by the tag
+fn seal(key: &Key, nonce: &[u8; NONCE_LEN], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    ChaCha20Poly1305::new(key.into())
+        .encrypt(
+            nonce.into(),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .expect("ChaCha20-Poly1305 encrypts any memo that fits in memory")
+}
+
+/// ChaCha20-Poly1305 decryption; `None` when the tag doesn't match
+fn open(key: &Key, nonce: &[u8; NONCE_LEN], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    ChaCha20Poly1305::new(key.into())
+        .decrypt(nonce.into(), Payload { msg: sealed, aad })
+        .ok()
+}
+
+/// PBKDF2-HMAC-SHA256 (RFC 8018), `output.len()` bytes
+pub(crate) fn pbkdf2(secret: &[u8], salt: &[u8], iterations: u32, output: &mut [u8]) {
+    pbkdf2::pbkdf2_hmac::<Sha256>(secret, salt, iterations, output);
+}
+
+pub(crate) fn random_bytes<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes).map_err(io::Error::other)?;
+    Ok(bytes)
+}
+
+fn encrypt_with(key: &Key, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+    let nonce = random_bytes::<NONCE_LEN>()?;
+    let mut data = MAGIC.to_vec();
+    data.extend_from_slice(&nonce);
+    data.extend(seal(key, &nonce, MAGIC, plaintext));
+    Ok(data)
+}
+
+fn decrypt_with(key: &Key, data: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let rest = data
+        .strip_prefix(MAGIC.as_slice())
+        .ok_or_else(|| invalid("Not an encrypted memo"))?;
+    if rest.len() < NONCE_LEN + TAG_LEN {
+        return Err(invalid("Encrypted memo is truncated"));
+    }
+    let (nonce, sealed) = rest.split_at(NONCE_LEN);
+    let nonce: [u8; NONCE_LEN] = nonce.try_into().expect("split at the nonce length");
+    open(key, &nonce, MAGIC, sealed)
+        .ok_or_else(|| invalid("Encrypted memo is corrupt or was encrypted with another key"))
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// `.fmemo/vault.json`: how to derive a root's key and check it
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct Vault {
+    pub iterations: u32,
+    /// Hex
+    pub salt: String,
+    /// Hex of the encrypted check text
+    pub check: String,
+}
+
+pub fn vault_path(root: &Path) -> PathBuf {
+    root.join(FMEMO_DIR).join("vault.json")
+}
+
+impl Vault {
+    /// The root's vault; `NotFound` before it's set up
+    pub fn load(root: &Path) -> io::Result<Self> {
+        let content = std::fs::read_to_string(vault_path(root))?;
+        serde_json::from_str(&content)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    /// A vault for `secret` with a new salt, written to the root
+    pub fn create(root: &Path, secret: &[u8], iterations: u32) -> io::Result<Self> {
+        let salt = random_bytes::<SALT_LEN>()?;
+        let mut key = [0u8; KEY_LEN];
+        pbkdf2(secret, &salt, iterations, &mut key);
+        let vault = Vault {
+            iterations,
+            salt: to_hex(&salt),
+            check: to_hex(&encrypt_with(&key, CHECK_TEXT)?),
+        };
+        let path = vault_path(root);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(&vault)?)?;
+        Ok(vault)
+    }
+
+    /// The key `secret` derives; `PermissionDenied` when it's the wrong one
+    fn key(&self, secret: &[u8]) -> io::Result<Key> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid vault.json");
+        let salt = from_hex(&self.salt).ok_or_else(invalid)?;
+        let check = from_hex(&self.check).ok_or_else(invalid)?;
+        let mut key = [0u8; KEY_LEN];
+        pbkdf2(secret, &salt, self.iterations, &mut key);
+        match decrypt_with(&key, &check) {
+            Ok(text) if text == CHECK_TEXT => Ok(key),
+            _ => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Wrong passphrase or key file",
+            )),
+        }
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn check_secret(secret: &[u8]) -> io::Result<()> {
+    if secret.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The passphrase or key file is empty",
+        ));
+    }
+    Ok(())
+}
+
+/// Set up the vault of `root` for a passphrase or a key file's contents; `AlreadyExists` when
+/// it has one
+pub fn init(root: &Path, secret: &[u8]) -> io::Result<()> {
+    check_secret(secret)?;
+    if vault_path(root).exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "The vault is already set up",
+        ));
+    }
+    Vault::create(root, secret, DEFAULT_ITERATIONS)?;
+    Ok(())
+}
+
+/// Unlock the encrypted memos below `root` with a passphrase or a key file's contents;
+/// `NotFound` before the vault is set up with `fmemo vault`
+pub fn unlock(root: &Path, secret: &[u8]) -> io::Result<()> {
+    check_secret(secret)?;
+    let vault = Vault::load(root).map_err(|e| {
+        if e.kind() == io::ErrorKind::NotFound {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "No vault yet; set one up with fmemo vault",
+            )
+        } else {
+            e
+        }
+    })?;
+    let key = vault.key(secret)?;
+    KEYS.write()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(canonical(root), key);
+    Ok(())
+}
+
+/// Forget the key of `root`; returns whether it was unlocked
+pub fn lock(root: &Path) -> bool {
+    let mut keys = KEYS.write().unwrap();
+    let removed = keys.as_mut().and_then(|keys| keys.remove(&canonical(root)));
+    removed.is_some()
+}
+
+pub fn is_unlocked(root: &Path) -> bool {
+    KEYS.read()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|keys| keys.contains_key(&canonical(root)))
+}
+
+pub fn is_encrypted(path: &Path) -> bool {
+    path.extension().and_then(|s| s.to_str()) == Some(EXTENSION)
+}
+
+/// The key of the unlocked root `path` is in
+fn key_for(path: &Path) -> io::Result<Key> {
+    // The file itself may not exist yet
+    let path = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => canonical(parent).join(name),
+        _ => canonical(path),
+    };
+    KEYS.read()
+        .unwrap()
+        .iter()
+        .flatten()
+        .find(|(root, _)| path.starts_with(root))
+        .map(|(_, key)| *key)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Encrypted memos are locked; unlock them with POST /api/unlock",
+            )
+        })
+}
+
+/// Content of a memo file, decrypted when it's a `.fmemox`
+pub fn read_memo(path: &Path) -> io::Result<String> {
+    if !is_encrypted(path) {
+        return std::fs::read_to_string(path);
+    }
+    let data = std::fs::read(path)?;
+    let plaintext = decrypt_with(&key_for(path)?, &data)?;
+    String::from_utf8(plaintext)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Memo is not valid UTF-8"))
+}
+
+/// Like `publish::read_memo`, but a large plain file is memory-mapped instead of copied into
+/// memory
+pub fn load_memo(path: &Path, publish: bool) -> io::Result<crate::mapped::MemoText> {
+    use crate::mapped::{MAP_THRESHOLD, MemoText};
+
+    if is_encrypted(path) {
+        return crate::publish::read_memo(path, publish).map(MemoText::Owned);
+    }
+    let text = crate::mapped::read_text(path, MAP_THRESHOLD)?;
+    if publish && text.contains(crate::publish::OPEN_TAG) {
+        return Ok(MemoText::Owned(crate::publish::visible(
+            publish,
+            text.into(),
+        )));
+    }
+    Ok(text)
+}
+
+/// Write a memo file, encrypted when it's a `.fmemox`
+pub fn write_memo(path: &Path, content: &str) -> io::Result<()> {
+    if !is_encrypted(path) {
+        return crate::atomic::write(path, content.as_bytes());
+    }
+    let data = encrypt_with(&key_for(path)?, content.as_bytes())?;
+    std::fs::write(path, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn hex(hex: &str) -> Vec<u8> {
+        from_hex(&hex.replace([' ', ':'], "")).unwrap()
+    }
+
+    #[test]
+    fn test_chacha20_poly1305() {
+        // RFC 8439 2.8.2
+        let key: Key = hex("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f")
+            .try_into()
+            .unwrap();
+        let nonce: [u8; NONCE_LEN] = hex("070000004041424344454647").try_into().unwrap();
+        let aad = hex("50515253c0c1c2c3c4c5c6c7");
+        let plaintext: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you \
+            only one tip for the future, sunscreen would be it.";
+        let sealed = seal(&key, &nonce, &aad, plaintext);
+        assert_eq!(sealed[..16], hex("d31a8d34648e60db7b86afbc53ef7ec2")[..]);
+        assert_eq!(
+            sealed[sealed.len() - TAG_LEN..],
+            hex("1ae10b594f09e26a7e902ecbd0600691")[..]
+        );
+        assert_eq!(open(&key, &nonce, &aad, &sealed).unwrap(), plaintext);
+
+        let mut tampered = sealed.clone();
+        tampered[0] ^= 1;
+        assert!(open(&key, &nonce, &aad, &tampered).is_none());
+        assert!(open(&key, &nonce, b"other", &sealed).is_none());
+    }
+
+    #[test]
+    fn test_pbkdf2() {
+        // The PBKDF2-HMAC-SHA256 counterparts of the RFC 6070 vectors
+        let mut output = [0u8; 32];
+        pbkdf2(b"password", b"salt", 1, &mut output);
+        assert_eq!(
+            output[..],
+            hex("120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b")[..]
+        );
+        pbkdf2(b"password", b"salt", 2, &mut output);
+        assert_eq!(
+            output[..],
+            hex("ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43")[..]
+        );
+        pbkdf2(b"password", b"salt", 4096, &mut output);
+        assert_eq!(
+            output[..],
+            hex("c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a")[..]
+        );
+
+        // RFC 7914 11
+        let mut output = [0u8; 64];
+        pbkdf2(b"passwd", b"salt", 1, &mut output);
+        assert_eq!(
+            output[..],
+            hex(
+                "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc\
+                 49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+            )[..]
+        );
+    }
+
+    #[test]
+    fn test_encrypted_memos() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let path = root.join("secret.fmemox");
+        // No vault is set up by unlocking
+        assert_eq!(
+            unlock(root, b"passphrase").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        assert!(!vault_path(root).exists());
+        Vault::create(root, b"passphrase", 10).unwrap();
+        assert_eq!(
+            init(root, b"other").unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+
+        assert!(!is_unlocked(root));
+        let locked = write_memo(&path, "# Secret").unwrap_err();
+        assert_eq!(locked.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(
+            unlock(root, b"wrong").unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+
+        unlock(root, b"passphrase").unwrap();
+        assert!(is_unlocked(root));
+        write_memo(&path, "# Secret\nbody").unwrap();
+        let stored = fs::read(&path).unwrap();
+        assert!(stored.starts_with(MAGIC));
+        assert!(!String::from_utf8_lossy(&stored).contains("Secret"));
+        assert_eq!(read_memo(&path).unwrap(), "# Secret\nbody");
+
+        // Plain memos are untouched
+        write_memo(&root.join("plain.fmemo"), "# Plain").unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("plain.fmemo")).unwrap(),
+            "# Plain"
+        );
+
+        assert!(lock(root));
+        assert!(!lock(root));
+        assert_eq!(
+            read_memo(&path).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+}
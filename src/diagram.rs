@@ -0,0 +1,48 @@
+use crate::schema::DiagramKind;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static RENDER_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Render a diagram to SVG using an external renderer.
+///
+/// Mermaid is rendered with `mmdc` (mermaid-cli), which must be on `PATH`;
+/// an `ErrorKind::NotFound` error means the renderer isn't installed.
+pub fn render_svg(kind: DiagramKind, source: &str) -> std::io::Result<String> {
+    match kind {
+        DiagramKind::Mermaid => render_mermaid(source),
+    }
+}
+
+fn render_mermaid(source: &str) -> std::io::Result<String> {
+    let id = RENDER_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let base = std::env::temp_dir().join(format!("fmemo-diagram-{}-{}", std::process::id(), id));
+    let input = base.with_extension("mmd");
+    let output = base.with_extension("svg");
+    fs::write(&input, source)?;
+
+    let result = run_mmdc(&input, &output);
+    let _ = fs::remove_file(&input);
+    let svg = result.and_then(|_| fs::read_to_string(&output));
+    let _ = fs::remove_file(&output);
+    svg
+}
+
+fn run_mmdc(input: &PathBuf, output: &PathBuf) -> std::io::Result<()> {
+    let result = Command::new("mmdc")
+        .arg("-i")
+        .arg(input)
+        .arg("-o")
+        .arg(output)
+        .arg("--quiet")
+        .output()?;
+    if !result.status.success() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            String::from_utf8_lossy(&result.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
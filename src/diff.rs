@@ -0,0 +1,177 @@
+//! Memo-level comparison of two versions of a file.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::parser::{ParseOptions, normalize_source, parse_document};
+use crate::schema::Memo;
+
+/// A memo's own lines (children excluded), identified by its heading path
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Section {
+    /// Titles from the top-level ancestor down to the memo itself
+    pub titles: Vec<String>,
+    /// 1-based count among memos with the same heading path
+    pub occurrence: usize,
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+impl Section {
+    pub(crate) fn key(&self) -> (&[String], usize) {
+        (&self.titles, self.occurrence)
+    }
+}
+
+/// Sections of every memo in `content`, in source order
+pub(crate) fn sections(content: &str) -> Vec<Section> {
+    fn walk(
+        memos: &[Memo],
+        parent: &[String],
+        seen: &mut HashMap<Vec<String>, usize>,
+        out: &mut Vec<Section>,
+    ) {
+        for memo in memos {
+            let mut titles = parent.to_vec();
+            titles.push(memo.title().clone());
+            let occurrence = seen.entry(titles.clone()).or_default();
+            *occurrence += 1;
+            if let Some(span) = memo.span() {
+                out.push(Section {
+                    titles: titles.clone(),
+                    occurrence: *occurrence,
+                    start_line: span.start_line,
+                    end_line: span.end_line,
+                });
+            }
+            walk(memo.children(), &titles, seen, out);
+        }
+    }
+
+    let mut out = Vec::new();
+    let document = parse_document(content, &ParseOptions::default());
+    walk(&document.memos, &[], &mut HashMap::new(), &mut out);
+    out.sort_by_key(|section| section.start_line);
+    out
+}
+
+/// What happened to a memo between two versions
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// A memo that differs between two versions of a file
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct MemoChange {
+    pub kind: MemoChangeKind,
+    /// Titles from the top-level ancestor down to the memo itself
+    pub titles: Vec<String>,
+    /// Heading line in the old version
+    pub old_line: Option<usize>,
+    /// Heading line in the new version
+    pub new_line: Option<usize>,
+}
+
+/// Memos added, removed or changed between `old` and `new`. A memo counts as changed when
+/// its own section differs; changes in children are reported for the children.
+/// Memos are matched by heading path, so a renamed memo is removed and added.
+/// Added and changed memos come first in new order, then removed ones in old order.
+pub fn diff_memos(old: &str, new: &str) -> Vec<MemoChange> {
+    let old = normalize_source(old);
+    let new = normalize_source(new);
+    let old_lines: Vec<&str> = old.split('\n').collect();
+    let new_lines: Vec<&str> = new.split('\n').collect();
+    let section_text = |lines: &[&str], section: &Section| {
+        lines[section.start_line - 1..section.end_line.min(lines.len())]
+            .join("\n")
+            .trim_end()
+            .to_string()
+    };
+
+    let old_sections = sections(&old);
+    let new_sections = sections(&new);
+    let old_by_key: HashMap<_, _> = old_sections
+        .iter()
+        .map(|section| (section.key(), section))
+        .collect();
+    let new_keys: HashSet<_> = new_sections.iter().map(Section::key).collect();
+
+    let mut changes = Vec::new();
+    for section in &new_sections {
+        let kind = match old_by_key.get(&section.key()) {
+            None => MemoChangeKind::Added,
+            Some(old_section)
+                if section_text(&old_lines, old_section) != section_text(&new_lines, section) =>
+            {
+                MemoChangeKind::Changed
+            }
+            Some(_) => continue,
+        };
+        changes.push(MemoChange {
+            kind,
+            titles: section.titles.clone(),
+            old_line: old_by_key.get(&section.key()).map(|old| old.start_line),
+            new_line: Some(section.start_line),
+        });
+    }
+    for section in &old_sections {
+        if !new_keys.contains(&section.key()) {
+            changes.push(MemoChange {
+                kind: MemoChangeKind::Removed,
+                titles: section.titles.clone(),
+                old_line: Some(section.start_line),
+                new_line: None,
+            });
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoChange, MemoChangeKind, diff_memos};
+
+    fn summary(old: &str, new: &str) -> Vec<(MemoChangeKind, String)> {
+        diff_memos(old, new)
+            .into_iter()
+            .map(|MemoChange { kind, titles, .. }| (kind, titles.join(" > ")))
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_memos() {
+        let old = "# A\nalpha\n## B\nbeta\n## C\ngamma\n# D\n";
+        let new = "# A\nalpha\n\n## B\nbeta!\n## E\n# D\n";
+        // Only a trailing blank line was added to A's own section
+        assert_eq!(
+            summary(old, new),
+            [
+                (MemoChangeKind::Changed, "A > B".to_string()),
+                (MemoChangeKind::Added, "A > E".to_string()),
+                (MemoChangeKind::Removed, "A > C".to_string()),
+            ]
+        );
+
+        let changes = diff_memos(old, new);
+        assert_eq!(changes[0].old_line, Some(3));
+        assert_eq!(changes[0].new_line, Some(4));
+        assert!(diff_memos(old, &old.replace('\n', "\r\n")).is_empty());
+    }
+
+    #[test]
+    fn test_diff_matches_repeated_titles_by_occurrence() {
+        let old = "# Log\n## Entry\none\n## Entry\ntwo\n";
+        let new = "# Log\n## Entry\none\n## Entry\ntwo\n## Entry\nthree\n";
+        let changes = diff_memos(old, new);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].kind, MemoChangeKind::Added);
+        assert_eq!(changes[0].new_line, Some(6));
+        assert_eq!(
+            summary("", "# New"),
+            [(MemoChangeKind::Added, "New".to_string())]
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! Autosaved editor drafts, kept apart from the memo files in `.fmemo/drafts/`.
+//!
+//! Saving a draft doesn't touch the memo file, so the watcher and WebSocket clients
+//! don't see half-written content. A draft is discarded once its file is saved.
+
+use std::path::{Path, PathBuf};
+
+use crate::config::FMEMO_DIR;
+
+/// Unsaved content of a memo file
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct Draft {
+    /// File path relative to the root
+    pub path: String,
+    pub content: String,
+    /// RFC 3339
+    pub saved_at: String,
+}
+
+/// Where the draft of a memo file (relative to the root) is stored. The `.json`
+/// extension keeps the watcher from treating it as a memo.
+pub fn draft_path(root: &Path, relative: &str) -> PathBuf {
+    root.join(FMEMO_DIR)
+        .join("drafts")
+        .join(format!("{}.json", relative))
+}
+
+/// Store the draft of a file, replacing the previous one
+pub fn save_draft(root: &Path, relative: &str, content: &str) -> std::io::Result<Draft> {
+    if crate::crypt::is_encrypted(Path::new(relative)) {
+        // The draft would be stored in plaintext
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Encrypted memos have no drafts",
+        ));
+    }
+    let draft = Draft {
+        path: relative.to_string(),
+        content: content.to_string(),
+        saved_at: chrono::Utc::now().to_rfc3339(),
+    };
+    let path = draft_path(root, relative);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_string(&draft)?)?;
+    Ok(draft)
+}
+
+/// The draft of a file; `NotFound` when there is none
+pub fn load_draft(root: &Path, relative: &str) -> std::io::Result<Draft> {
+    let content = std::fs::read_to_string(draft_path(root, relative)).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "No draft for this file")
+        } else {
+            e
+        }
+    })?;
+    serde_json::from_str(&content)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))
+}
+
+/// Remove the draft of a file; returns whether there was one
+pub fn discard_draft(root: &Path, relative: &str) -> std::io::Result<bool> {
+    match std::fs::remove_file(draft_path(root, relative)) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{discard_draft, load_draft, save_draft};
+    use crate::server::list_memo_files;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_drafts() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        assert_eq!(
+            load_draft(root, "notes/a.fmemo").unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+
+        save_draft(root, "notes/a.fmemo", "# Half").unwrap();
+        let draft = save_draft(root, "notes/a.fmemo", "# Half written").unwrap();
+        assert_eq!(load_draft(root, "notes/a.fmemo").unwrap(), draft);
+        assert!(!root.join("notes/a.fmemo").exists());
+        assert!(list_memo_files(root).unwrap().is_empty());
+
+        assert!(discard_draft(root, "notes/a.fmemo").unwrap());
+        assert!(!discard_draft(root, "notes/a.fmemo").unwrap());
+        assert!(load_draft(root, "notes/a.fmemo").is_err());
+    }
+}
@@ -0,0 +1,255 @@
+//! Duplicate detection over the index, for `GET /api/duplicates`, to clean up notes copied
+//! between machines.
+//!
+//! Texts are compared as lowercase words, so case, punctuation, whitespace and line endings
+//! don't matter. Identical texts are grouped by their SHA-256 hash; near-identical ones are
+//! paired by the Jaccard similarity of their three-word shingles. Memos are compared by
+//! their own title, descriptions and content (not their children's), and only when they
+//! have at least `MIN_MEMO_WORDS` words, so short headings like `# Notes` don't match.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+use sha2::{Digest, Sha256};
+
+use crate::crypt::to_hex;
+use crate::indexer::Documents;
+use crate::schema::Memo;
+use crate::search::searchable_text;
+
+/// Similarity above which texts count as near-identical, when none is requested
+pub const DEFAULT_THRESHOLD: f32 = 0.9;
+/// Memos with fewer words are left out
+pub const MIN_MEMO_WORDS: usize = 8;
+/// Words per shingle
+const SHINGLE_WORDS: usize = 3;
+
+/// A file, or a memo of one
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct DuplicateItem {
+    /// File path relative to the root
+    pub file: String,
+    /// Anchor of the memo; `None` for the file as a whole
+    pub slug: Option<String>,
+    pub title: String,
+    pub line: usize,
+}
+
+/// Files or memos with the same or nearly the same text
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct DuplicateGroup {
+    /// 1 for identical texts
+    pub similarity: f32,
+    pub items: Vec<DuplicateItem>,
+}
+
+/// Everything found, identical groups first, then the most similar pairs
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct Duplicates {
+    pub files: Vec<DuplicateGroup>,
+    pub memos: Vec<DuplicateGroup>,
+}
+
+struct Text {
+    item: DuplicateItem,
+    hash: String,
+    shingles: HashSet<u64>,
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+impl Text {
+    fn new(item: DuplicateItem, words: &[String]) -> Self {
+        let mut shingles = HashSet::new();
+        for shingle in words.windows(SHINGLE_WORDS.min(words.len()).max(1)) {
+            let mut hasher = DefaultHasher::new();
+            shingle.hash(&mut hasher);
+            shingles.insert(hasher.finish());
+        }
+        Self {
+            item,
+            hash: to_hex(&Sha256::digest(words.join(" ").as_bytes())),
+            shingles,
+        }
+    }
+}
+
+fn jaccard(a: &HashSet<u64>, b: &HashSet<u64>) -> f32 {
+    let shared = a.intersection(b).count();
+    shared as f32 / (a.len() + b.len() - shared) as f32
+}
+
+/// Groups of identical texts, then pairs of near-identical ones from different groups.
+/// Pairs for which `skip` is true are left out.
+fn find(
+    texts: &[Text],
+    threshold: f32,
+    skip: impl Fn(&Text, &Text) -> bool,
+) -> Vec<DuplicateGroup> {
+    let mut by_hash: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (index, text) in texts.iter().enumerate() {
+        by_hash.entry(&text.hash).or_default().push(index);
+    }
+    let mut groups: Vec<DuplicateGroup> = by_hash
+        .values()
+        .filter(|indices| {
+            indices.len() > 1
+                && indices
+                    .iter()
+                    .any(|&other| !skip(&texts[indices[0]], &texts[other]))
+        })
+        .map(|indices| DuplicateGroup {
+            similarity: 1.0,
+            items: indices.iter().map(|&i| texts[i].item.clone()).collect(),
+        })
+        .collect();
+
+    // One text per hash; a pair can only reach the threshold when the smaller set has at
+    // least `threshold` times the elements of the larger one
+    let mut distinct: Vec<&Text> = by_hash.values().map(|indices| &texts[indices[0]]).collect();
+    distinct.sort_by_key(|text| text.shingles.len());
+    let mut pairs = Vec::new();
+    for (i, a) in distinct.iter().enumerate() {
+        for b in &distinct[i + 1..] {
+            if (a.shingles.len() as f32) < threshold * b.shingles.len() as f32 {
+                break;
+            }
+            let similarity = jaccard(&a.shingles, &b.shingles);
+            if similarity >= threshold && !skip(a, b) {
+                pairs.push(DuplicateGroup {
+                    similarity,
+                    items: vec![a.item.clone(), b.item.clone()],
+                });
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    groups.extend(pairs);
+    groups
+}
+
+fn collect_memos(memos: &[Memo], file: &str, texts: &mut Vec<Text>) {
+    for memo in memos {
+        let words = words(&searchable_text(memo));
+        if words.len() >= MIN_MEMO_WORDS {
+            let item = DuplicateItem {
+                file: file.to_string(),
+                slug: Some(memo.anchor().into_owned()),
+                title: memo.title().clone(),
+                line: memo.span().map_or(0, |span| span.start_line),
+            };
+            texts.push(Text::new(item, &words));
+        }
+        collect_memos(memo.children(), file, texts);
+    }
+}
+
+/// Identical and near-identical files and memos among the indexed documents. Memos of two
+/// identical files aren't reported again.
+pub fn duplicates(documents: &Documents, threshold: f32) -> Duplicates {
+    let mut files = Vec::new();
+    let mut memos = Vec::new();
+    for (file, file_memos) in documents {
+        let mut all_words = Vec::new();
+        let mut stack: Vec<&Memo> = file_memos.iter().rev().collect();
+        while let Some(memo) = stack.pop() {
+            all_words.extend(words(&searchable_text(memo)));
+            stack.extend(memo.children().iter().rev());
+        }
+        if !all_words.is_empty() {
+            let item = DuplicateItem {
+                file: file.clone(),
+                slug: None,
+                title: file_memos
+                    .first()
+                    .map_or_else(|| file.clone(), |memo| memo.title().clone()),
+                line: 1,
+            };
+            files.push(Text::new(item, &all_words));
+        }
+        collect_memos(file_memos, file, &mut memos);
+    }
+
+    let file_hashes: HashMap<&str, &str> = files
+        .iter()
+        .map(|text| (text.item.file.as_str(), text.hash.as_str()))
+        .collect();
+    let same_file = |a: &Text, b: &Text| {
+        a.item.file == b.item.file
+            || file_hashes.get(a.item.file.as_str()) == file_hashes.get(b.item.file.as_str())
+    };
+    Duplicates {
+        files: find(&files, threshold, |_, _| false),
+        memos: find(&memos, threshold, same_file),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::duplicates;
+    use crate::indexer::Documents;
+    use crate::parser::{ParseOptions, parse_document};
+
+    #[test]
+    fn test_duplicates() {
+        let setup = "## Setup\nInstall the toolchain, clone the repository and run the tests before \
+                     sending a pull request to the project";
+        let mut documents = Documents::new();
+        for (file, content) in [
+            ("laptop/rust.fmemo", format!("# Rust\n{}", setup)),
+            (
+                "desktop/rust.fmemo",
+                format!("# rust\n{}", setup.replace(", ", " ")),
+            ),
+            (
+                "work/onboarding.fmemo",
+                format!(
+                    "# Onboarding\n{}\n## Accounts\nAsk for a badge",
+                    setup.replace("tests", "test suite")
+                ),
+            ),
+            ("notes.fmemo", "# Notes\n## Setup\nShort".to_string()),
+            ("other.fmemo", "# Other\n## Setup\nShort".to_string()),
+        ] {
+            documents.insert(
+                file.to_string(),
+                parse_document(&content, &ParseOptions::default()).memos,
+            );
+        }
+
+        let found = duplicates(&documents, 0.6);
+        assert_eq!(found.files[0].similarity, 1.0);
+        let files: Vec<&str> = found.files[0]
+            .items
+            .iter()
+            .map(|item| item.file.as_str())
+            .collect();
+        assert_eq!(files, ["desktop/rust.fmemo", "laptop/rust.fmemo"]);
+        // The copies' memos are only reported against the third file
+        assert!(found.memos.iter().all(|group| {
+            group
+                .items
+                .iter()
+                .any(|item| item.file == "work/onboarding.fmemo")
+        }));
+        let similar = &found.memos[0];
+        assert!(similar.similarity >= 0.6 && similar.similarity < 1.0);
+        assert_eq!(similar.items[0].slug.as_deref(), Some("setup"));
+        // Too short to count
+        assert!(
+            found
+                .files
+                .iter()
+                .chain(&found.memos)
+                .all(|group| group.items.iter().all(|item| item.file != "other.fmemo"))
+        );
+
+        assert!(duplicates(&documents, 1.0).memos.is_empty());
+    }
+}
@@ -0,0 +1,259 @@
+//! Transclusion: `![[target]]` embeds another memo file, `![[target#anchor]]` one memo of
+//! it with its children. Targets resolve like wiki-links.
+//!
+//! Embeds are collected when a memo is parsed; `resolve_embeds` fills in the embedded file
+//! and, on request, the embedded memos as HTML, with their own embeds expanded in turn. An
+//! embed of a memo that contains it, directly or through other files, is a cycle and isn't
+//! expanded, and neither are embeds nested more than `MAX_DEPTH` levels deep. Placeholders
+//! in expanded memos are filled in with the embedded file as `{{file}}`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::Path;
+
+use crate::graph::resolve_wiki_target;
+use crate::parser::{find_memo_by_slug, resolve_image_paths};
+use crate::plugin::Plugins;
+use crate::render::sections_html;
+use crate::schema::{Embed, EmbedError, Memo};
+use crate::server::{list_memo_files, read_fmemo_file_with};
+use crate::template::{TemplateContext, expand_variables};
+
+/// Levels of embeds inside embeds that are expanded
+pub const MAX_DEPTH: usize = 4;
+
+/// A memo an embed is expanded from: its file and the anchors of it and its ancestors
+type Frame = (String, Vec<String>);
+
+struct Resolver<'a> {
+    root: &'a Path,
+    plugins: &'a Plugins,
+    /// Embedded files are read without their `<private>` regions
+    publish: bool,
+    files: Vec<String>,
+    expand: bool,
+    /// `[variables]` from the config, when expanding
+    variables: BTreeMap<String, String>,
+    /// Parsed memo files by path; `None` for files that couldn't be read
+    documents: HashMap<String, Option<Vec<Memo>>>,
+}
+
+/// Anchors of the memos above the one with `anchor`, outermost first
+fn ancestor_anchors(memos: &[Memo], anchor: &str) -> Option<Vec<String>> {
+    memos.iter().find_map(|memo| {
+        if memo.anchor() == anchor {
+            Some(Vec::new())
+        } else {
+            let mut anchors = ancestor_anchors(memo.children(), anchor)?;
+            anchors.insert(0, memo.anchor().into_owned());
+            Some(anchors)
+        }
+    })
+}
+
+fn has_embeds(memos: &[Memo]) -> bool {
+    memos
+        .iter()
+        .any(|memo| !memo.embeds().is_empty() || has_embeds(memo.children()))
+}
+
+impl Resolver<'_> {
+    fn document(&mut self, path: &str) -> Option<Vec<Memo>> {
+        let (root, plugins, publish) = (self.root, self.plugins, self.publish);
+        self.documents
+            .entry(path.to_string())
+            .or_insert_with(|| {
+                read_fmemo_file_with(root.join(path), plugins, publish)
+                    .ok()
+                    .map(|content| content.memos)
+            })
+            .clone()
+    }
+
+    fn resolve(
+        &mut self,
+        memos: &mut [Memo],
+        file: &str,
+        anchors: &mut Vec<String>,
+        stack: &mut Vec<Frame>,
+    ) {
+        for memo in memos {
+            anchors.push(memo.anchor().into_owned());
+            let mut embeds = std::mem::take(memo.embeds_mut());
+            for embed in &mut embeds {
+                self.resolve_embed(embed, file, anchors, stack);
+            }
+            *memo.embeds_mut() = embeds;
+            self.resolve(memo.children_mut(), file, anchors, stack);
+            anchors.pop();
+        }
+    }
+
+    fn resolve_embed(
+        &mut self,
+        embed: &mut Embed,
+        file: &str,
+        anchors: &[String],
+        stack: &mut Vec<Frame>,
+    ) {
+        let Some(path) = resolve_wiki_target(&self.files, &embed.target).cloned() else {
+            embed.error = Some(EmbedError::NotFound);
+            return;
+        };
+        embed.path = Some(path.clone());
+        let Some(document) = self.document(&path) else {
+            embed.error = Some(EmbedError::NotFound);
+            return;
+        };
+        let (mut memos, mut inner_anchors) = match &embed.anchor {
+            None => (document, Vec::new()),
+            Some(anchor) => match find_memo_by_slug(&document, anchor) {
+                Some(memo) => (
+                    vec![memo.clone()],
+                    ancestor_anchors(&document, anchor).unwrap_or_default(),
+                ),
+                None => {
+                    embed.error = Some(EmbedError::NotFound);
+                    return;
+                }
+            },
+        };
+
+        // Whether the embedded memos contain the memo at `anchors` in `file`
+        let contains = |file: &str, anchors: &[String]| {
+            file == path
+                && embed
+                    .anchor
+                    .as_ref()
+                    .is_none_or(|anchor| anchors.contains(anchor))
+        };
+        if contains(file, anchors) || stack.iter().any(|(file, anchors)| contains(file, anchors)) {
+            embed.error = Some(EmbedError::Cycle);
+            return;
+        }
+        if stack.len() >= MAX_DEPTH {
+            embed.error = Some(EmbedError::TooDeep);
+            return;
+        }
+        if !self.expand {
+            return;
+        }
+
+        resolve_image_paths(&mut memos, &path);
+        stack.push((file.to_string(), anchors.to_vec()));
+        self.resolve(&mut memos, &path, &mut inner_anchors, stack);
+        stack.pop();
+        let mut context = TemplateContext::new(
+            memos.first().map_or("", |memo| memo.title().as_str()),
+            &path,
+        );
+        context.variables = self.variables.clone();
+        expand_variables(&mut memos, &context);
+        embed.html = Some(sections_html(&memos, &path));
+    }
+}
+
+/// Resolve the embeds of memos read from `file` (relative to `root`): the embedded file,
+/// or why it can't be embedded, and with `expand` the embedded memos as HTML for
+/// `render::memo_html`. With `publish`, embedded files are read without their private regions.
+pub fn resolve_embeds(
+    root: &Path,
+    plugins: &Plugins,
+    publish: bool,
+    file: &str,
+    memos: &mut [Memo],
+    expand: bool,
+) -> io::Result<()> {
+    if !has_embeds(memos) {
+        return Ok(());
+    }
+    let mut resolver = Resolver {
+        root,
+        plugins,
+        publish,
+        files: list_memo_files(root)?,
+        expand,
+        variables: match expand {
+            true => crate::config::load_config(root)?.variables,
+            false => BTreeMap::new(),
+        },
+        documents: HashMap::new(),
+    };
+    resolver.resolve(memos, file, &mut Vec::new(), &mut Vec::new());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::memo_html;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn resolved(root: &Path, file: &str, expand: bool) -> Vec<Memo> {
+        let mut memos = read_fmemo_file_with(root.join(file), &Plugins::default(), false)
+            .unwrap()
+            .memos;
+        resolve_embeds(root, &Plugins::default(), false, file, &mut memos, expand).unwrap();
+        memos
+    }
+
+    #[test]
+    fn test_resolve_embeds() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("team")).unwrap();
+        fs::write(
+            root.join("index.fmemo"),
+            "# Index\n![[plan#next-steps]]\n\n![[missing]]\n## Loop\n![[index#loop]]",
+        )
+        .unwrap();
+        fs::write(
+            root.join("team/plan.fmemo"),
+            "# Plan\n## Next steps\nShip it\n\n![[index]]\n## Later\nRest",
+        )
+        .unwrap();
+
+        let memos = resolved(root, "index.fmemo", false);
+        let embeds = memos[0].embeds();
+        assert_eq!(embeds[0].path.as_deref(), Some("team/plan.fmemo"));
+        assert_eq!(embeds[0].anchor.as_deref(), Some("next-steps"));
+        assert_eq!(embeds[0].error, None);
+        assert_eq!(embeds[0].html, None);
+        assert_eq!(embeds[1].error, Some(EmbedError::NotFound));
+        assert_eq!(
+            memos[0].children()[0].embeds()[0].error,
+            Some(EmbedError::Cycle)
+        );
+
+        let memos = resolved(root, "index.fmemo", true);
+        let html = memo_html(&memos[0], "index.fmemo");
+        assert!(html.starts_with(
+            "<div class=\"embed\" data-target=\"plan#next-steps\"><section><h2 id=\"next-steps\">Next steps</h2><p>Ship it</p>"
+        ));
+        // The plan embeds the index back, which isn't expanded again
+        assert!(html.contains("<p><a class=\"embed\" data-target=\"index\">index</a></p>"));
+        assert!(!html.contains("Rest"));
+        assert!(html.ends_with("<p><a class=\"embed\" data-target=\"missing\">missing</a></p>"));
+    }
+
+    #[test]
+    fn test_resolve_embeds_depth() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        for n in 0..=MAX_DEPTH + 1 {
+            fs::write(
+                root.join(format!("{}.fmemo", n)),
+                format!("# Note {}\n![[{}]]", n, n + 1),
+            )
+            .unwrap();
+        }
+
+        let memos = resolved(root, "0.fmemo", true);
+        assert_eq!(memos[0].embeds()[0].error, None);
+        let html = memo_html(&memos[0], "0.fmemo");
+        assert_eq!(html.matches("<div class=\"embed\"").count(), MAX_DEPTH);
+        assert!(html.contains(&format!("<h1 id=\"note-{}\">", MAX_DEPTH)));
+        assert!(!html.contains(&format!("<h1 id=\"note-{}\">", MAX_DEPTH + 1)));
+    }
+}
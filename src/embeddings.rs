@@ -0,0 +1,373 @@
+//! Embedding vectors of every memo, for `GET /api/search?mode=semantic`.
+//!
+//! The indexer hands each new snapshot of its documents to a worker thread, which embeds
+//! the memos whose text changed and swaps in the new vectors; unchanged memos keep their
+//! vector, so the embedding API is only called for what was edited. Queries are embedded
+//! the same way and memos ranked by cosine similarity.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::sync::mpsc::{Sender, channel};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::config::EmbeddingsConfig;
+use crate::indexer::Documents;
+use crate::schema::Memo;
+use crate::search::searchable_text;
+
+/// Turns texts into vectors of the same length
+pub trait Embedder: Send + Sync {
+    fn embed(&self, texts: &[String]) -> io::Result<Vec<Vec<f32>>>;
+}
+
+/// An OpenAI-compatible `POST {url}/embeddings` API
+pub struct ApiEmbedder {
+    url: String,
+    config: EmbeddingsConfig,
+    agent: ureq::Agent,
+}
+
+impl ApiEmbedder {
+    /// `None` when no URL is configured
+    pub fn new(config: EmbeddingsConfig) -> Option<Self> {
+        let url = config.url.clone().filter(|url| !url.is_empty())?;
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build();
+        Some(Self { url, config, agent })
+    }
+}
+
+/// The vectors of an embeddings response, in input order
+pub fn parse_embeddings(response: &serde_json::Value, count: usize) -> io::Result<Vec<Vec<f32>>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Malformed embeddings response");
+    let mut data: Vec<(usize, Vec<f32>)> = response["data"]
+        .as_array()
+        .ok_or_else(invalid)?
+        .iter()
+        .enumerate()
+        .map(|(position, item)| {
+            let index = item["index"]
+                .as_u64()
+                .map_or(position, |index| index as usize);
+            let vector = item["embedding"]
+                .as_array()?
+                .iter()
+                .map(|value| value.as_f64().map(|value| value as f32))
+                .collect::<Option<Vec<f32>>>()?;
+            Some((index, vector))
+        })
+        .collect::<Option<_>>()
+        .ok_or_else(invalid)?;
+    if data.len() != count {
+        return Err(invalid());
+    }
+    data.sort_by_key(|(index, _)| *index);
+    Ok(data.into_iter().map(|(_, vector)| vector).collect())
+}
+
+impl Embedder for ApiEmbedder {
+    fn embed(&self, texts: &[String]) -> io::Result<Vec<Vec<f32>>> {
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(self.config.batch_size.max(1)) {
+            let input: Vec<String> = batch
+                .iter()
+                .map(|text| text.chars().take(self.config.max_input_chars).collect())
+                .collect();
+            let body = serde_json::json!({"model": self.config.model, "input": input});
+            let mut request = self
+                .agent
+                .post(&format!("{}/embeddings", self.url.trim_end_matches('/')))
+                .set("Content-Type", "application/json");
+            if let Some(key) = &self.config.api_key {
+                request = request.set("Authorization", &format!("Bearer {}", key));
+            }
+            let response = request
+                .send_string(&body.to_string())
+                .map_err(|e| io::Error::other(format!("Embedding request failed: {}", e)))?;
+            let response: serde_json::Value = serde_json::from_str(&response.into_string()?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            vectors.extend(parse_embeddings(&response, batch.len())?);
+        }
+        Ok(vectors)
+    }
+}
+
+/// A memo ranked by similarity to the query
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct SemanticHit {
+    /// File path relative to the root
+    pub file: String,
+    /// Line of the memo heading
+    pub line: usize,
+    pub title: String,
+    /// Cosine similarity, 1 for the same direction
+    pub score: f32,
+}
+
+#[derive(Debug, Clone)]
+struct EmbeddedMemo {
+    file: String,
+    line: usize,
+    title: String,
+    vector: Arc<Vec<f32>>,
+}
+
+fn text_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+/// Handle to the vectors of the indexed memos; clones share them
+#[derive(Clone)]
+pub struct EmbeddingIndex {
+    embedder: Arc<dyn Embedder>,
+    memos: Arc<RwLock<Vec<EmbeddedMemo>>>,
+    /// Vectors by hash of the embedded text, for the memos of the current snapshot
+    cache: Arc<Mutex<HashMap<u64, Arc<Vec<f32>>>>>,
+    /// Snapshots waiting for the worker
+    sender: Sender<Arc<Documents>>,
+}
+
+impl std::fmt::Debug for EmbeddingIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmbeddingIndex")
+            .field("memos", &self.len())
+            .finish()
+    }
+}
+
+impl EmbeddingIndex {
+    /// Start the worker thread; it stops when every handle is dropped
+    pub fn start(embedder: Arc<dyn Embedder>) -> Self {
+        let (sender, receiver) = channel::<Arc<Documents>>();
+        let index = Self {
+            embedder,
+            memos: Arc::new(RwLock::new(Vec::new())),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            sender,
+        };
+        let worker = Self {
+            sender: channel().0,
+            ..index.clone()
+        };
+        std::thread::spawn(move || {
+            while let Ok(mut documents) = receiver.recv() {
+                // Only the newest snapshot matters
+                while let Ok(newer) = receiver.try_recv() {
+                    documents = newer;
+                }
+                if let Err(e) = worker.refresh(&documents) {
+                    eprintln!("Failed to embed memos: {}", e);
+                }
+            }
+        });
+        index
+    }
+
+    /// Embed a new snapshot in the background
+    pub fn update(&self, documents: Arc<Documents>) {
+        let _ = self.sender.send(documents);
+    }
+
+    /// Memos with a vector
+    pub fn len(&self) -> usize {
+        self.memos.read().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Embed the memos of `documents` whose text isn't cached yet and replace the vectors.
+    /// On failure the previous vectors stay.
+    pub fn refresh(&self, documents: &Documents) -> io::Result<()> {
+        fn collect(memos: &[Memo], file: &str, out: &mut Vec<(String, usize, String, String)>) {
+            for memo in memos {
+                out.push((
+                    file.to_string(),
+                    memo.span().map_or(0, |span| span.start_line),
+                    memo.title().to_string(),
+                    searchable_text(memo),
+                ));
+                collect(memo.children(), file, out);
+            }
+        }
+        let mut entries = Vec::new();
+        for (file, memos) in documents {
+            collect(memos, file, &mut entries);
+        }
+
+        let mut vectors: HashMap<u64, Arc<Vec<f32>>> =
+            self.cache.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let hashes: Vec<u64> = entries
+            .iter()
+            .map(|(_, _, _, text)| text_hash(text))
+            .collect();
+        let mut missing: Vec<(u64, String)> = Vec::new();
+        let mut queued = HashSet::new();
+        for ((_, _, _, text), &hash) in entries.iter().zip(&hashes) {
+            if !vectors.contains_key(&hash) && queued.insert(hash) {
+                missing.push((hash, text.clone()));
+            }
+        }
+        if !missing.is_empty() {
+            let texts: Vec<String> = missing.iter().map(|(_, text)| text.clone()).collect();
+            let embedded = self.embedder.embed(&texts)?;
+            for ((hash, _), vector) in missing.into_iter().zip(embedded) {
+                vectors.insert(hash, Arc::new(vector));
+            }
+        }
+        // Forget the vectors of texts that are gone
+        let used: HashSet<u64> = hashes.iter().copied().collect();
+        vectors.retain(|hash, _| used.contains(hash));
+
+        let memos: Vec<EmbeddedMemo> = entries
+            .into_iter()
+            .zip(hashes)
+            .filter_map(|((file, line, title, _), hash)| {
+                Some(EmbeddedMemo {
+                    file,
+                    line,
+                    title,
+                    vector: vectors.get(&hash)?.clone(),
+                })
+            })
+            .collect();
+        *self.cache.lock().unwrap_or_else(|e| e.into_inner()) = vectors;
+        *self.memos.write().unwrap_or_else(|e| e.into_inner()) = memos;
+        Ok(())
+    }
+
+    /// Cosine similarity of the mean vector of `file`'s memos to that of every other file
+    pub fn file_similarities(&self, file: &str) -> HashMap<String, f32> {
+        let memos = self.memos.read().unwrap_or_else(|e| e.into_inner());
+        let mut sums: HashMap<&str, Vec<f32>> = HashMap::new();
+        for memo in memos.iter() {
+            let sum = sums.entry(&memo.file).or_default();
+            sum.resize(sum.len().max(memo.vector.len()), 0.0);
+            sum.iter_mut()
+                .zip(memo.vector.iter())
+                .for_each(|(s, v)| *s += v);
+        }
+        // Summed rather than averaged vectors: cosine doesn't depend on length
+        let Some(own) = sums.get(file) else {
+            return HashMap::new();
+        };
+        sums.iter()
+            .filter(|(path, _)| **path != file)
+            .map(|(path, sum)| (path.to_string(), cosine(own, sum)))
+            .collect()
+    }
+
+    /// The `limit` memos most similar to `query`, best first. Blocks while the query is
+    /// embedded.
+    pub fn search(&self, query: &str, limit: usize) -> io::Result<Vec<SemanticHit>> {
+        if query.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        let query = self
+            .embedder
+            .embed(&[query.to_string()])?
+            .pop()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No query embedding"))?;
+        let memos = self.memos.read().unwrap_or_else(|e| e.into_inner());
+        let mut hits: Vec<SemanticHit> = memos
+            .iter()
+            .map(|memo| SemanticHit {
+                file: memo.file.clone(),
+                line: memo.line,
+                title: memo.title.clone(),
+                score: cosine(&query, &memo.vector),
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(limit);
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{Embedder, EmbeddingIndex, parse_embeddings};
+    use crate::indexer::Documents;
+    use crate::parser::{ParseOptions, parse_document};
+    use std::sync::{Arc, Mutex};
+
+    /// One dimension per topic, so paraphrases land close together
+    pub(crate) struct TopicEmbedder {
+        pub calls: Mutex<Vec<usize>>,
+    }
+
+    impl Embedder for TopicEmbedder {
+        fn embed(&self, texts: &[String]) -> std::io::Result<Vec<Vec<f32>>> {
+            self.calls.lock().unwrap().push(texts.len());
+            let topics = [
+                &["car", "automobile", "vehicle", "engine"][..],
+                &["pizza", "pasta", "food", "dinner"][..],
+            ];
+            Ok(texts
+                .iter()
+                .map(|text| {
+                    let text = text.to_lowercase();
+                    topics
+                        .iter()
+                        .map(|words| words.iter().filter(|w| text.contains(*w)).count() as f32)
+                        .collect()
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_semantic_search() {
+        let embedder = Arc::new(TopicEmbedder {
+            calls: Mutex::new(Vec::new()),
+        });
+        let index = EmbeddingIndex::start(embedder.clone());
+        let parse = |content: &str| parse_document(content, &ParseOptions::default()).memos;
+        let mut documents = Documents::new();
+        documents.insert("a.fmemo".to_string(), parse("# Garage\nfix the car engine"));
+        documents.insert("b.fmemo".to_string(), parse("# Tonight\npasta for dinner"));
+        index.refresh(&documents).unwrap();
+        assert_eq!(index.len(), 2);
+
+        let hits = index.search("my automobile", 5).unwrap();
+        assert_eq!(hits[0].title, "Garage");
+        assert!(hits[0].score > hits[1].score);
+        assert_eq!(index.search("food", 1).unwrap()[0].file, "b.fmemo");
+        assert!(index.search(" ", 5).unwrap().is_empty());
+        // Garage and dinner notes have nothing in common
+        assert_eq!(index.file_similarities("a.fmemo")["b.fmemo"], 0.0);
+
+        // Only the edited memo is embedded again
+        documents.insert("b.fmemo".to_string(), parse("# Tonight\npizza"));
+        index.refresh(&documents).unwrap();
+        assert_eq!(*embedder.calls.lock().unwrap(), [2, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_parse_embeddings() {
+        let response = serde_json::json!({"data": [
+            {"index": 1, "embedding": [0.5, 1.0]},
+            {"index": 0, "embedding": [1.0, 0.0]}
+        ]});
+        assert_eq!(
+            parse_embeddings(&response, 2).unwrap(),
+            [vec![1.0, 0.0], vec![0.5, 1.0]]
+        );
+        assert!(parse_embeddings(&response, 3).is_err());
+        assert!(parse_embeddings(&serde_json::json!({}), 0).is_err());
+    }
+}
@@ -0,0 +1,432 @@
+//! Git integration for roots that are (inside) a git repository, using the `git` CLI.
+
+use std::path::Path;
+use std::process::Command;
+
+use crate::diff::{MemoChange, diff_memos};
+use crate::schema::Timestamp;
+
+/// A commit touching a memo file
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct CommitInfo {
+    pub hash: String,
+    /// Author time
+    pub time: Timestamp,
+    /// First line of the commit message
+    pub summary: String,
+}
+
+/// Run `git` in `root` and return its stdout. A non-zero exit is an error carrying git's stderr.
+fn git(root: &Path, args: &[&str]) -> std::io::Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(args)
+        .output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(std::io::Error::other(format!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            stderr.trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Whether `root` is inside a git work tree
+pub fn is_repository(root: &Path) -> bool {
+    git(root, &["rev-parse", "--is-inside-work-tree"]).is_ok_and(|out| out.trim() == "true")
+}
+
+fn ensure_repository(root: &Path) -> std::io::Result<()> {
+    if is_repository(root) {
+        Ok(())
+    } else {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Root directory is not a git repository",
+        ))
+    }
+}
+
+/// Git output is read as text, which would garble the ciphertext of an encrypted memo
+fn ensure_plain(relative: &str) -> std::io::Result<()> {
+    if crate::crypt::is_encrypted(Path::new(relative)) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Git history is not available for encrypted memos",
+        ));
+    }
+    Ok(())
+}
+
+/// Commits touching a file (relative to `root`), newest first, following renames
+pub fn file_history(root: &Path, relative: &str) -> std::io::Result<Vec<CommitInfo>> {
+    ensure_plain(relative)?;
+    ensure_repository(root)?;
+    // Fields separated by \x1f, which can't appear in a summary line
+    let log = git(
+        root,
+        &[
+            "log",
+            "--follow",
+            "--format=%H%x1f%aI%x1f%s",
+            "--",
+            relative,
+        ],
+    )?;
+    log.lines()
+        .map(|line| {
+            let mut fields = line.splitn(3, '\x1f');
+            let (Some(hash), Some(time), Some(summary)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(std::io::Error::other(format!(
+                    "Unexpected git log line '{}'",
+                    line
+                )));
+            };
+            let time = chrono::DateTime::parse_from_rfc3339(time)
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            Ok(CommitInfo {
+                hash: hash.to_string(),
+                time,
+                summary: summary.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Full hash of the commit `rev` (a hash, branch, tag, `HEAD~2`, ...) names
+pub fn resolve_revision(root: &Path, rev: &str) -> std::io::Result<String> {
+    ensure_repository(root)?;
+    let commit = format!("{}^{{commit}}", rev);
+    git(
+        root,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            "--end-of-options",
+            &commit,
+        ],
+    )
+    .map(|hash| hash.trim().to_string())
+    .map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Unknown revision '{}'", rev),
+        )
+    })
+}
+
+/// Content of a file (relative to `root`) at a revision; `NotFound` if the
+/// revision is unknown or the file didn't exist in it, `InvalidInput` for an encrypted memo
+pub fn file_at_revision(root: &Path, relative: &str, rev: &str) -> std::io::Result<String> {
+    ensure_plain(relative)?;
+    let hash = resolve_revision(root, rev)?;
+    git(root, &["show", &format!("{}:./{}", hash, relative)]).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("'{}' does not exist at revision '{}'", relative, rev),
+        )
+    })
+}
+
+/// Overwrite a file (relative to `root`) in the working tree with its content at `rev`,
+/// recreating it if it was deleted. Returns the full hash of the revision.
+pub fn restore_file(root: &Path, relative: &str, rev: &str) -> std::io::Result<String> {
+    ensure_plain(relative)?;
+    let hash = resolve_revision(root, rev)?;
+    let content = file_at_revision(root, relative, &hash)?;
+    let path = root.join(relative);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    crate::atomic::write(&path, content.as_bytes())?;
+    Ok(hash)
+}
+
+/// Changes to a file between two revisions
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct FileDiff {
+    /// Full hash of the old revision
+    pub from: String,
+    /// Full hash of the new revision; `None` for the working tree
+    pub to: Option<String>,
+    /// `git diff` output
+    pub unified: String,
+    pub memos: Vec<MemoChange>,
+}
+
+/// Compare a file (relative to `root`) between revision `from` and revision `to`,
+/// or the working tree when `to` is `None`. A file missing on one side counts as empty.
+/// With `publish`, both sides are compared without their private regions.
+pub fn diff_file(
+    root: &Path,
+    relative: &str,
+    from: &str,
+    to: Option<&str>,
+    publish: bool,
+) -> std::io::Result<FileDiff> {
+    fn missing_as_empty(content: std::io::Result<String>) -> std::io::Result<String> {
+        match content {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+            content => content,
+        }
+    }
+
+    ensure_plain(relative)?;
+    let from = resolve_revision(root, from)?;
+    let to = to.map(|rev| resolve_revision(root, rev)).transpose()?;
+    let old = missing_as_empty(file_at_revision(root, relative, &from))?;
+    let new = match &to {
+        Some(to) => missing_as_empty(file_at_revision(root, relative, to))?,
+        None => missing_as_empty(crate::crypt::read_memo(&root.join(relative)))?,
+    };
+    let (old, new) = (
+        crate::publish::visible(publish, old),
+        crate::publish::visible(publish, new),
+    );
+
+    // `git diff` shows private regions, so publishing only gives the memo changes
+    let unified = if publish {
+        String::new()
+    } else {
+        let mut args = vec!["diff", "--no-color", "--no-ext-diff", from.as_str()];
+        args.extend(to.as_deref());
+        args.extend(["--", relative]);
+        git(root, &args)?
+    };
+
+    Ok(FileDiff {
+        memos: diff_memos(&old, &new),
+        from,
+        to,
+        unified,
+    })
+}
+
+/// Commit the current state of one file (relative to `root`), including its deletion.
+/// Only that file is committed, whatever else is staged.
+/// Returns `false` when the file has no changes to commit.
+pub fn commit_file(root: &Path, relative: &str) -> std::io::Result<bool> {
+    let status = git(root, &["status", "--porcelain", "--", relative])?;
+    let Some(code) = status.lines().next().and_then(|line| line.get(..2)) else {
+        return Ok(false);
+    };
+    let action = match code {
+        "??" | "A " | "AM" => "Add",
+        _ if code.contains('D') => "Delete",
+        _ => "Update",
+    };
+    git(root, &["add", "--all", "--", relative])?;
+    let message = format!("fmemo: {} {}", action, relative);
+    git(
+        root,
+        &[
+            "commit",
+            "--quiet",
+            "--message",
+            &message,
+            "--only",
+            "--",
+            relative,
+        ],
+    )?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        commit_file, diff_file, file_at_revision, file_history, git, is_repository, restore_file,
+    };
+    use crate::diff::MemoChangeKind;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn init_repository(root: &std::path::Path) {
+        git(root, &["init", "--quiet"]).unwrap();
+        git(root, &["config", "user.name", "fmemo"]).unwrap();
+        git(root, &["config", "user.email", "fmemo@localhost"]).unwrap();
+        git(root, &["config", "commit.gpgsign", "false"]).unwrap();
+    }
+
+    fn subjects(root: &std::path::Path) -> Vec<String> {
+        git(root, &["log", "--format=%s"])
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_commit_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        assert!(!is_repository(root));
+        init_repository(root);
+        assert!(is_repository(root));
+
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("sub/a.fmemo"), "# A").unwrap();
+        fs::write(root.join("other.fmemo"), "# Other").unwrap();
+        git(root, &["add", "other.fmemo"]).unwrap();
+
+        assert!(commit_file(root, "sub/a.fmemo").unwrap());
+        assert!(!commit_file(root, "sub/a.fmemo").unwrap());
+        fs::write(root.join("sub/a.fmemo"), "# A\nmore").unwrap();
+        assert!(commit_file(root, "sub/a.fmemo").unwrap());
+        fs::remove_file(root.join("sub/a.fmemo")).unwrap();
+        assert!(commit_file(root, "sub/a.fmemo").unwrap());
+
+        assert_eq!(
+            subjects(root),
+            [
+                "fmemo: Delete sub/a.fmemo",
+                "fmemo: Update sub/a.fmemo",
+                "fmemo: Add sub/a.fmemo",
+            ]
+        );
+        // Whatever else was staged stays staged
+        let status = git(root, &["status", "--porcelain"]).unwrap();
+        assert_eq!(status, "A  other.fmemo\n");
+    }
+
+    #[test]
+    fn test_file_history() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        assert_eq!(
+            file_history(root, "a.fmemo").unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+        init_repository(root);
+
+        fs::write(root.join("a.fmemo"), "# V1").unwrap();
+        commit_file(root, "a.fmemo").unwrap();
+        fs::write(root.join("a.fmemo"), "# V2").unwrap();
+        commit_file(root, "a.fmemo").unwrap();
+        fs::write(root.join("b.fmemo"), "# B").unwrap();
+        commit_file(root, "b.fmemo").unwrap();
+
+        let history = file_history(root, "a.fmemo").unwrap();
+        let summaries: Vec<_> = history.iter().map(|c| c.summary.as_str()).collect();
+        assert_eq!(summaries, ["fmemo: Update a.fmemo", "fmemo: Add a.fmemo"]);
+        assert_eq!(history[0].hash.len(), 40);
+
+        assert_eq!(file_at_revision(root, "a.fmemo", "HEAD").unwrap(), "# V2");
+        assert_eq!(file_at_revision(root, "a.fmemo", "HEAD~2").unwrap(), "# V1");
+        assert_eq!(
+            file_at_revision(root, "a.fmemo", &history[1].hash).unwrap(),
+            "# V1"
+        );
+        for rev in ["HEAD~3", "--all", "nope"] {
+            assert_eq!(
+                file_at_revision(root, "a.fmemo", rev).unwrap_err().kind(),
+                std::io::ErrorKind::NotFound
+            );
+        }
+        assert_eq!(
+            file_at_revision(root, "b.fmemo", "HEAD~1")
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_restore_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        init_repository(root);
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("sub/a.fmemo"), "# Keep").unwrap();
+        commit_file(root, "sub/a.fmemo").unwrap();
+        fs::remove_dir_all(root.join("sub")).unwrap();
+        commit_file(root, "sub/a.fmemo").unwrap();
+
+        let hash = restore_file(root, "sub/a.fmemo", "HEAD~1").unwrap();
+        assert_eq!(hash.len(), 40);
+        assert_eq!(
+            fs::read_to_string(root.join("sub/a.fmemo")).unwrap(),
+            "# Keep"
+        );
+        assert_eq!(
+            restore_file(root, "sub/a.fmemo", "HEAD")
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_diff_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        init_repository(root);
+        fs::write(root.join("a.fmemo"), "# A\none\n## B\n").unwrap();
+        commit_file(root, "a.fmemo").unwrap();
+        fs::write(root.join("a.fmemo"), "# A\ntwo\n## B\n").unwrap();
+        commit_file(root, "a.fmemo").unwrap();
+        fs::write(root.join("a.fmemo"), "# A\ntwo\n").unwrap();
+
+        let diff = diff_file(root, "a.fmemo", "HEAD~1", Some("HEAD"), false).unwrap();
+        assert_eq!(diff.from.len(), 40);
+        assert!(diff.unified.contains("-one\n+two\n"));
+        assert_eq!(diff.memos.len(), 1);
+        assert_eq!(diff.memos[0].kind, MemoChangeKind::Changed);
+
+        // Against the working tree
+        let diff = diff_file(root, "a.fmemo", "HEAD", None, false).unwrap();
+        assert_eq!(diff.to, None);
+        assert!(diff.unified.contains("-## B\n"));
+        assert_eq!(diff.memos[0].kind, MemoChangeKind::Removed);
+        assert_eq!(diff.memos[0].titles, ["A", "B"]);
+
+        // Added since the first revision
+        fs::write(root.join("new.fmemo"), "# New").unwrap();
+        let diff = diff_file(root, "new.fmemo", "HEAD~1", None, false).unwrap();
+        assert_eq!(diff.memos[0].kind, MemoChangeKind::Added);
+        assert_eq!(
+            diff_file(root, "a.fmemo", "nope", None, false)
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_encrypted_files_are_refused() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        init_repository(root);
+        fs::write(root.join("s.fmemox"), b"FMEMOX\0\x01\xff\xfe").unwrap();
+        commit_file(root, "s.fmemox").unwrap();
+
+        let invalid = std::io::ErrorKind::InvalidInput;
+        assert_eq!(file_history(root, "s.fmemox").unwrap_err().kind(), invalid);
+        assert_eq!(
+            file_at_revision(root, "s.fmemox", "HEAD")
+                .unwrap_err()
+                .kind(),
+            invalid
+        );
+        assert_eq!(
+            diff_file(root, "s.fmemox", "HEAD", None, false)
+                .unwrap_err()
+                .kind(),
+            invalid
+        );
+        assert_eq!(
+            restore_file(root, "s.fmemox", "HEAD").unwrap_err().kind(),
+            invalid
+        );
+        assert_eq!(
+            fs::read(root.join("s.fmemox")).unwrap(),
+            b"FMEMOX\0\x01\xff\xfe"
+        );
+    }
+}
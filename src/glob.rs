@@ -0,0 +1,228 @@
+//! Glob patterns over paths relative to the root, for `GET /api/files?glob=...`.
+//!
+//! `*` matches within one path segment, `**` across segments (`a/**/b` also matches `a/b`),
+//! `?` one character, `[a-z]` / `[!a-z]` one character of a class and `{x,y}` either
+//! alternative.
+
+use std::io;
+
+/// Most patterns `{...}` groups may expand to
+const MAX_ALTERNATIVES: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Char(char),
+    /// `?`
+    Any,
+    /// `*`
+    Star,
+    /// `**`
+    Globstar,
+    /// `**/`: no directories or any number of them
+    GlobstarSlash,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+/// A compiled glob pattern
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glob {
+    /// One token list per `{...}` alternative
+    alternatives: Vec<Vec<Token>>,
+}
+
+fn invalid(pattern: &str, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Invalid glob '{}': {}", pattern, reason),
+    )
+}
+
+/// Expand the first `{a,b}` group (and the ones after it, recursively)
+fn expand_braces(pattern: &str) -> Result<Vec<String>, &'static str> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let Some(open) = chars.iter().position(|&c| c == '{') else {
+        return Ok(vec![pattern.to_string()]);
+    };
+    let mut depth = 0;
+    let mut parts = Vec::new();
+    let mut start = open + 1;
+    for (i, &c) in chars.iter().enumerate().skip(open) {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    parts.push(chars[start..i].iter().collect::<String>());
+                    let prefix: String = chars[..open].iter().collect();
+                    let suffix: String = chars[i + 1..].iter().collect();
+                    let mut expanded = Vec::new();
+                    for part in parts {
+                        expanded.extend(expand_braces(&format!("{}{}{}", prefix, part, suffix))?);
+                        if expanded.len() > MAX_ALTERNATIVES {
+                            return Err("too many {...} alternatives");
+                        }
+                    }
+                    return Ok(expanded);
+                }
+            }
+            ',' if depth == 1 => {
+                parts.push(chars[start..i].iter().collect::<String>());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    Err("unclosed '{'")
+}
+
+fn compile(pattern: &str) -> Result<Vec<Token>, &'static str> {
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '?' => tokens.push(Token::Any),
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    tokens.push(Token::GlobstarSlash);
+                } else {
+                    tokens.push(Token::Globstar);
+                }
+            }
+            '*' => tokens.push(Token::Star),
+            '[' => {
+                let negated = matches!(chars.peek(), Some('!' | '^'));
+                if negated {
+                    chars.next();
+                }
+                let mut ranges = Vec::new();
+                loop {
+                    match chars.next() {
+                        None => return Err("unclosed '['"),
+                        Some(']') if !ranges.is_empty() => break,
+                        Some(first) => {
+                            let mut lookahead = chars.clone();
+                            if lookahead.next() == Some('-')
+                                && let Some(last) = lookahead.next().filter(|&c| c != ']')
+                            {
+                                chars.next();
+                                chars.next();
+                                ranges.push((first, last));
+                            } else {
+                                ranges.push((first, first));
+                            }
+                        }
+                    }
+                }
+                tokens.push(Token::Class { negated, ranges });
+            }
+            '\\' => tokens.push(Token::Char(chars.next().ok_or("trailing '\\'")?)),
+            c => tokens.push(Token::Char(c)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Whether `tokens[t..]` matches `path[p..]`, remembering results in `seen` so patterns with
+/// many stars stay linear in the number of (t, p) pairs
+fn matches_from(
+    tokens: &[Token],
+    path: &[char],
+    t: usize,
+    p: usize,
+    seen: &mut [Option<bool>],
+) -> bool {
+    let key = t * (path.len() + 1) + p;
+    if let Some(result) = seen[key] {
+        return result;
+    }
+    let result = match tokens.get(t) {
+        None => p == path.len(),
+        Some(Token::Char(c)) => {
+            path.get(p) == Some(c) && matches_from(tokens, path, t + 1, p + 1, seen)
+        }
+        Some(Token::Any) => {
+            path.get(p).is_some_and(|&c| c != '/') && matches_from(tokens, path, t + 1, p + 1, seen)
+        }
+        Some(Token::Class { negated, ranges }) => {
+            path.get(p).is_some_and(|&c| {
+                c != '/' && ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+            }) && matches_from(tokens, path, t + 1, p + 1, seen)
+        }
+        Some(Token::Star) => {
+            let end = path[p..]
+                .iter()
+                .position(|&c| c == '/')
+                .map_or(path.len(), |i| p + i);
+            (p..=end).any(|q| matches_from(tokens, path, t + 1, q, seen))
+        }
+        Some(Token::Globstar) => {
+            (p..=path.len()).any(|q| matches_from(tokens, path, t + 1, q, seen))
+        }
+        Some(Token::GlobstarSlash) => {
+            matches_from(tokens, path, t + 1, p, seen)
+                || (p + 1..=path.len())
+                    .any(|q| path[q - 1] == '/' && matches_from(tokens, path, t + 1, q, seen))
+        }
+    };
+    seen[key] = Some(result);
+    result
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> io::Result<Self> {
+        let pattern = pattern.trim_start_matches('/');
+        if pattern.is_empty() {
+            return Err(invalid(pattern, "empty pattern"));
+        }
+        let alternatives = expand_braces(pattern)
+            .and_then(|patterns| patterns.iter().map(|p| compile(p)).collect())
+            .map_err(|reason| invalid(pattern, reason))?;
+        Ok(Self { alternatives })
+    }
+
+    /// Whether a relative path with `/` separators matches
+    pub fn matches(&self, path: &str) -> bool {
+        let path: Vec<char> = path.chars().collect();
+        self.alternatives.iter().any(|tokens| {
+            let mut seen = vec![None; (tokens.len() + 1) * (path.len() + 1)];
+            matches_from(tokens, &path, 0, 0, &mut seen)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Glob;
+
+    #[test]
+    fn test_glob() {
+        let glob = Glob::new("projects/**/meeting-*.fmemo").unwrap();
+        assert!(glob.matches("projects/meeting-01.fmemo"));
+        assert!(glob.matches("projects/2024/q1/meeting-kickoff.fmemo"));
+        assert!(!glob.matches("projects/meeting-01.md"));
+        assert!(!glob.matches("other/projects/meeting-01.fmemo"));
+
+        let glob = Glob::new("*.{fmemo,md}").unwrap();
+        assert!(glob.matches("a.md"));
+        assert!(glob.matches("a.fmemo"));
+        assert!(!glob.matches("dir/a.md"));
+
+        let glob = Glob::new("notes/day-[0-9]?.md").unwrap();
+        assert!(glob.matches("notes/day-07.md"));
+        assert!(!glob.matches("notes/day-x7.md"));
+        assert!(Glob::new("[!a]*").unwrap().matches("b.md"));
+        assert!(Glob::new("**").unwrap().matches("a/b/c.md"));
+
+        // Would backtrack for ages without remembering partial matches
+        let stars = Glob::new(&"*a".repeat(30)).unwrap();
+        assert!(!stars.matches(&format!("{}b", "a".repeat(200))));
+        assert!(Glob::new("").is_err());
+        assert!(Glob::new("{a,b").is_err());
+        assert!(Glob::new("[ab").is_err());
+    }
+}
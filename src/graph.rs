@@ -0,0 +1,285 @@
+//! Knowledge graph of the memos below a root, for `GET /api/graph`.
+//!
+//! Nodes are files, memos and tags. Edges connect a file to its memos and a memo to its
+//! children (`contains`), a memo to the files it links to with wiki-links or relative
+//! links (`link`), and a memo to the values of its `<tag>`s (`tag`), so memos sharing a
+//! tag meet at the tag's node.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::indexer::Documents;
+use crate::parser::normalize_relative_path;
+use crate::plugin::Plugins;
+use crate::schema::{LinkKind, Memo};
+use crate::server::{list_memo_files, read_fmemo_file_with};
+
+/// Name a memo file is linked by with `[[name]]`: its path without the extension
+pub fn wiki_name(file: &str) -> &str {
+    file.rsplit_once('.').map_or(file, |(name, _)| name)
+}
+
+/// File a wiki-link target points to: a path without extension, or else a bare file
+/// name anywhere below the root (case-insensitive)
+pub fn resolve_wiki_target<'a>(files: &'a [String], target: &str) -> Option<&'a String> {
+    let target = target.trim().to_lowercase();
+    files
+        .iter()
+        .find(|file| wiki_name(file).to_lowercase() == target)
+        .or_else(|| {
+            files.iter().find(|file| {
+                let name = wiki_name(file);
+                name.rsplit('/').next().unwrap_or(name).to_lowercase() == target
+            })
+        })
+}
+
+/// File below the root a link in `file` points to, if it's a memo file
+pub fn resolve_link(files: &[String], file: &str, kind: LinkKind, url: &str) -> Option<String> {
+    let path = url.split_once('#').map_or(url, |(path, _)| path);
+    if path.is_empty() {
+        return None;
+    }
+    match kind {
+        LinkKind::External => None,
+        LinkKind::Wiki => resolve_wiki_target(files, path).cloned(),
+        LinkKind::Internal => {
+            let base_dir = file.rfind('/').map_or("", |idx| &file[..idx]);
+            let target = normalize_relative_path(base_dir, path)?;
+            files.contains(&target).then_some(target)
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    File,
+    Memo,
+    Tag,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct GraphNode {
+    /// `<path>` for files, `<path>:<heading line>` for memos, `tag:<value>` for tags
+    pub id: String,
+    pub kind: NodeKind,
+    pub label: String,
+    /// File the node belongs to; `None` for tags
+    pub file: Option<String>,
+}
+
+#[derive(
+    Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeKind {
+    Contains,
+    Link,
+    Tag,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GraphEdge {
+    pub source: String,
+    pub target: String,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct Graph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+struct Builder<'a> {
+    files: &'a [String],
+    /// Leave out memo nodes; their links and tags start at the file instead
+    files_only: bool,
+    nodes: Vec<GraphNode>,
+    tags: BTreeSet<String>,
+    edges: BTreeSet<GraphEdge>,
+}
+
+impl Builder<'_> {
+    fn edge(&mut self, source: &str, target: String, kind: EdgeKind) {
+        if source != target {
+            self.edges.insert(GraphEdge {
+                source: source.to_string(),
+                target,
+                kind,
+            });
+        }
+    }
+
+    fn add_memos(&mut self, memos: &[Memo], file: &str, parent: &str) {
+        for memo in memos {
+            let id = if self.files_only {
+                file.to_string()
+            } else {
+                let id = format!("{}:{}", file, memo.span().map_or(0, |span| span.start_line));
+                self.nodes.push(GraphNode {
+                    id: id.clone(),
+                    kind: NodeKind::Memo,
+                    label: memo.title().clone(),
+                    file: Some(file.to_string()),
+                });
+                self.edge(parent, id.clone(), EdgeKind::Contains);
+                id
+            };
+
+            for link in memo.links() {
+                if let Some(target) = resolve_link(self.files, file, link.kind, &link.url) {
+                    self.edge(&id, target, EdgeKind::Link);
+                }
+            }
+            for tag in memo.metadata().get("tag").into_iter().flatten() {
+                self.tags.insert(tag.clone());
+                self.edge(&id, format!("tag:{}", tag), EdgeKind::Tag);
+            }
+            self.add_memos(memo.children(), file, &id);
+        }
+    }
+}
+
+/// Build the graph of every memo file below `root`; with `files_only`, memos are folded
+/// into their files. With `publish`, the files are read without their private regions.
+pub fn build_graph(root: &Path, files_only: bool, publish: bool) -> std::io::Result<Graph> {
+    let documents: Documents = list_memo_files(root)?
+        .into_iter()
+        .map(|file| {
+            let memos = read_fmemo_file_with(root.join(&file), &Plugins::default(), publish)
+                .map_or_else(|_| Vec::new(), |c| c.memos);
+            (file, memos)
+        })
+        .collect();
+    Ok(graph_from_documents(&documents, files_only))
+}
+
+/// `build_graph` from already parsed files, e.g. the server's index
+pub fn graph_from_documents(documents: &Documents, files_only: bool) -> Graph {
+    let files: Vec<String> = documents.keys().cloned().collect();
+    let mut builder = Builder {
+        files: &files,
+        files_only,
+        nodes: Vec::new(),
+        tags: BTreeSet::new(),
+        edges: BTreeSet::new(),
+    };
+    for (file, memos) in documents {
+        builder.nodes.push(GraphNode {
+            id: file.clone(),
+            kind: NodeKind::File,
+            label: file.clone(),
+            file: Some(file.clone()),
+        });
+        builder.add_memos(memos, file, file);
+    }
+
+    let mut nodes = builder.nodes;
+    nodes.extend(builder.tags.into_iter().map(|tag| GraphNode {
+        id: format!("tag:{}", tag),
+        kind: NodeKind::Tag,
+        label: tag,
+        file: None,
+    }));
+    Graph {
+        nodes,
+        edges: builder.edges.into_iter().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EdgeKind, GraphEdge, NodeKind, build_graph, resolve_wiki_target};
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_resolve_wiki_target() {
+        let files = vec![
+            "daily/2024-01-01.fmemo".to_string(),
+            "projects/Plan.md".to_string(),
+            "plan.fmemo".to_string(),
+        ];
+        assert_eq!(resolve_wiki_target(&files, "plan").unwrap(), "plan.fmemo");
+        assert_eq!(
+            resolve_wiki_target(&files, "projects/plan").unwrap(),
+            "projects/Plan.md"
+        );
+        assert_eq!(
+            resolve_wiki_target(&files, "2024-01-01").unwrap(),
+            "daily/2024-01-01.fmemo"
+        );
+        assert!(resolve_wiki_target(&files, "missing").is_none());
+    }
+
+    fn edge(source: &str, target: &str, kind: EdgeKind) -> GraphEdge {
+        GraphEdge {
+            source: source.to_string(),
+            target: target.to_string(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_build_graph() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        fs::write(
+            temp_dir.path().join("a.fmemo"),
+            "# A\n<tag>rust</tag>\nsee [[b]] and [web](https://example.com)\n## Child\n[b again](sub/b.md#top) [[missing]]",
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.path().join("sub/b.md"),
+            "# B\n<tag>rust</tag>\n[back](../a.fmemo) [self](#b)",
+        )
+        .unwrap();
+
+        let graph = build_graph(temp_dir.path(), false, false).unwrap();
+        let ids: Vec<(&str, NodeKind)> = graph
+            .nodes
+            .iter()
+            .map(|node| (node.id.as_str(), node.kind))
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                ("a.fmemo", NodeKind::File),
+                ("a.fmemo:1", NodeKind::Memo),
+                ("a.fmemo:4", NodeKind::Memo),
+                ("sub/b.md", NodeKind::File),
+                ("sub/b.md:1", NodeKind::Memo),
+                ("tag:rust", NodeKind::Tag),
+            ]
+        );
+        assert_eq!(
+            graph.edges,
+            [
+                edge("a.fmemo", "a.fmemo:1", EdgeKind::Contains),
+                edge("a.fmemo:1", "a.fmemo:4", EdgeKind::Contains),
+                edge("a.fmemo:1", "sub/b.md", EdgeKind::Link),
+                edge("a.fmemo:1", "tag:rust", EdgeKind::Tag),
+                edge("a.fmemo:4", "sub/b.md", EdgeKind::Link),
+                edge("sub/b.md", "sub/b.md:1", EdgeKind::Contains),
+                edge("sub/b.md:1", "a.fmemo", EdgeKind::Link),
+                edge("sub/b.md:1", "tag:rust", EdgeKind::Tag),
+            ]
+        );
+
+        let graph = build_graph(temp_dir.path(), true, false).unwrap();
+        assert_eq!(graph.nodes.len(), 3);
+        assert_eq!(
+            graph.edges,
+            [
+                edge("a.fmemo", "sub/b.md", EdgeKind::Link),
+                edge("a.fmemo", "tag:rust", EdgeKind::Tag),
+                edge("sub/b.md", "a.fmemo", EdgeKind::Link),
+                edge("sub/b.md", "tag:rust", EdgeKind::Tag),
+            ]
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Server-side syntax highlighting of code blocks with syntect, for clients that don't
+//! ship a highlighter of their own.
+//!
+//! Highlighted code is a run of `<span style="color:...">` elements (no `<pre>`), so
+//! clients keep their own code block layout.
+
+use std::sync::OnceLock;
+
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{IncludeBackground, styled_line_to_highlighted_html};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+use crate::config::HighlightConfig;
+use crate::schema::Memo;
+
+/// Theme used when none is configured
+pub const DEFAULT_THEME: &str = "InspiredGitHub";
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn themes() -> &'static ThemeSet {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    THEMES.get_or_init(ThemeSet::load_defaults)
+}
+
+/// Names of the built-in themes
+pub fn theme_names() -> Vec<&'static str> {
+    themes().themes.keys().map(String::as_str).collect()
+}
+
+fn theme(name: &str) -> std::io::Result<&'static Theme> {
+    themes().themes.get(name).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Unknown highlight theme '{}' (available: {})",
+                name,
+                theme_names().join(", ")
+            ),
+        )
+    })
+}
+
+/// Highlight code of a language given by name or file extension; `None` when the
+/// language isn't known
+fn highlight_with(language: &str, code: &str, theme: &Theme) -> Option<String> {
+    let language = language.trim();
+    if language.is_empty() {
+        return None;
+    }
+    let syntax = syntaxes()
+        .find_syntax_by_token(language)
+        .or_else(|| syntaxes().find_syntax_by_name(language))?;
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut html = String::new();
+    for line in LinesWithEndings::from(code) {
+        let regions = highlighter.highlight_line(line, syntaxes()).ok()?;
+        html.push_str(&styled_line_to_highlighted_html(&regions, IncludeBackground::No).ok()?);
+    }
+    Some(html)
+}
+
+/// Highlight code with a theme; `Ok(None)` when the language isn't known
+pub fn highlight_code(
+    language: &str,
+    code: &str,
+    theme_name: &str,
+) -> std::io::Result<Option<String>> {
+    Ok(highlight_with(language, code, theme(theme_name)?))
+}
+
+/// Theme to highlight a response with, if any: from the `highlight` query parameter
+/// (`true`, `false` or a theme name), else from the config
+pub fn requested_theme(query: Option<&str>, config: &HighlightConfig) -> Option<String> {
+    let configured = || {
+        config
+            .theme
+            .clone()
+            .unwrap_or_else(|| DEFAULT_THEME.to_string())
+    };
+    match query {
+        Some("false" | "0") => None,
+        Some("" | "true" | "1") => Some(configured()),
+        Some(name) => Some(name.to_string()),
+        None => config.enabled.then(configured),
+    }
+}
+
+/// Set `highlighted_html` on the code blocks of the memos and their children
+pub fn highlight_memos(memos: &mut [Memo], theme_name: &str) -> std::io::Result<()> {
+    fn walk(memos: &mut [Memo], theme: &Theme) {
+        for memo in memos {
+            for block in memo.code_blocks_mut() {
+                block.highlighted_html = highlight_with(&block.language, &block.code, theme);
+            }
+            walk(memo.children_mut(), theme);
+        }
+    }
+    walk(memos, theme(theme_name)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DEFAULT_THEME, highlight_code, highlight_memos, requested_theme};
+    use crate::config::HighlightConfig;
+    use crate::parser::{ParseOptions, parse_document};
+
+    #[test]
+    fn test_highlight_code() {
+        let html = highlight_code("rust", "fn main() {}\n", DEFAULT_THEME)
+            .unwrap()
+            .unwrap();
+        assert!(html.starts_with("<span style=\""));
+        assert!(html.contains(">main</span>"));
+        assert!(!html.contains("<pre"));
+        // File extensions work as language names too
+        assert!(
+            highlight_code("py", "x = 1", DEFAULT_THEME)
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            highlight_code("nope", "x", DEFAULT_THEME)
+                .unwrap()
+                .is_none()
+        );
+        assert!(highlight_code("", "x", DEFAULT_THEME).unwrap().is_none());
+        assert!(highlight_code("rust", "x", "no-such-theme").is_err());
+    }
+
+    #[test]
+    fn test_highlight_memos() {
+        let mut document = parse_document(
+            "# A\n```rust\nlet x = 1;\n```\n## B\n```\nplain\n```",
+            &ParseOptions::default(),
+        );
+        highlight_memos(&mut document.memos, "base16-ocean.dark").unwrap();
+        let html = document.memos[0].code_blocks()[0].highlighted_html.as_ref();
+        assert!(html.unwrap().contains("let"));
+        assert!(
+            document.memos[0].children()[0].code_blocks()[0]
+                .highlighted_html
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_requested_theme() {
+        let mut config = HighlightConfig::default();
+        assert_eq!(requested_theme(None, &config), None);
+        assert_eq!(
+            requested_theme(Some("true"), &config).unwrap(),
+            DEFAULT_THEME
+        );
+        assert_eq!(
+            requested_theme(Some("Solarized (dark)"), &config).unwrap(),
+            "Solarized (dark)"
+        );
+
+        config.enabled = true;
+        config.theme = Some("base16-ocean.dark".to_string());
+        assert_eq!(requested_theme(None, &config).unwrap(), "base16-ocean.dark");
+        assert_eq!(requested_theme(Some("false"), &config), None);
+    }
+}
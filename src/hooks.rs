@@ -0,0 +1,267 @@
+//! Shell hooks: commands from the `[hooks]` section of `.fmemo/config.toml` run when
+//! memo files are created, updated or deleted.
+//!
+//! Commands run through `sh -c` (`cmd /C` on Windows) in the root directory with
+//! `FMEMO_EVENT`, `FMEMO_PATH` (relative to the root), `FMEMO_FILE` (absolute) and
+//! `FMEMO_ROOT` set.
+
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{RecvTimeoutError, Sender, channel};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::HooksConfig;
+use crate::server::list_memo_files;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    FileCreated,
+    FileUpdated,
+    FileDeleted,
+}
+
+impl HookEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HookEvent::FileCreated => "file_created",
+            HookEvent::FileUpdated => "file_updated",
+            HookEvent::FileDeleted => "file_deleted",
+        }
+    }
+}
+
+impl HooksConfig {
+    fn commands(&self, event: HookEvent) -> &[String] {
+        match event {
+            HookEvent::FileCreated => &self.file_created,
+            HookEvent::FileUpdated => &self.file_updated,
+            HookEvent::FileDeleted => &self.file_deleted,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.file_created.is_empty() && self.file_updated.is_empty() && self.file_deleted.is_empty()
+    }
+}
+
+/// What happened to a file, from whether it was known before and exists now.
+/// `None` for a file that came and went between two checks.
+fn classify(known: bool, exists: bool) -> Option<HookEvent> {
+    match (known, exists) {
+        (false, true) => Some(HookEvent::FileCreated),
+        (true, true) => Some(HookEvent::FileUpdated),
+        (true, false) => Some(HookEvent::FileDeleted),
+        (false, false) => None,
+    }
+}
+
+/// Counts running commands so no more than the configured number run at once
+struct Slots {
+    running: Mutex<usize>,
+    finished: Condvar,
+    max: usize,
+}
+
+impl Slots {
+    fn acquire(&self) {
+        let mut running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        while *running >= self.max {
+            running = self
+                .finished
+                .wait(running)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        *running += 1;
+    }
+
+    fn release(&self) {
+        *self.running.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+        self.finished.notify_one();
+    }
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    }
+}
+
+/// Run one hook command on its own thread once a slot is free, logging failures
+fn run(slots: &Arc<Slots>, root: &Path, event: HookEvent, path: &str, command: &str) {
+    slots.acquire();
+    let mut shell = shell(command);
+    shell
+        .current_dir(root)
+        .env("FMEMO_EVENT", event.as_str())
+        .env("FMEMO_PATH", path)
+        .env("FMEMO_FILE", root.join(path))
+        .env("FMEMO_ROOT", root);
+    let (slots, command) = (slots.clone(), command.to_string());
+    thread::spawn(move || {
+        match shell.status() {
+            Ok(status) if !status.success() => {
+                eprintln!("Hook '{}' failed with {}", command, status)
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Failed to run hook '{}': {}", command, e),
+        }
+        slots.release();
+    });
+}
+
+/// Runs hook commands from a background thread. Changes are collected until files have
+/// been quiet for the configured debounce, so a burst of writes runs the hooks once.
+#[derive(Debug, Clone)]
+pub struct HookRunner {
+    sender: Sender<String>,
+}
+
+impl HookRunner {
+    /// Start the runner for the memos below `root`; `None` without any commands
+    pub fn start(root: PathBuf, config: HooksConfig) -> Option<Self> {
+        if config.is_empty() {
+            return None;
+        }
+        // Listed up front, so files created right after starting count as new
+        let mut known: HashSet<String> = list_memo_files(&root)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        let (sender, receiver) = channel::<String>();
+        thread::spawn(move || {
+            let slots = Arc::new(Slots {
+                running: Mutex::new(0),
+                finished: Condvar::new(),
+                max: config.max_concurrent.max(1),
+            });
+            let debounce = Duration::from_millis(config.debounce_ms);
+            let mut pending = BTreeSet::new();
+
+            loop {
+                // Only wait out the debounce while there are changes to run hooks for
+                let change = if pending.is_empty() {
+                    receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+                } else {
+                    receiver.recv_timeout(debounce)
+                };
+                match change {
+                    Ok(path) => {
+                        pending.insert(path);
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                for path in std::mem::take(&mut pending) {
+                    let exists = root.join(&path).is_file();
+                    let known_before = if exists {
+                        !known.insert(path.clone())
+                    } else {
+                        known.remove(&path)
+                    };
+                    let Some(event) = classify(known_before, exists) else {
+                        continue;
+                    };
+                    for command in config.commands(event) {
+                        run(&slots, &root, event, &path, command);
+                    }
+                }
+            }
+        });
+        Some(Self { sender })
+    }
+
+    /// A memo file (relative to the root) was written, created or deleted
+    pub fn file_changed(&self, path: &str) {
+        // Only fails once the runner thread is gone, and then there's nothing to run
+        let _ = self.sender.send(path.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HookEvent, HookRunner, classify};
+    use crate::config::HooksConfig;
+    use std::fs;
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(false, true), Some(HookEvent::FileCreated));
+        assert_eq!(classify(true, true), Some(HookEvent::FileUpdated));
+        assert_eq!(classify(true, false), Some(HookEvent::FileDeleted));
+        assert_eq!(classify(false, false), None);
+    }
+
+    /// Wait until `path` has `lines` lines and return them
+    fn wait_for_lines(path: &std::path::Path, lines: usize) -> Vec<String> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let content = fs::read_to_string(path).unwrap_or_default();
+            let found: Vec<String> = content.lines().map(str::to_string).collect();
+            if found.len() >= lines || Instant::now() > deadline {
+                return found;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hooks_run_commands_with_file_env() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("old.fmemo"), "# Old").unwrap();
+        let log = temp_dir.path().join("hooks.log");
+        let command = format!(
+            "echo \"$FMEMO_EVENT $FMEMO_PATH $(basename \"$FMEMO_FILE\")\" >> {}",
+            log.display()
+        );
+        let runner = HookRunner::start(
+            temp_dir.path().to_path_buf(),
+            HooksConfig {
+                file_created: vec![command.clone()],
+                file_updated: vec![command.clone()],
+                file_deleted: vec![command],
+                debounce_ms: 50,
+                max_concurrent: 1,
+            },
+        )
+        .unwrap();
+        assert!(HookRunner::start(temp_dir.path().to_path_buf(), HooksConfig::default()).is_none());
+
+        // A burst of writes to a new file is one creation
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        for content in ["# A", "# A\n## B"] {
+            fs::write(temp_dir.path().join("sub/new.fmemo"), content).unwrap();
+            runner.file_changed("sub/new.fmemo");
+        }
+        assert_eq!(
+            wait_for_lines(&log, 1),
+            ["file_created sub/new.fmemo new.fmemo"]
+        );
+
+        fs::write(temp_dir.path().join("old.fmemo"), "# Older").unwrap();
+        runner.file_changed("old.fmemo");
+        assert_eq!(
+            wait_for_lines(&log, 2)[1],
+            "file_updated old.fmemo old.fmemo"
+        );
+
+        fs::remove_file(temp_dir.path().join("old.fmemo")).unwrap();
+        runner.file_changed("old.fmemo");
+        assert_eq!(
+            wait_for_lines(&log, 3)[2],
+            "file_deleted old.fmemo old.fmemo"
+        );
+    }
+}
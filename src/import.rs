@@ -0,0 +1,255 @@
+//! Importing notes from a zip archive, e.g. an export from another notes app.
+//!
+//! Every file in the archive is extracted below the root (or a directory inside it),
+//! keeping the archive's layout. Archives with paths that would land outside that
+//! directory are refused as a whole; hidden entries (`.DS_Store`, `__MACOSX/...`) and
+//! symlinks are left out. Nothing is written before the whole archive has been read, so
+//! an archive that is too large or corrupt leaves the root untouched.
+
+use std::io::Read;
+use std::path::{Component, Path};
+
+use crate::config::ConflictPolicy;
+
+/// Limits and behavior of one import
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// Directory below the root to extract into (`/` separated, empty for the root)
+    pub directory: String,
+    /// Largest total size of the extracted files, in bytes
+    pub max_size: u64,
+    pub on_conflict: ConflictPolicy,
+}
+
+/// A file written by an import
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct ImportedFile {
+    /// Path inside the archive
+    pub from: String,
+    /// Path relative to the root
+    pub path: String,
+}
+
+/// Outcome of an import
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct ImportReport {
+    pub imported: Vec<ImportedFile>,
+    /// Archive paths not written because the file exists (`ConflictPolicy::Skip`)
+    pub skipped: Vec<String>,
+    /// Hidden entries and symlinks that were left out
+    pub ignored: Vec<String>,
+}
+
+fn invalid(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message.into())
+}
+
+/// `/` separated path of plain segments, or `None` for anything that could escape
+fn relative_path(path: &Path) -> Option<String> {
+    let segments: Option<Vec<&str>> = path
+        .components()
+        .map(|component| match component {
+            Component::Normal(segment) => segment.to_str(),
+            _ => None,
+        })
+        .collect();
+    segments
+        .filter(|segments| !segments.is_empty())
+        .map(|segments| segments.join("/"))
+}
+
+fn is_hidden(path: &str) -> bool {
+    path.split('/')
+        .any(|segment| segment.starts_with('.') || segment == "__MACOSX")
+}
+
+/// Target directory of an import: plain, non-hidden segments only
+fn target_directory(directory: &str) -> std::io::Result<String> {
+    let directory = directory.trim_matches('/');
+    if directory.is_empty() {
+        return Ok(String::new());
+    }
+    relative_path(Path::new(directory))
+        .filter(|path| !is_hidden(path) && !path.contains('\\'))
+        .ok_or_else(|| invalid("Import directory must be a plain path inside the root"))
+}
+
+/// `a/b.md`, `a/b-1.md`, `a/b-2.md`, ... until one doesn't exist
+fn free_path(root: &Path, path: &str) -> String {
+    let (stem, extension) = match path.rfind('.') {
+        Some(idx) if idx > path.rfind('/').map_or(0, |slash| slash + 1) => path.split_at(idx),
+        _ => (path, ""),
+    };
+    (1..)
+        .map(|n| format!("{}-{}{}", stem, n, extension))
+        .find(|candidate| !root.join(candidate).exists())
+        .unwrap_or_default()
+}
+
+/// Extract a zip archive below `root`
+pub fn import_zip(
+    root: &Path,
+    archive: &[u8],
+    options: &ImportOptions,
+) -> std::io::Result<ImportReport> {
+    let directory = target_directory(&options.directory)?;
+    let mut zip = zip::ZipArchive::new(std::io::Cursor::new(archive))
+        .map_err(|e| invalid(format!("Not a valid zip archive: {}", e)))?;
+
+    let mut report = ImportReport::default();
+    let mut files = Vec::new();
+    let mut remaining = options.max_size;
+    for index in 0..zip.len() {
+        let mut entry = zip
+            .by_index(index)
+            .map_err(|e| invalid(format!("Not a valid zip archive: {}", e)))?;
+        let name = entry.name().to_string();
+        let from = entry
+            .enclosed_name()
+            .as_deref()
+            .and_then(relative_path)
+            .filter(|path| !path.contains('\\'))
+            .ok_or_else(|| {
+                invalid(format!(
+                    "Archive entry '{}' points outside the import directory",
+                    name
+                ))
+            })?;
+        if entry.is_dir() {
+            continue;
+        }
+        if is_hidden(&from) || entry.is_symlink() {
+            report.ignored.push(from);
+            continue;
+        }
+        // The sizes in the archive can't be trusted; count what is actually extracted
+        let mut content = Vec::new();
+        (&mut entry)
+            .take(remaining + 1)
+            .read_to_end(&mut content)
+            .map_err(|e| invalid(format!("Failed to extract '{}': {}", from, e)))?;
+        remaining = remaining.checked_sub(content.len() as u64).ok_or_else(|| {
+            invalid(format!(
+                "Archive is larger than {} bytes when extracted",
+                options.max_size
+            ))
+        })?;
+        files.push((from, content));
+    }
+
+    for (from, content) in files {
+        let mut path = if directory.is_empty() {
+            from.clone()
+        } else {
+            format!("{}/{}", directory, from)
+        };
+        if root.join(&path).exists() {
+            match options.on_conflict {
+                ConflictPolicy::Skip => {
+                    report.skipped.push(from);
+                    continue;
+                }
+                ConflictPolicy::Overwrite => {}
+                ConflictPolicy::Rename => path = free_path(root, &path),
+            }
+        }
+        let target = root.join(&path);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&target, content)?;
+        report.imported.push(ImportedFile { from, path });
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImportOptions, ImportedFile, import_zip};
+    use crate::config::ConflictPolicy;
+    use std::fs;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    /// A zip archive holding the given files
+    fn zip_archive(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, content) in files {
+            writer
+                .start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn options(on_conflict: ConflictPolicy) -> ImportOptions {
+        ImportOptions {
+            directory: "imported".to_string(),
+            max_size: 1024,
+            on_conflict,
+        }
+    }
+
+    #[test]
+    fn test_import_zip() {
+        let temp_dir = TempDir::new().unwrap();
+        let archive = zip_archive(&[
+            ("notes/a.md", "# A"),
+            ("notes/img/p.png", "png"),
+            ("__MACOSX/notes/._a.md", "junk"),
+            ("notes/.DS_Store", "junk"),
+        ]);
+        let report =
+            import_zip(temp_dir.path(), &archive, &options(ConflictPolicy::Rename)).unwrap();
+        assert_eq!(report.imported.len(), 2);
+        assert_eq!(report.ignored.len(), 2);
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("imported/notes/a.md")).unwrap(),
+            "# A"
+        );
+
+        // Importing again renames, skips or overwrites
+        let archive = zip_archive(&[("notes/a.md", "# B")]);
+        let report =
+            import_zip(temp_dir.path(), &archive, &options(ConflictPolicy::Rename)).unwrap();
+        assert_eq!(
+            report.imported,
+            vec![ImportedFile {
+                from: "notes/a.md".to_string(),
+                path: "imported/notes/a-1.md".to_string(),
+            }]
+        );
+        let report = import_zip(temp_dir.path(), &archive, &options(ConflictPolicy::Skip)).unwrap();
+        assert_eq!(report.skipped, vec!["notes/a.md"]);
+        import_zip(
+            temp_dir.path(),
+            &archive,
+            &options(ConflictPolicy::Overwrite),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("imported/notes/a.md")).unwrap(),
+            "# B"
+        );
+    }
+
+    #[test]
+    fn test_import_zip_rejects_unsafe_archives() {
+        let temp_dir = TempDir::new().unwrap();
+        let import =
+            |archive: &[u8]| import_zip(temp_dir.path(), archive, &options(ConflictPolicy::Rename));
+
+        let escaping = zip_archive(&[("a.md", "# A"), ("../evil.md", "# Evil")]);
+        assert!(import(&escaping).is_err());
+        let too_large = zip_archive(&[("a.md", &"x".repeat(2048))]);
+        assert!(import(&too_large).is_err());
+        assert!(import(b"not a zip").is_err());
+        // Nothing was written
+        assert!(!temp_dir.path().join("imported").exists());
+
+        let mut bad_directory = options(ConflictPolicy::Rename);
+        bad_directory.directory = "../outside".to_string();
+        assert!(import_zip(temp_dir.path(), &zip_archive(&[]), &bad_directory).is_err());
+    }
+}
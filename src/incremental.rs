@@ -0,0 +1,265 @@
+//! Incremental re-parsing for files that are saved over and over while being edited.
+//!
+//! The parser keeps the flat list of memos from the previous parse together with their
+//! source spans. On the next parse only the sections touched by the changed lines are
+//! parsed again; sections before the change are reused and sections after it are reused
+//! with their line numbers shifted.
+
+use crate::parser::{ParseOptions, ParsedDocument, build_hierarchy, normalize_source, parse_flat};
+use crate::schema::{Memo, ParseWarning};
+
+/// Parser that remembers the previous version of one file
+#[derive(Debug, Clone, Default)]
+pub struct IncrementalParser {
+    options: ParseOptions,
+    lines: Vec<String>,
+    flat_memos: Vec<Memo>,
+    warnings: Vec<ParseWarning>,
+    parsed: bool,
+    last_reparsed_lines: usize,
+}
+
+impl IncrementalParser {
+    pub fn new(options: ParseOptions) -> Self {
+        Self {
+            options,
+            ..Self::default()
+        }
+    }
+
+    /// Number of lines that were actually parsed by the last call to `parse`
+    pub fn last_reparsed_lines(&self) -> usize {
+        self.last_reparsed_lines
+    }
+
+    /// Parse `content`, reusing everything unaffected since the previous call.
+    /// The result is always the same as `parse_document(content, options)`.
+    pub fn parse(&mut self, content: &str) -> ParsedDocument {
+        let content = normalize_source(content);
+        let lines: Vec<String> = content.lines().map(str::to_string).collect();
+
+        // Any later `---` line can close front matter opened on the first line, so such files
+        // are always parsed in full
+        let front_matter = lines.first().is_some_and(|line| line == "---");
+        if !self.parsed || front_matter || !self.reparse_changed(&lines) {
+            self.full_parse(&content);
+        }
+        self.lines = lines;
+        self.parsed = true;
+
+        let (memos, level_warnings) = build_hierarchy(self.flat_memos.iter().cloned());
+        let mut warnings = self.warnings.clone();
+        warnings.extend(level_warnings);
+        warnings.sort_by_key(|w| w.line);
+        ParsedDocument { memos, warnings }
+    }
+
+    fn full_parse(&mut self, content: &str) {
+        let mut warnings = Vec::new();
+        let (flat_memos, _) = parse_flat(content, &self.options, 0, &mut warnings);
+        self.flat_memos = flat_memos;
+        self.warnings = warnings;
+        self.last_reparsed_lines = content.lines().count();
+    }
+
+    /// Re-parse only the sections around the changed lines. Returns false when the
+    /// change can't be contained (e.g. it opens a code fence that runs to the end of file).
+    fn reparse_changed(&mut self, new_lines: &[String]) -> bool {
+        let old_lines = &self.lines;
+        let prefix = old_lines
+            .iter()
+            .zip(new_lines)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let max_suffix = old_lines.len().min(new_lines.len()) - prefix;
+        let suffix = old_lines
+            .iter()
+            .rev()
+            .zip(new_lines.iter().rev())
+            .take(max_suffix)
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        if prefix == old_lines.len() && prefix == new_lines.len() {
+            self.last_reparsed_lines = 0;
+            return true;
+        }
+
+        // 1-based line numbers. Start from the section holding the line before the change,
+        // since an edited heading line can merge into the section above it.
+        let last_changed_old = old_lines.len() - suffix;
+        let delta = new_lines.len() as isize - old_lines.len() as isize;
+        let starts: Vec<usize> = self
+            .flat_memos
+            .iter()
+            .filter_map(|memo| memo.span().map(|s| s.start_line))
+            .collect();
+        // Front matter timestamps apply to every memo, so edits up to the first heading
+        // are parsed in full
+        if starts.first().is_none_or(|&first| prefix < first) {
+            return false;
+        }
+        let region_start = starts
+            .iter()
+            .copied()
+            .filter(|&start| start <= prefix.max(1))
+            .max()
+            .unwrap_or(1);
+        let old_region_end = starts
+            .iter()
+            .copied()
+            .find(|&start| start > last_changed_old && start > prefix)
+            .unwrap_or(old_lines.len() + 1);
+        let new_region_end = old_region_end.saturating_add_signed(delta);
+
+        let slice = new_lines[region_start - 1..new_region_end - 1].join("\n");
+        let mut slice_warnings = Vec::new();
+        let (mut slice_memos, ends_open) =
+            parse_flat(&slice, &self.options, region_start - 1, &mut slice_warnings);
+        if ends_open && new_region_end <= new_lines.len() {
+            return false;
+        }
+        // Joining drops a trailing blank line, which still belongs to the last section
+        if let Some(span) = slice_memos
+            .last_mut()
+            .and_then(|memo| memo.span_mut().as_mut())
+        {
+            span.end_line = new_region_end - 1;
+        }
+
+        let shift = |mut memo: Memo| {
+            if let Some(span) = memo.span_mut() {
+                *span = span.shifted(delta);
+            }
+            memo
+        };
+        let mut flat_memos = Vec::with_capacity(self.flat_memos.len());
+        let mut later = Vec::new();
+        for memo in self.flat_memos.drain(..) {
+            match memo.span().map(|s| s.start_line) {
+                Some(start) if start < region_start => flat_memos.push(memo),
+                Some(start) if start >= old_region_end => later.push(shift(memo)),
+                _ => {}
+            }
+        }
+        flat_memos.extend(slice_memos);
+        flat_memos.extend(later);
+
+        let mut warnings: Vec<ParseWarning> = self
+            .warnings
+            .drain(..)
+            .filter_map(|mut warning| {
+                if warning.line < region_start {
+                    Some(warning)
+                } else if warning.line >= old_region_end {
+                    warning.line = warning.line.saturating_add_signed(delta);
+                    Some(warning)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        warnings.extend(slice_warnings);
+
+        self.flat_memos = flat_memos;
+        self.warnings = warnings;
+        self.last_reparsed_lines = new_region_end - region_start;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IncrementalParser;
+    use crate::parser::{ParseOptions, parse_document};
+
+    const BASE: &str = "# Intro\n<desc>overview</desc>\ntext\n\n## Setup\n```rust\nfn main() {}\n```\n\n## Usage\nrun it\n\n# Reference\n$$\nx^2\n$$\n\n### Deep\nnote\n";
+
+    fn assert_matches_full_parse(parser: &mut IncrementalParser, content: &str) {
+        let incremental = parser.parse(content);
+        let full = parse_document(content, &ParseOptions::default());
+        assert_eq!(incremental, full, "content:\n{}", content);
+        let spans = |memos: &[crate::schema::Memo]| {
+            let mut out = Vec::new();
+            let mut stack: Vec<&crate::schema::Memo> = memos.iter().collect();
+            while let Some(memo) = stack.pop() {
+                out.push(memo.span());
+                stack.extend(memo.children());
+            }
+            out
+        };
+        assert_eq!(spans(&incremental.memos), spans(&full.memos));
+    }
+
+    #[test]
+    fn test_edits_match_full_parse() {
+        let edits = [
+            BASE.replace("run it", "run it twice\nand again"),
+            BASE.replace("## Usage\n", ""),
+            BASE.replace("# Reference", "Reference"),
+            BASE.replace("note\n", "note\n# Appendix\nmore\n"),
+            BASE.replace("text\n", "```\ntext\n"),
+            BASE.replace("run it", "<desc>unfinished"),
+            format!("preamble\n{}", BASE),
+            format!("---\nupdated: 2024-01-01\n---\n{}", BASE),
+            String::new(),
+            BASE.to_string(),
+        ];
+        let mut parser = IncrementalParser::new(ParseOptions::default());
+        assert_matches_full_parse(&mut parser, BASE);
+        for edited in &edits {
+            assert_matches_full_parse(&mut parser, edited);
+            assert_matches_full_parse(&mut parser, BASE);
+        }
+    }
+
+    #[test]
+    fn test_single_line_edits_everywhere_match_full_parse() {
+        let lines: Vec<&str> = BASE.lines().collect();
+        let mut parser = IncrementalParser::new(ParseOptions::default());
+        for index in 0..=lines.len() {
+            for inserted in ["# New", "```", "$$", "<!--", "plain"] {
+                let mut edited = lines.clone();
+                edited.insert(index, inserted);
+                assert_matches_full_parse(&mut parser, &edited.join("\n"));
+                if index < lines.len() {
+                    let mut removed = lines.clone();
+                    removed.remove(index);
+                    assert_matches_full_parse(&mut parser, &removed.join("\n"));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_only_changed_section_is_reparsed() {
+        let mut parser = IncrementalParser::new(ParseOptions::default());
+        parser.parse(BASE);
+        assert_eq!(parser.last_reparsed_lines(), BASE.lines().count());
+
+        parser.parse(&BASE.replace("run it", "run it now"));
+        // only the "## Usage" section
+        assert_eq!(parser.last_reparsed_lines(), 3);
+
+        parser.parse(&BASE.replace("run it", "run it now"));
+        assert_eq!(parser.last_reparsed_lines(), 0);
+    }
+
+    #[test]
+    fn test_unclosed_fence_falls_back_to_full_parse() {
+        let mut parser = IncrementalParser::new(ParseOptions::default());
+        parser.parse(BASE);
+        let edited = BASE.replace("run it", "```\nrun it");
+        assert_matches_full_parse(&mut parser, &edited);
+        assert_eq!(parser.last_reparsed_lines(), edited.lines().count());
+    }
+
+    #[test]
+    fn test_front_matter_closed_after_heading_matches_full_parse() {
+        let mut parser = IncrementalParser::new(ParseOptions::default());
+        let base = "---\nupdated: 2024-01-01\n# Intro\ntext\n\n## Setup\nsteps\n";
+        assert_matches_full_parse(&mut parser, base);
+        assert_matches_full_parse(&mut parser, &base.replace("text\n", "text\n---\n"));
+        assert_matches_full_parse(&mut parser, base);
+    }
+}
@@ -0,0 +1,277 @@
+//! Parsed memos of every file below a root, shared by search and the graph so they
+//! don't each walk and parse the root per request.
+//!
+//! The server indexes the root when it starts and again on `POST /api/reindex`. A pass
+//! builds a new index next to the current one and swaps it in when done, so readers always
+//! see a complete index. WebSocket clients follow a pass through `index_started`,
+//! `index_progress` and `index_finished` messages. The directory watcher keeps the index
+//! current between passes. With an embedding index, every new state of the index is also
+//! handed to it to embed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::embeddings::EmbeddingIndex;
+use crate::plugin::Plugins;
+use crate::schema::{Memo, WsServerMessage};
+use crate::server::{
+    WebSocketClients, broadcast_to_clients, list_memo_files, read_fmemo_file_with,
+};
+
+/// Parsed memos per file path (relative to the root)
+pub type Documents = BTreeMap<String, Vec<Memo>>;
+
+/// Files parsed between two `index_progress` messages
+const PROGRESS_INTERVAL: usize = 50;
+
+/// Where the index stands
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct IndexStatus {
+    /// A pass is running
+    pub indexing: bool,
+    /// Files parsed so far by the running (or last) pass
+    pub indexed: usize,
+    /// Files the running (or last) pass covers
+    pub total: usize,
+    /// Files in the index
+    pub files: usize,
+    /// RFC 3339 end of the last finished pass
+    pub indexed_at: Option<String>,
+}
+
+/// Handle to the index of a root; clones share the index
+#[derive(Debug, Clone)]
+pub struct Indexer {
+    root: PathBuf,
+    documents: Arc<RwLock<Arc<Documents>>>,
+    status: Arc<Mutex<IndexStatus>>,
+    running: Arc<AtomicBool>,
+    /// Files changed while a pass runs, parsed again once its index is swapped in
+    changed: Arc<Mutex<BTreeSet<String>>>,
+    /// Told about the progress of each pass
+    clients: Option<WebSocketClients>,
+    /// Vectors for semantic search, kept in step with the documents
+    embeddings: Option<EmbeddingIndex>,
+    /// Files are indexed without their `<private>` regions
+    publish: bool,
+}
+
+impl Indexer {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: root.into(),
+            documents: Arc::new(RwLock::new(Arc::new(Documents::new()))),
+            status: Arc::new(Mutex::new(IndexStatus::default())),
+            running: Arc::new(AtomicBool::new(false)),
+            changed: Arc::new(Mutex::new(BTreeSet::new())),
+            clients: None,
+            embeddings: None,
+            publish: false,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Send progress messages to these WebSocket clients
+    pub fn clients(mut self, clients: WebSocketClients) -> Self {
+        self.clients = Some(clients);
+        self
+    }
+
+    /// Keep this embedding index current
+    pub fn embeddings(mut self, embeddings: EmbeddingIndex) -> Self {
+        self.embeddings = Some(embeddings);
+        self
+    }
+
+    /// Index the files as a server in publish mode shows them
+    pub fn publish(mut self, publish: bool) -> Self {
+        self.publish = publish;
+        self
+    }
+
+    /// Parsed memos of a file (relative to the root)
+    fn read(&self, file: &str) -> std::io::Result<Vec<Memo>> {
+        read_fmemo_file_with(self.root.join(file), &Plugins::default(), self.publish)
+            .map(|content| content.memos)
+    }
+
+    pub fn embedding_index(&self) -> Option<&EmbeddingIndex> {
+        self.embeddings.as_ref()
+    }
+
+    fn embed_documents(&self) {
+        if let Some(embeddings) = &self.embeddings {
+            embeddings.update(self.documents());
+        }
+    }
+
+    fn emit(&self, message: WsServerMessage) {
+        if let Some(clients) = &self.clients {
+            broadcast_to_clients(clients, message);
+        }
+    }
+
+    fn update_status(&self, f: impl FnOnce(&mut IndexStatus)) {
+        f(&mut self.status.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+
+    pub fn status(&self) -> IndexStatus {
+        self.status
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// The current index; later changes don't affect the returned snapshot
+    pub fn documents(&self) -> Arc<Documents> {
+        self.documents
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Walk and parse the whole root, then replace the index. Fails with `AlreadyExists`
+    /// while another pass is running.
+    pub fn reindex(&self) -> std::io::Result<IndexStatus> {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "Indexing is already running",
+            ));
+        }
+        let result = self.index_all();
+        self.running.store(false, Ordering::SeqCst);
+        if result.is_err() {
+            self.update_status(|status| status.indexing = false);
+        }
+        result
+    }
+
+    fn index_all(&self) -> std::io::Result<IndexStatus> {
+        let started = std::time::Instant::now();
+        let files = list_memo_files(&self.root)?;
+        let total = files.len();
+        self.update_status(|status| {
+            status.indexing = true;
+            status.indexed = 0;
+            status.total = total;
+        });
+        self.emit(WsServerMessage::IndexStarted { total });
+
+        let mut documents = Documents::new();
+        for (index, file) in files.into_iter().enumerate() {
+            if let Ok(memos) = self.read(&file) {
+                documents.insert(file, memos);
+            }
+            let indexed = index + 1;
+            self.update_status(|status| status.indexed = indexed);
+            if indexed % PROGRESS_INTERVAL == 0 && indexed < total {
+                self.emit(WsServerMessage::IndexProgress { indexed, total });
+            }
+        }
+
+        *self.documents.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(documents);
+        let changed = std::mem::take(&mut *self.changed.lock().unwrap_or_else(|e| e.into_inner()));
+        for file in changed {
+            self.update_file(&file);
+        }
+        self.embed_documents();
+        let count = self.documents().len();
+        self.update_status(|status| {
+            status.indexing = false;
+            status.files = count;
+            status.indexed_at = Some(chrono::Utc::now().to_rfc3339());
+        });
+        self.emit(WsServerMessage::IndexFinished {
+            files: count,
+            duration_ms: started.elapsed().as_millis() as u64,
+        });
+        Ok(self.status())
+    }
+
+    /// Run `reindex` on a thread of its own; `false` when a pass is already running
+    pub fn spawn_reindex(&self) -> bool {
+        if self.running.load(Ordering::SeqCst) {
+            return false;
+        }
+        let indexer = self.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = indexer.reindex()
+                && e.kind() != std::io::ErrorKind::AlreadyExists
+            {
+                eprintln!("Failed to index {}: {}", indexer.root.display(), e);
+            }
+        });
+        true
+    }
+
+    /// Parse a file (relative to the root) again after it changed, or drop it when it's gone
+    pub fn file_changed(&self, file: &str) {
+        if self.running.load(Ordering::SeqCst) {
+            self.changed
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(file.to_string());
+        }
+        let count = self.update_file(file);
+        self.update_status(|status| status.files = count);
+        self.embed_documents();
+    }
+
+    /// Returns the number of files in the index
+    fn update_file(&self, file: &str) -> usize {
+        let memos = self.read(file).ok();
+        let mut documents = self.documents.write().unwrap_or_else(|e| e.into_inner());
+        let documents = Arc::make_mut(&mut documents);
+        match memos {
+            Some(memos) => {
+                documents.insert(file.to_string(), memos);
+            }
+            None => {
+                documents.remove(file);
+            }
+        }
+        documents.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Indexer;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_indexer() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        fs::write(temp_dir.path().join("a.fmemo"), "# A").unwrap();
+        fs::write(temp_dir.path().join("sub/b.md"), "# B").unwrap();
+
+        let indexer = Indexer::new(temp_dir.path()).clients(Arc::new(Mutex::new(Vec::new())));
+        assert!(indexer.documents().is_empty());
+        let status = indexer.reindex().unwrap();
+        assert!(!status.indexing);
+        assert_eq!((status.indexed, status.total, status.files), (2, 2, 2));
+        assert!(status.indexed_at.is_some());
+        let snapshot = indexer.documents();
+        assert_eq!(snapshot["sub/b.md"][0].title(), "B");
+
+        fs::write(temp_dir.path().join("a.fmemo"), "# Changed").unwrap();
+        indexer.file_changed("a.fmemo");
+        fs::remove_file(temp_dir.path().join("sub/b.md")).unwrap();
+        indexer.file_changed("sub/b.md");
+        let documents = indexer.documents();
+        assert_eq!(documents.keys().collect::<Vec<_>>(), vec!["a.fmemo"]);
+        assert_eq!(documents["a.fmemo"][0].title(), "Changed");
+        assert_eq!(indexer.status().files, 1);
+        // Earlier snapshots stay as they were
+        assert_eq!(snapshot.len(), 2);
+    }
+}
@@ -0,0 +1,642 @@
+use crate::schema::{ContentBlock, Span};
+
+/// Split memo content into paragraph, list item and quote blocks with parsed inline spans
+pub fn parse_blocks(content: &str) -> Vec<ContentBlock> {
+    let mut blocks = Vec::new();
+    let mut pending: Option<PendingBlock> = None;
+
+    for line in content.lines() {
+        if line.trim().is_empty() {
+            if let Some(block) = pending.take() {
+                blocks.push(block.finish());
+            }
+            continue;
+        }
+
+        if let Some((depth, ordered, text)) = parse_list_marker(line) {
+            if let Some(block) = pending.take() {
+                blocks.push(block.finish());
+            }
+            pending = Some(PendingBlock::ListItem {
+                depth,
+                ordered,
+                text: text.to_string(),
+            });
+        } else if let Some(text) = parse_quote_marker(line) {
+            match pending {
+                Some(PendingBlock::Quote(ref mut buf)) => {
+                    buf.push('\n');
+                    buf.push_str(text);
+                }
+                _ => {
+                    if let Some(block) = pending.take() {
+                        blocks.push(block.finish());
+                    }
+                    pending = Some(PendingBlock::Quote(text.to_string()));
+                }
+            }
+        } else {
+            match pending {
+                Some(PendingBlock::Paragraph(ref mut buf))
+                | Some(PendingBlock::Quote(ref mut buf))
+                | Some(PendingBlock::ListItem {
+                    text: ref mut buf, ..
+                }) => {
+                    // Lazy continuation line
+                    buf.push('\n');
+                    buf.push_str(line.trim());
+                }
+                None => pending = Some(PendingBlock::Paragraph(line.trim().to_string())),
+            }
+        }
+    }
+
+    if let Some(block) = pending {
+        blocks.push(block.finish());
+    }
+    blocks
+}
+
+enum PendingBlock {
+    Paragraph(String),
+    Quote(String),
+    ListItem {
+        depth: usize,
+        ordered: bool,
+        text: String,
+    },
+}
+
+impl PendingBlock {
+    fn finish(self) -> ContentBlock {
+        match self {
+            PendingBlock::Paragraph(text) => ContentBlock::Paragraph {
+                spans: parse_inline(&text),
+            },
+            PendingBlock::Quote(text) => ContentBlock::Quote {
+                spans: parse_inline(&text),
+            },
+            PendingBlock::ListItem {
+                depth,
+                ordered,
+                text,
+            } => ContentBlock::ListItem {
+                depth,
+                ordered,
+                spans: parse_inline(&text),
+            },
+        }
+    }
+}
+
+/// `- item`, `* item`, `+ item`, `1. item`, `1) item` with two spaces of indentation per depth
+fn parse_list_marker(line: &str) -> Option<(usize, bool, &str)> {
+    let trimmed = line.trim_start();
+    let indent = line.len() - trimmed.len();
+    let depth = indent / 2;
+
+    for bullet in ["- ", "* ", "+ "] {
+        if let Some(text) = trimmed.strip_prefix(bullet) {
+            return Some((depth, false, text.trim()));
+        }
+    }
+
+    let digits = trimmed.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 && digits <= 9 {
+        let rest = &trimmed[digits..];
+        if let Some(text) = rest.strip_prefix(". ").or_else(|| rest.strip_prefix(") ")) {
+            return Some((depth, true, text.trim()));
+        }
+    }
+    None
+}
+
+fn parse_quote_marker(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    let text = trimmed.strip_prefix('>')?;
+    Some(text.strip_prefix(' ').unwrap_or(text).trim_end())
+}
+
+/// Parse inline Markdown (code, bold, italic, links) into spans
+pub fn parse_inline(text: &str) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut buf = String::new();
+    let mut i = 0;
+
+    while i < text.len() {
+        let rest = &text[i..];
+        let c = rest.chars().next().unwrap();
+
+        match c {
+            '\\' => {
+                let next = rest[1..].chars().next();
+                match next {
+                    Some(escaped) if escaped.is_ascii_punctuation() => {
+                        buf.push(escaped);
+                        i += 1 + escaped.len_utf8();
+                    }
+                    _ => {
+                        buf.push('\\');
+                        i += 1;
+                    }
+                }
+                continue;
+            }
+            '`' => {
+                let ticks = rest.chars().take_while(|&c| c == '`').count();
+                if let Some((code, consumed)) = parse_code_span(rest, ticks) {
+                    flush_text(&mut spans, &mut buf);
+                    spans.push(Span::Code { code });
+                    i += consumed;
+                } else {
+                    buf.push_str(&rest[..ticks]);
+                    i += ticks;
+                }
+                continue;
+            }
+            '*' | '_' => {
+                let prev = text[..i].chars().next_back();
+                if let Some((span, consumed)) = parse_emphasis(rest, c, prev) {
+                    flush_text(&mut spans, &mut buf);
+                    spans.push(span);
+                    i += consumed;
+                    continue;
+                }
+                let run = rest.chars().take_while(|&r| r == c).count();
+                buf.push_str(&rest[..run]);
+                i += run;
+                continue;
+            }
+            '$' => {
+                let prev = text[..i].chars().next_back();
+                if let Some((tex, consumed)) = parse_inline_math(rest, prev) {
+                    flush_text(&mut spans, &mut buf);
+                    spans.push(Span::Math { tex });
+                    i += consumed;
+                    continue;
+                }
+            }
+            '!' if rest.starts_with("![[") => {
+                if let Some((target, None, consumed)) = parse_wiki_link(&rest[1..]) {
+                    flush_text(&mut spans, &mut buf);
+                    spans.push(Span::Embed { target });
+                    i += consumed + 1;
+                    continue;
+                }
+            }
+            '!' if rest.starts_with("![") => {
+                if let Some((alt, src, consumed)) = parse_link(&rest[1..]) {
+                    flush_text(&mut spans, &mut buf);
+                    spans.push(Span::Image {
+                        alt: alt.to_string(),
+                        src: src.to_string(),
+                    });
+                    i += consumed + 1;
+                    continue;
+                }
+            }
+            '[' if rest.starts_with("[[") => {
+                if let Some((target, label, consumed)) = parse_wiki_link(rest) {
+                    flush_text(&mut spans, &mut buf);
+                    spans.push(Span::WikiLink { target, label });
+                    i += consumed;
+                    continue;
+                }
+            }
+            '<' => {
+                // Autolink: <https://example.com>
+                if let Some(end) = rest.find('>') {
+                    let url = &rest[1..end];
+                    if is_bare_url_start(url) && !url.contains(char::is_whitespace) {
+                        flush_text(&mut spans, &mut buf);
+                        spans.push(url_span(url));
+                        i += end + 1;
+                        continue;
+                    }
+                }
+            }
+            'h' if is_bare_url_start(rest)
+                && !text[..i]
+                    .chars()
+                    .next_back()
+                    .is_some_and(|p| p.is_alphanumeric()) =>
+            {
+                let len = bare_url_len(rest);
+                flush_text(&mut spans, &mut buf);
+                spans.push(url_span(&rest[..len]));
+                i += len;
+                continue;
+            }
+            '[' => {
+                if let Some((label, url, consumed)) = parse_link(rest) {
+                    flush_text(&mut spans, &mut buf);
+                    spans.push(Span::Link {
+                        url: url.to_string(),
+                        children: parse_inline(label),
+                    });
+                    i += consumed;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+
+        buf.push(c);
+        i += c.len_utf8();
+    }
+
+    flush_text(&mut spans, &mut buf);
+    spans
+}
+
+/// Concatenate the visible text of spans
+pub fn spans_to_text(spans: &[Span]) -> String {
+    let mut text = String::new();
+    for span in spans {
+        match span {
+            Span::Text { text: t } => text.push_str(t),
+            Span::Code { code } => text.push_str(code),
+            Span::Math { tex } => text.push_str(tex),
+            Span::Bold { children } | Span::Italic { children } | Span::Link { children, .. } => {
+                text.push_str(&spans_to_text(children))
+            }
+            Span::WikiLink { target, label } => text.push_str(label.as_deref().unwrap_or(target)),
+            Span::Embed { target } => text.push_str(target),
+            Span::Image { alt, .. } => text.push_str(alt),
+        }
+    }
+    text
+}
+
+fn flush_text(spans: &mut Vec<Span>, buf: &mut String) {
+    if !buf.is_empty() {
+        spans.push(Span::Text {
+            text: std::mem::take(buf),
+        });
+    }
+}
+
+/// Match a code span opened by `ticks` backticks; returns the code and bytes consumed
+fn parse_code_span(rest: &str, ticks: usize) -> Option<(String, usize)> {
+    let body = &rest[ticks..];
+    let mut search = 0;
+    while let Some(pos) = body[search..].find('`') {
+        let start = search + pos;
+        let run = body[start..].chars().take_while(|&c| c == '`').count();
+        if run == ticks {
+            let code = &body[..start];
+            // A single leading and trailing space is stripped so `` ` `` can be written
+            let code = if code.len() > 1 && code.starts_with(' ') && code.ends_with(' ') {
+                &code[1..code.len() - 1]
+            } else {
+                code
+            };
+            return Some((code.to_string(), ticks + start + run));
+        }
+        search = start + run;
+    }
+    None
+}
+
+/// Match `**bold**`, `__bold__`, `*italic*` or `_italic_` starting at `rest`
+fn parse_emphasis(rest: &str, delim: char, prev: Option<char>) -> Option<(Span, usize)> {
+    // Underscores inside words (snake_case) never open emphasis
+    if delim == '_' && prev.is_some_and(|p| p.is_alphanumeric()) {
+        return None;
+    }
+
+    let run = rest.chars().take_while(|&c| c == delim).count();
+    let width = if run >= 2 { 2 } else { 1 };
+    let body = &rest[width..];
+    if body.starts_with(char::is_whitespace) || body.is_empty() {
+        return None;
+    }
+
+    let marker = &rest[..width];
+    let mut search = 0;
+    while let Some(pos) = body[search..].find(marker) {
+        let end = search + pos;
+        let after = body[end + width..].chars().next();
+        let before = body[..end].chars().next_back();
+        let closes = end > 0
+            && !before.is_some_and(char::is_whitespace)
+            && (width == 2 || after != Some(delim))
+            && !(delim == '_' && after.is_some_and(|a| a.is_alphanumeric()));
+        if closes {
+            let children = parse_inline(&body[..end]);
+            let span = if width == 2 {
+                Span::Bold { children }
+            } else {
+                Span::Italic { children }
+            };
+            return Some((span, width + end + width));
+        }
+        search = end + body[end..].chars().take_while(|&c| c == delim).count();
+    }
+    None
+}
+
+/// Match `$tex$` (Pandoc rules: no space inside the delimiters, closing `$` not followed by a digit)
+fn parse_inline_math(rest: &str, prev: Option<char>) -> Option<(String, usize)> {
+    if prev == Some('$') || rest.starts_with("$$") {
+        return None;
+    }
+    let body = &rest[1..];
+    if body.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let mut search = 0;
+    while let Some(pos) = body[search..].find('$') {
+        let end = search + pos;
+        let before = body[..end].chars().next_back();
+        let after = body[end + 1..].chars().next();
+        if end > 0
+            && !before.is_some_and(char::is_whitespace)
+            && before != Some('\\')
+            && !after.is_some_and(|a| a.is_ascii_digit())
+        {
+            return Some((body[..end].to_string(), end + 2));
+        }
+        search = end + 1;
+    }
+    None
+}
+
+fn is_bare_url_start(rest: &str) -> bool {
+    rest.starts_with("https://") || rest.starts_with("http://")
+}
+
+/// Length of a bare URL, excluding trailing punctuation and unbalanced closing parens
+fn bare_url_len(rest: &str) -> usize {
+    let mut end = rest
+        .find(|c: char| c.is_whitespace() || c == '<' || c == '`')
+        .unwrap_or(rest.len());
+    loop {
+        let url = &rest[..end];
+        match url.chars().next_back() {
+            Some('.' | ',' | ';' | ':' | '!' | '?' | '*' | '\'' | '"') => end -= 1,
+            Some(')') if url.matches(')').count() > url.matches('(').count() => end -= 1,
+            _ => break,
+        }
+    }
+    end
+}
+
+fn url_span(url: &str) -> Span {
+    Span::Link {
+        url: url.to_string(),
+        children: vec![Span::Text {
+            text: url.to_string(),
+        }],
+    }
+}
+
+/// Match `[[target]]` or `[[target|label]]`
+fn parse_wiki_link(rest: &str) -> Option<(String, Option<String>, usize)> {
+    let body = &rest[2..];
+    let end = body.find("]]")?;
+    let inner = &body[..end];
+    if inner.trim().is_empty() || inner.contains('\n') || inner.contains('[') {
+        return None;
+    }
+    let (target, label) = match inner.split_once('|') {
+        Some((target, label)) => (target.trim(), Some(label.trim().to_string())),
+        None => (inner.trim(), None),
+    };
+    Some((target.to_string(), label, end + 4))
+}
+
+/// Match `[label](url)`; returns label, url and bytes consumed
+fn parse_link(rest: &str) -> Option<(&str, &str, usize)> {
+    let mut depth = 0;
+    let mut label_end = None;
+    for (idx, c) in rest.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    label_end = Some(idx);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let label_end = label_end?;
+    let after = &rest[label_end + 1..];
+    let target = after.strip_prefix('(')?;
+    let close = target.find(')')?;
+    let url = target[..close].trim();
+    if url.contains(char::is_whitespace) && !url.contains('"') {
+        return None;
+    }
+    // Drop an optional link title: [text](url "title")
+    let url = url.split_whitespace().next().unwrap_or("");
+    Some((&rest[1..label_end], url, label_end + 1 + 1 + close + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_blocks, parse_inline};
+    use crate::schema::{ContentBlock, Span};
+
+    fn text(s: &str) -> Span {
+        Span::Text {
+            text: s.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_inline_code_bold_italic() {
+        let spans = parse_inline("call `foo()` **now** or *later*");
+        assert_eq!(
+            spans,
+            vec![
+                text("call "),
+                Span::Code {
+                    code: "foo()".to_string()
+                },
+                text(" "),
+                Span::Bold {
+                    children: vec![text("now")]
+                },
+                text(" or "),
+                Span::Italic {
+                    children: vec![text("later")]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inline_link_with_emphasis() {
+        let spans = parse_inline("see [the *docs*](https://example.com \"Docs\")");
+        assert_eq!(
+            spans,
+            vec![
+                text("see "),
+                Span::Link {
+                    url: "https://example.com".to_string(),
+                    children: vec![
+                        text("the "),
+                        Span::Italic {
+                            children: vec![text("docs")]
+                        }
+                    ],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inline_snake_case_and_math_stars_stay_text() {
+        assert_eq!(
+            parse_inline("helper_function_two"),
+            vec![text("helper_function_two")]
+        );
+        assert_eq!(parse_inline("2 * 3 * 4"), vec![text("2 * 3 * 4")]);
+    }
+
+    #[test]
+    fn test_inline_escapes_and_unclosed() {
+        assert_eq!(parse_inline(r"\*not italic\*"), vec![text("*not italic*")]);
+        assert_eq!(parse_inline("`unclosed"), vec![text("`unclosed")]);
+        assert_eq!(parse_inline("[no target]"), vec![text("[no target]")]);
+    }
+
+    #[test]
+    fn test_inline_double_backtick_code() {
+        assert_eq!(
+            parse_inline("`` a ` b ``"),
+            vec![Span::Code {
+                code: "a ` b".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_inline_math() {
+        assert_eq!(
+            parse_inline("area $\\pi r^2$ here"),
+            vec![
+                text("area "),
+                Span::Math {
+                    tex: "\\pi r^2".to_string()
+                },
+                text(" here"),
+            ]
+        );
+        assert_eq!(
+            parse_inline("costs $5 and $10"),
+            vec![text("costs $5 and $10")]
+        );
+    }
+
+    #[test]
+    fn test_inline_bare_urls_and_autolinks() {
+        assert_eq!(
+            parse_inline("see https://example.com/a_(b). or <http://x.y>"),
+            vec![
+                text("see "),
+                Span::Link {
+                    url: "https://example.com/a_(b)".to_string(),
+                    children: vec![text("https://example.com/a_(b)")],
+                },
+                text(". or "),
+                Span::Link {
+                    url: "http://x.y".to_string(),
+                    children: vec![text("http://x.y")],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inline_wiki_links() {
+        assert_eq!(
+            parse_inline("[[Other Note]] and [[notes/a|A]]"),
+            vec![
+                Span::WikiLink {
+                    target: "Other Note".to_string(),
+                    label: None
+                },
+                text(" and "),
+                Span::WikiLink {
+                    target: "notes/a".to_string(),
+                    label: Some("A".to_string())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inline_embeds() {
+        assert_eq!(
+            parse_inline("![[plan#next-steps]] but not ![[a|b]]"),
+            vec![
+                Span::Embed {
+                    target: "plan#next-steps".to_string()
+                },
+                text(" but not !"),
+                Span::WikiLink {
+                    target: "a".to_string(),
+                    label: Some("b".to_string())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inline_image() {
+        assert_eq!(
+            parse_inline("![diagram](img/flow.png \"Flow\") after"),
+            vec![
+                Span::Image {
+                    alt: "diagram".to_string(),
+                    src: "img/flow.png".to_string()
+                },
+                text(" after"),
+            ]
+        );
+        assert_eq!(parse_inline("wow!"), vec![text("wow!")]);
+    }
+
+    #[test]
+    fn test_parse_blocks() {
+        let blocks = parse_blocks(
+            "Intro line\ncontinued\n\n- first\n  - nested `x`\n1. ordered\n\n> quoted",
+        );
+        assert_eq!(
+            blocks,
+            vec![
+                ContentBlock::Paragraph {
+                    spans: vec![text("Intro line\ncontinued")]
+                },
+                ContentBlock::ListItem {
+                    depth: 0,
+                    ordered: false,
+                    spans: vec![text("first")]
+                },
+                ContentBlock::ListItem {
+                    depth: 1,
+                    ordered: false,
+                    spans: vec![
+                        text("nested "),
+                        Span::Code {
+                            code: "x".to_string()
+                        }
+                    ],
+                },
+                ContentBlock::ListItem {
+                    depth: 0,
+                    ordered: true,
+                    spans: vec![text("ordered")]
+                },
+                ContentBlock::Quote {
+                    spans: vec![text("quoted")]
+                },
+            ]
+        );
+    }
+}
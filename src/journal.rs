@@ -0,0 +1,377 @@
+//! Journal mode: one memo file per day, at a path built from its date.
+//!
+//! `[journal]` in `.fmemo/config.toml` sets the `layout` of entry paths (a chrono format,
+//! `journal/%Y/%m/%Y-%m-%d.fmemo` by default), the `template` of new entries and the
+//! `timezone` that decides which day is today. Entries are recognized by their path alone,
+//! so existing daily notes show up as soon as the layout matches them.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+use chrono::format::StrftimeItems;
+use chrono::{FixedOffset, NaiveDate, Offset, TimeZone, Utc};
+
+use crate::config::{Config, JournalConfig};
+use crate::plugin::Plugins;
+use crate::schema::RenderedTemplate;
+use crate::search::{SearchHit, search_documents};
+use crate::server::{list_memo_files, read_fmemo_file_with, resolve_memo_path};
+use crate::template::{TemplateContext, load_template, render_template};
+
+/// Used for new entries when no template is configured
+pub const DEFAULT_TEMPLATE: &str = "# {{date:%A, %B %-d, %Y}}\n{{cursor}}";
+
+/// A day of the journal, with or without an entry
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct JournalDay {
+    /// `YYYY-MM-DD`
+    pub date: String,
+    /// Path of the day's entry relative to the root, whether or not it exists
+    pub path: String,
+    pub exists: bool,
+    /// Closest earlier day with an entry
+    pub previous: Option<String>,
+    /// Closest later day with an entry
+    pub next: Option<String>,
+}
+
+/// An existing entry, as listed by `search`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct JournalEntry {
+    /// `YYYY-MM-DD`
+    pub date: String,
+    pub path: String,
+    /// Title of the first memo, or the path
+    pub title: String,
+    /// Memos matching the query; empty without one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hits: Vec<SearchHit>,
+}
+
+fn invalid_config(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn format_date(date: NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+fn timezone(config: &JournalConfig) -> io::Result<FixedOffset> {
+    match config.timezone.as_deref().map(str::trim) {
+        None | Some("" | "local") => Ok(*chrono::Local::now().offset()),
+        Some("UTC" | "Z") => Ok(Utc.fix()),
+        Some(offset) => offset.parse().map_err(|_| {
+            invalid_config("journal timezone must be local, UTC or an offset like +09:00")
+        }),
+    }
+}
+
+/// Today in the journal's timezone
+pub fn today(config: &JournalConfig) -> io::Result<NaiveDate> {
+    Ok(Utc::now().with_timezone(&timezone(config)?).date_naive())
+}
+
+/// A date as given in a request: `YYYY-MM-DD`, `today`, `yesterday` or `tomorrow`
+pub fn parse_day(config: &JournalConfig, value: &str) -> io::Result<NaiveDate> {
+    let offset = match value {
+        "today" => 0,
+        "yesterday" => -1,
+        "tomorrow" => 1,
+        _ => {
+            return NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Date must be like 2024-05-01, today, yesterday or tomorrow",
+                )
+            });
+        }
+    };
+    Ok(today(config)? + chrono::Duration::days(offset))
+}
+
+/// Path of a day's entry relative to the root
+pub fn entry_path(config: &JournalConfig, date: NaiveDate) -> io::Result<String> {
+    // An invalid format string would panic when displayed
+    let items = StrftimeItems::new(&config.layout)
+        .parse()
+        .map_err(|_| invalid_config("journal layout is not a valid date format"))?;
+    let path = date.format_with_items(items.iter()).to_string();
+    let ext = Path::new(&path).extension().and_then(|s| s.to_str());
+    if !matches!(ext, Some("fmemo" | "md")) || resolve_memo_path(Path::new(""), &path).is_none() {
+        return Err(invalid_config(
+            "journal layout must be a .fmemo or .md path inside the root",
+        ));
+    }
+    Ok(path)
+}
+
+/// The date of an entry path, if the layout produces it
+pub fn entry_date(config: &JournalConfig, path: &str) -> Option<NaiveDate> {
+    let date = NaiveDate::parse_from_str(path, &config.layout).ok()?;
+    // Layouts can be ambiguous when parsed back (`%-d`, ...); only exact matches count
+    (entry_path(config, date).ok()? == path).then_some(date)
+}
+
+/// Every entry below `root` with its path, oldest first
+pub fn entries(root: &Path, config: &JournalConfig) -> io::Result<Vec<(NaiveDate, String)>> {
+    // Only the directories before the first date field need to be scanned
+    let fixed = &config.layout[..config.layout.find('%').unwrap_or(config.layout.len())];
+    let dir = fixed.rfind('/').map_or("", |idx| &fixed[..idx]);
+    if !root.join(dir).is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut entries: Vec<(NaiveDate, String)> = list_memo_files(root.join(dir))?
+        .into_iter()
+        .filter_map(|file| {
+            let path = match dir {
+                "" => file,
+                dir => format!("{}/{}", dir, file),
+            };
+            Some((entry_date(config, &path)?, path))
+        })
+        .collect();
+    entries.sort();
+    Ok(entries)
+}
+
+/// A day with its entry path and the closest days around it that have entries
+pub fn day(root: &Path, config: &JournalConfig, date: NaiveDate) -> io::Result<JournalDay> {
+    let path = entry_path(config, date)?;
+    let entries = entries(root, config)?;
+    Ok(JournalDay {
+        date: format_date(date),
+        exists: root.join(&path).is_file(),
+        path,
+        previous: entries
+            .iter()
+            .rev()
+            .find(|(day, _)| *day < date)
+            .map(|(day, _)| format_date(*day)),
+        next: entries
+            .iter()
+            .find(|(day, _)| *day > date)
+            .map(|(day, _)| format_date(*day)),
+    })
+}
+
+/// The closest day before (or after, with `later`) `date` that has an entry; `NotFound`
+/// when there is none
+pub fn adjacent_day(
+    root: &Path,
+    config: &JournalConfig,
+    date: NaiveDate,
+    later: bool,
+) -> io::Result<JournalDay> {
+    let current = day(root, config, date)?;
+    let target = if later {
+        current.next
+    } else {
+        current.previous
+    };
+    let target = target
+        .and_then(|value| NaiveDate::parse_from_str(&value, "%Y-%m-%d").ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                if later {
+                    "No later journal entry"
+                } else {
+                    "No earlier journal entry"
+                },
+            )
+        })?;
+    day(root, config, target)
+}
+
+/// Create the entry of a day from the journal template. Placeholders like `{{date}}` are
+/// filled in with the entry's day, not the current one. Returns `None` when the entry
+/// already exists, which is left as it is.
+pub fn create_entry(
+    root: &Path,
+    config: &Config,
+    date: NaiveDate,
+) -> io::Result<Option<RenderedTemplate>> {
+    let path = entry_path(&config.journal, date)?;
+    let file_path = root.join(&path);
+    if file_path.exists() {
+        return Ok(None);
+    }
+    let template = match &config.journal.template {
+        Some(name) => {
+            let dir = path.rfind('/').map_or("", |idx| &path[..idx]);
+            load_template(root, dir, Some(name))?
+        }
+        None => DEFAULT_TEMPLATE.to_string(),
+    };
+    let mut context = TemplateContext::new(&format_date(date), &path);
+    let offset = timezone(&config.journal)?;
+    let time = Utc::now().with_timezone(&offset).time();
+    if let Some(now) = offset.from_local_datetime(&date.and_time(time)).single() {
+        context.now = now;
+    }
+    context.variables = config.variables.clone();
+    let (content, cursor) = render_template(&template, &context);
+
+    if let Some(parent) = file_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // create_new: a concurrent request may have created it meanwhile
+    match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&file_path)
+    {
+        Ok(mut file) => file.write_all(content.as_bytes())?,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    Ok(Some(RenderedTemplate {
+        path,
+        content,
+        cursor,
+    }))
+}
+
+/// Entries from `from` to `to` (both included, either open), oldest first. With a query,
+/// only entries with matching memos, as for `GET /api/search`. Unreadable entries are
+/// skipped. With `publish`, entries are read without their private regions.
+pub fn search(
+    root: &Path,
+    config: &JournalConfig,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    query: Option<&str>,
+    publish: bool,
+) -> io::Result<Vec<JournalEntry>> {
+    let query = query.map(str::trim).filter(|query| !query.is_empty());
+    let mut found = Vec::new();
+    for (date, path) in entries(root, config)? {
+        if from.is_some_and(|from| date < from) || to.is_some_and(|to| date > to) {
+            continue;
+        }
+        let Ok(parsed) = read_fmemo_file_with(root.join(&path), &Plugins::default(), publish)
+        else {
+            continue;
+        };
+        let title = match parsed.memos.first() {
+            Some(memo) => memo.title().clone(),
+            None => path.clone(),
+        };
+        let hits = match query {
+            Some(query) => {
+                let hits = search_documents(&BTreeMap::from([(path.clone(), parsed.memos)]), query);
+                if hits.is_empty() {
+                    continue;
+                }
+                hits
+            }
+            None => Vec::new(),
+        };
+        found.push(JournalEntry {
+            date: format_date(date),
+            path,
+            title,
+            hits,
+        });
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn test_journal() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let mut config = Config::default();
+        config.journal.timezone = Some("+09:00".to_string());
+        let journal = &config.journal;
+
+        assert_eq!(
+            entry_path(journal, date("2024-05-01")).unwrap(),
+            "journal/2024/05/2024-05-01.fmemo"
+        );
+        assert_eq!(
+            entry_date(journal, "journal/2024/05/2024-05-01.fmemo"),
+            Some(date("2024-05-01"))
+        );
+        assert_eq!(
+            entry_date(journal, "journal/2024/06/2024-05-01.fmemo"),
+            None
+        );
+        assert_eq!(
+            parse_day(journal, "today").unwrap(),
+            today(journal).unwrap()
+        );
+        assert!(parse_day(journal, "May 1").is_err());
+
+        let created = create_entry(root, &config, date("2024-05-01"))
+            .unwrap()
+            .unwrap();
+        assert!(created.content.starts_with("# Wednesday, May 1, 2024\n"));
+        assert!(
+            create_entry(root, &config, date("2024-05-01"))
+                .unwrap()
+                .is_none()
+        );
+        std::fs::write(
+            root.join(entry_path(journal, date("2024-05-03")).unwrap()),
+            "# Friday\n## Standup\nDeploy the parser",
+        )
+        .unwrap();
+        std::fs::write(root.join("journal/notes.fmemo"), "# Not an entry").unwrap();
+
+        let day = day(root, journal, date("2024-05-02")).unwrap();
+        assert!(!day.exists);
+        assert_eq!(day.previous.as_deref(), Some("2024-05-01"));
+        assert_eq!(day.next.as_deref(), Some("2024-05-03"));
+        let next = adjacent_day(root, journal, date("2024-05-01"), true).unwrap();
+        assert_eq!(next.date, "2024-05-03");
+        assert!(next.exists);
+        assert_eq!(
+            adjacent_day(root, journal, date("2024-05-03"), true)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+
+        let all = search(root, journal, None, None, None, false).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[1].title, "Friday");
+        let found = search(
+            root,
+            journal,
+            Some(date("2024-05-01")),
+            None,
+            Some("deploy"),
+            false,
+        )
+        .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].hits[0].title, "Standup");
+        assert!(
+            search(
+                root,
+                journal,
+                None,
+                Some(date("2024-05-02")),
+                Some("deploy"),
+                false
+            )
+            .unwrap()
+            .is_empty()
+        );
+
+        let mut broken = journal.clone();
+        broken.layout = "journal/%Y-%m-%d.txt".to_string();
+        assert!(entry_path(&broken, date("2024-05-01")).is_err());
+    }
+}
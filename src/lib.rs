@@ -1,3 +1,71 @@
+// The API is one long chain of warp filters
+#![recursion_limit = "256"]
+
+pub mod access_log;
+pub mod app;
+pub mod archive;
+pub mod atomic;
+pub mod audit;
+pub mod blog;
+pub mod board;
+pub mod calendar;
+pub mod chat;
+pub mod collab;
+pub mod comments;
+pub mod config;
+pub mod crypt;
+pub mod diagram;
+pub mod diff;
+pub mod draft;
+pub mod duplicates;
+pub mod embed;
+pub mod embeddings;
+pub mod git;
+pub mod glob;
+pub mod graph;
+pub mod highlight;
+pub mod hooks;
+pub mod import;
+pub mod indexer;
+pub mod incremental;
+pub mod inline;
+pub mod journal;
+pub mod links;
+pub mod lint;
+pub mod llm;
+pub mod lsp;
+pub mod mapped;
+pub mod mdns;
+pub mod merge;
+pub mod network;
+pub mod oidc;
+pub mod page;
+pub mod pandoc;
+pub mod mcp;
 pub mod parser;
+pub mod plugin;
+pub mod publish;
+pub mod presence;
+pub mod related;
+pub mod render;
+pub mod request_id;
 pub mod schema;
+pub mod search;
 pub mod server;
+pub mod sitemap;
+pub mod spell;
+pub mod stamp;
+pub mod state;
+pub mod static_site;
+pub mod tags;
+pub mod template;
+pub mod text;
+pub mod tokens;
+pub mod trash;
+pub mod users;
+pub mod views;
+pub mod watcher;
+pub mod webhook;
+pub mod ws_format;
+
+pub use app::{FmemoServer, Frontend};
@@ -0,0 +1,239 @@
+//! Links and images that point nowhere, for `GET /api/links/broken`.
+//!
+//! Relative links must name an existing file below the root, wiki-links a memo file
+//! (resolved like the graph does) and local images an existing file. A `#anchor` after a
+//! memo file, or on its own for the same file, must be the anchor of one of its memos.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use percent_encoding::percent_decode_str;
+
+use crate::graph::resolve_wiki_target;
+use crate::lint::line_containing;
+use crate::parser::{ParseOptions, normalize_relative_path, normalize_source, parse_document};
+use crate::schema::{LinkKind, Memo};
+use crate::server::list_memo_files;
+
+/// How the broken target was referenced
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReferenceKind {
+    Link,
+    Wiki,
+    Image,
+}
+
+/// What is missing
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BrokenReason {
+    MissingFile,
+    MissingAnchor,
+}
+
+/// An unresolved reference, with the file and 1-based line it was written on
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+pub struct BrokenLink {
+    pub file: String,
+    pub line: usize,
+    pub target: String,
+    pub kind: ReferenceKind,
+    pub reason: BrokenReason,
+}
+
+struct Source {
+    content: String,
+    memos: Vec<Memo>,
+    anchors: HashSet<String>,
+}
+
+fn collect_anchors(memos: &[Memo], anchors: &mut HashSet<String>) {
+    for memo in memos {
+        anchors.insert(memo.anchor().into_owned());
+        collect_anchors(memo.children(), anchors);
+    }
+}
+
+fn decode(text: &str) -> String {
+    percent_decode_str(text).decode_utf8_lossy().into_owned()
+}
+
+/// Every broken reference in the memo files below `root`, by file and line; with `publish`,
+/// private regions aren't checked
+pub fn find_broken_links(root: &Path, publish: bool) -> std::io::Result<Vec<BrokenLink>> {
+    let files = list_memo_files(root)?;
+    let sources: BTreeMap<String, Source> = files
+        .iter()
+        .map(|file| {
+            let raw = crate::publish::read_memo(&root.join(file), publish).unwrap_or_default();
+            let content = normalize_source(&raw).into_owned();
+            let memos = parse_document(&content, &ParseOptions::default()).memos;
+            let mut anchors = HashSet::new();
+            collect_anchors(&memos, &mut anchors);
+            let source = Source {
+                content,
+                memos,
+                anchors,
+            };
+            (file.clone(), source)
+        })
+        .collect();
+
+    let mut broken = Vec::new();
+    for (file, source) in &sources {
+        let lines: Vec<&str> = source.content.lines().collect();
+        let mut checker = Checker {
+            root,
+            files: &files,
+            sources: &sources,
+            file,
+            lines: &lines,
+            broken: &mut broken,
+        };
+        checker.check(&source.memos);
+    }
+    Ok(broken)
+}
+
+struct Checker<'a> {
+    root: &'a Path,
+    files: &'a [String],
+    sources: &'a BTreeMap<String, Source>,
+    file: &'a str,
+    lines: &'a [&'a str],
+    broken: &'a mut Vec<BrokenLink>,
+}
+
+impl Checker<'_> {
+    fn check(&mut self, memos: &[Memo]) {
+        for memo in memos {
+            for link in memo.links() {
+                let (path, anchor) = match link.url.split_once('#') {
+                    Some((path, anchor)) => (path, Some(anchor)),
+                    None => (link.url.as_str(), None),
+                };
+                let (kind, target) = match link.kind {
+                    LinkKind::External => continue,
+                    LinkKind::Wiki => (
+                        ReferenceKind::Wiki,
+                        resolve_wiki_target(self.files, path)
+                            .cloned()
+                            .ok_or(BrokenReason::MissingFile),
+                    ),
+                    LinkKind::Internal if path.is_empty() => {
+                        (ReferenceKind::Link, Ok(self.file.to_string()))
+                    }
+                    LinkKind::Internal => (ReferenceKind::Link, self.resolve_path(path)),
+                };
+                let result = target.and_then(|target| match (anchor, self.sources.get(&target)) {
+                    (Some(anchor), Some(source))
+                        if !anchor.is_empty() && !source.anchors.contains(&decode(anchor)) =>
+                    {
+                        Err(BrokenReason::MissingAnchor)
+                    }
+                    _ => Ok(()),
+                });
+                if let Err(reason) = result {
+                    self.report(memo, &link.url, kind, reason);
+                }
+            }
+            for image in memo.images() {
+                if LinkKind::classify(&image.src) == LinkKind::Internal
+                    && let Err(reason) = self.resolve_path(&image.src)
+                {
+                    self.report(memo, &image.src, ReferenceKind::Image, reason);
+                }
+            }
+            self.check(memo.children());
+        }
+    }
+
+    /// Root-relative path of an existing file a relative reference points to
+    fn resolve_path(&self, path: &str) -> Result<String, BrokenReason> {
+        let base_dir = self.file.rfind('/').map_or("", |idx| &self.file[..idx]);
+        normalize_relative_path(base_dir, &decode(path))
+            .filter(|target| self.root.join(target).is_file())
+            .ok_or(BrokenReason::MissingFile)
+    }
+
+    fn report(&mut self, memo: &Memo, target: &str, kind: ReferenceKind, reason: BrokenReason) {
+        self.broken.push(BrokenLink {
+            file: self.file.to_string(),
+            line: line_containing(self.lines, memo.span(), target),
+            target: target.to_string(),
+            kind,
+            reason,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BrokenReason, ReferenceKind, find_broken_links};
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_find_broken_links() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("notes")).unwrap();
+        fs::write(temp_dir.path().join("logo.png"), "").unwrap();
+        fs::write(
+            temp_dir.path().join("notes/a.fmemo"),
+            "# A\n[ok](../b.fmemo#setup) [[b]] ![logo](../logo.png)\n\n## Later\n[same](#later) [web](https://example.com)\n[gone](old.fmemo)\n[[nowhere]] [bad](../b.fmemo#teardown)\n![photo](photo.jpg)",
+        )
+        .unwrap();
+        fs::write(temp_dir.path().join("b.fmemo"), "# Setup\n[back](#nope)").unwrap();
+
+        let broken: Vec<(String, usize, String, ReferenceKind, BrokenReason)> =
+            find_broken_links(temp_dir.path(), false)
+                .unwrap()
+                .into_iter()
+                .map(|link| (link.file, link.line, link.target, link.kind, link.reason))
+                .collect();
+        let entry = |file: &str, line, target: &str, kind, reason| {
+            (file.to_string(), line, target.to_string(), kind, reason)
+        };
+        assert_eq!(
+            broken,
+            [
+                entry(
+                    "b.fmemo",
+                    2,
+                    "#nope",
+                    ReferenceKind::Link,
+                    BrokenReason::MissingAnchor
+                ),
+                entry(
+                    "notes/a.fmemo",
+                    6,
+                    "old.fmemo",
+                    ReferenceKind::Link,
+                    BrokenReason::MissingFile
+                ),
+                entry(
+                    "notes/a.fmemo",
+                    7,
+                    "nowhere",
+                    ReferenceKind::Wiki,
+                    BrokenReason::MissingFile
+                ),
+                entry(
+                    "notes/a.fmemo",
+                    7,
+                    "../b.fmemo#teardown",
+                    ReferenceKind::Link,
+                    BrokenReason::MissingAnchor
+                ),
+                entry(
+                    "notes/a.fmemo",
+                    8,
+                    "photo.jpg",
+                    ReferenceKind::Image,
+                    BrokenReason::MissingFile
+                ),
+            ]
+        );
+    }
+}
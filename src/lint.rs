@@ -0,0 +1,262 @@
+//! Memo hygiene checks behind `fmemo lint`.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::parser::{ParseOptions, normalize_relative_path, normalize_source, parse_document};
+use crate::schema::{LinkKind, Memo, ParseWarningKind, SourceSpan};
+use crate::spell::Spellchecker;
+
+/// What a lint issue is about
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum LintRule {
+    UnclosedCodeFence,
+    UnclosedMathBlock,
+    UnclosedDesc,
+    MalformedDesc,
+    SkippedHeadingLevel,
+    InvalidTimestamp,
+    BrokenLink,
+    DuplicateTitle,
+    Spelling,
+}
+
+impl From<ParseWarningKind> for LintRule {
+    fn from(kind: ParseWarningKind) -> Self {
+        match kind {
+            ParseWarningKind::UnclosedCodeFence => LintRule::UnclosedCodeFence,
+            ParseWarningKind::UnclosedMathBlock => LintRule::UnclosedMathBlock,
+            ParseWarningKind::UnclosedDesc => LintRule::UnclosedDesc,
+            ParseWarningKind::SkippedHeadingLevel => LintRule::SkippedHeadingLevel,
+            ParseWarningKind::InvalidTimestamp => LintRule::InvalidTimestamp,
+        }
+    }
+}
+
+impl LintRule {
+    /// Issues `fix_source` can repair on its own
+    pub fn is_fixable(self) -> bool {
+        matches!(
+            self,
+            LintRule::UnclosedCodeFence | LintRule::UnclosedMathBlock | LintRule::UnclosedDesc
+        )
+    }
+}
+
+/// A problem found in a memo file, with its 1-based line
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct LintIssue {
+    pub line: usize,
+    pub rule: LintRule,
+    pub message: String,
+}
+
+/// Check one file. `file_path` is relative to `root` and used to resolve links;
+/// without a root, links aren't checked.
+pub fn lint_source(content: &str, file_path: &str, root: Option<&Path>) -> Vec<LintIssue> {
+    let content = normalize_source(content);
+    let lines: Vec<&str> = content.lines().collect();
+    let document = parse_document(&content, &ParseOptions::default());
+
+    let mut issues: Vec<LintIssue> = document
+        .warnings
+        .into_iter()
+        .map(|warning| LintIssue {
+            line: warning.line,
+            rule: warning.kind.into(),
+            message: warning.message,
+        })
+        .collect();
+    check_memos(&document.memos, &lines, file_path, root, &mut issues);
+    issues.sort_by_key(|issue| issue.line);
+    issues
+}
+
+/// Unknown words, one issue each; kept apart from `lint_source` so the dictionaries are
+/// loaded once for many files
+pub fn spelling_issues(content: &str, speller: &Spellchecker) -> Vec<LintIssue> {
+    speller
+        .misspellings(content)
+        .into_iter()
+        .map(|misspelling| {
+            let hint = match misspelling.suggestions.as_slice() {
+                [] => String::new(),
+                suggestions => format!(" (did you mean {}?)", suggestions.join(", ")),
+            };
+            LintIssue {
+                line: misspelling.line,
+                rule: LintRule::Spelling,
+                message: format!("Unknown word '{}'{}", misspelling.word, hint),
+            }
+        })
+        .collect()
+}
+
+/// Read and check a file below `root`
+pub fn lint_file(root: &Path, file_path: &str) -> std::io::Result<Vec<LintIssue>> {
+    let content = crate::crypt::read_memo(&root.join(file_path))?;
+    Ok(lint_source(&content, file_path, Some(root)))
+}
+
+/// Repair trivial issues: close unclosed `<desc>` tags, code fences and math blocks,
+/// and normalize line endings and trailing whitespace.
+pub fn fix_source(content: &str) -> String {
+    let content = normalize_source(content);
+    let document = parse_document(&content, &ParseOptions::default());
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+
+    for warning in &document.warnings {
+        let Some(line) = lines.get_mut(warning.line.wrapping_sub(1)) else {
+            continue;
+        };
+        match warning.kind {
+            ParseWarningKind::UnclosedDesc => line.push_str("</desc>"),
+            ParseWarningKind::UnclosedCodeFence => {
+                let opening = line.trim_start();
+                let marker = opening.chars().next().unwrap_or('`');
+                let fence: String = opening.chars().take_while(|&c| c == marker).collect();
+                lines.push(fence);
+            }
+            ParseWarningKind::UnclosedMathBlock => lines.push("$$".to_string()),
+            _ => {}
+        }
+    }
+
+    let mut fixed = lines.join("\n");
+    if content.ends_with('\n') || fixed != content.trim_end_matches('\n') {
+        fixed.push('\n');
+    }
+    fixed
+}
+
+fn check_memos(
+    memos: &[Memo],
+    lines: &[&str],
+    file_path: &str,
+    root: Option<&Path>,
+    issues: &mut Vec<LintIssue>,
+) {
+    let mut titles = HashSet::new();
+    for memo in memos {
+        let span = memo.span();
+        if !titles.insert(memo.title()) {
+            issues.push(LintIssue {
+                line: span.map_or(0, |span| span.start_line),
+                rule: LintRule::DuplicateTitle,
+                message: format!("Another sibling memo is already titled '{}'", memo.title()),
+            });
+        }
+
+        // A complete <desc> is removed from the content, so a leftover close tag is stray
+        if memo
+            .content()
+            .as_deref()
+            .is_some_and(|content| content.contains("</desc>"))
+        {
+            issues.push(LintIssue {
+                line: line_containing(lines, span, "</desc>"),
+                rule: LintRule::MalformedDesc,
+                message: "</desc> without a matching <desc>".to_string(),
+            });
+        }
+
+        if let Some(root) = root {
+            for link in memo.links() {
+                if link.kind != LinkKind::Internal || link.url.starts_with('#') {
+                    continue;
+                }
+                let exists = normalize_relative_path(base_dir(file_path), &link.url)
+                    .is_some_and(|target| root.join(target).exists());
+                if !exists {
+                    issues.push(LintIssue {
+                        line: line_containing(lines, span, &link.url),
+                        rule: LintRule::BrokenLink,
+                        message: format!("Link target '{}' does not exist", link.url),
+                    });
+                }
+            }
+        }
+
+        check_memos(memo.children(), lines, file_path, root, issues);
+    }
+}
+
+fn base_dir(file_path: &str) -> &str {
+    file_path.rfind('/').map_or("", |idx| &file_path[..idx])
+}
+
+/// First line within the memo's own span containing `needle`, or its heading line
+pub(crate) fn line_containing(lines: &[&str], span: Option<SourceSpan>, needle: &str) -> usize {
+    let Some(span) = span else {
+        return 0;
+    };
+    (span.start_line..=span.end_line)
+        .find(|&line| {
+            lines
+                .get(line - 1)
+                .is_some_and(|text| text.contains(needle))
+        })
+        .unwrap_or(span.start_line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LintRule, fix_source, lint_file, lint_source};
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn rules(content: &str) -> Vec<(usize, LintRule)> {
+        lint_source(content, "notes.fmemo", None)
+            .into_iter()
+            .map(|issue| (issue.line, issue.rule))
+            .collect()
+    }
+
+    #[test]
+    fn test_lint_reports_structure_problems() {
+        let content = "# A\n<desc>open\n### Deep\ntext</desc>\n# A\n```rust\nfn main() {}";
+        assert_eq!(
+            rules(content),
+            [
+                (2, LintRule::UnclosedDesc),
+                (3, LintRule::SkippedHeadingLevel),
+                (4, LintRule::MalformedDesc),
+                (5, LintRule::DuplicateTitle),
+                (6, LintRule::UnclosedCodeFence),
+            ]
+        );
+        assert!(rules("# A\n## B\n# B\n").is_empty());
+    }
+
+    #[test]
+    fn test_lint_checks_internal_links() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        fs::write(temp_dir.path().join("other.fmemo"), "# Other").unwrap();
+        fs::write(
+            temp_dir.path().join("sub").join("notes.fmemo"),
+            "# Links\n[ok](../other.fmemo#top) [web](https://example.com) [anchor](#links)\n\n[missing](gone.fmemo)",
+        )
+        .unwrap();
+
+        let issues = lint_file(temp_dir.path(), "sub/notes.fmemo").unwrap();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].rule, LintRule::BrokenLink);
+        assert_eq!(issues[0].line, 4);
+    }
+
+    #[test]
+    fn test_fix_closes_unclosed_constructs() {
+        let content = "# A  \r\n<desc>open\n## B\n~~~~sh\necho hi";
+        let fixed = fix_source(content);
+        assert_eq!(
+            fixed,
+            "# A\n<desc>open</desc>\n## B\n~~~~sh\necho hi\n~~~~\n"
+        );
+        assert!(rules(&fixed).is_empty());
+
+        let clean = "# A\ntext\n";
+        assert_eq!(fix_source(clean), clean);
+    }
+}
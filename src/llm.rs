@@ -0,0 +1,244 @@
+//! Memo summaries from an OpenAI-compatible chat completions API, configured as `[llm]` in
+//! `.fmemo/config.toml`, for `POST /api/files/{path}/summarize`.
+//!
+//! An `ErrorKind::Unsupported` error means no backend is configured.
+
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::LlmConfig;
+use crate::parser::{
+    ParseOptions, find_memo_by_slug, normalize_source, parse_document, subtree_markdown,
+};
+use crate::schema::{Memo, SummarizeRequest};
+
+pub const DEFAULT_PROMPT: &str = "Summarize the following Markdown notes in one or two \
+    sentences. Reply with the summary only, in the language of the notes.";
+
+/// What `summarize_file` did
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct Summary {
+    pub path: String,
+    /// Anchor of the summarized memo; `None` for the whole file
+    pub memo: Option<String>,
+    pub summary: String,
+    /// Anchor of the memo whose `<desc>` was set, when written back
+    pub written_to: Option<String>,
+}
+
+/// Chat completions request asking for a summary of `text`, cut to `max_input_chars`
+pub fn request_body(config: &LlmConfig, text: &str) -> serde_json::Value {
+    let text: String = text.chars().take(config.max_input_chars).collect();
+    serde_json::json!({
+        "model": config.model,
+        "messages": [
+            {"role": "system", "content": config.prompt.as_deref().unwrap_or(DEFAULT_PROMPT)},
+            {"role": "user", "content": text}
+        ]
+    })
+}
+
+/// The reply text of a chat completions response
+pub fn completion_text(response: &serde_json::Value) -> io::Result<String> {
+    response["choices"][0]["message"]["content"]
+        .as_str()
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "LLM response has no summary"))
+}
+
+/// Ask the configured backend for a summary of `text`. Blocks for up to `timeout_secs`.
+pub fn summarize(config: &LlmConfig, text: &str) -> io::Result<String> {
+    let Some(url) = config.url.as_deref().filter(|url| !url.is_empty()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "No LLM backend configured; set [llm] url in .fmemo/config.toml",
+        ));
+    };
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .build();
+    let mut request = agent
+        .post(&format!("{}/chat/completions", url.trim_end_matches('/')))
+        .set("Content-Type", "application/json");
+    if let Some(key) = &config.api_key {
+        request = request.set("Authorization", &format!("Bearer {}", key));
+    }
+    let response = request
+        .send_string(&request_body(config, text).to_string())
+        .map_err(|e| io::Error::other(format!("LLM request failed: {}", e)))?;
+    let response: serde_json::Value = serde_json::from_str(&response.into_string()?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    completion_text(&response)
+}
+
+/// `content` with the memo's first `<desc>...</desc>` replaced by `description`, or a new
+/// `<desc>` line after its heading when it has none. `None` without a source span.
+pub fn set_description(content: &str, memo: &Memo, description: &str) -> Option<String> {
+    let span = memo.span()?;
+    let content = normalize_source(content);
+    let mut lines: Vec<String> = content.split('\n').map(str::to_string).collect();
+    // The summary mustn't close the tag early or open another one
+    let tag = format!(
+        "<desc>{}</desc>",
+        description.replace("<desc>", "").replace("</desc>", "")
+    );
+
+    let own_lines = span.start_line..span.end_line.min(lines.len());
+    let open = own_lines
+        .clone()
+        .find_map(|index| Some((index, lines[index].find("<desc>")?)));
+    let close = open.and_then(|(open_line, open_col)| {
+        own_lines
+            .clone()
+            .skip_while(|&index| index < open_line)
+            .find_map(|index| {
+                let from = if index == open_line { open_col } else { 0 };
+                let col = lines[index][from..].find("</desc>")? + from;
+                Some((index, col + "</desc>".len()))
+            })
+    });
+
+    match (open, close) {
+        (Some((open_line, open_col)), Some((close_line, close_end))) => {
+            let replaced = format!(
+                "{}{}{}",
+                &lines[open_line][..open_col],
+                tag,
+                &lines[close_line][close_end..]
+            );
+            lines.splice(open_line..=close_line, [replaced]);
+        }
+        _ => lines.insert(span.start_line, tag),
+    }
+    Some(lines.join("\n"))
+}
+
+/// Summarize a memo file below `root` (or one memo of it, with its children) and, if asked
+/// to, store the summary as a `<desc>`
+pub fn summarize_file(root: &Path, file: &str, request: &SummarizeRequest) -> io::Result<Summary> {
+    let config = crate::config::load_config(root)?.llm;
+    let path = root.join(file);
+    let content = crate::crypt::read_memo(&path)?;
+    let document = parse_document(&content, &ParseOptions::default());
+
+    let memo = match &request.memo {
+        Some(anchor) => Some(find_memo_by_slug(&document.memos, anchor).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No memo '{}' in {}", anchor, file),
+            )
+        })?),
+        None => None,
+    };
+    let text = match memo {
+        Some(memo) => subtree_markdown(&content, memo).unwrap_or_default(),
+        None => content.clone(),
+    };
+    let summary = summarize(&config, &text)?;
+
+    let mut written_to = None;
+    if request.write {
+        let target = memo.or(document.memos.first()).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The file has no memo to store the summary in",
+            )
+        })?;
+        let updated = set_description(&content, target, &summary).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The memo has no source position",
+            )
+        })?;
+        crate::crypt::write_memo(&path, &updated)?;
+        written_to = Some(target.anchor().into_owned());
+    }
+
+    Ok(Summary {
+        path: file.to_string(),
+        memo: memo.map(|memo| memo.anchor().into_owned()),
+        summary,
+        written_to,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{completion_text, request_body, set_description, summarize};
+    use crate::config::LlmConfig;
+    use crate::parser::{ParseOptions, parse_document};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_set_description() {
+        let content = "# A\n<desc>old\nsummary</desc> kept\ntext\n## B\nbody\n";
+        let memos = parse_document(content, &ParseOptions::default()).memos;
+        assert_eq!(
+            set_description(content, &memos[0], "new").unwrap(),
+            "# A\n<desc>new</desc> kept\ntext\n## B\nbody\n"
+        );
+        assert_eq!(
+            set_description(content, &memos[0].children()[0], "A <desc>b</desc>").unwrap(),
+            "# A\n<desc>old\nsummary</desc> kept\ntext\n## B\n<desc>A b</desc>\nbody\n"
+        );
+    }
+
+    #[test]
+    fn test_summarize() {
+        let config = LlmConfig {
+            max_input_chars: 4,
+            ..LlmConfig::default()
+        };
+        let body = request_body(&config, "abcdef");
+        assert_eq!(body["messages"][1]["content"], "abcd");
+        assert!(completion_text(&serde_json::json!({"choices": []})).is_err());
+        assert_eq!(
+            summarize(&config, "text").unwrap_err().kind(),
+            std::io::ErrorKind::Unsupported
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = LlmConfig {
+            url: Some(format!("http://{}/v1/", listener.local_addr().unwrap())),
+            api_key: Some("key".to_string()),
+            ..LlmConfig::default()
+        };
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim_end().is_empty() {
+                    break;
+                }
+                head.push(line.trim_end().to_lowercase());
+            }
+            let length: usize = head
+                .iter()
+                .find_map(|line| line.strip_prefix("content-length: "))
+                .unwrap()
+                .parse()
+                .unwrap();
+            reader.read_exact(&mut vec![0; length]).unwrap();
+            let body = r#"{"choices":[{"message":{"role":"assistant","content":" Short. "}}]}"#;
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+            head
+        });
+        assert_eq!(summarize(&config, "text").unwrap(), "Short.");
+        let head = server.join().unwrap();
+        assert_eq!(head[0], "post /v1/chat/completions http/1.1");
+        assert!(head.contains(&"authorization: bearer key".to_string()));
+    }
+}
@@ -0,0 +1,567 @@
+//! Language server for memo files, spoken over stdio by `fmemo lsp`.
+//!
+//! Documents are synced in full. The server offers document symbols from the memo
+//! hierarchy, completion of `[[wiki-links]]` and metadata tags, go-to-definition for
+//! wiki-links and relative links, and lint issues (with spelling, when enabled) as diagnostics.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode};
+use serde_json::{Value, json};
+
+use crate::graph::{resolve_link, wiki_name};
+use crate::lint::{lint_source, spelling_issues};
+use crate::parser::{ParseOptions, parse_document};
+use crate::schema::{LinkKind, Memo};
+use crate::server::{list_memo_files, resolve_memo_path};
+use crate::spell::Spellchecker;
+use crate::template::slugify;
+
+/// Characters escaped in `file://` URIs
+const PATH_ESCAPES: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b']')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+// LSP enum values used below
+const SYMBOL_KIND_STRING: u32 = 15;
+const COMPLETION_KIND_FILE: u32 = 17;
+const COMPLETION_KIND_KEYWORD: u32 = 14;
+const COMPLETION_KIND_VALUE: u32 = 12;
+const INSERT_TEXT_FORMAT_SNIPPET: u32 = 2;
+const SEVERITY_WARNING: u32 = 2;
+const METHOD_NOT_FOUND: i32 = -32601;
+
+pub fn path_to_uri(path: &Path) -> String {
+    format!(
+        "file://{}",
+        utf8_percent_encode(&path.to_string_lossy().replace('\\', "/"), PATH_ESCAPES)
+    )
+}
+
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    Some(PathBuf::from(
+        percent_decode_str(path).decode_utf8_lossy().as_ref(),
+    ))
+}
+
+/// Byte offset in `line` of an LSP character position (UTF-16 code units)
+fn byte_offset(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (idx, c) in line.char_indices() {
+        if units >= character {
+            return idx;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.chars().map(char::len_utf16).sum()
+}
+
+/// Range covering a whole 0-based line
+fn line_range(lines: &[&str], line: usize) -> Value {
+    let len = lines.get(line).map_or(0, |text| utf16_len(text));
+    json!({
+        "start": {"line": line, "character": 0},
+        "end": {"line": line, "character": len}
+    })
+}
+
+/// 0-based line of the heading an anchor (a title or its slug) refers to
+fn anchor_line(memos: &[Memo], anchor: &str) -> Option<usize> {
+    memos.iter().find_map(|memo| {
+        let matches = memo.title().eq_ignore_ascii_case(anchor) || slugify(memo.title()) == anchor;
+        match memo.span() {
+            Some(span) if matches => Some(span.start_line - 1),
+            _ => anchor_line(memo.children(), anchor),
+        }
+    })
+}
+
+fn symbols(memos: &[Memo], lines: &[&str]) -> Vec<Value> {
+    memos
+        .iter()
+        .filter_map(|memo| {
+            let span = memo.span()?;
+            let end = memo.subtree_end_line().unwrap_or(span.end_line) - 1;
+            let name = if memo.title().is_empty() {
+                "(untitled)"
+            } else {
+                memo.title()
+            };
+            Some(json!({
+                "name": name,
+                "detail": memo.description(),
+                "kind": SYMBOL_KIND_STRING,
+                "range": {
+                    "start": {"line": span.start_line - 1, "character": 0},
+                    "end": line_range(lines, end)["end"]
+                },
+                "selectionRange": line_range(lines, span.start_line - 1),
+                "children": symbols(memo.children(), lines)
+            }))
+        })
+        .collect()
+}
+
+fn collect_tag_values(memos: &[Memo], tag: &str, values: &mut BTreeSet<String>) {
+    for memo in memos {
+        if let Some(found) = memo.metadata().get(tag) {
+            values.extend(found.iter().cloned());
+        }
+        collect_tag_values(memo.children(), tag, values);
+    }
+}
+
+/// Target of the `[[wiki-link]]` or `[label](url)` link under `offset`, as written
+fn link_at(line: &str, offset: usize) -> Option<(LinkKind, &str)> {
+    for (start, _) in line.match_indices("[[") {
+        let Some(len) = line[start..].find("]]") else {
+            continue;
+        };
+        if (start..=start + len + 2).contains(&offset) {
+            let inner = &line[start + 2..start + len];
+            let target = inner.split_once('|').map_or(inner, |(target, _)| target);
+            return Some((LinkKind::Wiki, target.trim()));
+        }
+    }
+    for (middle, _) in line.match_indices("](") {
+        let (Some(open), Some(len)) = (line[..middle].rfind('['), line[middle..].find(')')) else {
+            continue;
+        };
+        if (open..=middle + len).contains(&offset) {
+            let url = line[middle + 2..middle + len].trim();
+            return Some((LinkKind::classify(url), url));
+        }
+    }
+    None
+}
+
+struct Server {
+    root: PathBuf,
+    /// Keep the root given on the command line over the client's workspace
+    fixed_root: bool,
+    options: ParseOptions,
+    /// Open documents by URI
+    documents: HashMap<String, String>,
+    /// The root's dictionaries, when spellchecking is enabled
+    speller: Option<Spellchecker>,
+}
+
+impl Server {
+    fn set_root(&mut self, root: PathBuf) {
+        self.root = root.canonicalize().unwrap_or(root);
+        // A missing dictionary shouldn't take the other diagnostics down with it
+        self.speller = Spellchecker::for_root(&self.root).ok().flatten();
+    }
+
+    /// A document's path relative to the root; `None` outside of it
+    fn relative_path(&self, uri: &str) -> Option<String> {
+        let path = uri_to_path(uri)?;
+        let path = path.canonicalize().unwrap_or(path);
+        let relative = path
+            .strip_prefix(&self.root)
+            .ok()?
+            .to_str()?
+            .replace('\\', "/");
+        resolve_memo_path(&self.root, &relative)?;
+        Some(relative)
+    }
+
+    /// The open document's text, or the file on disk
+    fn text(&self, uri: &str) -> Option<String> {
+        match self.documents.get(uri) {
+            Some(text) => Some(text.clone()),
+            None => std::fs::read_to_string(uri_to_path(uri)?).ok(),
+        }
+    }
+
+    fn files(&self) -> Vec<String> {
+        list_memo_files(&self.root).unwrap_or_default()
+    }
+
+    /// Answer a request; `None` for methods the server doesn't know
+    fn request(&mut self, method: &str, params: &Value) -> Option<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let position = &params["position"];
+        let line = position["line"].as_u64().unwrap_or(0) as usize;
+        let character = position["character"].as_u64().unwrap_or(0) as usize;
+        match method {
+            "initialize" => {
+                if !self.fixed_root
+                    && let Some(root) = params["rootUri"].as_str().and_then(uri_to_path)
+                {
+                    self.set_root(root);
+                }
+                Some(json!({
+                    "capabilities": {
+                        "textDocumentSync": 1,
+                        "documentSymbolProvider": true,
+                        "definitionProvider": true,
+                        "completionProvider": {"triggerCharacters": ["[", "<", ">"]}
+                    },
+                    "serverInfo": {"name": "fmemo", "version": env!("CARGO_PKG_VERSION")}
+                }))
+            }
+            "shutdown" => Some(Value::Null),
+            "textDocument/documentSymbol" => {
+                let text = self.text(uri).unwrap_or_default();
+                let lines: Vec<&str> = text.lines().collect();
+                let document = parse_document(&text, &self.options);
+                Some(json!(symbols(&document.memos, &lines)))
+            }
+            "textDocument/completion" => Some(json!(self.completion(uri, line, character))),
+            "textDocument/definition" => {
+                Some(self.definition(uri, line, character).unwrap_or_default())
+            }
+            _ => None,
+        }
+    }
+
+    fn completion(&self, uri: &str, line: usize, character: usize) -> Vec<Value> {
+        let text = self.text(uri).unwrap_or_default();
+        let line = text.lines().nth(line).unwrap_or_default();
+        let prefix = &line[..byte_offset(line, character)];
+
+        if let Some(start) = prefix.rfind("[[")
+            && !prefix[start..].contains("]]")
+        {
+            return self
+                .files()
+                .iter()
+                .map(|file| {
+                    json!({"label": wiki_name(file), "kind": COMPLETION_KIND_FILE, "detail": file})
+                })
+                .collect();
+        }
+
+        let Some(open) = prefix.rfind('<') else {
+            return Vec::new();
+        };
+        let inside = &prefix[open + 1..];
+        match inside.split_once('>') {
+            // `<ta` - tag names
+            None if !inside.starts_with('/') => self
+                .options
+                .metadata_tags
+                .iter()
+                .map(|tag| {
+                    json!({
+                        "label": tag,
+                        "kind": COMPLETION_KIND_KEYWORD,
+                        "insertText": format!("{}>$1</{}>", tag, tag),
+                        "insertTextFormat": INSERT_TEXT_FORMAT_SNIPPET
+                    })
+                })
+                .collect(),
+            // `<tag>val` - values used with that tag anywhere below the root
+            Some((tag, _)) if self.options.metadata_tags.iter().any(|known| known == tag) => {
+                let mut values = BTreeSet::new();
+                for file in self.files() {
+                    if let Ok(content) = std::fs::read_to_string(self.root.join(&file)) {
+                        let document = parse_document(&content, &self.options);
+                        collect_tag_values(&document.memos, tag, &mut values);
+                    }
+                }
+                values
+                    .into_iter()
+                    .map(|value| json!({"label": value, "kind": COMPLETION_KIND_VALUE}))
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn definition(&self, uri: &str, line: usize, character: usize) -> Option<Value> {
+        let text = self.text(uri)?;
+        let line = text.lines().nth(line)?;
+        let (kind, target) = link_at(line, byte_offset(line, character))?;
+        let (path, anchor) = match target.split_once('#') {
+            Some((path, anchor)) => (path, Some(anchor)),
+            None => (target, None),
+        };
+
+        let current = self.relative_path(uri);
+        let file = match kind {
+            LinkKind::External => return None,
+            _ if path.is_empty() => current?,
+            LinkKind::Wiki => resolve_link(&self.files(), "", kind, target)?,
+            LinkKind::Internal => resolve_link(&self.files(), current.as_deref()?, kind, target)?,
+        };
+        let target_uri = path_to_uri(&self.root.join(&file));
+        let target_line = anchor
+            .and_then(|anchor| {
+                let content = self.text(&target_uri)?;
+                anchor_line(&parse_document(&content, &self.options).memos, anchor)
+            })
+            .unwrap_or(0);
+        Some(json!({
+            "uri": target_uri,
+            "range": {
+                "start": {"line": target_line, "character": 0},
+                "end": {"line": target_line, "character": 0}
+            }
+        }))
+    }
+
+    /// Handle a notification, returning the notifications to send back
+    fn notify(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_string());
+            }
+            "textDocument/didChange" => {
+                let Some(text) = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                else {
+                    return Vec::new();
+                };
+                self.documents.insert(uri.clone(), text.to_string());
+            }
+            "textDocument/didSave" => {}
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish_diagnostics(&uri, Vec::new())];
+            }
+            _ => return Vec::new(),
+        }
+        vec![self.diagnostics(&uri)]
+    }
+
+    fn diagnostics(&self, uri: &str) -> Value {
+        let text = self.text(uri).unwrap_or_default();
+        let lines: Vec<&str> = text.lines().collect();
+        let mut issues = match self.relative_path(uri) {
+            Some(relative) => lint_source(&text, &relative, Some(&self.root)),
+            None => lint_source(&text, "", None),
+        };
+        if let Some(speller) = &self.speller {
+            issues.extend(spelling_issues(&text, speller));
+        }
+        let diagnostics = issues
+            .into_iter()
+            .map(|issue| {
+                json!({
+                    "range": line_range(&lines, issue.line.saturating_sub(1)),
+                    "severity": SEVERITY_WARNING,
+                    "source": "fmemo",
+                    "code": issue.rule,
+                    "message": issue.message
+                })
+            })
+            .collect();
+        publish_diagnostics(uri, diagnostics)
+    }
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {"uri": uri, "diagnostics": diagnostics}
+    })
+}
+
+/// Read one message; `None` at the end of the input
+fn read_message(input: &mut impl BufRead) -> std::io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Message without Content-Length",
+        )
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> std::io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// Serve one client until it sends `exit` or closes the input. Without a `root`, the
+/// client's workspace folder (or else the working directory) holds the memos.
+pub fn run(
+    root: Option<PathBuf>,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> std::io::Result<()> {
+    let mut server = Server {
+        root: PathBuf::new(),
+        fixed_root: root.is_some(),
+        options: ParseOptions::default(),
+        documents: HashMap::new(),
+        speller: None,
+    };
+    server.set_root(root.unwrap_or_else(|| PathBuf::from(".")));
+
+    while let Some(message) = read_message(&mut input)? {
+        // Responses to requests the server never sends
+        let Some(method) = message["method"].as_str() else {
+            continue;
+        };
+        let params = &message["params"];
+        match message.get("id") {
+            Some(id) => {
+                let response = match server.request(method, params) {
+                    Some(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                    None => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {"code": METHOD_NOT_FOUND, "message": format!("Unknown method {}", method)}
+                    }),
+                };
+                write_message(&mut output, &response)?;
+            }
+            None if method == "exit" => break,
+            None => {
+                for notification in server.notify(method, params) {
+                    write_message(&mut output, &notification)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{path_to_uri, run};
+    use serde_json::{Value, json};
+    use std::fs;
+    use std::io::BufRead;
+    use tempfile::TempDir;
+
+    fn frame(message: Value) -> String {
+        let body = message.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    /// Run a session and return every message the server sent
+    fn session(root: &std::path::Path, messages: Vec<Value>) -> Vec<Value> {
+        let input: String = messages.into_iter().map(frame).collect();
+        let mut output = Vec::new();
+        run(Some(root.to_path_buf()), input.as_bytes(), &mut output).unwrap();
+
+        let mut reader = output.as_slice();
+        let mut sent = Vec::new();
+        while let Some(message) = super::read_message(&mut reader).unwrap() {
+            sent.push(message);
+        }
+        assert!(reader.fill_buf().unwrap().is_empty());
+        sent
+    }
+
+    fn request(id: u64, method: &str, params: Value) -> Value {
+        json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params})
+    }
+
+    #[test]
+    fn test_session() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join("team")).unwrap();
+        fs::write(
+            root.join("team/plan.fmemo"),
+            "# Plan\n## Next Steps\n<status>blocked</status>",
+        )
+        .unwrap();
+        let uri = path_to_uri(&root.join("notes.fmemo"));
+        let text = "# Notes\n## Links\nsee [[plan#next-steps]] and [x](team/plan.fmemo)\n[[\n<status>\n[gone](gone.fmemo)";
+        let position = |line: u64, character: u64| json!({"textDocument": {"uri": uri}, "position": {"line": line, "character": character}});
+
+        let sent = session(
+            &root,
+            vec![
+                request(1, "initialize", json!({"capabilities": {}})),
+                json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}),
+                json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                    "textDocument": {"uri": uri, "languageId": "fmemo", "version": 1, "text": text}
+                }}),
+                request(
+                    2,
+                    "textDocument/documentSymbol",
+                    json!({"textDocument": {"uri": uri}}),
+                ),
+                request(3, "textDocument/definition", position(2, 10)),
+                request(4, "textDocument/definition", position(2, 30)),
+                request(5, "textDocument/completion", position(3, 2)),
+                request(6, "textDocument/completion", position(4, 8)),
+                request(7, "unknown/method", json!({})),
+                request(8, "shutdown", Value::Null),
+                json!({"jsonrpc": "2.0", "method": "exit"}),
+            ],
+        );
+
+        assert!(sent[0]["result"]["capabilities"]["documentSymbolProvider"] == true);
+
+        // Lint issues arrive as diagnostics after opening
+        let diagnostics = &sent[1]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics[0]["range"]["start"]["line"], 5);
+        assert_eq!(diagnostics[0]["code"], "broken_link");
+
+        let symbols = &sent[2]["result"];
+        assert_eq!(symbols[0]["name"], "Notes");
+        assert_eq!(symbols[0]["range"]["end"]["line"], 5);
+        assert_eq!(symbols[0]["children"][0]["name"], "Links");
+        assert_eq!(
+            symbols[0]["children"][0]["selectionRange"]["start"]["line"],
+            1
+        );
+
+        let plan_uri = path_to_uri(&root.join("team/plan.fmemo"));
+        assert_eq!(sent[3]["result"]["uri"], plan_uri);
+        assert_eq!(sent[3]["result"]["range"]["start"]["line"], 1);
+        assert_eq!(sent[4]["result"]["uri"], plan_uri);
+        assert_eq!(sent[4]["result"]["range"]["start"]["line"], 0);
+
+        assert_eq!(sent[5]["result"][0]["label"], "team/plan");
+        assert_eq!(sent[6]["result"][0]["label"], "blocked");
+        assert_eq!(sent[7]["error"]["code"], -32601);
+        assert_eq!(sent[8]["result"], Value::Null);
+        assert_eq!(sent.len(), 9);
+    }
+}
@@ -0,0 +1,151 @@
+//! Memo files too large to copy around: above `MAP_THRESHOLD` a plain memo file is
+//! memory-mapped instead of read into a `String`, so a huge export dropped into the vault is
+//! parsed straight from the page cache and its Markdown is served from the map.
+//!
+//! Only plain files are mapped. Encrypted files have to be decrypted into memory anyway, and
+//! below a published root a file with private regions is read with them cut out
+//! (`publish::strip_private`), which needs a copy too.
+//!
+//! A map of a file that shrinks while it's read faults with `SIGBUS`, and editors rewrite memo
+//! files in place. So the file itself is never mapped: it's copied on read into a private
+//! snapshot next to it, unlinked right away so no other program can open it, and the snapshot
+//! is mapped. The copy stays in the kernel (`copy_file_range`, a reflink on filesystems that
+//! have them) and the snapshot on the file's own filesystem, so memory use stays flat. Where
+//! files can't be unlinked while open (not Unix), large files are read into memory instead.
+
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+/// Files of at least this many bytes are mapped
+pub const MAP_THRESHOLD: u64 = 4 * 1024 * 1024;
+
+/// The text of a memo file, in memory or mapped
+pub enum MemoText {
+    Owned(String),
+    /// Checked to be UTF-8 when mapped
+    Mapped(Mmap),
+}
+
+impl Deref for MemoText {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match self {
+            MemoText::Owned(text) => text,
+            // SAFETY: `map_text` only builds a `Mapped` after checking the bytes are UTF-8
+            MemoText::Mapped(map) => unsafe { std::str::from_utf8_unchecked(map) },
+        }
+    }
+}
+
+impl MemoText {
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, MemoText::Mapped(_))
+    }
+
+    /// The text as a response body; a map is handed over without copying
+    pub fn into_bytes(self) -> bytes::Bytes {
+        match self {
+            MemoText::Owned(text) => bytes::Bytes::from(text),
+            MemoText::Mapped(map) => bytes::Bytes::from_owner(map),
+        }
+    }
+}
+
+impl From<MemoText> for String {
+    fn from(text: MemoText) -> Self {
+        match text {
+            MemoText::Owned(text) => text,
+            MemoText::Mapped(_) => text.to_string(),
+        }
+    }
+}
+
+/// The plain memo file at `path`, mapped when it has at least `threshold` bytes
+pub fn read_text(path: &Path, threshold: u64) -> io::Result<MemoText> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    if len == 0 || len < threshold {
+        return io::read_to_string(file).map(MemoText::Owned);
+    }
+    map_text(path, &file)
+}
+
+/// A copy of `file` (at `path`) that only this process can reach
+#[cfg(unix)]
+fn snapshot(path: &Path, mut file: &File) -> io::Result<File> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Path has no file name"))?;
+    let (copy_path, mut copy) = loop {
+        let copy_path = path.with_file_name(format!(
+            ".{}.{}.map",
+            name.to_string_lossy(),
+            crate::crypt::to_hex(&crate::crypt::random_bytes::<8>()?)
+        ));
+        match std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&copy_path)
+        {
+            Ok(copy) => break (copy_path, copy),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    };
+    std::fs::remove_file(&copy_path)?;
+    io::copy(&mut file, &mut copy)?;
+    Ok(copy)
+}
+
+#[cfg(unix)]
+fn map_text(path: &Path, file: &File) -> io::Result<MemoText> {
+    let snapshot = snapshot(path, file)?;
+    // SAFETY: the snapshot is unlinked, so nothing but this process can change its size
+    let map = unsafe { Mmap::map(&snapshot)? };
+    std::str::from_utf8(&map).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(MemoText::Mapped(map))
+}
+
+#[cfg(not(unix))]
+fn map_text(_path: &Path, file: &File) -> io::Result<MemoText> {
+    io::read_to_string(file).map(MemoText::Owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_read_text() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("big.fmemo");
+        fs::write(&path, "# Big\nbody\n").unwrap();
+
+        let small = read_text(&path, MAP_THRESHOLD).unwrap();
+        assert!(!small.is_mapped());
+        let mapped = read_text(&path, 1).unwrap();
+        assert_eq!(&*mapped, "# Big\nbody\n");
+        #[cfg(unix)]
+        {
+            assert!(mapped.is_mapped());
+            // The snapshot is gone from the directory already
+            assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+        }
+        // Truncating the file in place doesn't reach the map
+        fs::write(&path, "").unwrap();
+        assert_eq!(&*mapped, "# Big\nbody\n");
+        assert_eq!(&mapped.into_bytes()[..], b"# Big\nbody\n");
+
+        fs::write(&path, b"# Bad \xff\n").unwrap();
+        let error = read_text(&path, 1).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,311 @@
+//! Model Context Protocol server behind `fmemo mcp`: tools for AI assistants to list,
+//! search, read and write the memos below a root, over stdio.
+//!
+//! Messages are JSON-RPC, one per line. Paths are checked like the HTTP API's, so
+//! the tools can't reach files outside the root, hidden files or non-memo files.
+
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+use serde_json::{Value, json};
+
+use crate::schema::WriteFileRequest;
+use crate::server::{
+    INVALID_MEMO_PATH, list_memo_files, read_fmemo_file, resolve_memo_path, write_fmemo_file,
+};
+
+/// Protocol revision answered when the client asks for one we don't know
+const PROTOCOL_VERSION: &str = "2025-03-26";
+const SUPPORTED_VERSIONS: &[&str] = &["2024-11-05", "2025-03-26", "2025-06-18"];
+
+const PARSE_ERROR: i32 = -32700;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+fn tool(name: &str, description: &str, properties: Value, required: &[&str]) -> Value {
+    json!({
+        "name": name,
+        "description": description,
+        "inputSchema": {"type": "object", "properties": properties, "required": required}
+    })
+}
+
+fn tools(read_only: bool) -> Vec<Value> {
+    let mut tools = vec![
+        tool(
+            "list_tree",
+            "List the memo files (.fmemo and .md), as paths relative to the memo root",
+            json!({"dir": {"type": "string", "description": "Only list files below this directory"}}),
+            &[],
+        ),
+        tool(
+            "search_memos",
+            "Find memos whose title, description or content contain all the words of the query (case-insensitive)",
+            json!({"query": {"type": "string"}}),
+            &["query"],
+        ),
+        tool(
+            "read_memo",
+            "Read a memo file as Markdown",
+            json!({"path": {"type": "string", "description": "Path relative to the memo root"}}),
+            &["path"],
+        ),
+    ];
+    if !read_only {
+        tools.push(tool(
+            "write_memo",
+            "Create or replace a memo file with Markdown content. Read it first to keep what's there.",
+            json!({
+                "path": {"type": "string", "description": "Path relative to the memo root, ending in .fmemo, .md or .fmemox"},
+                "content": {"type": "string"}
+            }),
+            &["path", "content"],
+        ));
+    }
+    tools
+}
+
+struct Server {
+    root: PathBuf,
+    read_only: bool,
+}
+
+impl Server {
+    /// Run a tool; `Err` is reported to the model as a failed call
+    fn call_tool(&self, name: &str, arguments: &Value) -> Result<String, String> {
+        let argument = |key: &str| {
+            arguments[key]
+                .as_str()
+                .ok_or_else(|| format!("Missing string argument '{}'", key))
+        };
+        let memo_path = |path: &str| {
+            resolve_memo_path(&self.root, path.trim_start_matches('/'))
+                .ok_or_else(|| format!("Invalid path '{}': {}", path, INVALID_MEMO_PATH))
+        };
+
+        match name {
+            "list_tree" => {
+                let dir = arguments["dir"].as_str().unwrap_or("").trim_matches('/');
+                let files: Vec<String> = list_memo_files(&self.root)
+                    .map_err(|e| e.to_string())?
+                    .into_iter()
+                    .filter(|file| {
+                        dir.is_empty()
+                            || file
+                                .strip_prefix(dir)
+                                .is_some_and(|rest| rest.starts_with('/'))
+                    })
+                    .collect();
+                Ok(files.join("\n"))
+            }
+            "search_memos" => {
+                let hits = crate::search::search(&self.root, argument("query")?)
+                    .map_err(|e| e.to_string())?;
+                if hits.is_empty() {
+                    return Ok("No matching memos".to_string());
+                }
+                Ok(hits
+                    .iter()
+                    .map(|hit| {
+                        format!("{}:{}: {} - {}", hit.file, hit.line, hit.title, hit.snippet)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+            "read_memo" => {
+                let path = memo_path(argument("path")?)?;
+                crate::crypt::read_memo(&path).map_err(|e| e.to_string())
+            }
+            "write_memo" if !self.read_only => {
+                let path = argument("path")?;
+                let file_path = memo_path(path)?;
+                let request = WriteFileRequest {
+                    content: argument("content")?.to_string(),
+                    auto_stamp: false,
+                };
+                write_fmemo_file(&file_path, &request)
+                    .and_then(|_| read_fmemo_file(&file_path))
+                    .map_err(|e| e.to_string())?;
+                Ok(format!("Wrote {}", path))
+            }
+            _ => Err(format!("Unknown tool '{}'", name)),
+        }
+    }
+
+    /// Answer a request; `Err((code, message))` for a JSON-RPC error
+    fn request(&self, method: &str, params: &Value) -> Result<Value, (i32, String)> {
+        match method {
+            "initialize" => {
+                let requested = params["protocolVersion"].as_str().unwrap_or_default();
+                let version = if SUPPORTED_VERSIONS.contains(&requested) {
+                    requested
+                } else {
+                    PROTOCOL_VERSION
+                };
+                Ok(json!({
+                    "protocolVersion": version,
+                    "capabilities": {"tools": {}},
+                    "serverInfo": {"name": "fmemo", "version": env!("CARGO_PKG_VERSION")}
+                }))
+            }
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({"tools": tools(self.read_only)})),
+            "tools/call" => {
+                let name = params["name"]
+                    .as_str()
+                    .ok_or((INVALID_PARAMS, "Missing tool name".to_string()))?;
+                let (text, is_error) = match self.call_tool(name, &params["arguments"]) {
+                    Ok(text) => (text, false),
+                    Err(message) => (message, true),
+                };
+                Ok(json!({"content": [{"type": "text", "text": text}], "isError": is_error}))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+        }
+    }
+}
+
+fn error(id: &Value, code: i32, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+/// Serve one client until it closes the input. With `read_only`, `write_memo` isn't offered.
+pub fn run(
+    root: PathBuf,
+    read_only: bool,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> std::io::Result<()> {
+    let server = Server { root, read_only };
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Err(e) => error(&Value::Null, PARSE_ERROR, &e.to_string()),
+            // Notifications (`notifications/initialized`, ...) need no answer
+            Ok(message) if message.get("id").is_none() => continue,
+            // Responses to requests the server never sends
+            Ok(message) if message.get("method").is_none() => continue,
+            Ok(message) => {
+                let id = &message["id"];
+                let method = message["method"].as_str().unwrap_or_default();
+                match server.request(method, &message["params"]) {
+                    Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                    Err((code, message)) => error(id, code, &message),
+                }
+            }
+        };
+        writeln!(output, "{}", response)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+    use serde_json::{Value, json};
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Run a session and return the responses
+    fn session(root: &std::path::Path, read_only: bool, messages: &[Value]) -> Vec<Value> {
+        let input: String = messages.iter().map(|m| format!("{}\n", m)).collect();
+        let mut output = Vec::new();
+        run(root.to_path_buf(), read_only, input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn call(id: u64, name: &str, arguments: Value) -> Value {
+        json!({"jsonrpc": "2.0", "id": id, "method": "tools/call", "params": {"name": name, "arguments": arguments}})
+    }
+
+    fn text(response: &Value) -> &str {
+        response["result"]["content"][0]["text"].as_str().unwrap()
+    }
+
+    #[test]
+    fn test_tools() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("work")).unwrap();
+        fs::write(
+            temp_dir.path().join("work/plan.fmemo"),
+            "# Plan\nship the mcp server",
+        )
+        .unwrap();
+        fs::write(temp_dir.path().join("secret.txt"), "no").unwrap();
+
+        let responses = session(
+            temp_dir.path(),
+            false,
+            &[
+                json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {"protocolVersion": "2024-11-05"}}),
+                json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+                json!({"jsonrpc": "2.0", "id": 2, "method": "tools/list"}),
+                call(
+                    3,
+                    "write_memo",
+                    json!({"path": "work/notes.md", "content": "# Notes\nmcp rocks"}),
+                ),
+                call(4, "list_tree", json!({"dir": "work"})),
+                call(5, "search_memos", json!({"query": "MCP"})),
+                call(6, "read_memo", json!({"path": "work/notes.md"})),
+                call(7, "read_memo", json!({"path": "../secret.txt"})),
+                call(8, "read_memo", json!({"path": "secret.txt"})),
+                json!({"jsonrpc": "2.0", "id": 9, "method": "resources/list"}),
+            ],
+        );
+
+        assert_eq!(responses[0]["result"]["protocolVersion"], "2024-11-05");
+        assert_eq!(responses[1]["result"]["tools"].as_array().unwrap().len(), 4);
+        assert_eq!(text(&responses[2]), "Wrote work/notes.md");
+        assert_eq!(text(&responses[3]), "work/notes.md\nwork/plan.fmemo");
+        assert_eq!(
+            text(&responses[4]),
+            "work/notes.md:1: Notes - mcp rocks\nwork/plan.fmemo:1: Plan - ship the mcp server"
+        );
+        assert_eq!(text(&responses[5]), "# Notes\nmcp rocks");
+        assert_eq!(responses[6]["result"]["isError"], true);
+        assert_eq!(responses[7]["result"]["isError"], true);
+        assert_eq!(responses[8]["error"]["code"], -32601);
+        assert_eq!(responses.len(), 9);
+
+        let mut output = Vec::new();
+        run(
+            temp_dir.path().to_path_buf(),
+            false,
+            "not json\n".as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        let response: Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(response["error"]["code"], -32700);
+    }
+
+    #[test]
+    fn test_read_only_hides_write() {
+        let temp_dir = TempDir::new().unwrap();
+        let responses = session(
+            temp_dir.path(),
+            true,
+            &[
+                json!({"jsonrpc": "2.0", "id": 1, "method": "tools/list"}),
+                call(
+                    2,
+                    "write_memo",
+                    json!({"path": "a.fmemo", "content": "# A"}),
+                ),
+            ],
+        );
+        let tools = responses[0]["result"]["tools"].as_array().unwrap();
+        assert!(tools.iter().all(|tool| tool["name"] != "write_memo"));
+        assert_eq!(responses[1]["result"]["isError"], true);
+        assert!(!temp_dir.path().join("a.fmemo").exists());
+    }
+}
@@ -0,0 +1,138 @@
+//! Announcing a running server on the local network with mDNS / DNS-SD, so other
+//! devices (a phone app, another fmemo) can find it without knowing its address.
+//!
+//! The service type is `_fmemo._tcp`. The instance is named after the root directory and
+//! the machine, and its TXT record carries the root's name and the fmemo version.
+
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+
+/// DNS-SD service type of fmemo servers
+pub const SERVICE_TYPE: &str = "_fmemo._tcp.local.";
+
+/// A registered service; dropping it withdraws the announcement
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl std::fmt::Debug for Advertisement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Advertisement")
+            .field("fullname", &self.fullname)
+            .finish()
+    }
+}
+
+impl Advertisement {
+    /// Full DNS-SD name, e.g. `notes on laptop._fmemo._tcp.local.`
+    pub fn fullname(&self) -> &str {
+        &self.fullname
+    }
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        let _ = self.daemon.unregister(&self.fullname);
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Name of the served directory, `fmemo` when it has none (e.g. `/`)
+pub fn root_name(root: &Path) -> String {
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    root.file_name()
+        .and_then(|name| name.to_str())
+        .map(str::to_string)
+        .unwrap_or_else(|| "fmemo".to_string())
+}
+
+/// Name of this machine as far as it can be told, for the instance and host names
+fn machine_name() -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+/// A DNS label: letters, digits and `-` only
+fn dns_label(name: &str) -> String {
+    let label: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let label = label.trim_matches('-');
+    if label.is_empty() {
+        "fmemo".to_string()
+    } else {
+        label.to_string()
+    }
+}
+
+/// Instance name shown to users browsing for servers (at most 63 bytes)
+fn instance_name(root_name: &str, machine: &str) -> String {
+    let mut name = format!("{} on {}", root_name, machine);
+    while name.len() > 63 {
+        name.pop();
+    }
+    name
+}
+
+/// Announce a server listening on `addr` that serves `root`. An unspecified address
+/// (`0.0.0.0`) is announced with every address of the machine.
+pub fn advertise(root: &Path, addr: SocketAddr) -> std::io::Result<Advertisement> {
+    let error = |e: mdns_sd::Error| std::io::Error::other(format!("mDNS: {}", e));
+    let root_name = root_name(root);
+    let machine = machine_name();
+    let host_name = format!("{}.local.", dns_label(&machine));
+    let properties = [
+        ("root", root_name.as_str()),
+        ("version", env!("CARGO_PKG_VERSION")),
+    ];
+    let ip: Vec<IpAddr> = if addr.ip().is_unspecified() {
+        Vec::new()
+    } else {
+        vec![addr.ip()]
+    };
+    let mut info = ServiceInfo::new(
+        SERVICE_TYPE,
+        &instance_name(&root_name, &machine),
+        &host_name,
+        &ip[..],
+        addr.port(),
+        &properties[..],
+    )
+    .map_err(error)?;
+    if ip.is_empty() {
+        info = info.enable_addr_auto();
+    }
+    let fullname = info.get_fullname().to_string();
+    let daemon = ServiceDaemon::new().map_err(error)?;
+    daemon.register(info).map_err(error)?;
+    Ok(Advertisement { daemon, fullname })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dns_label, instance_name, root_name};
+    use std::path::Path;
+
+    #[test]
+    fn test_names() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path().join("my notes");
+        std::fs::create_dir(&root).unwrap();
+        assert_eq!(root_name(&root), "my notes");
+        assert_eq!(root_name(Path::new("/")), "fmemo");
+
+        assert_eq!(dns_label("Kai's MacBook.local"), "Kai-s-MacBook-local");
+        assert_eq!(dns_label("..."), "fmemo");
+        assert_eq!(instance_name("notes", "laptop"), "notes on laptop");
+        assert_eq!(instance_name(&"x".repeat(80), "laptop").len(), 63);
+    }
+}
@@ -0,0 +1,401 @@
+//! Merging one memo file into another, for `POST /api/files/merge`, and splitting one into a
+//! file per top-level memo, for `POST /api/files/{path}/split`.
+//!
+//! Memos are cut from the text by their source spans, so their content stays exactly as
+//! written; only heading levels change. When merging, text before the source's first
+//! heading (such as front matter) is left out; when splitting, it is copied into every new
+//! file.
+
+use std::io;
+use std::path::Path;
+
+use crate::parser::{ParseOptions, normalize_source, parse_document, subtree_markdown};
+use crate::schema::{Memo, MergeRequest, SplitRequest};
+use crate::server::{INVALID_MEMO_PATH, resolve_memo_path};
+use crate::template::slugify;
+use crate::trash::TrashEntry;
+
+/// How the source's memos are placed in the target
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeMode {
+    /// After the target's content
+    #[default]
+    Append,
+    /// Into the target's top-level memo with the same slug, if there is one; after the
+    /// target's content otherwise
+    Interleave,
+}
+
+/// What happens to the source file after a merge
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceAction {
+    /// Moved to the trash, so it can be restored
+    #[default]
+    Trash,
+    Delete,
+    Keep,
+}
+
+/// Result of `merge_files`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergedFiles {
+    /// The target's content before and after the merge
+    pub previous: String,
+    pub content: String,
+    /// Where the source went, with `SourceAction::Trash`
+    pub trash: Option<TrashEntry>,
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Number of `#` of a memo's heading
+fn heading_level(memo: &Memo) -> isize {
+    memo.level().level() as isize + 1
+}
+
+/// Last line of a memo and its descendants
+fn subtree_end(memo: &Memo) -> usize {
+    memo.children()
+        .iter()
+        .map(subtree_end)
+        .chain(memo.span().map(|span| span.end_line))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Lines of a memo's span; an error for memos without one
+fn span_lines<'a>(lines: &'a [&'a str], memo: &Memo) -> io::Result<&'a [&'a str]> {
+    let missing = || invalid_input(format!("Memo '{}' has no source position", memo.title()));
+    let span = memo.span().ok_or_else(missing)?;
+    let start = span.start_line.checked_sub(1).ok_or_else(missing)?;
+    lines
+        .get(start..span.end_line.min(lines.len()))
+        .ok_or_else(missing)
+}
+
+/// A memo's heading moved `delta` levels down (up when negative)
+fn shift_heading(line: &str, memo: &Memo, delta: isize) -> io::Result<String> {
+    let level = heading_level(memo) + delta;
+    if !(1..=6).contains(&level) {
+        return Err(invalid_input(format!(
+            "Memo '{}' would need heading level {}",
+            memo.title(),
+            level
+        )));
+    }
+    let rest = line.trim_start_matches('#');
+    Ok(format!("{}{}", "#".repeat(level as usize), rest))
+}
+
+/// A memo and its children, headings moved by `delta`
+fn push_subtree(lines: &[&str], memo: &Memo, delta: isize, out: &mut String) -> io::Result<()> {
+    for (index, line) in span_lines(lines, memo)?.iter().enumerate() {
+        // The first line is the memo's heading, the only heading line in its span
+        if index == 0 {
+            out.push_str(&shift_heading(line, memo, delta)?);
+        } else {
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    for child in memo.children() {
+        push_subtree(lines, child, delta, out)?;
+    }
+    Ok(())
+}
+
+/// `target` with the memos of `source` merged in. With `level`, the source's top-level
+/// memos get headings of that level (1 for `#`) when appended; otherwise they keep theirs
+/// (`Append`) or take the level of the target's top-level memos (`Interleave`). An
+/// interleaved memo's text goes right after the matching memo's own text, its children
+/// after the matching memo's children.
+pub fn merge_content(
+    target: &str,
+    source: &str,
+    mode: MergeMode,
+    level: Option<u8>,
+) -> io::Result<String> {
+    let target = normalize_source(target);
+    let source = normalize_source(source);
+    let target_memos = parse_document(&target, &ParseOptions::default()).memos;
+    let source_memos = parse_document(&source, &ParseOptions::default()).memos;
+    let target_lines: Vec<&str> = target.lines().collect();
+    let source_lines: Vec<&str> = source.lines().collect();
+
+    let Some(top_level) = source_memos.iter().map(heading_level).min() else {
+        return Err(invalid_input("The source has no memos".to_string()));
+    };
+    let appended_level = match (level, mode) {
+        (Some(level), _) => level as isize,
+        (None, MergeMode::Append) => top_level,
+        (None, MergeMode::Interleave) => target_memos.first().map_or(top_level, heading_level),
+    };
+
+    // Chunks to insert after a (1-based) target line
+    let mut insertions: Vec<(usize, String)> = Vec::new();
+    let mut appended = String::new();
+    for memo in &source_memos {
+        let matching = match mode {
+            MergeMode::Append => None,
+            MergeMode::Interleave => target_memos
+                .iter()
+                .find(|target_memo| target_memo.anchor() == memo.anchor()),
+        };
+        match matching.and_then(|target_memo| Some((target_memo, target_memo.span()?))) {
+            Some((target_memo, span)) => {
+                let delta = heading_level(target_memo) - heading_level(memo);
+                let mut text = String::new();
+                for line in &span_lines(&source_lines, memo)?[1..] {
+                    text.push_str(line);
+                    text.push('\n');
+                }
+                insertions.push((span.end_line, text));
+                let mut children = String::new();
+                for child in memo.children() {
+                    push_subtree(&source_lines, child, delta, &mut children)?;
+                }
+                insertions.push((subtree_end(target_memo), children));
+            }
+            None => push_subtree(
+                &source_lines,
+                memo,
+                appended_level - top_level,
+                &mut appended,
+            )?,
+        }
+    }
+
+    let mut merged = String::new();
+    for (index, line) in target_lines.iter().enumerate() {
+        merged.push_str(line);
+        merged.push('\n');
+        for (_, text) in insertions.iter().filter(|(after, _)| *after == index + 1) {
+            merged.push_str(text);
+        }
+    }
+    if !appended.is_empty() {
+        let trimmed = merged.trim_end().len();
+        merged.truncate(trimmed);
+        if !merged.is_empty() {
+            merged.push_str("\n\n");
+        }
+        merged.push_str(&appended);
+    }
+    Ok(merged)
+}
+
+/// Merge the source file into the target file (both relative to the root), then trash,
+/// delete or keep the source. Errors: `InvalidInput` for bad paths, merging a file into
+/// itself or an encrypted file into a plain one, `NotFound` when either file is missing.
+pub fn merge_files(root: &Path, request: &MergeRequest) -> io::Result<MergedFiles> {
+    let (Some(source_path), Some(target_path)) = (
+        resolve_memo_path(root, &request.source),
+        resolve_memo_path(root, &request.target),
+    ) else {
+        return Err(invalid_input(INVALID_MEMO_PATH.to_string()));
+    };
+    if request.source == request.target {
+        return Err(invalid_input("Can't merge a file into itself".to_string()));
+    }
+    if crate::crypt::is_encrypted(&source_path) && !crate::crypt::is_encrypted(&target_path) {
+        return Err(invalid_input(
+            "An encrypted memo can only be merged into another encrypted one".to_string(),
+        ));
+    }
+
+    let source = crate::crypt::read_memo(&source_path)?;
+    let previous = crate::crypt::read_memo(&target_path)?;
+    let content = merge_content(&previous, &source, request.mode, request.level)?;
+    crate::crypt::write_memo(&target_path, &content)?;
+    let trash = match request.source_action {
+        SourceAction::Trash => Some(crate::trash::trash_file(root, &request.source)?),
+        SourceAction::Delete => {
+            std::fs::remove_file(&source_path)?;
+            None
+        }
+        SourceAction::Keep => None,
+    };
+    Ok(MergedFiles {
+        previous,
+        content,
+        trash,
+    })
+}
+
+/// Result of `split_file`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitFiles {
+    /// The new files relative to the root, with their content, in document order
+    pub files: Vec<(String, String)>,
+    /// Where the source went, with `SourceAction::Trash`
+    pub trash: Option<TrashEntry>,
+}
+
+/// Write each top-level memo of a file (relative to the root) to a new file in the same
+/// directory, named after its heading (`a-1.fmemo`, ... when taken) with headings raised
+/// so it starts at `#`, then trash, delete or keep the file. Errors: `InvalidInput` for a
+/// bad path or a file with fewer than two top-level memos, `NotFound` for a missing file.
+pub fn split_file(root: &Path, relative: &str, request: &SplitRequest) -> io::Result<SplitFiles> {
+    let Some(path) = resolve_memo_path(root, relative) else {
+        return Err(invalid_input(INVALID_MEMO_PATH.to_string()));
+    };
+    let content = crate::crypt::read_memo(&path)?;
+    let content = normalize_source(&content);
+    let memos = parse_document(&content, &ParseOptions::default()).memos;
+    if memos.len() < 2 {
+        return Err(invalid_input(
+            "Only files with two or more top-level memos can be split".to_string(),
+        ));
+    }
+    let preamble: String = match memos[0].span() {
+        Some(span) => content
+            .lines()
+            .take(span.start_line.saturating_sub(1))
+            .map(|line| format!("{}\n", line))
+            .collect(),
+        None => String::new(),
+    };
+
+    let dir = relative.rfind('/').map_or("", |idx| &relative[..=idx]);
+    let extension = Path::new(relative)
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("fmemo");
+    let mut files: Vec<(String, String)> = Vec::new();
+    for memo in &memos {
+        let markdown = subtree_markdown(&content, memo).ok_or_else(|| {
+            invalid_input(format!("Memo '{}' has no source position", memo.title()))
+        })?;
+        let mut new_path = format!("{}{}.{}", dir, slugify(memo.title()), extension);
+        let taken =
+            |path: &str| root.join(path).exists() || files.iter().any(|(file, _)| file == path);
+        if taken(&new_path) {
+            new_path = (1..)
+                .map(|n| format!("{}{}-{}.{}", dir, slugify(memo.title()), n, extension))
+                .find(|candidate| !taken(candidate))
+                .unwrap_or_default();
+        }
+        files.push((new_path, format!("{}{}", preamble, markdown)));
+    }
+
+    for (file, content) in &files {
+        crate::crypt::write_memo(&root.join(file), content)?;
+    }
+    let trash = match request.source_action {
+        SourceAction::Trash => Some(crate::trash::trash_file(root, relative)?),
+        SourceAction::Delete => {
+            std::fs::remove_file(&path)?;
+            None
+        }
+        SourceAction::Keep => None,
+    };
+    Ok(SplitFiles { files, trash })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_merge_content() {
+        let target = "---\ntags: [a]\n---\n# Setup\nOld steps\n## Linux\napt\n# Usage\nRun it\n";
+        let source = "# Setup\nNew steps\n## macOS\nbrew\n### Apple silicon\narm\n# FAQ\nAsk\n";
+
+        let appended = merge_content(target, source, MergeMode::Append, Some(2)).unwrap();
+        assert_eq!(
+            appended,
+            "---\ntags: [a]\n---\n# Setup\nOld steps\n## Linux\napt\n# Usage\nRun it\n\n\
+             ## Setup\nNew steps\n### macOS\nbrew\n#### Apple silicon\narm\n## FAQ\nAsk\n"
+        );
+
+        let interleaved = merge_content(target, source, MergeMode::Interleave, None).unwrap();
+        assert_eq!(
+            interleaved,
+            "---\ntags: [a]\n---\n# Setup\nOld steps\nNew steps\n## Linux\napt\n## macOS\nbrew\n\
+             ### Apple silicon\narm\n# Usage\nRun it\n\n# FAQ\nAsk\n"
+        );
+
+        assert!(merge_content(target, source, MergeMode::Append, Some(5)).is_err());
+        assert!(merge_content(target, "no headings", MergeMode::Append, None).is_err());
+    }
+
+    #[test]
+    fn test_merge_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::write(root.join("a.fmemo"), "# A\none").unwrap();
+        std::fs::write(root.join("b.fmemo"), "# B\ntwo").unwrap();
+        let request = |source_action| MergeRequest {
+            source: "b.fmemo".to_string(),
+            target: "a.fmemo".to_string(),
+            source_action,
+            ..Default::default()
+        };
+
+        let merged = merge_files(root, &request(SourceAction::Keep)).unwrap();
+        assert_eq!(merged.previous, "# A\none");
+        assert_eq!(merged.content, "# A\none\n\n# B\ntwo\n");
+        assert!(root.join("b.fmemo").exists());
+
+        let merged = merge_files(root, &request(SourceAction::Trash)).unwrap();
+        assert_eq!(merged.trash.unwrap().path, "b.fmemo");
+        assert!(!root.join("b.fmemo").exists());
+        assert_eq!(
+            merge_files(root, &request(SourceAction::Delete))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+
+        let mut itself = request(SourceAction::Keep);
+        itself.source = "a.fmemo".to_string();
+        assert_eq!(
+            merge_files(root, &itself).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_split_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir(root.join("notes")).unwrap();
+        std::fs::write(
+            root.join("notes/big.md"),
+            "---\ntags: [a]\n---\n## Setup\napt\n### Linux\nok\n## Usage\nRun\n## Setup\nAgain",
+        )
+        .unwrap();
+        std::fs::write(root.join("notes/usage.md"), "# Taken").unwrap();
+
+        let split = split_file(root, "notes/big.md", &SplitRequest::default()).unwrap();
+        let paths: Vec<&str> = split.files.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(
+            paths,
+            ["notes/setup.md", "notes/usage-1.md", "notes/setup-1.md"]
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("notes/setup.md")).unwrap(),
+            "---\ntags: [a]\n---\n# Setup\napt\n## Linux\nok\n"
+        );
+        assert_eq!(split.trash.unwrap().path, "notes/big.md");
+        assert!(!root.join("notes/big.md").exists());
+
+        assert_eq!(
+            split_file(root, "notes/usage.md", &SplitRequest::default())
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            split_file(root, "notes/big.md", &SplitRequest::default())
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
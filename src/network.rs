@@ -0,0 +1,89 @@
+//! The address other devices on the network reach the server at, and a QR code of it
+//! for opening the notes on a phone.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket};
+
+use qrcode::QrCode;
+use qrcode::render::unicode::Dense1x2;
+
+/// Address of the interface traffic to the internet leaves through. Connecting a UDP
+/// socket sends nothing; it only makes the OS pick a route and a source address.
+pub fn outbound_ip(ipv6: bool) -> Option<IpAddr> {
+    let (bind, target): (IpAddr, IpAddr) = if ipv6 {
+        (
+            Ipv6Addr::UNSPECIFIED.into(),
+            "2001:4860:4860::8888".parse().ok()?,
+        )
+    } else {
+        (
+            Ipv4Addr::UNSPECIFIED.into(),
+            Ipv4Addr::new(8, 8, 8, 8).into(),
+        )
+    };
+    let socket = UdpSocket::bind((bind, 0)).ok()?;
+    socket.connect((target, 80)).ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_unspecified() && !ip.is_loopback()).then_some(ip)
+}
+
+/// Address to give other devices for a server bound to `host`: the outbound interface's
+/// for an unspecified host (`0.0.0.0`), `None` for loopback
+pub fn lan_ip(host: IpAddr) -> Option<IpAddr> {
+    if host.is_loopback() {
+        None
+    } else if host.is_unspecified() {
+        outbound_ip(host.is_ipv6())
+    } else {
+        Some(host)
+    }
+}
+
+/// `http://<ip>:<port>/`, with brackets around IPv6 addresses
+pub fn url(ip: IpAddr, port: u16) -> String {
+    match ip {
+        IpAddr::V4(ip) => format!("http://{}:{}/", ip, port),
+        IpAddr::V6(ip) => format!("http://[{}]:{}/", ip, port),
+    }
+}
+
+/// QR code of `text` drawn with half-block characters, light on dark so it scans from
+/// a terminal with a dark background
+pub fn terminal_qr_code(text: &str) -> Option<String> {
+    let code = QrCode::new(text.as_bytes()).ok()?;
+    Some(
+        code.render::<Dense1x2>()
+            .dark_color(Dense1x2::Light)
+            .light_color(Dense1x2::Dark)
+            .quiet_zone(true)
+            .build(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lan_ip, terminal_qr_code, url};
+    use std::net::IpAddr;
+
+    #[test]
+    fn test_lan_ip_and_url() {
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        assert_eq!(lan_ip(localhost), None);
+        let lan: IpAddr = "192.168.1.20".parse().unwrap();
+        assert_eq!(lan_ip(lan), Some(lan));
+        assert_eq!(url(lan, 3030), "http://192.168.1.20:3030/");
+        assert_eq!(
+            url("fe80::1".parse().unwrap(), 3030),
+            "http://[fe80::1]:3030/"
+        );
+    }
+
+    #[test]
+    fn test_terminal_qr_code() {
+        let qr = terminal_qr_code("http://192.168.1.20:3030/").unwrap();
+        let lines: Vec<&str> = qr.lines().collect();
+        // Two modules per character row, so about half as many rows as columns
+        assert!(lines.len() > 10);
+        assert!(lines[0].chars().count() > lines.len());
+        assert!(qr.contains('█') || qr.contains('▀') || qr.contains('▄'));
+    }
+}
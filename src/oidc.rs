@@ -0,0 +1,530 @@
+//! Logging in through an OpenID Connect provider (or an OAuth2 one like GitHub), configured as
+//! `[oidc]` in `.fmemo/config.toml`.
+//!
+//! `GET /api/auth/oidc/login` redirects to the provider with a one-time `state`, which is also
+//! set as a short-lived cookie so only the browser that started the login can finish it, a
+//! PKCE (S256) challenge and a `nonce`. The provider's redirect back to
+//! `GET /api/auth/oidc/callback` carries a code, which is exchanged for an access token at the
+//! token endpoint together with the PKCE verifier. The user is then read from the userinfo
+//! endpoint rather than from the ID token, so no token signatures need checking: both answers
+//! come straight from the provider over TLS. An ID token in the answer must still carry the
+//! login's nonce. The login ends in the same session cookie as a local account's.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use sha2::{Digest, Sha256};
+
+use crate::config::{OidcConfig, Permission};
+use crate::crypt::{random_bytes, to_hex};
+
+/// How long a login may take at the provider
+pub const STATE_TTL: Duration = Duration::from_secs(10 * 60);
+/// At most this many logins are pending; the oldest gives way to a new one
+pub const MAX_PENDING: usize = 1024;
+/// The cookie tying a login to the browser that started it
+pub const STATE_COOKIE: &str = "fmemo_oidc_state";
+/// The callback route; the part of `redirect_url` before it is where users land afterwards
+pub const CALLBACK_PATH: &str = "api/auth/oidc/callback";
+
+/// The provider's endpoints
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoints {
+    pub authorization: String,
+    pub token: String,
+    pub userinfo: String,
+}
+
+/// The endpoints in an `openid-configuration` document, or those configured explicitly
+pub fn parse_discovery(config: &OidcConfig, document: &serde_json::Value) -> io::Result<Endpoints> {
+    let endpoint = |configured: &Option<String>, key: &str| {
+        configured
+            .clone()
+            .or_else(|| document[key].as_str().map(str::to_string))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("The provider's configuration has no {}", key),
+                )
+            })
+    };
+    Ok(Endpoints {
+        authorization: endpoint(&config.authorization_url, "authorization_endpoint")?,
+        token: endpoint(&config.token_url, "token_endpoint")?,
+        userinfo: endpoint(&config.userinfo_url, "userinfo_endpoint")?,
+    })
+}
+
+/// Where to send users once they're logged in: `redirect_url` without the callback path
+pub fn home_url(config: &OidcConfig) -> String {
+    config
+        .redirect_url
+        .strip_suffix(CALLBACK_PATH)
+        .unwrap_or("/")
+        .to_string()
+}
+
+/// The user name in a userinfo response; `None` without one or for an unverified email
+pub fn user_from_userinfo(config: &OidcConfig, userinfo: &serde_json::Value) -> Option<String> {
+    if config.user_claim == "email" && userinfo["email_verified"] == false {
+        return None;
+    }
+    match &userinfo[&config.user_claim] {
+        serde_json::Value::String(user) if !user.is_empty() => Some(user.clone()),
+        serde_json::Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+/// `Set-Cookie` value for a login's state; an empty state clears the cookie
+pub fn state_cookie(state: &str) -> String {
+    let max_age = if state.is_empty() {
+        0
+    } else {
+        STATE_TTL.as_secs()
+    };
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}",
+        STATE_COOKIE, state, max_age
+    )
+}
+
+/// The login state in a `Cookie` header
+pub fn state_from_cookie(cookie_header: &str) -> Option<&str> {
+    cookie_header.split(';').find_map(|pair| {
+        let (name, value) = pair.trim().split_once('=')?;
+        (name == STATE_COOKIE && !value.is_empty()).then_some(value)
+    })
+}
+
+/// The PKCE S256 challenge for `verifier`
+pub fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// The `nonce` claim of an ID token, read without checking its signature
+fn id_token_nonce(id_token: &str) -> Option<String> {
+    let payload = URL_SAFE_NO_PAD.decode(id_token.split('.').nth(1)?).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+    claims["nonce"].as_str().map(str::to_string)
+}
+
+/// A login started by `begin`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Login {
+    /// The provider URL to redirect the user to
+    pub url: String,
+    /// To be set as the state cookie
+    pub state: String,
+}
+
+#[derive(Debug)]
+struct PendingLogin {
+    started: Instant,
+    verifier: String,
+    nonce: String,
+}
+
+/// Logins in progress, and the provider's endpoints once known
+#[derive(Debug, Clone)]
+pub struct Oidc {
+    config: Arc<OidcConfig>,
+    endpoints: Arc<Mutex<Option<Endpoints>>>,
+    pending: Arc<Mutex<HashMap<String, PendingLogin>>>,
+}
+
+impl Oidc {
+    pub fn new(config: OidcConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            endpoints: Arc::default(),
+            pending: Arc::default(),
+        }
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    fn agent(&self) -> ureq::Agent {
+        ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(self.config.timeout_secs))
+            .build()
+    }
+
+    /// The endpoints, discovered from the issuer the first time unless all are configured
+    fn endpoints(&self) -> io::Result<Endpoints> {
+        if let Some(endpoints) = self.endpoints.lock().unwrap().clone() {
+            return Ok(endpoints);
+        }
+        let config = &self.config;
+        let configured = config.authorization_url.is_some()
+            && config.token_url.is_some()
+            && config.userinfo_url.is_some();
+        let document = match (&config.issuer, configured) {
+            (_, true) => serde_json::Value::Null,
+            (Some(issuer), false) => {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    issuer.trim_end_matches('/')
+                );
+                let response = self
+                    .agent()
+                    .get(&url)
+                    .call()
+                    .map_err(|e| io::Error::other(format!("OIDC discovery failed: {}", e)))?;
+                serde_json::from_str(&response.into_string()?)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?
+            }
+            (None, false) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "[oidc] needs an issuer or all of authorization_url, token_url and userinfo_url",
+                ));
+            }
+        };
+        let endpoints = parse_discovery(config, &document)?;
+        *self.endpoints.lock().unwrap() = Some(endpoints.clone());
+        Ok(endpoints)
+    }
+
+    /// Start a login
+    pub fn begin(&self) -> io::Result<Login> {
+        let endpoints = self.endpoints()?;
+        let state = to_hex(&random_bytes::<16>()?);
+        let verifier = to_hex(&random_bytes::<32>()?);
+        let nonce = to_hex(&random_bytes::<16>()?);
+        let challenge = pkce_challenge(&verifier);
+        let mut pending = self.pending.lock().unwrap();
+        let now = Instant::now();
+        pending.retain(|_, login| now.duration_since(login.started) < STATE_TTL);
+        if pending.len() >= MAX_PENDING
+            && let Some(oldest) = pending
+                .iter()
+                .min_by_key(|(_, login)| login.started)
+                .map(|(state, _)| state.clone())
+        {
+            pending.remove(&oldest);
+        }
+        pending.insert(
+            state.clone(),
+            PendingLogin {
+                started: now,
+                verifier,
+                nonce: nonce.clone(),
+            },
+        );
+        drop(pending);
+
+        let encode = |value: &str| utf8_percent_encode(value, NON_ALPHANUMERIC).to_string();
+        let separator = if endpoints.authorization.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        let url = format!(
+            "{}{}response_type=code&client_id={}&redirect_uri={}&scope={}&state={}\
+             &nonce={}&code_challenge={}&code_challenge_method=S256",
+            endpoints.authorization,
+            separator,
+            encode(&self.config.client_id),
+            encode(&self.config.redirect_url),
+            encode(&self.config.scopes.join(" ")),
+            state,
+            nonce,
+            challenge
+        );
+        Ok(Login { url, state })
+    }
+
+    /// Finish a login from the callback's `code` and `state`, and the state in the browser's
+    /// cookie: the user and their permission. `PermissionDenied` for an unknown state, one
+    /// that isn't the browser's or a user without permission. Blocks on the provider.
+    pub fn finish(
+        &self,
+        code: &str,
+        state: &str,
+        browser_state: Option<&str>,
+    ) -> io::Result<(String, Permission)> {
+        let unknown = || {
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Unknown or expired login; start again",
+            )
+        };
+        if browser_state != Some(state) {
+            return Err(unknown());
+        }
+        let login = self.pending.lock().unwrap().remove(state);
+        let login = login
+            .filter(|login| login.started.elapsed() < STATE_TTL)
+            .ok_or_else(unknown)?;
+        let endpoints = self.endpoints()?;
+        let agent = self.agent();
+        let provider_error = |e: ureq::Error| io::Error::other(format!("OIDC provider: {}", e));
+        let invalid = |e: serde_json::Error| io::Error::new(io::ErrorKind::InvalidData, e);
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_url.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("code_verifier", login.verifier.as_str()),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let response = agent
+            .post(&endpoints.token)
+            .set("Accept", "application/json")
+            .send_form(&form)
+            .map_err(provider_error)?;
+        let token: serde_json::Value =
+            serde_json::from_str(&response.into_string()?).map_err(invalid)?;
+        let access_token = token["access_token"].as_str().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The token response has no access_token",
+            )
+        })?;
+        if let Some(id_token) = token["id_token"].as_str()
+            && id_token_nonce(id_token).as_deref() != Some(login.nonce.as_str())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "The ID token is for another login",
+            ));
+        }
+
+        let response = agent
+            .get(&endpoints.userinfo)
+            .set("Accept", "application/json")
+            .set("Authorization", &format!("Bearer {}", access_token))
+            .call()
+            .map_err(provider_error)?;
+        let userinfo: serde_json::Value =
+            serde_json::from_str(&response.into_string()?).map_err(invalid)?;
+        let user = user_from_userinfo(&self.config, &userinfo).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("The provider didn't confirm a {}", self.config.user_claim),
+            )
+        })?;
+        let permission = self
+            .config
+            .users
+            .get(&user)
+            .copied()
+            .or(self.config.default_permission)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{} may not use this server", user),
+                )
+            })?;
+        Ok((user, permission))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    fn config() -> OidcConfig {
+        OidcConfig {
+            issuer: None,
+            client_id: "fmemo".to_string(),
+            client_secret: Some("s3cret".to_string()),
+            redirect_url: "https://notes.example.com/team/api/auth/oidc/callback".to_string(),
+            scopes: vec!["openid".to_string(), "email".to_string()],
+            authorization_url: Some("https://id.example.com/authorize".to_string()),
+            token_url: None,
+            userinfo_url: None,
+            user_claim: "email".to_string(),
+            default_permission: None,
+            users: BTreeMap::from([("kai@example.com".to_string(), Permission::Write)]),
+            timeout_secs: 5,
+        }
+    }
+
+    /// Answers each request with the body for its path; returns the request lines
+    fn provider(
+        listener: TcpListener,
+        bodies: Vec<(&'static str, &'static str)>,
+    ) -> std::thread::JoinHandle<Vec<String>> {
+        std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for _ in 0..bodies.len() {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut head = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim_end().is_empty() {
+                        break;
+                    }
+                    head.push(line.trim_end().to_string());
+                }
+                let length: usize = head
+                    .iter()
+                    .find_map(|line| {
+                        line.to_lowercase()
+                            .strip_prefix("content-length: ")?
+                            .parse()
+                            .ok()
+                    })
+                    .unwrap_or(0);
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let path = head[0].split(' ').nth(1).unwrap().to_string();
+                let reply = bodies.iter().find(|(p, _)| *p == path).unwrap().1;
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                    reply.len(),
+                    reply
+                )
+                .unwrap();
+                requests.push(format!("{} {}", head[0], String::from_utf8_lossy(&body)));
+            }
+            requests
+        })
+    }
+
+    #[test]
+    fn test_oidc_helpers() {
+        let mut config = config();
+        assert_eq!(home_url(&config), "https://notes.example.com/team/");
+        let document = serde_json::json!({
+            "authorization_endpoint": "https://other.example.com/auth",
+            "token_endpoint": "https://id.example.com/token",
+            "userinfo_endpoint": "https://id.example.com/userinfo"
+        });
+        let endpoints = parse_discovery(&config, &document).unwrap();
+        assert_eq!(endpoints.authorization, "https://id.example.com/authorize");
+        assert_eq!(endpoints.token, "https://id.example.com/token");
+        assert!(parse_discovery(&config, &serde_json::json!({})).is_err());
+
+        let userinfo = serde_json::json!({"email": "kai@example.com", "email_verified": false});
+        assert_eq!(user_from_userinfo(&config, &userinfo), None);
+        config.user_claim = "id".to_string();
+        assert_eq!(
+            user_from_userinfo(&config, &serde_json::json!({"id": 42})).as_deref(),
+            Some("42")
+        );
+
+        // RFC 7636, appendix B
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+        let claims = URL_SAFE_NO_PAD.encode(r#"{"sub":"1","nonce":"n-1"}"#);
+        assert_eq!(
+            id_token_nonce(&format!("e30.{}.sig", claims)).as_deref(),
+            Some("n-1")
+        );
+        assert_eq!(id_token_nonce("not a token"), None);
+
+        assert_eq!(
+            state_from_cookie("fmemo_session=s; fmemo_oidc_state=abc"),
+            Some("abc")
+        );
+        assert!(state_cookie("").ends_with("Max-Age=0"));
+    }
+
+    #[test]
+    fn test_oidc_pending_is_capped() {
+        let mut config = config();
+        config.token_url = Some("https://id.example.com/token".to_string());
+        config.userinfo_url = Some("https://id.example.com/userinfo".to_string());
+        let oidc = Oidc::new(config);
+        let first = oidc.begin().unwrap();
+        for _ in 0..MAX_PENDING {
+            oidc.begin().unwrap();
+        }
+        assert_eq!(oidc.pending.lock().unwrap().len(), MAX_PENDING);
+        assert!(!oidc.pending.lock().unwrap().contains_key(&first.state));
+    }
+
+    #[test]
+    fn test_oidc_login() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let mut config = config();
+        config.token_url = Some(format!("{}/token", base));
+        config.userinfo_url = Some(format!("{}/userinfo", base));
+        let oidc = Oidc::new(config);
+
+        let login = oidc.begin().unwrap();
+        let url = &login.url;
+        assert!(
+            url.starts_with("https://id.example.com/authorize?response_type=code&client_id=fmemo")
+        );
+        assert!(url.contains("&scope=openid%20email&"));
+        assert!(url.contains(&format!("&state={}&", login.state)));
+        assert!(url.ends_with("&code_challenge_method=S256"));
+        let challenge = url
+            .split("code_challenge=")
+            .nth(1)
+            .unwrap()
+            .split('&')
+            .next()
+            .unwrap()
+            .to_string();
+        let state = login.state.clone();
+        assert_eq!(
+            oidc.finish("code", "forged", Some("forged"))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        // Another browser, without the state cookie, can't finish the login
+        for browser_state in [None, Some("forged")] {
+            assert_eq!(
+                oidc.finish("code", &state, browser_state)
+                    .unwrap_err()
+                    .kind(),
+                io::ErrorKind::PermissionDenied
+            );
+        }
+
+        let provider = provider(
+            listener,
+            vec![
+                ("/token", r#"{"access_token":"at-1","token_type":"Bearer"}"#),
+                (
+                    "/userinfo",
+                    r#"{"email":"kai@example.com","email_verified":true}"#,
+                ),
+            ],
+        );
+        let (user, permission) = oidc.finish("the-code", &state, Some(&state)).unwrap();
+        assert_eq!(user, "kai@example.com");
+        assert_eq!(permission, Permission::Write);
+        let requests = provider.join().unwrap();
+        assert!(requests[0].starts_with("POST /token "));
+        assert!(requests[0].contains("code=the-code"));
+        assert!(requests[0].contains("client_secret=s3cret"));
+        let verifier = requests[0]
+            .split("code_verifier=")
+            .nth(1)
+            .unwrap()
+            .split('&')
+            .next()
+            .unwrap();
+        assert_eq!(pkce_challenge(verifier), challenge);
+        assert!(requests[1].starts_with("GET /userinfo "));
+
+        // The state is used up
+        assert!(oidc.finish("the-code", &state, Some(&state)).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+//! Cursor pagination of long API responses.
+//!
+//! A listing sorted by a string key is cut into pages of `limit` items. The cursor of the
+//! next page is the key of the last item returned, hex-encoded so it is opaque and URL safe.
+//! Pages pick up after that key rather than at a position, so files added or removed while a
+//! client pages through don't make it skip or repeat anything.
+
+use std::collections::HashMap;
+use std::io;
+
+/// Page size when only `cursor` is given
+pub const DEFAULT_LIMIT: usize = 100;
+/// Largest page size a client can ask for
+pub const MAX_LIMIT: usize = 1000;
+
+/// `?limit=` and `?cursor=` of a request
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageRequest {
+    pub limit: usize,
+    /// Key of the last item of the previous page
+    pub after: Option<String>,
+}
+
+impl PageRequest {
+    /// The page asked for, or `None` when the request has neither parameter and gets
+    /// the whole listing
+    pub fn from_query(query: &HashMap<String, String>) -> io::Result<Option<Self>> {
+        if !query.contains_key("limit") && !query.contains_key("cursor") {
+            return Ok(None);
+        }
+        let limit = match query.get("limit") {
+            Some(limit) => match limit.parse::<usize>() {
+                Ok(limit) if limit > 0 => limit.min(MAX_LIMIT),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "limit must be a positive integer",
+                    ));
+                }
+            },
+            None => DEFAULT_LIMIT,
+        };
+        let after = match query.get("cursor").filter(|cursor| !cursor.is_empty()) {
+            Some(cursor) => Some(decode_cursor(cursor)?),
+            None => None,
+        };
+        Ok(Some(Self { limit, after }))
+    }
+
+    /// The items of this page from a listing sorted by `key`, and the next page's cursor
+    pub fn apply<T>(&self, items: Vec<T>, key: impl Fn(&T) -> String) -> (Vec<T>, Option<String>) {
+        let mut page: Vec<T> = items
+            .into_iter()
+            .filter(|item| self.after.as_ref().is_none_or(|after| key(item) > *after))
+            .take(self.limit + 1)
+            .collect();
+        let next = if page.len() > self.limit {
+            page.truncate(self.limit);
+            page.last().map(|item| encode_cursor(&key(item)))
+        } else {
+            None
+        };
+        (page, next)
+    }
+}
+
+pub fn encode_cursor(key: &str) -> String {
+    crate::crypt::to_hex(key.as_bytes())
+}
+
+pub fn decode_cursor(cursor: &str) -> io::Result<String> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "Invalid cursor");
+    let bytes = crate::crypt::from_hex(cursor).ok_or_else(invalid)?;
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PageRequest, decode_cursor, encode_cursor};
+    use std::collections::HashMap;
+
+    fn query(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_pages() {
+        assert_eq!(PageRequest::from_query(&query(&[])).unwrap(), None);
+        assert!(PageRequest::from_query(&query(&[("limit", "0")])).is_err());
+        assert!(PageRequest::from_query(&query(&[("cursor", "zz")])).is_err());
+        assert_eq!(
+            decode_cursor(&encode_cursor("notes/ä.fmemo")).unwrap(),
+            "notes/ä.fmemo"
+        );
+
+        let files = || vec!["a.fmemo", "b.fmemo", "c.fmemo"];
+        let page = PageRequest::from_query(&query(&[("limit", "2")]))
+            .unwrap()
+            .unwrap();
+        let (items, next) = page.apply(files(), |f| f.to_string());
+        assert_eq!(items, ["a.fmemo", "b.fmemo"]);
+        let next = next.unwrap();
+
+        // b.fmemo is deleted before the next page is fetched; c.fmemo still comes next
+        let page = PageRequest::from_query(&query(&[("limit", "2"), ("cursor", &next)]))
+            .unwrap()
+            .unwrap();
+        let (items, next) = page.apply(vec!["a.fmemo", "c.fmemo"], |f| f.to_string());
+        assert_eq!(items, ["c.fmemo"]);
+        assert_eq!(next, None);
+    }
+}
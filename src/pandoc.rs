@@ -0,0 +1,226 @@
+//! Word processor documents of a memo file, converted by pandoc, for
+//! `GET /api/files/{path}/export?format=docx|odt`.
+//!
+//! pandoc has to be installed: `[pandoc] path` in the config, or else `pandoc` on `PATH`; an
+//! `ErrorKind::Unsupported` error means it isn't there. The file's Markdown is converted as it
+//! would be served (without `<private>` regions on a published root), with wiki-links and
+//! embeds reduced to their text.
+//!
+//! pandoc runs with `--sandbox` (pandoc 2.15 or later), so a memo can't pull server files or
+//! internal URLs into the document; images are left out. A conversion taking longer than
+//! `TIMEOUT` is killed with `ErrorKind::TimedOut`.
+
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// How long pandoc may take for one document
+pub const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A format pandoc converts memos to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentFormat {
+    Docx,
+    Odt,
+}
+
+impl DocumentFormat {
+    /// The format of `?format=`; `InvalidInput` for others
+    pub fn from_name(name: &str) -> io::Result<Self> {
+        match name {
+            "docx" => Ok(DocumentFormat::Docx),
+            "odt" => Ok(DocumentFormat::Odt),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "format must be docx or odt",
+            )),
+        }
+    }
+
+    /// pandoc's name of the format, also the file extension
+    pub fn name(self) -> &'static str {
+        match self {
+            DocumentFormat::Docx => "docx",
+            DocumentFormat::Odt => "odt",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            DocumentFormat::Docx => {
+                "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+            }
+            DocumentFormat::Odt => "application/vnd.oasis.opendocument.text",
+        }
+    }
+}
+
+/// Convert `markdown` with the pandoc binary `program`, giving up after `timeout`
+pub fn convert(
+    program: &str,
+    markdown: &str,
+    format: DocumentFormat,
+    timeout: Duration,
+) -> io::Result<Vec<u8>> {
+    let mut child = Command::new(program)
+        .args([
+            "--sandbox",
+            "--from",
+            "markdown",
+            "--to",
+            format.name(),
+            "--output",
+            "-",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => {
+                io::Error::new(io::ErrorKind::Unsupported, "pandoc is not installed")
+            }
+            _ => e,
+        })?;
+    // Pipes are served from other threads so a large document can't block on a full one
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = markdown.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let read_all = |mut pipe: Box<dyn Read + Send>| {
+        std::thread::spawn(move || {
+            let mut data = Vec::new();
+            pipe.read_to_end(&mut data).map(|_| data)
+        })
+    };
+    let stdout = read_all(Box::new(child.stdout.take().expect("stdout is piped")));
+    let stderr = read_all(Box::new(child.stderr.take().expect("stderr is piped")));
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("pandoc took longer than {} seconds", timeout.as_secs()),
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    let _ = writer.join();
+    let joined = |reader: std::thread::JoinHandle<io::Result<Vec<u8>>>| {
+        reader
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("pandoc output reader panicked")))
+    };
+    let (stdout, stderr) = (joined(stdout)?, joined(stderr)?);
+    if !status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            String::from_utf8_lossy(&stderr).trim().to_string(),
+        ));
+    }
+    Ok(stdout)
+}
+
+/// The memo file at `path` below `root` as a `format` document, without its private regions
+/// when `publish` is set
+pub fn export_file(
+    root: &Path,
+    path: &Path,
+    format: DocumentFormat,
+    publish: bool,
+) -> io::Result<Vec<u8>> {
+    let config = crate::config::load_config(root)?;
+    let content = crate::publish::read_memo(path, publish)?;
+    let markdown = crate::blog::rewrite_links(&content, "", &[]);
+    convert(
+        config.pandoc.path.as_deref().unwrap_or("pandoc"),
+        &markdown,
+        format,
+        TIMEOUT,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_document_formats() {
+        assert_eq!(
+            DocumentFormat::from_name("odt").unwrap(),
+            DocumentFormat::Odt
+        );
+        assert_eq!(
+            DocumentFormat::from_name("pdf").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(DocumentFormat::Docx.name(), "docx");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_export_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join(".fmemo")).unwrap();
+        fs::create_dir_all(root.join("notes")).unwrap();
+        fs::write(root.join("notes/a.fmemo"), "# A\nSee [[plan|the plan]].\n").unwrap();
+
+        let missing = root.join("no-pandoc");
+        fs::write(
+            root.join(".fmemo/config.toml"),
+            format!("[pandoc]\npath = {:?}\n", missing.display().to_string()),
+        )
+        .unwrap();
+        let error = export_file(
+            root,
+            &root.join("notes/a.fmemo"),
+            DocumentFormat::Docx,
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+
+        // A stand-in for pandoc that echoes its arguments and input
+        let fake = root.join("pandoc");
+        fs::write(&fake, "#!/bin/sh\necho \"$@\"\ncat\n").unwrap();
+        fs::set_permissions(&fake, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(
+            root.join(".fmemo/config.toml"),
+            format!("[pandoc]\npath = {:?}\n", fake.display().to_string()),
+        )
+        .unwrap();
+        let output = export_file(
+            root,
+            &root.join("notes/a.fmemo"),
+            DocumentFormat::Odt,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "--sandbox --from markdown --to odt --output -\n# A\nSee the plan.\n"
+        );
+
+        // A pandoc that hangs is killed
+        fs::write(&fake, "#!/bin/sh\nsleep 10\n").unwrap();
+        let error = convert(
+            &fake.display().to_string(),
+            "# A",
+            DocumentFormat::Docx,
+            Duration::from_millis(200),
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -1,149 +1,781 @@
-use crate::schema::{Level, Memo, MemoBuilder};
+use std::borrow::Cow;
+
+use crate::schema::{
+    CodeBlock, Diagram, DiagramKind, Level, LinkKind, MathBlock, Memo, MemoBuilder, ParseWarning,
+    ParseWarningKind, SourceSpan, Timestamp, TocEntry,
+};
+
+/// Parser configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseOptions {
+    /// Inline tags (`<name>value</name>`) collected into `Memo::metadata`
+    pub metadata_tags: Vec<String>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            metadata_tags: ["todo", "due", "priority", "status", "tag"]
+                .iter()
+                .map(|t| t.to_string())
+                .collect(),
+        }
+    }
+}
 
 pub fn parse_memo(content: &str) -> Vec<Memo> {
-    let flat_memos = parse_flat(content);
-    build_hierarchy(flat_memos)
+    parse_memo_with(content, &ParseOptions::default())
+}
+
+pub fn parse_memo_with(content: &str, options: &ParseOptions) -> Vec<Memo> {
+    parse_document(content, options).memos
 }
 
-fn parse_flat(content: &str) -> Vec<Memo> {
+/// Parsed memos together with the problems found while parsing
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedDocument {
+    pub memos: Vec<Memo>,
+    pub warnings: Vec<ParseWarning>,
+}
+
+pub fn parse_document(content: &str, options: &ParseOptions) -> ParsedDocument {
+    let content = normalize_source(content);
+    let mut warnings = Vec::new();
+    let mut hierarchy = Hierarchy::default();
+    parse_sections(&content, options, 0, &mut warnings, &mut |memo| hierarchy.push(memo));
+    let (memos, level_warnings) = hierarchy.finish();
+    warnings.extend(level_warnings);
+    warnings.sort_by_key(|w| w.line);
+    ParsedDocument { memos, warnings }
+}
+
+/// Make parsing independent of the platform that saved the file: strip a UTF-8 BOM,
+/// turn CRLF / lone CR line endings into LF and drop trailing whitespace on every line.
+pub fn normalize_source(content: &str) -> Cow<'_, str> {
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let needs_work = content.contains('\r')
+        || content
+            .split('\n')
+            .any(|line| line.ends_with([' ', '\t']));
+    if !needs_work {
+        return Cow::Borrowed(content);
+    }
+
+    let mut normalized = String::with_capacity(content.len());
+    let unified = content.replace("\r\n", "\n").replace('\r', "\n");
+    for (index, line) in unified.split('\n').enumerate() {
+        if index > 0 {
+            normalized.push('\n');
+        }
+        normalized.push_str(line.trim_end_matches([' ', '\t']));
+    }
+    Cow::Owned(normalized)
+}
+
+/// Resolve local image sources against the directory of `file_path` (relative to the root).
+/// Images pointing outside the root or to external URLs keep `path: None`.
+pub fn resolve_image_paths(memos: &mut [Memo], file_path: &str) {
+    let base_dir = match file_path.rfind('/') {
+        Some(idx) => &file_path[..idx],
+        None => "",
+    };
+    for memo in memos {
+        for image in memo.images_mut() {
+            if LinkKind::classify(&image.src) == LinkKind::Internal {
+                image.path = normalize_relative_path(base_dir, &image.src);
+            }
+        }
+        resolve_image_paths(memo.children_mut(), file_path);
+    }
+}
+
+/// Lexically join `target` onto `base_dir`, rejecting paths that escape the root
+pub fn normalize_relative_path(base_dir: &str, target: &str) -> Option<String> {
+    let target = target.split(['?', '#']).next().unwrap_or("");
+    if target.is_empty() {
+        return None;
+    }
+    let mut parts: Vec<&str> = Vec::new();
+    let start = if target.starts_with('/') { "" } else { base_dir };
+    for part in start.split('/').chain(target.split('/')) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+/// The memo whose anchor is `slug` (the first one, depth-first, for memos without anchors)
+pub fn find_memo_by_slug<'a>(memos: &'a [Memo], slug: &str) -> Option<&'a Memo> {
+    memos.iter().find_map(|memo| {
+        if memo.anchor() == slug {
+            Some(memo)
+        } else {
+            find_memo_by_slug(memo.children(), slug)
+        }
+    })
+}
+
+/// Keep `depth` levels of memos; the memos on the last kept level get their children replaced
+/// by `children_count` and `children_slugs`
+pub fn truncate_depth(memos: &mut [Memo], depth: usize) {
+    for memo in memos {
+        if depth <= 1 {
+            memo.collapse_children();
+        } else {
+            truncate_depth(memo.children_mut(), depth - 1);
+        }
+    }
+}
+
+/// The heading hierarchy of `memos`, without their bodies
+pub fn table_of_contents(memos: &[Memo]) -> Vec<TocEntry> {
+    memos
+        .iter()
+        .map(|memo| TocEntry {
+            title: memo.title().clone(),
+            slug: memo.anchor().into_owned(),
+            level: memo.level().level() + 1,
+            line: memo.span().map_or(0, |span| span.start_line),
+            children: table_of_contents(memo.children()),
+        })
+        .collect()
+}
+
+/// Markdown of a memo and its children, cut from the `content` it was parsed from, with
+/// headings raised so the memo's own heading is `#`. Only memos that are still in the tree
+/// are included (children a plugin dropped are left out). `None` without source spans.
+pub fn subtree_markdown(content: &str, memo: &Memo) -> Option<String> {
+    fn push_memo(lines: &[&str], memo: &Memo, shift: usize, markdown: &mut String) -> Option<()> {
+        let span = memo.span()?;
+        let section = lines.get(span.start_line.checked_sub(1)?..span.end_line.min(lines.len()))?;
+        for (index, line) in section.iter().enumerate() {
+            // The first line is the memo's heading, the only heading line in its span
+            markdown.push_str(if index == 0 { &line[shift.min(line.len())..] } else { line });
+            markdown.push('\n');
+        }
+        for child in memo.children() {
+            push_memo(lines, child, shift, markdown)?;
+        }
+        Some(())
+    }
+
+    let content = normalize_source(content);
+    let lines: Vec<&str> = content.lines().collect();
+    let mut markdown = String::new();
+    push_memo(&lines, memo, memo.level().level() as usize, &mut markdown)?;
+    let trimmed = markdown.trim_end().len();
+    markdown.truncate(trimmed);
+    markdown.push('\n');
+    Some(markdown)
+}
+
+/// Parse memos without nesting them. Line numbers in spans and warnings start after `line_offset`.
+/// Returns the memos and whether the input ended inside a fence, math block or HTML comment.
+pub(crate) fn parse_flat(
+    content: &str,
+    options: &ParseOptions,
+    line_offset: usize,
+    warnings: &mut Vec<ParseWarning>,
+) -> (Vec<Memo>, bool) {
     let mut memos = Vec::new();
+    let ends_open = parse_sections(content, options, line_offset, warnings, &mut |memo| memos.push(memo));
+    (memos, ends_open)
+}
+
+/// Parse memos in document order, handing each one to `emit` as soon as its section ends.
+/// Returns whether the content ends inside a code fence, math block or HTML comment.
+fn parse_sections(
+    content: &str,
+    options: &ParseOptions,
+    line_offset: usize,
+    warnings: &mut Vec<ParseWarning>,
+    emit: &mut dyn FnMut(Memo),
+) -> bool {
     let mut current_memo: Option<MemoBuilder> = None;
-    let mut in_code_block = false;
+    let mut open_fence: Option<Fence> = None;
     let mut current_code = String::new();
-    let mut current_lang = String::new();
+    let mut current_info = String::new();
     let mut current_content = String::new();
-
-    for line in content.lines() {
-        if line.starts_with("```") {
-            if in_code_block {
+    let mut in_html_comment = false;
+    let mut current_math: Option<String> = None;
+    // 1-based line numbers of constructs that are still open, for diagnostics
+    let mut fence_line = 0;
+    let mut math_line = 0;
+    let mut open_desc_line: Option<usize> = None;
+    let mut memo_start_line = 0;
+    let mut last_line_number = line_offset;
+    // Front matter can only start on the first line of the file
+    let front_matter = if line_offset == 0 { split_front_matter(content) } else { None };
+    let front_matter_lines = front_matter.as_ref().map_or(0, |fm| fm.line_count);
+    // Front matter timestamps are the defaults of every memo
+    let default_timestamps = front_matter
+        .as_ref()
+        .map(|fm| (fm.timestamp("created", warnings), fm.timestamp("updated", warnings)));
+    let mut emit = |memo: Memo| match default_timestamps {
+        Some((created, updated)) => emit(memo.with_default_timestamps(created, updated)),
+        None => emit(memo),
+    };
+
+    for (index, line) in content.lines().enumerate() {
+        let line_number = line_offset + index + 1;
+        last_line_number = line_number;
+        if index < front_matter_lines {
+            continue;
+        }
+        if in_html_comment {
+            // Everything inside <!-- ... --> is plain content, even lines starting with #
+            in_html_comment = !line.contains("-->");
+            current_content.push_str(line);
+            current_content.push('\n');
+        } else if let Some(ref mut math) = current_math {
+            if line.trim() == "$$" {
+                if let Some(builder) = current_memo.as_mut() {
+                    builder.push_math_block(MathBlock::display(math.trim().to_string()));
+                }
+                current_math = None;
+            } else {
+                math.push_str(line);
+                math.push('\n');
+            }
+        } else if let Some(fence) = &open_fence {
+            if fence.is_closed_by(line) {
                 // End of code block
-                if let Some(ref mut builder) = current_memo {
-                    *builder = builder.clone().add_code_block(current_lang.clone(), current_code.trim().to_string());
+                if let Some(builder) = current_memo.as_mut() {
+                    let code_block = parse_info_string(&current_info, current_code.trim().to_string());
+                    match DiagramKind::from_language(&code_block.language) {
+                        Some(kind) => builder.push_diagram(Diagram {
+                            kind,
+                            source: code_block.code,
+                        }),
+                        None => builder.push_code_block(code_block),
+                    }
                 }
                 current_code.clear();
-                current_lang.clear();
-                in_code_block = false;
+                current_info.clear();
+                open_fence = None;
             } else {
-                // Start of code block
-                current_lang = line[3..].to_string();
-                in_code_block = true;
+                current_code.push_str(fence.strip_indent(line));
+                current_code.push('\n');
+            }
+        } else if let Some((fence, info)) = Fence::open(line) {
+            // Start of code block
+            current_info = info.to_string();
+            open_fence = Some(fence);
+            fence_line = line_number;
+        } else if line.trim() == "$$" {
+            current_math = Some(String::new());
+            math_line = line_number;
+        } else if let Some(tex) = single_line_display_math(line) {
+            if let Some(builder) = current_memo.as_mut() {
+                builder.push_math_block(MathBlock::display(tex.to_string()));
+            }
+        } else if let Some((level_count, title)) = parse_heading(line) {
+            if let Some(desc_line) = open_desc_line.take() {
+                warnings.push(ParseWarning::unclosed_desc(desc_line));
             }
-        } else if in_code_block {
-            current_code.push_str(line);
-            current_code.push('\n');
-        } else if line.starts_with('#') {
             // Save current memo before creating new one
-            if let Some(builder) = current_memo.take() {
-                let (final_content, description) = extract_description(&current_content);
-                let mut final_builder = builder.content(final_content.trim().to_string());
-                if let Some(desc) = description {
-                    final_builder = final_builder.description(desc);
-                }
-                memos.push(final_builder.build());
+            if let Some(mut builder) = current_memo.take() {
+                builder.set_span(SourceSpan::new(memo_start_line, line_number - 1));
+                emit(finish_memo(builder, &current_content, options, warnings));
             }
             
-            let level_count = line.chars().take_while(|&c| c == '#').count() as u8;
-            let title = line[level_count as usize..].trim().to_string();
             let level = Level::new(level_count - 1); // 0-indexed
             
-            current_memo = Some(MemoBuilder::new(level, title));
+            current_memo = Some(MemoBuilder::new(level, title.to_string()));
+            memo_start_line = line_number;
             current_content.clear();
         } else {
+            in_html_comment = opens_html_comment(line);
+            open_desc_line = track_open_desc(line, line_number, open_desc_line);
             current_content.push_str(line);
             current_content.push('\n');
         }
     }
+
+    if open_fence.is_some() {
+        warnings.push(ParseWarning {
+            line: fence_line,
+            kind: ParseWarningKind::UnclosedCodeFence,
+            message: "Code fence is never closed; its content is ignored".to_string(),
+        });
+    }
+    if current_math.is_some() {
+        warnings.push(ParseWarning {
+            line: math_line,
+            kind: ParseWarningKind::UnclosedMathBlock,
+            message: "Math block ($$) is never closed; its content is ignored".to_string(),
+        });
+    }
+    if let Some(desc_line) = open_desc_line {
+        warnings.push(ParseWarning::unclosed_desc(desc_line));
+    }
+    let ends_open = open_fence.is_some() || current_math.is_some() || in_html_comment;
     
     // Handle the last memo
-    if let Some(builder) = current_memo {
-        let (final_content, description) = extract_description(&current_content);
-        let mut final_builder = builder.content(final_content.trim().to_string());
-        if let Some(desc) = description {
-            final_builder = final_builder.description(desc);
+    if let Some(mut builder) = current_memo {
+        builder.set_span(SourceSpan::new(memo_start_line, last_line_number));
+        emit(finish_memo(builder, &current_content, options, warnings));
+    }
+
+    ends_open
+}
+
+/// Update the line of an unclosed `<desc>` after seeing a content line
+fn track_open_desc(line: &str, line_number: usize, open: Option<usize>) -> Option<usize> {
+    let last_open = line.rfind("<desc>");
+    let last_close = line.rfind("</desc>");
+    match (last_open, last_close) {
+        (Some(o), Some(c)) if o > c => open.or(Some(line_number)),
+        (Some(_), None) => open.or(Some(line_number)),
+        (_, Some(_)) => None,
+        (None, None) => open,
+    }
+}
+
+/// Attach the accumulated content, descriptions, timestamps and metadata tags to a memo.
+/// Every `<desc>` between this heading and the next one belongs to this memo.
+fn finish_memo(
+    mut builder: MemoBuilder,
+    content: &str,
+    options: &ParseOptions,
+    warnings: &mut Vec<ParseWarning>,
+) -> Memo {
+    // Private regions only matter to publish mode, which cuts them out before parsing
+    let content: Cow<str> = if content.contains("private>") {
+        content.replace(crate::publish::OPEN_TAG, "").replace(crate::publish::CLOSE_TAG, "").into()
+    } else {
+        content.into()
+    };
+    let (mut content, descriptions) = extract_tag_values(&content, "desc");
+    for description in descriptions {
+        builder.push_description(description);
+    }
+    let heading_line = builder.span_start_line();
+    for tag in ["created", "updated"] {
+        let (remaining, values) = extract_tag_values(&content, tag);
+        content = remaining;
+        // The first valid value wins
+        let timestamp = values
+            .iter()
+            .filter_map(|value| checked_timestamp(tag, value, heading_line, warnings))
+            .next();
+        match (tag, timestamp) {
+            ("created", Some(timestamp)) => builder.set_created(timestamp),
+            (_, Some(timestamp)) => builder.set_updated(timestamp),
+            (_, None) => {}
         }
-        memos.push(final_builder.build());
     }
-    
-    memos
+    for tag in &options.metadata_tags {
+        let (remaining, values) = extract_tag_values(&content, tag);
+        content = remaining;
+        for value in values {
+            builder.insert_metadata(tag.clone(), value.trim().to_string());
+        }
+    }
+    builder.set_content(content.trim().to_string());
+    builder.build()
 }
 
-fn extract_description(content: &str) -> (String, Option<String>) {
-    // Look for <desc>...</desc> pattern
-    let desc_start = "<desc>";
-    let desc_end = "</desc>";
-    
-    if let Some(start_pos) = content.find(desc_start) {
-        if let Some(end_pos) = content[start_pos..].find(desc_end) {
-            let desc_content_start = start_pos + desc_start.len();
-            let desc_content_end = start_pos + end_pos;
-            
-            let description = content[desc_content_start..desc_content_end].to_string();
-            
-            // Remove the desc tag from content
-            let before_desc = &content[..start_pos];
-            let after_desc = &content[start_pos + end_pos + desc_end.len()..];
-            let cleaned_content = format!("{}{}", before_desc, after_desc);
-            
-            return (cleaned_content, Some(description));
+/// Accept RFC 3339 (`2024-05-01T09:30:00+09:00`), `YYYY-MM-DD HH:MM[:SS]` and `YYYY-MM-DD`.
+/// Values without an offset are taken as UTC.
+pub fn parse_timestamp(value: &str) -> Option<Timestamp> {
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    let value = value.trim();
+    if let Ok(timestamp) = Timestamp::parse_from_rfc3339(value) {
+        return Some(timestamp);
+    }
+    let naive = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })?;
+    Some(naive.and_utc().fixed_offset())
+}
+
+/// Parse a timestamp tag value, warning about values that aren't timestamps
+fn checked_timestamp(
+    tag: &str,
+    value: &str,
+    line: usize,
+    warnings: &mut Vec<ParseWarning>,
+) -> Option<Timestamp> {
+    let timestamp = parse_timestamp(value);
+    if timestamp.is_none() {
+        warnings.push(ParseWarning {
+            line,
+            kind: ParseWarningKind::InvalidTimestamp,
+            message: format!("<{}> value '{}' is not a date or RFC 3339 timestamp", tag, value.trim()),
+        });
+    }
+    timestamp
+}
+
+/// `---` delimited block of `key: value` lines at the very top of a file
+struct FrontMatter {
+    fields: Vec<(String, String)>,
+    /// Lines taken up by the block, including both delimiters
+    line_count: usize,
+}
+
+impl FrontMatter {
+    fn timestamp(&self, key: &str, warnings: &mut Vec<ParseWarning>) -> Option<Timestamp> {
+        let (index, value) = self
+            .fields
+            .iter()
+            .enumerate()
+            .find_map(|(index, (k, v))| (k == key).then_some((index, v)))?;
+        // Fields are one per line, right after the opening `---`
+        checked_timestamp(key, value, index + 2, warnings)
+    }
+}
+
+/// Split off YAML-style front matter. Only flat `key: value` pairs are read;
+/// a block without a closing `---` (or `...`) before the first heading is not front matter,
+/// so a memo opening with a `---` rule keeps its headings. YAML comments inside the block
+/// are indented or have no space after the `#`.
+fn split_front_matter(content: &str) -> Option<FrontMatter> {
+    let mut lines = content.lines();
+    if lines.next()? != "---" {
+        return None;
+    }
+    let mut fields = Vec::new();
+    for (index, line) in lines.enumerate() {
+        if parse_heading(line).is_some() {
+            return None;
+        }
+        if line == "---" || line == "..." {
+            return Some(FrontMatter {
+                fields,
+                line_count: index + 2,
+            });
         }
+        let Some((key, value)) = line.split_once(':') else {
+            fields.push((String::new(), String::new()));
+            continue;
+        };
+        let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+        fields.push((key.trim().to_string(), value.to_string()));
     }
-    
-    (content.to_string(), None)
+    None
 }
 
-fn build_hierarchy(flat_memos: Vec<Memo>) -> Vec<Memo> {
-    let mut root_memos = Vec::new();
-    let mut stack: Vec<MemoBuilder> = Vec::new();
+/// Value of a front matter field, e.g. a file-wide `due:` date
+pub(crate) fn front_matter_value(content: &str, key: &str) -> Option<String> {
+    split_front_matter(content)?
+        .fields
+        .into_iter()
+        .find_map(|(k, v)| (k == key).then_some(v))
+}
 
-    for memo in flat_memos {
-        // Convert memo back to builder for hierarchy building
-        let memo_level = memo.level().clone();
-        let mut builder = MemoBuilder::new(memo_level.clone(), memo.title().clone());
-        
-        if let Some(content) = memo.content() {
-            builder = builder.content(content.clone());
+/// Front matter fields of `content` and the rest of it after the block
+pub(crate) fn front_matter_fields(content: &str) -> (Vec<(String, String)>, &str) {
+    let Some(front_matter) = split_front_matter(content) else {
+        return (Vec::new(), content);
+    };
+    let offset: usize = content
+        .split_inclusive('\n')
+        .take(front_matter.line_count)
+        .map(str::len)
+        .sum();
+    let fields = front_matter.fields.into_iter().filter(|(key, _)| !key.is_empty()).collect();
+    (fields, &content[offset..])
+}
+
+/// Remove every complete `<tag>...</tag>` from `content`, returning the rest and the raw values
+pub(crate) fn extract_tag_values(content: &str, tag: &str) -> (String, Vec<String>) {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut remaining = String::with_capacity(content.len());
+    let mut values = Vec::new();
+    let mut rest = content;
+
+    while let Some(start) = rest.find(&open) {
+        let Some(end) = rest[start..].find(&close) else {
+            break;
+        };
+        values.push(rest[start + open.len()..start + end].to_string());
+        remaining.push_str(&rest[..start]);
+        rest = &rest[start + end + close.len()..];
+    }
+    remaining.push_str(rest);
+    (remaining, values)
+}
+
+/// Recognize an ATX heading: 1-6 `#` at the start of the line followed by a space (or nothing).
+/// Returns the number of `#` and the title with any closing `#` sequence removed.
+/// `#!/bin/bash`, `#include`, `\# escaped` and indented `# comments` are not headings.
+fn parse_heading(line: &str) -> Option<(u8, &str)> {
+    let level_count = line.chars().take_while(|&c| c == '#').count();
+    if level_count == 0 || level_count > 6 {
+        return None;
+    }
+    let rest = &line[level_count..];
+    if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+        return None;
+    }
+
+    let mut title = rest.trim();
+    // Optional closing sequence: "## Title ##"
+    let without_closing = title.trim_end_matches('#');
+    if without_closing.is_empty() {
+        title = without_closing;
+    } else if without_closing.len() != title.len() && without_closing.ends_with([' ', '\t']) {
+        title = without_closing.trim_end();
+    }
+    Some((level_count as u8, title))
+}
+
+/// `$$ E = mc^2 $$` on a line of its own
+fn single_line_display_math(line: &str) -> Option<&str> {
+    let tex = line.trim().strip_prefix("$$")?.strip_suffix("$$")?.trim();
+    (!tex.is_empty()).then_some(tex)
+}
+
+/// Whether a content line leaves an HTML comment open
+fn opens_html_comment(line: &str) -> bool {
+    match line.rfind("<!--") {
+        Some(start) => !line[start..].contains("-->"),
+        None => false,
+    }
+}
+
+/// An open code fence (CommonMark style: ``` or ~~~, up to 3 spaces of indentation)
+struct Fence {
+    marker: char,
+    len: usize,
+    indent: usize,
+}
+
+impl Fence {
+    /// Recognize an opening fence and return it together with its info string
+    fn open(line: &str) -> Option<(Fence, &str)> {
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        if indent > 3 {
+            return None;
         }
-        
-        if let Some(description) = memo.description() {
-            builder = builder.description(description.clone());
+        let rest = &line[indent..];
+        let marker = rest.chars().next().filter(|c| *c == '`' || *c == '~')?;
+        let len = rest.chars().take_while(|c| *c == marker).count();
+        if len < 3 {
+            return None;
         }
-        
-        for code_block in memo.code_blocks() {
-            builder = builder.add_code_block(code_block.language.clone(), code_block.code.clone());
+        let info = rest[len..].trim();
+        // Backtick fences may not contain backticks in their info string
+        if marker == '`' && info.contains('`') {
+            return None;
         }
+        Some((Fence { marker, len, indent }, info))
+    }
+
+    /// A closing fence uses the same marker, is at least as long and has nothing after it
+    fn is_closed_by(&self, line: &str) -> bool {
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        if indent > 3 {
+            return false;
+        }
+        let rest = &line[indent..];
+        let len = rest.chars().take_while(|c| *c == self.marker).count();
+        len >= self.len && rest[len..].trim().is_empty()
+    }
 
-        // Pop stack until we find a parent or reach the root
-        while let Some(last) = stack.last() {
-            if last.level().level() < memo_level.level() {
-                break;
+    /// Remove up to the opening fence's indentation from a content line
+    fn strip_indent<'a>(&self, line: &'a str) -> &'a str {
+        let spaces = line.len() - line.trim_start_matches(' ').len();
+        &line[spaces.min(self.indent)..]
+    }
+}
+
+/// Parse a fence info string such as `rust title=main.rs {3-5} showLineNumbers`
+fn parse_info_string(info: &str, code: String) -> CodeBlock {
+    let mut tokens = split_info_tokens(info).into_iter();
+    let line_count = code.lines().count();
+    let mut code_block = CodeBlock::new(String::new(), code);
+
+    let mut rest = Vec::new();
+    if let Some(first) = tokens.next() {
+        if first.contains('=') || first.starts_with('{') {
+            rest.push(first);
+        } else {
+            code_block.language = first;
+        }
+    }
+    rest.extend(tokens);
+
+    for token in rest {
+        if let Some(ranges) = token.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+            code_block.highlight_lines.extend(parse_line_ranges(ranges, line_count));
+        } else if let Some((key, value)) = token.split_once('=') {
+            let value = value.trim_matches('"').trim_matches('\'').to_string();
+            match key {
+                "title" | "filename" | "file" => code_block.filename = Some(value),
+                "highlight" | "hl_lines" => {
+                    code_block.highlight_lines.extend(parse_line_ranges(&value, line_count))
+                }
+                _ => code_block.flags.push(token),
             }
-            let completed = stack.pop().unwrap();
-            if let Some(parent) = stack.last_mut() {
-                *parent = parent.clone().add_child(completed.build());
-            } else {
-                root_memos.push(completed.build());
+        } else {
+            code_block.flags.push(token);
+        }
+    }
+
+    code_block.highlight_lines.sort_unstable();
+    code_block.highlight_lines.dedup();
+    code_block
+}
+
+/// Split an info string on whitespace, keeping quoted values and `{...}` groups intact
+fn split_info_tokens(info: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut in_braces = false;
+
+    for c in info.trim().chars() {
+        match c {
+            '"' | '\'' if quote == Some(c) => {
+                quote = None;
+                current.push(c);
+            }
+            '"' | '\'' if quote.is_none() && !in_braces => {
+                quote = Some(c);
+                current.push(c);
+            }
+            '{' if quote.is_none() => {
+                in_braces = true;
+                current.push(c);
+            }
+            '}' if quote.is_none() => {
+                in_braces = false;
+                current.push(c);
+            }
+            c if c.is_whitespace() && quote.is_none() && !in_braces => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+/// Expand `1,3-5` into `[1, 3, 4, 5]`, ignoring malformed parts (line 0, ranges out of
+/// order) and lines past `line_count`
+fn parse_line_ranges(ranges: &str, line_count: usize) -> Vec<usize> {
+    let mut lines = Vec::new();
+    for part in ranges.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                if let (Ok(start), Ok(end)) = (start.trim().parse::<usize>(), end.trim().parse::<usize>())
+                    && 1 <= start
+                    && start <= end
+                {
+                    lines.extend(start..=end.min(line_count));
+                }
+            }
+            None => {
+                if let Ok(line) = part.parse::<usize>()
+                    && 1 <= line
+                    && line <= line_count
+                {
+                    lines.push(line);
+                }
             }
         }
+    }
+    lines
+}
+
+/// Memos nested under their headings while they come out of the parser, one at a time: a
+/// stack of the memos whose sections are still open. Also gives every memo a unique anchor and
+/// warns about headings that skip a level.
+#[derive(Default)]
+pub(crate) struct Hierarchy {
+    roots: Vec<Memo>,
+    open: Vec<Memo>,
+    anchors: std::collections::HashSet<String>,
+    previous_level: Option<u8>,
+    warnings: Vec<ParseWarning>,
+}
 
-        stack.push(builder);
+impl Hierarchy {
+    /// Add the next memo in document order
+    pub(crate) fn push(&mut self, mut memo: Memo) {
+        let level = memo.level().level();
+        if let Some(previous) = self.previous_level
+            && level > previous + 1
+        {
+            self.warnings.push(ParseWarning {
+                line: memo.span().map(|s| s.start_line).unwrap_or_default(),
+                kind: ParseWarningKind::SkippedHeadingLevel,
+                message: format!("Heading level jumps from h{} to h{}", previous + 1, level + 1),
+            });
+        }
+        self.previous_level = Some(level);
+
+        let slug = crate::template::slugify(memo.title());
+        let mut anchor = slug.clone();
+        let mut repeat = 0;
+        while !self.anchors.insert(anchor.clone()) {
+            repeat += 1;
+            anchor = format!("{}-{}", slug, repeat);
+        }
+        memo.set_anchor(anchor);
+
+        // Close the sections this heading ends
+        while self.open.last().is_some_and(|last| last.level().level() >= level) {
+            self.close_last();
+        }
+        self.open.push(memo);
     }
 
-    // Process remaining items in stack
-    while let Some(builder) = stack.pop() {
-        if let Some(parent) = stack.last_mut() {
-            *parent = parent.clone().add_child(builder.build());
-        } else {
-            root_memos.push(builder.build());
+    /// The top-level memos and the heading level warnings
+    pub(crate) fn finish(mut self) -> (Vec<Memo>, Vec<ParseWarning>) {
+        while !self.open.is_empty() {
+            self.close_last();
         }
+        (self.roots, self.warnings)
     }
 
-    root_memos
+    fn close_last(&mut self) {
+        let Some(memo) = self.open.pop() else { return };
+        match self.open.last_mut() {
+            Some(parent) => parent.children_mut().push(memo),
+            None => self.roots.push(memo),
+        }
+    }
+}
+
+/// Nest memos in document order under their headings. Each gets its anchor: the slug of its
+/// title, and for the n-th repeat of a slug `slug-n` (`notes`, `notes-1`, `notes-2`), skipping
+/// anchors already taken. Also returns a warning for each heading that skips a level.
+pub(crate) fn build_hierarchy(flat_memos: impl IntoIterator<Item = Memo>) -> (Vec<Memo>, Vec<ParseWarning>) {
+    let mut hierarchy = Hierarchy::default();
+    for memo in flat_memos {
+        hierarchy.push(memo);
+    }
+    hierarchy.finish()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::schema::{MemoBuilder, Level};
-    use super::parse_memo;
+    use crate::schema::{DiagramKind, LinkKind, MemoBuilder, Level, ParseWarningKind};
+    use super::{
+        find_memo_by_slug, normalize_relative_path, normalize_source, parse_document, parse_memo, parse_memo_with,
+        resolve_image_paths, subtree_markdown, table_of_contents, truncate_depth, ParseOptions,
+    };
 
     #[test]
     fn test_simple_hierarchy() {
@@ -760,10 +1392,14 @@ More content.
         assert_eq!(result.len(), 1);
         let memo = &result[0];
         assert_eq!(memo.title(), "Multiple Descriptions");
-        // Should only capture the first desc tag
+        // `description` stays the first one, `descriptions` keeps all of them in order
         assert_eq!(memo.description(), &Some("First description".to_string()));
+        assert_eq!(
+            memo.descriptions(),
+            &vec!["First description".to_string(), "Second description".to_string()]
+        );
         assert!(memo.content().as_ref().unwrap().contains("Some content"));
-        assert!(memo.content().as_ref().unwrap().contains("Second description"));
+        assert!(!memo.content().as_ref().unwrap().contains("Second description"));
     }
 
     #[test]
@@ -785,4 +1421,675 @@ Implementation here.
         assert_eq!(memo.description(), &Some(expected_desc.to_string()));
         assert!(memo.content().as_ref().unwrap().contains("Implementation here"));
     }
+
+    #[test]
+    fn test_code_block_info_string_metadata() {
+        let content = r#"
+# Example
+
+```rust title=main.rs {3-5} showLineNumbers
+fn main() {
+    let x = 1;
+    let y = 2;
+    let z = 3;
+    println!("{}", x + y + z);
+}
+```
+"#;
+        let result = parse_memo(content);
+        let code_block = &result[0].code_blocks()[0];
+        assert_eq!(code_block.language, "rust");
+        assert_eq!(code_block.filename, Some("main.rs".to_string()));
+        assert_eq!(code_block.highlight_lines, vec![3, 4, 5]);
+        assert_eq!(code_block.flags, vec!["showLineNumbers".to_string()]);
+    }
+
+    #[test]
+    fn test_code_block_info_string_quoted_title_and_ranges() {
+        let content = r#"
+# Example
+
+```ts filename="src/app main.ts" {1, 4-5,2}
+const a = 1;
+const b = 2;
+const c = 3;
+const d = 4;
+const e = 5;
+```
+"#;
+        let result = parse_memo(content);
+        let code_block = &result[0].code_blocks()[0];
+        assert_eq!(code_block.language, "ts");
+        assert_eq!(code_block.filename, Some("src/app main.ts".to_string()));
+        assert_eq!(code_block.highlight_lines, vec![1, 2, 4, 5]);
+        assert!(code_block.flags.is_empty());
+    }
+
+    #[test]
+    fn test_code_block_line_ranges_clamped_to_block() {
+        let content = "# Example\n\n```rust {2-18446744073709551615,9} hl_lines=\"1-100000000\"\nfn a() {}\nfn b() {}\nfn c() {}\n```\n";
+        let result = parse_memo(content);
+        assert_eq!(result[0].code_blocks()[0].highlight_lines, vec![1, 2, 3]);
+
+        // Lines count from 1
+        let content = "# Example\n\n```rust {0,0-3,3-1,2}\nfn a() {}\nfn b() {}\nfn c() {}\n```\n";
+        let result = parse_memo(content);
+        assert_eq!(result[0].code_blocks()[0].highlight_lines, vec![2]);
+    }
+
+    #[test]
+    fn test_code_block_info_string_without_language() {
+        let content = r#"
+# Example
+
+```title=notes.txt
+plain
+```
+"#;
+        let result = parse_memo(content);
+        let code_block = &result[0].code_blocks()[0];
+        assert_eq!(code_block.language, "");
+        assert_eq!(code_block.filename, Some("notes.txt".to_string()));
+    }
+
+    #[test]
+    fn test_tilde_fence() {
+        let content = r#"
+# Tilde
+
+~~~python
+print("hi")
+~~~
+"#;
+        let result = parse_memo(content);
+        assert_eq!(result[0].code_blocks().len(), 1);
+        assert_eq!(result[0].code_blocks()[0].language, "python");
+        assert_eq!(result[0].code_blocks()[0].code, "print(\"hi\")");
+    }
+
+    #[test]
+    fn test_longer_fence_contains_backtick_fence() {
+        let content = r#"
+# Nested Fence
+
+````markdown
+```rust
+fn main() {}
+```
+````
+
+After.
+"#;
+        let result = parse_memo(content);
+        let memo = &result[0];
+        assert_eq!(memo.code_blocks().len(), 1);
+        assert_eq!(memo.code_blocks()[0].language, "markdown");
+        assert_eq!(memo.code_blocks()[0].code, "```rust\nfn main() {}\n```");
+        assert!(memo.content().as_ref().unwrap().contains("After."));
+    }
+
+    #[test]
+    fn test_tilde_fence_not_closed_by_backticks() {
+        let content = r#"
+# Mixed
+
+~~~
+```
+still code
+~~~
+"#;
+        let result = parse_memo(content);
+        assert_eq!(result[0].code_blocks().len(), 1);
+        assert_eq!(result[0].code_blocks()[0].code, "```\nstill code");
+    }
+
+    #[test]
+    fn test_indented_fence_strips_indentation() {
+        let content = r#"
+# Indented
+
+- step one
+  ```bash
+  echo one
+    echo nested
+  ```
+"#;
+        let result = parse_memo(content);
+        let memo = &result[0];
+        assert_eq!(memo.code_blocks().len(), 1);
+        assert_eq!(memo.code_blocks()[0].language, "bash");
+        assert_eq!(memo.code_blocks()[0].code, "echo one\n  echo nested");
+        assert!(memo.content().as_ref().unwrap().contains("- step one"));
+    }
+
+    #[test]
+    fn test_closing_fence_must_be_long_enough() {
+        let content = r#"
+# Lengths
+
+`````
+```
+````
+`````
+"#;
+        let result = parse_memo(content);
+        assert_eq!(result[0].code_blocks().len(), 1);
+        assert_eq!(result[0].code_blocks()[0].code, "```\n````");
+    }
+
+    #[test]
+    fn test_hash_without_space_is_not_heading() {
+        let content = r#"
+# Setup
+#!/bin/bash
+#include <stdio.h>
+#hashtag
+"#;
+        let result = parse_memo(content);
+        assert_eq!(result.len(), 1);
+        let memo_content = result[0].content().as_ref().unwrap();
+        assert!(memo_content.contains("#!/bin/bash"));
+        assert!(memo_content.contains("#include <stdio.h>"));
+        assert!(memo_content.contains("#hashtag"));
+    }
+
+    #[test]
+    fn test_indented_and_escaped_hash_is_not_heading() {
+        let content = r#"
+# Install
+Run these:
+    # install dependencies
+    apt-get install -y curl
+  # also not a heading
+\# not a heading either
+"#;
+        let result = parse_memo(content);
+        assert_eq!(result.len(), 1);
+        assert!(result[0].children().is_empty());
+        let memo_content = result[0].content().as_ref().unwrap();
+        assert!(memo_content.contains("    # install dependencies"));
+        assert!(memo_content.contains("\\# not a heading either"));
+    }
+
+    #[test]
+    fn test_bash_comments_in_fences_and_html_comments() {
+        let content = r#"
+# Deploy
+
+```bash
+# build the image
+docker build .
+```
+
+<!--
+# draft section
+-->
+
+## Verify
+curl localhost
+"#;
+        let result = parse_memo(content);
+        assert_eq!(result.len(), 1);
+        let deploy = &result[0];
+        assert_eq!(deploy.code_blocks()[0].code, "# build the image\ndocker build .");
+        assert!(deploy.content().as_ref().unwrap().contains("# draft section"));
+        assert_eq!(deploy.children().len(), 1);
+        assert_eq!(deploy.children()[0].title(), "Verify");
+    }
+
+    #[test]
+    fn test_heading_closing_sequence_and_too_many_hashes() {
+        let content = r#"
+## Closed ##
+####### seven is too many
+# C# Notes
+"#;
+        let result = parse_memo(content);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].title(), "Closed");
+        assert!(result[0].content().as_ref().unwrap().contains("####### seven is too many"));
+        assert_eq!(result[1].title(), "C# Notes");
+    }
+
+    #[test]
+    fn test_display_math_block() {
+        let content = r#"
+# Energy
+Einstein said:
+
+$$
+E = mc^2
+$$
+
+and more.
+"#;
+        let result = parse_memo(content);
+        let memo = &result[0];
+        assert_eq!(memo.math_blocks().len(), 1);
+        assert_eq!(memo.math_blocks()[0].tex, "E = mc^2");
+        assert!(memo.math_blocks()[0].display);
+        let memo_content = memo.content().as_ref().unwrap();
+        assert!(!memo_content.contains("mc^2"));
+        assert!(memo_content.contains("and more."));
+    }
+
+    #[test]
+    fn test_single_line_display_and_inline_math() {
+        let content = r#"
+# Formulas
+$$ a^2 + b^2 = c^2 $$
+The area is $\pi r^2$ but it costs $5 and $10.
+
+## Child
+$x$
+"#;
+        let result = parse_memo(content);
+        let memo = &result[0];
+        assert_eq!(memo.math_blocks().len(), 2);
+        assert_eq!(memo.math_blocks()[0].tex, "a^2 + b^2 = c^2");
+        assert!(memo.math_blocks()[0].display);
+        assert_eq!(memo.math_blocks()[1].tex, "\\pi r^2");
+        assert!(!memo.math_blocks()[1].display);
+        let child = &memo.children()[0];
+        assert_eq!(child.math_blocks().len(), 1);
+        assert_eq!(child.math_blocks()[0].tex, "x");
+    }
+
+    #[test]
+    fn test_dollars_inside_code_fence_are_not_math() {
+        let content = r#"
+# Shell
+```bash
+echo $$
+echo "$HOME"
+```
+"#;
+        let result = parse_memo(content);
+        assert!(result[0].math_blocks().is_empty());
+        assert_eq!(result[0].code_blocks()[0].code, "echo $$\necho \"$HOME\"");
+    }
+
+    #[test]
+    fn test_mermaid_fence_becomes_diagram() {
+        let content = r#"
+# Flow
+
+```mermaid
+graph TD
+  A --> B
+```
+
+```rust
+fn main() {}
+```
+"#;
+        let result = parse_memo(content);
+        let memo = &result[0];
+        assert_eq!(memo.diagrams().len(), 1);
+        assert_eq!(memo.diagrams()[0].kind, DiagramKind::Mermaid);
+        assert_eq!(memo.diagrams()[0].source, "graph TD\n  A --> B");
+        assert_eq!(memo.code_blocks().len(), 1);
+        assert_eq!(memo.code_blocks()[0].language, "rust");
+    }
+
+    #[test]
+    fn test_links_per_memo() {
+        let content = r#"
+# Links
+Read [the guide](./guide.fmemo#setup), [RFC](https://www.rfc-editor.org/rfc/rfc9110)
+and https://example.com/docs. Also [[Other Note]] and `https://not.a.link`.
+
+## Child
+[mail](mailto:me@example.com)
+"#;
+        let result = parse_memo(content);
+        let links = result[0].links();
+        assert_eq!(links.len(), 4);
+        assert_eq!(links[0].text, "the guide");
+        assert_eq!(links[0].url, "./guide.fmemo#setup");
+        assert_eq!(links[0].kind, LinkKind::Internal);
+        assert_eq!(links[1].kind, LinkKind::External);
+        assert_eq!(links[2].url, "https://example.com/docs");
+        assert_eq!(links[2].kind, LinkKind::External);
+        assert_eq!(links[3].url, "Other Note");
+        assert_eq!(links[3].kind, LinkKind::Wiki);
+
+        let child_links = result[0].children()[0].links();
+        assert_eq!(child_links.len(), 1);
+        assert_eq!(child_links[0].kind, LinkKind::External);
+    }
+
+    #[test]
+    fn test_image_references_resolved_relative_to_file() {
+        let content = r#"
+# Screenshots
+![login](../assets/login.png) and ![logo](https://example.com/logo.svg)
+
+## Detail
+![flow](img/flow.png#zoom)
+![escape](../../../etc/passwd)
+"#;
+        let mut result = parse_memo(content);
+        resolve_image_paths(&mut result, "notes/app.fmemo");
+
+        let images = result[0].images();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].alt, "login");
+        assert_eq!(images[0].src, "../assets/login.png");
+        assert_eq!(images[0].path, Some("assets/login.png".to_string()));
+        assert_eq!(images[1].path, None);
+
+        let child_images = result[0].children()[0].images();
+        assert_eq!(child_images[0].path, Some("notes/img/flow.png".to_string()));
+        assert_eq!(child_images[1].path, None);
+        // Images are not reported as links
+        assert!(result[0].links().iter().all(|l| l.url != "../assets/login.png"));
+    }
+
+    #[test]
+    fn test_normalize_relative_path() {
+        assert_eq!(normalize_relative_path("a/b", "./c.png"), Some("a/b/c.png".to_string()));
+        assert_eq!(normalize_relative_path("a/b", "/c.png"), Some("c.png".to_string()));
+        assert_eq!(normalize_relative_path("", "../c.png"), None);
+    }
+
+    #[test]
+    fn test_private_tags_are_dropped() {
+        let result = parse_memo("# A\nPublic <private>and <status>done</status> mine</private>\n<private>\n## B\nDiary\n</private>");
+        assert_eq!(result[0].content().as_deref(), Some("Public and  mine"));
+        assert_eq!(result[0].metadata()["status"], vec!["done".to_string()]);
+        assert_eq!(result[0].children()[0].content().as_deref(), Some("Diary"));
+    }
+
+    #[test]
+    fn test_metadata_tags() {
+        let content = r#"
+# Release
+<desc>Ship it</desc>
+<status>doing</status>
+<due>2024-05-01</due>
+<todo>write notes</todo>
+Body text.
+<todo> tag the release </todo>
+<unknown>stays</unknown>
+
+## Child
+<priority>high</priority>
+"#;
+        let result = parse_memo(content);
+        let memo = &result[0];
+        assert_eq!(memo.description(), &Some("Ship it".to_string()));
+        assert_eq!(memo.metadata()["status"], vec!["doing".to_string()]);
+        assert_eq!(memo.metadata()["due"], vec!["2024-05-01".to_string()]);
+        assert_eq!(
+            memo.metadata()["todo"],
+            vec!["write notes".to_string(), "tag the release".to_string()]
+        );
+        assert!(!memo.metadata().contains_key("unknown"));
+        let memo_content = memo.content().as_ref().unwrap();
+        assert!(!memo_content.contains("<status>"));
+        assert!(memo_content.contains("Body text."));
+        assert!(memo_content.contains("<unknown>stays</unknown>"));
+        assert_eq!(memo.children()[0].metadata()["priority"], vec!["high".to_string()]);
+    }
+
+    #[test]
+    fn test_metadata_tags_are_configurable() {
+        let content = "# Task\n<owner>kai</owner>\n<status>todo</status>\n";
+        let options = ParseOptions {
+            metadata_tags: vec!["owner".to_string()],
+        };
+        let result = parse_memo_with(content, &options);
+        let memo = &result[0];
+        assert_eq!(memo.metadata()["owner"], vec!["kai".to_string()]);
+        assert!(!memo.metadata().contains_key("status"));
+        assert!(memo.content().as_ref().unwrap().contains("<status>todo</status>"));
+    }
+
+    #[test]
+    fn test_unclosed_metadata_tag_left_in_content() {
+        let result = parse_memo("# Task\n<due>tomorrow\n");
+        assert!(result[0].metadata().is_empty());
+        assert!(result[0].content().as_ref().unwrap().contains("<due>tomorrow"));
+    }
+
+    #[test]
+    fn test_desc_after_child_heading_belongs_to_child() {
+        let content = r#"
+# Parent
+<desc>Parent description</desc>
+
+## Child
+Child content.
+<desc>Child description</desc>
+
+# Sibling
+"#;
+        let result = parse_memo(content);
+        assert_eq!(result.len(), 2);
+        let parent = &result[0];
+        assert_eq!(parent.descriptions(), &vec!["Parent description".to_string()]);
+        let child = &parent.children()[0];
+        assert_eq!(child.descriptions(), &vec!["Child description".to_string()]);
+        assert!(result[1].descriptions().is_empty());
+        assert_eq!(result[1].description(), &None);
+    }
+
+    #[test]
+    fn test_desc_between_children_belongs_to_preceding_child() {
+        let content = r#"
+# Parent
+## First
+<desc>one</desc>
+## Second
+<desc>two</desc>
+<desc>three</desc>
+"#;
+        let result = parse_memo(content);
+        let parent = &result[0];
+        assert!(parent.descriptions().is_empty());
+        assert_eq!(parent.children()[0].descriptions(), &vec!["one".to_string()]);
+        assert_eq!(
+            parent.children()[1].descriptions(),
+            &vec!["two".to_string(), "three".to_string()]
+        );
+        assert_eq!(parent.children()[1].description(), &Some("two".to_string()));
+    }
+
+    #[test]
+    fn test_parse_warnings() {
+        let content = r#"# Title
+<desc>never closed
+
+### Skipped
+$$
+x
+"#;
+        let document = parse_document(content, &ParseOptions::default());
+        let kinds: Vec<(usize, ParseWarningKind)> =
+            document.warnings.iter().map(|w| (w.line, w.kind)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (2, ParseWarningKind::UnclosedDesc),
+                (4, ParseWarningKind::SkippedHeadingLevel),
+                (5, ParseWarningKind::UnclosedMathBlock),
+            ]
+        );
+        assert_eq!(document.memos.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_warning_unclosed_fence() {
+        let content = "# Code\n\n```rust\nfn main() {}\n";
+        let document = parse_document(content, &ParseOptions::default());
+        assert_eq!(document.warnings.len(), 1);
+        assert_eq!(document.warnings[0].line, 3);
+        assert_eq!(document.warnings[0].kind, ParseWarningKind::UnclosedCodeFence);
+    }
+
+    #[test]
+    fn test_no_warnings_for_clean_document() {
+        let content = "# A\n<desc>one\ntwo</desc>\n## B\n```\ncode\n```\n# C\n### D after reset is still skipped\n";
+        let document = parse_document(content, &ParseOptions::default());
+        assert_eq!(document.warnings.len(), 1);
+        assert_eq!(document.warnings[0].kind, ParseWarningKind::SkippedHeadingLevel);
+        assert_eq!(document.warnings[0].line, 9);
+    }
+
+    const PLATFORM_SAMPLE: &str = "# Title\n<desc>Multi\nline</desc>\nSome text\n\n```bash\necho hi\n```\n\n## Child\n<status>done</status>\n- item\n";
+
+    fn with_line_ending(source: &str, ending: &str) -> String {
+        source.replace('\n', ending)
+    }
+
+    #[test]
+    fn test_crlf_output_identical_to_lf() {
+        let lf = parse_document(PLATFORM_SAMPLE, &ParseOptions::default());
+        let crlf = parse_document(&with_line_ending(PLATFORM_SAMPLE, "\r\n"), &ParseOptions::default());
+        assert_eq!(lf, crlf);
+        assert_eq!(crlf.memos[0].title(), "Title");
+        assert_eq!(crlf.memos[0].description(), &Some("Multi\nline".to_string()));
+        assert_eq!(crlf.memos[0].code_blocks()[0].code, "echo hi");
+    }
+
+    #[test]
+    fn test_classic_mac_cr_output_identical_to_lf() {
+        let lf = parse_document(PLATFORM_SAMPLE, &ParseOptions::default());
+        let cr = parse_document(&with_line_ending(PLATFORM_SAMPLE, "\r"), &ParseOptions::default());
+        assert_eq!(lf, cr);
+    }
+
+    #[test]
+    fn test_bom_and_trailing_whitespace_ignored() {
+        let lf = parse_document(PLATFORM_SAMPLE, &ParseOptions::default());
+        let noisy = format!(
+            "\u{feff}{}",
+            with_line_ending(PLATFORM_SAMPLE, " \t\r\n")
+        );
+        let parsed = parse_document(&noisy, &ParseOptions::default());
+        assert_eq!(lf, parsed);
+        assert_eq!(parsed.memos.len(), 1);
+        assert_eq!(parsed.memos[0].children()[0].title(), "Child");
+    }
+
+    #[test]
+    fn test_normalize_source_borrows_clean_input() {
+        assert!(matches!(normalize_source("# a\nb\n"), std::borrow::Cow::Borrowed(_)));
+        assert_eq!(normalize_source("a\r\nb \r"), "a\nb\n");
+    }
+
+    #[test]
+    fn test_source_spans() {
+        let content = "intro\n# A\ntext\n\n## B\n```\n# not heading\n```\n# C";
+        let result = parse_memo(content);
+        let a = &result[0];
+        assert_eq!(a.span().map(|s| (s.start_line, s.end_line)), Some((2, 4)));
+        assert_eq!(a.children()[0].span().map(|s| (s.start_line, s.end_line)), Some((5, 8)));
+        assert_eq!(a.subtree_end_line(), Some(8));
+        assert_eq!(result[1].span().map(|s| (s.start_line, s.end_line)), Some((9, 9)));
+    }
+
+    #[test]
+    fn test_repeated_headings_get_unique_anchors() {
+        let content = "# Notes\n## Notes\n# Notes 1\n# Notes\n## Other";
+        let memos = parse_memo(content);
+        assert_eq!(memos[0].anchor(), "notes");
+        assert_eq!(memos[0].children()[0].anchor(), "notes-1");
+        // A heading of its own already took notes-1
+        assert_eq!(memos[1].anchor(), "notes-1-1");
+        assert_eq!(memos[2].anchor(), "notes-2");
+        assert_eq!(find_memo_by_slug(&memos, "notes-2").unwrap().children()[0].title(), "Other");
+        // Same input, same anchors
+        assert_eq!(
+            parse_memo(content).iter().map(|m| m.anchor().into_owned()).collect::<Vec<_>>(),
+            memos.iter().map(|m| m.anchor().into_owned()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_truncate_depth() {
+        let mut memos = parse_memo("# Top\n## Plan A\n### Step\n## Plan B\n# Other");
+        truncate_depth(&mut memos, 2);
+        let plan = &memos[0].children()[0];
+        assert!(plan.children().is_empty());
+        assert_eq!(plan.children_count(), Some(1));
+        assert_eq!(plan.children_slugs().unwrap(), &vec!["step".to_string()]);
+        assert_eq!(memos[0].children_count(), None);
+        assert_eq!(memos[1].children_count(), None);
+
+        truncate_depth(&mut memos, 1);
+        assert_eq!(memos[0].children_count(), Some(2));
+        assert_eq!(memos[0].children_slugs().unwrap(), &vec!["plan-a".to_string(), "plan-b".to_string()]);
+        assert_eq!(memos[1].children_count(), Some(0));
+    }
+
+    #[test]
+    fn test_table_of_contents() {
+        let toc = table_of_contents(&parse_memo("# Top\ntext\n```sh\n# not a heading\n```\n## Plan\n### Step\n## Plan\n# Other"));
+        assert_eq!(toc.len(), 2);
+        assert_eq!((toc[0].title.as_str(), toc[0].level, toc[0].line), ("Top", 1, 1));
+        let plans: Vec<_> = toc[0].children.iter().map(|entry| (entry.slug.as_str(), entry.line)).collect();
+        assert_eq!(plans, [("plan", 6), ("plan-1", 8)]);
+        assert_eq!(toc[0].children[0].children[0].level, 3);
+        assert!(toc[1].children.is_empty());
+    }
+
+    #[test]
+    fn test_subtree_markdown() {
+        let content = "# Top\n## Plan\ntext\n```sh\n# comment\n```\n### Step one\n- a\n\n## Other\nmore";
+        let memos = parse_memo(content);
+        let plan = find_memo_by_slug(&memos, "plan").unwrap();
+        assert_eq!(
+            subtree_markdown(content, plan).unwrap(),
+            "# Plan\ntext\n```sh\n# comment\n```\n## Step one\n- a\n"
+        );
+        let step = find_memo_by_slug(&memos, "step-one").unwrap();
+        assert_eq!(subtree_markdown(content, step).unwrap(), "# Step one\n- a\n");
+        assert!(find_memo_by_slug(&memos, "missing").is_none());
+
+        // Children dropped after parsing are left out
+        let mut memos = parse_memo(content);
+        memos[0].children_mut().pop();
+        memos[0].children_mut()[0].children_mut().clear();
+        assert_eq!(
+            subtree_markdown(content, &memos[0]).unwrap(),
+            "# Top\n## Plan\ntext\n```sh\n# comment\n```\n"
+        );
+    }
+
+    #[test]
+    fn test_created_and_updated_tags() {
+        let content = "# A\n<created>2024-01-02</created>\n<updated>2024-03-04T05:06:07+09:00</updated>\nbody\n# B\n<updated>soon</updated>";
+        let document = parse_document(content, &ParseOptions::default());
+        let a = &document.memos[0];
+        assert_eq!(a.created().map(|t| t.to_rfc3339()), Some("2024-01-02T00:00:00+00:00".to_string()));
+        assert_eq!(a.updated().map(|t| t.to_rfc3339()), Some("2024-03-04T05:06:07+09:00".to_string()));
+        assert_eq!(a.content(), &Some("body".to_string()));
+        assert_eq!(document.memos[1].updated(), None);
+        assert_eq!(document.warnings.len(), 1);
+        assert_eq!(document.warnings[0].kind, ParseWarningKind::InvalidTimestamp);
+        assert_eq!(document.warnings[0].line, 5);
+    }
+
+    #[test]
+    fn test_front_matter_timestamps_are_defaults() {
+        let content = "---\ntitle: Notes\ncreated: 2024-01-01\nupdated: \"2024-02-01 10:00\"\n  # yaml comment\n---\n# A\n# B\n<updated>2024-05-05</updated>";
+        let result = parse_memo(content);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].created().map(|t| t.to_rfc3339()), Some("2024-01-01T00:00:00+00:00".to_string()));
+        assert_eq!(result[0].updated().map(|t| t.to_rfc3339()), Some("2024-02-01T10:00:00+00:00".to_string()));
+        assert_eq!(result[1].updated().map(|t| t.to_rfc3339()), Some("2024-05-05T00:00:00+00:00".to_string()));
+        assert_eq!(result[0].span().map(|s| s.start_line), Some(7));
+    }
+
+    #[test]
+    fn test_opening_rule_before_heading_is_not_front_matter() {
+        let content = "---\n# A\ntext\n---\n## B\nmore";
+        let result = parse_memo(content);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].title(), "A");
+        assert_eq!(result[0].children()[0].title(), "B");
+        assert_eq!(super::front_matter_value(content, "title"), None);
+    }
 }
@@ -0,0 +1,178 @@
+//! Plugins extending the parser and the server without forking fmemo.
+//!
+//! A plugin can collect extra inline tags into `Memo::metadata`, transform the parsed
+//! memos of every file and, when written in Rust, add routes. Rust plugins are registered
+//! with `FmemoServer::plugin`. With the `wasm-plugins` feature, WebAssembly plugins are
+//! also loaded from `<root>/.fmemo/plugins/*.wasm` (see [`wasm`] for their interface).
+
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use warp::Filter;
+use warp::filters::BoxedFilter;
+
+use crate::parser::{ParseOptions, ParsedDocument, parse_document};
+use crate::schema::Memo;
+
+#[cfg(feature = "wasm-plugins")]
+pub mod wasm;
+
+/// Routes a plugin adds to the server
+pub type PluginRoutes = BoxedFilter<(Box<dyn warp::Reply>,)>;
+
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Extra inline tags (`<name>value</name>`) collected into `Memo::metadata`
+    fn metadata_tags(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Change the memos of a file after parsing
+    fn transform(&self, _memos: &mut Vec<Memo>) {}
+
+    /// Routes served next to the API. Keep them below `/api/plugins/<name>/` so they share
+    /// the API's token check.
+    fn routes(&self) -> Option<PluginRoutes> {
+        None
+    }
+}
+
+/// The plugins of a server, applied in the order they were added
+#[derive(Clone, Default)]
+pub struct Plugins(Vec<Arc<dyn Plugin>>);
+
+impl fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|plugin| plugin.name()))
+            .finish()
+    }
+}
+
+impl Plugins {
+    pub fn add(&mut self, plugin: Arc<dyn Plugin>) {
+        self.0.push(plugin);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The default parse options plus every plugin's tags
+    pub fn parse_options(&self) -> ParseOptions {
+        let mut options = ParseOptions::default();
+        for tag in self.0.iter().flat_map(|plugin| plugin.metadata_tags()) {
+            if !options.metadata_tags.contains(&tag) {
+                options.metadata_tags.push(tag);
+            }
+        }
+        options
+    }
+
+    /// Add the plugins installed in `<root>/.fmemo/plugins`. Without the `wasm-plugins`
+    /// feature there is nothing to load.
+    pub fn load_installed(&mut self, root: &Path) -> std::io::Result<()> {
+        #[cfg(feature = "wasm-plugins")]
+        self.0.extend(wasm::load_plugins(root)?);
+        #[cfg(not(feature = "wasm-plugins"))]
+        let _ = root;
+        Ok(())
+    }
+
+    /// Run every plugin's transform over freshly parsed memos
+    pub fn transform(&self, memos: &mut Vec<Memo>) {
+        for plugin in &self.0 {
+            plugin.transform(memos);
+        }
+    }
+
+    /// Parse a file with the plugins' tags and transforms
+    pub fn parse(&self, content: &str) -> ParsedDocument {
+        let mut document = parse_document(content, &self.parse_options());
+        self.transform(&mut document.memos);
+        document
+    }
+
+    /// All plugin routes combined; `None` when no plugin has any
+    pub fn routes(&self) -> Option<PluginRoutes> {
+        self.0
+            .iter()
+            .filter_map(|plugin| plugin.routes())
+            .reduce(|all, routes| all.or(routes).unify().boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Plugin, PluginRoutes, Plugins};
+    use crate::schema::Memo;
+    use std::sync::Arc;
+    use warp::Filter;
+
+    /// Drops memos tagged `<visibility>private</visibility>`
+    struct HidePrivate;
+
+    impl Plugin for HidePrivate {
+        fn name(&self) -> &str {
+            "hide-private"
+        }
+
+        fn metadata_tags(&self) -> Vec<String> {
+            vec!["visibility".to_string(), "tag".to_string()]
+        }
+
+        fn transform(&self, memos: &mut Vec<Memo>) {
+            memos.retain(|memo| {
+                memo.metadata()
+                    .get("visibility")
+                    .is_none_or(|values| !values.iter().any(|value| value == "private"))
+            });
+            for memo in memos {
+                self.transform(memo.children_mut());
+            }
+        }
+
+        fn routes(&self) -> Option<PluginRoutes> {
+            Some(
+                warp::path!("api" / "plugins" / "hide-private")
+                    .map(|| Box::new("on") as Box<dyn warp::Reply>)
+                    .boxed(),
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plugins_extend_parsing_and_routes() {
+        let mut plugins = Plugins::default();
+        assert!(plugins.routes().is_none());
+        plugins.add(Arc::new(HidePrivate));
+        assert_eq!(format!("{:?}", plugins), r#"["hide-private"]"#);
+
+        let options = plugins.parse_options();
+        assert_eq!(
+            options
+                .metadata_tags
+                .iter()
+                .filter(|tag| *tag == "tag")
+                .count(),
+            1
+        );
+
+        let document = plugins.parse(
+            "# Public\n## Secret\n<visibility>private</visibility>\n## Shared\n<visibility>team</visibility>",
+        );
+        let children = document.memos[0].children();
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].title(), "Shared");
+        assert_eq!(children[0].metadata()["visibility"], ["team"]);
+
+        let routes = plugins.routes().unwrap();
+        let response = warp::test::request()
+            .path("/api/plugins/hide-private")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.body(), "on");
+    }
+}
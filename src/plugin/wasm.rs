@@ -0,0 +1,245 @@
+//! WebAssembly plugins from `<root>/.fmemo/plugins/*.wasm`, run with wasmi.
+//!
+//! Data crosses the boundary as UTF-8 JSON in the module's memory. A module exports:
+//!
+//! - `memory`
+//! - `fmemo_alloc(len: i32) -> i32` - a buffer of `len` bytes for the host to write into
+//! - `fmemo_metadata_tags() -> i64` (optional) - a JSON array of tag names
+//! - `fmemo_transform(ptr: i32, len: i32) -> i64` (optional) - gets the JSON array of a
+//!   file's memos, returns the transformed array
+//!
+//! Returned strings are packed as `ptr << 32 | len`. The plugin is named after its file.
+//! Every call is limited in fuel, so a looping plugin fails instead of hanging the server.
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use wasmi::{Config, Engine, Instance, Linker, Memory, Module, Store};
+
+use super::Plugin;
+use crate::config::FMEMO_DIR;
+use crate::schema::Memo;
+
+/// Fuel for a single call into a plugin
+const FUEL_PER_CALL: u64 = 1_000_000_000;
+
+/// A loaded WebAssembly plugin
+pub struct WasmPlugin {
+    name: String,
+    metadata_tags: Vec<String>,
+    instance: Mutex<(Store<()>, Instance)>,
+}
+
+fn invalid(name: &str, message: impl std::fmt::Display) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("Plugin '{}': {}", name, message),
+    )
+}
+
+impl WasmPlugin {
+    /// Compile and instantiate a module, reading its metadata tags
+    pub fn load(name: &str, wasm: &[u8]) -> std::io::Result<Self> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm).map_err(|e| invalid(name, e))?;
+        let mut store = Store::new(&engine, ());
+        store
+            .set_fuel(FUEL_PER_CALL)
+            .map_err(|e| invalid(name, e))?;
+        let instance = Linker::<()>::new(&engine)
+            .instantiate_and_start(&mut store, &module)
+            .map_err(|e| invalid(name, e))?;
+
+        let mut plugin = Self {
+            name: name.to_string(),
+            metadata_tags: Vec::new(),
+            instance: Mutex::new((store, instance)),
+        };
+        if let Some(tags) = plugin.call_json("fmemo_metadata_tags", None)? {
+            plugin.metadata_tags = serde_json::from_str(&tags).map_err(|e| invalid(name, e))?;
+        }
+        Ok(plugin)
+    }
+
+    /// Call an export taking an optional string and returning one;
+    /// `Ok(None)` if the module doesn't export `function`
+    fn call_json(&self, function: &str, input: Option<&str>) -> std::io::Result<Option<String>> {
+        let mut guard = self.instance.lock().unwrap_or_else(|e| e.into_inner());
+        let (store, instance) = &mut *guard;
+        if instance.get_func(&*store, function).is_none() {
+            return Ok(None);
+        }
+        store
+            .set_fuel(FUEL_PER_CALL)
+            .map_err(|e| invalid(&self.name, e))?;
+        let memory = instance
+            .get_memory(&*store, "memory")
+            .ok_or_else(|| invalid(&self.name, "no exported memory"))?;
+
+        let packed = match input {
+            None => instance
+                .get_typed_func::<(), i64>(&*store, function)
+                .and_then(|func| func.call(&mut *store, ())),
+            Some(input) => {
+                let len = i32::try_from(input.len()).map_err(|e| invalid(&self.name, e))?;
+                let ptr = instance
+                    .get_typed_func::<i32, i32>(&*store, "fmemo_alloc")
+                    .and_then(|alloc| alloc.call(&mut *store, len))
+                    .map_err(|e| invalid(&self.name, e))?;
+                memory
+                    .write(&mut *store, ptr as u32 as usize, input.as_bytes())
+                    .map_err(|e| invalid(&self.name, e))?;
+                instance
+                    .get_typed_func::<(i32, i32), i64>(&*store, function)
+                    .and_then(|func| func.call(&mut *store, (ptr, len)))
+            }
+        }
+        .map_err(|e| invalid(&self.name, e))?;
+
+        read_string(&self.name, store, memory, packed as u64).map(Some)
+    }
+}
+
+fn read_string(
+    name: &str,
+    store: &Store<()>,
+    memory: Memory,
+    packed: u64,
+) -> std::io::Result<String> {
+    let (ptr, len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+    let mut buffer = vec![0; len];
+    memory
+        .read(store, ptr, &mut buffer)
+        .map_err(|e| invalid(name, e))?;
+    String::from_utf8(buffer).map_err(|e| invalid(name, e))
+}
+
+impl Plugin for WasmPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn metadata_tags(&self) -> Vec<String> {
+        self.metadata_tags.clone()
+    }
+
+    /// A failing plugin leaves the memos as they were
+    fn transform(&self, memos: &mut Vec<Memo>) {
+        let result = serde_json::to_string(&*memos)
+            .map_err(|e| invalid(&self.name, e))
+            .and_then(|input| self.call_json("fmemo_transform", Some(&input)));
+        match result {
+            Ok(Some(output)) => match serde_json::from_str(&output) {
+                Ok(transformed) => *memos = transformed,
+                Err(e) => eprintln!("Plugin '{}' returned invalid memos: {}", self.name, e),
+            },
+            Ok(None) => {}
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+/// Load every `.wasm` file in `<root>/.fmemo/plugins`, sorted by name
+pub fn load_plugins(root: &Path) -> std::io::Result<Vec<Arc<dyn Plugin>>> {
+    let dir = root.join(FMEMO_DIR).join("plugins");
+    let mut paths = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .map(|entry| Ok(entry?.path()))
+            .collect::<std::io::Result<Vec<_>>>()?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    paths.retain(|path| path.extension().and_then(|s| s.to_str()) == Some("wasm"));
+    paths.sort();
+
+    let mut plugins: Vec<Arc<dyn Plugin>> = Vec::new();
+    for path in paths {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        plugins.push(Arc::new(WasmPlugin::load(&name, &std::fs::read(&path)?)?));
+    }
+    Ok(plugins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WasmPlugin, load_plugins};
+    use crate::config::FMEMO_DIR;
+    use crate::plugin::{Plugin, Plugins};
+    use crate::schema::{Level, MemoBuilder};
+    use std::fs;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    /// A module returning `tags` from `fmemo_metadata_tags` and `output` from every transform
+    fn module(tags: &str, output: &str) -> Vec<u8> {
+        let escape =
+            |text: &str| -> String { text.bytes().map(|byte| format!("\\{:02x}", byte)).collect() };
+        let packed = |offset: usize, len: usize| ((offset as u64) << 32) | len as u64;
+        wat::parse_str(format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{tags}")
+                (data (i32.const 1024) "{output}")
+                (func (export "fmemo_alloc") (param i32) (result i32) i32.const 4096)
+                (func (export "fmemo_metadata_tags") (result i64) i64.const {tags_packed})
+                (func (export "fmemo_transform") (param i32 i32) (result i64) i64.const {output_packed}))"#,
+            tags = escape(tags),
+            output = escape(output),
+            tags_packed = packed(0, tags.len()),
+            output_packed = packed(1024, output.len()),
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_wasm_plugin() {
+        let replaced = MemoBuilder::new(Level::new(1), "Replaced".to_string()).build();
+        let output = serde_json::to_string(&[replaced]).unwrap();
+        let plugin = WasmPlugin::load("demo", &module(r#"["owner"]"#, &output)).unwrap();
+        assert_eq!(plugin.metadata_tags(), ["owner"]);
+
+        let mut plugins = Plugins::default();
+        plugins.add(Arc::new(plugin));
+        assert!(
+            plugins
+                .parse_options()
+                .metadata_tags
+                .contains(&"owner".to_string())
+        );
+        let document = plugins.parse("# A\n<owner>kai</owner>");
+        assert_eq!(document.memos[0].title(), "Replaced");
+
+        // Invalid output leaves the memos alone
+        let broken = WasmPlugin::load("broken", &module("[]", "not json")).unwrap();
+        let mut memos = crate::parser::parse_memo("# A");
+        broken.transform(&mut memos);
+        assert_eq!(memos[0].title(), "A");
+    }
+
+    #[test]
+    fn test_load_plugins() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(load_plugins(temp_dir.path()).unwrap().is_empty());
+
+        let dir = temp_dir.path().join(FMEMO_DIR).join("plugins");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("b.wasm"), module("[]", "[]")).unwrap();
+        fs::write(dir.join("a.wasm"), module("[]", "[]")).unwrap();
+        fs::write(dir.join("notes.txt"), "ignored").unwrap();
+        let names: Vec<String> = load_plugins(temp_dir.path())
+            .unwrap()
+            .iter()
+            .map(|plugin| plugin.name().to_string())
+            .collect();
+        assert_eq!(names, ["a", "b"]);
+
+        fs::write(dir.join("c.wasm"), "not wasm").unwrap();
+        let error = load_plugins(temp_dir.path()).err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,157 @@
+//! Who is viewing which file, for showing other people on a note.
+//!
+//! A WebSocket client sends `{"type": "viewing", "path": "notes/a.fmemo", "name": "Kai"}`
+//! when it opens a file (`"path": null` when it closes it; `name` is optional). Every
+//! change is broadcast to all clients as
+//! `{"type": "presence", "files": {"notes/a.fmemo": [{"client": 3, "name": "Kai"}]}}`,
+//! which new clients also get when they connect.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::schema::{Viewer as PresenceViewer, WsClientMessage, WsServerMessage};
+use crate::server::ClientId;
+
+/// Longest name kept for a viewer, in characters
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Viewer {
+    path: String,
+    name: Option<String>,
+}
+
+/// Files open in the connected clients; clones share the state
+#[derive(Debug, Clone, Default)]
+pub struct Presence {
+    viewers: Arc<Mutex<BTreeMap<ClientId, Viewer>>>,
+}
+
+impl Presence {
+    /// The `presence` message describing every viewer
+    pub fn snapshot(&self) -> WsServerMessage {
+        let viewers = self.viewers.lock().unwrap_or_else(|e| e.into_inner());
+        let mut files: BTreeMap<String, Vec<PresenceViewer>> = BTreeMap::new();
+        for (client, viewer) in viewers.iter() {
+            files
+                .entry(viewer.path.clone())
+                .or_default()
+                .push(PresenceViewer {
+                    client: *client,
+                    name: viewer.name.clone(),
+                });
+        }
+        WsServerMessage::Presence { files }
+    }
+
+    /// Handle a message from a client; returns the update to broadcast when it changed
+    /// what the client views
+    pub fn handle_message(
+        &self,
+        client: ClientId,
+        message: &WsClientMessage,
+    ) -> Option<WsServerMessage> {
+        let WsClientMessage::Viewing { path, name } = message else {
+            return None;
+        };
+        let viewer = path
+            .as_deref()
+            .map(|path| path.trim_start_matches('/'))
+            .filter(|path| !path.is_empty())
+            .map(|path| Viewer {
+                path: path.to_string(),
+                name: name
+                    .as_deref()
+                    .map(|name| name.chars().take(MAX_NAME_LEN).collect()),
+            });
+        let changed = {
+            let mut viewers = self.viewers.lock().unwrap_or_else(|e| e.into_inner());
+            match viewer {
+                Some(viewer) => viewers.insert(client, viewer.clone()) != Some(viewer),
+                None => viewers.remove(&client).is_some(),
+            }
+        };
+        changed.then(|| self.snapshot())
+    }
+
+    /// Forget a closed connection; returns the update to broadcast if it was viewing a file
+    pub fn disconnect(&self, client: ClientId) -> Option<WsServerMessage> {
+        let removed = self
+            .viewers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&client)
+            .is_some();
+        removed.then(|| self.snapshot())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Presence;
+    use crate::schema::WsClientMessage;
+    use serde_json::{Value, json};
+
+    fn message(value: Value) -> WsClientMessage {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn value<T: serde::Serialize>(message: T) -> Value {
+        serde_json::to_value(message).unwrap()
+    }
+
+    #[test]
+    fn test_presence() {
+        let presence = Presence::default();
+        assert_eq!(
+            value(presence.snapshot()),
+            json!({"type": "presence", "files": {}})
+        );
+
+        let update = value(
+            presence
+                .handle_message(
+                    1,
+                    &message(json!({"type": "viewing", "path": "a.fmemo", "name": "Kai"})),
+                )
+                .unwrap(),
+        );
+        assert_eq!(
+            update["files"],
+            json!({"a.fmemo": [{"client": 1, "name": "Kai"}]})
+        );
+        presence.handle_message(2, &message(json!({"type": "viewing", "path": "/a.fmemo"})));
+        presence.handle_message(3, &message(json!({"type": "viewing", "path": "b.md"})));
+        assert_eq!(
+            value(presence.snapshot())["files"]["a.fmemo"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // Nothing changed
+        assert!(
+            presence
+                .handle_message(3, &message(json!({"type": "viewing", "path": "b.md"})))
+                .is_none()
+        );
+        assert!(
+            presence
+                .handle_message(3, &message(json!({"type": "edit_leave", "path": "b.md"})))
+                .is_none()
+        );
+
+        let update = value(
+            presence
+                .handle_message(3, &message(json!({"type": "viewing", "path": null})))
+                .unwrap(),
+        );
+        assert!(update["files"].get("b.md").is_none());
+        assert_eq!(
+            value(presence.disconnect(1).unwrap())["files"],
+            json!({"a.fmemo": [{"client": 2, "name": null}]})
+        );
+        assert!(presence.disconnect(1).is_none());
+    }
+}
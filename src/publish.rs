@@ -0,0 +1,86 @@
+//! Publish mode: one set of memo files served both as the personal view and as a public,
+//! read-only site (`fmemo serve --publish`).
+//!
+//! `<private>...</private>` regions are for the personal view only. Normally the parser just
+//! drops the tags; a server in publish mode (`ApiOptions::publish`, `WatcherOptions::publish`,
+//! and the indexes it builds) and the site exports read memo files with the regions cut out
+//! (`read_memo`, past git revisions and drafts), so no response, index or broadcast built
+//! from them can show a private line. A region may span several lines and headings; one
+//! that is never closed hides the rest of the file.
+
+use std::borrow::Cow;
+use std::io;
+use std::path::Path;
+
+pub const OPEN_TAG: &str = "<private>";
+pub const CLOSE_TAG: &str = "</private>";
+
+/// `content` without its `<private>...</private>` regions
+pub fn strip_private(content: &str) -> Cow<'_, str> {
+    if !content.contains(OPEN_TAG) {
+        return Cow::Borrowed(content);
+    }
+    let mut visible = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find(OPEN_TAG) {
+        visible.push_str(&rest[..start]);
+        match rest[start..].find(CLOSE_TAG) {
+            Some(end) => rest = &rest[start + end + CLOSE_TAG.len()..],
+            None => {
+                rest = "";
+                break;
+            }
+        }
+    }
+    visible.push_str(rest);
+    Cow::Owned(visible)
+}
+
+/// Memo file content as it may be shown, without its private regions when `publish` is set
+pub fn visible(publish: bool, content: String) -> String {
+    if publish && content.contains(OPEN_TAG) {
+        strip_private(&content).into_owned()
+    } else {
+        content
+    }
+}
+
+/// `crypt::read_memo`, without the private regions when `publish` is set
+pub fn read_memo(path: &Path, publish: bool) -> io::Result<String> {
+    crate::crypt::read_memo(path).map(|content| visible(publish, content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_strip_private() {
+        assert_eq!(
+            strip_private(
+                "# A\nPublic <private>secret</private>text\n<private>\n## Diary\nx\n</private>\n## B"
+            ),
+            "# A\nPublic text\n\n## B"
+        );
+        assert_eq!(
+            strip_private("# A\nkept\n<private>never closed\n# B"),
+            "# A\nkept\n"
+        );
+        assert!(matches!(strip_private("# A"), Cow::Borrowed("# A")));
+    }
+
+    #[test]
+    fn test_read_memo() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("a.fmemo");
+        let content = "# A\n<private>secret</private>";
+        fs::write(&path, content).unwrap();
+
+        assert_eq!(read_memo(&path, true).unwrap(), "# A\n");
+        // Nothing is left behind for readers that aren't publishing
+        assert_eq!(read_memo(&path, false).unwrap(), content);
+        assert_eq!(crate::crypt::read_memo(&path).unwrap(), content);
+    }
+}
@@ -0,0 +1,215 @@
+//! "See also" suggestions for a memo file, for `GET /api/files/{path}/related`.
+//!
+//! Every other file gets a score from the tags it shares with the file, links between the
+//! two (either way) and how similar their text is: TF-IDF weighted term overlap, or the
+//! similarity of their memos' embeddings when the index keeps them.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::embeddings::EmbeddingIndex;
+use crate::graph::resolve_link;
+use crate::indexer::Documents;
+use crate::schema::Memo;
+use crate::search::searchable_text;
+
+/// Score of each shared tag
+const TAG_WEIGHT: f32 = 2.0;
+/// Score of a link in either direction
+const LINK_WEIGHT: f32 = 3.0;
+/// Score of identical text
+const SIMILARITY_WEIGHT: f32 = 5.0;
+/// Words shorter than this don't count as terms
+const MIN_TERM_LEN: usize = 3;
+
+/// A file suggested as related, with why
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct RelatedFile {
+    pub file: String,
+    /// Title of the file's first memo
+    pub title: Option<String>,
+    pub score: f32,
+    pub shared_tags: Vec<String>,
+    /// One of the files links to the other
+    pub linked: bool,
+    /// Text similarity between 0 and 1
+    pub similarity: f32,
+}
+
+fn walk<'a>(memos: &'a [Memo], f: &mut impl FnMut(&'a Memo)) {
+    for memo in memos {
+        f(memo);
+        walk(memo.children(), f);
+    }
+}
+
+fn tags(memos: &[Memo]) -> BTreeSet<String> {
+    let mut tags = BTreeSet::new();
+    walk(memos, &mut |memo| {
+        tags.extend(memo.metadata().get("tag").into_iter().flatten().cloned());
+    });
+    tags
+}
+
+/// Files (other than itself) that `file` links to
+fn link_targets(files: &[String], file: &str, memos: &[Memo]) -> BTreeSet<String> {
+    let mut targets = BTreeSet::new();
+    walk(memos, &mut |memo| {
+        for link in memo.links() {
+            if let Some(target) = resolve_link(files, file, link.kind, &link.url)
+                && target != file
+            {
+                targets.insert(target);
+            }
+        }
+    });
+    targets
+}
+
+fn term_counts(memos: &[Memo]) -> HashMap<String, f32> {
+    let mut counts = HashMap::new();
+    walk(memos, &mut |memo| {
+        let text = searchable_text(memo).to_lowercase();
+        for term in text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|term| term.chars().count() >= MIN_TERM_LEN)
+        {
+            *counts.entry(term.to_string()).or_insert(0.0) += 1.0;
+        }
+    });
+    counts
+}
+
+/// Cosine similarity of the TF-IDF vectors of `file` and every other file
+fn term_similarities(documents: &Documents, file: &str) -> HashMap<String, f32> {
+    let counts: HashMap<&String, HashMap<String, f32>> = documents
+        .iter()
+        .map(|(path, memos)| (path, term_counts(memos)))
+        .collect();
+    let mut document_frequency: HashMap<&str, f32> = HashMap::new();
+    for terms in counts.values() {
+        for term in terms.keys() {
+            *document_frequency.entry(term).or_insert(0.0) += 1.0;
+        }
+    }
+    let total = counts.len() as f32;
+    let weigh = |terms: &HashMap<String, f32>| -> HashMap<String, f32> {
+        terms
+            .iter()
+            .map(|(term, count)| {
+                // Terms in every file weigh nothing
+                let idf = (total / document_frequency[term.as_str()]).ln();
+                (term.clone(), count * idf)
+            })
+            .collect()
+    };
+    let norm = |vector: &HashMap<String, f32>| vector.values().map(|w| w * w).sum::<f32>().sqrt();
+
+    let Some(own) = counts.get(&file.to_string()).map(weigh) else {
+        return HashMap::new();
+    };
+    let own_norm = norm(&own);
+    counts
+        .iter()
+        .filter(|(path, _)| path.as_str() != file)
+        .map(|(path, terms)| {
+            let other = weigh(terms);
+            let dot: f32 = own
+                .iter()
+                .filter_map(|(term, weight)| Some(weight * other.get(term)?))
+                .sum();
+            let norms = own_norm * norm(&other);
+            let similarity = if norms == 0.0 { 0.0 } else { dot / norms };
+            (path.to_string(), similarity)
+        })
+        .collect()
+}
+
+/// Up to `limit` files related to `file`, best first; `None` when the file isn't indexed
+pub fn related_files(
+    documents: &Documents,
+    embeddings: Option<&EmbeddingIndex>,
+    file: &str,
+    limit: usize,
+) -> Option<Vec<RelatedFile>> {
+    let memos = documents.get(file)?;
+    let files: Vec<String> = documents.keys().cloned().collect();
+    let own_tags = tags(memos);
+    let own_links = link_targets(&files, file, memos);
+    let similarities = match embeddings.filter(|embeddings| !embeddings.is_empty()) {
+        Some(embeddings) => embeddings.file_similarities(file),
+        None => term_similarities(documents, file),
+    };
+
+    let mut related: Vec<RelatedFile> = documents
+        .iter()
+        .filter(|(path, _)| path.as_str() != file)
+        .filter_map(|(path, other)| {
+            let shared_tags: Vec<String> = tags(other).intersection(&own_tags).cloned().collect();
+            let linked =
+                own_links.contains(path) || link_targets(&files, path, other).contains(file);
+            let similarity = similarities.get(path).copied().unwrap_or(0.0).max(0.0);
+            let score = shared_tags.len() as f32 * TAG_WEIGHT
+                + if linked { LINK_WEIGHT } else { 0.0 }
+                + similarity * SIMILARITY_WEIGHT;
+            (score > 0.0).then(|| RelatedFile {
+                file: path.clone(),
+                title: other.first().map(|memo| memo.title().clone()),
+                score,
+                shared_tags,
+                linked,
+                similarity,
+            })
+        })
+        .collect();
+    // Ties by path, so the order is stable
+    related.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.file.cmp(&b.file))
+    });
+    related.truncate(limit);
+    Some(related)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::related_files;
+    use crate::indexer::Documents;
+    use crate::parser::{ParseOptions, parse_document};
+
+    #[test]
+    fn test_related_files() {
+        let mut documents = Documents::new();
+        let mut add = |file: &str, content: &str| {
+            let memos = parse_document(content, &ParseOptions::default()).memos;
+            documents.insert(file.to_string(), memos);
+        };
+        add(
+            "rust.fmemo",
+            "# Rust\n<tag>lang</tag>\nownership and borrowing in the compiler",
+        );
+        add("go.fmemo", "# Go\n<tag>lang</tag>\ngoroutines");
+        add(
+            "borrow.fmemo",
+            "# Borrowing\nownership rules, borrowing rules",
+        );
+        add("links.fmemo", "# Index\n[[rust]]");
+        add("cooking.fmemo", "# Pasta\nboil water");
+
+        let related = related_files(&documents, None, "rust.fmemo", 10).unwrap();
+        let files: Vec<&str> = related.iter().map(|r| r.file.as_str()).collect();
+        assert_eq!(files, ["links.fmemo", "go.fmemo", "borrow.fmemo"]);
+        assert!(related[0].linked);
+        assert_eq!(related[1].shared_tags, ["lang"]);
+        assert!(related[2].similarity > 0.0 && !related[2].linked);
+        assert_eq!(related[0].title.as_deref(), Some("Index"));
+
+        assert_eq!(
+            related_files(&documents, None, "rust.fmemo", 1)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(related_files(&documents, None, "missing.fmemo", 10).is_none());
+    }
+}
@@ -0,0 +1,452 @@
+//! Memo bodies as HTML, for clients that don't render Markdown themselves.
+//!
+//! The HTML is generated from the parsed blocks and spans rather than the raw Markdown,
+//! so raw HTML in a memo is escaped like any other text. Link targets are limited to
+//! relative paths and `http`, `https` and `mailto` URLs; local images point at
+//! `/api/assets/...`. Embeds are links, or the embedded memos when `resolve_embeds` expanded
+//! them and the embed is a paragraph of its own.
+//!
+//! For an exported static site (`site_sections_html`), wiki-links, embeds and links to memo
+//! files point at the pages of the files they resolve to, and local images are relative to
+//! the page.
+
+use crate::graph::{resolve_link, wiki_name};
+use crate::parser::normalize_relative_path;
+use crate::schema::{ContentBlock, Embed, LinkKind, Memo, Span};
+use crate::sitemap::encode_path;
+
+/// Escape text for HTML element content and attribute values
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A link target that is safe to put in `href`; `None` for other schemes
+fn safe_url(url: &str) -> Option<&str> {
+    let url = url.trim();
+    match LinkKind::classify(url) {
+        LinkKind::Internal => Some(url),
+        _ => {
+            let scheme = url
+                .split_once(':')
+                .map(|(scheme, _)| scheme.to_ascii_lowercase());
+            matches!(scheme.as_deref(), Some("http" | "https" | "mailto")).then_some(url)
+        }
+    }
+}
+
+/// Page of an exported site that shows the memo file `file`
+pub fn page_path(file: &str) -> String {
+    format!("{}.html", wiki_name(file))
+}
+
+/// `target` (relative to the root) as a URL relative to the page of `file`
+pub fn relative_url(file: &str, target: &str) -> String {
+    format!(
+        "{}{}",
+        "../".repeat(file.matches('/').count()),
+        encode_path(target)
+    )
+}
+
+struct Renderer<'a> {
+    /// The memo's file, relative to the root
+    file_path: &'a str,
+    /// Directory of the memo's file, for local images
+    base_dir: &'a str,
+    /// Memo files of the exported site, when rendering its pages
+    site: Option<&'a [String]>,
+    /// The memo's embeds, in the order their spans come up
+    embeds: &'a [Embed],
+    next_embed: usize,
+    html: String,
+}
+
+impl Renderer<'_> {
+    /// `href` of the page a link resolves to, when rendering a site
+    fn page_href(&self, kind: LinkKind, url: &str) -> Option<String> {
+        let target = resolve_link(self.site?, self.file_path, kind, url)?;
+        let mut href = relative_url(self.file_path, &page_path(&target));
+        if let Some((_, anchor)) = url.split_once('#') {
+            href.push('#');
+            href.push_str(&encode_path(anchor));
+        }
+        Some(href)
+    }
+
+    /// `href` attribute (with a leading space) of a wiki-link or embed, if any
+    fn wiki_href(&self, target: &str) -> String {
+        self.page_href(LinkKind::Wiki, target)
+            .map(|href| format!(" href=\"{}\"", escape_html(&href)))
+            .unwrap_or_default()
+    }
+
+    fn spans(&mut self, spans: &[Span]) {
+        for span in spans {
+            match span {
+                Span::Text { text } => self.html.push_str(&escape_html(text)),
+                Span::Code { code } => self
+                    .html
+                    .push_str(&format!("<code>{}</code>", escape_html(code))),
+                Span::Bold { children } => {
+                    self.html.push_str("<strong>");
+                    self.spans(children);
+                    self.html.push_str("</strong>");
+                }
+                Span::Italic { children } => {
+                    self.html.push_str("<em>");
+                    self.spans(children);
+                    self.html.push_str("</em>");
+                }
+                Span::Link { url, children } => match safe_url(url) {
+                    Some(url) => {
+                        let href = match LinkKind::classify(url) {
+                            LinkKind::Internal => self.page_href(LinkKind::Internal, url),
+                            _ => None,
+                        };
+                        self.html.push_str(&format!(
+                            "<a href=\"{}\">",
+                            escape_html(href.as_deref().unwrap_or(url))
+                        ));
+                        self.spans(children);
+                        self.html.push_str("</a>");
+                    }
+                    None => self.spans(children),
+                },
+                Span::WikiLink { target, label } => self.html.push_str(&format!(
+                    "<a class=\"wiki-link\" data-target=\"{}\"{}>{}</a>",
+                    escape_html(target),
+                    self.wiki_href(target),
+                    escape_html(label.as_deref().unwrap_or(target))
+                )),
+                Span::Embed { target } => {
+                    self.next_embed += 1;
+                    self.html.push_str(&format!(
+                        "<a class=\"embed\" data-target=\"{}\"{}>{}</a>",
+                        escape_html(target),
+                        self.wiki_href(target),
+                        escape_html(target)
+                    ));
+                }
+                Span::Image { alt, src } => {
+                    let src = match LinkKind::classify(src) {
+                        LinkKind::Internal => {
+                            normalize_relative_path(self.base_dir, src).map(|path| {
+                                match self.site {
+                                    Some(_) => relative_url(self.file_path, &path),
+                                    None => format!("/api/assets/{}", path),
+                                }
+                            })
+                        }
+                        _ => safe_url(src).map(str::to_string),
+                    };
+                    match src {
+                        Some(src) => self.html.push_str(&format!(
+                            "<img src=\"{}\" alt=\"{}\">",
+                            escape_html(&src),
+                            escape_html(alt)
+                        )),
+                        None => self.html.push_str(&escape_html(alt)),
+                    }
+                }
+                Span::Math { tex } => self
+                    .html
+                    .push_str(&format!("<span class=\"math\">{}</span>", escape_html(tex))),
+            }
+        }
+    }
+
+    /// Paragraphs, quotes and (nested) lists
+    fn blocks(&mut self, blocks: &[ContentBlock]) {
+        // Open lists, innermost last, each with an open `<li>`
+        let mut lists: Vec<bool> = Vec::new();
+        let close = |html: &mut String, ordered: bool| {
+            html.push_str(if ordered { "</li></ol>" } else { "</li></ul>" })
+        };
+        for block in blocks {
+            let ContentBlock::ListItem {
+                depth,
+                ordered,
+                spans,
+            } = block
+            else {
+                while let Some(ordered) = lists.pop() {
+                    close(&mut self.html, ordered);
+                }
+                if let ContentBlock::Paragraph { spans } = block
+                    && let [Span::Embed { target }] = spans.as_slice()
+                    && let Some(html) = self
+                        .embeds
+                        .get(self.next_embed)
+                        .and_then(|embed| embed.html.as_ref())
+                {
+                    self.next_embed += 1;
+                    self.html.push_str(&format!(
+                        "<div class=\"embed\" data-target=\"{}\">{}</div>",
+                        escape_html(target),
+                        html
+                    ));
+                    continue;
+                }
+                let tag = match block {
+                    ContentBlock::Quote { .. } => "blockquote",
+                    _ => "p",
+                };
+                self.html.push_str(&format!("<{}>", tag));
+                self.spans(block.spans());
+                self.html.push_str(&format!("</{}>", tag));
+                continue;
+            };
+
+            while lists.len() > depth + 1 {
+                let ordered = lists.pop().unwrap_or_default();
+                close(&mut self.html, ordered);
+            }
+            if lists.len() == depth + 1 {
+                if lists.last() == Some(ordered) {
+                    self.html.push_str("</li>");
+                } else {
+                    let previous = lists.pop().unwrap_or_default();
+                    close(&mut self.html, previous);
+                }
+            }
+            while lists.len() < depth + 1 {
+                self.html.push_str(if *ordered { "<ol>" } else { "<ul>" });
+                lists.push(*ordered);
+            }
+            self.html.push_str("<li>");
+            self.spans(spans);
+        }
+        while let Some(ordered) = lists.pop() {
+            close(&mut self.html, ordered);
+        }
+    }
+}
+
+/// HTML of a memo's own body (not its children): descriptions, content, display math,
+/// code blocks and diagrams. `file_path` is the memo's file relative to the root.
+pub fn memo_html(memo: &Memo, file_path: &str) -> String {
+    body_html(memo, file_path, None)
+}
+
+fn body_html(memo: &Memo, file_path: &str, site: Option<&[String]>) -> String {
+    let mut renderer = Renderer {
+        file_path,
+        base_dir: file_path.rfind('/').map_or("", |idx| &file_path[..idx]),
+        site,
+        embeds: memo.embeds(),
+        next_embed: 0,
+        html: String::new(),
+    };
+    for description in memo.descriptions() {
+        renderer.html.push_str(&format!(
+            "<p class=\"description\">{}</p>",
+            escape_html(description)
+        ));
+    }
+    renderer.blocks(memo.content_blocks());
+    for math in memo.math_blocks().iter().filter(|math| math.display) {
+        renderer.html.push_str(&format!(
+            "<div class=\"math\">{}</div>",
+            escape_html(&math.tex)
+        ));
+    }
+    for block in memo.code_blocks() {
+        let code = match &block.highlighted_html {
+            Some(html) => html.clone(),
+            None => escape_html(&block.code),
+        };
+        if block.language.is_empty() {
+            renderer
+                .html
+                .push_str(&format!("<pre><code>{}</code></pre>", code));
+        } else {
+            renderer.html.push_str(&format!(
+                "<pre><code class=\"language-{}\">{}</code></pre>",
+                escape_html(&block.language),
+                code
+            ));
+        }
+    }
+    for diagram in memo.diagrams() {
+        let kind = serde_json::to_value(diagram.kind).unwrap_or_default();
+        renderer.html.push_str(&format!(
+            "<pre class=\"diagram {}\">{}</pre>",
+            kind.as_str().unwrap_or_default(),
+            escape_html(&diagram.source)
+        ));
+    }
+    renderer.html
+}
+
+/// Set `content_html` on the memos and their children
+pub fn render_memos(memos: &mut [Memo], file_path: &str) {
+    for memo in memos {
+        let html = memo_html(memo, file_path);
+        memo.set_content_html(Some(html));
+        render_memos(memo.children_mut(), file_path);
+    }
+}
+
+/// Every memo's heading and body, children nested in `<section>`s
+pub fn sections_html(memos: &[Memo], file_path: &str) -> String {
+    sections(memos, file_path, None)
+}
+
+/// `sections_html` for a page of an exported site with the memo files `files`
+pub fn site_sections_html(memos: &[Memo], file_path: &str, files: &[String]) -> String {
+    sections(memos, file_path, Some(files))
+}
+
+fn sections(memos: &[Memo], file_path: &str, site: Option<&[String]>) -> String {
+    let mut html = String::new();
+    for memo in memos {
+        let tag = format!("h{}", (memo.level().level() + 1).min(6));
+        html.push_str(&format!(
+            "<section><{tag} id=\"{}\">{}</{tag}>",
+            escape_html(&memo.anchor()),
+            escape_html(memo.title())
+        ));
+        html.push_str(&body_html(memo, file_path, site));
+        html.push_str(&sections(memo.children(), file_path, site));
+        html.push_str("</section>");
+    }
+    html
+}
+
+/// A whole file as a standalone HTML page, see `sections_html`
+pub fn document_html(memos: &[Memo], file_path: &str) -> String {
+    let title = memos
+        .first()
+        .map_or(file_path, |memo| memo.title().as_str());
+    let mut html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{}</title></head><body>",
+        escape_html(title)
+    );
+    html.push_str(&sections_html(memos, file_path));
+    html.push_str("</body></html>");
+    html
+}
+
+/// Style of `print_html`: a plain serif page, each top-level memo starting a new sheet,
+/// headings kept with what follows and code, quotes and images not split across sheets
+const PRINT_STYLE: &str = "\
+@page { margin: 2cm 1.8cm; }
+body { font-family: Georgia, 'Times New Roman', serif; font-size: 11pt; line-height: 1.5; \
+color: #000; background: #fff; max-width: 46em; margin: 0 auto; }
+@media screen { body { padding: 2em 1em; } }
+header.print { font: 9pt sans-serif; color: #555; border-bottom: 1px solid #ccc; margin-bottom: 1em; }
+main > section + section { break-before: page; page-break-before: always; }
+h1, h2, h3, h4, h5, h6 { break-after: avoid; page-break-after: avoid; }
+pre, blockquote, img, .embed, .math { break-inside: avoid; page-break-inside: avoid; }
+pre { white-space: pre-wrap; font-size: 9pt; border: 1px solid #ccc; padding: 0.5em; }
+img { max-width: 100%; }
+a { color: inherit; }
+a[href^='http']::after { content: ' (' attr(href) ')'; font-size: 9pt; color: #555; }
+p.description { font-style: italic; }
+";
+
+/// A whole file as a page for printing (or saving as PDF from the browser): like
+/// `document_html` with a print style sheet and the file's path above the memos
+pub fn print_html(memos: &[Memo], file_path: &str) -> String {
+    let title = memos
+        .first()
+        .map_or(file_path, |memo| memo.title().as_str());
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{}</title>\
+         <style>{}</style></head><body><header class=\"print\">{}</header><main>{}</main>\
+         </body></html>",
+        escape_html(title),
+        PRINT_STYLE,
+        escape_html(file_path),
+        sections_html(memos, file_path)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{document_html, memo_html, print_html, render_memos};
+    use crate::parser::{ParseOptions, parse_document};
+
+    fn render(content: &str) -> String {
+        let document = parse_document(content, &ParseOptions::default());
+        memo_html(&document.memos[0], "notes/a.fmemo")
+    }
+
+    #[test]
+    fn test_memo_html() {
+        assert_eq!(
+            render("# A\nSome **bold** and `code` with [[Plan|a plan]].\n\n> quoted"),
+            "<p>Some <strong>bold</strong> and <code>code</code> with \
+             <a class=\"wiki-link\" data-target=\"Plan\">a plan</a>.</p>\
+             <blockquote>quoted</blockquote>"
+        );
+        assert_eq!(
+            render("# A\n- one\n  - nested\n- two\n1. first"),
+            "<ul><li>one<ul><li>nested</li></ul></li><li>two</li></ul><ol><li>first</li></ol>"
+        );
+        assert_eq!(
+            render("# A\n![pic](img/p.png)\n\n```rust\nlet x = 1 < 2;\n```"),
+            "<p><img src=\"/api/assets/notes/img/p.png\" alt=\"pic\"></p>\
+             <pre><code class=\"language-rust\">let x = 1 &lt; 2;</code></pre>"
+        );
+    }
+
+    #[test]
+    fn test_memo_html_is_sanitized() {
+        let html = render(
+            "# A\n<script>alert(1)</script>\n\n[click](javascript:alert(1)) <img src=x onerror=alert(1)>",
+        );
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("<img"));
+        assert!(!html.contains("href"));
+        assert!(html.contains("&lt;script&gt;"));
+    }
+
+    #[test]
+    fn test_document_html() {
+        let document = parse_document("# A & B\ntext\n## C\nmore", &ParseOptions::default());
+        assert_eq!(
+            document_html(&document.memos, "a.fmemo"),
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>A &amp; B</title></head><body>\
+             <section><h1 id=\"a-b\">A &amp; B</h1><p>text</p>\
+             <section><h2 id=\"c\">C</h2><p>more</p></section></section></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_print_html() {
+        let document = parse_document("# A\ntext\n# B", &ParseOptions::default());
+        let html = print_html(&document.memos, "notes/a.fmemo");
+        assert!(html.starts_with(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>A</title><style>"
+        ));
+        assert!(html.contains("main > section + section { break-before: page;"));
+        assert!(html.ends_with(
+            "<header class=\"print\">notes/a.fmemo</header><main>\
+             <section><h1 id=\"a\">A</h1><p>text</p></section>\
+             <section><h1 id=\"b\">B</h1></section></main></body></html>"
+        ));
+    }
+
+    #[test]
+    fn test_render_memos() {
+        let mut document = parse_document("# A\ntext\n## B\nmore", &ParseOptions::default());
+        render_memos(&mut document.memos, "a.fmemo");
+        assert_eq!(document.memos[0].content_html().unwrap(), "<p>text</p>");
+        assert_eq!(
+            document.memos[0].children()[0].content_html().unwrap(),
+            "<p>more</p>"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! Request IDs, to match what a user reports with the server's logs.
+//!
+//! Every request gets an ID: the client's `X-Request-Id` when it sends a usable one, a new
+//! random one otherwise. The ID comes back in the `X-Request-Id` response header, in the
+//! `request_id` field of JSON error bodies, and in the access log.
+
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::convert::Infallible;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use warp::Filter;
+use warp::http::HeaderValue;
+use warp::reply::Response;
+
+/// Header carrying the ID in both directions
+pub const HEADER: &str = "x-request-id";
+
+thread_local! {
+    /// ID of the response `attach` finished last on this thread. The access log runs right
+    /// after, in the same poll, and picks it up with `take_finished`.
+    static FINISHED: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Whether a client's ID can be used as is: up to 128 letters, digits and `-_.:`
+pub fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+}
+
+/// A new random ID of 16 hex digits
+pub fn generate() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    // Each `RandomState` has its own random keys
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    format!("{:016x}", hasher.finish())
+}
+
+/// The request's ID
+pub fn request_id() -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
+    // A header that isn't even text counts as missing
+    warp::header::optional::<String>(HEADER)
+        .or(warp::any().map(|| None))
+        .unify()
+        .map(|id: Option<String>| id.filter(|id| is_valid(id)).unwrap_or_else(generate))
+}
+
+/// Put the ID into the response's header and, for JSON errors, its body
+pub async fn attach(response: Response, id: String) -> Response {
+    let is_json_error = response.status().as_u16() >= 400
+        && response
+            .headers()
+            .get(warp::http::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("application/json"));
+    let mut response = if is_json_error {
+        let (mut parts, body) = response.into_parts();
+        let body = match warp::hyper::body::to_bytes(body).await {
+            Ok(bytes) => match serde_json::from_slice::<serde_json::Value>(&bytes) {
+                Ok(serde_json::Value::Object(mut error)) => {
+                    error.insert("request_id".to_string(), id.clone().into());
+                    parts.headers.remove(warp::http::header::CONTENT_LENGTH);
+                    serde_json::Value::Object(error).to_string().into()
+                }
+                _ => bytes.into(),
+            },
+            Err(_) => warp::hyper::Body::empty(),
+        };
+        Response::from_parts(parts, body)
+    } else {
+        response
+    };
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(HEADER, value);
+    }
+    FINISHED.with(|finished| *finished.borrow_mut() = Some(id));
+    response
+}
+
+/// ID of the response just finished on this thread, see `attach`
+pub fn take_finished() -> Option<String> {
+    FINISHED.with(|finished| finished.borrow_mut().take())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, is_valid};
+
+    #[test]
+    fn test_ids() {
+        assert!(is_valid("3f2a-9b01_req.1:2"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("with space"));
+        assert!(!is_valid(&"x".repeat(129)));
+        let (a, b) = (generate(), generate());
+        assert_eq!(a.len(), 16);
+        assert!(is_valid(&a));
+        assert_ne!(a, b);
+    }
+}
@@ -6,30 +6,331 @@ pub struct DirectoryTree {
     pub subdirectories: Vec<DirectoryTree>,
 }
 
+/// One file of GET /api/files - every memo file below the root, without nesting
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct FileEntry {
+    /// Path relative to the root, with `/` separators
+    pub path: String,
+    /// Size in bytes
+    pub size: u64,
+    pub last_modified: Option<u64>,
+    /// Text of the first heading
+    pub title: Option<String>,
+    /// Times the file was fetched through GET /api/files/{filepath}
+    #[serde(default)]
+    pub views: u64,
+}
+
 /// Response for GET /api/files/{filepath} - file content
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
 pub struct FileContent {
     pub memos: Vec<Memo>,
     pub last_modified: Option<u64>,
+    /// Problems found while parsing, e.g. an unclosed code fence
+    #[serde(default)]
+    pub warnings: Vec<ParseWarning>,
+}
+
+/// Request body for PUT /api/file/{filepath} - new file content
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct WriteFileRequest {
+    pub content: String,
+    /// Stamp `<created>` / `<updated>` into the memos this write changes
+    #[serde(default)]
+    pub auto_stamp: bool,
+}
+
+/// Request body for PUT /api/files/{filepath}/draft - unsaved editor content
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct DraftRequest {
+    pub content: String,
+}
+
+/// Request body for POST /api/files/{filepath}/restore - the git revision to bring back
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct RestoreRequest {
+    pub rev: String,
+}
+
+/// Request body for POST /api/login
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
 }
 
+/// Request body for POST /api/files/{path}/memos/{slug}/comments
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct CommentRequest {
+    pub body: String,
+    /// Name to show when nobody is logged in; ignored for logged in users and tokens
+    #[serde(default)]
+    pub author: Option<String>,
+}
+
+/// Request body for POST /api/archive
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct ArchiveRequest {
+    /// Age like `1y` or `30d`; `[archive] older_than` when omitted
+    #[serde(default)]
+    pub older_than: Option<String>,
+    /// Only report what would be moved
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Request body for POST /api/files/merge
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct MergeRequest {
+    /// File merged in, relative to the root
+    pub source: String,
+    /// File merged into, relative to the root
+    pub target: String,
+    #[serde(default)]
+    pub mode: crate::merge::MergeMode,
+    /// Heading level (1 for `#`) of the source's top-level memos in the target
+    #[serde(default)]
+    pub level: Option<u8>,
+    #[serde(default)]
+    pub source_action: crate::merge::SourceAction,
+}
+
+/// Request body for POST /api/files/{path}/split
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct SplitRequest {
+    #[serde(default)]
+    pub source_action: crate::merge::SourceAction,
+}
+
+/// Request body for POST /api/tokens
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct TokenRequest {
+    pub name: String,
+    /// `read` when not given
+    #[serde(default)]
+    pub scope: crate::config::Permission,
+}
+
+/// Request body for POST /api/unlock; key files are for `fmemo serve --key-file` only
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct UnlockRequest {
+    pub passphrase: String,
+}
+
+/// Request body for POST /api/files/{filepath}/summarize
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct SummarizeRequest {
+    /// Anchor of the memo to summarize with its children; the whole file when omitted
+    #[serde(default)]
+    pub memo: Option<String>,
+    /// Also store the summary as the memo's `<desc>` (the first top-level memo's for the
+    /// whole file)
+    #[serde(default)]
+    pub write: bool,
+}
+
+/// Request body for POST /api/pins - pin or unpin a file
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct PinRequest {
+    /// File path relative to the root
+    pub path: String,
+    /// Pins the file unless `false`
+    #[serde(default)]
+    pub pinned: Option<bool>,
+}
+
+/// Request body for POST /api/files/from-template - create a memo from a template
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct NewMemoRequest {
+    pub title: String,
+    /// Template name in `.fmemo/templates/`; `default` (or the built-in one) when omitted
+    #[serde(default)]
+    pub template: Option<String>,
+    /// File path relative to the root; derived from the title when omitted
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+/// A heading of a file's table of contents (`GET /api/files/{path}/toc`)
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct TocEntry {
+    pub title: String,
+    /// The memo's anchor
+    pub slug: String,
+    /// 1 for `#`, 2 for `##`, ...
+    pub level: u8,
+    /// 1-based line of the heading; 0 for memos that weren't parsed from a file
+    pub line: usize,
+    pub children: Vec<TocEntry>,
+}
+
+/// Template rendered for a new memo, with the `{{cursor}}` position if the template has one
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct RenderedTemplate {
+    /// Path (relative to the root) the memo would be created at
+    pub path: String,
+    pub content: String,
+    pub cursor: Option<CursorPosition>,
+}
+
+/// 1-based line and column (in characters) within a text
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+pub struct CursorPosition {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A parser diagnostic with its 1-based source line
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct ParseWarning {
+    pub line: usize,
+    pub kind: ParseWarningKind,
+    pub message: String,
+}
+
+impl ParseWarning {
+    pub fn unclosed_desc(line: usize) -> Self {
+        Self {
+            line,
+            kind: ParseWarningKind::UnclosedDesc,
+            message: "<desc> is never closed and is kept as content".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseWarningKind {
+    UnclosedCodeFence,
+    UnclosedMathBlock,
+    UnclosedDesc,
+    SkippedHeadingLevel,
+    InvalidTimestamp,
+}
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Memo {
     level: Level,
+    /// Lines of the source this memo was parsed from (heading up to the next heading)
+    #[serde(default)]
+    span: Option<SourceSpan>,
     title: String,
+    /// Anchor of the memo, unique within its file: the title's slug, with `-1`, `-2`, ...
+    /// added to repeated titles in document order
+    #[serde(default)]
+    anchor: String,
+    /// First `<desc>` of the memo, kept for clients that only show one description
     description: Option<String>,
+    /// Every `<desc>` of the memo in document order
+    #[serde(default)]
+    descriptions: Vec<String>,
     content: Option<String>,
     code_blocks: Vec<CodeBlock>,
+    /// Values of metadata tags such as `<status>` or `<due>`, in document order per tag
+    #[serde(default)]
+    metadata: BTreeMap<String, Vec<String>>,
+    /// `<created>` of the memo, or `created:` from the file's front matter
+    #[serde(default)]
+    created: Option<Timestamp>,
+    /// `<updated>` of the memo, or `updated:` from the file's front matter
+    #[serde(default)]
+    updated: Option<Timestamp>,
+    /// Diagram fences (```` ```mermaid ````) kept apart from regular code blocks
+    #[serde(default)]
+    diagrams: Vec<Diagram>,
+    /// `$$...$$` blocks extracted from the content, followed by inline `$...$` math
+    #[serde(default)]
+    math_blocks: Vec<MathBlock>,
+    /// Markdown links, bare URLs and wiki-links found in the content
+    #[serde(default)]
+    links: Vec<Link>,
+    /// `![alt](src)` references found in the content
+    #[serde(default)]
+    images: Vec<Image>,
+    /// `![[target]]` embeds of other files or memos found in the content
+    #[serde(default)]
+    embeds: Vec<Embed>,
+    /// `content` split into blocks of inline spans, derived when the memo is built
+    #[serde(default)]
+    content_blocks: Vec<ContentBlock>,
+    /// The memo's body as sanitized HTML, when a client asked for it (`?render=html`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_html: Option<String>,
+    /// Number of children when they were left out of a shallow response (`?depth=`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    children_count: Option<usize>,
+    /// Slugs of the children left out of a shallow response, to fetch them one by one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    children_slugs: Option<Vec<String>>,
     children: Vec<Memo>,
 }
 
+/// Memos compare by parsed structure; `span` and `anchor` are left out so moving a section
+/// within a file (or building a memo by hand) doesn't make it unequal, and
+/// `content_html` and the shallow response fields because they're only set on request.
+impl PartialEq for Memo {
+    fn eq(&self, other: &Self) -> bool {
+        self.level == other.level
+            && self.title == other.title
+            && self.description == other.description
+            && self.descriptions == other.descriptions
+            && self.content == other.content
+            && self.code_blocks == other.code_blocks
+            && self.metadata == other.metadata
+            && self.created == other.created
+            && self.updated == other.updated
+            && self.diagrams == other.diagrams
+            && self.math_blocks == other.math_blocks
+            && self.links == other.links
+            && self.images == other.images
+            && self.embeds == other.embeds
+            && self.content_blocks == other.content_blocks
+            && self.children == other.children
+    }
+}
+
+/// Point in time with the offset it was written with (serialized as RFC 3339)
+pub type Timestamp = chrono::DateTime<chrono::FixedOffset>;
+
+/// 1-based, inclusive line range in the source file
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+pub struct SourceSpan {
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+impl SourceSpan {
+    pub fn new(start_line: usize, end_line: usize) -> Self {
+        Self {
+            start_line,
+            end_line,
+        }
+    }
+
+    /// Move the span by `delta` lines
+    pub fn shifted(self, delta: isize) -> Self {
+        Self {
+            start_line: self.start_line.saturating_add_signed(delta),
+            end_line: self.end_line.saturating_add_signed(delta),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct MemoBuilder {
     level: Level,
+    span: Option<SourceSpan>,
     title: String,
-    description: Option<String>,
+    descriptions: Vec<String>,
     content: Option<String>,
     code_blocks: Vec<CodeBlock>,
+    metadata: BTreeMap<String, Vec<String>>,
+    created: Option<Timestamp>,
+    updated: Option<Timestamp>,
+    diagrams: Vec<Diagram>,
+    math_blocks: Vec<MathBlock>,
     children: Vec<Memo>,
 }
 
@@ -58,42 +359,136 @@ impl MemoBuilder {
     pub fn new(level: Level, title: String) -> Self {
         Self {
             level,
+            span: None,
             title,
-            description: None,
+            descriptions: Vec::new(),
             content: None,
             code_blocks: Vec::new(),
+            metadata: BTreeMap::new(),
+            created: None,
+            updated: None,
+            diagrams: Vec::new(),
+            math_blocks: Vec::new(),
             children: Vec::new(),
         }
     }
+    pub fn span(mut self, span: SourceSpan) -> Self {
+        self.set_span(span);
+        self
+    }
+    /// First line of the span, or 0 when the memo wasn't parsed from a file
+    pub fn span_start_line(&self) -> usize {
+        self.span.map_or(0, |span| span.start_line)
+    }
+    /// Replace all descriptions with a single one
     pub fn description(mut self, description: String) -> Self {
-        self.description = Some(description);
+        self.descriptions = vec![description];
+        self
+    }
+    pub fn add_description(mut self, description: String) -> Self {
+        self.push_description(description);
         self
     }
     pub fn content(mut self, content: String) -> Self {
-        self.content = Some(content);
+        self.set_content(content);
         self
     }
 
     pub fn append_content(mut self, additional_content: &str) -> Self {
-        let current = self.content.unwrap_or_default();
-        self.content = Some(current + additional_content);
+        self.content.get_or_insert_with(String::new).push_str(additional_content);
         self
     }
     pub fn add_code_block(mut self, language: String, code: String) -> Self {
-        self.code_blocks.push(CodeBlock { language, code });
+        self.push_code_block(CodeBlock::new(language, code));
+        self
+    }
+    pub fn add_metadata(mut self, key: String, value: String) -> Self {
+        self.insert_metadata(key, value);
+        self
+    }
+    pub fn created(mut self, created: Timestamp) -> Self {
+        self.set_created(created);
+        self
+    }
+    pub fn updated(mut self, updated: Timestamp) -> Self {
+        self.set_updated(updated);
+        self
+    }
+    pub fn add_diagram(mut self, diagram: Diagram) -> Self {
+        self.push_diagram(diagram);
+        self
+    }
+    pub fn add_math_block(mut self, math_block: MathBlock) -> Self {
+        self.push_math_block(math_block);
         self
     }
     pub fn add_child(mut self, child: Memo) -> Self {
         self.children.push(child);
         self
     }
+
+    // In-place counterparts of the chained methods above, for filling one builder line by line
+    // (the parser) without moving or cloning it on every block
+    pub fn set_span(&mut self, span: SourceSpan) {
+        self.span = Some(span);
+    }
+    pub fn push_description(&mut self, description: String) {
+        self.descriptions.push(description);
+    }
+    pub fn set_content(&mut self, content: String) {
+        self.content = Some(content);
+    }
+    pub fn push_code_block(&mut self, code_block: CodeBlock) {
+        self.code_blocks.push(code_block);
+    }
+    pub fn insert_metadata(&mut self, key: String, value: String) {
+        self.metadata.entry(key).or_default().push(value);
+    }
+    pub fn set_created(&mut self, created: Timestamp) {
+        self.created = Some(created);
+    }
+    pub fn set_updated(&mut self, updated: Timestamp) {
+        self.updated = Some(updated);
+    }
+    pub fn push_diagram(&mut self, diagram: Diagram) {
+        self.diagrams.push(diagram);
+    }
+    pub fn push_math_block(&mut self, math_block: MathBlock) {
+        self.math_blocks.push(math_block);
+    }
+
     pub fn build(self) -> Memo {
+        let content_blocks = self
+            .content
+            .as_deref()
+            .map(crate::inline::parse_blocks)
+            .unwrap_or_default();
+        let mut math_blocks = self.math_blocks;
+        collect_inline_math(&content_blocks, &mut math_blocks);
+        let links = collect_links(&content_blocks);
+        let images = collect_images(&content_blocks);
+        let embeds = collect_embeds(&content_blocks);
         Memo {
             level: self.level,
+            span: self.span,
             title: self.title,
-            description: self.description,
+            anchor: String::new(),
+            description: self.descriptions.first().cloned(),
+            descriptions: self.descriptions,
             content: self.content,
             code_blocks: self.code_blocks,
+            metadata: self.metadata,
+            created: self.created,
+            updated: self.updated,
+            diagrams: self.diagrams,
+            math_blocks,
+            links,
+            images,
+            embeds,
+            content_blocks,
+            content_html: None,
+            children_count: None,
+            children_slugs: None,
             children: self.children,
         }
     }
@@ -108,10 +503,72 @@ impl Memo {
         &self.level
     }
 
+    pub fn span(&self) -> Option<SourceSpan> {
+        self.span
+    }
+
+    pub fn span_mut(&mut self) -> &mut Option<SourceSpan> {
+        &mut self.span
+    }
+
+    /// Fill in timestamps the memo doesn't set itself (e.g. from front matter)
+    pub fn with_default_timestamps(
+        mut self,
+        created: Option<Timestamp>,
+        updated: Option<Timestamp>,
+    ) -> Self {
+        self.created = self.created.or(created);
+        self.updated = self.updated.or(updated);
+        self
+    }
+
+    /// Last source line of this memo including all of its descendants
+    pub fn subtree_end_line(&self) -> Option<usize> {
+        match self.children.last() {
+            Some(child) => child.subtree_end_line(),
+            None => self.span.map(|s| s.end_line),
+        }
+    }
+
     pub fn title(&self) -> &String {
         &self.title
     }
 
+    /// The memo's anchor; the title's slug for memos that weren't parsed from a file
+    pub fn anchor(&self) -> std::borrow::Cow<'_, str> {
+        if self.anchor.is_empty() {
+            crate::template::slugify(&self.title).into()
+        } else {
+            self.anchor.as_str().into()
+        }
+    }
+
+    pub fn set_anchor(&mut self, anchor: String) {
+        self.anchor = anchor;
+    }
+
+    /// Rewrite the memo's prose with `f`: its title, descriptions and the text of its
+    /// content spans, leaving code, math and link targets alone (not its children)
+    pub fn map_text(&mut self, f: &impl Fn(&str) -> String) {
+        fn walk(spans: &mut [Span], f: &impl Fn(&str) -> String) {
+            for span in spans {
+                match span {
+                    Span::Text { text } => *text = f(text),
+                    Span::Bold { children } | Span::Italic { children } | Span::Link { children, .. } => walk(children, f),
+                    _ => {}
+                }
+            }
+        }
+        self.title = f(&self.title);
+        for description in &mut self.descriptions {
+            *description = f(description);
+        }
+        self.description = self.descriptions.first().cloned();
+        for block in &mut self.content_blocks {
+            walk(block.spans_mut(), f);
+        }
+    }
+
     pub fn content(&self) -> &Option<String> {
         &self.content
     }
@@ -120,17 +577,511 @@ impl Memo {
         &self.description
     }
 
+    pub fn descriptions(&self) -> &Vec<String> {
+        &self.descriptions
+    }
+
     pub fn code_blocks(&self) -> &Vec<CodeBlock> {
         &self.code_blocks
     }
 
+    pub fn code_blocks_mut(&mut self) -> &mut Vec<CodeBlock> {
+        &mut self.code_blocks
+    }
+
+    pub fn metadata(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.metadata
+    }
+
+    pub fn created(&self) -> Option<&Timestamp> {
+        self.created.as_ref()
+    }
+
+    pub fn updated(&self) -> Option<&Timestamp> {
+        self.updated.as_ref()
+    }
+
+    pub fn diagrams(&self) -> &Vec<Diagram> {
+        &self.diagrams
+    }
+
+    pub fn math_blocks(&self) -> &Vec<MathBlock> {
+        &self.math_blocks
+    }
+
+    pub fn links(&self) -> &Vec<Link> {
+        &self.links
+    }
+
+    pub fn images(&self) -> &Vec<Image> {
+        &self.images
+    }
+
+    pub fn images_mut(&mut self) -> &mut Vec<Image> {
+        &mut self.images
+    }
+
+    pub fn embeds(&self) -> &Vec<Embed> {
+        &self.embeds
+    }
+
+    pub fn embeds_mut(&mut self) -> &mut Vec<Embed> {
+        &mut self.embeds
+    }
+
+    pub fn children_mut(&mut self) -> &mut Vec<Memo> {
+        &mut self.children
+    }
+
+    pub fn content_blocks(&self) -> &Vec<ContentBlock> {
+        &self.content_blocks
+    }
+
+    pub fn content_html(&self) -> Option<&String> {
+        self.content_html.as_ref()
+    }
+
+    pub fn set_content_html(&mut self, html: Option<String>) {
+        self.content_html = html;
+    }
+
     pub fn children(&self) -> &Vec<Memo> {
         &self.children
     }
+
+    pub fn children_count(&self) -> Option<usize> {
+        self.children_count
+    }
+
+    pub fn children_slugs(&self) -> Option<&Vec<String>> {
+        self.children_slugs.as_ref()
+    }
+
+    /// Replace the children with their count and slugs
+    pub fn collapse_children(&mut self) {
+        let children = std::mem::take(&mut self.children);
+        self.children_slugs = Some(
+            children
+                .iter()
+                .map(|child| child.anchor().into_owned())
+                .collect(),
+        );
+        self.children_count = Some(children.len());
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
 pub struct CodeBlock {
     pub language: String,
     pub code: String,
+    /// File name from `title=...` / `filename=...` in the fence info string
+    #[serde(default)]
+    pub filename: Option<String>,
+    /// 1-based line numbers to highlight, expanded from `{1,3-5}`
+    #[serde(default)]
+    pub highlight_lines: Vec<usize>,
+    /// Bare words after the language, e.g. `showLineNumbers`
+    #[serde(default)]
+    pub flags: Vec<String>,
+    /// The code as HTML `<span>`s with inline colors, when highlighting was asked for
+    /// and the language is known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub highlighted_html: Option<String>,
+}
+
+impl CodeBlock {
+    pub fn new(language: String, code: String) -> Self {
+        Self {
+            language,
+            code,
+            filename: None,
+            highlight_lines: Vec::new(),
+            flags: Vec::new(),
+            highlighted_html: None,
+        }
+    }
+}
+
+/// Source of a diagram fence, rendered by the frontend (or `POST /api/diagrams/render`)
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct Diagram {
+    pub kind: DiagramKind,
+    pub source: String,
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagramKind {
+    Mermaid,
+}
+
+impl DiagramKind {
+    /// Map a fence language to a diagram kind
+    pub fn from_language(language: &str) -> Option<Self> {
+        match language {
+            "mermaid" => Some(DiagramKind::Mermaid),
+            _ => None,
+        }
+    }
+}
+
+/// TeX source of a math expression; `display` is true for `$$...$$` blocks
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct MathBlock {
+    pub tex: String,
+    pub display: bool,
+}
+
+impl MathBlock {
+    pub fn display(tex: String) -> Self {
+        Self { tex, display: true }
+    }
+    pub fn inline(tex: String) -> Self {
+        Self {
+            tex,
+            display: false,
+        }
+    }
+}
+
+fn collect_inline_math(blocks: &[ContentBlock], math_blocks: &mut Vec<MathBlock>) {
+    visit_spans(blocks, &mut |span| {
+        if let Span::Math { tex } = span {
+            math_blocks.push(MathBlock::inline(tex.clone()));
+        }
+    });
+}
+
+fn collect_links(blocks: &[ContentBlock]) -> Vec<Link> {
+    let mut links = Vec::new();
+    visit_spans(blocks, &mut |span| match span {
+        Span::Link { url, children } => links.push(Link {
+            text: crate::inline::spans_to_text(children),
+            url: url.clone(),
+            kind: LinkKind::classify(url),
+        }),
+        Span::WikiLink { target, label } => links.push(Link {
+            text: label.clone().unwrap_or_else(|| target.clone()),
+            url: target.clone(),
+            kind: LinkKind::Wiki,
+        }),
+        // An embed links to what it embeds, for backlinks and link checks
+        Span::Embed { target } => links.push(Link {
+            text: target.clone(),
+            url: target.clone(),
+            kind: LinkKind::Wiki,
+        }),
+        _ => {}
+    });
+    links
+}
+
+fn collect_images(blocks: &[ContentBlock]) -> Vec<Image> {
+    let mut images = Vec::new();
+    visit_spans(blocks, &mut |span| {
+        if let Span::Image { alt, src } = span {
+            images.push(Image {
+                alt: alt.clone(),
+                src: src.clone(),
+                path: None,
+            });
+        }
+    });
+    images
+}
+
+fn collect_embeds(blocks: &[ContentBlock]) -> Vec<Embed> {
+    let mut embeds = Vec::new();
+    visit_spans(blocks, &mut |span| {
+        if let Span::Embed { target } = span {
+            embeds.push(Embed::new(target));
+        }
+    });
+    embeds
+}
+
+/// Visit every span (depth first, parents before children) in the given blocks
+pub fn visit_spans(blocks: &[ContentBlock], f: &mut impl FnMut(&Span)) {
+    fn walk(spans: &[Span], f: &mut impl FnMut(&Span)) {
+        for span in spans {
+            f(span);
+            if let Span::Bold { children } | Span::Italic { children } | Span::Link { children, .. } = span {
+                walk(children, f);
+            }
+        }
+    }
+    for block in blocks {
+        walk(block.spans(), f);
+    }
+}
+
+/// An image referenced from a memo
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct Image {
+    pub alt: String,
+    /// Source as written in the Markdown
+    pub src: String,
+    /// Root-relative path of a local image, filled in by `resolve_image_paths`
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+/// A `![[target]]` or `![[target#anchor]]` embed of another file, or of one memo of it
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct Embed {
+    /// File name or path the embed names, as written, without the anchor
+    pub target: String,
+    /// Anchor of the embedded memo; `None` embeds the whole file
+    #[serde(default)]
+    pub anchor: Option<String>,
+    /// Root-relative path of the embedded file, filled in by `resolve_embeds`
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Why the embed can't be expanded, filled in by `resolve_embeds`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<EmbedError>,
+    /// The embedded memos as HTML, when a client asked for expanded embeds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub html: Option<String>,
+}
+
+impl Embed {
+    pub fn new(target: &str) -> Self {
+        let (name, anchor) = match target.split_once('#') {
+            Some((name, anchor)) => (name, Some(anchor.trim().to_string())),
+            None => (target, None),
+        };
+        Self {
+            target: name.trim().to_string(),
+            anchor: anchor.filter(|anchor| !anchor.is_empty()),
+            path: None,
+            error: None,
+            html: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbedError {
+    /// No memo file (or no memo with the anchor) matches the target
+    NotFound,
+    /// The target embeds, directly or not, the memo the embed is in
+    Cycle,
+    /// Embeds nest deeper than `embed::MAX_DEPTH`
+    TooDeep,
+}
+
+/// An outgoing link found in a memo's content
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct Link {
+    pub text: String,
+    pub url: String,
+    pub kind: LinkKind,
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    /// Relative path or `#anchor` inside the vault
+    Internal,
+    /// URL with a scheme (`https:`, `mailto:`, ...) or protocol-relative `//host`
+    External,
+    /// `[[target]]` wiki-link, resolved by file name
+    Wiki,
+}
+
+impl LinkKind {
+    pub fn classify(url: &str) -> Self {
+        let has_scheme = url
+            .split_once(':')
+            .is_some_and(|(scheme, _)| {
+                !scheme.is_empty()
+                    && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+                    && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            });
+        if has_scheme || url.starts_with("//") {
+            LinkKind::External
+        } else {
+            LinkKind::Internal
+        }
+    }
+}
+
+/// A block of memo content with its inline Markdown already parsed
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Paragraph { spans: Vec<Span> },
+    ListItem { depth: usize, ordered: bool, spans: Vec<Span> },
+    Quote { spans: Vec<Span> },
+}
+
+impl ContentBlock {
+    pub fn spans(&self) -> &Vec<Span> {
+        match self {
+            ContentBlock::Paragraph { spans }
+            | ContentBlock::ListItem { spans, .. }
+            | ContentBlock::Quote { spans } => spans,
+        }
+    }
+
+    pub fn spans_mut(&mut self) -> &mut Vec<Span> {
+        match self {
+            ContentBlock::Paragraph { spans }
+            | ContentBlock::ListItem { spans, .. }
+            | ContentBlock::Quote { spans } => spans,
+        }
+    }
+}
+
+/// Inline Markdown span
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Span {
+    Text { text: String },
+    Code { code: String },
+    Bold { children: Vec<Span> },
+    Italic { children: Vec<Span> },
+    Link { url: String, children: Vec<Span> },
+    WikiLink { target: String, label: Option<String> },
+    /// `![[target]]`, with an optional `#anchor` in the target
+    Embed { target: String },
+    Image { alt: String, src: String },
+    Math { tex: String },
+}
+
+/// Version of the WebSocket protocol below, sent to every client in the `hello` message.
+/// Raised when a message changes in a way older clients would misread.
+pub const WS_PROTOCOL_VERSION: u32 = 1;
+
+/// Message the server sends over the WebSocket, tagged by `type`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsServerMessage {
+    /// First message on every connection
+    Hello { protocol: u32 },
+    /// A memo file changed; `file_path` is the absolute path, `path` the file name.
+    /// `issues` are what `fmemo lint` (without spelling) finds in the new content.
+    FileUpdated {
+        file_path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+        memos: Vec<Memo>,
+        warnings: Vec<ParseWarning>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        issues: Vec<crate::lint::LintIssue>,
+    },
+    FileDeleted { file_path: String, path: String },
+    /// Files and directories directly in the root
+    DirectoryUpdated { tree: DirectoryListing },
+    /// Who is viewing which file (relative to the root)
+    Presence { files: BTreeMap<String, Vec<Viewer>> },
+    EditState { path: String, revision: usize, content: String },
+    EditAck { path: String, revision: usize },
+    /// Another editor's (or the disk's) change to a file being edited
+    Edit { path: String, revision: usize, ops: crate::collab::TextOperation },
+    EditError { path: String, error: String },
+    IndexStarted { total: usize },
+    IndexProgress { indexed: usize, total: usize },
+    IndexFinished { files: usize, duration_ms: u64 },
+    /// Directories the client follows now; empty for the whole root
+    Subscribed { dirs: Vec<String> },
+    /// Someone commented on a memo of a file (relative to the root)
+    CommentAdded { path: String, comment: crate::comments::Comment },
+    /// `source` was merged into `target` (both relative to the root); `removed` when the
+    /// source is gone, so its viewers can switch to the target
+    FilesMerged { source: String, target: String, removed: bool },
+    /// The directory watcher failed: changes on disk are missed until it's re-established
+    WatcherDegraded { error: String, since: Option<chrono::DateTime<chrono::Utc>> },
+    /// The directory watcher works again; changes made meanwhile weren't sent, so clients
+    /// should reload what they show
+    WatcherRestored { restarts: u32 },
+    /// A client message of a known type that couldn't be read
+    Error { error: String },
+}
+
+/// Message a client sends over the WebSocket, tagged by `type`. Paths are relative to
+/// the root.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsClientMessage {
+    /// Follow only files below `dir` (the whole root again when empty)
+    Subscribe {
+        #[serde(default)]
+        dir: String,
+    },
+    Unsubscribe {
+        #[serde(default)]
+        dir: String,
+    },
+    EditJoin { path: String },
+    Edit { path: String, revision: usize, ops: crate::collab::TextOperation },
+    EditLeave { path: String },
+    /// The file the client has open; `None` when it closed it
+    Viewing {
+        path: Option<String>,
+        #[serde(default)]
+        name: Option<String>,
+    },
+}
+
+impl WsClientMessage {
+    /// Every `type` a client may send, to tell unreadable messages from unknown ones
+    pub const TYPES: &'static [&'static str] = &[
+        "subscribe",
+        "unsubscribe",
+        "edit_join",
+        "edit",
+        "edit_leave",
+        "viewing",
+    ];
+}
+
+/// Root listing in `directory_updated` messages
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct DirectoryListing {
+    pub files: Vec<String>,
+    /// Directory names
+    pub directories: Vec<String>,
+}
+
+/// A client viewing a file, in `presence` messages
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct Viewer {
+    pub client: usize,
+    pub name: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WsClientMessage, WsServerMessage};
+    use serde_json::json;
+
+    #[test]
+    fn test_ws_messages() {
+        let message: WsClientMessage =
+            serde_json::from_value(json!({"type": "edit", "path": "a.md", "revision": 2, "ops": [3, "x"]}))
+                .unwrap();
+        let WsClientMessage::Edit { revision, ops, .. } = &message else {
+            panic!("not an edit: {:?}", message);
+        };
+        assert_eq!((*revision, ops.base_len()), (2, 3));
+        assert_eq!(
+            serde_json::from_value::<WsClientMessage>(json!({"type": "viewing", "path": null})).unwrap(),
+            WsClientMessage::Viewing { path: None, name: None }
+        );
+        assert!(serde_json::from_value::<WsClientMessage>(json!({"type": "edit", "path": "a.md"})).is_err());
+
+        let message = WsServerMessage::IndexProgress { indexed: 50, total: 120 };
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            json!({"type": "index_progress", "indexed": 50, "total": 120})
+        );
+        let message: WsServerMessage = serde_json::from_value(
+            json!({"type": "file_updated", "file_path": "/notes/a.md", "memos": [], "warnings": []}),
+        )
+        .unwrap();
+        assert!(matches!(message, WsServerMessage::FileUpdated { path: None, .. }));
+    }
 }
@@ -0,0 +1,125 @@
+//! Full-text search over the memos below a root directory.
+
+use std::path::Path;
+
+use crate::indexer::Documents;
+use crate::schema::Memo;
+use crate::server::{list_memo_files, read_fmemo_file};
+
+/// A memo matching a search query
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct SearchHit {
+    /// File path relative to the root
+    pub file: String,
+    /// Line of the memo heading
+    pub line: usize,
+    pub title: String,
+    /// The first matching line of the memo
+    pub snippet: String,
+}
+
+/// Case-insensitive search of memo titles, descriptions and content.
+/// Every query term has to appear in the memo.
+pub fn search<P: AsRef<Path>>(root: P, query: &str) -> std::io::Result<Vec<SearchHit>> {
+    let root = root.as_ref();
+    let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+    let mut hits = Vec::new();
+    if terms.is_empty() {
+        return Ok(hits);
+    }
+
+    for file in list_memo_files(root)? {
+        let Ok(content) = read_fmemo_file(root.join(&file)) else {
+            continue;
+        };
+        search_memos(&content.memos, &file, &terms, &mut hits);
+    }
+    Ok(hits)
+}
+
+/// `search` over already parsed files, e.g. the server's index
+pub fn search_documents(documents: &Documents, query: &str) -> Vec<SearchHit> {
+    let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+    let mut hits = Vec::new();
+    if terms.is_empty() {
+        return hits;
+    }
+    for (file, memos) in documents {
+        search_memos(memos, file, &terms, &mut hits);
+    }
+    hits
+}
+
+fn search_memos(memos: &[Memo], file: &str, terms: &[String], hits: &mut Vec<SearchHit>) {
+    for memo in memos {
+        let text = searchable_text(memo);
+        let lower = text.to_lowercase();
+        if terms.iter().all(|term| lower.contains(term.as_str())) {
+            let snippet = text
+                .lines()
+                .find(|line| {
+                    let line = line.to_lowercase();
+                    terms.iter().any(|term| line.contains(term.as_str()))
+                })
+                .unwrap_or_default()
+                .trim()
+                .to_string();
+            hits.push(SearchHit {
+                file: file.to_string(),
+                line: memo.span().map_or(0, |span| span.start_line),
+                title: memo.title().to_string(),
+                snippet,
+            });
+        }
+        search_memos(memo.children(), file, terms, hits);
+    }
+}
+
+/// Title, descriptions and content of one memo (children are searched separately)
+pub(crate) fn searchable_text(memo: &Memo) -> String {
+    let mut text = memo.title().to_string();
+    for description in memo.descriptions() {
+        text.push('\n');
+        text.push_str(description);
+    }
+    if let Some(content) = memo.content() {
+        text.push('\n');
+        text.push_str(content);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::search;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_search_matches_all_terms() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        fs::write(
+            temp_dir.path().join("a.fmemo"),
+            "# Rust notes\nownership and borrowing\n## Lifetimes\nborrowing rules",
+        )
+        .unwrap();
+        fs::write(
+            temp_dir.path().join("sub").join("b.md"),
+            "# Go\n<desc>Borrowing is not a thing</desc>",
+        )
+        .unwrap();
+
+        let hits = search(temp_dir.path(), "BORROWING rules").unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].file, "a.fmemo");
+        assert_eq!(hits[0].title, "Lifetimes");
+        assert_eq!(hits[0].line, 3);
+        assert_eq!(hits[0].snippet, "borrowing rules");
+
+        let hits = search(temp_dir.path(), "borrowing").unwrap();
+        let files: Vec<_> = hits.iter().map(|hit| hit.file.as_str()).collect();
+        assert_eq!(files, ["a.fmemo", "a.fmemo", "sub/b.md"]);
+        assert!(search(temp_dir.path(), "  ").unwrap().is_empty());
+    }
+}
@@ -1,5 +1,8 @@
-use crate::parser::parse_memo;
-use crate::schema::{DirectoryTree, FileContent};
+use crate::incremental::IncrementalParser;
+use crate::parser::{parse_document, resolve_image_paths, ParseOptions};
+use crate::plugin::Plugins;
+use crate::schema::{ArchiveRequest, CommentRequest, DirectoryTree, DraftRequest, FileContent, FileEntry, LoginRequest, MergeRequest, NewMemoRequest, PinRequest, RestoreRequest, SplitRequest, SummarizeRequest, TokenRequest, UnlockRequest, WriteFileRequest, WsClientMessage, WsServerMessage, WS_PROTOCOL_VERSION};
+use crate::ws_format::{WsCompression, WsFormat};
 use futures_util::{SinkExt, StreamExt};
 use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use std::fs;
@@ -11,7 +14,77 @@ use tokio::sync::mpsc::UnboundedSender;
 use warp::Filter;
 
 /// WebSocket client manager
-pub type WebSocketClients = Arc<Mutex<Vec<UnboundedSender<warp::ws::Message>>>>;
+pub type WebSocketClients = Arc<Mutex<Vec<WebSocketClient>>>;
+
+/// A connected WebSocket client
+#[derive(Debug, Clone)]
+pub struct WebSocketClient {
+    pub id: ClientId,
+    pub tx: UnboundedSender<warp::ws::Message>,
+    /// Directories (relative to the root) the client subscribed to; empty for the whole root
+    pub dirs: Vec<String>,
+    /// Encoding the client asked for with `?format=...`
+    pub format: WsFormat,
+    /// Compression the client asked for with `?compress=...`
+    pub compression: WsCompression,
+    pub connected_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A connected WebSocket client as `GET /api/ws/clients` lists it
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WebSocketClientInfo {
+    pub id: ClientId,
+    /// Subscribed directories; empty for the whole root
+    pub dirs: Vec<String>,
+    pub format: WsFormat,
+    pub compression: WsCompression,
+    pub connected_at: chrono::DateTime<chrono::Utc>,
+    /// Seconds since the client connected
+    pub age_secs: i64,
+    /// Whether messages still reach the connection; `false` for one that is gone but not
+    /// yet removed
+    pub open: bool,
+}
+
+impl WebSocketClient {
+    pub fn new(tx: UnboundedSender<warp::ws::Message>) -> Self {
+        Self {
+            id: NEXT_CLIENT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+            tx,
+            dirs: Vec::new(),
+            format: WsFormat::Json,
+            compression: WsCompression::None,
+            connected_at: chrono::Utc::now(),
+        }
+    }
+
+    pub fn info(&self) -> WebSocketClientInfo {
+        WebSocketClientInfo {
+            id: self.id,
+            dirs: self.dirs.clone(),
+            format: self.format,
+            compression: self.compression,
+            connected_at: self.connected_at,
+            age_secs: (chrono::Utc::now() - self.connected_at).num_seconds(),
+            open: !self.tx.is_closed(),
+        }
+    }
+
+    /// Whether messages about a file (relative to the root) go to this client
+    pub fn wants(&self, file: &str) -> bool {
+        self.dirs.is_empty()
+            || self.dirs.iter().any(|dir| {
+                file.strip_prefix(dir.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+            })
+    }
+}
+
+impl From<UnboundedSender<warp::ws::Message>> for WebSocketClient {
+    fn from(tx: UnboundedSender<warp::ws::Message>) -> Self {
+        Self::new(tx)
+    }
+}
 
 /// File change notification data
 #[derive(Debug, Clone)]
@@ -39,7 +112,7 @@ pub fn scan_directory<P: AsRef<Path>>(root_path: P) -> std::io::Result<Directory
 
         if path.is_file() {
             if let Some(ext) = path.extension() {
-                if ext == "fmemo" || ext == "md" {
+                if ext == "fmemo" || ext == "md" || ext == crate::crypt::EXTENSION {
                     if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
                         files.push(file_name.to_string());
                     }
@@ -66,6 +139,90 @@ pub fn scan_directory<P: AsRef<Path>>(root_path: P) -> std::io::Result<Directory
     })
 }
 
+/// Relative paths (with `/` separators) of every memo file below `root_path`, sorted
+pub fn list_memo_files<P: AsRef<Path>>(root_path: P) -> std::io::Result<Vec<String>> {
+    fn collect(tree: &DirectoryTree, root: &Path, out: &mut Vec<String>) {
+        let dir = Path::new(&tree.path);
+        let relative_dir = dir.strip_prefix(root).unwrap_or(dir);
+        for file in &tree.files {
+            let relative = relative_dir.join(file);
+            let parts: Vec<_> = relative.iter().map(|p| p.to_string_lossy()).collect();
+            out.push(parts.join("/"));
+        }
+        for subdir in &tree.subdirectories {
+            collect(subdir, root, out);
+        }
+    }
+
+    let root_path = root_path.as_ref();
+    let tree = scan_directory(root_path)?;
+    let mut files = Vec::new();
+    collect(&tree, root_path, &mut files);
+    files.sort();
+    Ok(files)
+}
+
+/// Every memo file below `root_path` with its size, modification time and title, sorted by path
+pub fn list_memo_entries<P: AsRef<Path>>(root_path: P) -> std::io::Result<Vec<FileEntry>> {
+    let root_path = root_path.as_ref();
+    Ok(memo_entries(root_path, list_memo_files(root_path)?))
+}
+
+/// `list_memo_entries` of the files whose relative path matches `glob`
+pub fn list_memo_entries_matching<P: AsRef<Path>>(
+    root_path: P,
+    glob: &crate::glob::Glob,
+) -> std::io::Result<Vec<FileEntry>> {
+    let root_path = root_path.as_ref();
+    let mut files = list_memo_files(root_path)?;
+    files.retain(|file| glob.matches(file));
+    Ok(memo_entries(root_path, files))
+}
+
+fn memo_entries(root_path: &Path, files: Vec<String>) -> Vec<FileEntry> {
+    use std::io::BufRead;
+
+    let mut entries = Vec::new();
+    for path in files {
+        let full_path = root_path.join(&path);
+        let Ok(metadata) = full_path.metadata() else {
+            // Deleted since the scan
+            continue;
+        };
+        let last_modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+        let first_heading = |line: String| {
+            let title = line.trim_start_matches('#');
+            (title.len() < line.len() && title.starts_with(' ')).then(|| title.trim().to_string())
+        };
+        let title = if crate::crypt::is_encrypted(&full_path) {
+            // No title while locked
+            crate::crypt::read_memo(&full_path)
+                .ok()
+                .and_then(|content| content.lines().map(str::to_string).find_map(first_heading))
+        } else {
+            // Only read up to the first heading
+            fs::File::open(&full_path).ok().and_then(|file| {
+                std::io::BufReader::new(file)
+                    .lines()
+                    .map_while(Result::ok)
+                    .find_map(first_heading)
+            })
+        };
+        entries.push(FileEntry {
+            path,
+            size: metadata.len(),
+            last_modified,
+            title,
+            views: 0,
+        });
+    }
+    entries
+}
+
 /// Check if directory tree contains any .fmemo files (recursively)
 fn has_fmemo_files(tree: &DirectoryTree) -> bool {
     !tree.files.is_empty() || tree.subdirectories.iter().any(has_fmemo_files)
@@ -73,19 +230,26 @@ fn has_fmemo_files(tree: &DirectoryTree) -> bool {
 
 /// Read and parse a .fmemo file
 pub fn read_fmemo_file<P: AsRef<Path>>(file_path: P) -> std::io::Result<FileContent> {
+    read_fmemo_file_with(file_path, &Plugins::default(), false)
+}
+
+/// Read and parse a .fmemo file with the plugins' tags and transforms, without its
+/// `<private>` regions when `publish` is set
+pub fn read_fmemo_file_with<P: AsRef<Path>>(file_path: P, plugins: &Plugins, publish: bool) -> std::io::Result<FileContent> {
     let file_path = file_path.as_ref();
     
-    // Verify it's a .fmemo or .md file
+    // Verify it's a .fmemo, .md or .fmemox file
     let ext = file_path.extension().and_then(|s| s.to_str());
-    if ext != Some("fmemo") && ext != Some("md") {
+    if ext != Some("fmemo") && ext != Some("md") && ext != Some(crate::crypt::EXTENSION) {
         return Err(std::io::Error::new(
             std::io::ErrorKind::InvalidInput,
-            "File must have .fmemo or .md extension",
+            "File must have .fmemo, .md or .fmemox extension",
         ));
     }
 
-    let content = fs::read_to_string(file_path)?;
-    let memos = parse_memo(&content);
+    // Parsed straight from the map for large files
+    let content = crate::crypt::load_memo(file_path, publish)?;
+    let document = plugins.parse(&content);
     
     // Get last modified time
     let last_modified = file_path
@@ -96,14 +260,360 @@ pub fn read_fmemo_file<P: AsRef<Path>>(file_path: P) -> std::io::Result<FileCont
         .map(|d| d.as_secs());
 
     Ok(FileContent {
-        memos,
+        memos: document.memos,
         last_modified,
+        warnings: document.warnings,
     })
 }
 
+/// Error message for a path `resolve_memo_path` rejects
+pub const INVALID_MEMO_PATH: &str = "Path must be a .fmemo, .md or .fmemox file inside the root";
+
+/// Map a client supplied relative path to a memo file under `root_dir`.
+/// Rejects `..`, hidden segments and files that aren't .fmemo, .md or .fmemox.
+pub fn resolve_memo_path(root_dir: &Path, relative: &str) -> Option<PathBuf> {
+    let valid_segments = relative
+        .split('/')
+        .all(|segment| !segment.is_empty() && !segment.starts_with('.'));
+    let ext = Path::new(relative).extension().and_then(|s| s.to_str());
+    if !valid_segments || !matches!(ext, Some("fmemo" | "md" | crate::crypt::EXTENSION)) {
+        return None;
+    }
+    Some(root_dir.join(relative))
+}
+
+/// Write a memo file, optionally stamping the memos that changed
+pub fn write_fmemo_file(file_path: &Path, request: &WriteFileRequest) -> std::io::Result<()> {
+    let content = if request.auto_stamp {
+        let previous = crate::crypt::read_memo(file_path).unwrap_or_default();
+        let now = chrono::Utc::now().fixed_offset();
+        crate::stamp::stamp_changed_memos(&previous, &request.content, now)
+    } else {
+        request.content.clone()
+    };
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    crate::crypt::write_memo(file_path, &content)
+}
+
+/// What `/api/files/{path}/...` asks for about a file's git history
+enum FileRevisionAction<'a> {
+    History,
+    At(&'a str),
+    Diff,
+}
+
+/// Split an API path like `dir/a.fmemo/history` into the memo path and what follows it
+fn split_file_action(tail: &str) -> Option<(&str, &str)> {
+    tail.match_indices('/')
+        .map(|(idx, _)| (&tail[..idx], &tail[idx + 1..]))
+        .find(|(file, _)| file.ends_with(".fmemo") || file.ends_with(".md") || file.ends_with(".fmemox"))
+}
+
+/// HTTP status for an io error from a request handler
+fn io_error_status(e: &std::io::Error) -> warp::http::StatusCode {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => warp::http::StatusCode::NOT_FOUND,
+        std::io::ErrorKind::InvalidInput => warp::http::StatusCode::BAD_REQUEST,
+        std::io::ErrorKind::AlreadyExists => warp::http::StatusCode::CONFLICT,
+        std::io::ErrorKind::PermissionDenied => warp::http::StatusCode::FORBIDDEN,
+        _ => warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// The archive directory to leave out of a listing, unless the request asks for `?archived=true`
+fn hidden_archive_dir(root_dir: &Path, query: &std::collections::HashMap<String, String>) -> Option<String> {
+    if query.get("archived").is_some_and(|archived| archived == "true") {
+        return None;
+    }
+    Some(crate::config::load_config(root_dir).unwrap_or_default().archive.dir)
+}
+
+/// `GET /api/root?flat=true`: every memo file's relative path, paged with `limit`/`cursor`
+fn flat_root_listing(
+    root_dir: &Path,
+    query: &std::collections::HashMap<String, String>,
+    media: &str,
+) -> warp::reply::Response {
+    use warp::Reply;
+    let archive = hidden_archive_dir(root_dir, query);
+    let result = list_memo_files(root_dir).and_then(|mut files| {
+        if let Some(dir) = &archive {
+            files.retain(|file| !crate::archive::is_archived(dir, file));
+        }
+        Ok(match crate::page::PageRequest::from_query(query)? {
+            Some(page) => {
+                let (files, next_cursor) = page.apply(files, String::clone);
+                serde_json::json!({"files": files, "next_cursor": next_cursor})
+            }
+            None => serde_json::json!({"files": files}),
+        })
+    });
+    match result {
+        Ok(body) => structured_reply(&body, media),
+        Err(e) => warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+            io_error_status(&e),
+        )
+        .into_response(),
+    }
+}
+
+/// `GET /api/files/{filename}` as parsed data in `media` (JSON, YAML or TOML), honoring
+/// `?highlight=`, `?render=html`, `?embeds=expand` and `?depth=`
+fn file_data_response(
+    root_dir: &Path,
+    plugins: &Plugins,
+    publish: bool,
+    filename: &str,
+    query: &std::collections::HashMap<String, String>,
+    media: &str,
+) -> warp::reply::Response {
+    use warp::Reply;
+    match read_fmemo_file_with(root_dir.join(filename), plugins, publish) {
+        Ok(mut content) => {
+            resolve_image_paths(&mut content.memos, filename);
+            // ?embeds=expand renders embedded memos into the HTML of ?render=html
+            let render_html = query.get("render").is_some_and(|render| render == "html");
+            let expand = render_html && query.get("embeds").is_some_and(|embeds| embeds == "expand");
+            if let Err(e) = crate::embed::resolve_embeds(root_dir, plugins, publish, filename, &mut content.memos, expand) {
+                return warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                    io_error_status(&e),
+                )
+                .into_response();
+            }
+            // ?highlight=true|false|<theme>, or the [highlight] config
+            let config = crate::config::load_config(root_dir).unwrap_or_default();
+            let theme = crate::highlight::requested_theme(
+                query.get("highlight").map(String::as_str),
+                &config.highlight,
+            );
+            if let Some(theme) = theme
+                && let Err(e) = crate::highlight::highlight_memos(&mut content.memos, &theme)
+            {
+                return warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                    io_error_status(&e),
+                )
+                .into_response();
+            }
+            // ?render=html, after highlighting so code blocks use it; {{placeholders}} are
+            // filled in for the rendered memos only
+            if render_html {
+                let mut context = crate::template::TemplateContext::new(
+                    content.memos.first().map_or("", |memo| memo.title().as_str()),
+                    filename,
+                );
+                context.variables = config.variables.clone();
+                crate::template::expand_variables(&mut content.memos, &context);
+                crate::render::render_memos(&mut content.memos, filename);
+            }
+            // ?depth=N: N levels of memos, the children below as counts and slugs
+            if let Some(depth) = query.get("depth") {
+                match depth.parse::<usize>() {
+                    Ok(depth) if depth > 0 => crate::parser::truncate_depth(&mut content.memos, depth),
+                    _ => {
+                        return warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"error": "depth must be a positive integer"})),
+                            warp::http::StatusCode::BAD_REQUEST,
+                        )
+                        .into_response();
+                    }
+                }
+            }
+            structured_reply(&content, media)
+        }
+        // Encrypted and locked
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+            warp::http::StatusCode::FORBIDDEN,
+        )
+        .into_response(),
+        Err(e) => {
+            let error_msg = match e.kind() {
+                std::io::ErrorKind::NotFound => "File not found",
+                std::io::ErrorKind::InvalidInput => "Invalid file type (must be .fmemo, .md or .fmemox)",
+                _ => "Failed to read file",
+            };
+            warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({"error": error_msg})),
+                warp::http::StatusCode::NOT_FOUND,
+            )
+            .into_response()
+        }
+    }
+}
+
+/// Serializations of the data endpoints besides JSON, by `?format=` name and media type
+const DATA_FORMATS: [(&str, &str); 5] = [
+    ("json", "application/json"),
+    ("yaml", "application/yaml"),
+    ("toml", "application/toml"),
+    ("markdown", "text/markdown"),
+    ("html", "text/html"),
+];
+
+/// The media type of `offered` a request asks for: `?format=` when given, the `Accept` header
+/// otherwise. `None` when neither can be served.
+fn requested_media_type<'a>(
+    query: &std::collections::HashMap<String, String>,
+    accept: Option<&str>,
+    offered: &[&'a str],
+) -> Option<&'a str> {
+    match query.get("format") {
+        Some(name) => {
+            let (_, media) = DATA_FORMATS.iter().find(|(format, _)| format == name)?;
+            offered.iter().copied().find(|offered| offered == media)
+        }
+        None => negotiate_media_type(accept, offered),
+    }
+}
+
+/// `value` serialized as `media` (`application/json`, `application/yaml` or `application/toml`)
+fn structured_reply<T: serde::Serialize>(value: &T, media: &str) -> warp::reply::Response {
+    use warp::Reply;
+
+    /// TOML has no null; leave such fields out
+    fn drop_nulls(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                map.retain(|_, value| !value.is_null());
+                map.values_mut().for_each(drop_nulls);
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(drop_nulls),
+            _ => {}
+        }
+    }
+
+    let body = match media {
+        "application/yaml" => serde_yaml::to_string(value).map_err(|e| e.to_string()),
+        "application/toml" => serde_json::to_value(value)
+            .map_err(|e| e.to_string())
+            .and_then(|mut value| {
+                drop_nulls(&mut value);
+                toml::to_string(&value).map_err(|e| e.to_string())
+            }),
+        _ => return warp::reply::json(value).into_response(),
+    };
+    match body {
+        Ok(body) => warp::reply::with_header(body, "content-type", format!("{}; charset=utf-8", media))
+            .into_response(),
+        Err(e) => warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({"error": format!("Can't serialize as {}: {}", media, e)})),
+            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        )
+        .into_response(),
+    }
+}
+
+fn not_acceptable(offered: &[&str]) -> warp::reply::Response {
+    use warp::Reply;
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({
+            "error": format!("Not acceptable, use {}", offered.join(", "))
+        })),
+        warp::http::StatusCode::NOT_ACCEPTABLE,
+    )
+    .into_response()
+}
+
+/// The type of `offered` an `Accept` header prefers, the earlier one on a tie, or `None` when it
+/// takes none of them. Without the header anything goes.
+fn negotiate_media_type<'a>(accept: Option<&str>, offered: &[&'a str]) -> Option<&'a str> {
+    let Some(accept) = accept.filter(|accept| !accept.trim().is_empty()) else {
+        return offered.first().copied();
+    };
+    let ranges: Vec<(String, f32)> = accept
+        .split(',')
+        .map(|range| {
+            let mut params = range.split(';');
+            let media = match params.next().unwrap_or_default().trim().to_ascii_lowercase() {
+                // Older names of YAML's type
+                media if media == "text/yaml" || media == "application/x-yaml" => "application/yaml".to_string(),
+                media => media,
+            };
+            let q = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (media, q)
+        })
+        .collect();
+    let quality = |offered: &str| {
+        // The most specific range that matches decides
+        let (kind, _) = offered.split_once('/').unwrap_or((offered, ""));
+        let wildcard = format!("{}/*", kind);
+        [offered, wildcard.as_str(), "*/*"]
+            .iter()
+            .find_map(|media| ranges.iter().find(|(range, _)| range == media).map(|(_, q)| *q))
+            .unwrap_or(0.0)
+    };
+    let mut best: Option<(&'a str, f32)> = None;
+    for media in offered {
+        let q = quality(media);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((media, q));
+        }
+    }
+    best.map(|(media, _)| media)
+}
+
+/// Hard limit on `POST /api/import` uploads; `[import] max_size_mb` is checked below it
+const MAX_IMPORT_UPLOAD: u64 = 1024 * 1024 * 1024;
+
+/// Content of one field of a multipart upload, refusing more than `max_size` bytes
+async fn read_upload(
+    mut form: warp::multipart::FormData,
+    field: &str,
+    max_size: u64,
+) -> std::io::Result<Vec<u8>> {
+    use warp::Buf;
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+    while let Some(part) = form.next().await {
+        let part = part.map_err(|e| invalid(format!("Invalid upload: {}", e)))?;
+        if part.name() != field {
+            continue;
+        }
+        let mut content = Vec::new();
+        let mut stream = Box::pin(part.stream());
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| invalid(format!("Invalid upload: {}", e)))?;
+            content.extend_from_slice(chunk.chunk());
+            if content.len() as u64 > max_size {
+                return Err(invalid(format!("Upload is larger than {} bytes", max_size)));
+            }
+        }
+        return Ok(content);
+    }
+    Err(invalid(format!("Missing '{}' field", field)))
+}
+
 /// Create static file serving routes for React frontend
 pub fn create_static_routes(
     dist_dir: PathBuf,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    create_static_routes_with_base_path(dist_dir, String::new())
+}
+
+/// `index.html` with a `<base href>` pointing at `base_path`, so the frontend's relative
+/// asset and API URLs resolve below it from any page
+pub fn index_html_with_base(html: &str, base_path: &str) -> String {
+    let base = format!("<base href=\"{}/\">", crate::render::escape_html(base_path));
+    match html.find("<head>") {
+        Some(head) => {
+            let at = head + "<head>".len();
+            format!("{}\n    {}{}", &html[..at], base, &html[at..])
+        }
+        None => format!("{}{}", base, html),
+    }
+}
+
+/// Static frontend routes for a server mounted under `base_path` (e.g. `/notes`, or empty)
+pub fn create_static_routes_with_base_path(
+    dist_dir: PathBuf,
+    base_path: String,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     // Serve static assets (CSS, JS, etc.)
     let static_files = warp::path("assets")
@@ -117,10 +627,18 @@ pub fn create_static_routes(
         .and(warp::fs::file(dist_dir.join("vite.svg")));
     
     // Catch all route for SPA - serve index.html for all non-API, non-WS routes
+    let index_path = dist_dir.join("index.html");
     let spa_routes = warp::get()
-        .and(warp::path::full())
-        .and(warp::fs::file(dist_dir.join("index.html")))
-        .map(|_path: warp::path::FullPath, file| file);
+        .and_then(move || {
+            let index_path = index_path.clone();
+            let base_path = base_path.clone();
+            async move {
+                match fs::read_to_string(&index_path) {
+                    Ok(html) => Ok(warp::reply::html(index_html_with_base(&html, &base_path))),
+                    Err(_) => Err(warp::reject::not_found()),
+                }
+            }
+        });
     
     static_files
         .or(favicon)
@@ -132,1042 +650,5269 @@ pub fn create_static_routes(
 pub fn create_api_routes(
     root_dir: PathBuf,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    create_api_routes_with_plugins(root_dir, Plugins::default())
+}
+
+/// Create API routes that parse memo files with the given plugins
+pub fn create_api_routes_with_plugins(
+    root_dir: PathBuf,
+    plugins: Plugins,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    create_api_routes_with_options(root_dir, ApiOptions { plugins, ..Default::default() })
+}
+
+/// What the API routes share with the rest of the server
+#[derive(Debug, Clone, Default)]
+pub struct ApiOptions {
+    /// Tags and transforms applied when parsing memo files
+    pub plugins: Plugins,
+    /// Changes are recorded in the audit log under the name these know the client by
+    pub users: crate::users::Users,
+    /// Counts `GET /api/files/{path}` and answers `GET /api/popular`
+    pub views: Option<crate::views::ViewCounter>,
+    /// Told about new comments
+    pub clients: Option<WebSocketClients>,
+    /// Status of the directory watcher for `GET /api/health`
+    pub watcher: Option<crate::watcher::WatcherHealth>,
+    /// Serve a published site: `<private>` regions are cut out of every file read
+    pub publish: bool,
+}
+
+/// Who makes a request, for the audit log (see `Users::actor`)
+fn request_actor(
+    users: crate::users::Users,
+) -> impl Filter<Extract = (Option<String>,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("cookie")
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .map(move |cookie: Option<String>, authorization: Option<String>, query: String| {
+            let bearer = authorization
+                .as_deref()
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .or_else(|| query.split('&').find_map(|pair| pair.strip_prefix("token=")));
+            users.actor(cookie.as_deref().and_then(crate::users::session_id), bearer)
+        })
+}
+
+/// Create API routes
+pub fn create_api_routes_with_options(
+    root_dir: PathBuf,
+    options: ApiOptions,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let ApiOptions { plugins, users, views, clients, watcher, publish } = options;
+    let actor = request_actor(users);
     let root_route = {
         let root_dir = root_dir.clone();
         warp::path!("api" / "root")
             .and(warp::get())
-            .map(move || {
-                match scan_directory(&root_dir) {
-                    Ok(tree) => {
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .and(warp::header::optional::<String>("accept"))
+            .map(move |query: std::collections::HashMap<String, String>, accept: Option<String>| {
+                use warp::Reply;
+                let offered = ["application/json", "application/yaml", "application/toml"];
+                let Some(media) = requested_media_type(&query, accept.as_deref(), &offered) else {
+                    return not_acceptable(&offered);
+                };
+                let response = if query.get("flat").is_some_and(|flat| flat == "true") {
+                    flat_root_listing(&root_dir, &query, media)
+                } else {
+                    match scan_directory(&root_dir) {
                         // Return full hierarchical structure
-                        warp::reply::with_status(
-                            warp::reply::json(&tree),
-                            warp::http::StatusCode::OK,
-                        )
+                        Ok(tree) => match hidden_archive_dir(&root_dir, &query) {
+                            Some(dir) => structured_reply(&crate::archive::without_archive(tree, &root_dir, &dir), media),
+                            None => structured_reply(&tree, media),
+                        },
+                        Err(_) => {
+                            warp::reply::with_status(
+                                warp::reply::json(&serde_json::json!({"error": "Failed to scan directory"})),
+                                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                            )
+                            .into_response()
+                        }
                     }
-                    Err(_) => {
-                        warp::reply::with_status(
-                            warp::reply::json(&serde_json::json!({"error": "Failed to scan directory"})),
-                            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-                        )
+                };
+                warp::reply::with_header(response, "vary", "accept").into_response()
+            })
+    };
+
+    // GET /api/files - every memo file (or those matching ?glob=), flat, paged with limit/cursor
+    let list_files_route = {
+        let root_dir = root_dir.clone();
+        let views = views.clone();
+        warp::path!("api" / "files")
+            .and(warp::get())
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .map(move |query: std::collections::HashMap<String, String>| {
+                let files = match query.get("glob") {
+                    Some(pattern) => crate::glob::Glob::new(pattern)
+                        .and_then(|glob| list_memo_entries_matching(&root_dir, &glob)),
+                    None => list_memo_entries(&root_dir),
+                };
+                let archive = hidden_archive_dir(&root_dir, &query);
+                let files = files.map(|mut files| {
+                    if let Some(dir) = &archive {
+                        files.retain(|file| !crate::archive::is_archived(dir, &file.path));
+                    }
+                    if let Some(views) = &views {
+                        for file in &mut files {
+                            file.views = views.views(&file.path);
+                        }
                     }
+                    files
+                });
+                let result = files.and_then(|files| {
+                    Ok(match crate::page::PageRequest::from_query(&query)? {
+                        Some(page) => {
+                            let (files, next_cursor) = page.apply(files, |file| file.path.clone());
+                            serde_json::json!({"files": files, "next_cursor": next_cursor})
+                        }
+                        None => serde_json::json!({"files": files}),
+                    })
+                });
+                match result {
+                    Ok(body) => warp::reply::with_status(warp::reply::json(&body), warp::http::StatusCode::OK),
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                        io_error_status(&e),
+                    ),
                 }
             })
     };
 
     let files_route = {
         let root_dir = root_dir.clone();
+        let plugins = plugins.clone();
+        let views = views.clone();
         warp::path!("api" / "files" / String)
             .and(warp::get())
-            .map(move |filename: String| {
-                let file_path = root_dir.join(&filename);
-                
-                match read_fmemo_file(&file_path) {
-                    Ok(content) => {
-                        warp::reply::with_status(
-                            warp::reply::json(&content),
-                            warp::http::StatusCode::OK,
-                        )
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .and(warp::header::optional::<String>("accept"))
+            .map(move |filename: String, query: std::collections::HashMap<String, String>, accept: Option<String>| {
+                use warp::Reply;
+                // One URL, several representations: parsed data, the Markdown source or HTML
+                let offered = ["application/json", "text/markdown", "text/html", "application/yaml", "application/toml"];
+                let response = match requested_media_type(&query, accept.as_deref(), &offered) {
+                    Some(media @ ("application/json" | "application/yaml" | "application/toml")) => {
+                        file_data_response(&root_dir, &plugins, publish, &filename, &query, media)
                     }
-                    Err(e) => {
-                        let error_msg = match e.kind() {
-                            std::io::ErrorKind::NotFound => "File not found",
-                            std::io::ErrorKind::InvalidInput => "Invalid file type (must be .fmemo or .md)",
-                            _ => "Failed to read file",
-                        };
-                        warp::reply::with_status(
-                            warp::reply::json(&serde_json::json!({"error": error_msg})),
-                            warp::http::StatusCode::NOT_FOUND,
-                        )
+                    Some(media) => {
+                        let result = resolve_memo_path(&root_dir, &filename)
+                            .ok_or_else(|| {
+                                std::io::Error::new(
+                                    std::io::ErrorKind::InvalidInput,
+                                    INVALID_MEMO_PATH,
+                                )
+                            })
+                            .and_then(|path| crate::crypt::load_memo(&path, publish));
+                        match result {
+                            Ok(content) if media == "text/markdown" => {
+                                // A mapped file goes out from the map without being copied
+                                let body = warp::hyper::Body::from(content.into_bytes());
+                                warp::reply::with_header(
+                                    warp::reply::Response::new(body),
+                                    "content-type",
+                                    "text/markdown; charset=utf-8",
+                                )
+                                .into_response()
+                            }
+                            Ok(content) => {
+                                let mut document = plugins.parse(&content);
+                                resolve_image_paths(&mut document.memos, &filename);
+                                let expand = query.get("embeds").is_some_and(|embeds| embeds == "expand");
+                                let result = crate::embed::resolve_embeds(&root_dir, &plugins, publish, &filename, &mut document.memos, expand)
+                                    .and_then(|()| crate::config::load_config(&root_dir));
+                                match result {
+                                    Ok(config) => {
+                                        let mut context = crate::template::TemplateContext::new(
+                                            document.memos.first().map_or("", |memo| memo.title().as_str()),
+                                            &filename,
+                                        );
+                                        context.variables = config.variables;
+                                        crate::template::expand_variables(&mut document.memos, &context);
+                                        warp::reply::html(crate::render::document_html(&document.memos, &filename))
+                                            .into_response()
+                                    }
+                                    Err(e) => warp::reply::with_status(
+                                        warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                                        io_error_status(&e),
+                                    )
+                                    .into_response(),
+                                }
+                            }
+                            Err(e) => warp::reply::with_status(
+                                warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                                io_error_status(&e),
+                            )
+                            .into_response(),
+                        }
                     }
+                    None => not_acceptable(&offered),
+                };
+                if let Some(views) = &views
+                    && response.status().is_success()
+                {
+                    views.record(&filename);
                 }
+                warp::reply::with_header(response, "vary", "accept").into_response()
             })
     };
 
-    // Add compatibility route for frontend API client
-    // Support nested paths for files (e.g., sub/dir/file.fmemo)
-    let file_route = {
+    // Past versions of a file from git: /api/files/{path}/history, /api/files/{path}/at/{rev}
+    // and /api/files/{path}/diff?from=REV&to=REV
+    let history_route = {
         let root_dir = root_dir.clone();
+        let plugins = plugins.clone();
         warp::path("api")
-            .and(warp::path("file"))
+            .and(warp::path("files"))
             .and(warp::path::tail())
             .and(warp::get())
-            .map(move |tail: warp::path::Tail| {
-                // Simple URL decode for %2F -> /
-                let filename = tail.as_str().replace("%2F", "/").replace("%2f", "/");
-                let file_path = root_dir.join(&filename);
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .and_then(move |tail: warp::path::Tail, query: std::collections::HashMap<String, String>| {
+                let root_dir = root_dir.clone();
+                let plugins = plugins.clone();
+                async move {
+                    let tail = percent_encoding::percent_decode_str(tail.as_str()).decode_utf8_lossy();
+                    let (filename, action) = split_file_action(&tail).ok_or_else(warp::reject::not_found)?;
+                    let action = match action.split_once('/') {
+                        None if action == "history" => FileRevisionAction::History,
+                        None if action == "diff" => FileRevisionAction::Diff,
+                        Some(("at", rev)) if !rev.is_empty() => FileRevisionAction::At(rev),
+                        _ => return Err(warp::reject::not_found()),
+                    };
+                    let result = match action {
+                        _ if resolve_memo_path(&root_dir, filename).is_none() => Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            INVALID_MEMO_PATH,
+                        )),
+                        FileRevisionAction::History => crate::git::file_history(&root_dir, filename)
+                            .map(|commits| serde_json::json!({"path": filename, "commits": commits})),
+                        FileRevisionAction::At(rev) => crate::git::resolve_revision(&root_dir, rev).and_then(|hash| {
+                            let content = crate::git::file_at_revision(&root_dir, filename, &hash)?;
+                            let mut document = plugins.parse(&crate::publish::visible(publish, content));
+                            resolve_image_paths(&mut document.memos, filename);
+                            Ok(serde_json::json!({
+                                "path": filename,
+                                "rev": hash,
+                                "memos": document.memos,
+                                "warnings": document.warnings
+                            }))
+                        }),
+                        // Defaults: from the last commit to the working tree
+                        FileRevisionAction::Diff => crate::git::diff_file(
+                            &root_dir,
+                            filename,
+                            query.get("from").map(String::as_str).unwrap_or("HEAD"),
+                            query.get("to").map(String::as_str),
+                            publish,
+                        )
+                        .map(|diff| {
+                            let mut body = serde_json::json!(diff);
+                            body["path"] = serde_json::json!(filename);
+                            body
+                        }),
+                    };
+                    Ok::<_, warp::Rejection>(match result {
+                        Ok(body) => warp::reply::with_status(warp::reply::json(&body), warp::http::StatusCode::OK),
+                        Err(e) => warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                            io_error_status(&e),
+                        ),
+                    })
+                }
+            })
+    };
 
-                match read_fmemo_file(&file_path) {
-                    Ok(content) => {
-                        // Transform to frontend expected format
-                        let response = serde_json::json!({
-                            "path": filename,
-                            "content": format!("# {}\n\nParsed from fmemo file", filename),
-                            "memos": content.memos
-                        });
-                        warp::reply::with_status(
-                            warp::reply::json(&response),
+    // Headings of a file without their bodies, for outlines: /api/files/{path}/toc
+    let toc_route = {
+        let root_dir = root_dir.clone();
+        let plugins = plugins.clone();
+        warp::path("api")
+            .and(warp::path("files"))
+            .and(warp::path::tail())
+            .and(warp::get())
+            .and_then(move |tail: warp::path::Tail| {
+                let root_dir = root_dir.clone();
+                let plugins = plugins.clone();
+                async move {
+                    let tail = percent_encoding::percent_decode_str(tail.as_str()).decode_utf8_lossy();
+                    let filename = match split_file_action(&tail) {
+                        Some((filename, "toc")) => filename,
+                        _ => return Err(warp::reject::not_found()),
+                    };
+                    let result = resolve_memo_path(&root_dir, filename)
+                        .ok_or_else(|| std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            INVALID_MEMO_PATH,
+                        ))
+                        .and_then(|path| read_fmemo_file_with(path, &plugins, publish));
+                    Ok::<_, warp::Rejection>(match result {
+                        Ok(content) => warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({
+                                "path": filename,
+                                "toc": crate::parser::table_of_contents(&content.memos)
+                            })),
                             warp::http::StatusCode::OK,
+                        ),
+                        Err(e) => warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                            io_error_status(&e),
+                        ),
+                    })
+                }
+            })
+    };
+
+    // A file as a page for printing or saving as PDF: /api/files/{path}/print, embeds
+    // expanded and placeholders filled in
+    let print_route = {
+        let root_dir = root_dir.clone();
+        let plugins = plugins.clone();
+        warp::path("api")
+            .and(warp::path("files"))
+            .and(warp::path::tail())
+            .and(warp::get())
+            .and_then(move |tail: warp::path::Tail| {
+                let root_dir = root_dir.clone();
+                let plugins = plugins.clone();
+                async move {
+                    use warp::Reply;
+                    let tail = percent_encoding::percent_decode_str(tail.as_str()).decode_utf8_lossy();
+                    let filename = match split_file_action(&tail) {
+                        Some((filename, "print")) => filename,
+                        _ => return Err(warp::reject::not_found()),
+                    };
+                    let result = resolve_memo_path(&root_dir, filename)
+                        .ok_or_else(|| std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            INVALID_MEMO_PATH,
+                        ))
+                        .and_then(|path| read_fmemo_file_with(path, &plugins, publish))
+                        .and_then(|mut content| {
+                            resolve_image_paths(&mut content.memos, filename);
+                            crate::embed::resolve_embeds(&root_dir, &plugins, publish, filename, &mut content.memos, true)?;
+                            let config = crate::config::load_config(&root_dir)?;
+                            let mut context = crate::template::TemplateContext::new(
+                                content.memos.first().map_or("", |memo| memo.title().as_str()),
+                                filename,
+                            );
+                            context.variables = config.variables;
+                            crate::template::expand_variables(&mut content.memos, &context);
+                            Ok(content.memos)
+                        });
+                    Ok::<_, warp::Rejection>(match result {
+                        Ok(memos) => warp::reply::html(crate::render::print_html(&memos, filename)).into_response(),
+                        Err(e) => warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                            io_error_status(&e),
                         )
-                    }
-                    Err(e) => {
-                        let error_msg = match e.kind() {
-                            std::io::ErrorKind::NotFound => "File not found",
-                            std::io::ErrorKind::InvalidInput => "Invalid file type (must be .fmemo or .md)",
-                            _ => "Failed to read file",
-                        };
-                        warp::reply::with_status(
-                            warp::reply::json(&serde_json::json!({"error": error_msg})),
-                            warp::http::StatusCode::NOT_FOUND,
+                        .into_response(),
+                    })
+                }
+            })
+    };
+
+    // A file as a word processor document: /api/files/{path}/export?format=docx|odt, converted
+    // by pandoc
+    let document_export_route = {
+        let root_dir = root_dir.clone();
+        warp::path("api")
+            .and(warp::path("files"))
+            .and(warp::path::tail())
+            .and(warp::get())
+            .and_then(|tail: warp::path::Tail| async move {
+                let tail = percent_encoding::percent_decode_str(tail.as_str()).decode_utf8_lossy();
+                match split_file_action(&tail) {
+                    Some((filename, "export")) => Ok(filename.to_string()),
+                    _ => Err(warp::reject::not_found()),
+                }
+            })
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .and_then(move |filename: String, query: std::collections::HashMap<String, String>| {
+                let root_dir = root_dir.clone();
+                async move {
+                    use warp::Reply;
+                    let format = crate::pandoc::DocumentFormat::from_name(
+                        query.get("format").map_or("", String::as_str),
+                    );
+                    let result = match (format, resolve_memo_path(&root_dir, &filename)) {
+                        (Err(e), _) => Err(e),
+                        (_, None) => Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            INVALID_MEMO_PATH,
+                        )),
+                        // pandoc runs as a blocking child process
+                        (Ok(format), Some(path)) => tokio::task::spawn_blocking(move || {
+                            crate::pandoc::export_file(&root_dir, &path, format, publish).map(|document| (format, document))
+                        })
+                        .await
+                        .unwrap_or_else(|e| Err(std::io::Error::other(e.to_string()))),
+                    };
+                    Ok::<_, warp::Rejection>(match result {
+                        Ok((format, document)) => {
+                            let stem = crate::graph::wiki_name(&filename).rsplit('/').next().unwrap_or_default().to_string();
+                            let disposition = format!(
+                                "attachment; filename*=UTF-8''{}.{}",
+                                crate::sitemap::encode_path(&stem),
+                                format.name()
+                            );
+                            let reply = warp::reply::with_header(document, "content-type", format.content_type());
+                            warp::reply::with_header(reply, "content-disposition", disposition).into_response()
+                        }
+                        Err(e) => {
+                            let status = match e.kind() {
+                                std::io::ErrorKind::Unsupported => warp::http::StatusCode::NOT_IMPLEMENTED,
+                                std::io::ErrorKind::TimedOut => warp::http::StatusCode::GATEWAY_TIMEOUT,
+                                _ => io_error_status(&e),
+                            };
+                            warp::reply::with_status(
+                                warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                                status,
+                            )
+                            .into_response()
+                        }
+                    })
+                }
+            })
+    };
+
+    // A file as plain text: /api/files/{path}/text, ?memo=<slug> for one memo and its
+    // children, ?format=json for each memo's own text
+    let text_route = {
+        let root_dir = root_dir.clone();
+        let plugins = plugins.clone();
+        warp::path("api")
+            .and(warp::path("files"))
+            .and(warp::path::tail())
+            .and(warp::get())
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .and_then(move |tail: warp::path::Tail, query: std::collections::HashMap<String, String>| {
+                let root_dir = root_dir.clone();
+                let plugins = plugins.clone();
+                async move {
+                    use warp::Reply;
+                    let tail = percent_encoding::percent_decode_str(tail.as_str()).decode_utf8_lossy();
+                    let filename = match split_file_action(&tail) {
+                        Some((filename, "text")) => filename,
+                        _ => return Err(warp::reject::not_found()),
+                    };
+                    let result = resolve_memo_path(&root_dir, filename)
+                        .ok_or_else(|| std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            INVALID_MEMO_PATH,
+                        ))
+                        .and_then(|path| read_fmemo_file_with(path, &plugins, publish))
+                        .and_then(|content| match query.get("memo") {
+                            None => Ok(content.memos),
+                            Some(slug) => crate::parser::find_memo_by_slug(&content.memos, slug)
+                                .map(|memo| vec![memo.clone()])
+                                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Memo not found")),
+                        });
+                    Ok::<_, warp::Rejection>(match result {
+                        Ok(memos) if query.get("format").is_some_and(|format| format == "json") => {
+                            fn collect(memos: &[crate::schema::Memo], out: &mut Vec<serde_json::Value>) {
+                                for memo in memos {
+                                    out.push(serde_json::json!({
+                                        "slug": memo.anchor(),
+                                        "title": memo.title(),
+                                        "line": memo.span().map_or(0, |span| span.start_line),
+                                        "text": crate::text::memo_text(memo),
+                                    }));
+                                    collect(memo.children(), out);
+                                }
+                            }
+                            let mut texts = Vec::new();
+                            collect(&memos, &mut texts);
+                            warp::reply::json(&serde_json::json!({"path": filename, "memos": texts})).into_response()
+                        }
+                        Ok(memos) => warp::reply::with_header(
+                            crate::text::document_text(&memos),
+                            "content-type",
+                            "text/plain; charset=utf-8",
                         )
-                    }
+                        .into_response(),
+                        Err(e) => warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                            io_error_status(&e),
+                        )
+                        .into_response(),
+                    })
                 }
             })
     };
 
-    // Add CORS headers for API routes
-    let cors = warp::cors()
-        .allow_any_origin()
-        .allow_headers(vec!["content-type"])
-        .allow_methods(vec!["GET", "POST", "PUT", "DELETE"]);
+    // GET /api/ws/clients - connected WebSocket clients, oldest first; admins only (see
+    // `app::require_auth`)
+    let ws_clients_route = {
+        let clients = clients.clone();
+        warp::path!("api" / "ws" / "clients")
+            .and(warp::get())
+            .map(move || {
+                let infos: Vec<WebSocketClientInfo> = match &clients {
+                    Some(clients) => clients.lock().unwrap().iter().map(WebSocketClient::info).collect(),
+                    None => Vec::new(),
+                };
+                warp::reply::json(&serde_json::json!({"clients": infos}))
+            })
+    };
 
-    root_route
-        .or(files_route)
-        .or(file_route)
-        .with(cors)
-}
+    // Whether the server sees changes on disk: "degraded" while the directory watcher has
+    // failed and isn't re-established yet
+    let health_route = warp::path!("api" / "health")
+        .and(warp::get())
+        .map(move || {
+            let status = watcher.as_ref().map(crate::watcher::WatcherHealth::status).unwrap_or_default();
+            let health = if status.degraded { "degraded" } else { "ok" };
+            warp::reply::json(&serde_json::json!({"status": health, "watcher": status}))
+        });
 
-/// Create full server routes (API + WebSocket + optionally static files)
-pub fn create_full_routes(
+    // Unknown words in a file: /api/files/{path}/spelling, in the configured languages or
+    // those of ?lang=en_US,de_DE
+    let spelling_route = {
+        let root_dir = root_dir.clone();
+        warp::path("api")
+            .and(warp::path("files"))
+            .and(warp::path::tail())
+            .and(warp::get())
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .and_then(move |tail: warp::path::Tail, query: std::collections::HashMap<String, String>| {
+                let root_dir = root_dir.clone();
+                async move {
+                    let tail = percent_encoding::percent_decode_str(tail.as_str()).decode_utf8_lossy();
+                    let filename = match split_file_action(&tail) {
+                        Some((filename, "spelling")) => filename,
+                        _ => return Err(warp::reject::not_found()),
+                    };
+                    let result = resolve_memo_path(&root_dir, filename)
+                        .ok_or_else(|| std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            INVALID_MEMO_PATH,
+                        ))
+                        .and_then(|path| {
+                            let mut config = crate::config::load_config(&root_dir)?.spellcheck;
+                            if let Some(lang) = query.get("lang") {
+                                config.languages = lang.split(',').map(|l| l.trim().to_string()).collect();
+                            }
+                            let speller = crate::spell::Spellchecker::load(&root_dir, &config)?;
+                            let content = crate::publish::read_memo(&path, publish)?;
+                            Ok(serde_json::json!({
+                                "path": filename,
+                                "languages": config.languages,
+                                "misspellings": speller.misspellings(&content)
+                            }))
+                        });
+                    Ok::<_, warp::Rejection>(match result {
+                        Ok(body) => warp::reply::with_status(warp::reply::json(&body), warp::http::StatusCode::OK),
+                        Err(e) => warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                            io_error_status(&e),
+                        ),
+                    })
+                }
+            })
+    };
+
+    // One heading subtree as Markdown: /api/files/{path}/memos/{slug}/markdown
+    let memo_markdown_route = {
+        let root_dir = root_dir.clone();
+        let plugins = plugins.clone();
+        warp::path("api")
+            .and(warp::path("files"))
+            .and(warp::path::tail())
+            .and_then(|tail: warp::path::Tail| async move {
+                let tail = percent_encoding::percent_decode_str(tail.as_str()).decode_utf8_lossy();
+                let (filename, action) = split_file_action(&tail).ok_or_else(warp::reject::not_found)?;
+                let slug = action
+                    .strip_prefix("memos/")
+                    .and_then(|rest| rest.strip_suffix("/markdown"))
+                    .filter(|slug| !slug.is_empty() && !slug.contains('/'))
+                    .ok_or_else(warp::reject::not_found)?;
+                Ok::<_, warp::Rejection>((filename.to_string(), slug.to_string()))
+            })
+            .untuple_one()
+            .and(warp::get())
+            .map(move |filename: String, slug: String| {
+                use warp::Reply;
+                let result = resolve_memo_path(&root_dir, &filename)
+                    .ok_or_else(|| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            INVALID_MEMO_PATH,
+                        )
+                    })
+                    .and_then(|path| crate::publish::read_memo(&path, publish))
+                    .and_then(|content| {
+                        let document = plugins.parse(&content);
+                        crate::parser::find_memo_by_slug(&document.memos, &slug)
+                            .and_then(|memo| crate::parser::subtree_markdown(&content, memo))
+                            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Memo not found"))
+                    });
+                match result {
+                    Ok(markdown) => {
+                        warp::reply::with_header(markdown, "content-type", "text/markdown; charset=utf-8").into_response()
+                    }
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                        io_error_status(&e),
+                    )
+                    .into_response(),
+                }
+            })
+    };
+
+    // Comments on a memo: GET and POST /api/files/{path}/memos/{slug}/comments
+    let comments_route = {
+        let root_dir = root_dir.clone();
+        let plugins = plugins.clone();
+        let clients = clients.clone();
+        warp::path("api")
+            .and(warp::path("files"))
+            .and(warp::path::tail())
+            .and_then(|tail: warp::path::Tail| async move {
+                let tail = percent_encoding::percent_decode_str(tail.as_str()).decode_utf8_lossy();
+                let (filename, action) = split_file_action(&tail).ok_or_else(warp::reject::not_found)?;
+                let slug = action
+                    .strip_prefix("memos/")
+                    .and_then(|rest| rest.strip_suffix("/comments"))
+                    .filter(|slug| !slug.is_empty() && !slug.contains('/'))
+                    .ok_or_else(warp::reject::not_found)?;
+                Ok::<_, warp::Rejection>((filename.to_string(), slug.to_string()))
+            })
+            .untuple_one()
+            .and(
+                warp::get()
+                    .map(|| None)
+                    .or(warp::post().and(warp::body::json()).map(Some))
+                    .unify(),
+            )
+            .and(actor.clone())
+            .map(move |filename: String, slug: String, request: Option<CommentRequest>, actor: Option<String>| {
+                let memo_exists = resolve_memo_path(&root_dir, &filename)
+                    .ok_or_else(|| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            INVALID_MEMO_PATH,
+                        )
+                    })
+                    .and_then(|path| crate::publish::read_memo(&path, publish))
+                    .and_then(|content| {
+                        crate::parser::find_memo_by_slug(&plugins.parse(&content).memos, &slug)
+                            .map(|_| ())
+                            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Memo not found"))
+                    });
+                let result = match request {
+                    None => memo_exists
+                        .and_then(|_| crate::comments::list_comments(&root_dir, &filename, &slug))
+                        .map(|comments| (warp::http::StatusCode::OK, serde_json::json!({"comments": comments}))),
+                    Some(request) => memo_exists
+                        .and_then(|_| {
+                            let author = actor.or(request.author.filter(|author| !author.trim().is_empty()));
+                            crate::comments::add_comment(&root_dir, &filename, &slug, author, &request.body)
+                        })
+                        .map(|comment| {
+                            if let Some(clients) = &clients {
+                                broadcast_file_message(
+                                    clients,
+                                    &filename,
+                                    WsServerMessage::CommentAdded { path: filename.clone(), comment: comment.clone() },
+                                );
+                            }
+                            (warp::http::StatusCode::CREATED, serde_json::json!(comment))
+                        }),
+                };
+                match result {
+                    Ok((status, body)) => warp::reply::with_status(warp::reply::json(&body), status),
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                        io_error_status(&e),
+                    ),
+                }
+            })
+    };
+
+    // Add compatibility route for frontend API client
+    // Support nested paths for files (e.g., sub/dir/file.fmemo)
+    let file_route = {
+        let root_dir = root_dir.clone();
+        let plugins = plugins.clone();
+        warp::path("api")
+            .and(warp::path("file"))
+            .and(warp::path::tail())
+            .and(warp::get())
+            .map(move |tail: warp::path::Tail| {
+                // Simple URL decode for %2F -> /
+                let filename = tail.as_str().replace("%2F", "/").replace("%2f", "/");
+                let file_path = root_dir.join(&filename);
+
+                match read_fmemo_file_with(&file_path, &plugins, publish) {
+                    Ok(mut content) => {
+                        resolve_image_paths(&mut content.memos, &filename);
+                        // Transform to frontend expected format
+                        let response = serde_json::json!({
+                            "path": filename,
+                            "content": format!("# {}\n\nParsed from fmemo file", filename),
+                            "memos": content.memos
+                        });
+                        warp::reply::with_status(
+                            warp::reply::json(&response),
+                            warp::http::StatusCode::OK,
+                        )
+                    }
+                    Err(e) => {
+                        let error_msg = match e.kind() {
+                            std::io::ErrorKind::NotFound => "File not found",
+                            std::io::ErrorKind::InvalidInput => "Invalid file type (must be .fmemo, .md or .fmemox)",
+                            _ => "Failed to read file",
+                        };
+                        warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"error": error_msg})),
+                            warp::http::StatusCode::NOT_FOUND,
+                        )
+                    }
+                }
+            })
+    };
+
+    // Bring back a file's content from a git revision; the watcher broadcasts it like any edit
+    let restore_route = {
+        let root_dir = root_dir.clone();
+        let plugins = plugins.clone();
+        warp::path("api")
+            .and(warp::path("files"))
+            .and(warp::path::tail())
+            .and_then(|tail: warp::path::Tail| async move {
+                let tail = percent_encoding::percent_decode_str(tail.as_str()).decode_utf8_lossy();
+                match split_file_action(&tail) {
+                    Some((filename, "restore")) => Ok(filename.to_string()),
+                    _ => Err(warp::reject::not_found()),
+                }
+            })
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(actor.clone())
+            .map(move |filename: String, request: RestoreRequest, actor: Option<String>| {
+                let mut previous = String::new();
+                let result = match resolve_memo_path(&root_dir, &filename) {
+                    None => Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        INVALID_MEMO_PATH,
+                    )),
+                    Some(file_path) => {
+                        previous = crate::crypt::read_memo(&file_path).unwrap_or_default();
+                        crate::git::restore_file(&root_dir, &filename, &request.rev)
+                            .and_then(|hash| Ok((hash, read_fmemo_file_with(&file_path, &plugins, publish)?)))
+                    }
+                };
+                match result {
+                    Ok((hash, mut content)) => {
+                        let restored = crate::crypt::read_memo(&root_dir.join(&filename)).unwrap_or_default();
+                        crate::audit::log(
+                            &root_dir,
+                            crate::audit::AuditEntry::new(actor, crate::audit::AuditAction::Restore, &filename)
+                                .diff(&previous, &restored),
+                        );
+                        resolve_image_paths(&mut content.memos, &filename);
+                        warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({
+                                "path": filename,
+                                "rev": hash,
+                                "memos": content.memos,
+                                "warnings": content.warnings
+                            })),
+                            warp::http::StatusCode::OK,
+                        )
+                    }
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                        io_error_status(&e),
+                    ),
+                }
+            })
+    };
+
+    // Summary of a file, or of one memo with its children, from the configured LLM backend
+    let summarize_route = {
+        let root_dir = root_dir.clone();
+        warp::path("api")
+            .and(warp::path("files"))
+            .and(warp::path::tail())
+            .and_then(|tail: warp::path::Tail| async move {
+                let tail = percent_encoding::percent_decode_str(tail.as_str()).decode_utf8_lossy();
+                match split_file_action(&tail) {
+                    Some((filename, "summarize")) => Ok(filename.to_string()),
+                    _ => Err(warp::reject::not_found()),
+                }
+            })
+            .and(warp::post())
+            .and(warp::body::json())
+            .and_then(move |filename: String, request: SummarizeRequest| {
+                let root_dir = root_dir.clone();
+                async move {
+                    let result = match resolve_memo_path(&root_dir, &filename) {
+                        None => Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            INVALID_MEMO_PATH,
+                        )),
+                        // The backend is called with a blocking client
+                        Some(_) => tokio::task::spawn_blocking(move || {
+                            crate::llm::summarize_file(&root_dir, &filename, &request)
+                        })
+                        .await
+                        .unwrap_or_else(|e| Err(std::io::Error::other(e.to_string()))),
+                    };
+                    Ok::<_, warp::Rejection>(match result {
+                        Ok(summary) => warp::reply::with_status(warp::reply::json(&summary), warp::http::StatusCode::OK),
+                        Err(e) => {
+                            let status = match e.kind() {
+                                std::io::ErrorKind::Unsupported => warp::http::StatusCode::NOT_IMPLEMENTED,
+                                std::io::ErrorKind::NotFound | std::io::ErrorKind::InvalidInput => io_error_status(&e),
+                                // The backend failed or answered with something unusable
+                                _ => warp::http::StatusCode::BAD_GATEWAY,
+                            };
+                            warp::reply::with_status(
+                                warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                                status,
+                            )
+                        }
+                    })
+                }
+            })
+    };
+
+    // Delete a file by moving it to the trash
+    let delete_route = {
+        let root_dir = root_dir.clone();
+        warp::path("api")
+            .and(warp::path("files"))
+            .and(warp::path::tail())
+            // Match the path before the method, so other requests below /api/files still get 404s
+            .and_then(|tail: warp::path::Tail| async move {
+                let filename = percent_encoding::percent_decode_str(tail.as_str()).decode_utf8_lossy().to_string();
+                if filename.ends_with(".fmemo") || filename.ends_with(".md") || filename.ends_with(".fmemox") {
+                    Ok(filename)
+                } else {
+                    Err(warp::reject::not_found())
+                }
+            })
+            .and(warp::delete())
+            .and(actor.clone())
+            .map(move |filename: String, actor: Option<String>| {
+                let result = match resolve_memo_path(&root_dir, &filename) {
+                    None => Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        INVALID_MEMO_PATH,
+                    )),
+                    Some(_) => crate::trash::trash_file(&root_dir, &filename),
+                };
+                match result {
+                    Ok(entry) => {
+                        crate::audit::log(
+                            &root_dir,
+                            crate::audit::AuditEntry::new(actor, crate::audit::AuditAction::Delete, &filename),
+                        );
+                        warp::reply::with_status(
+                            warp::reply::json(&entry),
+                            warp::http::StatusCode::OK,
+                        )
+                    }
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                        io_error_status(&e),
+                    ),
+                }
+            })
+    };
+
+    let trash_route = {
+        let root_dir = root_dir.clone();
+        warp::path!("api" / "trash")
+            .and(warp::get())
+            .map(move || match crate::trash::list_trash(&root_dir) {
+                Ok(items) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"trash": items})),
+                    warp::http::StatusCode::OK,
+                ),
+                Err(e) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                    io_error_status(&e),
+                ),
+            })
+    };
+
+    // Put a trashed file back where it was
+    let trash_restore_route = {
+        let root_dir = root_dir.clone();
+        warp::path!("api" / "trash" / String / "restore")
+            .and(warp::post())
+            .and(actor.clone())
+            .map(move |id: String, actor: Option<String>| match crate::trash::restore(&root_dir, &id) {
+                Ok(path) => {
+                    crate::audit::log(
+                        &root_dir,
+                        crate::audit::AuditEntry::new(actor, crate::audit::AuditAction::Untrash, &path),
+                    );
+                    warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"path": path})),
+                        warp::http::StatusCode::OK,
+                    )
+                }
+                Err(e) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                    io_error_status(&e),
+                ),
+            })
+    };
+
+    // The most fetched files: GET /api/popular?limit=N
+    let popular_route = warp::path!("api" / "popular")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .map(move |query: std::collections::HashMap<String, String>| {
+            let limit = match query.get("limit").map(|limit| limit.parse::<usize>()) {
+                None => Ok(MAX_POPULAR_FILES),
+                Some(Ok(limit)) => Ok(limit),
+                Some(Err(_)) => Err("limit must be a number"),
+            };
+            match (limit, &views) {
+                (Ok(limit), Some(views)) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"files": views.popular(limit)})),
+                    warp::http::StatusCode::OK,
+                ),
+                (Ok(_), None) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"error": "Views are not counted"})),
+                    warp::http::StatusCode::NOT_IMPLEMENTED,
+                ),
+                (Err(error), _) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"error": error})),
+                    warp::http::StatusCode::BAD_REQUEST,
+                ),
+            }
+        });
+
+    // Move memos not modified for a while into the archive directory:
+    // POST /api/archive {"older_than": "1y", "dry_run": false}
+    let archive_route = {
+        let root_dir = root_dir.clone();
+        warp::path!("api" / "archive")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(actor.clone())
+            .and_then(move |request: ArchiveRequest, actor: Option<String>| {
+                let root_dir = root_dir.clone();
+                async move {
+                    let result = tokio::task::spawn_blocking(move || {
+                        let config = crate::config::load_config(&root_dir)?.archive;
+                        let older_than = request.older_than.as_deref().unwrap_or(&config.older_than);
+                        let cutoff = crate::archive::cutoff(chrono::Utc::now(), older_than)?;
+                        let report = crate::archive::archive(&root_dir, &config.dir, cutoff, request.dry_run)?;
+                        if !request.dry_run {
+                            for file in &report.archived {
+                                crate::audit::log(
+                                    &root_dir,
+                                    crate::audit::AuditEntry::new(actor.clone(), crate::audit::AuditAction::Archive, &file.from),
+                                );
+                            }
+                        }
+                        Ok::<_, std::io::Error>(report)
+                    })
+                    .await
+                    .unwrap_or_else(|e| Err(std::io::Error::other(e.to_string())));
+                    Ok::<_, warp::Rejection>(match result {
+                        Ok(report) => warp::reply::with_status(warp::reply::json(&report), warp::http::StatusCode::OK),
+                        Err(e) => warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                            io_error_status(&e),
+                        ),
+                    })
+                }
+            })
+    };
+
+    // Changes made through the API, newest first:
+    // GET /api/audit?path=&actor=&action=&since=&limit=
+    let audit_route = {
+        let root_dir = root_dir.clone();
+        warp::path!("api" / "audit")
+            .and(warp::get())
+            .and(warp::query::<crate::audit::AuditQuery>())
+            .map(move |query: crate::audit::AuditQuery| match crate::audit::query(&root_dir, &query) {
+                Ok(entries) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"entries": entries})),
+                    warp::http::StatusCode::OK,
+                ),
+                Err(e) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                    io_error_status(&e),
+                ),
+            })
+    };
+
+    // Extract an uploaded zip archive (multipart field `file`) below the root:
+    // POST /api/import?dir=<directory>&on_conflict=skip|overwrite|rename
+    let import_route = {
+        let root_dir = root_dir.clone();
+        warp::path!("api" / "import")
+            .and(warp::post())
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .and(warp::multipart::form().max_length(MAX_IMPORT_UPLOAD))
+            .and(actor.clone())
+            .and_then(move |query: std::collections::HashMap<String, String>, form: warp::multipart::FormData, actor: Option<String>| {
+                let root_dir = root_dir.clone();
+                async move {
+                    let config = crate::config::load_config(&root_dir).unwrap_or_default();
+                    let max_size = config.import.max_size_mb.saturating_mul(1024 * 1024);
+                    let on_conflict = match query.get("on_conflict") {
+                        Some(policy) => serde_json::from_value(serde_json::json!(policy)).map_err(|_| {
+                            std::io::Error::new(
+                                std::io::ErrorKind::InvalidInput,
+                                "on_conflict must be skip, overwrite or rename",
+                            )
+                        }),
+                        None => Ok(config.import.on_conflict),
+                    };
+                    let result = match on_conflict {
+                        Ok(on_conflict) => read_upload(form, "file", max_size).await.and_then(|archive| {
+                            let options = crate::import::ImportOptions {
+                                directory: query.get("dir").cloned().unwrap_or_default(),
+                                max_size,
+                                on_conflict,
+                            };
+                            crate::import::import_zip(&root_dir, &archive, &options)
+                        }),
+                        Err(e) => Err(e),
+                    };
+                    Ok::<_, warp::Rejection>(match result {
+                        Ok(report) => {
+                            for file in &report.imported {
+                                crate::audit::log(
+                                    &root_dir,
+                                    crate::audit::AuditEntry::new(actor.clone(), crate::audit::AuditAction::Import, &file.path),
+                                );
+                            }
+                            warp::reply::with_status(warp::reply::json(&report), warp::http::StatusCode::OK)
+                        }
+                        Err(e) => warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                            io_error_status(&e),
+                        ),
+                    })
+                }
+            })
+    };
+
+    // Save a file edited in the UI
+    let write_route = {
+        let root_dir = root_dir.clone();
+        let plugins = plugins.clone();
+        warp::path("api")
+            .and(warp::path("file"))
+            .and(warp::path::tail())
+            .and(warp::put())
+            .and(warp::body::json())
+            .and(actor.clone())
+            .map(move |tail: warp::path::Tail, request: WriteFileRequest, actor: Option<String>| {
+                let filename = tail.as_str().replace("%2F", "/").replace("%2f", "/");
+                let Some(file_path) = resolve_memo_path(&root_dir, &filename) else {
+                    return warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": INVALID_MEMO_PATH})),
+                        warp::http::StatusCode::BAD_REQUEST,
+                    );
+                };
+
+                let previous = crate::crypt::read_memo(&file_path).unwrap_or_default();
+                match write_fmemo_file(&file_path, &request).and_then(|_| read_fmemo_file_with(&file_path, &plugins, publish)) {
+                    Ok(mut content) => {
+                        let saved = crate::crypt::read_memo(&file_path).unwrap_or_default();
+                        crate::audit::log(
+                            &root_dir,
+                            crate::audit::AuditEntry::new(actor, crate::audit::AuditAction::Write, &filename)
+                                .diff(&previous, &saved),
+                        );
+                        // The draft has been saved for real
+                        if let Err(e) = crate::draft::discard_draft(&root_dir, &filename) {
+                            eprintln!("Failed to discard draft of {}: {}", filename, e);
+                        }
+                        resolve_image_paths(&mut content.memos, &filename);
+                        warp::reply::with_status(
+                            warp::reply::json(&content),
+                            warp::http::StatusCode::OK,
+                        )
+                    }
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": format!("Failed to write file: {}", e)})),
+                        io_error_status(&e),
+                    ),
+                }
+            })
+    };
+
+    // Autosaved drafts: GET, PUT and DELETE /api/files/{path}/draft. The file itself
+    // doesn't have to exist yet.
+    let draft_file = {
+        let root_dir = root_dir.clone();
+        warp::path("api")
+            .and(warp::path("files"))
+            .and(warp::path::tail())
+            .and_then(move |tail: warp::path::Tail| {
+                let root_dir = root_dir.clone();
+                async move {
+                    let tail = percent_encoding::percent_decode_str(tail.as_str()).decode_utf8_lossy();
+                    match split_file_action(&tail) {
+                        Some((filename, "draft")) => Ok(resolve_memo_path(&root_dir, filename)
+                            .map(|_| filename.to_string())
+                            .ok_or_else(|| std::io::Error::new(
+                                std::io::ErrorKind::InvalidInput,
+                                INVALID_MEMO_PATH,
+                            ))),
+                        _ => Err(warp::reject::not_found()),
+                    }
+                }
+            })
+    };
+    let draft_reply = |result: std::io::Result<serde_json::Value>| match result {
+        Ok(body) => warp::reply::with_status(
+            warp::reply::json(&body),
+            warp::http::StatusCode::OK,
+        ),
+        Err(e) => warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+            io_error_status(&e),
+        ),
+    };
+    let get_draft_route = {
+        let root_dir = root_dir.clone();
+        draft_file
+            .clone()
+            .and(warp::get())
+            .map(move |filename: std::io::Result<String>| {
+                draft_reply(filename.and_then(|filename| {
+                    crate::draft::load_draft(&root_dir, &filename).map(|mut draft| {
+                        draft.content = crate::publish::visible(publish, draft.content);
+                        serde_json::json!(draft)
+                    })
+                }))
+            })
+    };
+    let put_draft_route = {
+        let root_dir = root_dir.clone();
+        draft_file
+            .clone()
+            .and(warp::put())
+            .and(warp::body::json())
+            .map(move |filename: std::io::Result<String>, request: DraftRequest| {
+                draft_reply(filename.and_then(|filename| {
+                    crate::draft::save_draft(&root_dir, &filename, &request.content)
+                        .map(|draft| serde_json::json!(draft))
+                }))
+            })
+    };
+    let delete_draft_route = {
+        let root_dir = root_dir.clone();
+        draft_file
+            .and(warp::delete())
+            .map(move |filename: std::io::Result<String>| {
+                draft_reply(filename.and_then(|filename| {
+                    crate::draft::discard_draft(&root_dir, &filename)
+                        .map(|discarded| serde_json::json!({"path": filename, "discarded": discarded}))
+                }))
+            })
+    };
+
+    // Templates from .fmemo/templates and creating memos from them
+    let templates_route = {
+        let root_dir = root_dir.clone();
+        warp::path!("api" / "templates")
+            .and(warp::get())
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .map(move |query: std::collections::HashMap<String, String>| {
+                let dir = query.get("dir").map(String::as_str).unwrap_or("");
+                match crate::template::list_templates(&root_dir, dir) {
+                Ok(templates) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"templates": templates})),
+                    warp::http::StatusCode::OK,
+                ),
+                Err(_) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"error": "Failed to list templates"})),
+                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                ),
+                }
+            })
+    };
+
+    // Preview a new memo: its path, rendered template and cursor, without creating it
+    let render_template_route = {
+        let root_dir = root_dir.clone();
+        warp::path!("api" / "templates" / "render")
+            .and(warp::post())
+            .and(warp::body::json())
+            .map(move |request: NewMemoRequest| {
+                match crate::template::render_new_memo(&root_dir, &request) {
+                    Ok(rendered) => warp::reply::with_status(
+                        warp::reply::json(&rendered),
+                        warp::http::StatusCode::OK,
+                    ),
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                        io_error_status(&e),
+                    ),
+                }
+            })
+    };
+
+    let from_template_route = {
+        let root_dir = root_dir.clone();
+        let plugins = plugins.clone();
+        warp::path!("api" / "files" / "from-template")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(actor.clone())
+            .map(move |request: NewMemoRequest, actor: Option<String>| {
+                let created = crate::template::create_from_template(&root_dir, &request)
+                    .and_then(|rendered| Ok((read_fmemo_file_with(root_dir.join(&rendered.path), &plugins, publish)?, rendered)));
+                match created {
+                    Ok((content, rendered)) => {
+                        crate::audit::log(
+                            &root_dir,
+                            crate::audit::AuditEntry::new(actor, crate::audit::AuditAction::Create, &rendered.path)
+                                .diff("", &rendered.content),
+                        );
+                        warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({
+                                "path": rendered.path,
+                                "cursor": rendered.cursor,
+                                "memos": content.memos
+                            })),
+                            warp::http::StatusCode::CREATED,
+                        )
+                    }
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                        io_error_status(&e),
+                    ),
+                }
+            })
+    };
+
+    // Merge one memo file into another: POST /api/files/merge
+    // {"source", "target", "mode": "append"|"interleave", "level", "source_action": "trash"|"delete"|"keep"}
+    let merge_route = {
+        let root_dir = root_dir.clone();
+        let plugins = plugins.clone();
+        let clients = clients.clone();
+        warp::path!("api" / "files" / "merge")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(actor.clone())
+            .map(move |request: MergeRequest, actor: Option<String>| {
+                let merged = crate::merge::merge_files(&root_dir, &request)
+                    .and_then(|merged| Ok((read_fmemo_file_with(root_dir.join(&request.target), &plugins, publish)?, merged)));
+                match merged {
+                    Ok((mut content, merged)) => {
+                        crate::audit::log(
+                            &root_dir,
+                            crate::audit::AuditEntry::new(actor.clone(), crate::audit::AuditAction::Write, &request.target)
+                                .diff(&merged.previous, &merged.content),
+                        );
+                        let removed = request.source_action != crate::merge::SourceAction::Keep;
+                        if removed {
+                            crate::audit::log(
+                                &root_dir,
+                                crate::audit::AuditEntry::new(actor, crate::audit::AuditAction::Delete, &request.source),
+                            );
+                        }
+                        if let Some(clients) = &clients {
+                            broadcast_to_clients(
+                                clients,
+                                WsServerMessage::FilesMerged { source: request.source.clone(), target: request.target.clone(), removed },
+                            );
+                        }
+                        resolve_image_paths(&mut content.memos, &request.target);
+                        warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({
+                                "path": request.target,
+                                "memos": content.memos,
+                                "trash": merged.trash
+                            })),
+                            warp::http::StatusCode::OK,
+                        )
+                    }
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                        io_error_status(&e),
+                    ),
+                }
+            })
+    };
+
+    // Break a file into one file per top-level memo: POST /api/files/{path}/split
+    // {"source_action": "trash"|"delete"|"keep"}; the body is optional
+    let split_route = {
+        let root_dir = root_dir.clone();
+        warp::path("api")
+            .and(warp::path("files"))
+            .and(warp::path::tail())
+            .and_then(|tail: warp::path::Tail| async move {
+                let tail = percent_encoding::percent_decode_str(tail.as_str()).decode_utf8_lossy();
+                match split_file_action(&tail) {
+                    Some((filename, "split")) => Ok(filename.to_string()),
+                    _ => Err(warp::reject::not_found()),
+                }
+            })
+            .and(warp::post())
+            .and(warp::body::json().or(warp::any().map(SplitRequest::default)).unify())
+            .and(actor.clone())
+            .map(move |filename: String, request: SplitRequest, actor: Option<String>| {
+                match crate::merge::split_file(&root_dir, &filename, &request) {
+                    Ok(split) => {
+                        for (file, content) in &split.files {
+                            crate::audit::log(
+                                &root_dir,
+                                crate::audit::AuditEntry::new(actor.clone(), crate::audit::AuditAction::Create, file).diff("", content),
+                            );
+                        }
+                        if request.source_action != crate::merge::SourceAction::Keep {
+                            crate::audit::log(
+                                &root_dir,
+                                crate::audit::AuditEntry::new(actor, crate::audit::AuditAction::Delete, &filename),
+                            );
+                        }
+                        let files: Vec<&String> = split.files.iter().map(|(file, _)| file).collect();
+                        warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"files": files, "trash": split.trash})),
+                            warp::http::StatusCode::CREATED,
+                        )
+                    }
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                        io_error_status(&e),
+                    ),
+                }
+            })
+    };
+
+    // Serve images and other assets referenced from memos. Hidden paths are excluded, and so
+    // are memo files, which only `/api/files` serves (with private regions cut out when
+    // published). `fs::dir` percent-decodes the path before splitting it, so the checks look
+    // at the decoded one
+    let assets_route = warp::path("api")
+        .and(warp::path("assets"))
+        .and(warp::get())
+        .and(warp::path::peek())
+        .and_then(|peek: warp::path::Peek| async move {
+            let path = percent_encoding::percent_decode_str(peek.as_str()).decode_utf8_lossy();
+            let ext = Path::new(path.as_ref()).extension().and_then(|s| s.to_str());
+            if path.split('/').any(|segment| segment.starts_with('.'))
+                || matches!(ext, Some("fmemo" | "md" | crate::crypt::EXTENSION))
+            {
+                Err(warp::reject::not_found())
+            } else {
+                Ok(())
+            }
+        })
+        .untuple_one()
+        .and(warp::fs::dir(root_dir.clone()));
+
+    // Server-side diagram rendering for clients without JS
+    let diagram_route = warp::path!("api" / "diagrams" / "render")
+        .and(warp::post())
+        .and(warp::body::json())
+        .map(|diagram: crate::schema::Diagram| {
+            use warp::Reply;
+            match crate::diagram::render_svg(diagram.kind, &diagram.source) {
+                Ok(svg) => warp::reply::with_header(svg, "content-type", "image/svg+xml").into_response(),
+                Err(e) => {
+                    let (error_msg, status) = match e.kind() {
+                        std::io::ErrorKind::NotFound => (
+                            "Diagram renderer not installed".to_string(),
+                            warp::http::StatusCode::NOT_IMPLEMENTED,
+                        ),
+                        _ => (
+                            format!("Failed to render diagram: {}", e),
+                            warp::http::StatusCode::UNPROCESSABLE_ENTITY,
+                        ),
+                    };
+                    warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": error_msg})),
+                        status,
+                    )
+                    .into_response()
+                }
+            }
+        });
+
+    // Files, memos and tags with the links between them, for a graph view
+    let graph_route = {
+        let root_dir = root_dir.clone();
+        warp::path!("api" / "graph")
+            .and(warp::get())
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .map(move |query: std::collections::HashMap<String, String>| {
+                let files_only = query.get("memos").is_some_and(|memos| memos == "false");
+                match crate::graph::build_graph(&root_dir, files_only, publish) {
+                    Ok(graph) => warp::reply::with_status(
+                        warp::reply::json(&graph),
+                        warp::http::StatusCode::OK,
+                    ),
+                    Err(_) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": "Failed to scan directory"})),
+                        warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                    ),
+                }
+            })
+    };
+
+    // Links, wiki-links and images whose target is gone, e.g. after a rename
+    let broken_links_route = {
+        let root_dir = root_dir.clone();
+        warp::path!("api" / "links" / "broken")
+            .and(warp::get())
+            .map(move || match crate::links::find_broken_links(&root_dir, publish) {
+                Ok(broken) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"broken": broken})),
+                    warp::http::StatusCode::OK,
+                ),
+                Err(_) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"error": "Failed to scan directory"})),
+                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                ),
+            })
+    };
+
+    // Pinned files, kept in .fmemo/state.json; files deleted since they were pinned are left out
+    let pins_route = {
+        let root_dir = root_dir.clone();
+        warp::path!("api" / "pins")
+            .and(warp::get())
+            .map(move || match crate::state::load_state(&root_dir) {
+                Ok(state) => {
+                    let pins: Vec<String> = state
+                        .pins
+                        .into_iter()
+                        .filter(|pin| resolve_memo_path(&root_dir, pin).is_some_and(|path| path.is_file()))
+                        .collect();
+                    warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"pins": pins})),
+                        warp::http::StatusCode::OK,
+                    )
+                }
+                Err(e) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                    io_error_status(&e),
+                ),
+            })
+    };
+
+    // Pin ({"path": ...}) or unpin ({"path": ..., "pinned": false}) a file
+    let pin_route = {
+        let root_dir = root_dir.clone();
+        warp::path!("api" / "pins")
+            .and(warp::post())
+            .and(warp::body::json())
+            .map(move |request: PinRequest| {
+                let path = request.path.trim_start_matches('/');
+                let pinned = request.pinned.unwrap_or(true);
+                let result = match resolve_memo_path(&root_dir, path) {
+                    None => Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        INVALID_MEMO_PATH,
+                    )),
+                    Some(file_path) if pinned && !file_path.is_file() => Err(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        "File not found",
+                    )),
+                    Some(_) => crate::state::set_pinned(&root_dir, path, pinned),
+                };
+                match result {
+                    Ok(pins) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"pins": pins})),
+                        warp::http::StatusCode::OK,
+                    ),
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                        io_error_status(&e),
+                    ),
+                }
+            })
+    };
+
+    // Due dates as an iCalendar feed for calendar apps to subscribe to
+    let calendar_route = {
+        let root_dir = root_dir.clone();
+        warp::path!("calendar.ics")
+            .and(warp::get())
+            .map(move || {
+                use warp::Reply;
+                match crate::calendar::due_items(&root_dir, publish) {
+                    Ok(items) => warp::reply::with_header(
+                        crate::calendar::to_ics(&items, chrono::Utc::now().fixed_offset()),
+                        "content-type",
+                        "text/calendar; charset=utf-8",
+                    )
+                    .into_response(),
+                    Err(_) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": "Failed to scan directory"})),
+                        warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                    )
+                    .into_response(),
+                }
+            })
+    };
+
+    // Memos and files by day for a month view: GET /api/calendar?from=YYYY-MM-DD&to=YYYY-MM-DD,
+    // the current month by default
+    let calendar_days_route = {
+        let root_dir = root_dir.clone();
+        warp::path!("api" / "calendar")
+            .and(warp::get())
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .map(move |query: std::collections::HashMap<String, String>| {
+                use chrono::Datelike;
+                let today = chrono::Local::now().date_naive();
+                let first = today.with_day(1).unwrap_or(today);
+                let last = first.checked_add_months(chrono::Months::new(1)).and_then(|next| next.pred_opt()).unwrap_or(today);
+                let date = |name: &str, default: chrono::NaiveDate| match query.get(name) {
+                    None => Ok(default),
+                    Some(value) => chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+                        std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} must be a date like 2024-05-01", name))
+                    }),
+                };
+                let result = date("from", first).and_then(|from| Ok((from, date("to", last)?))).and_then(|(from, to)| {
+                    if from > to {
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "from must not be after to"));
+                    }
+                    let days = crate::calendar::calendar_days(&root_dir, from, to, publish)?;
+                    Ok(serde_json::json!({
+                        "from": from.format("%Y-%m-%d").to_string(),
+                        "to": to.format("%Y-%m-%d").to_string(),
+                        "days": days
+                    }))
+                });
+                match result {
+                    Ok(body) => warp::reply::with_status(warp::reply::json(&body), warp::http::StatusCode::OK),
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                        io_error_status(&e),
+                    ),
+                }
+            })
+    };
+
+    // Journal entries by date, oldest first: GET /api/journal?from=YYYY-MM-DD&to=YYYY-MM-DD&q=<query>
+    let journal_route = {
+        let root_dir = root_dir.clone();
+        warp::path!("api" / "journal")
+            .and(warp::get())
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .map(move |query: std::collections::HashMap<String, String>| {
+                let result = crate::config::load_config(&root_dir).and_then(|config| {
+                    let date = |name: &str| query.get(name).map(|value| crate::journal::parse_day(&config.journal, value)).transpose();
+                    let (from, to) = (date("from")?, date("to")?);
+                    if let (Some(from), Some(to)) = (from, to) && from > to {
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "from must not be after to"));
+                    }
+                    crate::journal::search(&root_dir, &config.journal, from, to, query.get("q").map(String::as_str), publish)
+                });
+                match result {
+                    Ok(entries) => warp::reply::with_status(warp::reply::json(&serde_json::json!({"entries": entries})), warp::http::StatusCode::OK),
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                        io_error_status(&e),
+                    ),
+                }
+            })
+    };
+
+    // A day of the journal with its neighbours: GET /api/journal/{YYYY-MM-DD|today|yesterday|tomorrow},
+    // and the closest day with an entry: GET /api/journal/{day}/previous, GET /api/journal/{day}/next
+    let journal_day_route = {
+        let root_dir = root_dir.clone();
+        warp::path!("api" / "journal" / String)
+            .map(|day: String| (day, None))
+            .or(warp::path!("api" / "journal" / String / String).and_then(|day: String, step: String| async move {
+                match step.as_str() {
+                    "previous" => Ok((day, Some(false))),
+                    "next" => Ok((day, Some(true))),
+                    _ => Err(warp::reject::not_found()),
+                }
+            }))
+            .unify()
+            .untuple_one()
+            .and(warp::get())
+            .map(move |day: String, later: Option<bool>| {
+                let result = crate::config::load_config(&root_dir).and_then(|config| {
+                    let date = crate::journal::parse_day(&config.journal, &day)?;
+                    match later {
+                        Some(later) => crate::journal::adjacent_day(&root_dir, &config.journal, date, later),
+                        None => crate::journal::day(&root_dir, &config.journal, date),
+                    }
+                });
+                match result {
+                    Ok(day) => warp::reply::with_status(warp::reply::json(&day), warp::http::StatusCode::OK),
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                        io_error_status(&e),
+                    ),
+                }
+            })
+    };
+
+    // Create a day's entry from the journal template: POST /api/journal/{day}.
+    // 201 with the new entry, or 200 when it already exists
+    let journal_create_route = {
+        let root_dir = root_dir.clone();
+        warp::path!("api" / "journal" / String)
+            .and(warp::post())
+            .and(actor.clone())
+            .map(move |day: String, actor: Option<String>| {
+                let result = crate::config::load_config(&root_dir).and_then(|config| {
+                    let date = crate::journal::parse_day(&config.journal, &day)?;
+                    let created = crate::journal::create_entry(&root_dir, &config, date)?;
+                    Ok((crate::journal::day(&root_dir, &config.journal, date)?, created))
+                });
+                match result {
+                    Ok((day, Some(rendered))) => {
+                        crate::audit::log(
+                            &root_dir,
+                            crate::audit::AuditEntry::new(actor, crate::audit::AuditAction::Create, &rendered.path)
+                                .diff("", &rendered.content),
+                        );
+                        warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"day": day, "cursor": rendered.cursor})),
+                            warp::http::StatusCode::CREATED,
+                        )
+                    }
+                    Ok((day, None)) => warp::reply::with_status(warp::reply::json(&serde_json::json!({"day": day})), warp::http::StatusCode::OK),
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                        io_error_status(&e),
+                    ),
+                }
+            })
+    };
+
+    // Add CORS headers for API routes
+    let cors = warp::cors()
+        .allow_any_origin()
+        .allow_headers(vec!["content-type", "authorization"])
+        .allow_methods(vec!["GET", "POST", "PUT", "DELETE"]);
+
+    root_route
+        .or(list_files_route)
+        .or(files_route)
+        .or(history_route)
+        .or(toc_route)
+        .or(print_route)
+        .or(document_export_route)
+        .or(text_route)
+        .or(ws_clients_route)
+        .or(health_route)
+        .or(spelling_route)
+        .or(memo_markdown_route)
+        .or(comments_route)
+        .or(restore_route)
+        .or(summarize_route)
+        .or(file_route)
+        .or(write_route)
+        .or(render_template_route)
+        .or(templates_route)
+        .or(from_template_route)
+        .or(merge_route)
+        .or(split_route)
+        .or(assets_route)
+        .or(diagram_route)
+        .or(calendar_route)
+        .or(calendar_days_route)
+        .or(journal_route)
+        .or(journal_day_route)
+        .or(journal_create_route)
+        .or(graph_route)
+        .or(broken_links_route)
+        .or(pins_route)
+        .or(pin_route)
+        .or(delete_route)
+        .or(trash_route)
+        .or(trash_restore_route)
+        .or(import_route)
+        .or(audit_route)
+        .or(archive_route)
+        .or(popular_route)
+        .or(get_draft_route)
+        .or(put_draft_route)
+        .or(delete_draft_route)
+        .with(cors)
+}
+
+/// Files `GET /api/popular` returns without `limit`
+const MAX_POPULAR_FILES: usize = 20;
+
+/// Most files one page of `GET /api/tags/{tag}/files` returns
+const MAX_TAG_FILES_PAGE: usize = 500;
+
+/// `GET /sitemap.xml` for a published site: every memo file's HTML page
+/// (`/api/files/{path}?format=html` below `base_path`) with its modification time
+pub fn create_sitemap_route(
+    root_dir: PathBuf,
+    base_path: String,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("sitemap.xml")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("host"))
+        .and(warp::header::optional::<String>("x-forwarded-proto"))
+        .map(move |host: Option<String>, proto: Option<String>| {
+            use warp::Reply;
+            let result = crate::config::load_config(&root_dir)
+                .and_then(|config| Ok((config, crate::sitemap::pages(&root_dir)?)));
+            match result {
+                Ok((config, pages)) => {
+                    let site = match config.publish.url {
+                        Some(url) => url.trim_end_matches('/').to_string(),
+                        None => format!(
+                            "{}://{}{}",
+                            proto.as_deref().unwrap_or("http"),
+                            host.as_deref().unwrap_or("localhost"),
+                            base_path
+                        ),
+                    };
+                    let xml = crate::sitemap::to_xml(&pages, |path| {
+                        format!("{}/api/files/{}?format=html", site, crate::sitemap::encode_path(path))
+                    });
+                    warp::reply::with_header(xml, "content-type", "application/xml; charset=utf-8").into_response()
+                }
+                Err(e) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                    io_error_status(&e),
+                )
+                .into_response(),
+            }
+        })
+}
+
+/// `GET /api/tags/{tag}/files?offset=&limit=`: files using a tag, from the tag index. With
+/// `cursor` (from a previous page's `next_cursor`) the page starts after that file instead of
+/// at `offset`.
+pub fn create_tag_routes(
+    index: crate::tags::TagIndex,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("api" / "tags" / String / "files")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .map(move |tag: String, query: std::collections::HashMap<String, String>| {
+            let tag = percent_encoding::percent_decode_str(&tag).decode_utf8_lossy().to_string();
+            let number = |key: &str, default: usize| match query.get(key) {
+                Some(value) => value.parse::<usize>().ok(),
+                None => Some(default),
+            };
+            let (Some(offset), Some(limit)) = (number("offset", 0), number("limit", 50)) else {
+                return warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"error": "offset and limit must be non-negative integers"})),
+                    warp::http::StatusCode::BAD_REQUEST,
+                );
+            };
+            let limit = limit.min(MAX_TAG_FILES_PAGE);
+            let after = match query.get("cursor").filter(|cursor| !cursor.is_empty()) {
+                Some(cursor) => match crate::page::decode_cursor(cursor) {
+                    Ok(after) => Some(after),
+                    Err(e) => {
+                        return warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                            warp::http::StatusCode::BAD_REQUEST,
+                        );
+                    }
+                },
+                None => None,
+            };
+            let files = index.files(&tag);
+            let remaining: Vec<&String> = match &after {
+                Some(after) => files.iter().filter(|file| *file > after).collect(),
+                None => files.iter().skip(offset).collect(),
+            };
+            let page = &remaining[..limit.min(remaining.len())];
+            let next_cursor = (remaining.len() > limit)
+                .then(|| page.last().map(|file| crate::page::encode_cursor(file)))
+                .flatten();
+            warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({
+                    "tag": tag,
+                    "total": files.len(),
+                    "offset": offset,
+                    "limit": limit,
+                    "files": page,
+                    "next_cursor": next_cursor
+                })),
+                warp::http::StatusCode::OK,
+            )
+        })
+        .with(
+            warp::cors()
+                .allow_any_origin()
+                .allow_headers(vec!["content-type", "authorization"])
+                .allow_methods(vec!["GET"]),
+        )
+}
+
+/// Logging in: `POST /api/login` for local accounts, `GET /api/auth/oidc/login` and its
+/// callback for an OpenID Connect provider, `POST /api/logout` and `GET /api/me`; also
+/// `POST /api/tokens`, `GET /api/tokens` and `DELETE /api/tokens/{id}` for API tokens
+pub fn create_user_routes(
+    users: crate::users::Users,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    use warp::Reply;
+
+    let login_route = {
+        let users = users.clone();
+        warp::path!("api" / "login")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and_then(move |request: LoginRequest| {
+                let users = users.clone();
+                async move {
+                    // Checking the password takes a while on purpose
+                    let result = tokio::task::spawn_blocking(move || users.login(&request.username, &request.password))
+                        .await
+                        .unwrap_or_else(|e| Err(std::io::Error::other(e.to_string())));
+                    Ok::<_, warp::Rejection>(match result {
+                        Ok((id, session)) => warp::reply::with_header(
+                            warp::reply::json(&session),
+                            "set-cookie",
+                            crate::users::session_cookie(&id),
+                        )
+                        .into_response(),
+                        Err(e) => warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                            if e.kind() == std::io::ErrorKind::PermissionDenied {
+                                warp::http::StatusCode::UNAUTHORIZED
+                            } else {
+                                io_error_status(&e)
+                            },
+                        )
+                        .into_response(),
+                    })
+                }
+            })
+    };
+
+    let logout_route = {
+        let users = users.clone();
+        warp::path!("api" / "logout")
+            .and(warp::post())
+            .and(warp::header::optional::<String>("cookie"))
+            .map(move |cookie: Option<String>| {
+                let logged_out = cookie
+                    .as_deref()
+                    .and_then(crate::users::session_id)
+                    .is_some_and(|id| users.logout(id));
+                warp::reply::with_header(
+                    warp::reply::json(&serde_json::json!({"logged_out": logged_out})),
+                    "set-cookie",
+                    crate::users::session_cookie(""),
+                )
+            })
+    };
+
+    let oidc_error = |e: std::io::Error| {
+        let status = match e.kind() {
+            std::io::ErrorKind::PermissionDenied => warp::http::StatusCode::FORBIDDEN,
+            std::io::ErrorKind::InvalidInput => warp::http::StatusCode::BAD_REQUEST,
+            // The provider failed or answered with something unusable
+            _ => warp::http::StatusCode::BAD_GATEWAY,
+        };
+        warp::reply::with_status(warp::reply::json(&serde_json::json!({"error": e.to_string()})), status)
+            .into_response()
+    };
+    let oidc_not_configured = || {
+        warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({"error": "OIDC login needs [oidc] in .fmemo/config.toml"})),
+            warp::http::StatusCode::NOT_IMPLEMENTED,
+        )
+        .into_response()
+    };
+    let redirect = |location: String| {
+        warp::reply::with_header(
+            warp::reply::with_status(warp::reply(), warp::http::StatusCode::FOUND),
+            "location",
+            location,
+        )
+    };
+
+    // Off to the provider
+    let oidc_login_route = {
+        let users = users.clone();
+        warp::path!("api" / "auth" / "oidc" / "login")
+            .and(warp::get())
+            .and_then(move || {
+                let oidc = users.oidc_provider().cloned();
+                async move {
+                    let Some(oidc) = oidc else {
+                        return Ok::<_, warp::Rejection>(oidc_not_configured());
+                    };
+                    // Discovery blocks the first time
+                    let result = tokio::task::spawn_blocking(move || oidc.begin())
+                        .await
+                        .unwrap_or_else(|e| Err(std::io::Error::other(e.to_string())));
+                    Ok(match result {
+                        Ok(login) => warp::reply::with_header(
+                            redirect(login.url),
+                            "set-cookie",
+                            crate::oidc::state_cookie(&login.state),
+                        )
+                        .into_response(),
+                        Err(e) => oidc_error(e),
+                    })
+                }
+            })
+    };
+
+    // Back from the provider with a code, or an error
+    let oidc_callback_route = {
+        let users = users.clone();
+        warp::path!("api" / "auth" / "oidc" / "callback")
+            .and(warp::get())
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .and(warp::header::optional::<String>("cookie"))
+            .and_then(move |query: std::collections::HashMap<String, String>, cookie: Option<String>| {
+                let users = users.clone();
+                async move {
+                    let Some(oidc) = users.oidc_provider().cloned() else {
+                        return Ok::<_, warp::Rejection>(oidc_not_configured());
+                    };
+                    if let Some(error) = query.get("error") {
+                        return Ok(oidc_error(std::io::Error::new(
+                            std::io::ErrorKind::PermissionDenied,
+                            format!("The provider refused the login: {}", error),
+                        )));
+                    }
+                    let (Some(code), Some(state)) = (query.get("code").cloned(), query.get("state").cloned()) else {
+                        return Ok(oidc_error(std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            "code and state are required",
+                        )));
+                    };
+                    let home = crate::oidc::home_url(oidc.config());
+                    let browser_state = cookie.as_deref().and_then(crate::oidc::state_from_cookie).map(str::to_string);
+                    let result = tokio::task::spawn_blocking(move || {
+                        let (user, permission) = oidc.finish(&code, &state, browser_state.as_deref())?;
+                        users.start_session(&user, permission)
+                    })
+                    .await
+                    .unwrap_or_else(|e| Err(std::io::Error::other(e.to_string())));
+                    let mut response = match result {
+                        Ok((id, _)) => {
+                            warp::reply::with_header(redirect(home), "set-cookie", crate::users::session_cookie(&id))
+                                .into_response()
+                        }
+                        Err(e) => oidc_error(e),
+                    };
+                    // The state is used up either way
+                    if let Ok(cleared) = warp::http::HeaderValue::from_str(&crate::oidc::state_cookie("")) {
+                        response.headers_mut().append("set-cookie", cleared);
+                    }
+                    Ok(response)
+                }
+            })
+    };
+
+    // API tokens; only admins get here (see `require_auth`)
+    let create_token_route = {
+        let users = users.clone();
+        warp::path!("api" / "tokens")
+            .and(warp::post())
+            .and(warp::body::json())
+            .map(move |request: TokenRequest| match users.api_tokens().create(&request.name, request.scope) {
+                Ok((value, token)) => {
+                    let mut body = serde_json::to_value(&token).unwrap_or_default();
+                    body["token"] = serde_json::Value::String(value);
+                    warp::reply::with_status(warp::reply::json(&body), warp::http::StatusCode::CREATED)
+                }
+                Err(e) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                    io_error_status(&e),
+                ),
+            })
+    };
+
+    let list_tokens_route = {
+        let users = users.clone();
+        warp::path!("api" / "tokens")
+            .and(warp::get())
+            .map(move || warp::reply::json(&users.api_tokens().list()))
+    };
+
+    let revoke_token_route = {
+        let users = users.clone();
+        warp::path!("api" / "tokens" / String)
+            .and(warp::delete())
+            .map(move |id: String| match users.api_tokens().revoke(&id) {
+                Ok(token) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"revoked": token})),
+                    warp::http::StatusCode::OK,
+                ),
+                Err(e) => warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                    io_error_status(&e),
+                ),
+            })
+    };
+
+    // Who the session belongs to; `user` is null with a bearer token, whose scope is given
+    let me_route = warp::path!("api" / "me")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("cookie"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .map(move |cookie: Option<String>, authorization: Option<String>, query: String| {
+            let session = cookie
+                .as_deref()
+                .and_then(crate::users::session_id)
+                .and_then(|id| users.session(id));
+            let scope = authorization
+                .as_deref()
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .or_else(|| query.split('&').find_map(|pair| pair.strip_prefix("token=")))
+                .and_then(|token| users.api_tokens().scope(token));
+            match (session, scope) {
+                (Some(session), _) => warp::reply::json(&session),
+                (None, Some(scope)) => warp::reply::json(&serde_json::json!({"user": null, "permission": scope})),
+                // The `--token` one, or no credentials needed
+                (None, None) => warp::reply::json(&serde_json::json!({"user": null, "permission": "admin"})),
+            }
+        });
+
+    login_route
+        .or(logout_route)
+        .or(oidc_login_route)
+        .or(oidc_callback_route)
+        .or(create_token_route)
+        .or(list_tokens_route)
+        .or(revoke_token_route)
+        .or(me_route)
+        .with(
+        warp::cors()
+            .allow_any_origin()
+            .allow_headers(vec!["content-type", "authorization"])
+            .allow_methods(vec!["GET", "POST", "DELETE"]),
+    )
+}
+
+/// Hits `GET /api/search?mode=semantic` returns without `limit`
+const MAX_SEMANTIC_HITS: usize = 20;
+/// Files `GET /api/files/{path}/related` suggests without `limit`
+const MAX_RELATED_FILES: usize = 10;
+
+/// Routes answered from the index: `GET /api/index` (status), `POST /api/reindex`,
+/// `GET /api/search?q=`, `GET /api/files/{path}/related`, `GET /api/board`, `GET /api/duplicates` and `GET /api/graph`. Mounted
+/// before the API routes, the graph comes from the index instead of a scan per request.
+pub fn create_index_routes(
+    indexer: crate::indexer::Indexer,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let status_route = {
+        let indexer = indexer.clone();
+        warp::path!("api" / "index")
+            .and(warp::get())
+            .map(move || warp::reply::json(&indexer.status()))
+    };
+
+    // Starts a pass in the background; progress arrives over the WebSocket
+    let reindex_route = {
+        let indexer = indexer.clone();
+        warp::path!("api" / "reindex")
+            .and(warp::post())
+            .map(move || {
+                if indexer.spawn_reindex() {
+                    warp::reply::with_status(warp::reply::json(&indexer.status()), warp::http::StatusCode::ACCEPTED)
+                } else {
+                    warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": "Indexing is already running"})),
+                        warp::http::StatusCode::CONFLICT,
+                    )
+                }
+            })
+    };
+
+    // Keyword search, or with ?mode=semantic memos ranked by embedding similarity
+    let search_route = {
+        let indexer = indexer.clone();
+        warp::path!("api" / "search")
+            .and(warp::get())
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .and_then(move |query: std::collections::HashMap<String, String>| {
+                let indexer = indexer.clone();
+                async move {
+                    let q = query.get("q").cloned().unwrap_or_default();
+                    let archive = hidden_archive_dir(indexer.root(), &query);
+                    let error = |message: String, status| {
+                        Ok::<_, warp::Rejection>(warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"error": message})),
+                            status,
+                        ))
+                    };
+                    match query.get("mode").map(String::as_str) {
+                        None | Some("keyword") => {}
+                        Some("semantic") => {
+                            let Some(embeddings) = indexer.embedding_index().cloned() else {
+                                return error(
+                                    "Semantic search needs [embeddings] in .fmemo/config.toml".to_string(),
+                                    warp::http::StatusCode::NOT_IMPLEMENTED,
+                                );
+                            };
+                            let limit = match query.get("limit").map(|limit| limit.parse::<usize>()) {
+                                None => MAX_SEMANTIC_HITS,
+                                Some(Ok(limit)) if limit > 0 => limit.min(crate::page::MAX_LIMIT),
+                                Some(_) => {
+                                    return error(
+                                        "limit must be a positive integer".to_string(),
+                                        warp::http::StatusCode::BAD_REQUEST,
+                                    );
+                                }
+                            };
+                            // The query is embedded with a blocking client
+                            let query = q.clone();
+                            let hits = tokio::task::spawn_blocking(move || embeddings.search(&query, limit))
+                                .await
+                                .unwrap_or_else(|e| Err(std::io::Error::other(e.to_string())))
+                                .map(|mut hits| {
+                                    if let Some(dir) = &archive {
+                                        hits.retain(|hit| !crate::archive::is_archived(dir, &hit.file));
+                                    }
+                                    hits
+                                });
+                            return match hits {
+                                Ok(hits) => Ok(warp::reply::with_status(
+                                    warp::reply::json(&serde_json::json!({"query": q, "mode": "semantic", "hits": hits})),
+                                    warp::http::StatusCode::OK,
+                                )),
+                                Err(e) => error(e.to_string(), warp::http::StatusCode::BAD_GATEWAY),
+                            };
+                        }
+                        Some(mode) => {
+                            return error(format!("Unknown search mode '{}'", mode), warp::http::StatusCode::BAD_REQUEST);
+                        }
+                    }
+
+                    let mut hits = crate::search::search_documents(&indexer.documents(), &q);
+                    if let Some(dir) = &archive {
+                        hits.retain(|hit| !crate::archive::is_archived(dir, &hit.file));
+                    }
+                    let body = match crate::page::PageRequest::from_query(&query) {
+                        Ok(Some(page)) => {
+                            // Hits come by file, then line
+                            let (hits, next_cursor) =
+                                page.apply(hits, |hit| format!("{}\0{:010}", hit.file, hit.line));
+                            serde_json::json!({"query": q, "hits": hits, "next_cursor": next_cursor})
+                        }
+                        Ok(None) => serde_json::json!({"query": q, "hits": hits}),
+                        Err(e) => return error(e.to_string(), warp::http::StatusCode::BAD_REQUEST),
+                    };
+                    Ok(warp::reply::with_status(warp::reply::json(&body), warp::http::StatusCode::OK))
+                }
+            })
+    };
+
+    // "See also" for a file: /api/files/{path}/related?limit=
+    let related_route = {
+        let indexer = indexer.clone();
+        warp::path("api")
+            .and(warp::path("files"))
+            .and(warp::path::tail())
+            .and(warp::get())
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .and_then(move |tail: warp::path::Tail, query: std::collections::HashMap<String, String>| {
+                let indexer = indexer.clone();
+                async move {
+                    let tail = percent_encoding::percent_decode_str(tail.as_str()).decode_utf8_lossy();
+                    let filename = match split_file_action(&tail) {
+                        Some((filename, "related")) => filename.to_string(),
+                        _ => return Err(warp::reject::not_found()),
+                    };
+                    let limit = match query.get("limit").map(|limit| limit.parse::<usize>()) {
+                        None => MAX_RELATED_FILES,
+                        Some(Ok(limit)) if limit > 0 => limit.min(crate::page::MAX_LIMIT),
+                        Some(_) => {
+                            return Ok(warp::reply::with_status(
+                                warp::reply::json(&serde_json::json!({"error": "limit must be a positive integer"})),
+                                warp::http::StatusCode::BAD_REQUEST,
+                            ));
+                        }
+                    };
+                    let documents = indexer.documents();
+                    Ok(match crate::related::related_files(&documents, indexer.embedding_index(), &filename, limit) {
+                        Some(related) => warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"path": filename, "related": related})),
+                            warp::http::StatusCode::OK,
+                        ),
+                        None => warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"error": "File not found"})),
+                            warp::http::StatusCode::NOT_FOUND,
+                        ),
+                    })
+                }
+            })
+    };
+
+    // Encrypted memos: POST /api/unlock and /api/lock, GET /api/vault. Either way the index is
+    // rebuilt, so it holds the decrypted memos exactly while they're unlocked.
+    let unlock_route = {
+        let indexer = indexer.clone();
+        warp::path!("api" / "unlock")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and_then(move |request: UnlockRequest| {
+                let indexer = indexer.clone();
+                async move {
+                    let root = indexer.root().to_path_buf();
+                    // Deriving the key takes a while on purpose
+                    let result = tokio::task::spawn_blocking(move || {
+                        crate::crypt::unlock(&root, request.passphrase.as_bytes())
+                    })
+                    .await
+                    .unwrap_or_else(|e| Err(std::io::Error::other(e.to_string())));
+                    Ok::<_, warp::Rejection>(match result {
+                        Ok(()) => {
+                            indexer.spawn_reindex();
+                            warp::reply::with_status(
+                                warp::reply::json(&serde_json::json!({"unlocked": true})),
+                                warp::http::StatusCode::OK,
+                            )
+                        }
+                        Err(e) => warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({"error": e.to_string()})),
+                            io_error_status(&e),
+                        ),
+                    })
+                }
+            })
+    };
+    let lock_route = {
+        let indexer = indexer.clone();
+        warp::path!("api" / "lock")
+            .and(warp::post())
+            .map(move || {
+                let was_unlocked = crate::crypt::lock(indexer.root());
+                if was_unlocked {
+                    indexer.spawn_reindex();
+                }
+                warp::reply::json(&serde_json::json!({"unlocked": false, "was_unlocked": was_unlocked}))
+            })
+    };
+    let vault_route = {
+        let indexer = indexer.clone();
+        warp::path!("api" / "vault")
+            .and(warp::get())
+            .map(move || {
+                let root = indexer.root();
+                warp::reply::json(&serde_json::json!({
+                    "initialized": crate::crypt::vault_path(root).is_file(),
+                    "unlocked": crate::crypt::is_unlocked(root)
+                }))
+            })
+    };
+
+    // Memos with a `<status>` or a checkbox as kanban columns: GET /api/board?columns=todo,doing,done
+    let board_route = {
+        let indexer = indexer.clone();
+        warp::path!("api" / "board")
+            .and(warp::get())
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .map(move |query: std::collections::HashMap<String, String>| {
+                let columns: Vec<String> = match query.get("columns") {
+                    Some(columns) => columns.split(',').filter(|status| !status.trim().is_empty()).map(String::from).collect(),
+                    None => crate::board::DEFAULT_COLUMNS.map(String::from).to_vec(),
+                };
+                warp::reply::json(&serde_json::json!({
+                    "columns": crate::board::board(&indexer.documents(), &columns)
+                }))
+            })
+    };
+
+    // Identical and near-identical files and memos: GET /api/duplicates?threshold=0.9
+    let duplicates_route = {
+        let indexer = indexer.clone();
+        warp::path!("api" / "duplicates")
+            .and(warp::get())
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .and_then(move |query: std::collections::HashMap<String, String>| {
+                let indexer = indexer.clone();
+                async move {
+                    let threshold = match query.get("threshold").map(|threshold| threshold.parse::<f32>()) {
+                        None => crate::duplicates::DEFAULT_THRESHOLD,
+                        Some(Ok(threshold)) if threshold > 0.0 && threshold <= 1.0 => threshold,
+                        Some(_) => {
+                            return Ok::<_, warp::Rejection>(warp::reply::with_status(
+                                warp::reply::json(&serde_json::json!({"error": "threshold must be a number above 0 and at most 1"})),
+                                warp::http::StatusCode::BAD_REQUEST,
+                            ));
+                        }
+                    };
+                    let mut documents = indexer.documents();
+                    if let Some(dir) = hidden_archive_dir(indexer.root(), &query)
+                        && documents.keys().any(|file| crate::archive::is_archived(&dir, file))
+                    {
+                        documents = std::sync::Arc::new(
+                            documents
+                                .iter()
+                                .filter(|(file, _)| !crate::archive::is_archived(&dir, file))
+                                .map(|(file, memos)| (file.clone(), memos.clone()))
+                                .collect(),
+                        );
+                    }
+                    // Comparing every pair of memos takes a while in a big vault
+                    let found = tokio::task::spawn_blocking(move || crate::duplicates::duplicates(&documents, threshold))
+                        .await
+                        .unwrap_or_default();
+                    Ok(warp::reply::with_status(warp::reply::json(&found), warp::http::StatusCode::OK))
+                }
+            })
+    };
+
+    let graph_route = warp::path!("api" / "graph")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .map(move |query: std::collections::HashMap<String, String>| {
+            let files_only = query.get("memos").is_some_and(|memos| memos == "false");
+            warp::reply::json(&crate::graph::graph_from_documents(&indexer.documents(), files_only))
+        });
+
+    status_route
+        .or(reindex_route)
+        .or(search_route)
+        .or(related_route)
+        .or(unlock_route)
+        .or(lock_route)
+        .or(vault_route)
+        .or(board_route)
+        .or(duplicates_route)
+        .or(graph_route)
+        .with(
+            warp::cors()
+                .allow_any_origin()
+                .allow_headers(vec!["content-type", "authorization"])
+                .allow_methods(vec!["GET", "POST"]),
+        )
+}
+
+/// Create full server routes (API + WebSocket + optionally static files)
+pub fn create_full_routes(
+    root_dir: PathBuf,
+    dist_dir: PathBuf,
+    clients: WebSocketClients,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let api_routes = create_api_routes(root_dir);
+    let ws_route = create_websocket_route(clients);
+    let static_routes = create_static_routes(dist_dir);
+    
+    api_routes.or(ws_route).or(static_routes)
+}
+
+/// Create API-only routes (API + WebSocket)
+pub fn create_api_only_routes(
+    root_dir: PathBuf,
+    clients: WebSocketClients,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let api_routes = create_api_routes(root_dir);
+    let ws_route = create_websocket_route(clients);
+    
+    api_routes.or(ws_route)
+}
+
+// Embedded static file serving (feature-gated)
+#[cfg(feature = "embed_frontend")]
+pub(crate) mod embedded {
+    use super::*;
+    use mime_guess::from_path;
+    use rust_embed::RustEmbed;
+
+    #[derive(RustEmbed)]
+    #[folder = "frontend/dist/"]
+    struct Assets;
+
+    fn respond(path: &str) -> Option<warp::reply::Response> {
+        let asset = Assets::get(path)?;
+        let body = warp::hyper::Body::from(asset.data.into_owned());
+        let mime = from_path(path).first_or_octet_stream();
+        let mut resp = warp::http::Response::new(body);
+        resp.headers_mut().insert(
+            warp::http::header::CONTENT_TYPE,
+            warp::http::HeaderValue::from_str(mime.as_ref()).unwrap_or(warp::http::HeaderValue::from_static("application/octet-stream")),
+        );
+        Some(resp)
+    }
+
+    pub fn create_embedded_static_routes(
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        create_embedded_static_routes_with_base_path(String::new())
+    }
+
+    /// `index.html` with its `<base href>`, see `index_html_with_base`
+    fn respond_index(base_path: &str) -> Option<warp::reply::Response> {
+        let asset = Assets::get("index.html")?;
+        let html = String::from_utf8_lossy(&asset.data);
+        Some(warp::Reply::into_response(warp::reply::html(index_html_with_base(&html, base_path))))
+    }
+
+    pub fn create_embedded_static_routes_with_base_path(
+        base_path: String,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        // /assets/*
+        let assets = warp::path("assets")
+            .and(warp::path::tail())
+            .and(warp::get())
+            .map(|tail: warp::path::Tail| {
+                let path = format!("assets/{}", tail.as_str());
+                respond(&path).unwrap_or_else(|| warp::reply::Response::new(warp::hyper::Body::empty()))
+            });
+
+        // Root-level files like index.html, favicon.ico, vite.svg
+        let index_base = base_path.clone();
+        let root_files = warp::get().and(warp::path::param::<String>()).and_then(move |name: String| {
+            let base_path = index_base.clone();
+            async move {
+                let resp = match name.as_str() {
+                    "index.html" => respond_index(&base_path),
+                    "favicon.ico" | "vite.svg" => respond(&name),
+                    _ => None,
+                };
+                resp.ok_or_else(warp::reject::not_found)
+            }
+        });
+
+        // SPA fallback: serve index.html for all non-API, non-WS GET routes
+        let spa = warp::get()
+            .and(warp::path::full())
+            .and_then(move |_path: warp::path::FullPath| {
+                let base_path = base_path.clone();
+                async move {
+                    if let Some(resp) = respond_index(&base_path) {
+                        Ok::<_, warp::reject::Rejection>(resp)
+                    } else {
+                        Err(warp::reject::not_found())
+                    }
+                }
+            });
+
+        assets.or(root_files).or(spa)
+    }
+}
+
+#[cfg(feature = "embed_frontend")]
+pub fn create_full_routes_embedded(
     root_dir: PathBuf,
-    dist_dir: PathBuf,
     clients: WebSocketClients,
-) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    let api_routes = create_api_routes(root_dir);
-    let ws_route = create_websocket_route(clients);
-    let static_routes = create_static_routes(dist_dir);
-    
-    api_routes.or(ws_route).or(static_routes)
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let api_routes = create_api_routes(root_dir);
+    let ws_route = create_websocket_route(clients);
+    let static_routes = embedded::create_embedded_static_routes();
+    api_routes.or(ws_route).or(static_routes)
+}
+
+/// Create WebSocket route for real-time updates
+pub fn create_websocket_route(
+    clients: WebSocketClients,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    create_websocket_route_with_options(clients, WebSocketOptions::default())
+}
+
+/// Identifies a WebSocket connection
+pub type ClientId = usize;
+
+static NEXT_CLIENT_ID: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// What WebSocket clients can do besides receiving file changes
+#[derive(Debug, Clone, Default)]
+pub struct WebSocketOptions {
+    /// Collaborative editing (`edit_join`, `edit`, `edit_leave` messages)
+    pub collab: Option<crate::collab::Collab>,
+    /// Who is viewing which file (`viewing` messages)
+    pub presence: Option<crate::presence::Presence>,
+    /// Local accounts and API tokens: read-only clients can't `edit`
+    pub users: Option<crate::users::Users>,
+}
+
+/// WebSocket route that also handles the messages clients send
+pub fn create_websocket_route_with_options(
+    clients: WebSocketClients,
+    options: WebSocketOptions,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path("ws")
+        .and(warp::ws())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::header::optional::<String>("cookie"))
+        .and(warp::header::optional::<String>("authorization"))
+        .map(move |ws: warp::ws::Ws, query: std::collections::HashMap<String, String>, cookie: Option<String>, authorization: Option<String>| {
+            let (format, compression) = match websocket_encoding(&query) {
+                Ok(encoding) => encoding,
+                Err(error) => {
+                    return Box::new(warp::reply::with_status(
+                        warp::reply::json(&serde_json::json!({"error": error})),
+                        warp::http::StatusCode::BAD_REQUEST,
+                    )) as Box<dyn warp::Reply>;
+                }
+            };
+            // The `--token` holder may write; so may sessions and API tokens with write permission
+            let read_only = options.users.as_ref().is_some_and(|users| {
+                let token = authorization
+                    .as_deref()
+                    .and_then(|value| value.strip_prefix("Bearer "))
+                    .or(query.get("token").map(String::as_str));
+                cookie
+                    .as_deref()
+                    .and_then(crate::users::session_id)
+                    .and_then(|id| users.session(id))
+                    .map(|session| session.permission)
+                    .or_else(|| token.and_then(|token| users.api_tokens().scope(token)))
+                    .is_some_and(|permission| permission < crate::config::Permission::Write)
+            });
+            let clients = Arc::clone(&clients);
+            let options = options.clone();
+            Box::new(ws.on_upgrade(move |websocket| async move {
+                handle_websocket_connection(websocket, clients, options, format, compression, read_only).await;
+            }))
+        })
+}
+
+/// `?format=...` and `?compress=...` of a WebSocket connection
+fn websocket_encoding(
+    query: &std::collections::HashMap<String, String>,
+) -> Result<(WsFormat, WsCompression), String> {
+    let format = match query.get("format") {
+        None => WsFormat::Json,
+        Some(name) => WsFormat::from_name(name)
+            .ok_or_else(|| format!("Unknown format '{}', use json, msgpack or cbor", name))?,
+    };
+    let compression = match query.get("compress") {
+        None => WsCompression::None,
+        Some(name) => WsCompression::from_name(name)
+            .ok_or_else(|| format!("Unknown compression '{}', use deflate", name))?,
+    };
+    Ok((format, compression))
+}
+
+/// Handle individual WebSocket connection
+async fn handle_websocket_connection(
+    websocket: warp::ws::WebSocket,
+    clients: WebSocketClients,
+    options: WebSocketOptions,
+    format: WsFormat,
+    compression: WsCompression,
+    read_only: bool,
+) {
+    let (mut ws_tx, mut ws_rx) = websocket.split();
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut client = WebSocketClient::new(tx.clone());
+    client.format = format;
+    client.compression = compression;
+    let client_id = client.id;
+    clients.lock().unwrap().push(client);
+    send_message(&tx, &WsServerMessage::Hello { protocol: WS_PROTOCOL_VERSION });
+    if let Some(presence) = &options.presence {
+        send_message(&tx, &presence.snapshot());
+    }
+
+    let send_task = tokio::spawn(async move {
+        while let Some(msg) = rx.recv().await {
+            // Messages sent straight to the client (replies, editing) are JSON text; broadcasts
+            // come already encoded
+            let msg = match msg.to_str() {
+                Ok(text) if format != WsFormat::Json => {
+                    match serde_json::from_str::<serde_json::Value>(text)
+                        .ok()
+                        .and_then(|value| format.encode(&value).ok())
+                    {
+                        Some(frame) => compression.compress(frame),
+                        None => msg,
+                    }
+                }
+                Ok(_) => compression.compress(msg),
+                Err(_) => msg,
+            };
+            if ws_tx.send(msg).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let recv_options = options.clone();
+    let recv_clients = clients.clone();
+    let recv_task = tokio::spawn(async move {
+        while let Some(result) = ws_rx.next().await {
+            let Ok(msg) = result else {
+                break;
+            };
+            let message = match format.decode::<WsClientMessage>(&msg) {
+                Ok(message) => message,
+                Err(e) => {
+                    // Unknown types are left alone, for clients newer than the server
+                    #[derive(serde::Deserialize)]
+                    struct Typed {
+                        r#type: String,
+                    }
+                    let known = format
+                        .decode::<Typed>(&msg)
+                        .is_ok_and(|typed| WsClientMessage::TYPES.contains(&typed.r#type.as_str()));
+                    if known {
+                        send_message(&tx, &WsServerMessage::Error { error: e.to_string() });
+                    }
+                    continue;
+                }
+            };
+            if let Some(reply) = handle_subscription(&recv_clients, client_id, &message) {
+                send_message(&tx, &reply);
+                continue;
+            }
+            if read_only && matches!(message, WsClientMessage::Edit { .. }) {
+                send_message(&tx, &WsServerMessage::Error { error: "Read-only account".to_string() });
+                continue;
+            }
+            if let Some(collab) = &recv_options.collab
+                && collab.handle_message(client_id, &tx, &message)
+            {
+                continue;
+            }
+            if let Some(presence) = &recv_options.presence
+                && let Some(update) = presence.handle_message(client_id, &message)
+            {
+                broadcast_to_clients(&recv_clients, update);
+            }
+        }
+    });
+
+    tokio::select! {
+        _ = send_task => {},
+        _ = recv_task => {},
+    }
+
+    clients.lock().unwrap().retain(|client| client.id != client_id);
+    if let Some(collab) = &options.collab {
+        collab.disconnect(client_id);
+    }
+    if let Some(presence) = &options.presence
+        && let Some(update) = presence.disconnect(client_id)
+    {
+        broadcast_to_clients(&clients, update);
+    }
+}
+
+/// `subscribe` and `unsubscribe` messages: follow only files below some directories. Returns
+/// the `subscribed` reply, or `None` for other messages.
+fn handle_subscription(
+    clients: &WebSocketClients,
+    client_id: ClientId,
+    message: &WsClientMessage,
+) -> Option<WsServerMessage> {
+    let (subscribe, dir) = match message {
+        WsClientMessage::Subscribe { dir } => (true, dir),
+        WsClientMessage::Unsubscribe { dir } => (false, dir),
+        _ => return None,
+    };
+    let dir = dir.replace('\\', "/");
+    let dir = dir.trim_matches('/');
+    let mut clients = clients.lock().unwrap();
+    let client = clients.iter_mut().find(|client| client.id == client_id)?;
+    if dir.is_empty() {
+        // The whole root: everything again
+        client.dirs.clear();
+    } else if subscribe {
+        if !client.dirs.iter().any(|d| d == dir) {
+            client.dirs.push(dir.to_string());
+        }
+    } else {
+        client.dirs.retain(|d| d != dir);
+    }
+    Some(WsServerMessage::Subscribed { dirs: client.dirs.clone() })
+}
+
+/// Send a message to one client
+fn send_message(tx: &UnboundedSender<warp::ws::Message>, message: &WsServerMessage) {
+    if let Ok(text) = serde_json::to_string(message) {
+        let _ = tx.send(warp::ws::Message::text(text));
+    }
+}
+
+/// Broadcast message to all WebSocket clients: a `WsServerMessage`, or any other JSON
+/// (e.g. from code embedding the server)
+pub fn broadcast_to_clients<M: serde::Serialize>(clients: &WebSocketClients, message: M) {
+    let clients_lock = clients.lock().unwrap();
+    let mut frames = Frames::new(&message);
+
+    clients_lock.iter().for_each(|client| {
+        if let Some(frame) = frames.get(client.format, client.compression) {
+            let _ = client.tx.send(frame);
+        }
+    });
+}
+
+/// Broadcast a message about a file (relative to the root) to the clients following it
+pub fn broadcast_file_message<M: serde::Serialize>(clients: &WebSocketClients, file: &str, message: M) {
+    let clients_lock = clients.lock().unwrap();
+    let mut frames = Frames::new(&message);
+
+    clients_lock.iter().filter(|client| client.wants(file)).for_each(|client| {
+        if let Some(frame) = frames.get(client.format, client.compression) {
+            let _ = client.tx.send(frame);
+        }
+    });
+}
+
+/// A broadcast message, encoded once for each format and compression clients use
+struct Frames<'a, M> {
+    message: &'a M,
+    encoded: Vec<((WsFormat, WsCompression), Option<warp::ws::Message>)>,
+}
+
+impl<'a, M: serde::Serialize> Frames<'a, M> {
+    fn new(message: &'a M) -> Self {
+        Self { message, encoded: Vec::new() }
+    }
+
+    /// `None` when the message can't be encoded in `format`
+    fn get(&mut self, format: WsFormat, compression: WsCompression) -> Option<warp::ws::Message> {
+        let key = (format, compression);
+        if let Some((_, frame)) = self.encoded.iter().find(|(k, _)| *k == key) {
+            return frame.clone();
+        }
+        let frame = format.encode(self.message).ok().map(|frame| compression.compress(frame));
+        self.encoded.push((key, frame.clone()));
+        frame
+    }
+}
+
+/// Start file watcher for a specific file
+pub fn start_file_watcher<P: AsRef<Path>>(
+    file_path: P,
+    clients: WebSocketClients,
+) -> std::io::Result<()> {
+    let file_path = file_path.as_ref().to_path_buf();
+    let (tx, rx) = channel();
+    let mut watcher = RecommendedWatcher::new(tx, notify::Config::default())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    
+    watcher.watch(&file_path, RecursiveMode::NonRecursive)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    // Spawn background thread to handle file changes
+    thread::spawn(move || {
+        // Keep watcher alive
+        let _watcher = watcher;
+        // Hash of the last message sent, so events that don't change the memos send nothing
+        let mut last_sent = None;
+        
+        loop {
+            match rx.recv() {
+                Ok(Ok(_event)) => {
+                    if let Ok(content) = fs::read_to_string(&file_path) {
+                        let document = parse_document(&content, &ParseOptions::default());
+                        
+                        let update_msg = WsServerMessage::FileUpdated {
+                            file_path: file_path.to_string_lossy().to_string(),
+                            path: None,
+                            memos: document.memos,
+                            warnings: document.warnings,
+                            issues: crate::lint::lint_source(&content, "", None),
+                        };
+                        let hash = content_hash(&serde_json::to_string(&update_msg).unwrap_or_default());
+                        if last_sent.replace(hash) == Some(hash) {
+                            continue;
+                        }
+                        
+                        broadcast_to_clients(&clients, update_msg);
+                    }
+                }
+                Ok(Err(e)) => {
+                    eprintln!("File watch event error: {:?}", e);
+                }
+                Err(e) => {
+                    eprintln!("File watch channel error: {:?}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Extra work the directory watcher does for each change
+#[derive(Debug, Clone, Default)]
+pub struct WatcherOptions {
+    /// Commit every changed memo file to the root's git repository
+    pub git_autocommit: bool,
+    /// Also POST every message sent to WebSocket clients to these URLs
+    pub webhooks: Vec<crate::config::WebhookConfig>,
+    /// Chat channels to post a summary of each changed memo file to
+    pub chat: Vec<crate::config::ChatConfig>,
+    /// Plugins applied when parsing changed files
+    pub plugins: Plugins,
+    /// Shell commands run for created, updated and deleted memo files
+    pub hooks: crate::config::HooksConfig,
+    /// Tag index to keep up to date
+    pub tag_index: Option<crate::tags::TagIndex>,
+    /// Memo index to keep up to date
+    pub indexer: Option<crate::indexer::Indexer>,
+    /// How long the watcher has to be quiet before changed files are read and sent
+    pub coalesce: std::time::Duration,
+    /// Where the watcher reports failing and being re-established
+    pub health: Option<crate::watcher::WatcherHealth>,
+    /// Changed files are sent without their `<private>` regions
+    pub publish: bool,
+}
+
+/// Topic of the watch on a whole root
+pub const ROOT_TOPIC: &str = "root";
+
+/// Start directory watcher for .fmemo files
+pub fn start_directory_watcher<P: AsRef<Path>>(
+    root_path: P,
+    clients: WebSocketClients,
+) -> std::io::Result<()> {
+    start_directory_watcher_with_options(root_path, clients, WatcherOptions::default())
+}
+
+/// Path of a watched memo file relative to the root; `None` for other and hidden files
+fn memo_relative_path(root_path: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root_path).ok()?.to_str()?.replace('\\', "/");
+    resolve_memo_path(root_path, &relative)?;
+    Some(relative)
+}
+
+/// Commit a changed memo file below the root, logging the outcome
+fn autocommit_change(root_path: &Path, path: &Path) {
+    let Some(relative) = memo_relative_path(root_path, path) else {
+        return;
+    };
+    match crate::git::commit_file(root_path, &relative) {
+        Ok(true) => println!("Committed change to {}", relative),
+        Ok(false) => {}
+        Err(e) => eprintln!("Failed to commit {}: {}", relative, e),
+    }
+}
+
+/// Start directory watcher for .fmemo files, with extra per-change work
+pub fn start_directory_watcher_with_options<P: AsRef<Path>>(
+    root_path: P,
+    clients: WebSocketClients,
+    options: WatcherOptions,
+) -> std::io::Result<()> {
+    let hub = crate::watcher::WatcherHub::with_health(options.health.clone().unwrap_or_default())?;
+    broadcast_watcher_status(hub.health(), clients.clone());
+    watch_root_with_options(&hub, root_path, clients, options).map(|_| ())
+}
+
+/// Tell WebSocket clients when the watcher fails and when it works again
+fn broadcast_watcher_status(health: &crate::watcher::WatcherHealth, clients: WebSocketClients) {
+    health.on_change(move |status| {
+        let message = match &status.error {
+            Some(error) if status.degraded => WsServerMessage::WatcherDegraded {
+                error: error.clone(),
+                since: status.degraded_since,
+            },
+            _ if status.restarts > 0 => WsServerMessage::WatcherRestored { restarts: status.restarts },
+            _ => return,
+        };
+        broadcast_to_clients(&clients, message);
+    });
+}
+
+/// A message about one file goes to the clients subscribed to it and the configured webhooks
+fn emit_file_message(
+    root_path: &Path,
+    clients: &WebSocketClients,
+    webhooks: Option<&crate::webhook::WebhookDispatcher>,
+    path: &Path,
+    message: WsServerMessage,
+) {
+    if let Some(webhooks) = webhooks
+        && let Some(event) = crate::webhook::file_event(path, &message)
+    {
+        webhooks.dispatch(&event);
+    }
+    let relative = path.strip_prefix(root_path).unwrap_or(path);
+    broadcast_file_message(clients, &relative.to_string_lossy().replace('\\', "/"), message);
+}
+
+/// What the watcher tells the thread sending `file_updated` messages
+enum FileChange {
+    Changed(PathBuf),
+    Removed(PathBuf),
+}
+
+fn content_hash(content: &str) -> u64 {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Send `file_updated` for changed memo files from a background thread. Changes are collected
+/// until the watcher has been quiet for `coalesce`, so the several writes an editor makes for
+/// one save are read once. A file is skipped when its content is the same as when it was last
+/// sent, or parses to the same message (only trailing spaces or line endings changed).
+fn start_update_sender(
+    root_path: PathBuf,
+    clients: WebSocketClients,
+    plugins: Plugins,
+    webhooks: Option<crate::webhook::WebhookDispatcher>,
+    coalesce: std::time::Duration,
+    publish: bool,
+) -> std::sync::mpsc::Sender<FileChange> {
+    use std::sync::mpsc::RecvTimeoutError;
+
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let mut parsers: std::collections::HashMap<PathBuf, IncrementalParser> = std::collections::HashMap::new();
+        let mut sent_hashes: std::collections::HashMap<PathBuf, u64> = std::collections::HashMap::new();
+        let mut sent_messages: std::collections::HashMap<PathBuf, u64> = std::collections::HashMap::new();
+        let mut pending = std::collections::BTreeSet::new();
+
+        loop {
+            let change = if pending.is_empty() {
+                receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                receiver.recv_timeout(coalesce)
+            };
+            match change {
+                Ok(FileChange::Changed(path)) => {
+                    pending.insert(path);
+                    continue;
+                }
+                Ok(FileChange::Removed(path)) => {
+                    pending.remove(&path);
+                    parsers.remove(&path);
+                    sent_hashes.remove(&path);
+                    sent_messages.remove(&path);
+                    continue;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            for path in std::mem::take(&mut pending) {
+                // Not for encrypted files while locked
+                let Ok(content) = crate::publish::read_memo(&path, publish) else {
+                    continue;
+                };
+                let hash = content_hash(&content);
+                if sent_hashes.insert(path.clone(), hash) == Some(hash) {
+                    continue;
+                }
+
+                let mut document = parsers
+                    .entry(path.clone())
+                    .or_insert_with(|| IncrementalParser::new(plugins.parse_options()))
+                    .parse(&content);
+                plugins.transform(&mut document.memos);
+                let relative = path.strip_prefix(&root_path).ok().map(|relative| {
+                    let parts: Vec<_> = relative.iter().map(|part| part.to_string_lossy()).collect();
+                    parts.join("/")
+                });
+                if let Some(relative) = &relative {
+                    resolve_image_paths(&mut document.memos, relative);
+                }
+                // Lint issues (links are checked against the root) for the editor to show
+                let issues = match &relative {
+                    Some(relative) => crate::lint::lint_source(&content, relative, Some(&root_path)),
+                    None => crate::lint::lint_source(&content, "", None),
+                };
+
+                let file_update_msg = WsServerMessage::FileUpdated {
+                    file_path: path.to_string_lossy().to_string(),
+                    path: Some(path.file_name().and_then(|n| n.to_str()).unwrap_or("").to_string()),
+                    memos: document.memos,
+                    warnings: document.warnings,
+                    issues,
+                };
+                let message_hash = content_hash(&serde_json::to_string(&file_update_msg).unwrap_or_default());
+                if sent_messages.insert(path.clone(), message_hash) == Some(message_hash) {
+                    continue;
+                }
+                emit_file_message(&root_path, &clients, webhooks.as_ref(), &path, file_update_msg);
+                println!("Sent file update for: {}", path.display());
+            }
+        }
+    });
+    sender
+}
+
+/// Watch a root on a hub: parse changed memo files, tell WebSocket clients and do the
+/// extra per-change work
+pub fn watch_root_with_options<P: AsRef<Path>>(
+    hub: &crate::watcher::WatcherHub,
+    root_path: P,
+    clients: WebSocketClients,
+    options: WatcherOptions,
+) -> std::io::Result<crate::watcher::WatchId> {
+    let root_path = root_path.as_ref().to_path_buf();
+    let webhooks = crate::webhook::WebhookDispatcher::start(options.webhooks.clone());
+    let updates = start_update_sender(
+        root_path.clone(),
+        clients.clone(),
+        options.plugins.clone(),
+        webhooks.clone(),
+        options.coalesce,
+        options.publish,
+    );
+    let chat = crate::chat::ChatNotifier::start(root_path.clone(), options.chat.clone());
+    let hooks = crate::hooks::HookRunner::start(root_path.clone(), options.hooks.clone());
+    let trash_dir = root_path.join(crate::trash::TRASH_DIR);
+    let watched_root = root_path.clone();
+
+    hub.watch(&watched_root, ROOT_TOPIC, move |event: &crate::watcher::WatchEvent| {
+        // These check the file on disk (chat and hooks after their own debounce), so they
+        // see every change
+        let notify_settled = |path: &Path| {
+            let Some(relative) = memo_relative_path(&root_path, path) else {
+                return;
+            };
+            if let Some(tag_index) = &options.tag_index {
+                tag_index.file_changed(&relative);
+            }
+            if let Some(indexer) = &options.indexer {
+                indexer.file_changed(&relative);
+            }
+            if let Some(chat) = &chat {
+                chat.file_changed(&relative);
+            }
+            if let Some(hooks) = &hooks {
+                hooks.file_changed(&relative);
+            }
+        };
+        let in_trash = |path: &Path| path.starts_with(&trash_dir);
+        // Every message goes to WebSocket clients and the configured webhooks
+        let emit = |message: WsServerMessage| {
+            if let Some(webhooks) = &webhooks
+                && let Ok(event) = serde_json::to_value(&message)
+            {
+                webhooks.dispatch(&event);
+            }
+            broadcast_to_clients(&clients, message);
+        };
+        let emit_file = |path: &Path, message: WsServerMessage| {
+            emit_file_message(&root_path, &clients, webhooks.as_ref(), path, message);
+        };
+
+        use std::collections::HashSet;
+        use notify::EventKind;
+
+        // Commit before the filtering below, so deletions and renames are recorded too
+        if options.git_autocommit && matches!(event.kind,
+            EventKind::Create(_) |
+            EventKind::Remove(_) |
+            EventKind::Modify(notify::event::ModifyKind::Data(_)) |
+            EventKind::Modify(notify::event::ModifyKind::Name(_))
+        ) {
+            for path in &event.paths {
+                autocommit_change(&root_path, path);
+            }
+        }
+        
+        // Deleted memo files, including those renamed away (e.g. into the trash):
+        // forget their parser state and tell clients
+        let renamed = matches!(event.kind, EventKind::Modify(notify::event::ModifyKind::Name(_)));
+        if matches!(event.kind, EventKind::Remove(_)) || renamed {
+            for path in &event.paths {
+                let ext = path.extension().and_then(|s| s.to_str());
+                if matches!(ext, Some("fmemo" | "md" | crate::crypt::EXTENSION)) && !path.exists() && !in_trash(path) {
+                    let _ = updates.send(FileChange::Removed(path.clone()));
+                    notify_settled(path);
+                    emit_file(path, WsServerMessage::FileDeleted {
+                        file_path: path.to_string_lossy().to_string(),
+                        path: path.file_name().and_then(|n| n.to_str()).unwrap_or("").to_string(),
+                    });
+                    println!("Sent file deletion for: {}", path.display());
+                }
+            }
+        }
+
+        // Only process actual file content changes, renames and deletions
+        if !matches!(event.kind, 
+            EventKind::Modify(notify::event::ModifyKind::Data(_)) | 
+            EventKind::Create(_) |
+            EventKind::Remove(_)
+        ) && !renamed {
+            return;
+        }
+        
+        let mut processed_files = HashSet::new();
+        
+        // Check if any changed file is a .fmemo or .md file
+        for path in &event.paths {
+            let ext = path.extension().and_then(|s| s.to_str());
+            if matches!(ext, Some("fmemo" | "md" | crate::crypt::EXTENSION)) && 
+               !matches!(event.kind, EventKind::Remove(_)) &&
+               path.exists() && !in_trash(path) &&
+               processed_files.insert(path.clone()) {
+                
+                notify_settled(path);
+                // Sent once the watcher is quiet (only fails once the sender thread is gone)
+                let _ = updates.send(FileChange::Changed(path.clone()));
+            }
+        }
+
+        // If structure changed (create/remove/rename), broadcast directory update
+        if matches!(event.kind,
+            EventKind::Create(_) |
+            EventKind::Remove(_) |
+            EventKind::Modify(notify::event::ModifyKind::Name(_))
+        ) {
+            if let Ok(tree) = scan_directory(&root_path) {
+                // Transform to frontend expected format
+                let listing = crate::schema::DirectoryListing {
+                    files: tree.files,
+                    directories: tree.subdirectories.iter().map(|subdir| {
+                        std::path::Path::new(&subdir.path)
+                            .file_name()
+                            .and_then(|name| name.to_str())
+                            .unwrap_or(&subdir.path)
+                            .to_string()
+                    }).collect(),
+                };
+
+                emit(WsServerMessage::DirectoryUpdated { tree: listing });
+                println!("Sent directory update for root: {}", root_path.display());
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn create_test_fmemo_file(dir: &Path, name: &str, content: &str) -> PathBuf {
+        let file_path = dir.join(format!("{}.fmemo", name));
+        fs::write(&file_path, content).unwrap();
+        file_path
+    }
+
+    #[test]
+    fn test_scan_empty_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let result = scan_directory(temp_dir.path()).unwrap();
+        
+        assert_eq!(result.files.len(), 0);
+        assert_eq!(result.subdirectories.len(), 0);
+    }
+
+    #[test]
+    fn test_scan_directory_with_fmemo_files() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        create_test_fmemo_file(temp_dir.path(), "test1", "# Test 1\nContent 1");
+        create_test_fmemo_file(temp_dir.path(), "test2", "# Test 2\nContent 2");
+        
+        // Create non-fmemo file (should be ignored)
+        fs::write(temp_dir.path().join("ignored.md"), "# Ignored").unwrap();
+        
+        let result = scan_directory(temp_dir.path()).unwrap();
+        
+        assert_eq!(result.files.len(), 2);
+        assert!(result.files.contains(&"test1.fmemo".to_string()));
+        assert!(result.files.contains(&"test2.fmemo".to_string()));
+        assert_eq!(result.subdirectories.len(), 0);
+    }
+
+    #[test]
+    fn test_scan_directory_with_subdirectories() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        // Create files in root
+        create_test_fmemo_file(temp_dir.path(), "root", "# Root");
+        
+        // Create subdirectory with fmemo files
+        let sub_dir = temp_dir.path().join("subdir");
+        fs::create_dir(&sub_dir).unwrap();
+        create_test_fmemo_file(&sub_dir, "sub1", "# Sub 1");
+        create_test_fmemo_file(&sub_dir, "sub2", "# Sub 2");
+        
+        // Create empty subdirectory (should be excluded)
+        let empty_dir = temp_dir.path().join("empty");
+        fs::create_dir(&empty_dir).unwrap();
+        
+        let result = scan_directory(temp_dir.path()).unwrap();
+        
+        assert_eq!(result.files.len(), 1);
+        assert_eq!(result.subdirectories.len(), 1);
+        
+        let subdir_tree = &result.subdirectories[0];
+        assert_eq!(subdir_tree.files.len(), 2);
+        assert!(subdir_tree.files.contains(&"sub1.fmemo".to_string()));
+        assert!(subdir_tree.files.contains(&"sub2.fmemo".to_string()));
+    }
+
+    #[test]
+    fn test_scan_directory_skips_hidden_dirs() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        // Create hidden directory with fmemo file
+        let hidden_dir = temp_dir.path().join(".hidden");
+        fs::create_dir(&hidden_dir).unwrap();
+        create_test_fmemo_file(&hidden_dir, "hidden", "# Hidden");
+        
+        // Create normal directory with fmemo file
+        let normal_dir = temp_dir.path().join("normal");
+        fs::create_dir(&normal_dir).unwrap();
+        create_test_fmemo_file(&normal_dir, "normal", "# Normal");
+        
+        let result = scan_directory(temp_dir.path()).unwrap();
+        
+        assert_eq!(result.subdirectories.len(), 1);
+        assert_eq!(result.subdirectories[0].files[0], "normal.fmemo");
+    }
+
+    #[test]
+    fn test_read_fmemo_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_content = r#"
+# Test Function
+<desc>A test function</desc>
+
+This is some content.
+
+```rust
+fn test() {
+    println!("test");
 }
+```
+
+## Sub Section
+More content here.
+"#;
+        
+        let file_path = create_test_fmemo_file(temp_dir.path(), "test", file_content);
+        
+        let result = read_fmemo_file(&file_path).unwrap();
+        
+        assert_eq!(result.memos.len(), 1);
+        assert_eq!(result.memos[0].title(), "Test Function");
+        assert_eq!(result.memos[0].description(), &Some("A test function".to_string()));
+        assert_eq!(result.memos[0].code_blocks().len(), 1);
+        assert_eq!(result.memos[0].children().len(), 1);
+        assert_eq!(result.memos[0].children()[0].title(), "Sub Section");
+        assert!(result.last_modified.is_some());
+    }
+
+    #[test]
+    fn test_read_fmemo_file_reports_warnings() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_test_fmemo_file(temp_dir.path(), "broken", "# Broken\n```rust\nfn main() {}\n");
+
+        let result = read_fmemo_file(&file_path).unwrap();
+
+        assert_eq!(result.warnings.len(), 1);
+        assert_eq!(result.warnings[0].line, 2);
+        assert_eq!(result.warnings[0].kind, crate::schema::ParseWarningKind::UnclosedCodeFence);
+    }
+
+    #[test]
+    fn test_read_fmemo_file_with_windows_line_endings() {
+        let temp_dir = TempDir::new().unwrap();
+        let unix_path = create_test_fmemo_file(temp_dir.path(), "unix", "# Title\n<desc>Desc</desc>\nBody\n");
+        let windows_path = create_test_fmemo_file(
+            temp_dir.path(),
+            "windows",
+            "\u{feff}# Title\r\n<desc>Desc</desc>\r\nBody\r\n",
+        );
+
+        let unix = read_fmemo_file(&unix_path).unwrap();
+        let windows = read_fmemo_file(&windows_path).unwrap();
+
+        assert_eq!(unix.memos, windows.memos);
+        assert_eq!(windows.memos[0].title(), "Title");
+    }
+
+    #[test]
+    fn test_read_non_fmemo_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("test.md");
+        fs::write(&file_path, "# Test").unwrap();
+        
+        let result = read_fmemo_file(&file_path);
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_read_nonexistent_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("nonexistent.fmemo");
+        
+        let result = read_fmemo_file(&file_path);
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_has_fmemo_files() {
+        // Tree with files
+        let tree_with_files = DirectoryTree {
+            path: "/test".to_string(),
+            files: vec!["test.fmemo".to_string()],
+            subdirectories: vec![],
+        };
+        assert!(has_fmemo_files(&tree_with_files));
+
+        // Tree without files but with subdirectory that has files
+        let tree_with_sub_files = DirectoryTree {
+            path: "/test".to_string(),
+            files: vec![],
+            subdirectories: vec![tree_with_files],
+        };
+        assert!(has_fmemo_files(&tree_with_sub_files));
+
+        // Tree with no files
+        let tree_empty = DirectoryTree {
+            path: "/test".to_string(),
+            files: vec![],
+            subdirectories: vec![],
+        };
+        assert!(!has_fmemo_files(&tree_empty));
+    }
+
+    #[tokio::test]
+    async fn test_api_root_endpoint() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_fmemo_file(temp_dir.path(), "test1", "# Test 1\nContent 1");
+        create_test_fmemo_file(temp_dir.path(), "test2", "# Test 2\nContent 2");
+
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/root")
+            .reply(&api)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let files = body["files"].as_array().unwrap();
+        assert_eq!(files.len(), 2);
+        
+        let file_names: Vec<&str> = files.iter()
+            .map(|f| f.as_str().unwrap())
+            .collect();
+        assert!(file_names.contains(&"test1.fmemo"));
+        assert!(file_names.contains(&"test2.fmemo"));
+    }
+
+    #[tokio::test]
+    async fn test_api_files_endpoint_success() {
+        let temp_dir = TempDir::new().unwrap();
+        let content = r#"
+# Test Function
+<desc>A test function</desc>
+
+This is content.
+
+```rust
+fn test() {}
+```
+"#;
+        create_test_fmemo_file(temp_dir.path(), "test", content);
+
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/files/test.fmemo")
+            .reply(&api)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        
+        let body: FileContent = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body.memos.len(), 1);
+        assert_eq!(body.memos[0].title(), "Test Function");
+        assert_eq!(body.memos[0].description(), &Some("A test function".to_string()));
+        assert_eq!(body.memos[0].code_blocks().len(), 1);
+        assert!(body.last_modified.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_api_files_endpoint_not_found() {
+        let temp_dir = TempDir::new().unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/files/nonexistent.fmemo")
+            .reply(&api)
+            .await;
+
+        assert_eq!(response.status(), 404);
+        
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert!(body["error"].as_str().unwrap().contains("File not found"));
+    }
+
+    #[tokio::test]
+    async fn test_api_files_endpoint_invalid_extension() {
+        let temp_dir = TempDir::new().unwrap();
+        // Create a .md file instead of .fmemo
+        fs::write(temp_dir.path().join("test.md"), "# Test").unwrap();
+        
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/files/test.md")
+            .reply(&api)
+            .await;
+
+        assert_eq!(response.status(), 404);
+        
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert!(body["error"].as_str().unwrap().contains("Invalid file type"));
+    }
+
+    #[tokio::test]
+    async fn test_api_root_endpoint_with_subdirectories() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        // Create file in root
+        create_test_fmemo_file(temp_dir.path(), "root", "# Root");
+        
+        // Create subdirectory with files
+        let sub_dir = temp_dir.path().join("subdir");
+        fs::create_dir(&sub_dir).unwrap();
+        create_test_fmemo_file(&sub_dir, "sub1", "# Sub 1");
+        
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/root")
+            .reply(&api)
+            .await;
+
+        assert_eq!(response.status(), 200);
+        
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let files = body["files"].as_array().unwrap();
+        let directories = body["directories"].as_array().unwrap();
+        
+        assert_eq!(files.len(), 1);
+        assert_eq!(directories.len(), 1);
+        assert_eq!(directories[0].as_str().unwrap(), "subdir");
+    }
+
+    #[tokio::test]
+    async fn test_api_root_flat_pages() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_fmemo_file(temp_dir.path(), "a", "# A");
+        create_test_fmemo_file(temp_dir.path(), "b", "# B");
+        let sub_dir = temp_dir.path().join("subdir");
+        fs::create_dir(&sub_dir).unwrap();
+        create_test_fmemo_file(&sub_dir, "c", "# C");
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+
+        let response = warp::test::request().path("/api/root?flat=true").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body, serde_json::json!({"files": ["a.fmemo", "b.fmemo", "subdir/c.fmemo"]}));
+
+        let mut files = Vec::new();
+        let mut path = "/api/root?flat=true&limit=2".to_string();
+        loop {
+            let response = warp::test::request().path(&path).reply(&api).await;
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            files.extend(body["files"].as_array().unwrap().iter().cloned());
+            let Some(cursor) = body["next_cursor"].as_str() else {
+                break;
+            };
+            path = format!("/api/root?flat=true&limit=2&cursor={}", cursor);
+        }
+        assert_eq!(files, ["a.fmemo", "b.fmemo", "subdir/c.fmemo"]);
+
+        let response = warp::test::request().path("/api/root?flat=true&cursor=xyz").reply(&api).await;
+        assert_eq!(response.status(), 400);
+
+        let response = warp::test::request().path("/api/root?format=yaml").reply(&api).await;
+        let yaml: serde_yaml::Value = serde_yaml::from_slice(response.body()).unwrap();
+        assert_eq!(yaml["files"].as_sequence().unwrap().len(), 2);
+        let response = warp::test::request()
+            .path("/api/root?flat=true&limit=5")
+            .header("accept", "application/toml")
+            .reply(&api)
+            .await;
+        // No next page: TOML has no null, so there's no next_cursor at all
+        assert_eq!(std::str::from_utf8(response.body()).unwrap(), "files = [\"a.fmemo\", \"b.fmemo\", \"subdir/c.fmemo\"]\n");
+    }
+
+    #[tokio::test]
+    async fn test_api_list_files() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("b.md"), "intro\n## Second\n# Later").unwrap();
+        let sub_dir = temp_dir.path().join("notes");
+        fs::create_dir(&sub_dir).unwrap();
+        fs::write(sub_dir.join("a.fmemo"), "#tag\n# Plans  ").unwrap();
+        fs::write(temp_dir.path().join("c.fmemo"), "no heading").unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+
+        let response = warp::test::request().path("/api/files").reply(&api).await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let files = body["files"].as_array().unwrap();
+        let paths: Vec<_> = files.iter().map(|f| f["path"].as_str().unwrap()).collect();
+        assert_eq!(paths, ["b.md", "c.fmemo", "notes/a.fmemo"]);
+        assert_eq!(files[0]["title"], "Second");
+        assert_eq!(files[0]["size"], 23);
+        assert!(files[0]["last_modified"].is_u64());
+        assert_eq!(files[1]["title"], serde_json::Value::Null);
+        assert_eq!(files[2]["title"], "Plans");
+
+        let response = warp::test::request().path("/api/files?limit=2").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["files"].as_array().unwrap().len(), 2);
+        assert_eq!(body["next_cursor"], crate::page::encode_cursor("c.fmemo"));
+
+        let response = warp::test::request().path("/api/files?glob=**/*.fmemo").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let paths: Vec<_> = body["files"].as_array().unwrap().iter().map(|f| f["path"].as_str().unwrap()).collect();
+        assert_eq!(paths, ["c.fmemo", "notes/a.fmemo"]);
+
+        let response = warp::test::request().path("/api/files?glob=notes/%7Ba,b%7D.*").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["files"][0]["path"], "notes/a.fmemo");
+
+        let response = warp::test::request().path("/api/files?glob=%5Bab").reply(&api).await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_api_file_content_negotiation() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.fmemo"), "# A\nSome *text*").unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+        let get = |accept: &'static str| {
+            warp::test::request()
+                .path("/api/files/a.fmemo")
+                .header("accept", accept)
+                .reply(&api)
+        };
+
+        let response = get("*/*").await;
+        assert_eq!(response.headers()["content-type"], "application/json");
+        assert_eq!(response.headers()["vary"], "accept");
+        let body: FileContent = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body.memos[0].title(), "A");
+
+        let response = get("text/markdown").await;
+        assert_eq!(response.headers()["content-type"], "text/markdown; charset=utf-8");
+        assert_eq!(response.body(), "# A\nSome *text*");
+
+        // What a browser sends when opening the URL
+        let response = get("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8").await;
+        assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/html"));
+        let html = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(html.contains("<h1 id=\"a\">A</h1><p>Some <em>text</em></p>"));
+
+        assert_eq!(get("text/*;q=0.5, application/json;q=0.4").await.headers()["content-type"], "text/markdown; charset=utf-8");
+        assert_eq!(get("image/png").await.status(), 406);
+        assert_eq!(get("application/json;q=0, */*").await.headers()["content-type"], "text/markdown; charset=utf-8");
+
+        let response = get("application/x-yaml").await;
+        assert_eq!(response.headers()["content-type"], "application/yaml; charset=utf-8");
+        let yaml: serde_yaml::Value = serde_yaml::from_slice(response.body()).unwrap();
+        assert_eq!(yaml["memos"][0]["title"], "A");
+
+        let response = warp::test::request().path("/api/files/a.fmemo?format=toml").reply(&api).await;
+        assert_eq!(response.status(), 200);
+        let toml: toml::Value = toml::from_str(std::str::from_utf8(response.body()).unwrap()).unwrap();
+        assert_eq!(toml["memos"][0]["title"].as_str(), Some("A"));
+        assert_eq!(warp::test::request().path("/api/files/a.fmemo?format=xml").reply(&api).await.status(), 406);
+    }
+
+    #[tokio::test]
+    async fn test_api_file_depth() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.fmemo"), "# A\n## B\n### C\n## D").unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+
+        let response = warp::test::request().path("/api/files/a.fmemo?depth=1").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["memos"][0]["children"], serde_json::json!([]));
+        assert_eq!(body["memos"][0]["children_count"], 2);
+        assert_eq!(body["memos"][0]["children_slugs"], serde_json::json!(["b", "d"]));
+
+        let response = warp::test::request().path("/api/files/a.fmemo").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert!(body["memos"][0].get("children_count").is_none());
+        assert_eq!(body["memos"][0]["children"][0]["children"][0]["title"], "C");
+
+        let response = warp::test::request().path("/api/files/a.fmemo?depth=0").reply(&api).await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test] 
+    async fn test_api_wrong_method() {
+        let temp_dir = TempDir::new().unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+
+        let response = warp::test::request()
+            .method("POST")  // Should be GET
+            .path("/api/root")
+            .reply(&api)
+            .await;
+
+        assert_eq!(response.status(), 405);  // Method Not Allowed
+    }
+
+    #[tokio::test]
+    async fn test_api_invalid_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/invalid")
+            .reply(&api)
+            .await;
+
+        assert_eq!(response.status(), 404);  // Not Found
+    }
+
+    #[tokio::test]
+    async fn test_file_change_websocket_integration() {
+        use std::time::Duration;
+        use tokio::time::timeout;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_test_fmemo_file(temp_dir.path(), "test", "# Original Title\nOriginal content");
+        
+        // Create mock WebSocket client
+        let (client_tx, mut client_rx) = tokio::sync::mpsc::unbounded_channel();
+        let clients: WebSocketClients = Arc::new(Mutex::new(vec![client_tx.into()]));
+        
+        // Start file watcher
+        start_file_watcher(&file_path, clients.clone()).unwrap();
+        
+        // Give the watcher a moment to start
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        
+        // Modify the file
+        fs::write(&file_path, "# Updated Title\n<desc>New description</desc>\nUpdated content\n\n```rust\nfn test() {}\n```").unwrap();
+        
+        // Wait for notification with timeout
+        let result = timeout(Duration::from_secs(2), client_rx.recv()).await;
+        
+        assert!(result.is_ok(), "Should receive WebSocket message within timeout");
+        
+        let message = result.unwrap().unwrap();
+        let text = message.to_str().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(text).unwrap();
+        
+        // Verify message structure
+        assert_eq!(parsed["type"], "file_updated");
+        assert!(parsed["file_path"].as_str().unwrap().ends_with("test.fmemo"));
+        
+        // Verify memo content
+        let memos = &parsed["memos"];
+        assert_eq!(memos.as_array().unwrap().len(), 1);
+        
+        let memo = &memos[0];
+        assert_eq!(memo["title"], "Updated Title");
+        assert_eq!(memo["description"], "New description");
+        assert_eq!(memo["code_blocks"].as_array().unwrap().len(), 1);
+        assert_eq!(memo["code_blocks"][0]["language"], "rust");
+        assert_eq!(parsed["warnings"].as_array().unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_file_update_carries_lint_issues() {
+        use std::time::Duration;
+        use tokio::time::timeout;
+
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_test_fmemo_file(temp_dir.path(), "test", "# Test");
+        let (client_tx, mut client_rx) = tokio::sync::mpsc::unbounded_channel();
+        let clients: WebSocketClients = Arc::new(Mutex::new(vec![client_tx.into()]));
+        start_file_watcher(&file_path, clients.clone()).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        fs::write(&file_path, "# Test\n```rust\nfn main() {}\n").unwrap();
+        let message = timeout(Duration::from_secs(2), client_rx.recv()).await.unwrap().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(message.to_str().unwrap()).unwrap();
+        assert_eq!(parsed["type"], "file_updated");
+        assert_eq!(parsed["warnings"][0]["kind"], "unclosed_code_fence");
+        assert_eq!(parsed["issues"][0]["rule"], "unclosed_code_fence");
+        assert_eq!(parsed["issues"][0]["line"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_directory_change_websocket_integration() {
+        use std::time::Duration;
+        use tokio::time::timeout;
+        
+        let temp_dir = TempDir::new().unwrap();
+        create_test_fmemo_file(temp_dir.path(), "existing", "# Existing File");
+        
+        // Create mock WebSocket client
+        let (client_tx, mut client_rx) = tokio::sync::mpsc::unbounded_channel();
+        let clients: WebSocketClients = Arc::new(Mutex::new(vec![client_tx.into()]));
+        
+        // Start directory watcher
+        start_directory_watcher(temp_dir.path(), clients.clone()).unwrap();
+        
+        // Give the watcher a moment to start
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        
+        // Create a new .fmemo file
+        create_test_fmemo_file(temp_dir.path(), "new_file", "# New File\nThis is new content");
+        
+        // Wait for notification with timeout
+        let result = timeout(Duration::from_secs(2), client_rx.recv()).await;
+        
+        assert!(result.is_ok(), "Should receive WebSocket message within timeout");
+        
+        let message = result.unwrap().unwrap();
+        let text = message.to_str().unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(text).unwrap();
+        
+        // Verify message structure
+        assert_eq!(parsed["type"], "directory_updated");
+        
+        // Verify directory tree content
+        let tree = &parsed["tree"];
+        assert_eq!(tree["files"].as_array().unwrap().len(), 2);
+        
+        let files = tree["files"].as_array().unwrap();
+        let file_names: Vec<&str> = files.iter()
+            .map(|f| f.as_str().unwrap())
+            .collect();
+        
+        assert!(file_names.contains(&"existing.fmemo"));
+        assert!(file_names.contains(&"new_file.fmemo"));
+    }
+
+    #[tokio::test]
+    async fn test_watcher_coalesces_saves_by_content() {
+        use std::time::Duration;
+        use tokio::time::timeout;
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = create_test_fmemo_file(temp_dir.path(), "notes", "# Notes");
+        let (client_tx, mut client_rx) = tokio::sync::mpsc::unbounded_channel();
+        let clients: WebSocketClients = Arc::new(Mutex::new(vec![client_tx.into()]));
+        let options = WatcherOptions {
+            coalesce: Duration::from_millis(300),
+            ..Default::default()
+        };
+        start_directory_watcher_with_options(temp_dir.path(), clients, options).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut next_update = async || -> Option<serde_json::Value> {
+            let message = timeout(Duration::from_secs(2), client_rx.recv()).await.ok()??;
+            serde_json::from_str(message.to_str().unwrap()).ok()
+        };
+
+        // An editor writing one save in several steps: only the result is sent, once
+        fs::write(&path, "").unwrap();
+        fs::write(&path, "# Notes\nFirst").unwrap();
+        fs::write(&path, "# Notes\nFirst draft").unwrap();
+        let update = next_update().await.unwrap();
+        assert_eq!(update["type"], "file_updated");
+        assert_eq!(update["memos"][0]["content"], "First draft");
+
+        // A real edit right after the last update is not dropped
+        fs::write(&path, "# Notes\nSecond draft").unwrap();
+        let update = next_update().await.unwrap();
+        assert_eq!(update["memos"][0]["content"], "Second draft");
+
+        // Touching the file without changing it sends nothing
+        fs::write(&path, "# Notes\nSecond draft").unwrap();
+        assert!(next_update().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_watchers_skip_unchanged_memos() {
+        use std::io::Write;
+        use std::time::Duration;
+        use tokio::time::timeout;
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = create_test_fmemo_file(temp_dir.path(), "notes", "# Notes\nBody");
+        let (dir_tx, mut dir_rx) = tokio::sync::mpsc::unbounded_channel();
+        let options = WatcherOptions {
+            coalesce: Duration::from_millis(300),
+            ..Default::default()
+        };
+        start_directory_watcher_with_options(temp_dir.path(), Arc::new(Mutex::new(vec![dir_tx.into()])), options).unwrap();
+        let (file_tx, mut file_rx) = tokio::sync::mpsc::unbounded_channel();
+        start_file_watcher(&path, Arc::new(Mutex::new(vec![file_tx.into()]))).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        async fn contents(rx: &mut tokio::sync::mpsc::UnboundedReceiver<warp::ws::Message>) -> Vec<String> {
+            let mut contents = Vec::new();
+            while let Ok(Some(message)) = timeout(Duration::from_millis(700), rx.recv()).await {
+                let update: serde_json::Value = serde_json::from_str(message.to_str().unwrap()).unwrap();
+                contents.push(update["memos"][0]["content"].as_str().unwrap_or_default().to_string());
+            }
+            contents
+        }
+
+        fs::write(&path, "# Notes\nEdited").unwrap();
+        assert_eq!(contents(&mut dir_rx).await, ["Edited"]);
+        assert_eq!(contents(&mut file_rx).await.last().map(String::as_str), Some("Edited"));
+
+        // Trailing spaces and line endings don't change the memos, so nothing is sent for them
+        fs::write(&path, "# Notes  \r\nEdited  \r\n").unwrap();
+        assert!(contents(&mut dir_rx).await.is_empty());
+        // Nor for writing the same memos again (in place, as the file watcher has no quiet period)
+        fs::write(&path, "# Notes\nEdited").unwrap();
+        let _ = contents(&mut file_rx).await;
+        fs::OpenOptions::new().write(true).open(&path).unwrap().write_all(b"# Notes\nEdited").unwrap();
+        assert!(contents(&mut file_rx).await.is_empty());
+
+        fs::write(&path, "# Notes\nEdited again").unwrap();
+        assert_eq!(contents(&mut dir_rx).await, ["Edited again"]);
+        assert_eq!(contents(&mut file_rx).await.last().map(String::as_str), Some("Edited again"));
+    }
+
+    #[tokio::test]
+    async fn test_multiple_clients_receive_updates() {
+        use std::time::Duration;
+        use tokio::time::timeout;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = create_test_fmemo_file(temp_dir.path(), "test", "# Test");
+        
+        // Create multiple mock WebSocket clients
+        let (client1_tx, mut client1_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (client2_tx, mut client2_rx) = tokio::sync::mpsc::unbounded_channel();
+        let clients: WebSocketClients = Arc::new(Mutex::new(vec![client1_tx.into(), client2_tx.into()]));
+        
+        // Start file watcher
+        start_file_watcher(&file_path, clients.clone()).unwrap();
+        
+        // Give the watcher a moment to start
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        
+        // Modify the file
+        fs::write(&file_path, "# Updated Content").unwrap();
+        
+        // Both clients should receive the message
+        let result1 = timeout(Duration::from_secs(2), client1_rx.recv()).await;
+        let result2 = timeout(Duration::from_secs(2), client2_rx.recv()).await;
+        
+        assert!(result1.is_ok(), "Client 1 should receive message");
+        assert!(result2.is_ok(), "Client 2 should receive message");
+        
+        // Both should have the same content
+        let message1 = result1.unwrap().unwrap();
+        let message2 = result2.unwrap().unwrap();
+        let msg1 = message1.to_str().unwrap();
+        let msg2 = message2.to_str().unwrap();
+        assert_eq!(msg1, msg2);
+        
+        let parsed: serde_json::Value = serde_json::from_str(msg1).unwrap();
+        assert_eq!(parsed["type"], "file_updated");
+        assert_eq!(parsed["memos"][0]["title"], "Updated Content");
+    }
+
+    #[test]
+    fn test_subscribed_clients_get_their_files() {
+        let (client1_tx, mut client1_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (client2_tx, mut client2_rx) = tokio::sync::mpsc::unbounded_channel();
+        let client1 = WebSocketClient::new(client1_tx);
+        let client1_id = client1.id;
+        let clients: WebSocketClients = Arc::new(Mutex::new(vec![client1, client2_tx.into()]));
+
+        let subscribe = WsClientMessage::Subscribe { dir: "projects/".to_string() };
+        let reply = handle_subscription(&clients, client1_id, &subscribe).unwrap();
+        assert_eq!(reply, WsServerMessage::Subscribed { dirs: vec!["projects".to_string()] });
+        let leave = WsClientMessage::EditLeave { path: "a.md".to_string() };
+        assert!(handle_subscription(&clients, client1_id, &leave).is_none());
+
+        broadcast_file_message(&clients, "projects/a.md", serde_json::json!({"n": 1}));
+        broadcast_file_message(&clients, "projects-old/b.md", serde_json::json!({"n": 2}));
+        broadcast_to_clients(&clients, serde_json::json!({"n": 3}));
+        let received = |rx: &mut tokio::sync::mpsc::UnboundedReceiver<warp::ws::Message>| {
+            let mut received = Vec::new();
+            while let Ok(message) = rx.try_recv() {
+                let message: serde_json::Value = serde_json::from_str(message.to_str().unwrap()).unwrap();
+                received.push(message["n"].as_i64().unwrap());
+            }
+            received
+        };
+        assert_eq!(received(&mut client1_rx), [1, 3]);
+        assert_eq!(received(&mut client2_rx), [1, 2, 3]);
+
+        // Unsubscribing from the last directory follows the whole root again
+        let unsubscribe = WsClientMessage::Unsubscribe { dir: "projects".to_string() };
+        let reply = handle_subscription(&clients, client1_id, &unsubscribe).unwrap();
+        assert_eq!(reply, WsServerMessage::Subscribed { dirs: Vec::new() });
+        broadcast_file_message(&clients, "projects-old/b.md", serde_json::json!({"n": 4}));
+        assert_eq!(received(&mut client1_rx), [4]);
+    }
+
+    #[tokio::test]
+    async fn test_static_routes_creation() {
+        let temp_dir = TempDir::new().unwrap();
+        
+        // Create mock dist directory structure
+        let dist_dir = temp_dir.path().join("dist");
+        fs::create_dir(&dist_dir).unwrap();
+        fs::create_dir(&dist_dir.join("assets")).unwrap();
+        fs::write(&dist_dir.join("index.html"), "<!DOCTYPE html><html><head></head><body></body></html>").unwrap();
+        fs::write(&dist_dir.join("favicon.ico"), "fake favicon").unwrap();
+        fs::write(&dist_dir.join("vite.svg"), "<svg></svg>").unwrap();
+        fs::write(&dist_dir.join("assets").join("main.js"), "console.log('test');").unwrap();
+        
+        let static_routes = create_static_routes(dist_dir.clone());
+        
+        // Test serving index.html for SPA routes
+        let response = warp::test::request()
+            .method("GET")
+            .path("/")
+            .reply(&static_routes)
+            .await;
+        
+        assert_eq!(response.status(), 200);
+        assert!(response.body().starts_with(b"<!DOCTYPE html"));
+        
+        // Test serving favicon
+        let response = warp::test::request()
+            .method("GET")
+            .path("/favicon.ico")
+            .reply(&static_routes)
+            .await;
+        
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body(), &b"fake favicon"[..]);
+        
+        // Test serving assets
+        let response = warp::test::request()
+            .method("GET")
+            .path("/assets/main.js")
+            .reply(&static_routes)
+            .await;
+        
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body(), &b"console.log('test');"[..]);
+    }
+
+    #[tokio::test]
+    async fn test_api_only_routes() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_fmemo_file(temp_dir.path(), "test", "# Test Content\nSome content");
+        
+        let clients: WebSocketClients = Arc::new(Mutex::new(Vec::new()));
+        let api_routes = create_api_only_routes(temp_dir.path().to_path_buf(), clients);
+        
+        // Test API endpoint works
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/root")
+            .reply(&api_routes)
+            .await;
+        
+        assert_eq!(response.status(), 200);
+        
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let files = body["files"].as_array().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].as_str().unwrap(), "test.fmemo");
+    }
+
+    #[tokio::test]
+    async fn test_full_routes_combination() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_fmemo_file(temp_dir.path(), "test", "# Test Content\nSome content");
+        
+        // Create mock dist directory
+        let dist_dir = temp_dir.path().join("dist");
+        fs::create_dir(&dist_dir).unwrap();
+        fs::write(&dist_dir.join("index.html"), "<!DOCTYPE html><html><head></head><body></body></html>").unwrap();
+        
+        let clients: WebSocketClients = Arc::new(Mutex::new(Vec::new()));
+        let full_routes = create_full_routes(
+            temp_dir.path().to_path_buf(),
+            dist_dir.clone(),
+            clients
+        );
+        
+        // Test API endpoint works
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/root")
+            .reply(&full_routes)
+            .await;
+        
+        assert_eq!(response.status(), 200);
+        
+        // Test static file serving works
+        let response = warp::test::request()
+            .method("GET")
+            .path("/")
+            .reply(&full_routes)
+            .await;
+        
+        assert_eq!(response.status(), 200);
+        assert!(response.body().starts_with(b"<!DOCTYPE html"));
+        
+        // Test SPA fallback for unknown routes
+        let response = warp::test::request()
+            .method("GET")
+            .path("/some/spa/route")
+            .reply(&full_routes)
+            .await;
+        
+        assert_eq!(response.status(), 200);
+        assert!(response.body().starts_with(b"<!DOCTYPE html"));
+    }
+
+    #[tokio::test]
+    async fn test_cors_headers() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_fmemo_file(temp_dir.path(), "test", "# Test Content");
+        
+        let api_routes = create_api_routes(temp_dir.path().to_path_buf());
+        
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/root")
+            .header("Origin", "http://localhost:3000")
+            .reply(&api_routes)
+            .await;
+        
+        assert_eq!(response.status(), 200);
+        
+        // Check CORS headers are present
+        let headers = response.headers();
+        assert!(headers.contains_key("access-control-allow-origin"));
+    }
+
+    #[tokio::test]
+    async fn test_api_file_endpoint_frontend_compatible() {
+        let temp_dir = TempDir::new().unwrap();
+        let content = r#"
+# Test Function
+<desc>A test function</desc>
+
+This is content.
+
+```rust
+fn test() {}
+```
+"#;
+        create_test_fmemo_file(temp_dir.path(), "test", content);
 
-/// Create API-only routes (API + WebSocket)
-pub fn create_api_only_routes(
-    root_dir: PathBuf,
-    clients: WebSocketClients,
-) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    let api_routes = create_api_routes(root_dir);
-    let ws_route = create_websocket_route(clients);
-    
-    api_routes.or(ws_route)
-}
+        let api = create_api_routes(temp_dir.path().to_path_buf());
 
-// Embedded static file serving (feature-gated)
-#[cfg(feature = "embed_frontend")]
-mod embedded {
-    use super::*;
-    use mime_guess::from_path;
-    use rust_embed::RustEmbed;
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/file/test.fmemo")
+            .reply(&api)
+            .await;
 
-    #[derive(RustEmbed)]
-    #[folder = "frontend/dist/"]
-    struct Assets;
+        assert_eq!(response.status(), 200);
+        
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["path"].as_str().unwrap(), "test.fmemo");
+        assert!(body["content"].as_str().is_some());
+        assert!(body["memos"].as_array().is_some());
+        
+        let memos = body["memos"].as_array().unwrap();
+        assert_eq!(memos.len(), 1);
+        assert_eq!(memos[0]["title"].as_str().unwrap(), "Test Function");
+    }
 
-    fn respond(path: &str) -> Option<warp::reply::Response> {
-        let asset = Assets::get(path)?;
-        let body = warp::hyper::Body::from(asset.data.into_owned());
-        let mime = from_path(path).first_or_octet_stream();
-        let mut resp = warp::http::Response::new(body);
-        resp.headers_mut().insert(
-            warp::http::header::CONTENT_TYPE,
-            warp::http::HeaderValue::from_str(mime.as_ref()).unwrap_or(warp::http::HeaderValue::from_static("application/octet-stream")),
-        );
-        Some(resp)
+    #[tokio::test]
+    async fn test_api_diagram_render_rejects_unknown_kind() {
+        let temp_dir = TempDir::new().unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/diagrams/render")
+            .json(&serde_json::json!({"kind": "plantuml", "source": "@startuml"}))
+            .reply(&api)
+            .await;
+
+        assert_eq!(response.status(), 400);
     }
 
-    pub fn create_embedded_static_routes(
-    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-        // /assets/*
-        let assets = warp::path("assets")
-            .and(warp::path::tail())
-            .and(warp::get())
-            .map(|tail: warp::path::Tail| {
-                let path = format!("assets/{}", tail.as_str());
-                respond(&path).unwrap_or_else(|| warp::reply::Response::new(warp::hyper::Body::empty()))
-            });
+    #[tokio::test]
+    async fn test_api_files_resolves_images_and_serves_assets() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_fmemo_file(temp_dir.path(), "shots", "# Shots\n![ui](img/ui.png)");
+        fs::create_dir(temp_dir.path().join("img")).unwrap();
+        fs::write(temp_dir.path().join("img").join("ui.png"), "png bytes").unwrap();
+        fs::create_dir(temp_dir.path().join(".fmemo")).unwrap();
+        fs::write(temp_dir.path().join(".fmemo").join("state.json"), "{}").unwrap();
 
-        // Root-level files like index.html, favicon.ico, vite.svg
-        let root_files = warp::get().and(warp::path::param::<String>()).and_then(|name: String| async move {
-            let known = ["index.html", "favicon.ico", "vite.svg"]; // fast path
-            if known.contains(&name.as_str()) {
-                if let Some(resp) = respond(&name) { return Ok(resp); }
-            }
-            Err(warp::reject::not_found())
-        });
+        let api = create_api_routes(temp_dir.path().to_path_buf());
 
-        // SPA fallback: serve index.html for all non-API, non-WS GET routes
-        let spa = warp::get()
-            .and(warp::path::full())
-            .and_then(|_path: warp::path::FullPath| async move {
-                if let Some(resp) = respond("index.html") {
-                    Ok::<_, warp::reject::Rejection>(resp)
-                } else {
-                    Err(warp::reject::not_found())
-                }
-            });
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/files/shots.fmemo")
+            .reply(&api)
+            .await;
+        let body: FileContent = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body.memos[0].images()[0].path, Some("img/ui.png".to_string()));
 
-        assets.or(root_files).or(spa)
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/assets/img/ui.png")
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body(), &b"png bytes"[..]);
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/assets/.fmemo/state.json")
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 404);
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/assets/%2Efmemo/state.json")
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 404);
+
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/assets/img%2F%2Efmemo/state.json")
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 404);
     }
-}
 
-#[cfg(feature = "embed_frontend")]
-pub fn create_full_routes_embedded(
-    root_dir: PathBuf,
-    clients: WebSocketClients,
-) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    let api_routes = create_api_routes(root_dir);
-    let ws_route = create_websocket_route(clients);
-    let static_routes = embedded::create_embedded_static_routes();
-    api_routes.or(ws_route).or(static_routes)
-}
+    #[tokio::test]
+    async fn test_api_write_file_with_auto_stamp() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_fmemo_file(temp_dir.path(), "notes", "# A\nalpha\n# B\nbeta\n");
+        let api = create_api_routes(temp_dir.path().to_path_buf());
 
-/// Create WebSocket route for real-time updates
-pub fn create_websocket_route(
-    clients: WebSocketClients,
-) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    warp::path("ws")
-        .and(warp::ws())
-        .map(move |ws: warp::ws::Ws| {
-            let clients = Arc::clone(&clients);
-            ws.on_upgrade(move |websocket| async move {
-                handle_websocket_connection(websocket, clients).await;
-            })
-        })
-}
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/api/file/notes.fmemo")
+            .json(&serde_json::json!({"content": "# A\nalpha\n# B\nbeta!\n", "auto_stamp": true}))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 200);
+        let body: FileContent = serde_json::from_slice(response.body()).unwrap();
+        assert!(body.memos[0].updated().is_none());
+        assert!(body.memos[1].updated().is_some());
+        assert!(body.memos[1].created().is_none());
+        let saved = fs::read_to_string(temp_dir.path().join("notes.fmemo")).unwrap();
+        assert!(saved.starts_with("# A\nalpha\n# B\n<updated>"));
 
-/// Handle individual WebSocket connection
-async fn handle_websocket_connection(
-    websocket: warp::ws::WebSocket,
-    clients: WebSocketClients,
-) {
-    let (mut ws_tx, mut ws_rx) = websocket.split();
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/api/file/sub/new.md")
+            .json(&serde_json::json!({"content": "# New"}))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(fs::read_to_string(temp_dir.path().join("sub").join("new.md")).unwrap(), "# New");
+    }
 
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
-    clients.lock().unwrap().push(tx);
+    #[tokio::test]
+    async fn test_api_write_file_rejects_invalid_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
 
-    let send_task = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            if ws_tx.send(msg).await.is_err() {
-                break;
-            }
+        for path in ["/api/file/../escape.fmemo", "/api/file/.fmemo/x.fmemo", "/api/file/notes.txt"] {
+            let response = warp::test::request()
+                .method("PUT")
+                .path(path)
+                .json(&serde_json::json!({"content": "# X"}))
+                .reply(&api)
+                .await;
+            assert_eq!(response.status(), 400, "{}", path);
         }
-    });
+    }
 
-    let recv_task = tokio::spawn(async move {
-        while let Some(result) = ws_rx.next().await {
-            if result.is_err() {
-                break;
-            }
-        }
-    });
+    #[tokio::test]
+    async fn test_api_create_file_from_template() {
+        let temp_dir = TempDir::new().unwrap();
+        let templates = temp_dir.path().join(".fmemo").join("templates");
+        fs::create_dir_all(&templates).unwrap();
+        fs::write(templates.join("meeting.fmemo"), "# {{title}}\n## Agenda\n").unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
 
-    tokio::select! {
-        _ = send_task => {},
-        _ = recv_task => {},
-    }
-}
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/templates")
+            .reply(&api)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["templates"], serde_json::json!(["meeting"]));
 
-/// Broadcast message to all WebSocket clients
-pub fn broadcast_to_clients(clients: &WebSocketClients, message: serde_json::Value) {
-    let clients_lock = clients.lock().unwrap();
-    let message_text = message.to_string();
-    
-    clients_lock.iter().for_each(|client| {
-        let _ = client.send(warp::ws::Message::text(message_text.clone()));
-    });
-}
+        let create = |body: serde_json::Value| {
+            warp::test::request()
+                .method("POST")
+                .path("/api/files/from-template")
+                .json(&body)
+        };
+        let response = create(serde_json::json!({"title": "Sync", "template": "meeting"}))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 201);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["path"], "sync.fmemo");
+        assert_eq!(body["memos"][0]["children"][0]["title"], "Agenda");
 
-/// Start file watcher for a specific file
-pub fn start_file_watcher<P: AsRef<Path>>(
-    file_path: P,
-    clients: WebSocketClients,
-) -> std::io::Result<()> {
-    let file_path = file_path.as_ref().to_path_buf();
-    let (tx, rx) = channel();
-    let mut watcher = RecommendedWatcher::new(tx, notify::Config::default())
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-    
-    watcher.watch(&file_path, RecursiveMode::NonRecursive)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        let response = create(serde_json::json!({"title": "Sync", "template": "meeting"}))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 409);
+        let response = create(serde_json::json!({"title": "Sync", "template": "nope"}))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 404);
 
-    // Spawn background thread to handle file changes
-    thread::spawn(move || {
-        // Keep watcher alive
-        let _watcher = watcher;
-        
-        loop {
-            match rx.recv() {
-                Ok(Ok(_event)) => {
-                    if let Ok(content) = fs::read_to_string(&file_path) {
-                        let memos = parse_memo(&content);
-                        
-                        let update_msg = serde_json::json!({
-                            "type": "file_updated",
-                            "file_path": file_path.to_string_lossy(),
-                            "memos": memos
-                        });
-                        
-                        broadcast_to_clients(&clients, update_msg);
-                    }
-                }
-                Ok(Err(e)) => {
-                    eprintln!("File watch event error: {:?}", e);
-                }
-                Err(e) => {
-                    eprintln!("File watch channel error: {:?}", e);
-                    break;
-                }
-            }
-        }
-    });
+        // Templates of a subdirectory, and rendering without creating a file
+        let work_templates = temp_dir.path().join("work").join(".fmemo").join("templates");
+        fs::create_dir_all(&work_templates).unwrap();
+        fs::write(work_templates.join("default.fmemo"), "# {{title}}\n- {{cursor}}\n").unwrap();
+        let response = warp::test::request()
+            .method("GET")
+            .path("/api/templates?dir=work")
+            .reply(&api)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["templates"], serde_json::json!(["default", "meeting"]));
 
-    Ok(())
-}
+        let response = warp::test::request()
+            .method("POST")
+            .path("/api/templates/render")
+            .json(&serde_json::json!({"title": "Plan", "path": "work/plan"}))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "path": "work/plan.fmemo",
+                "content": "# Plan\n- \n",
+                "cursor": {"line": 2, "column": 3}
+            })
+        );
+        assert!(!temp_dir.path().join("work").join("plan.fmemo").exists());
+    }
 
-/// Start directory watcher for .fmemo files
-pub fn start_directory_watcher<P: AsRef<Path>>(
-    root_path: P,
-    clients: WebSocketClients,
-) -> std::io::Result<()> {
-    let root_path = root_path.as_ref().to_path_buf();
-    let (tx, rx) = channel();
-    let mut watcher = RecommendedWatcher::new(tx, notify::Config::default())
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-    
-    watcher.watch(&root_path, RecursiveMode::Recursive)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    #[tokio::test]
+    async fn test_api_file_history() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let api = create_api_routes(root.to_path_buf());
+        let get = |path: &str| warp::test::request().method("GET").path(path).reply(&api);
 
-    thread::spawn(move || {
-        let _watcher = watcher;
-        let mut last_processed: std::collections::HashMap<std::path::PathBuf, std::time::SystemTime> = std::collections::HashMap::new();
-        
-        loop {
-            match rx.recv() {
-                Ok(Ok(event)) => {
-                    use std::collections::HashSet;
-                    use notify::EventKind;
-                    
-                    // Only process actual file content changes
-                    if !matches!(event.kind, 
-                        EventKind::Modify(notify::event::ModifyKind::Data(_)) | 
-                        EventKind::Create(_)
-                    ) {
-                        continue;
-                    }
-                    
-                    let now = std::time::SystemTime::now();
-                    let mut processed_files = HashSet::new();
-                    
-                    // Check if any changed file is a .fmemo or .md file
-                    for path in &event.paths {
-                        let ext = path.extension().and_then(|s| s.to_str());
-                        if (ext == Some("fmemo") || ext == Some("md")) && 
-                           processed_files.insert(path.clone()) {
-                            
-                            // Check if we processed this file recently (within 2 seconds)
-                            if let Some(last_time) = last_processed.get(path) {
-                                if let Ok(duration) = now.duration_since(*last_time) {
-                                    if duration.as_secs() < 2 {
-                                        println!("Skipping recent file change: {}", path.display());
-                                        continue;
-                                    }
-                                }
-                            }
-                            
-                            // Update last processed time
-                            last_processed.insert(path.clone(), now);
-                            
-                            // Send individual file update message
-                            if let Ok(content) = fs::read_to_string(path) {
-                                let memos = parse_memo(&content);
-                                
-                                let file_update_msg = serde_json::json!({
-                                    "type": "file_updated",
-                                    "file_path": path.to_string_lossy(),
-                                    "path": path.file_name().and_then(|n| n.to_str()).unwrap_or(""),
-                                    "memos": memos
-                                });
-                                
-                                broadcast_to_clients(&clients, file_update_msg);
-                                println!("Sent file update for: {}", path.display());
-                            }
-                        }
-                    }
+        let response = get("/api/files/sub/a.fmemo/history").await;
+        assert_eq!(response.status(), 404);
 
-                    // If structure changed (create/remove/rename), broadcast directory update
-                    if matches!(event.kind,
-                        EventKind::Create(_) |
-                        EventKind::Remove(_) |
-                        EventKind::Modify(notify::event::ModifyKind::Name(_))
-                    ) {
-                        if let Ok(tree) = scan_directory(&root_path) {
-                            // Transform to frontend expected format
-                            let response = serde_json::json!({
-                                "files": tree.files,
-                                "directories": tree.subdirectories.iter().map(|subdir| {
-                                    std::path::Path::new(&subdir.path)
-                                        .file_name()
-                                        .and_then(|name| name.to_str())
-                                        .unwrap_or(&subdir.path)
-                                }).collect::<Vec<_>>()
-                            });
-
-                            let dir_msg = serde_json::json!({
-                                "type": "directory_updated",
-                                "tree": response
-                            });
-                            broadcast_to_clients(&clients, dir_msg);
-                            println!("Sent directory update for root: {}", root_path.display());
-                        }
-                    }
-                }
-                Ok(Err(e)) => {
-                    eprintln!("Directory watch event error: {:?}", e);
-                }
-                Err(e) => {
-                    eprintln!("Directory watch channel error: {:?}", e);
-                    break;
-                }
-            }
+        for args in [
+            &["init", "--quiet"][..],
+            &["config", "user.name", "fmemo"],
+            &["config", "user.email", "fmemo@localhost"],
+        ] {
+            let status = std::process::Command::new("git")
+                .arg("-C")
+                .arg(root)
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success());
         }
-    });
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("sub/a.fmemo"), "# First\n![img](pic.png)").unwrap();
+        crate::git::commit_file(root, "sub/a.fmemo").unwrap();
+        fs::write(root.join("sub/a.fmemo"), "# Second").unwrap();
+        crate::git::commit_file(root, "sub/a.fmemo").unwrap();
 
-    Ok(())
-}
+        let response = get("/api/files/sub%2Fa.fmemo/history").await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let commits = body["commits"].as_array().unwrap();
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[1]["summary"], "fmemo: Add sub/a.fmemo");
+        let first = commits[1]["hash"].as_str().unwrap().to_string();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-    use tempfile::TempDir;
+        for rev in ["HEAD~1", first.as_str()] {
+            let response = get(&format!("/api/files/sub/a.fmemo/at/{}", rev)).await;
+            assert_eq!(response.status(), 200);
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(body["rev"], first.as_str());
+            assert_eq!(body["memos"][0]["title"], "First");
+        }
 
-    fn create_test_fmemo_file(dir: &Path, name: &str, content: &str) -> PathBuf {
-        let file_path = dir.join(format!("{}.fmemo", name));
-        fs::write(&file_path, content).unwrap();
-        file_path
-    }
+        assert_eq!(get("/api/files/sub/a.fmemo/at/nope").await.status(), 404);
+        assert_eq!(get("/api/files/sub/b.fmemo/at/HEAD").await.status(), 404);
+        assert_eq!(get("/api/files/.git/x.fmemo/history").await.status(), 400);
+        assert_eq!(get("/api/files/sub/a.fmemo/blame").await.status(), 404);
 
-    #[test]
-    fn test_scan_empty_directory() {
-        let temp_dir = TempDir::new().unwrap();
-        let result = scan_directory(temp_dir.path()).unwrap();
-        
-        assert_eq!(result.files.len(), 0);
-        assert_eq!(result.subdirectories.len(), 0);
-    }
+        let response = get("/api/files/sub/a.fmemo/diff?from=HEAD~1&to=HEAD").await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["path"], "sub/a.fmemo");
+        assert!(body["unified"].as_str().unwrap().contains("+# Second"));
+        let kinds: Vec<_> = body["memos"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|change| change["kind"].clone())
+            .collect();
+        assert_eq!(kinds, ["added", "removed"]);
 
-    #[test]
-    fn test_scan_directory_with_fmemo_files() {
-        let temp_dir = TempDir::new().unwrap();
-        
-        create_test_fmemo_file(temp_dir.path(), "test1", "# Test 1\nContent 1");
-        create_test_fmemo_file(temp_dir.path(), "test2", "# Test 2\nContent 2");
-        
-        // Create non-fmemo file (should be ignored)
-        fs::write(temp_dir.path().join("ignored.md"), "# Ignored").unwrap();
-        
-        let result = scan_directory(temp_dir.path()).unwrap();
-        
-        assert_eq!(result.files.len(), 2);
-        assert!(result.files.contains(&"test1.fmemo".to_string()));
-        assert!(result.files.contains(&"test2.fmemo".to_string()));
-        assert_eq!(result.subdirectories.len(), 0);
-    }
+        let response = get("/api/files/sub/a.fmemo/diff").await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["to"], serde_json::Value::Null);
+        assert_eq!(body["memos"], serde_json::json!([]));
+        assert_eq!(get("/api/files/sub/a.fmemo/diff?from=nope").await.status(), 404);
 
-    #[test]
-    fn test_scan_directory_with_subdirectories() {
-        let temp_dir = TempDir::new().unwrap();
-        
-        // Create files in root
-        create_test_fmemo_file(temp_dir.path(), "root", "# Root");
-        
-        // Create subdirectory with fmemo files
-        let sub_dir = temp_dir.path().join("subdir");
-        fs::create_dir(&sub_dir).unwrap();
-        create_test_fmemo_file(&sub_dir, "sub1", "# Sub 1");
-        create_test_fmemo_file(&sub_dir, "sub2", "# Sub 2");
-        
-        // Create empty subdirectory (should be excluded)
-        let empty_dir = temp_dir.path().join("empty");
-        fs::create_dir(&empty_dir).unwrap();
-        
-        let result = scan_directory(temp_dir.path()).unwrap();
-        
-        assert_eq!(result.files.len(), 1);
-        assert_eq!(result.subdirectories.len(), 1);
-        
-        let subdir_tree = &result.subdirectories[0];
-        assert_eq!(subdir_tree.files.len(), 2);
-        assert!(subdir_tree.files.contains(&"sub1.fmemo".to_string()));
-        assert!(subdir_tree.files.contains(&"sub2.fmemo".to_string()));
+        let restore = |path: &str, rev: &str| {
+            warp::test::request()
+                .method("POST")
+                .path(path)
+                .json(&serde_json::json!({"rev": rev}))
+                .reply(&api)
+        };
+        let response = restore("/api/files/sub/a.fmemo/restore", "HEAD~1").await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["rev"], first.as_str());
+        assert_eq!(body["memos"][0]["title"], "First");
+        assert_eq!(
+            fs::read_to_string(root.join("sub/a.fmemo")).unwrap(),
+            "# First\n![img](pic.png)"
+        );
+        assert_eq!(restore("/api/files/sub/a.fmemo/restore", "nope").await.status(), 404);
+        assert_eq!(restore("/api/files/.git/a.fmemo/restore", "HEAD").await.status(), 400);
     }
 
-    #[test]
-    fn test_scan_directory_skips_hidden_dirs() {
+    #[tokio::test]
+    async fn test_api_calendar() {
         let temp_dir = TempDir::new().unwrap();
-        
-        // Create hidden directory with fmemo file
-        let hidden_dir = temp_dir.path().join(".hidden");
-        fs::create_dir(&hidden_dir).unwrap();
-        create_test_fmemo_file(&hidden_dir, "hidden", "# Hidden");
-        
-        // Create normal directory with fmemo file
-        let normal_dir = temp_dir.path().join("normal");
-        fs::create_dir(&normal_dir).unwrap();
-        create_test_fmemo_file(&normal_dir, "normal", "# Normal");
-        
-        let result = scan_directory(temp_dir.path()).unwrap();
-        
-        assert_eq!(result.subdirectories.len(), 1);
-        assert_eq!(result.subdirectories[0].files[0], "normal.fmemo");
+        fs::write(temp_dir.path().join("2024-05-01.fmemo"), "# Day\n## Pay rent\n<due>2024-05-03</due>").unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+        let get = |path: &str| warp::test::request().path(path).reply(&api);
+
+        let response = get("/api/calendar?from=2024-05-01&to=2024-05-31").await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["days"][0]["date"], "2024-05-01");
+        assert_eq!(body["days"][0]["entries"][0]["source"], "file_name");
+        assert_eq!(body["days"][1]["entries"][0]["title"], "Pay rent");
+        assert_eq!(get("/api/calendar?from=2024-05-03&to=2024-05-03").await.status(), 200);
+        assert_eq!(get("/api/calendar?from=May").await.status(), 400);
+        assert_eq!(get("/api/calendar?from=2024-06-01&to=2024-05-01").await.status(), 400);
     }
 
-    #[test]
-    fn test_read_fmemo_file() {
+    #[tokio::test]
+    async fn test_api_journal() {
         let temp_dir = TempDir::new().unwrap();
-        let file_content = r#"
-# Test Function
-<desc>A test function</desc>
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+        let request = |method: &str, path: &str| warp::test::request().method(method).path(path).reply(&api);
 
-This is some content.
+        let response = request("POST", "/api/journal/2024-05-01").await;
+        assert_eq!(response.status(), 201);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["day"]["path"], "journal/2024/05/2024-05-01.fmemo");
+        assert!(temp_dir.path().join("journal/2024/05/2024-05-01.fmemo").is_file());
+        assert_eq!(request("POST", "/api/journal/2024-05-01").await.status(), 200);
+        fs::write(temp_dir.path().join("journal/2024/05/2024-05-09.fmemo"), "# Ninth\nRelease notes").unwrap();
 
-```rust
-fn test() {
-    println!("test");
-}
-```
+        let response = request("GET", "/api/journal/2024-05-04").await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["exists"], false);
+        assert_eq!(body["previous"], "2024-05-01");
+        assert_eq!(body["next"], "2024-05-09");
+        let response = request("GET", "/api/journal/2024-05-01/next").await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["date"], "2024-05-09");
+        assert_eq!(request("GET", "/api/journal/2024-05-01/previous").await.status(), 404);
+        assert_eq!(request("GET", "/api/journal/today").await.status(), 200);
+        assert_eq!(request("GET", "/api/journal/May").await.status(), 400);
 
-## Sub Section
-More content here.
-"#;
-        
-        let file_path = create_test_fmemo_file(temp_dir.path(), "test", file_content);
-        
-        let result = read_fmemo_file(&file_path).unwrap();
-        
-        assert_eq!(result.memos.len(), 1);
-        assert_eq!(result.memos[0].title(), "Test Function");
-        assert_eq!(result.memos[0].description(), &Some("A test function".to_string()));
-        assert_eq!(result.memos[0].code_blocks().len(), 1);
-        assert_eq!(result.memos[0].children().len(), 1);
-        assert_eq!(result.memos[0].children()[0].title(), "Sub Section");
-        assert!(result.last_modified.is_some());
+        let response = request("GET", "/api/journal?from=2024-05-02&to=2024-05-31&q=release").await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["entries"].as_array().unwrap().len(), 1);
+        assert_eq!(body["entries"][0]["title"], "Ninth");
+        assert_eq!(request("GET", "/api/journal?from=2024-06-01&to=2024-05-01").await.status(), 400);
     }
 
-    #[test]
-    fn test_read_non_fmemo_file() {
+    #[tokio::test]
+    async fn test_api_archive() {
         let temp_dir = TempDir::new().unwrap();
-        let file_path = temp_dir.path().join("test.md");
-        fs::write(&file_path, "# Test").unwrap();
-        
-        let result = read_fmemo_file(&file_path);
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
-    }
+        fs::write(temp_dir.path().join("old.fmemo"), "# Old").unwrap();
+        fs::write(temp_dir.path().join("new.fmemo"), "# New").unwrap();
+        let two_years_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(2 * 365 * 24 * 60 * 60);
+        fs::File::options().write(true).open(temp_dir.path().join("old.fmemo")).unwrap().set_modified(two_years_ago).unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+        let files = |path: &'static str| {
+            let api = api.clone();
+            async move {
+                let response = warp::test::request().path(path).reply(&api).await;
+                let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+                body["files"].as_array().unwrap().iter().map(|file| file["path"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+            }
+        };
 
-    #[test]
-    fn test_read_nonexistent_file() {
-        let temp_dir = TempDir::new().unwrap();
-        let file_path = temp_dir.path().join("nonexistent.fmemo");
-        
-        let result = read_fmemo_file(&file_path);
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+        let response = warp::test::request().method("POST").path("/api/archive").json(&serde_json::json!({"dry_run": true})).reply(&api).await;
+        assert_eq!(response.status(), 200);
+        assert!(temp_dir.path().join("old.fmemo").exists());
+        let response = warp::test::request().method("POST").path("/api/archive").json(&serde_json::json!({"older_than": "1y"})).reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["archived"][0]["to"], "archive/old.fmemo");
+        assert!(temp_dir.path().join("archive/old.fmemo").exists());
+
+        assert_eq!(files("/api/files").await, ["new.fmemo"]);
+        assert_eq!(files("/api/files?archived=true").await, ["archive/old.fmemo", "new.fmemo"]);
+        let response = warp::test::request().path("/api/root").reply(&api).await;
+        let tree: DirectoryTree = serde_json::from_slice(response.body()).unwrap();
+        assert!(tree.subdirectories.is_empty());
+        let response = warp::test::request().method("POST").path("/api/archive").json(&serde_json::json!({"older_than": "soon"})).reply(&api).await;
+        assert_eq!(response.status(), 400);
     }
 
-    #[test]
-    fn test_has_fmemo_files() {
-        // Tree with files
-        let tree_with_files = DirectoryTree {
-            path: "/test".to_string(),
-            files: vec!["test.fmemo".to_string()],
-            subdirectories: vec![],
-        };
-        assert!(has_fmemo_files(&tree_with_files));
+    #[tokio::test]
+    async fn test_api_merge() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("notes.fmemo"), "# Setup\napt\n# Usage\nRun it").unwrap();
+        fs::write(temp_dir.path().join("old.fmemo"), "# Setup\nbrew\n## Windows\nwinget").unwrap();
+        let (client_tx, mut client_rx) = tokio::sync::mpsc::unbounded_channel();
+        let clients: WebSocketClients = Arc::new(Mutex::new(vec![client_tx.into()]));
+        let api = create_api_routes_with_options(
+            temp_dir.path().to_path_buf(),
+            ApiOptions { clients: Some(clients), ..Default::default() },
+        );
+        let merge = |body: serde_json::Value| warp::test::request().method("POST").path("/api/files/merge").json(&body).reply(&api);
 
-        // Tree without files but with subdirectory that has files
-        let tree_with_sub_files = DirectoryTree {
-            path: "/test".to_string(),
-            files: vec![],
-            subdirectories: vec![tree_with_files],
-        };
-        assert!(has_fmemo_files(&tree_with_sub_files));
+        let response = merge(serde_json::json!({"source": "old.fmemo", "target": "notes.fmemo", "mode": "interleave"})).await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["memos"][0]["children"][0]["title"], "Windows");
+        assert_eq!(body["trash"]["path"], "old.fmemo");
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("notes.fmemo")).unwrap(),
+            "# Setup\napt\nbrew\n## Windows\nwinget\n# Usage\nRun it\n"
+        );
+        assert!(!temp_dir.path().join("old.fmemo").exists());
+        let message = client_rx.recv().await.unwrap();
+        let message: serde_json::Value = serde_json::from_str(message.to_str().unwrap()).unwrap();
+        assert_eq!(message["type"], "files_merged");
+        assert_eq!(message["removed"], true);
 
-        // Tree with no files
-        let tree_empty = DirectoryTree {
-            path: "/test".to_string(),
-            files: vec![],
-            subdirectories: vec![],
-        };
-        assert!(!has_fmemo_files(&tree_empty));
+        assert_eq!(merge(serde_json::json!({"source": "old.fmemo", "target": "notes.fmemo"})).await.status(), 404);
+        assert_eq!(merge(serde_json::json!({"source": "notes.fmemo", "target": "notes.fmemo"})).await.status(), 400);
+        assert_eq!(merge(serde_json::json!({"source": "../x.fmemo", "target": "notes.fmemo"})).await.status(), 400);
     }
 
     #[tokio::test]
-    async fn test_api_root_endpoint() {
+    async fn test_api_split() {
         let temp_dir = TempDir::new().unwrap();
-        create_test_fmemo_file(temp_dir.path(), "test1", "# Test 1\nContent 1");
-        create_test_fmemo_file(temp_dir.path(), "test2", "# Test 2\nContent 2");
-
+        fs::write(temp_dir.path().join("big.fmemo"), "# One\nfirst\n# Two\nsecond").unwrap();
         let api = create_api_routes(temp_dir.path().to_path_buf());
+        let split = |path: &str| warp::test::request().method("POST").path(path).reply(&api);
 
+        let response = split("/api/files/big.fmemo/split").await;
+        assert_eq!(response.status(), 201);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["files"], serde_json::json!(["one.fmemo", "two.fmemo"]));
+        assert_eq!(fs::read_to_string(temp_dir.path().join("two.fmemo")).unwrap(), "# Two\nsecond\n");
+        assert!(!temp_dir.path().join("big.fmemo").exists());
+
+        assert_eq!(split("/api/files/big.fmemo/split").await.status(), 404);
+        assert_eq!(split("/api/files/one.fmemo/split").await.status(), 400);
         let response = warp::test::request()
-            .method("GET")
-            .path("/api/root")
+            .method("POST")
+            .path("/api/files/missing.fmemo/split")
+            .json(&serde_json::json!({"source_action": "keep"}))
             .reply(&api)
             .await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_calendar_feed() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("tasks.fmemo"), "# Taxes\n<due>2024-04-15</due>").unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
 
+        let response = warp::test::request().path("/calendar.ics").reply(&api).await;
         assert_eq!(response.status(), 200);
-        
-        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
-        let files = body["files"].as_array().unwrap();
-        assert_eq!(files.len(), 2);
-        
-        let file_names: Vec<&str> = files.iter()
-            .map(|f| f.as_str().unwrap())
-            .collect();
-        assert!(file_names.contains(&"test1.fmemo"));
-        assert!(file_names.contains(&"test2.fmemo"));
+        assert_eq!(response.headers()["content-type"], "text/calendar; charset=utf-8");
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.contains("SUMMARY:Taxes\r\n"));
     }
 
     #[tokio::test]
-    async fn test_api_files_endpoint_success() {
+    async fn test_api_graph() {
         let temp_dir = TempDir::new().unwrap();
-        let content = r#"
-# Test Function
-<desc>A test function</desc>
-
-This is content.
+        fs::write(temp_dir.path().join("a.fmemo"), "# A\n[[b]]").unwrap();
+        fs::write(temp_dir.path().join("b.fmemo"), "# B").unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
 
-```rust
-fn test() {}
-```
-"#;
-        create_test_fmemo_file(temp_dir.path(), "test", content);
+        let response = warp::test::request().path("/api/graph").reply(&api).await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["nodes"].as_array().unwrap().len(), 4);
 
-        let api = create_api_routes(temp_dir.path().to_path_buf());
+        let response = warp::test::request().path("/api/graph?memos=false").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            body["edges"],
+            serde_json::json!([{"source": "a.fmemo", "target": "b.fmemo", "kind": "link"}])
+        );
+    }
 
-        let response = warp::test::request()
-            .method("GET")
-            .path("/api/files/test.fmemo")
-            .reply(&api)
-            .await;
+    #[tokio::test]
+    async fn test_api_broken_links() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.fmemo"), "# A\n[[b]]\n[[renamed]]").unwrap();
+        fs::write(temp_dir.path().join("b.fmemo"), "# B").unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
 
+        let response = warp::test::request().path("/api/links/broken").reply(&api).await;
         assert_eq!(response.status(), 200);
-        
-        let body: FileContent = serde_json::from_slice(response.body()).unwrap();
-        assert_eq!(body.memos.len(), 1);
-        assert_eq!(body.memos[0].title(), "Test Function");
-        assert_eq!(body.memos[0].description(), &Some("A test function".to_string()));
-        assert_eq!(body.memos[0].code_blocks().len(), 1);
-        assert!(body.last_modified.is_some());
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            body["broken"],
+            serde_json::json!([{
+                "file": "a.fmemo", "line": 3, "target": "renamed", "kind": "wiki", "reason": "missing_file"
+            }])
+        );
     }
 
     #[tokio::test]
-    async fn test_api_files_endpoint_not_found() {
+    async fn test_api_file_spelling() {
         let temp_dir = TempDir::new().unwrap();
+        let dictionaries = temp_dir.path().join(".fmemo/dictionaries");
+        fs::create_dir_all(&dictionaries).unwrap();
+        fs::write(dictionaries.join("en_US.dic"), "3\nthe\nnote/S\nlist\n").unwrap();
+        fs::write(dictionaries.join("en_US.aff"), "SFX S N 1\nSFX S 0 s .\n").unwrap();
+        fs::write(temp_dir.path().join(".fmemo/dictionary.txt"), "fmemo\n").unwrap();
+        fs::write(temp_dir.path().join("a.fmemo"), "# The notes\nfmemo lsit\n```\nnot chekced\n```").unwrap();
         let api = create_api_routes(temp_dir.path().to_path_buf());
 
-        let response = warp::test::request()
-            .method("GET")
-            .path("/api/files/nonexistent.fmemo")
-            .reply(&api)
-            .await;
+        let response = warp::test::request().path("/api/files/a.fmemo/spelling").reply(&api).await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            body["misspellings"],
+            serde_json::json!([{"line": 2, "column": 7, "word": "lsit", "suggestions": ["list"]}])
+        );
 
+        let response = warp::test::request().path("/api/files/a.fmemo/spelling?lang=xx").reply(&api).await;
+        assert_eq!(response.status(), 404);
+        let response = warp::test::request().path("/api/files/missing.fmemo/spelling").reply(&api).await;
         assert_eq!(response.status(), 404);
-        
-        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
-        assert!(body["error"].as_str().unwrap().contains("File not found"));
     }
 
     #[tokio::test]
-    async fn test_api_files_endpoint_invalid_extension() {
+    async fn test_api_summarize() {
+        use std::io::{BufRead, Read, Write};
+
         let temp_dir = TempDir::new().unwrap();
-        // Create a .md file instead of .fmemo
-        fs::write(temp_dir.path().join("test.md"), "# Test").unwrap();
-        
+        fs::write(temp_dir.path().join("a.fmemo"), "# Plan\nShip it\n## Steps\nTest, then release").unwrap();
         let api = create_api_routes(temp_dir.path().to_path_buf());
+        let summarize = |body: serde_json::Value| {
+            warp::test::request()
+                .method("POST")
+                .path("/api/files/a.fmemo/summarize")
+                .json(&body)
+                .reply(&api)
+        };
 
-        let response = warp::test::request()
-            .method("GET")
-            .path("/api/files/test.md")
-            .reply(&api)
-            .await;
+        assert_eq!(summarize(serde_json::json!({})).await.status(), 501);
 
-        assert_eq!(response.status(), 404);
-        
+        // A backend answering a single request
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        fs::create_dir(temp_dir.path().join(".fmemo")).unwrap();
+        fs::write(
+            temp_dir.path().join(".fmemo/config.toml"),
+            format!("[llm]\nurl = \"http://{}/v1\"\n", listener.local_addr().unwrap()),
+        )
+        .unwrap();
+        let backend = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = std::io::BufReader::new(stream);
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end().to_lowercase();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(value) = line.strip_prefix("content-length: ") {
+                    length = value.parse().unwrap();
+                }
+            }
+            let mut request = vec![0; length];
+            reader.read_exact(&mut request).unwrap();
+            let body = r#"{"choices":[{"message":{"content":"Test, then release."}}]}"#;
+            write!(reader.get_mut(), "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+            serde_json::from_slice::<serde_json::Value>(&request).unwrap()
+        });
+
+        let response = summarize(serde_json::json!({"memo": "steps", "write": true})).await;
+        assert_eq!(response.status(), 200);
         let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
-        assert!(body["error"].as_str().unwrap().contains("Invalid file type"));
+        assert_eq!(body["summary"], "Test, then release.");
+        assert_eq!(body["written_to"], "steps");
+        let request = backend.join().unwrap();
+        assert_eq!(request["messages"][1]["content"], "# Steps\nTest, then release\n");
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("a.fmemo")).unwrap(),
+            "# Plan\nShip it\n## Steps\n<desc>Test, then release.</desc>\nTest, then release"
+        );
+
+        assert_eq!(summarize(serde_json::json!({"memo": "nope"})).await.status(), 404);
     }
 
     #[tokio::test]
-    async fn test_api_root_endpoint_with_subdirectories() {
+    async fn test_api_tag_files() {
         let temp_dir = TempDir::new().unwrap();
-        
-        // Create file in root
-        create_test_fmemo_file(temp_dir.path(), "root", "# Root");
-        
-        // Create subdirectory with files
-        let sub_dir = temp_dir.path().join("subdir");
-        fs::create_dir(&sub_dir).unwrap();
-        create_test_fmemo_file(&sub_dir, "sub1", "# Sub 1");
-        
-        let api = create_api_routes(temp_dir.path().to_path_buf());
+        for name in ["a", "b", "c"] {
+            fs::write(
+                temp_dir.path().join(format!("{}.fmemo", name)),
+                format!("# {}\n<tag>C++</tag>", name),
+            )
+            .unwrap();
+        }
+        fs::write(temp_dir.path().join("d.fmemo"), "# D").unwrap();
+        let routes = create_tag_routes(crate::tags::TagIndex::new(temp_dir.path()));
 
         let response = warp::test::request()
-            .method("GET")
-            .path("/api/root")
-            .reply(&api)
+            .path("/api/tags/C%2B%2B/files?offset=1&limit=1")
+            .reply(&routes)
             .await;
-
         assert_eq!(response.status(), 200);
-        
         let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
-        let files = body["files"].as_array().unwrap();
-        let directories = body["directories"].as_array().unwrap();
-        
-        assert_eq!(files.len(), 1);
-        assert_eq!(directories.len(), 1);
-        assert_eq!(directories[0].as_str().unwrap(), "subdir");
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "tag": "C++", "total": 3, "offset": 1, "limit": 1, "files": ["b.fmemo"],
+                "next_cursor": crate::page::encode_cursor("b.fmemo")
+            })
+        );
+
+        // The cursor continues after b.fmemo even when a file before it goes away
+        fs::write(temp_dir.path().join("a.fmemo"), "# a").unwrap();
+        let routes = create_tag_routes(crate::tags::TagIndex::new(temp_dir.path()));
+        let response = warp::test::request()
+            .path(&format!("/api/tags/C%2B%2B/files?limit=1&cursor={}", body["next_cursor"].as_str().unwrap()))
+            .reply(&routes)
+            .await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["files"], serde_json::json!(["c.fmemo"]));
+        assert_eq!(body["next_cursor"], serde_json::Value::Null);
+
+        let response = warp::test::request()
+            .path("/api/tags/C%2B%2B/files?limit=-1")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 400);
     }
 
-    #[tokio::test] 
-    async fn test_api_wrong_method() {
+    #[tokio::test]
+    async fn test_api_pins() {
         let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.fmemo"), "# A").unwrap();
+        fs::write(temp_dir.path().join("b.md"), "# B").unwrap();
         let api = create_api_routes(temp_dir.path().to_path_buf());
+        let pin = |body: serde_json::Value| {
+            warp::test::request()
+                .method("POST")
+                .path("/api/pins")
+                .json(&body)
+                .reply(&api)
+        };
 
-        let response = warp::test::request()
-            .method("POST")  // Should be GET
-            .path("/api/root")
-            .reply(&api)
-            .await;
+        assert_eq!(pin(serde_json::json!({"path": "b.md"})).await.status(), 200);
+        let response = pin(serde_json::json!({"path": "a.fmemo"})).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["pins"], serde_json::json!(["b.md", "a.fmemo"]));
+        assert_eq!(pin(serde_json::json!({"path": "missing.fmemo"})).await.status(), 404);
+        assert_eq!(pin(serde_json::json!({"path": "../a.fmemo"})).await.status(), 400);
 
-        assert_eq!(response.status(), 405);  // Method Not Allowed
+        // Unpinned and deleted files drop out of the list
+        pin(serde_json::json!({"path": "a.fmemo", "pinned": false})).await;
+        fs::write(temp_dir.path().join("c.md"), "# C").unwrap();
+        pin(serde_json::json!({"path": "c.md"})).await;
+        fs::remove_file(temp_dir.path().join("c.md")).unwrap();
+        let response = warp::test::request().path("/api/pins").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body, serde_json::json!({"pins": ["b.md"]}));
     }
 
     #[tokio::test]
-    async fn test_api_invalid_path() {
+    async fn test_api_trash() {
         let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("notes")).unwrap();
+        fs::write(temp_dir.path().join("notes/a b.fmemo"), "# A").unwrap();
         let api = create_api_routes(temp_dir.path().to_path_buf());
 
         let response = warp::test::request()
-            .method("GET")
-            .path("/api/invalid")
+            .method("DELETE")
+            .path("/api/files/notes/a%20b.fmemo")
             .reply(&api)
             .await;
+        assert_eq!(response.status(), 200);
+        let entry: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(entry["path"], "notes/a b.fmemo");
+        assert!(!temp_dir.path().join("notes/a b.fmemo").exists());
 
-        assert_eq!(response.status(), 404);  // Not Found
-    }
+        let response = warp::test::request().path("/api/root").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["files"], serde_json::json!([]));
+        assert_eq!(body["subdirectories"], serde_json::json!([]));
 
-    #[tokio::test]
-    async fn test_file_change_websocket_integration() {
-        use std::time::Duration;
-        use tokio::time::timeout;
-        
-        let temp_dir = TempDir::new().unwrap();
-        let file_path = create_test_fmemo_file(temp_dir.path(), "test", "# Original Title\nOriginal content");
-        
-        // Create mock WebSocket client
-        let (client_tx, mut client_rx) = tokio::sync::mpsc::unbounded_channel();
-        let clients: WebSocketClients = Arc::new(Mutex::new(vec![client_tx]));
-        
-        // Start file watcher
-        start_file_watcher(&file_path, clients.clone()).unwrap();
-        
-        // Give the watcher a moment to start
-        tokio::time::sleep(Duration::from_millis(100)).await;
-        
-        // Modify the file
-        fs::write(&file_path, "# Updated Title\n<desc>New description</desc>\nUpdated content\n\n```rust\nfn test() {}\n```").unwrap();
-        
-        // Wait for notification with timeout
-        let result = timeout(Duration::from_secs(2), client_rx.recv()).await;
-        
-        assert!(result.is_ok(), "Should receive WebSocket message within timeout");
-        
-        let message = result.unwrap().unwrap();
-        let text = message.to_str().unwrap();
-        let parsed: serde_json::Value = serde_json::from_str(text).unwrap();
-        
-        // Verify message structure
-        assert_eq!(parsed["type"], "file_updated");
-        assert!(parsed["file_path"].as_str().unwrap().ends_with("test.fmemo"));
-        
-        // Verify memo content
-        let memos = &parsed["memos"];
-        assert_eq!(memos.as_array().unwrap().len(), 1);
-        
-        let memo = &memos[0];
-        assert_eq!(memo["title"], "Updated Title");
-        assert_eq!(memo["description"], "New description");
-        assert_eq!(memo["code_blocks"].as_array().unwrap().len(), 1);
-        assert_eq!(memo["code_blocks"][0]["language"], "rust");
-    }
+        let response = warp::test::request()
+            .method("DELETE")
+            .path("/api/files/notes/a%20b.fmemo")
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 404);
 
-    #[tokio::test]
-    async fn test_directory_change_websocket_integration() {
-        use std::time::Duration;
-        use tokio::time::timeout;
-        
-        let temp_dir = TempDir::new().unwrap();
-        create_test_fmemo_file(temp_dir.path(), "existing", "# Existing File");
-        
-        // Create mock WebSocket client
-        let (client_tx, mut client_rx) = tokio::sync::mpsc::unbounded_channel();
-        let clients: WebSocketClients = Arc::new(Mutex::new(vec![client_tx]));
-        
-        // Start directory watcher
-        start_directory_watcher(temp_dir.path(), clients.clone()).unwrap();
-        
-        // Give the watcher a moment to start
-        tokio::time::sleep(Duration::from_millis(100)).await;
-        
-        // Create a new .fmemo file
-        create_test_fmemo_file(temp_dir.path(), "new_file", "# New File\nThis is new content");
-        
-        // Wait for notification with timeout
-        let result = timeout(Duration::from_secs(2), client_rx.recv()).await;
-        
-        assert!(result.is_ok(), "Should receive WebSocket message within timeout");
-        
-        let message = result.unwrap().unwrap();
-        let text = message.to_str().unwrap();
-        let parsed: serde_json::Value = serde_json::from_str(text).unwrap();
-        
-        // Verify message structure
-        assert_eq!(parsed["type"], "directory_updated");
-        
-        // Verify directory tree content
-        let tree = &parsed["tree"];
-        assert_eq!(tree["files"].as_array().unwrap().len(), 2);
-        
-        let files = tree["files"].as_array().unwrap();
-        let file_names: Vec<&str> = files.iter()
-            .map(|f| f.as_str().unwrap())
-            .collect();
-        
-        assert!(file_names.contains(&"existing.fmemo"));
-        assert!(file_names.contains(&"new_file.fmemo"));
-    }
+        let response = warp::test::request().path("/api/trash").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["trash"], serde_json::json!([entry]));
 
-    #[tokio::test]
-    async fn test_multiple_clients_receive_updates() {
-        use std::time::Duration;
-        use tokio::time::timeout;
-        
-        let temp_dir = TempDir::new().unwrap();
-        let file_path = create_test_fmemo_file(temp_dir.path(), "test", "# Test");
-        
-        // Create multiple mock WebSocket clients
-        let (client1_tx, mut client1_rx) = tokio::sync::mpsc::unbounded_channel();
-        let (client2_tx, mut client2_rx) = tokio::sync::mpsc::unbounded_channel();
-        let clients: WebSocketClients = Arc::new(Mutex::new(vec![client1_tx, client2_tx]));
-        
-        // Start file watcher
-        start_file_watcher(&file_path, clients.clone()).unwrap();
-        
-        // Give the watcher a moment to start
-        tokio::time::sleep(Duration::from_millis(100)).await;
-        
-        // Modify the file
-        fs::write(&file_path, "# Updated Content").unwrap();
-        
-        // Both clients should receive the message
-        let result1 = timeout(Duration::from_secs(2), client1_rx.recv()).await;
-        let result2 = timeout(Duration::from_secs(2), client2_rx.recv()).await;
-        
-        assert!(result1.is_ok(), "Client 1 should receive message");
-        assert!(result2.is_ok(), "Client 2 should receive message");
-        
-        // Both should have the same content
-        let message1 = result1.unwrap().unwrap();
-        let message2 = result2.unwrap().unwrap();
-        let msg1 = message1.to_str().unwrap();
-        let msg2 = message2.to_str().unwrap();
-        assert_eq!(msg1, msg2);
-        
-        let parsed: serde_json::Value = serde_json::from_str(msg1).unwrap();
-        assert_eq!(parsed["type"], "file_updated");
-        assert_eq!(parsed["memos"][0]["title"], "Updated Content");
+        let restore_path = format!("/api/trash/{}/restore", entry["id"].as_str().unwrap());
+        let response = warp::test::request()
+            .method("POST")
+            .path(&restore_path)
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("notes/a b.fmemo")).unwrap(),
+            "# A"
+        );
+        let response = warp::test::request()
+            .method("POST")
+            .path(&restore_path)
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 404);
     }
 
     #[tokio::test]
-    async fn test_static_routes_creation() {
-        let temp_dir = TempDir::new().unwrap();
-        
-        // Create mock dist directory structure
-        let dist_dir = temp_dir.path().join("dist");
-        fs::create_dir(&dist_dir).unwrap();
-        fs::create_dir(&dist_dir.join("assets")).unwrap();
-        fs::write(&dist_dir.join("index.html"), "<!DOCTYPE html><html><head></head><body></body></html>").unwrap();
-        fs::write(&dist_dir.join("favicon.ico"), "fake favicon").unwrap();
-        fs::write(&dist_dir.join("vite.svg"), "<svg></svg>").unwrap();
-        fs::write(&dist_dir.join("assets").join("main.js"), "console.log('test');").unwrap();
-        
-        let static_routes = create_static_routes(dist_dir.clone());
-        
-        // Test serving index.html for SPA routes
-        let response = warp::test::request()
-            .method("GET")
-            .path("/")
-            .reply(&static_routes)
+    async fn test_api_draft() {
+        let temp_dir = TempDir::new().unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+        let request = |method: &str| {
+            warp::test::request()
+                .method(method)
+                .path("/api/files/notes%2Fnew.fmemo/draft")
+        };
+
+        assert_eq!(request("GET").reply(&api).await.status(), 404);
+        let response = request("PUT")
+            .json(&serde_json::json!({"content": "# Half wri"}))
+            .reply(&api)
             .await;
-        
         assert_eq!(response.status(), 200);
-        assert!(response.body().starts_with(b"<!DOCTYPE html"));
-        
-        // Test serving favicon
+        assert!(!temp_dir.path().join("notes/new.fmemo").exists());
+
+        let response = request("GET").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["path"], "notes/new.fmemo");
+        assert_eq!(body["content"], "# Half wri");
+
+        // Saving the file discards its draft
+        fs::create_dir(temp_dir.path().join("notes")).unwrap();
         let response = warp::test::request()
-            .method("GET")
-            .path("/favicon.ico")
-            .reply(&static_routes)
+            .method("PUT")
+            .path("/api/file/notes/new.fmemo")
+            .json(&serde_json::json!({"content": "# Half written"}))
+            .reply(&api)
             .await;
-        
         assert_eq!(response.status(), 200);
-        assert_eq!(response.body(), &b"fake favicon"[..]);
-        
-        // Test serving assets
+        assert_eq!(request("GET").reply(&api).await.status(), 404);
+
         let response = warp::test::request()
-            .method("GET")
-            .path("/assets/main.js")
-            .reply(&static_routes)
+            .method("PUT")
+            .path("/api/files/../x.fmemo/draft")
+            .json(&serde_json::json!({"content": ""}))
+            .reply(&api)
             .await;
-        
-        assert_eq!(response.status(), 200);
-        assert_eq!(response.body(), &b"console.log('test');"[..]);
+        assert_eq!(response.status(), 400);
     }
 
     #[tokio::test]
-    async fn test_api_only_routes() {
+    async fn test_api_files_highlight() {
         let temp_dir = TempDir::new().unwrap();
-        create_test_fmemo_file(temp_dir.path(), "test", "# Test Content\nSome content");
-        
-        let clients: WebSocketClients = Arc::new(Mutex::new(Vec::new()));
-        let api_routes = create_api_only_routes(temp_dir.path().to_path_buf(), clients);
-        
-        // Test API endpoint works
+        fs::write(temp_dir.path().join("a.fmemo"), "# A\n```rust\nfn main() {}\n```").unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+        let code_block = |body: &[u8]| {
+            let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+            body["memos"][0]["code_blocks"][0].clone()
+        };
+
+        let response = warp::test::request().path("/api/files/a.fmemo").reply(&api).await;
+        assert!(code_block(response.body()).get("highlighted_html").is_none());
+
         let response = warp::test::request()
-            .method("GET")
-            .path("/api/root")
-            .reply(&api_routes)
+            .path("/api/files/a.fmemo?highlight=true")
+            .reply(&api)
             .await;
-        
-        assert_eq!(response.status(), 200);
-        
-        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
-        let files = body["files"].as_array().unwrap();
-        assert_eq!(files.len(), 1);
-        assert_eq!(files[0].as_str().unwrap(), "test.fmemo");
+        let html = code_block(response.body())["highlighted_html"].clone();
+        assert!(html.as_str().unwrap().contains("<span style="));
+
+        let response = warp::test::request()
+            .path("/api/files/a.fmemo?highlight=nope")
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 400);
+
+        fs::create_dir(temp_dir.path().join(".fmemo")).unwrap();
+        fs::write(temp_dir.path().join(".fmemo/config.toml"), "[highlight]\nenabled = true").unwrap();
+        let response = warp::test::request().path("/api/files/a.fmemo").reply(&api).await;
+        assert!(code_block(response.body())["highlighted_html"].is_string());
     }
 
     #[tokio::test]
-    async fn test_full_routes_combination() {
+    async fn test_api_files_render_html() {
         let temp_dir = TempDir::new().unwrap();
-        create_test_fmemo_file(temp_dir.path(), "test", "# Test Content\nSome content");
-        
-        // Create mock dist directory
-        let dist_dir = temp_dir.path().join("dist");
-        fs::create_dir(&dist_dir).unwrap();
-        fs::write(&dist_dir.join("index.html"), "<!DOCTYPE html><html><head></head><body></body></html>").unwrap();
-        
-        let clients: WebSocketClients = Arc::new(Mutex::new(Vec::new()));
-        let full_routes = create_full_routes(
-            temp_dir.path().to_path_buf(),
-            dist_dir.clone(),
-            clients
-        );
-        
-        // Test API endpoint works
+        fs::write(temp_dir.path().join("a.fmemo"), "# A
+**hi** <b>x</b>
+## B
+```rust
+fn main() {}
+```").unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+
+        let response = warp::test::request().path("/api/files/a.fmemo").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert!(body["memos"][0].get("content_html").is_none());
+
         let response = warp::test::request()
-            .method("GET")
-            .path("/api/root")
-            .reply(&full_routes)
+            .path("/api/files/a.fmemo?render=html&highlight=true")
+            .reply(&api)
             .await;
-        
-        assert_eq!(response.status(), 200);
-        
-        // Test static file serving works
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            body["memos"][0]["content_html"],
+            "<p><strong>hi</strong> &lt;b&gt;x&lt;/b&gt;</p>"
+        );
+        let html = body["memos"][0]["children"][0]["content_html"].as_str().unwrap();
+        assert!(html.starts_with("<pre><code class=\"language-rust\"><span style="));
+    }
+
+    #[tokio::test]
+    async fn test_api_files_embeds() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.fmemo"), "# A\n![[b#steps]]").unwrap();
+        fs::write(temp_dir.path().join("b.fmemo"), "# B\n## Steps\nRun it").unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+
+        let response = warp::test::request().path("/api/files/a.fmemo").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["memos"][0]["embeds"][0]["path"], "b.fmemo");
+        assert_eq!(body["memos"][0]["embeds"][0]["anchor"], "steps");
+        assert!(body["memos"][0]["embeds"][0].get("html").is_none());
+
         let response = warp::test::request()
-            .method("GET")
-            .path("/")
-            .reply(&full_routes)
+            .path("/api/files/a.fmemo?render=html&embeds=expand")
+            .reply(&api)
             .await;
-        
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            body["memos"][0]["content_html"],
+            "<div class=\"embed\" data-target=\"b#steps\"><section><h2 id=\"steps\">Steps</h2><p>Run it</p></section></div>"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_api_toc() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        fs::write(
+            temp_dir.path().join("sub/a.fmemo"),
+            "# Top\n<desc>About</desc>\n```rust\nfn main() {}\n```\n## Release Plan\ntext\n",
+        )
+        .unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+        let get = |path: &str| warp::test::request().path(path).reply(&api);
+
+        let response = get("/api/files/sub/a.fmemo/toc").await;
         assert_eq!(response.status(), 200);
-        assert!(response.body().starts_with(b"<!DOCTYPE html"));
-        
-        // Test SPA fallback for unknown routes
-        let response = warp::test::request()
-            .method("GET")
-            .path("/some/spa/route")
-            .reply(&full_routes)
-            .await;
-        
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "path": "sub/a.fmemo",
+                "toc": [{
+                    "title": "Top", "slug": "top", "level": 1, "line": 1,
+                    "children": [{"title": "Release Plan", "slug": "release-plan", "level": 2, "line": 6, "children": []}]
+                }]
+            })
+        );
+        assert_eq!(get("/api/files/sub/b.fmemo/toc").await.status(), 404);
+        assert_eq!(get("/api/files/../a.fmemo/toc").await.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_api_print() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.fmemo"), "# Top\n![[b]]\n# Next\n").unwrap();
+        fs::write(temp_dir.path().join("b.fmemo"), "# Embedded\nFrom b").unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+        let get = |path: &str| warp::test::request().path(path).reply(&api);
+
+        let response = get("/api/files/a.fmemo/print").await;
         assert_eq!(response.status(), 200);
-        assert!(response.body().starts_with(b"<!DOCTYPE html"));
+        assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/html"));
+        let html = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(html.contains("<title>Top</title><style>"));
+        assert!(html.contains("<header class=\"print\">a.fmemo</header>"));
+        assert!(html.contains("<div class=\"embed\" data-target=\"b\"><section><h1 id=\"embedded\">Embedded</h1><p>From b</p>"));
+        assert_eq!(get("/api/files/missing.fmemo/print").await.status(), 404);
+        assert_eq!(get("/api/files/../a.fmemo/print").await.status(), 400);
     }
 
     #[tokio::test]
-    async fn test_cors_headers() {
+    async fn test_api_document_export() {
         let temp_dir = TempDir::new().unwrap();
-        create_test_fmemo_file(temp_dir.path(), "test", "# Test Content");
-        
-        let api_routes = create_api_routes(temp_dir.path().to_path_buf());
-        
+        fs::create_dir(temp_dir.path().join(".fmemo")).unwrap();
+        fs::write(temp_dir.path().join("a.fmemo"), "# Top\n").unwrap();
+        fs::write(
+            temp_dir.path().join(".fmemo/config.toml"),
+            format!("[pandoc]\npath = {:?}\n", temp_dir.path().join("no-pandoc").display().to_string()),
+        )
+        .unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+        let get = |path: &str| warp::test::request().path(path).reply(&api);
+
+        assert_eq!(get("/api/files/a.fmemo/export?format=docx").await.status(), 501);
+        assert_eq!(get("/api/files/a.fmemo/export?format=pdf").await.status(), 400);
+        assert_eq!(get("/api/files/a.fmemo/export").await.status(), 400);
+        assert_eq!(get("/api/files/../a.fmemo/export?format=odt").await.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_api_text() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.fmemo"), "# Top\nSome **bold** [[b|link]]\n## Sub\n- item\n").unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+        let get = |path: &str| warp::test::request().path(path).reply(&api);
+
+        let response = get("/api/files/a.fmemo/text").await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "text/plain; charset=utf-8");
+        assert_eq!(response.body().as_ref(), b"Top\n\nSome bold link\n\nSub\n\nitem\n");
+        let response = get("/api/files/a.fmemo/text?memo=sub").await;
+        assert_eq!(response.body().as_ref(), b"Sub\n\nitem\n");
+
+        let response = get("/api/files/a.fmemo/text?format=json").await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"path": "a.fmemo", "memos": [
+                {"slug": "top", "title": "Top", "line": 1, "text": "Top\n\nSome bold link"},
+                {"slug": "sub", "title": "Sub", "line": 3, "text": "Sub\n\nitem"}
+            ]})
+        );
+        assert_eq!(get("/api/files/a.fmemo/text?memo=nope").await.status(), 404);
+        assert_eq!(get("/api/files/../a.fmemo/text").await.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_api_large_file_is_mapped() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut content = String::from("# Export\n");
+        let paragraph = "Some *text* in a large export. ".repeat(12);
+        while (content.len() as u64) < crate::mapped::MAP_THRESHOLD {
+            content.push_str(&format!("## Entry\n{}\n\n{}\n", paragraph, paragraph));
+        }
+        fs::write(temp_dir.path().join("export.fmemo"), &content).unwrap();
+        assert!(crate::crypt::load_memo(&temp_dir.path().join("export.fmemo"), false).unwrap().is_mapped());
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+
+        let response = warp::test::request().path("/api/files/export.fmemo").reply(&api).await;
+        assert_eq!(response.status(), 200);
+        let body: FileContent = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body.memos[0].title(), "Export");
+        assert!(body.memos[0].children().len() > 5_000);
+
         let response = warp::test::request()
-            .method("GET")
-            .path("/api/root")
-            .header("Origin", "http://localhost:3000")
-            .reply(&api_routes)
+            .path("/api/files/export.fmemo?format=markdown")
+            .reply(&api)
             .await;
-        
+        assert_eq!(response.headers()["content-type"], "text/markdown; charset=utf-8");
+        assert_eq!(response.body(), content.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_api_health() {
+        let temp_dir = TempDir::new().unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+        let response = warp::test::request().path("/api/health").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["watcher"]["running"], false);
+
+        let hub = crate::watcher::WatcherHub::new().unwrap();
+        let (client_tx, mut client_rx) = tokio::sync::mpsc::unbounded_channel();
+        let clients: WebSocketClients = Arc::new(Mutex::new(vec![client_tx.into()]));
+        broadcast_watcher_status(hub.health(), clients);
+        let api = create_api_routes_with_options(
+            temp_dir.path().to_path_buf(),
+            ApiOptions { watcher: Some(hub.health().clone()), ..Default::default() },
+        );
+
+        hub.degrade(0, "inotify watch limit reached".to_string());
+        let response = warp::test::request().path("/api/health").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["watcher"]["error"], "inotify watch limit reached");
+        let message: serde_json::Value = serde_json::from_str(client_rx.recv().await.unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(message["type"], "watcher_degraded");
+        assert_eq!(message["error"], "inotify watch limit reached");
+
+        hub.reestablish().unwrap();
+        let response = warp::test::request().path("/api/health").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["watcher"]["restarts"], 1);
+        let message: serde_json::Value = serde_json::from_str(client_rx.recv().await.unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(message, serde_json::json!({"type": "watcher_restored", "restarts": 1}));
+    }
+
+    #[tokio::test]
+    async fn test_api_memo_markdown() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        fs::write(
+            temp_dir.path().join("sub/a.fmemo"),
+            "# Top\n## Release Plan\ntext\n### Steps\n- one\n## Other\n",
+        )
+        .unwrap();
+        let api = create_api_routes(temp_dir.path().to_path_buf());
+        let get = |path: &str| warp::test::request().path(path).reply(&api);
+
+        let response = get("/api/files/sub/a.fmemo/memos/release-plan/markdown").await;
         assert_eq!(response.status(), 200);
-        
-        // Check CORS headers are present
-        let headers = response.headers();
-        assert!(headers.contains_key("access-control-allow-origin"));
+        assert_eq!(response.headers()["content-type"], "text/markdown; charset=utf-8");
+        assert_eq!(response.body(), "# Release Plan\ntext\n## Steps\n- one\n");
+
+        assert_eq!(get("/api/files/sub/a.fmemo/memos/missing/markdown").await.status(), 404);
+        assert_eq!(get("/api/files/sub/b.fmemo/memos/top/markdown").await.status(), 404);
+        assert_eq!(get("/api/files/.hidden/a.fmemo/memos/top/markdown").await.status(), 400);
     }
 
     #[tokio::test]
-    async fn test_api_file_endpoint_frontend_compatible() {
+    async fn test_api_comments() {
         let temp_dir = TempDir::new().unwrap();
-        let content = r#"
-# Test Function
-<desc>A test function</desc>
+        fs::write(temp_dir.path().join("a.fmemo"), "# Top\n## Release Plan\ntext\n").unwrap();
+        let (client_tx, mut client_rx) = tokio::sync::mpsc::unbounded_channel();
+        let clients: WebSocketClients = Arc::new(Mutex::new(vec![client_tx.into()]));
+        let api = create_api_routes_with_options(
+            temp_dir.path().to_path_buf(),
+            ApiOptions { clients: Some(clients), ..Default::default() },
+        );
+        let post = |path: &str, body: serde_json::Value| {
+            warp::test::request().method("POST").path(path).json(&body).reply(&api)
+        };
 
-This is content.
+        let response = post("/api/files/a.fmemo/memos/release-plan/comments", serde_json::json!({"body": "Ship it?", "author": "kai"})).await;
+        assert_eq!(response.status(), 201);
+        let comment: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(comment["author"], "kai");
+        let message = client_rx.recv().await.unwrap();
+        let message: serde_json::Value = serde_json::from_str(message.to_str().unwrap()).unwrap();
+        assert_eq!(message["type"], "comment_added");
+        assert_eq!(message["path"], "a.fmemo");
+        assert_eq!(message["comment"], comment);
 
-```rust
-fn test() {}
-```
-"#;
-        create_test_fmemo_file(temp_dir.path(), "test", content);
+        let response = warp::test::request().path("/api/files/a.fmemo/memos/release-plan/comments").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["comments"], serde_json::json!([comment]));
+        assert_eq!(fs::read_to_string(temp_dir.path().join("a.fmemo")).unwrap(), "# Top\n## Release Plan\ntext\n");
+
+        assert_eq!(post("/api/files/a.fmemo/memos/missing/comments", serde_json::json!({"body": "Hm"})).await.status(), 404);
+        assert_eq!(post("/api/files/a.fmemo/memos/top/comments", serde_json::json!({"body": " "})).await.status(), 400);
+    }
 
+    #[tokio::test]
+    async fn test_api_import() {
+        use std::io::Write;
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.md"), "# Old").unwrap();
         let api = create_api_routes(temp_dir.path().to_path_buf());
 
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, content) in [("a.md", "# A"), ("sub/b.fmemo", "# B")] {
+            writer.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        let archive = writer.finish().unwrap().into_inner();
+        let mut body = b"--BOUNDARY\r\nContent-Disposition: form-data; name=\"file\"; filename=\"notes.zip\"\r\n\
+Content-Type: application/zip\r\n\r\n"
+            .to_vec();
+        body.extend_from_slice(&archive);
+        body.extend_from_slice(b"\r\n--BOUNDARY--\r\n");
+        let import = |query: &str| {
+            warp::test::request()
+                .method("POST")
+                .path(&format!("/api/import{}", query))
+                .header("content-type", "multipart/form-data; boundary=BOUNDARY")
+                .body(body.clone())
+                .reply(&api)
+        };
+
+        let response = import("").await;
+        assert_eq!(response.status(), 200);
+        let report: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(report["imported"][0]["path"], "a-1.md");
+        assert_eq!(report["imported"][1]["path"], "sub/b.fmemo");
+        assert_eq!(fs::read_to_string(temp_dir.path().join("a.md")).unwrap(), "# Old");
+
+        let response = import("?on_conflict=skip").await;
+        let report: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(report["skipped"], serde_json::json!(["a.md", "sub/b.fmemo"]));
+
+        assert_eq!(import("?on_conflict=merge").await.status(), 400);
+        assert_eq!(import("?dir=../up").await.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_index_routes() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.fmemo"), "# Rust\nborrowing <tag>lang</tag>").unwrap();
+        let indexer = crate::indexer::Indexer::new(temp_dir.path());
+        let routes = create_index_routes(indexer.clone());
+        let get = |path: &str| warp::test::request().path(path).reply(&routes);
+
+        let body: serde_json::Value = serde_json::from_slice(get("/api/index").await.body()).unwrap();
+        assert_eq!(body["files"], 0);
+        assert!(body["indexed_at"].is_null());
+
+        indexer.reindex().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(get("/api/search?q=borrow").await.body()).unwrap();
+        assert_eq!(body["hits"][0]["title"], "Rust");
+        let body: serde_json::Value = serde_json::from_slice(get("/api/graph?memos=false").await.body()).unwrap();
+        assert_eq!(body["nodes"].as_array().unwrap().len(), 2);
+
+        fs::write(temp_dir.path().join("b.fmemo"), "# Go\n<tag>lang</tag>").unwrap();
+        indexer.file_changed("b.fmemo");
+        let body: serde_json::Value = serde_json::from_slice(get("/api/files/a.fmemo/related").await.body()).unwrap();
+        assert_eq!(body["related"][0]["file"], "b.fmemo");
+        assert_eq!(body["related"][0]["shared_tags"], serde_json::json!(["lang"]));
+        assert_eq!(get("/api/files/missing.fmemo/related").await.status(), 404);
+
+        fs::write(temp_dir.path().join("c.fmemo"), "# Board\n## Write docs\n<status>review</status>\n## [ ] Test").unwrap();
+        indexer.file_changed("c.fmemo");
+        let body: serde_json::Value = serde_json::from_slice(get("/api/board?columns=todo,review").await.body()).unwrap();
+        assert_eq!(body["columns"][0]["cards"][0]["title"], "Test");
+        assert_eq!(body["columns"][1]["cards"][0]["slug"], "write-docs");
+
+        fs::create_dir(temp_dir.path().join("archive")).unwrap();
+        fs::write(temp_dir.path().join("archive/a.fmemo"), "# Rust\nborrowing <tag>lang</tag>").unwrap();
+        indexer.file_changed("archive/a.fmemo");
+        let body: serde_json::Value = serde_json::from_slice(get("/api/duplicates").await.body()).unwrap();
+        assert!(body["files"].as_array().unwrap().is_empty());
+        let body: serde_json::Value = serde_json::from_slice(get("/api/duplicates?archived=true").await.body()).unwrap();
+        assert_eq!(body["files"][0]["items"][0]["file"], "a.fmemo");
+        assert_eq!(body["files"][0]["items"][1]["file"], "archive/a.fmemo");
+        assert_eq!(get("/api/duplicates?threshold=2").await.status(), 400);
+
+        let response = warp::test::request().method("POST").path("/api/reindex").reply(&routes).await;
+        assert!(response.status() == 202 || response.status() == 409);
+    }
+
+    #[tokio::test]
+    async fn test_semantic_search() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.fmemo"), "# Garage\nfix the car engine").unwrap();
+        fs::write(temp_dir.path().join("b.fmemo"), "# Tonight\npasta for dinner").unwrap();
+        let get = |routes, path: &str| warp::test::request().path(path).reply(routes);
+
+        let plain = create_index_routes(crate::indexer::Indexer::new(temp_dir.path()));
+        assert_eq!(get(&plain, "/api/search?q=car&mode=semantic").await.status(), 501);
+        assert_eq!(get(&plain, "/api/search?q=car&mode=fuzzy").await.status(), 400);
+
+        let embedder = crate::embeddings::tests::TopicEmbedder { calls: Default::default() };
+        let embeddings = crate::embeddings::EmbeddingIndex::start(std::sync::Arc::new(embedder));
+        let indexer = crate::indexer::Indexer::new(temp_dir.path()).embeddings(embeddings.clone());
+        indexer.reindex().unwrap();
+        // Memos are embedded in the background
+        for _ in 0..500 {
+            if embeddings.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let routes = create_index_routes(indexer);
+        let response = get(&routes, "/api/search?q=automobile&mode=semantic&limit=1").await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["hits"].as_array().unwrap().len(), 1);
+        assert_eq!(body["hits"][0]["file"], "a.fmemo");
+        // "automobile" appears nowhere, so keyword search finds nothing
+        let body: serde_json::Value =
+            serde_json::from_slice(get(&routes, "/api/search?q=automobile").await.body()).unwrap();
+        assert_eq!(body["hits"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_api_encrypted_memos() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let api = create_api_routes(root.to_path_buf());
+        let indexer = crate::indexer::Indexer::new(root);
+        let vault = create_index_routes(indexer);
+        let unlock = |body: serde_json::Value| {
+            warp::test::request().method("POST").path("/api/unlock").json(&body)
+        };
+        // Unlocking doesn't set up a vault, and the server reads no key files for clients
+        assert_eq!(unlock(serde_json::json!({"passphrase": "open sesame"})).reply(&vault).await.status(), 404);
+        assert!(!crate::crypt::vault_path(root).exists());
+        crate::crypt::Vault::create(root, b"open sesame", 10).unwrap();
+        assert_eq!(unlock(serde_json::json!({"key_file": "/dev/zero"})).reply(&vault).await.status(), 400);
+        let write = warp::test::request()
+            .method("PUT")
+            .path("/api/file/secret.fmemox")
+            .json(&serde_json::json!({"content": "# Secret\nthe code is 1234"}));
+
+        assert_eq!(write.reply(&api).await.status(), 403);
+        assert_eq!(unlock(serde_json::json!({"passphrase": "wrong"})).reply(&vault).await.status(), 403);
+        assert_eq!(unlock(serde_json::json!({})).reply(&vault).await.status(), 400);
+        assert_eq!(unlock(serde_json::json!({"passphrase": "open sesame"})).reply(&vault).await.status(), 200);
+        let status: serde_json::Value =
+            serde_json::from_slice(warp::test::request().path("/api/vault").reply(&vault).await.body()).unwrap();
+        assert_eq!(status, serde_json::json!({"initialized": true, "unlocked": true}));
+
         let response = warp::test::request()
-            .method("GET")
-            .path("/api/file/test.fmemo")
+            .method("PUT")
+            .path("/api/file/secret.fmemox")
+            .json(&serde_json::json!({"content": "# Secret\nthe code is 1234"}))
             .reply(&api)
             .await;
+        assert_eq!(response.status(), 200);
+        let stored = fs::read(root.join("secret.fmemox")).unwrap();
+        assert!(!String::from_utf8_lossy(&stored).contains("1234"));
 
+        let response = warp::test::request().path("/api/files/secret.fmemox").reply(&api).await;
         assert_eq!(response.status(), 200);
-        
         let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
-        assert_eq!(body["path"].as_str().unwrap(), "test.fmemo");
-        assert!(body["content"].as_str().is_some());
-        assert!(body["memos"].as_array().is_some());
-        
-        let memos = body["memos"].as_array().unwrap();
-        assert_eq!(memos.len(), 1);
-        assert_eq!(memos[0]["title"].as_str().unwrap(), "Test Function");
+        assert_eq!(body["memos"][0]["title"], "Secret");
+        let response = warp::test::request().path("/api/files").reply(&api).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["files"][0]["title"], "Secret");
+
+        // Drafts would be stored in plaintext
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/api/files/secret.fmemox/draft")
+            .json(&serde_json::json!({"content": "# Secret"}))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), 400);
+
+        let response = warp::test::request().method("POST").path("/api/lock").reply(&vault).await;
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["was_unlocked"], true);
+        let response = warp::test::request().path("/api/files/secret.fmemox").reply(&api).await;
+        assert_eq!(response.status(), 403);
     }
 }
@@ -0,0 +1,102 @@
+//! `sitemap.xml` of a published vault, so search engines find every memo page. A server in
+//! publish mode serves it as `/sitemap.xml`, pointing at each file's HTML page.
+//!
+//! Encrypted memos are left out. Page URLs are absolute: `[publish] url` from the config, or
+//! else the address the request was made to.
+
+use std::io;
+use std::path::Path;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+
+use crate::render::escape_html;
+use crate::server::list_memo_files;
+
+/// Characters escaped in a path segment of a page URL
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// A memo file with a page on the site
+#[derive(Debug, Clone, PartialEq)]
+pub struct SitemapPage {
+    /// Path relative to the root
+    pub path: String,
+    pub modified: DateTime<Utc>,
+}
+
+/// Every memo file below `root` that gets a page
+pub fn pages(root: &Path) -> io::Result<Vec<SitemapPage>> {
+    let mut pages = Vec::new();
+    for path in list_memo_files(root)? {
+        if crate::crypt::is_encrypted(Path::new(&path)) {
+            continue;
+        }
+        let modified = std::fs::metadata(root.join(&path))?.modified()?.into();
+        pages.push(SitemapPage { path, modified });
+    }
+    Ok(pages)
+}
+
+/// `path` with each segment percent-encoded, for use in a URL
+pub fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The sitemap with one `<url>` per page; `url` gives a page's absolute URL
+pub fn to_xml(pages: &[SitemapPage], url: impl Fn(&str) -> String) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for page in pages {
+        xml.push_str(&format!(
+            "  <url><loc>{}</loc><lastmod>{}</lastmod></url>\n",
+            escape_html(&url(&page.path)),
+            page.modified.to_rfc3339_opts(SecondsFormat::Secs, true)
+        ));
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_sitemap() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("trips")).unwrap();
+        fs::write(root.join("trips/kyoto & nara.fmemo"), "# Kyoto").unwrap();
+        fs::write(root.join("secret.fmemox"), "locked").unwrap();
+
+        let pages = pages(root).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].path, "trips/kyoto & nara.fmemo");
+
+        let page = SitemapPage {
+            path: pages[0].path.clone(),
+            modified: DateTime::parse_from_rfc3339("2024-05-01T09:30:00+02:00")
+                .unwrap()
+                .to_utc(),
+        };
+        let xml = to_xml(&[page], |path| {
+            format!("https://notes.example.com/{}?a=1&b=2", encode_path(path))
+        });
+        assert!(xml.contains(
+            "<url><loc>https://notes.example.com/trips/kyoto%20%26%20nara.fmemo?a=1&amp;b=2</loc>\
+             <lastmod>2024-05-01T07:30:00Z</lastmod></url>"
+        ));
+        assert!(xml.ends_with("</urlset>\n"));
+    }
+}
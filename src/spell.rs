@@ -0,0 +1,581 @@
+//! Spellchecking of memo text against hunspell dictionaries, for the `spelling` lint rule
+//! and `GET /api/files/{path}/spelling`.
+//!
+//! A language is a `<lang>.dic` word list with an optional `<lang>.aff` of prefix and
+//! suffix rules (`PFX`/`SFX`, with `FLAG long`/`num` and cross products). Compounding,
+//! replacement tables and the other hunspell extras are not supported. Words in
+//! `.fmemo/dictionary.txt` are always accepted. Code blocks, math blocks, inline code,
+//! URLs, link targets, wiki-link targets and tags are never checked.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::{FMEMO_DIR, SpellcheckConfig};
+use crate::parser::normalize_source;
+
+/// Most suggestions given for a word
+const MAX_SUGGESTIONS: usize = 5;
+
+/// Where dictionaries are looked up after the configured directories
+const SYSTEM_DICTIONARY_DIRS: [&str; 2] = ["/usr/share/hunspell", "/usr/share/myspell"];
+
+/// The vault's own words, one per line
+pub fn custom_dictionary_path(root: &Path) -> PathBuf {
+    root.join(FMEMO_DIR).join("dictionary.txt")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FlagMode {
+    /// One character per flag
+    Char,
+    /// Two characters per flag
+    Long,
+    /// Comma separated numbers
+    Num,
+}
+
+impl FlagMode {
+    fn parse(self, flags: &str) -> Vec<String> {
+        match self {
+            FlagMode::Char => flags.chars().map(String::from).collect(),
+            FlagMode::Long => {
+                let chars: Vec<char> = flags.chars().collect();
+                chars.chunks(2).map(|pair| pair.iter().collect()).collect()
+            }
+            FlagMode::Num => flags
+                .split(',')
+                .map(str::trim)
+                .filter(|flag| !flag.is_empty())
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+/// One character of an affix condition
+#[derive(Debug, Clone, PartialEq)]
+enum CharClass {
+    Any,
+    Set { negated: bool, chars: Vec<char> },
+}
+
+impl CharClass {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            CharClass::Any => true,
+            CharClass::Set { negated, chars } => chars.contains(&c) != *negated,
+        }
+    }
+}
+
+fn parse_condition(condition: &str) -> Vec<CharClass> {
+    let mut classes = Vec::new();
+    let mut chars = condition.chars();
+    while let Some(c) = chars.next() {
+        classes.push(match c {
+            '.' => CharClass::Any,
+            '[' => {
+                let mut set: Vec<char> = chars.by_ref().take_while(|&c| c != ']').collect();
+                let negated = set.first() == Some(&'^');
+                if negated {
+                    set.remove(0);
+                }
+                CharClass::Set {
+                    negated,
+                    chars: set,
+                }
+            }
+            c => CharClass::Set {
+                negated: false,
+                chars: vec![c],
+            },
+        });
+    }
+    classes
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Affix {
+    flag: String,
+    strip: String,
+    add: String,
+    /// Matched against the start (prefixes) or end (suffixes) of the stem
+    condition: Vec<CharClass>,
+    /// May combine with an affix of the other kind
+    cross: bool,
+}
+
+impl Affix {
+    fn condition_matches(&self, stem: &[char], at_start: bool) -> bool {
+        if stem.len() < self.condition.len() {
+            return false;
+        }
+        let offset = if at_start {
+            0
+        } else {
+            stem.len() - self.condition.len()
+        };
+        self.condition
+            .iter()
+            .zip(&stem[offset..])
+            .all(|(class, &c)| class.matches(c))
+    }
+
+    /// The stem `word` comes from with this prefix removed
+    fn strip_prefix(&self, word: &str) -> Option<String> {
+        let rest = word.strip_prefix(self.add.as_str())?;
+        let stem = format!("{}{}", self.strip, rest);
+        let chars: Vec<char> = stem.chars().collect();
+        (!rest.is_empty() && self.condition_matches(&chars, true)).then_some(stem)
+    }
+
+    /// The stem `word` comes from with this suffix removed
+    fn strip_suffix(&self, word: &str) -> Option<String> {
+        let rest = word.strip_suffix(self.add.as_str())?;
+        let stem = format!("{}{}", rest, self.strip);
+        let chars: Vec<char> = stem.chars().collect();
+        (!rest.is_empty() && self.condition_matches(&chars, false)).then_some(stem)
+    }
+}
+
+/// A hunspell dictionary: stems with their affix flags, and the affix rules
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dictionary {
+    words: HashMap<String, Vec<String>>,
+    prefixes: Vec<Affix>,
+    suffixes: Vec<Affix>,
+    /// Characters tried when suggesting, most common first (`TRY`)
+    try_chars: String,
+}
+
+impl Dictionary {
+    /// Parse the contents of a `.aff` and a `.dic` file
+    pub fn parse(aff: &str, dic: &str) -> Self {
+        let mut dictionary = Dictionary::default();
+        let mut mode = FlagMode::Char;
+        let mut cross: HashMap<(bool, String), bool> = HashMap::new();
+
+        for line in aff.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["FLAG", "long", ..] => mode = FlagMode::Long,
+                ["FLAG", "num", ..] => mode = FlagMode::Num,
+                ["TRY", chars, ..] => dictionary.try_chars = chars.to_string(),
+                [kind @ ("PFX" | "SFX"), flag, yes_no @ ("Y" | "N"), count]
+                    if count.parse::<usize>().is_ok() =>
+                {
+                    cross.insert((*kind == "PFX", flag.to_string()), *yes_no == "Y");
+                }
+                [kind @ ("PFX" | "SFX"), flag, strip, add, rest @ ..] => {
+                    let is_prefix = *kind == "PFX";
+                    let empty_or = |text: &str| {
+                        if text == "0" {
+                            String::new()
+                        } else {
+                            text.to_string()
+                        }
+                    };
+                    // Continuation flags after the affix aren't supported
+                    let add = add.split('/').next().unwrap_or_default();
+                    let affix = Affix {
+                        flag: flag.to_string(),
+                        strip: empty_or(strip),
+                        add: empty_or(add),
+                        condition: parse_condition(rest.first().copied().unwrap_or(".")),
+                        cross: cross
+                            .get(&(is_prefix, flag.to_string()))
+                            .copied()
+                            .unwrap_or(false),
+                    };
+                    if is_prefix {
+                        dictionary.prefixes.push(affix);
+                    } else {
+                        dictionary.suffixes.push(affix);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut lines = dic.lines();
+        // The first line is the (approximate) word count
+        let first = lines
+            .next()
+            .filter(|line| line.trim().parse::<usize>().is_err());
+        for line in first.into_iter().chain(lines) {
+            // Morphological fields follow after whitespace
+            let entry = line.split(['\t', ' ']).next().unwrap_or_default();
+            if entry.is_empty() {
+                continue;
+            }
+            let (word, flags) = entry.split_once('/').unwrap_or((entry, ""));
+            dictionary
+                .words
+                .entry(word.to_string())
+                .or_default()
+                .extend(mode.parse(flags));
+        }
+        dictionary
+    }
+
+    fn has_flag(&self, stem: &str, flag: &str) -> bool {
+        self.words
+            .get(stem)
+            .is_some_and(|flags| flags.iter().any(|f| f == flag))
+    }
+
+    fn suffixed(&self, word: &str, prefix: Option<&Affix>) -> bool {
+        self.suffixes.iter().any(|suffix| {
+            suffix.strip_suffix(word).is_some_and(|stem| match prefix {
+                None => self.has_flag(&stem, &suffix.flag),
+                Some(prefix) => {
+                    suffix.cross
+                        && self.has_flag(&stem, &suffix.flag)
+                        && self.has_flag(&stem, &prefix.flag)
+                }
+            })
+        })
+    }
+
+    /// Whether the word, exactly as written, is in the dictionary or derived from a stem
+    pub fn contains(&self, word: &str) -> bool {
+        if self.words.contains_key(word) || self.suffixed(word, None) {
+            return true;
+        }
+        self.prefixes.iter().any(|prefix| {
+            prefix.strip_prefix(word).is_some_and(|stem| {
+                self.has_flag(&stem, &prefix.flag)
+                    || (prefix.cross && self.suffixed(&stem, Some(prefix)))
+            })
+        })
+    }
+}
+
+/// A word no dictionary knows, with its 1-based line and column (in characters)
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct Misspelling {
+    pub line: usize,
+    pub column: usize,
+    pub word: String,
+    pub suggestions: Vec<String>,
+}
+
+/// The dictionaries of the configured languages plus the vault's own words
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Spellchecker {
+    dictionaries: Vec<Dictionary>,
+    custom: HashSet<String>,
+}
+
+impl Spellchecker {
+    pub fn new(dictionaries: Vec<Dictionary>, custom: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            dictionaries,
+            custom: custom.into_iter().collect(),
+        }
+    }
+
+    /// Load the configured languages' dictionaries and the root's custom dictionary. A
+    /// language without a `.dic` file in any dictionary directory is `NotFound`.
+    pub fn load(root: &Path, config: &SpellcheckConfig) -> io::Result<Self> {
+        let dirs: Vec<PathBuf> = config
+            .dictionaries
+            .iter()
+            .map(|dir| root.join(dir))
+            .chain([root.join(FMEMO_DIR).join("dictionaries")])
+            .chain(SYSTEM_DICTIONARY_DIRS.iter().map(PathBuf::from))
+            .collect();
+
+        let mut dictionaries = Vec::new();
+        for language in &config.languages {
+            let Some(dic) = dirs
+                .iter()
+                .map(|dir| dir.join(format!("{}.dic", language)))
+                .find(|path| path.is_file())
+            else {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No dictionary found for language '{}'", language),
+                ));
+            };
+            let aff = std::fs::read_to_string(dic.with_extension("aff")).unwrap_or_default();
+            dictionaries.push(Dictionary::parse(&aff, &std::fs::read_to_string(&dic)?));
+        }
+
+        let custom = match std::fs::read_to_string(custom_dictionary_path(root)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let custom = custom
+            .lines()
+            .map(str::trim)
+            .filter(|word| !word.is_empty() && !word.starts_with('#'))
+            .map(String::from);
+        Ok(Self::new(dictionaries, custom))
+    }
+
+    /// The root's spellchecker when `[spellcheck]` is enabled in its config
+    pub fn for_root(root: &Path) -> io::Result<Option<Self>> {
+        let config = crate::config::load_config(root)?.spellcheck;
+        if !config.enabled {
+            return Ok(None);
+        }
+        Self::load(root, &config).map(Some)
+    }
+
+    /// Whether any dictionary knows the word. Capitalized and all-caps words are also
+    /// looked up in lower case.
+    pub fn check(&self, word: &str) -> bool {
+        let lower = word.to_lowercase();
+        let mut candidates = vec![word.to_string()];
+        if lower != word {
+            candidates.push(lower.clone());
+            if word.chars().all(|c| !c.is_lowercase()) {
+                let mut chars = lower.chars();
+                candidates.extend(
+                    chars
+                        .next()
+                        .map(|first| first.to_uppercase().chain(chars).collect()),
+                );
+            }
+        }
+        candidates.iter().any(|candidate| {
+            self.custom.contains(candidate)
+                || self
+                    .dictionaries
+                    .iter()
+                    .any(|dictionary| dictionary.contains(candidate))
+        })
+    }
+
+    /// Known words one edit (deletion, swap, replacement or insertion) away
+    pub fn suggest(&self, word: &str) -> Vec<String> {
+        let chars: Vec<char> = word.chars().collect();
+        let mut alphabet: Vec<char> = self
+            .dictionaries
+            .iter()
+            .flat_map(|dictionary| dictionary.try_chars.chars())
+            .collect();
+        if alphabet.is_empty() {
+            alphabet = ('a'..='z').collect();
+        }
+
+        let mut edits: Vec<Vec<char>> = Vec::new();
+        for i in 0..chars.len() {
+            let mut deleted = chars.clone();
+            deleted.remove(i);
+            edits.push(deleted);
+            if i + 1 < chars.len() {
+                let mut swapped = chars.clone();
+                swapped.swap(i, i + 1);
+                edits.push(swapped);
+            }
+        }
+        for i in 0..=chars.len() {
+            for &c in &alphabet {
+                if i < chars.len() && chars[i] != c {
+                    let mut replaced = chars.clone();
+                    replaced[i] = c;
+                    edits.push(replaced);
+                }
+                let mut inserted = chars.clone();
+                inserted.insert(i, c);
+                edits.push(inserted);
+            }
+        }
+
+        let mut suggestions: Vec<String> = Vec::new();
+        for edit in edits {
+            let candidate: String = edit.into_iter().collect();
+            if !candidate.is_empty() && !suggestions.contains(&candidate) && self.check(&candidate)
+            {
+                suggestions.push(candidate);
+                if suggestions.len() == MAX_SUGGESTIONS {
+                    break;
+                }
+            }
+        }
+        suggestions
+    }
+
+    /// Unknown words in a memo's source, skipping code, math, URLs, link targets and tags
+    pub fn misspellings(&self, content: &str) -> Vec<Misspelling> {
+        let content = normalize_source(content);
+        let mut misspellings = Vec::new();
+        let mut fence: Option<String> = None;
+        let mut in_math = false;
+
+        for (index, line) in content.lines().enumerate() {
+            let trimmed = line.trim_start();
+            if let Some(open) = &fence {
+                if trimmed.starts_with(open.as_str()) {
+                    fence = None;
+                }
+                continue;
+            }
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                let marker = trimmed.chars().next().unwrap_or('`');
+                fence = Some(trimmed.chars().take_while(|&c| c == marker).collect());
+                continue;
+            }
+            if trimmed.starts_with("$$") {
+                in_math = !(in_math || trimmed.len() > 2 && trimmed.trim_end().ends_with("$$"));
+                continue;
+            }
+            if in_math {
+                continue;
+            }
+
+            for (column, word) in words(line) {
+                if !self.check(&word) {
+                    misspellings.push(Misspelling {
+                        line: index + 1,
+                        column,
+                        suggestions: self.suggest(&word),
+                        word,
+                    });
+                }
+            }
+        }
+        misspellings
+    }
+}
+
+/// Mark the characters of a line that aren't prose: inline code, link targets, wiki-link
+/// targets, tags and autolinks, and whitespace-separated chunks that look like URLs or
+/// email addresses
+fn skipped_chars(chars: &[char]) -> Vec<bool> {
+    let mut skip = vec![false; chars.len()];
+    let find = |from: usize, needle: &[char]| {
+        (from..chars.len().saturating_sub(needle.len() - 1))
+            .find(|&i| chars[i..].starts_with(needle))
+    };
+
+    let mut i = 0;
+    while i < chars.len() {
+        let end = match chars[i] {
+            '`' => find(i + 1, &['`']),
+            '[' if chars.get(i + 1) == Some(&'[') => {
+                // Keep the label of [[target|label]]
+                find(i + 2, &[']', ']']).map(|close| {
+                    let pipe = (i + 2..close).find(|&j| chars[j] == '|');
+                    pipe.unwrap_or(close + 1)
+                })
+            }
+            ']' if chars.get(i + 1) == Some(&'(') => find(i + 2, &[')']),
+            '<' if chars
+                .get(i + 1)
+                .is_some_and(|&c| c.is_ascii_alphabetic() || c == '/') =>
+            {
+                find(i + 1, &['>'])
+            }
+            _ => None,
+        };
+        match end {
+            Some(end) => {
+                skip[i..=end].iter_mut().for_each(|s| *s = true);
+                i = end + 1;
+            }
+            None => i += 1,
+        }
+    }
+
+    // Chunks end at whitespace and at what's already skipped, so `[label](url)` keeps its label
+    let marked = skip.clone();
+    let mut start = 0;
+    for end in
+        (0..=chars.len()).filter(|&i| i == chars.len() || chars[i].is_whitespace() || marked[i])
+    {
+        let chunk: String = chars[start..end].iter().collect();
+        if chunk.contains("://") || chunk.contains('@') || chunk.starts_with("www.") {
+            skip[start..end].iter_mut().for_each(|s| *s = true);
+        }
+        start = end + 1;
+    }
+    skip
+}
+
+/// Words of a line that aren't skipped, with their 1-based columns. Apostrophes inside a
+/// word belong to it; words with digits and single letters are left out.
+fn words(line: &str) -> Vec<(usize, String)> {
+    let chars: Vec<char> = line.chars().collect();
+    let skip = skipped_chars(&chars);
+    let is_word_char = |i: usize| {
+        chars[i].is_alphanumeric()
+            || (matches!(chars[i], '\'' | '’')
+                && i > 0
+                && chars[i - 1].is_alphabetic()
+                && chars.get(i + 1).is_some_and(|c| c.is_alphabetic()))
+    };
+
+    let mut words = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if skip[i] || !is_word_char(i) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < chars.len() && !skip[i] && is_word_char(i) {
+            i += 1;
+        }
+        let word: String = chars[start..i].iter().collect();
+        if word.chars().count() > 1 && !word.chars().any(|c| c.is_numeric()) {
+            words.push((start + 1, word));
+        }
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dictionary, Spellchecker, words};
+
+    const AFF: &str = "SET UTF-8\nTRY esianrtolcdugmphbyfvkwz\n\nPFX U Y 1\nPFX U 0 un .\n\nSFX S Y 2\nSFX S y ies [^aeiou]y\nSFX S 0 s [^y]\n";
+    const DIC: &str = "7\nthe\nsee\nmemo/S\nquery/S\ndo/U\nlock/US\nnote\n";
+
+    #[test]
+    fn test_dictionary_affixes() {
+        let dictionary = Dictionary::parse(AFF, DIC);
+        for word in ["memo", "memos", "queries", "undo", "unlocks", "lock"] {
+            assert!(dictionary.contains(word), "{}", word);
+        }
+        for word in ["querys", "notes", "unnote", "Memo"] {
+            assert!(!dictionary.contains(word), "{}", word);
+        }
+
+        let long = Dictionary::parse("FLAG long\nSFX Aa N 1\nSFX Aa 0 ed .\n", "walk/AaBb\n");
+        assert!(long.contains("walked"));
+    }
+
+    #[test]
+    fn test_misspellings() {
+        let speller = Spellchecker::new(vec![Dictionary::parse(AFF, DIC)], ["fmemo".to_string()]);
+        assert!(speller.check("Memos"));
+        assert!(speller.check("THE"));
+        assert!(speller.check("fmemo"));
+        assert_eq!(speller.suggest("mmeo"), ["memo"]);
+
+        assert_eq!(
+            words("Teh `code` [link](https://x.io/pth) [[Trgt|note]] <status>don't</status> a v2"),
+            [
+                (1, "Teh".to_string()),
+                (13, "link".to_string()),
+                (44, "note".to_string()),
+                (59, "don't".to_string())
+            ]
+        );
+
+        let content = "# The memos\nTeh note, see https://example.com/qwerty\n```rust\nlet xyzzy = 1;\n```\n$$\nfrac\n$$\nThe memoes";
+        let misspellings = speller.misspellings(content);
+        let found: Vec<(usize, usize, &str)> = misspellings
+            .iter()
+            .map(|m| (m.line, m.column, m.word.as_str()))
+            .collect();
+        assert_eq!(found, [(2, 1, "Teh"), (9, 5, "memoes")]);
+        assert_eq!(misspellings[0].suggestions, ["The"]);
+        assert_eq!(misspellings[1].suggestions, ["memos"]);
+    }
+}
@@ -0,0 +1,121 @@
+//! Auto-stamping of `<created>` / `<updated>` tags when a file is written through the API.
+
+use std::collections::HashMap;
+
+use chrono::SecondsFormat;
+
+use crate::diff::sections;
+use crate::parser::{extract_tag_values, normalize_source};
+use crate::schema::Timestamp;
+
+/// Compare `updated` against the `previous` version of a file and stamp every memo whose
+/// own section changed with `<updated>now</updated>`. Memos that didn't exist before also
+/// get `<created>now</created>` unless they already carry one.
+///
+/// Memos are matched by their heading path (titles of the memo and its ancestors).
+/// Only the stamp tags themselves are ignored when comparing sections.
+pub fn stamp_changed_memos(previous: &str, updated: &str, now: Timestamp) -> String {
+    let previous = normalize_source(previous);
+    let updated = normalize_source(updated);
+    let previous_lines: Vec<&str> = previous.split('\n').collect();
+    let mut lines: Vec<String> = updated.split('\n').map(str::to_string).collect();
+
+    let previous_sections = sections(&previous);
+    let previous_sections: HashMap<_, _> = previous_sections
+        .iter()
+        .map(|section| {
+            let text = comparable(&previous_lines[section.start_line - 1..section.end_line]);
+            (section.key(), text)
+        })
+        .collect();
+
+    let now = now.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let mut new_sections = sections(&updated);
+    // Bottom-up so inserted lines don't move the sections still to be stamped
+    new_sections.reverse();
+    for new_section in &new_sections {
+        let (start, end) = (new_section.start_line, new_section.end_line);
+        let section = &lines[start - 1..end];
+        let is_new = match previous_sections.get(&new_section.key()) {
+            Some(previous) if *previous == comparable(section) => continue,
+            Some(_) => false,
+            None => true,
+        };
+        let has_created = section.iter().any(|line| line.contains("<created>"));
+        set_tag(&mut lines, start, end, "updated", &now);
+        if is_new && !has_created {
+            set_tag(&mut lines, start, start, "created", &now);
+        }
+    }
+    lines.join("\n")
+}
+
+/// Section text with stamp tags removed, so re-stamping alone isn't a change
+fn comparable<S: AsRef<str>>(lines: &[S]) -> String {
+    let text = lines
+        .iter()
+        .map(AsRef::as_ref)
+        .collect::<Vec<_>>()
+        .join("\n");
+    let (text, _) = extract_tag_values(&text, "updated");
+    text.trim_end().to_string()
+}
+
+/// Replace the value of a single-line `<tag>` within lines `start..=end`,
+/// or insert the tag right below the heading
+fn set_tag(lines: &mut Vec<String>, start: usize, end: usize, tag: &str, value: &str) {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    for line in &mut lines[start - 1..end] {
+        if let Some(from) = line.find(&open)
+            && let Some(to) = line[from..].find(&close)
+        {
+            line.replace_range(from + open.len()..from + to, value);
+            return;
+        }
+    }
+    lines.insert(start, format!("{}{}{}", open, value, close));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::stamp_changed_memos;
+    use crate::parser::{parse_memo, parse_timestamp};
+
+    fn now() -> crate::schema::Timestamp {
+        parse_timestamp("2024-05-01T12:00:00Z").unwrap()
+    }
+
+    #[test]
+    fn test_stamps_only_changed_memos() {
+        let previous = "# A\nalpha\n## B\nbeta\n# C\ngamma\n";
+        let updated = "# A\nalpha\n## B\nbeta changed\n# C\ngamma\n";
+        let stamped = stamp_changed_memos(previous, updated, now());
+        assert_eq!(
+            stamped,
+            "# A\nalpha\n## B\n<updated>2024-05-01T12:00:00Z</updated>\nbeta changed\n# C\ngamma\n"
+        );
+        let memos = parse_memo(&stamped);
+        assert_eq!(memos[0].updated(), None);
+        assert_eq!(memos[0].children()[0].updated(), Some(&now()));
+    }
+
+    #[test]
+    fn test_new_memo_gets_created_and_existing_tag_is_replaced() {
+        let previous = "# A\n<updated>2020-01-01</updated>\nalpha\n";
+        let updated = "# A\n<updated>2020-01-01</updated>\nalpha!\n# New\ntext\n";
+        let stamped = stamp_changed_memos(previous, updated, now());
+        assert_eq!(
+            stamped,
+            "# A\n<updated>2024-05-01T12:00:00Z</updated>\nalpha!\n# New\n<created>2024-05-01T12:00:00Z</created>\n<updated>2024-05-01T12:00:00Z</updated>\ntext\n"
+        );
+        let memos = parse_memo(&stamped);
+        assert_eq!(memos[1].created(), Some(&now()));
+    }
+
+    #[test]
+    fn test_unchanged_file_is_left_alone() {
+        let content = "# A\n<updated>2020-01-01</updated>\nalpha\n";
+        assert_eq!(stamp_changed_memos(content, content, now()), content);
+    }
+}
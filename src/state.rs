@@ -0,0 +1,87 @@
+//! Per-root UI state in `.fmemo/state.json`, such as pinned files. Unlike the config,
+//! it's written by the server, so every client sees the same state.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::config::FMEMO_DIR;
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct State {
+    /// Pinned (favorite) files, relative to the root, in the order they were pinned
+    #[serde(default)]
+    pub pins: Vec<String>,
+}
+
+/// Serializes read-modify-write cycles of state files
+static STATE_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn state_path(root: &Path) -> PathBuf {
+    root.join(FMEMO_DIR).join("state.json")
+}
+
+/// Load the state of `root`; empty when there's no state file yet
+pub fn load_state(root: &Path) -> std::io::Result<State> {
+    let content = match std::fs::read_to_string(state_path(root)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(State::default()),
+        Err(e) => return Err(e),
+    };
+    serde_json::from_str(&content)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))
+}
+
+/// Write the state through a temporary file, so readers never see half of it
+fn save_state(root: &Path, state: &State) -> std::io::Result<()> {
+    let path = state_path(root);
+    std::fs::create_dir_all(root.join(FMEMO_DIR))?;
+    let temp = path.with_extension("json.tmp");
+    std::fs::write(&temp, serde_json::to_string_pretty(state)?)?;
+    std::fs::rename(temp, path)
+}
+
+/// Load, change and save the state of `root` without racing other updates
+pub fn update_state<R>(root: &Path, f: impl FnOnce(&mut State) -> R) -> std::io::Result<R> {
+    let _guard = STATE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut state = load_state(root)?;
+    let result = f(&mut state);
+    save_state(root, &state)?;
+    Ok(result)
+}
+
+/// Pin or unpin a file and return the pins
+pub fn set_pinned(root: &Path, file: &str, pinned: bool) -> std::io::Result<Vec<String>> {
+    update_state(root, |state| {
+        if !pinned {
+            state.pins.retain(|pin| pin != file);
+        } else if !state.pins.iter().any(|pin| pin == file) {
+            state.pins.push(file.to_string());
+        }
+        state.pins.clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_state, set_pinned, state_path};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_pins_persist() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        assert!(load_state(root).unwrap().pins.is_empty());
+
+        set_pinned(root, "a.fmemo", true).unwrap();
+        set_pinned(root, "b/c.md", true).unwrap();
+        assert_eq!(
+            set_pinned(root, "a.fmemo", true).unwrap(),
+            ["a.fmemo", "b/c.md"]
+        );
+        assert_eq!(set_pinned(root, "b/c.md", false).unwrap(), ["a.fmemo"]);
+        assert_eq!(load_state(root).unwrap().pins, ["a.fmemo"]);
+
+        std::fs::write(state_path(root), "not json").unwrap();
+        assert!(set_pinned(root, "a.fmemo", false).is_err());
+    }
+}
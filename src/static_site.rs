@@ -0,0 +1,356 @@
+//! Static site export (`fmemo export --static <dir>`): the whole vault as plain HTML files
+//! any static host can serve, without running the server.
+//!
+//! Every memo file gets a page next to where it lives (`notes/plan.fmemo` becomes
+//! `notes/plan.html`), with wiki-links, embeds and links to other memo files pointing at
+//! their pages. Each directory gets an `index.html` listing its subdirectories and pages; a
+//! memo file named `index` gets the listing below its memos instead. Local images and other
+//! linked files are copied next to the pages, `search-index.json` has the text of every memo
+//! for client-side search, and with `[publish] url` in the config there is a `sitemap.xml`.
+//!
+//! The site is public, so it is built like `fmemo serve --publish` serves the vault:
+//! encrypted memos are left out and `<private>` regions are cut.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::graph::wiki_name;
+use crate::parser::{normalize_relative_path, resolve_image_paths};
+use crate::plugin::Plugins;
+use crate::render::{escape_html, page_path, relative_url, site_sections_html};
+use crate::schema::{LinkKind, Memo};
+use crate::server::{list_memo_files, read_fmemo_file_with};
+use crate::sitemap::encode_path;
+use crate::template::{TemplateContext, expand_variables};
+
+/// File name of the search index at the top of the site
+pub const SEARCH_INDEX: &str = "search-index.json";
+
+/// One memo in `search-index.json`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct SearchEntry {
+    /// The memo's page and anchor, relative to the top of the site
+    pub url: String,
+    /// Memo file relative to the root
+    pub file: String,
+    pub title: String,
+    /// Title, descriptions and content
+    pub text: String,
+}
+
+/// What an export wrote
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportSummary {
+    pub pages: usize,
+    /// Directory listings, including those added to an `index` memo's page
+    pub indexes: usize,
+    /// Copied images and other linked files
+    pub assets: usize,
+}
+
+/// A memo file with its title and rendered memos
+struct Page {
+    file: String,
+    title: String,
+    html: String,
+}
+
+fn parent_dir(path: &str) -> &str {
+    path.rfind('/').map_or("", |idx| &path[..idx])
+}
+
+fn index_path(dir: &str) -> String {
+    match dir {
+        "" => "index.html".to_string(),
+        dir => format!("{}/index.html", dir),
+    }
+}
+
+/// Links from the page at `path` to the top of the site and each directory above it
+fn breadcrumbs(path: &str) -> String {
+    let mut html = format!(
+        "<nav class=\"breadcrumbs\"><a href=\"{}\">Home</a>",
+        escape_html(&relative_url(path, "index.html"))
+    );
+    let dirs: Vec<&str> = parent_dir(path)
+        .split('/')
+        .filter(|dir| !dir.is_empty())
+        .collect();
+    for (n, name) in dirs.iter().enumerate() {
+        html.push_str(&format!(
+            " / <a href=\"{}\">{}</a>",
+            escape_html(&relative_url(path, &index_path(&dirs[..=n].join("/")))),
+            escape_html(name)
+        ));
+    }
+    html.push_str("</nav>");
+    html
+}
+
+/// A standalone HTML document at `path` in the site
+fn document(path: &str, title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{}</title></head><body>{}<main>{}</main></body></html>",
+        escape_html(title),
+        breadcrumbs(path),
+        body
+    )
+}
+
+/// The listing of `dir`: its subdirectories, then its pages by title
+fn listing(dir: &str, subdirs: &BTreeSet<String>, pages: &[Page]) -> String {
+    let from = index_path(dir);
+    let mut html = String::from("<nav class=\"directory\"><ul>");
+    for subdir in subdirs {
+        html.push_str(&format!(
+            "<li class=\"directory\"><a href=\"{}\">{}/</a></li>",
+            escape_html(&relative_url(&from, &index_path(subdir))),
+            escape_html(subdir.rsplit('/').next().unwrap_or(subdir))
+        ));
+    }
+    for page in pages.iter().filter(|page| parent_dir(&page.file) == dir) {
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>",
+            escape_html(&relative_url(&from, &page_path(&page.file))),
+            escape_html(&page.title)
+        ));
+    }
+    html.push_str("</ul></nav>");
+    html
+}
+
+fn collect_search_entries(memos: &[Memo], file: &str, entries: &mut Vec<SearchEntry>) {
+    for memo in memos {
+        entries.push(SearchEntry {
+            url: format!(
+                "{}#{}",
+                encode_path(&page_path(file)),
+                encode_path(&memo.anchor())
+            ),
+            file: file.to_string(),
+            title: memo.title().to_string(),
+            text: crate::search::searchable_text(memo),
+        });
+        collect_search_entries(memo.children(), file, entries);
+    }
+}
+
+/// Root-relative paths of the local files the memos of `file` show or link to, other than
+/// memo files
+fn collect_assets(memos: &[Memo], file: &str, files: &[String], assets: &mut BTreeSet<String>) {
+    for memo in memos {
+        assets.extend(memo.images().iter().filter_map(|image| image.path.clone()));
+        for link in memo.links() {
+            let path = link
+                .url
+                .split_once('#')
+                .map_or(link.url.as_str(), |(path, _)| path);
+            if link.kind == LinkKind::Internal
+                && let Some(path) = normalize_relative_path(parent_dir(file), path)
+                && !files.contains(&path)
+            {
+                assets.insert(path);
+            }
+        }
+        collect_assets(memo.children(), file, files, assets);
+    }
+}
+
+fn write(out: &Path, path: &str, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let target = out.join(path);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(target, contents)
+}
+
+/// Render the memo files below `root` into a static site in `out`
+pub fn export_site(root: &Path, out: &Path, plugins: &Plugins) -> io::Result<ExportSummary> {
+    let config = crate::config::load_config(root)?;
+    let theme = crate::highlight::requested_theme(None, &config.highlight);
+    let files: Vec<String> = list_memo_files(root)?
+        .into_iter()
+        .filter(|file| !crate::crypt::is_encrypted(Path::new(file)))
+        .collect();
+
+    let mut summary = ExportSummary::default();
+    let mut pages = Vec::new();
+    let mut search_entries = Vec::new();
+    let mut assets = BTreeSet::new();
+    for file in &files {
+        let mut memos = read_fmemo_file_with(root.join(file), plugins, true)?.memos;
+        resolve_image_paths(&mut memos, file);
+        // Embeds link to the embedded page
+        crate::embed::resolve_embeds(root, plugins, true, file, &mut memos, false)?;
+        if let Some(theme) = &theme {
+            crate::highlight::highlight_memos(&mut memos, theme)?;
+        }
+        let title = memos
+            .first()
+            .map_or_else(|| wiki_name(file).to_string(), |memo| memo.title().clone());
+        let mut context = TemplateContext::new(&title, file);
+        context.variables = config.variables.clone();
+        expand_variables(&mut memos, &context);
+
+        collect_search_entries(&memos, file, &mut search_entries);
+        collect_assets(&memos, file, &files, &mut assets);
+        pages.push(Page {
+            file: file.clone(),
+            title,
+            html: site_sections_html(&memos, file, &files),
+        });
+    }
+
+    // Every directory with a page below it, and its subdirectories
+    let mut dirs: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    dirs.insert(String::new(), BTreeSet::new());
+    for page in &pages {
+        let mut dir = parent_dir(&page.file);
+        while !dir.is_empty() {
+            let parent = parent_dir(dir);
+            dirs.entry(dir.to_string()).or_default();
+            dirs.entry(parent.to_string())
+                .or_default()
+                .insert(dir.to_string());
+            dir = parent;
+        }
+    }
+
+    for page in &pages {
+        let path = page_path(&page.file);
+        let mut body = page.html.clone();
+        let dir = parent_dir(&page.file);
+        if path == index_path(dir) {
+            body.push_str(&listing(dir, &dirs[dir], &pages));
+            summary.indexes += 1;
+        }
+        write(out, &path, document(&path, &page.title, &body))?;
+        summary.pages += 1;
+    }
+    for (dir, subdirs) in &dirs {
+        let path = index_path(dir);
+        if pages.iter().any(|page| page_path(&page.file) == path) {
+            continue;
+        }
+        let title = match dir.as_str() {
+            "" => "Index",
+            dir => dir.rsplit('/').next().unwrap_or(dir),
+        };
+        let body = format!(
+            "<h1>{}</h1>{}",
+            escape_html(title),
+            listing(dir, subdirs, &pages)
+        );
+        write(out, &path, document(&path, title, &body))?;
+        summary.indexes += 1;
+    }
+
+    for asset in &assets {
+        let source = root.join(asset);
+        // Hidden paths aren't served as assets either
+        if asset.split('/').any(|segment| segment.starts_with('.')) || !source.is_file() {
+            continue;
+        }
+        let target = out.join(asset);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(source, target)?;
+        summary.assets += 1;
+    }
+
+    write(out, SEARCH_INDEX, serde_json::to_vec(&search_entries)?)?;
+    if let Some(url) = &config.publish.url {
+        let url = url.trim_end_matches('/');
+        let sitemap_pages: Vec<_> = crate::sitemap::pages(root)?;
+        let xml = crate::sitemap::to_xml(&sitemap_pages, |path| {
+            format!("{}/{}", url, encode_path(&page_path(path)))
+        });
+        write(out, "sitemap.xml", xml)?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_export_site() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("vault");
+        let out = temp_dir.path().join("site");
+        fs::create_dir_all(root.join("trips/img")).unwrap();
+        fs::create_dir_all(root.join(".fmemo")).unwrap();
+        fs::write(
+            root.join(".fmemo/config.toml"),
+            "[variables]\nauthor = \"kai\"\n\n[publish]\nurl = \"https://notes.example.com/\"\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("index.fmemo"),
+            "# Home\nBy {{author}}, see [[kyoto#temples]] and [plan](plan.md).\n\n\
+             <private>diary</private>",
+        )
+        .unwrap();
+        fs::write(root.join("plan.md"), "# Plan\n[[index]]").unwrap();
+        fs::write(
+            root.join("trips/kyoto.fmemo"),
+            "# Kyoto\n![map](img/map.png)\n## Temples\nKinkaku-ji",
+        )
+        .unwrap();
+        fs::write(root.join("trips/img/map.png"), "png").unwrap();
+        fs::write(root.join("secret.fmemox"), "locked").unwrap();
+
+        let summary = export_site(&root, &out, &Plugins::default()).unwrap();
+        assert_eq!(
+            summary,
+            ExportSummary {
+                pages: 3,
+                indexes: 2,
+                assets: 1
+            }
+        );
+
+        let home = fs::read_to_string(out.join("index.html")).unwrap();
+        assert!(home.contains("<title>Home</title>"));
+        assert!(home.contains("By kai, see"));
+        assert!(home.contains(
+            "<a class=\"wiki-link\" data-target=\"kyoto#temples\" href=\"trips/kyoto.html#temples\">"
+        ));
+        assert!(home.contains("<a href=\"plan.html\">plan</a>"));
+        assert!(!home.contains("diary"));
+        // The root listing goes on the index memo's page
+        assert!(
+            home.contains("<li class=\"directory\"><a href=\"trips/index.html\">trips/</a></li>")
+        );
+        assert!(home.contains("<li><a href=\"plan.html\">Plan</a></li>"));
+
+        let kyoto = fs::read_to_string(out.join("trips/kyoto.html")).unwrap();
+        assert!(kyoto.contains(
+            "<a href=\"../index.html\">Home</a> / <a href=\"../trips/index.html\">trips</a>"
+        ));
+        assert!(kyoto.contains("<img src=\"../trips/img/map.png\" alt=\"map\">"));
+        assert!(out.join("trips/img/map.png").is_file());
+        let trips = fs::read_to_string(out.join("trips/index.html")).unwrap();
+        assert!(trips.contains("<li><a href=\"../trips/kyoto.html\">Kyoto</a></li>"));
+        assert!(!out.join("secret.html").exists());
+
+        let entries: Vec<SearchEntry> =
+            serde_json::from_str(&fs::read_to_string(out.join(SEARCH_INDEX)).unwrap()).unwrap();
+        let temples = entries
+            .iter()
+            .find(|entry| entry.title == "Temples")
+            .unwrap();
+        assert_eq!(temples.url, "trips/kyoto.html#temples");
+        assert_eq!(temples.text, "Temples\nKinkaku-ji");
+
+        let sitemap = fs::read_to_string(out.join("sitemap.xml")).unwrap();
+        assert!(sitemap.contains("<loc>https://notes.example.com/trips/kyoto.html</loc>"));
+    }
+}
@@ -0,0 +1,237 @@
+//! Index of which files use which `<tag>` values, so listing a tag's files doesn't
+//! parse the whole root.
+//!
+//! The index is kept in `.fmemo/tag-index.json` with each file's modification time;
+//! on first use only files changed since then are parsed again. The directory watcher
+//! keeps it current while the server runs. A server in publish mode indexes the files
+//! without their `<private>` regions, into `.fmemo/tag-index.published.json`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::config::FMEMO_DIR;
+use crate::plugin::Plugins;
+use crate::schema::Memo;
+use crate::server::{list_memo_files, read_fmemo_file_with};
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+struct IndexedFile {
+    /// Modification time (nanoseconds since the epoch) when the file was indexed
+    modified: Option<u64>,
+    tags: BTreeSet<String>,
+}
+
+type Entries = BTreeMap<String, IndexedFile>;
+
+/// Handle to the tag index of a root; clones share the index
+#[derive(Debug, Clone)]
+pub struct TagIndex {
+    root: PathBuf,
+    /// Loaded on first use
+    entries: Arc<Mutex<Option<Entries>>>,
+    /// Files are indexed without their `<private>` regions
+    publish: bool,
+}
+
+pub fn index_path(root: &Path) -> PathBuf {
+    root.join(FMEMO_DIR).join("tag-index.json")
+}
+
+/// Where a server in publish mode keeps its index, so private tags never get into it
+pub fn published_index_path(root: &Path) -> PathBuf {
+    root.join(FMEMO_DIR).join("tag-index.published.json")
+}
+
+fn modified(path: &Path) -> Option<u64> {
+    path.metadata()
+        .ok()?
+        .modified()
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_nanos() as u64)
+}
+
+/// `<tag>` values of the memos and their children
+pub(crate) fn collect_tags(memos: &[Memo], tags: &mut BTreeSet<String>) {
+    for memo in memos {
+        tags.extend(memo.metadata().get("tag").into_iter().flatten().cloned());
+        collect_tags(memo.children(), tags);
+    }
+}
+
+/// Parse one file; `None` when it can't be read (e.g. it was deleted)
+fn index_file(root: &Path, file: &str, publish: bool) -> Option<IndexedFile> {
+    let path = root.join(file);
+    let content = read_fmemo_file_with(&path, &Plugins::default(), publish).ok()?;
+    let mut tags = BTreeSet::new();
+    collect_tags(&content.memos, &mut tags);
+    Some(IndexedFile {
+        modified: modified(&path),
+        tags,
+    })
+}
+
+impl TagIndex {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: root.into(),
+            entries: Arc::new(Mutex::new(None)),
+            publish: false,
+        }
+    }
+
+    /// Index the files as a server in publish mode shows them
+    pub fn publish(mut self, publish: bool) -> Self {
+        self.publish = publish;
+        self
+    }
+
+    fn path(&self) -> PathBuf {
+        match self.publish {
+            true => published_index_path(&self.root),
+            false => index_path(&self.root),
+        }
+    }
+
+    /// Bring the saved index up to date with the files on disk
+    fn load(&self) -> Entries {
+        let mut saved: Entries = std::fs::read_to_string(self.path())
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        let mut entries = Entries::new();
+        for file in list_memo_files(&self.root).unwrap_or_default() {
+            let entry = match saved.remove(&file) {
+                Some(entry)
+                    if entry.modified.is_some()
+                        && entry.modified == modified(&self.root.join(&file)) =>
+                {
+                    Some(entry)
+                }
+                _ => index_file(&self.root, &file, self.publish),
+            };
+            if let Some(entry) = entry {
+                entries.insert(file, entry);
+            }
+        }
+        self.save(&entries);
+        entries
+    }
+
+    /// Best effort: without a saved index the next start parses everything again
+    fn save(&self, entries: &Entries) {
+        let path = self.path();
+        let written = std::fs::create_dir_all(self.root.join(FMEMO_DIR)).and_then(|_| {
+            std::fs::write(&path, serde_json::to_string(entries).unwrap_or_default())
+        });
+        if let Err(e) = written {
+            eprintln!("Failed to save tag index {}: {}", path.display(), e);
+        }
+    }
+
+    fn with_entries<R>(&self, f: impl FnOnce(&mut Entries) -> R) -> R {
+        let mut guard = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let entries = guard.get_or_insert_with(|| self.load());
+        f(entries)
+    }
+
+    /// Files using `tag`, sorted
+    pub fn files(&self, tag: &str) -> Vec<String> {
+        self.with_entries(|entries| {
+            entries
+                .iter()
+                .filter(|(_, entry)| entry.tags.contains(tag))
+                .map(|(file, _)| file.clone())
+                .collect()
+        })
+    }
+
+    /// Every tag with the number of files using it
+    pub fn tags(&self) -> BTreeMap<String, usize> {
+        self.with_entries(|entries| {
+            let mut counts = BTreeMap::new();
+            for tag in entries.values().flat_map(|entry| &entry.tags) {
+                *counts.entry(tag.clone()).or_insert(0) += 1;
+            }
+            counts
+        })
+    }
+
+    /// Re-index a memo file (relative to the root) that was written, created or deleted
+    pub fn file_changed(&self, file: &str) {
+        let mut guard = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        // Not loaded yet: loading will pick the change up
+        let Some(entries) = guard.as_mut() else {
+            return;
+        };
+        let changed = match index_file(&self.root, file, self.publish) {
+            Some(entry) => entries.insert(file.to_string(), entry.clone()) != Some(entry),
+            None => entries.remove(file).is_some(),
+        };
+        if changed {
+            self.save(entries);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TagIndex, index_path, published_index_path};
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_tag_index() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(
+            root.join("a.fmemo"),
+            "# A\n<tag>rust</tag>\n## B\n<tag>web</tag>",
+        )
+        .unwrap();
+        fs::write(root.join("sub/c.md"), "# C\n<tag>rust</tag>").unwrap();
+
+        let index = TagIndex::new(root);
+        assert_eq!(index.files("rust"), ["a.fmemo", "sub/c.md"]);
+        assert_eq!(index.files("web"), ["a.fmemo"]);
+        assert!(index.files("none").is_empty());
+        assert!(index_path(root).exists());
+
+        fs::write(root.join("sub/c.md"), "# C\n<tag>web</tag>").unwrap();
+        index.file_changed("sub/c.md");
+        fs::remove_file(root.join("a.fmemo")).unwrap();
+        index.file_changed("a.fmemo");
+        assert!(index.files("rust").is_empty());
+        assert_eq!(
+            index.tags().into_iter().collect::<Vec<_>>(),
+            [("web".to_string(), 1)]
+        );
+
+        // A new handle starts from the saved index; files that didn't change aren't parsed.
+        // Swap in a fake saved entry with the right modification time to prove it.
+        let saved = fs::read_to_string(index_path(root)).unwrap();
+        fs::write(index_path(root), saved.replace("\"web\"", "\"saved\"")).unwrap();
+        assert_eq!(TagIndex::new(root).files("saved"), ["sub/c.md"]);
+    }
+
+    #[test]
+    fn test_published_tag_index() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::write(
+            root.join("a.fmemo"),
+            "# A\n<tag>rust</tag><private><tag>diary</tag></private>",
+        )
+        .unwrap();
+
+        assert_eq!(TagIndex::new(root).files("diary"), ["a.fmemo"]);
+        // The personal index saved above isn't reused
+        let published = TagIndex::new(root).publish(true);
+        assert!(published.files("diary").is_empty());
+        assert_eq!(published.files("rust"), ["a.fmemo"]);
+        assert!(published_index_path(root).exists());
+    }
+}
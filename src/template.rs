@@ -0,0 +1,467 @@
+//! Memo templates, used by `fmemo new` and the template API endpoints.
+//!
+//! Templates are memo files in a `.fmemo/templates/` directory. Any directory below the
+//! root can have its own; a template next to the new memo (or in the closest ancestor)
+//! wins over one at the root. Placeholders filled in when rendering:
+//!
+//! - `{{title}}`, `{{slug}}`, `{{path}}` (or `{{file}}`) - the new memo
+//! - `{{date}}`, `{{time}}`, `{{datetime}}`, `{{date:%d.%m.%Y}}` - the current local time
+//! - `{{cursor}}` - removed; its position is reported so editors can put the caret there
+//! - any name from `[variables]` in `.fmemo/config.toml`
+//!
+//! Unknown placeholders are left as they are. The same placeholders are expanded in memos
+//! rendered as HTML (`expand_variables`), with the memo file in place of the new memo.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::format::StrftimeItems;
+
+use crate::config::{FMEMO_DIR, load_config};
+use crate::schema::{CursorPosition, Memo, NewMemoRequest, RenderedTemplate};
+use crate::server::{INVALID_MEMO_PATH, resolve_memo_path};
+
+/// Used when no `default` template is found
+pub const BUILTIN_TEMPLATE: &str = "---\ncreated: {{date}}\n---\n# {{title}}\n{{cursor}}";
+
+/// Values available to placeholders
+#[derive(Debug, Clone)]
+pub struct TemplateContext {
+    pub title: String,
+    /// Path of the new memo relative to the root
+    pub path: String,
+    pub now: chrono::DateTime<chrono::FixedOffset>,
+    pub variables: BTreeMap<String, String>,
+}
+
+impl TemplateContext {
+    pub fn new(title: &str, path: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            path: path.to_string(),
+            now: chrono::Local::now().fixed_offset(),
+            variables: BTreeMap::new(),
+        }
+    }
+
+    fn value(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "title" => self.title.clone(),
+            "slug" => slugify(&self.title),
+            "path" | "file" => self.path.clone(),
+            "date" => self.now.format("%Y-%m-%d").to_string(),
+            "time" => self.now.format("%H:%M").to_string(),
+            "datetime" => self.now.format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+            _ => match name.strip_prefix("date:") {
+                // An invalid format string would panic when displayed
+                Some(format) => {
+                    let items = StrftimeItems::new(format).parse().ok()?;
+                    self.now.format_with_items(items.iter()).to_string()
+                }
+                None => self.variables.get(name)?.clone(),
+            },
+        };
+        Some(value)
+    }
+}
+
+/// `.fmemo/templates` of a directory relative to the root (`""` for the root itself)
+pub fn templates_dir(root: &Path, dir: &str) -> PathBuf {
+    root.join(dir).join(FMEMO_DIR).join("templates")
+}
+
+/// Directories searched for templates of `dir`: itself, then each ancestor up to the root
+fn template_search_path(dir: &str) -> Vec<&str> {
+    let mut dirs = Vec::new();
+    let mut dir = dir.trim_matches('/');
+    loop {
+        dirs.push(dir);
+        if dir.is_empty() {
+            return dirs;
+        }
+        dir = parent_dir(dir);
+    }
+}
+
+/// Names of the templates available to memos in `dir`, sorted
+pub fn list_templates(root: &Path, dir: &str) -> std::io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for search_dir in template_search_path(dir) {
+        let entries = match fs::read_dir(templates_dir(root, search_dir)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let ext = path.extension().and_then(|s| s.to_str());
+            if (ext == Some("fmemo") || ext == Some("md"))
+                && let Some(stem) = path.file_stem().and_then(|s| s.to_str())
+            {
+                names.push(stem.to_string());
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+/// Template source by name as seen from `dir`; `None` means `default`,
+/// falling back to the built-in template
+pub fn load_template(root: &Path, dir: &str, name: Option<&str>) -> std::io::Result<String> {
+    let requested = name.unwrap_or("default");
+    if requested.is_empty() || requested.starts_with('.') || requested.contains(['/', '\\']) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Invalid template name '{}'", requested),
+        ));
+    }
+    for search_dir in template_search_path(dir) {
+        let templates = templates_dir(root, search_dir);
+        for ext in ["fmemo", "md"] {
+            match fs::read_to_string(templates.join(format!("{}.{}", requested, ext))) {
+                Ok(content) => return Ok(content),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    match name {
+        None => Ok(BUILTIN_TEMPLATE.to_string()),
+        Some(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Template '{}' not found", requested),
+        )),
+    }
+}
+
+/// Fill in the placeholders of `template`; also returns where the first `{{cursor}}` was
+pub fn render_template(
+    template: &str,
+    context: &TemplateContext,
+) -> (String, Option<CursorPosition>) {
+    let mut output = String::with_capacity(template.len());
+    let mut cursor = None;
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let end = start + 2 + len + 2;
+        output.push_str(&rest[..start]);
+        let name = rest[start + 2..end - 2].trim();
+        if name == "cursor" {
+            cursor = cursor.or_else(|| Some(end_position(&output)));
+        } else {
+            match context.value(name) {
+                Some(value) => output.push_str(&value),
+                None => output.push_str(&rest[start..end]),
+            }
+        }
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+    (output, cursor)
+}
+
+/// Fill in the placeholders in the titles, descriptions and text of `memos` and their
+/// children, for rendering; the files themselves keep the placeholders
+pub fn expand_variables(memos: &mut [Memo], context: &TemplateContext) {
+    let expand = |text: &str| {
+        if text.contains("{{") {
+            render_template(text, context).0
+        } else {
+            text.to_string()
+        }
+    };
+    for memo in memos {
+        memo.map_text(&expand);
+        expand_variables(memo.children_mut(), context);
+    }
+}
+
+/// 1-based position just after the end of `text`
+fn end_position(text: &str) -> CursorPosition {
+    let line_start = text.rfind('\n').map_or(0, |idx| idx + 1);
+    CursorPosition {
+        line: text.matches('\n').count() + 1,
+        column: text[line_start..].chars().count() + 1,
+    }
+}
+
+/// File name for a memo titled `title`: lowercase words joined by `-`
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.trim().chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() || c == '_' {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "untitled".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+/// Work out where a new memo goes and render its template, without writing anything.
+///
+/// Without an explicit path the file is named after the title and placed in the
+/// template's configured directory (`[templates.<name>] directory` in the config).
+/// Without an explicit template, the one configured for the target directory
+/// (`[directories."<dir>"] template`) or `default` is used.
+/// Errors: `NotFound` for an unknown template, `InvalidInput` for a bad path or name.
+pub fn render_new_memo(root: &Path, request: &NewMemoRequest) -> std::io::Result<RenderedTemplate> {
+    let config = load_config(root)?;
+
+    let (relative, template_name) = match &request.path {
+        Some(path) => {
+            let relative = with_memo_extension(path.clone());
+            let template = request
+                .template
+                .as_deref()
+                .or_else(|| config.directory_template(parent_dir(&relative)))
+                .map(str::to_string);
+            (relative, template)
+        }
+        None => {
+            let name = request.template.as_deref().unwrap_or("default");
+            let directory = config
+                .templates
+                .get(name)
+                .and_then(|template| template.directory.as_deref())
+                .map(|dir| dir.trim_matches('/'))
+                .filter(|dir| !dir.is_empty());
+            let relative = match directory {
+                Some(dir) => format!("{}/{}", dir, slugify(&request.title)),
+                None => slugify(&request.title),
+            };
+            (with_memo_extension(relative), request.template.clone())
+        }
+    };
+    if resolve_memo_path(root, &relative).is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            INVALID_MEMO_PATH,
+        ));
+    }
+
+    let template = load_template(root, parent_dir(&relative), template_name.as_deref())?;
+    let mut context = TemplateContext::new(&request.title, &relative);
+    context.variables = config.variables;
+    let (content, cursor) = render_template(&template, &context);
+    Ok(RenderedTemplate {
+        path: relative,
+        content,
+        cursor,
+    })
+}
+
+/// Create a memo file from a template, as rendered by `render_new_memo`.
+/// Fails with `AlreadyExists` instead of overwriting a file.
+pub fn create_from_template(
+    root: &Path,
+    request: &NewMemoRequest,
+) -> std::io::Result<RenderedTemplate> {
+    let rendered = render_new_memo(root, request)?;
+    let file_path = root.join(&rendered.path);
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // create_new: never overwrite an existing memo
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&file_path)?
+        .write_all(rendered.content.as_bytes())?;
+    Ok(rendered)
+}
+
+fn parent_dir(relative: &str) -> &str {
+    relative.rfind('/').map_or("", |idx| &relative[..idx])
+}
+
+fn with_memo_extension(mut path: String) -> String {
+    let ext = Path::new(&path).extension().and_then(|s| s.to_str());
+    if ext != Some("fmemo") && ext != Some("md") {
+        path.push_str(".fmemo");
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        TemplateContext, create_from_template, expand_variables, list_templates, render_new_memo,
+        render_template, slugify, templates_dir,
+    };
+    use crate::config::FMEMO_DIR;
+    use crate::schema::{CursorPosition, NewMemoRequest};
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn request(title: &str, template: Option<&str>, path: Option<&str>) -> NewMemoRequest {
+        NewMemoRequest {
+            title: title.to_string(),
+            template: template.map(str::to_string),
+            path: path.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_render_and_slugify() {
+        let mut context = TemplateContext::new("Weekly Plan", "plans/weekly-plan.fmemo");
+        context.now = chrono::DateTime::parse_from_rfc3339("2024-05-01T09:05:00+02:00").unwrap();
+        context
+            .variables
+            .insert("author".to_string(), "kai".to_string());
+
+        let (content, cursor) = render_template(
+            "# {{ title }} ({{slug}}, {{path}})\n{{date}} {{time}} {{date:%d.%m.%Y}} {{datetime}}\n- {{author}} {{unknown}} {{date:%Q}}\n  {{cursor}}é{{cursor}}\n{{ open",
+            &context,
+        );
+        assert_eq!(
+            content,
+            "# Weekly Plan (weekly-plan, plans/weekly-plan.fmemo)\n2024-05-01 09:05 01.05.2024 2024-05-01T09:05:00+02:00\n- kai {{unknown}} {{date:%Q}}\n  é\n{{ open"
+        );
+        assert_eq!(cursor, Some(CursorPosition { line: 4, column: 3 }));
+        assert_eq!(render_template("", &context), (String::new(), None));
+
+        assert_eq!(
+            slugify("  Weekly Review: 2024/05 "),
+            "weekly-review-2024-05"
+        );
+        assert_eq!(slugify("!!!"), "untitled");
+    }
+
+    #[test]
+    fn test_expand_variables() {
+        let mut context = TemplateContext::new("Letter", "letters/bank.fmemo");
+        context.now = chrono::DateTime::parse_from_rfc3339("2024-05-01T09:05:00+02:00").unwrap();
+        context
+            .variables
+            .insert("signature".to_string(), "Kai, Tokyo".to_string());
+        let source = "# Letter {{date}}\n<desc>From {{file}}</desc>\nDear bank,\n\n**{{signature}}** `{{signature}}`\n## PS\n{{unknown}}";
+        let mut memos = crate::parser::parse_document(source, &Default::default()).memos;
+        expand_variables(&mut memos, &context);
+
+        assert_eq!(memos[0].title(), "Letter 2024-05-01");
+        assert_eq!(memos[0].descriptions(), &["From letters/bank.fmemo"]);
+        assert_eq!(
+            crate::render::memo_html(&memos[0], "letters/bank.fmemo"),
+            "<p class=\"description\">From letters/bank.fmemo</p><p>Dear bank,</p>\
+             <p><strong>Kai, Tokyo</strong> <code>{{signature}}</code></p>"
+        );
+        assert_eq!(
+            crate::render::memo_html(&memos[0].children()[0], "letters/bank.fmemo"),
+            "<p>{{unknown}}</p>"
+        );
+        // The source keeps its placeholders
+        assert!(
+            memos[0]
+                .content()
+                .as_deref()
+                .unwrap()
+                .contains("**{{signature}}**")
+        );
+    }
+
+    #[test]
+    fn test_create_from_templates() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(templates_dir(root, "")).unwrap();
+        fs::write(
+            templates_dir(root, "").join("journal.fmemo"),
+            "# {{title}}\n## Done\n## Next\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join(FMEMO_DIR).join("config.toml"),
+            "[templates.journal]\ndirectory = \"journal\"\n",
+        )
+        .unwrap();
+        assert_eq!(list_templates(root, "").unwrap(), ["journal"]);
+
+        let created =
+            create_from_template(root, &request("Day One", Some("journal"), None)).unwrap();
+        assert_eq!(created.path, "journal/day-one.fmemo");
+        assert_eq!(created.cursor, None);
+        assert_eq!(
+            fs::read_to_string(root.join(&created.path)).unwrap(),
+            "# Day One\n## Done\n## Next\n"
+        );
+
+        // Built-in template, explicit path
+        let created = create_from_template(root, &request("Idea", None, Some("ideas/x"))).unwrap();
+        assert_eq!(created.path, "ideas/x.fmemo");
+        assert_eq!(created.cursor, Some(CursorPosition { line: 5, column: 1 }));
+        let content = fs::read_to_string(root.join(&created.path)).unwrap();
+        assert!(content.starts_with("---\ncreated: "));
+        assert!(content.ends_with("---\n# Idea\n"));
+
+        let kind =
+            |request: NewMemoRequest| create_from_template(root, &request).unwrap_err().kind();
+        assert_eq!(
+            kind(request("Idea", None, Some("ideas/x"))),
+            std::io::ErrorKind::AlreadyExists
+        );
+        assert_eq!(
+            kind(request("X", Some("missing"), None)),
+            std::io::ErrorKind::NotFound
+        );
+        assert_eq!(
+            kind(request("X", None, Some("../x"))),
+            std::io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_templates_per_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(templates_dir(root, "")).unwrap();
+        fs::create_dir_all(templates_dir(root, "work")).unwrap();
+        fs::write(
+            templates_dir(root, "").join("default.fmemo"),
+            "# {{title}} (root)",
+        )
+        .unwrap();
+        fs::write(
+            templates_dir(root, "work").join("default.fmemo"),
+            "# {{title}} (work)",
+        )
+        .unwrap();
+        fs::write(
+            templates_dir(root, "work").join("meeting.md"),
+            "# {{title}}\n## Agenda\n- {{cursor}}",
+        )
+        .unwrap();
+        fs::write(
+            root.join(FMEMO_DIR).join("config.toml"),
+            "[directories.\"work/meetings\"]\ntemplate = \"meeting\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(list_templates(root, "").unwrap(), ["default"]);
+        assert_eq!(
+            list_templates(root, "work/sub").unwrap(),
+            ["default", "meeting"]
+        );
+
+        let render = |path: &str| render_new_memo(root, &request("T", None, Some(path))).unwrap();
+        assert_eq!(render("home/a").content, "# T (root)");
+        assert_eq!(render("work/a").content, "# T (work)");
+        let meeting = render("work/meetings/standup");
+        assert_eq!(meeting.content, "# T\n## Agenda\n- ");
+        assert_eq!(meeting.cursor, Some(CursorPosition { line: 3, column: 3 }));
+        assert!(!root.join("work/meetings/standup.fmemo").exists());
+    }
+}
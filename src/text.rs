@@ -0,0 +1,98 @@
+//! Memos as plain text without any markup, for `GET /api/files/{path}/text`: for
+//! text-to-speech, word processors and other tools that only take text.
+//!
+//! Each memo is its title followed by its descriptions, paragraphs, quotes, display math and
+//! code as paragraphs separated by blank lines; list items are one per line, indented by
+//! depth, without markers. Links and wiki-links leave their text, images their alt text, and
+//! markup in titles is dropped too. Diagrams are left out.
+
+use crate::inline::{parse_inline, spans_to_text};
+use crate::schema::{ContentBlock, Memo};
+
+/// Paragraphs of a memo's own body (not its children)
+fn paragraphs(memo: &Memo) -> Vec<String> {
+    let mut paragraphs: Vec<String> = memo.descriptions().clone();
+    let mut list = String::new();
+    for block in memo.content_blocks() {
+        match block {
+            ContentBlock::ListItem { depth, spans, .. } => {
+                if !list.is_empty() {
+                    list.push('\n');
+                }
+                list.push_str(&"  ".repeat(*depth));
+                list.push_str(&spans_to_text(spans));
+            }
+            _ => {
+                if !list.is_empty() {
+                    paragraphs.push(std::mem::take(&mut list));
+                }
+                paragraphs.push(spans_to_text(block.spans()));
+            }
+        }
+    }
+    if !list.is_empty() {
+        paragraphs.push(list);
+    }
+    paragraphs.extend(
+        memo.math_blocks()
+            .iter()
+            .filter(|math| math.display)
+            .map(|math| math.tex.clone()),
+    );
+    paragraphs.extend(
+        memo.code_blocks()
+            .iter()
+            .map(|block| block.code.trim_end().to_string()),
+    );
+    paragraphs.retain(|paragraph| !paragraph.trim().is_empty());
+    paragraphs
+}
+
+/// A memo's title and body as text, without its children
+pub fn memo_text(memo: &Memo) -> String {
+    let mut text = spans_to_text(&parse_inline(memo.title()));
+    for paragraph in paragraphs(memo) {
+        text.push_str("\n\n");
+        text.push_str(&paragraph);
+    }
+    text
+}
+
+/// The memos and their children as text, one memo after another
+pub fn document_text(memos: &[Memo]) -> String {
+    fn collect(memos: &[Memo], texts: &mut Vec<String>) {
+        for memo in memos {
+            texts.push(memo_text(memo));
+            collect(memo.children(), texts);
+        }
+    }
+    let mut texts = Vec::new();
+    collect(memos, &mut texts);
+    let mut text = texts.join("\n\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{ParseOptions, parse_document};
+
+    #[test]
+    fn test_document_text() {
+        let document = parse_document(
+            "# Plan *v2*\n<desc>For the team</desc>\nSee **[[roadmap|the roadmap]]** and \
+             [docs](https://example.com).\n\n- one\n  - nested\n1. first\n\n> quoted `code`\n\
+             ![chart](c.png)\n```rust\nfn main() {}\n```\n```mermaid\ngraph TD\n```\n## Next\nShip",
+            &ParseOptions::default(),
+        );
+        assert_eq!(
+            document_text(&document.memos),
+            "Plan v2\n\nFor the team\n\nSee the roadmap and docs.\n\none\n  nested\nfirst\n\n\
+             quoted code\nchart\n\nfn main() {}\n\nNext\n\nShip\n"
+        );
+        assert_eq!(memo_text(&document.memos[0].children()[0]), "Next\n\nShip");
+    }
+}
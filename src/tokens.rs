@@ -0,0 +1,193 @@
+//! Scoped API tokens for scripts and integrations, kept in `.fmemo/tokens.json`.
+//!
+//! A token is shown once when it is created; only its SHA-256 hash is stored. Tokens
+//! are sent like the `--token` one (`Authorization: Bearer` or `?token=`) and act with
+//! their scope: `read`, `write` or `admin`, which also manages tokens.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256};
+
+use crate::config::{FMEMO_DIR, Permission};
+use crate::crypt::{random_bytes, to_hex};
+
+/// Prefix of token values, so they are easy to spot in scripts and logs
+pub const TOKEN_PREFIX: &str = "fmemo_";
+
+/// A stored token, without its value
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct ApiToken {
+    pub id: String,
+    /// What the token is for
+    pub name: String,
+    pub scope: Permission,
+    /// RFC 3339
+    pub created_at: String,
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    hash: String,
+}
+
+impl ApiToken {
+    /// The token as listed by the API: the hash stays on the server
+    pub fn public(&self) -> Self {
+        Self {
+            hash: String::new(),
+            ..self.clone()
+        }
+    }
+}
+
+/// The tokens of a root
+#[derive(Debug, Clone, Default)]
+pub struct ApiTokens {
+    /// `None` keeps tokens in memory only
+    path: Option<PathBuf>,
+    tokens: Arc<Mutex<Vec<ApiToken>>>,
+}
+
+pub fn tokens_path(root: &Path) -> PathBuf {
+    root.join(FMEMO_DIR).join("tokens.json")
+}
+
+fn hash_token(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+impl ApiTokens {
+    /// The tokens stored below a root; none when the file doesn't exist yet
+    pub fn load(root: &Path) -> io::Result<Self> {
+        let path = tokens_path(root);
+        let tokens = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path: Some(path),
+            tokens: Arc::new(Mutex::new(tokens)),
+        })
+    }
+
+    fn save(&self, tokens: &[ApiToken]) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(tokens)?)
+    }
+
+    /// A new token; returns it with its value, which can't be seen again
+    pub fn create(&self, name: &str, scope: Permission) -> io::Result<(String, ApiToken)> {
+        if name.trim().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Token name is required",
+            ));
+        }
+        let value = format!("{}{}", TOKEN_PREFIX, to_hex(&random_bytes::<32>()?));
+        let token = ApiToken {
+            id: to_hex(&random_bytes::<8>()?),
+            name: name.trim().to_string(),
+            scope,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            hash: hash_token(&value),
+        };
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.push(token.clone());
+        if let Err(e) = self.save(&tokens) {
+            tokens.pop();
+            return Err(e);
+        }
+        Ok((value, token.public()))
+    }
+
+    pub fn list(&self) -> Vec<ApiToken> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .iter()
+            .map(ApiToken::public)
+            .collect()
+    }
+
+    /// Revoke a token; `NotFound` for unknown IDs
+    pub fn revoke(&self, id: &str) -> io::Result<ApiToken> {
+        let mut tokens = self.tokens.lock().unwrap();
+        let index = tokens
+            .iter()
+            .position(|token| token.id == id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Token not found"))?;
+        let token = tokens.remove(index);
+        if let Err(e) = self.save(&tokens) {
+            tokens.insert(index, token);
+            return Err(e);
+        }
+        Ok(token.public())
+    }
+
+    fn find(&self, value: &str) -> Option<ApiToken> {
+        if !value.starts_with(TOKEN_PREFIX) {
+            return None;
+        }
+        let hash = hash_token(value);
+        self.tokens
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|token| token.hash == hash)
+            .cloned()
+    }
+
+    /// The scope of a token value, if it is one of ours
+    pub fn scope(&self, value: &str) -> Option<Permission> {
+        self.find(value).map(|token| token.scope)
+    }
+
+    /// The name of a token value, if it is one of ours
+    pub fn name(&self, value: &str) -> Option<String> {
+        self.find(value).map(|token| token.name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.lock().unwrap().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_api_tokens() {
+        let temp_dir = TempDir::new().unwrap();
+        let tokens = ApiTokens::load(temp_dir.path()).unwrap();
+        assert!(tokens.is_empty());
+        assert!(tokens.create(" ", Permission::Read).is_err());
+
+        let (value, token) = tokens.create("backup script", Permission::Read).unwrap();
+        assert!(value.starts_with(TOKEN_PREFIX));
+        assert_eq!(tokens.scope(&value), Some(Permission::Read));
+        assert_eq!(tokens.scope("fmemo_guess"), None);
+        let stored = std::fs::read_to_string(tokens_path(temp_dir.path())).unwrap();
+        assert!(!stored.contains(&value));
+
+        // Stored tokens survive a restart
+        let reloaded = ApiTokens::load(temp_dir.path()).unwrap();
+        assert_eq!(reloaded.list(), vec![token.clone()]);
+        assert_eq!(reloaded.scope(&value), Some(Permission::Read));
+
+        assert_eq!(reloaded.revoke(&token.id).unwrap().name, "backup script");
+        assert_eq!(
+            reloaded.revoke(&token.id).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        assert_eq!(reloaded.scope(&value), None);
+        assert!(ApiTokens::load(temp_dir.path()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+//! Soft delete: memo files deleted through the API move to `.fmemo-trash/` below the
+//! root instead of disappearing, and can be restored from there.
+//!
+//! Each deleted file gets a directory named after the deletion time (its id), holding
+//! the file at its original relative path. Like other hidden directories, the trash is
+//! never scanned for memos.
+
+use std::path::{Path, PathBuf};
+
+/// Trash directory inside a root
+pub const TRASH_DIR: &str = ".fmemo-trash";
+
+/// A deleted file
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct TrashEntry {
+    /// Identifies the entry for restoring
+    pub id: String,
+    /// Original path relative to the root
+    pub path: String,
+    /// RFC 3339
+    pub deleted_at: String,
+}
+
+const ID_FORMAT: &str = "%Y%m%dT%H%M%S%3fZ";
+
+fn trash_dir(root: &Path) -> PathBuf {
+    root.join(TRASH_DIR)
+}
+
+/// Directory of an entry; `None` for ids that aren't a single plain name
+fn entry_dir(root: &Path, id: &str) -> Option<PathBuf> {
+    let valid = !id.is_empty() && !id.starts_with('.') && !id.contains(['/', '\\']);
+    valid.then(|| trash_dir(root).join(id))
+}
+
+/// The one file inside an entry directory, relative to it
+fn entry_file(dir: &Path) -> Option<String> {
+    let mut current = dir.to_path_buf();
+    let mut parts = Vec::new();
+    loop {
+        let entry = std::fs::read_dir(&current).ok()?.flatten().next()?;
+        let name = entry.file_name().to_str()?.to_string();
+        current = entry.path();
+        parts.push(name);
+        if current.is_file() {
+            return Some(parts.join("/"));
+        }
+    }
+}
+
+fn deleted_at(id: &str) -> String {
+    let stamp = id.split('-').next().unwrap_or(id);
+    chrono::NaiveDateTime::parse_from_str(stamp.trim_end_matches('Z'), "%Y%m%dT%H%M%S%3f")
+        .map(|time| time.and_utc().to_rfc3339())
+        .unwrap_or_default()
+}
+
+/// Move a memo file (relative to the root) into the trash
+pub fn trash_file(root: &Path, relative: &str) -> std::io::Result<TrashEntry> {
+    let source = root.join(relative);
+    if !source.is_file() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "File not found",
+        ));
+    }
+
+    let stamp = chrono::Utc::now().format(ID_FORMAT).to_string();
+    let mut id = stamp.clone();
+    let mut n = 1;
+    while trash_dir(root).join(&id).exists() {
+        id = format!("{}-{}", stamp, n);
+        n += 1;
+    }
+    let target = trash_dir(root).join(&id).join(relative);
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::rename(&source, &target)?;
+    Ok(TrashEntry {
+        deleted_at: deleted_at(&id),
+        id,
+        path: relative.to_string(),
+    })
+}
+
+/// Files in the trash, most recently deleted first
+pub fn list_trash(root: &Path) -> std::io::Result<Vec<TrashEntry>> {
+    let entries = match std::fs::read_dir(trash_dir(root)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut items = Vec::new();
+    for entry in entries.flatten() {
+        let Some(id) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if let Some(path) = entry_file(&entry.path()) {
+            items.push(TrashEntry {
+                deleted_at: deleted_at(&id),
+                id,
+                path,
+            });
+        }
+    }
+    items.sort_by(|a, b| b.id.cmp(&a.id));
+    Ok(items)
+}
+
+/// Move a file back to where it was deleted from and return that path. Fails with
+/// `AlreadyExists` when a file has been created there since.
+pub fn restore(root: &Path, id: &str) -> std::io::Result<String> {
+    let not_found = || std::io::Error::new(std::io::ErrorKind::NotFound, "Not in the trash");
+    let dir = entry_dir(root, id).ok_or_else(not_found)?;
+    let relative = entry_file(&dir).ok_or_else(not_found)?;
+    let target = root.join(&relative);
+    if target.exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", relative),
+        ));
+    }
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::rename(dir.join(&relative), &target)?;
+    std::fs::remove_dir_all(&dir)?;
+    Ok(relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{list_trash, restore, trash_file};
+    use crate::server::list_memo_files;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_trash_and_restore() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("notes")).unwrap();
+        fs::write(root.join("notes/a.fmemo"), "# A").unwrap();
+        fs::write(root.join("b.md"), "# B").unwrap();
+
+        let first = trash_file(root, "notes/a.fmemo").unwrap();
+        let second = trash_file(root, "b.md").unwrap();
+        assert!(!first.deleted_at.is_empty());
+        assert!(trash_file(root, "b.md").is_err());
+        assert!(list_memo_files(root).unwrap().is_empty());
+
+        let items = list_trash(root).unwrap();
+        assert_eq!(items, [second.clone(), first.clone()]);
+
+        fs::write(root.join("notes/a.fmemo"), "# New A").unwrap();
+        assert_eq!(
+            restore(root, &first.id).unwrap_err().kind(),
+            std::io::ErrorKind::AlreadyExists
+        );
+        assert_eq!(restore(root, &second.id).unwrap(), "b.md");
+        assert_eq!(fs::read_to_string(root.join("b.md")).unwrap(), "# B");
+        assert_eq!(list_trash(root).unwrap(), [first]);
+        assert!(restore(root, &second.id).is_err());
+        assert!(restore(root, "../notes").is_err());
+    }
+}
@@ -0,0 +1,251 @@
+//! Local accounts from `[users.<name>]` in `.fmemo/config.toml`, and their login sessions.
+//!
+//! Passwords are stored as `pbkdf2-sha256$<iterations>$<salt>$<hash>` (hex), made by
+//! `fmemo hash-password`. Logging in, with a password or through `[oidc]`, hands out a random
+//! session ID in the `fmemo_session` cookie; sessions live in memory, so a restart logs
+//! everyone out.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::{Permission, UserConfig};
+use crate::crypt::{from_hex, pbkdf2, random_bytes, to_hex};
+use crate::oidc::Oidc;
+use crate::tokens::ApiTokens;
+
+pub const SESSION_COOKIE: &str = "fmemo_session";
+/// How long a session lasts after logging in
+pub const SESSION_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// PBKDF2 rounds of new password hashes
+pub const PASSWORD_ITERATIONS: u32 = 600_000;
+
+const HASH_SCHEME: &str = "pbkdf2-sha256";
+
+/// A logged in user
+#[derive(Debug, Clone, serde::Serialize, PartialEq)]
+pub struct Session {
+    pub user: String,
+    pub permission: Permission,
+    #[serde(skip)]
+    expires: Instant,
+}
+
+/// The accounts of a root and who is logged in
+#[derive(Debug, Clone, Default)]
+pub struct Users {
+    accounts: Arc<BTreeMap<String, UserConfig>>,
+    oidc: Option<Oidc>,
+    tokens: ApiTokens,
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+}
+
+/// A password hash with `iterations` PBKDF2 rounds and a new salt
+pub fn hash_password_with(password: &str, iterations: u32) -> io::Result<String> {
+    let salt = random_bytes::<16>()?;
+    let mut hash = [0u8; 32];
+    pbkdf2(password.as_bytes(), &salt, iterations, &mut hash);
+    Ok(format!(
+        "{}${}${}${}",
+        HASH_SCHEME,
+        iterations,
+        to_hex(&salt),
+        to_hex(&hash)
+    ))
+}
+
+pub fn hash_password(password: &str) -> io::Result<String> {
+    hash_password_with(password, PASSWORD_ITERATIONS)
+}
+
+/// Whether `password` matches a hash from `hash_password`; `false` for malformed hashes
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    let parts: Vec<&str> = hash.split('$').collect();
+    let [HASH_SCHEME, iterations, salt, expected] = parts[..] else {
+        return false;
+    };
+    let (Ok(iterations), Some(salt), Some(expected)) =
+        (iterations.parse(), from_hex(salt), from_hex(expected))
+    else {
+        return false;
+    };
+    let mut actual = vec![0u8; expected.len()];
+    pbkdf2(password.as_bytes(), &salt, iterations, &mut actual);
+    // Compare in constant time
+    !expected.is_empty()
+        && actual
+            .iter()
+            .zip(&expected)
+            .fold(0u8, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// The session ID in a `Cookie` header
+pub fn session_id(cookie_header: &str) -> Option<&str> {
+    cookie_header.split(';').find_map(|pair| {
+        let (name, value) = pair.trim().split_once('=')?;
+        (name == SESSION_COOKIE && !value.is_empty()).then_some(value)
+    })
+}
+
+/// `Set-Cookie` value for a session; an empty ID clears the cookie
+pub fn session_cookie(id: &str) -> String {
+    let max_age = if id.is_empty() {
+        0
+    } else {
+        SESSION_TTL.as_secs()
+    };
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}",
+        SESSION_COOKIE, id, max_age
+    )
+}
+
+impl Users {
+    pub fn new(accounts: BTreeMap<String, UserConfig>) -> Self {
+        Self {
+            accounts: Arc::new(accounts),
+            oidc: None,
+            tokens: ApiTokens::default(),
+            sessions: Arc::default(),
+        }
+    }
+
+    /// Also let users log in through an OpenID Connect provider
+    pub fn oidc(mut self, oidc: Oidc) -> Self {
+        self.oidc = Some(oidc);
+        self
+    }
+
+    /// Also accept these API tokens
+    pub fn tokens(mut self, tokens: ApiTokens) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    /// The accounts and OIDC provider in the root's config, and its API tokens
+    pub fn load(root: &Path) -> io::Result<Self> {
+        let config = crate::config::load_config(root)?;
+        let users = Self::new(config.users).tokens(ApiTokens::load(root)?);
+        Ok(match config.oidc {
+            Some(oidc) => users.oidc(Oidc::new(oidc)),
+            None => users,
+        })
+    }
+
+    pub fn oidc_provider(&self) -> Option<&Oidc> {
+        self.oidc.as_ref()
+    }
+
+    pub fn api_tokens(&self) -> &ApiTokens {
+        &self.tokens
+    }
+
+    /// Whether there are accounts or a provider, so requests need a session
+    pub fn requires_login(&self) -> bool {
+        !self.accounts.is_empty() || self.oidc.is_some()
+    }
+
+    /// Check the password and start a session; returns its ID. `PermissionDenied` for an
+    /// unknown user or a wrong password alike. Takes a while on purpose.
+    pub fn login(&self, user: &str, password: &str) -> io::Result<(String, Session)> {
+        let account = self
+            .accounts
+            .get(user)
+            .filter(|account| verify_password(&account.password, password))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "Wrong user name or password",
+                )
+            })?;
+        self.start_session(user, account.permission)
+    }
+
+    /// A new session for a user who has proven who they are; returns its ID
+    pub fn start_session(
+        &self,
+        user: &str,
+        permission: Permission,
+    ) -> io::Result<(String, Session)> {
+        let id = to_hex(&random_bytes::<32>()?);
+        let session = Session {
+            user: user.to_string(),
+            permission,
+            expires: Instant::now() + SESSION_TTL,
+        };
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        sessions.retain(|_, session| session.expires > now);
+        sessions.insert(id.clone(), session.clone());
+        Ok((id, session))
+    }
+
+    /// The live session with this ID
+    pub fn session(&self, id: &str) -> Option<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get(id) {
+            Some(session) if session.expires > Instant::now() => Some(session.clone()),
+            Some(_) => {
+                sessions.remove(id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Who is making a request, for the audit log: the session's user, `token:<name>` for
+    /// an API token and `token` for any other bearer token, which got past the auth check
+    /// only by being the `--token`
+    pub fn actor(&self, session_id: Option<&str>, bearer: Option<&str>) -> Option<String> {
+        if let Some(session) = session_id.and_then(|id| self.session(id)) {
+            return Some(session.user);
+        }
+        let bearer = bearer?;
+        Some(match self.tokens.name(bearer) {
+            Some(name) => format!("token:{}", name),
+            None => "token".to_string(),
+        })
+    }
+
+    /// End a session; returns whether there was one
+    pub fn logout(&self, id: &str) -> bool {
+        self.sessions.lock().unwrap().remove(id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_users() {
+        let hash = hash_password_with("hunter2", 10).unwrap();
+        assert!(hash.starts_with("pbkdf2-sha256$10$"));
+        assert!(verify_password(&hash, "hunter2"));
+        assert!(!verify_password(&hash, "hunter3"));
+        assert!(!verify_password("plain text", "plain text"));
+
+        let users = Users::new(BTreeMap::from([(
+            "kai".to_string(),
+            UserConfig {
+                password: hash,
+                permission: Permission::Read,
+            },
+        )]));
+        assert!(users.login("kai", "wrong").is_err());
+        assert!(users.login("nobody", "hunter2").is_err());
+        let (id, session) = users.login("kai", "hunter2").unwrap();
+        assert_eq!(session.permission, Permission::Read);
+        assert_eq!(users.session(&id).unwrap().user, "kai");
+
+        let cookie = format!("theme=dark; {}={}", SESSION_COOKIE, id);
+        assert_eq!(session_id(&cookie), Some(id.as_str()));
+        assert!(session_cookie("").contains("Max-Age=0"));
+
+        assert!(users.logout(&id));
+        assert!(users.session(&id).is_none());
+    }
+}
@@ -0,0 +1,185 @@
+//! How often each memo file has been fetched through `GET /api/files/{path}`, to see
+//! which notes are actually used.
+//!
+//! Counts are kept in memory and written to `.fmemo/views.json` every
+//! `FLUSH_INTERVAL` by `start_flushing`, so a crash loses at most that much.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use crate::config::FMEMO_DIR;
+
+/// How often changed counts are written
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A file and how often it was fetched
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct FileViews {
+    /// Path relative to the root
+    pub path: String,
+    pub views: u64,
+}
+
+#[derive(Debug)]
+struct Counts {
+    root: PathBuf,
+    /// Loaded on first use
+    counts: Mutex<Option<BTreeMap<String, u64>>>,
+    /// Changed since the last flush
+    dirty: AtomicBool,
+}
+
+/// Handle to the view counts of a root; clones share the counts
+#[derive(Debug, Clone)]
+pub struct ViewCounter {
+    inner: Arc<Counts>,
+}
+
+pub fn views_path(root: &Path) -> PathBuf {
+    root.join(FMEMO_DIR).join("views.json")
+}
+
+impl Counts {
+    fn with_counts<R>(&self, f: impl FnOnce(&mut BTreeMap<String, u64>) -> R) -> R {
+        let mut guard = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        let counts = guard.get_or_insert_with(|| {
+            std::fs::read_to_string(views_path(&self.root))
+                .ok()
+                .and_then(|content| serde_json::from_str(&content).ok())
+                .unwrap_or_default()
+        });
+        f(counts)
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        let content = self.with_counts(|counts| serde_json::to_string(counts))?;
+        let written = std::fs::create_dir_all(self.root.join(FMEMO_DIR))
+            .and_then(|_| std::fs::write(views_path(&self.root), content));
+        if written.is_err() {
+            // Try again next time
+            self.dirty.store(true, Ordering::Release);
+        }
+        written
+    }
+}
+
+impl Drop for Counts {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            eprintln!("Failed to save view counts: {}", e);
+        }
+    }
+}
+
+impl ViewCounter {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            inner: Arc::new(Counts {
+                root: root.into(),
+                counts: Mutex::new(None),
+                dirty: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Count a fetch of a file (relative to the root)
+    pub fn record(&self, file: &str) {
+        self.inner
+            .with_counts(|counts| *counts.entry(file.to_string()).or_insert(0) += 1);
+        self.inner.dirty.store(true, Ordering::Release);
+    }
+
+    pub fn views(&self, file: &str) -> u64 {
+        self.inner
+            .with_counts(|counts| counts.get(file).copied().unwrap_or(0))
+    }
+
+    /// The `limit` most fetched files that still exist, most viewed first
+    pub fn popular(&self, limit: usize) -> Vec<FileViews> {
+        let mut files: Vec<FileViews> = self.inner.with_counts(|counts| {
+            counts
+                .iter()
+                .filter(|(file, _)| self.inner.root.join(file).is_file())
+                .map(|(file, views)| FileViews {
+                    path: file.clone(),
+                    views: *views,
+                })
+                .collect()
+        });
+        // Stable, so ties stay in path order
+        files.sort_by_key(|file| std::cmp::Reverse(file.views));
+        files.truncate(limit);
+        files
+    }
+
+    /// Write the counts if they changed since the last time
+    pub fn flush(&self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Flush every `interval` in a background thread, which stops when every handle is dropped
+    pub fn start_flushing(&self, interval: Duration) {
+        let counts: Weak<Counts> = Arc::downgrade(&self.inner);
+        std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(interval);
+                let Some(counts) = counts.upgrade() else {
+                    break;
+                };
+                if let Err(e) = counts.flush() {
+                    eprintln!("Failed to save view counts: {}", e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_view_counter() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::write(root.join("a.fmemo"), "# A").unwrap();
+        std::fs::write(root.join("b.fmemo"), "# B").unwrap();
+
+        let views = ViewCounter::new(root);
+        views.record("a.fmemo");
+        views.record("b.fmemo");
+        views.record("b.fmemo");
+        views.record("gone.fmemo");
+        assert_eq!(views.views("b.fmemo"), 2);
+        assert_eq!(
+            views.popular(10),
+            vec![
+                FileViews {
+                    path: "b.fmemo".to_string(),
+                    views: 2
+                },
+                FileViews {
+                    path: "a.fmemo".to_string(),
+                    views: 1
+                },
+            ]
+        );
+        assert_eq!(views.popular(1).len(), 1);
+
+        views.flush().unwrap();
+        assert!(views_path(root).exists());
+        // Counts are saved when the last handle goes, too
+        views.record("a.fmemo");
+        drop(views);
+        let views = ViewCounter::new(root);
+        assert_eq!(views.views("a.fmemo"), 2);
+        assert_eq!(views.views("b.fmemo"), 2);
+    }
+}
@@ -0,0 +1,519 @@
+//! One file system watcher shared by several watched directories.
+//!
+//! Each watch is a directory (a root or a subtree of one) with a topic and a handler. The
+//! hub only asks the OS to watch directories no other watch already covers, so nested or
+//! repeated watches don't produce the same event twice, and hands every event to the watches
+//! it belongs to, with the paths outside each watch left out.
+//!
+//! When the OS watcher fails (its event queue overflowed and events were dropped, reading
+//! events failed, the watch limit was hit or its thread died) the hub's `WatcherHealth`
+//! says so, and the hub keeps trying to replace it with a new one watching the same
+//! directories. Directories created while the watch limit is hit are left out silently by
+//! `notify`, so those can't be noticed.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// First wait before replacing a failed watcher; doubled after every failed attempt
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Identifies a watch for `WatcherHub::unwatch`
+pub type WatchId = usize;
+
+/// A change below a watched directory, as its handler sees it
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchEvent {
+    /// Topic the watch was registered with, telling handlers where messages go
+    pub topic: String,
+    /// The watched directory
+    pub root: PathBuf,
+    pub kind: EventKind,
+    /// Changed paths below `root`, without duplicates
+    pub paths: Vec<PathBuf>,
+}
+
+type Handler = Arc<Mutex<dyn FnMut(&WatchEvent) + Send>>;
+
+struct Watch {
+    id: WatchId,
+    root: PathBuf,
+    topic: String,
+    handler: Handler,
+}
+
+struct HubState {
+    /// `None` only while the hub is being created
+    watcher: Option<RecommendedWatcher>,
+    /// Counts the OS watchers, so the thread of a replaced one can't report the hub failed
+    generation: u64,
+    watches: Vec<Watch>,
+    next_id: WatchId,
+}
+
+/// How the hub's OS watcher is doing, for `GET /api/health`
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct WatcherStatus {
+    /// Whether a hub is watching at all
+    pub running: bool,
+    /// Changes may be missed: the OS watcher failed and isn't replaced yet
+    pub degraded: bool,
+    /// What went wrong, while degraded
+    pub error: Option<String>,
+    pub degraded_since: Option<DateTime<Utc>>,
+    /// How often a failed watcher was replaced
+    pub restarts: u32,
+}
+
+type StatusListener = Box<dyn Fn(&WatcherStatus) + Send>;
+
+/// The status of a hub, shared with whoever reports it; clones share it
+#[derive(Clone, Default)]
+pub struct WatcherHealth {
+    status: Arc<Mutex<WatcherStatus>>,
+    listeners: Arc<Mutex<Vec<StatusListener>>>,
+}
+
+impl std::fmt::Debug for WatcherHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("WatcherHealth")
+            .field(&self.status())
+            .finish()
+    }
+}
+
+impl WatcherHealth {
+    pub fn status(&self) -> WatcherStatus {
+        self.status
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Call `listener` whenever the watcher fails or is replaced
+    pub fn on_change(&self, listener: impl Fn(&WatcherStatus) + Send + 'static) {
+        self.listeners
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Box::new(listener));
+    }
+
+    /// Change the status; listeners hear about it when `f` returns true
+    fn update(&self, f: impl FnOnce(&mut WatcherStatus) -> bool) -> bool {
+        let status = {
+            let mut status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+            if !f(&mut status) {
+                return false;
+            }
+            status.clone()
+        };
+        for listener in self
+            .listeners
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+        {
+            listener(&status);
+        }
+        true
+    }
+}
+
+/// Handle to a set of watches; clones share them. The hub keeps running for the life of
+/// the process.
+#[derive(Clone)]
+pub struct WatcherHub {
+    state: Arc<Mutex<HubState>>,
+    health: WatcherHealth,
+}
+
+impl std::fmt::Debug for WatcherHub {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WatcherHub")
+            .field("watches", &self.watches())
+            .finish()
+    }
+}
+
+/// Directories the OS has to watch: those not inside another one
+fn covering(roots: impl IntoIterator<Item = PathBuf>) -> BTreeSet<PathBuf> {
+    let roots: BTreeSet<PathBuf> = roots.into_iter().collect();
+    roots
+        .iter()
+        .filter(|root| {
+            !roots
+                .iter()
+                .any(|other| other != *root && root.starts_with(other))
+        })
+        .cloned()
+        .collect()
+}
+
+/// The event as each watch it concerns sees it
+fn route(watches: &[(WatchId, &Path, &str)], event: &notify::Event) -> Vec<(WatchId, WatchEvent)> {
+    watches
+        .iter()
+        .filter_map(|(id, root, topic)| {
+            let mut seen = BTreeSet::new();
+            let paths: Vec<PathBuf> = event
+                .paths
+                .iter()
+                .filter(|path| path.starts_with(root) && seen.insert(*path))
+                .cloned()
+                .collect();
+            (!paths.is_empty()).then(|| {
+                let event = WatchEvent {
+                    topic: topic.to_string(),
+                    root: root.to_path_buf(),
+                    kind: event.kind,
+                    paths,
+                };
+                (*id, event)
+            })
+        })
+        .collect()
+}
+
+fn watch_error(e: notify::Error) -> std::io::Error {
+    std::io::Error::other(e)
+}
+
+impl WatcherHub {
+    /// Start the hub's watcher and the thread handing out its events
+    pub fn new() -> std::io::Result<Self> {
+        Self::with_health(WatcherHealth::default())
+    }
+
+    /// Like `new`, reporting the watcher's status to `health`
+    pub fn with_health(health: WatcherHealth) -> std::io::Result<Self> {
+        let hub = Self {
+            state: Arc::new(Mutex::new(HubState {
+                watcher: None,
+                generation: 0,
+                watches: Vec::new(),
+                next_id: 1,
+            })),
+            health,
+        };
+        let watcher = hub.start_os_watcher(0)?;
+        hub.lock().watcher = Some(watcher);
+        hub.health.update(|status| {
+            status.running = true;
+            true
+        });
+        Ok(hub)
+    }
+
+    pub fn health(&self) -> &WatcherHealth {
+        &self.health
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HubState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// A new OS watcher with a thread handing its events to the hub
+    fn start_os_watcher(&self, generation: u64) -> std::io::Result<RecommendedWatcher> {
+        let (tx, rx) = channel();
+        let watcher =
+            RecommendedWatcher::new(tx, notify::Config::default()).map_err(watch_error)?;
+        let dispatcher = self.clone();
+        std::thread::spawn(move || {
+            loop {
+                match rx.recv() {
+                    Ok(Ok(event)) if event.need_rescan() => dispatcher.degrade(
+                        generation,
+                        "Too many changes at once; some were dropped".to_string(),
+                    ),
+                    Ok(Ok(event)) => dispatcher.dispatch(&event),
+                    Ok(Err(e)) => dispatcher.degrade(generation, e.to_string()),
+                    // The watcher was dropped: replaced, or gone with the hub
+                    Err(_) => {
+                        dispatcher.degrade(generation, "Watcher stopped".to_string());
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(watcher)
+    }
+
+    /// Report the watcher of `generation` failed, and keep replacing it until that works
+    pub(crate) fn degrade(&self, generation: u64, error: String) {
+        if self.lock().generation != generation {
+            return;
+        }
+        eprintln!("Directory watcher failed: {}", error);
+        let first = self.health.update(|status| {
+            if status.degraded {
+                return false;
+            }
+            status.degraded = true;
+            status.error = Some(error);
+            status.degraded_since = Some(Utc::now());
+            true
+        });
+        if !first {
+            return;
+        }
+        let hub = self.clone();
+        std::thread::spawn(move || {
+            let mut interval = RETRY_INTERVAL;
+            while hub.health.status().degraded {
+                std::thread::sleep(interval);
+                match hub.reestablish() {
+                    Ok(()) => println!("Directory watcher re-established"),
+                    Err(e) => eprintln!("Failed to re-establish the directory watcher: {}", e),
+                }
+                interval = (interval * 2).min(MAX_RETRY_INTERVAL);
+            }
+        });
+    }
+
+    /// Replace the OS watcher with a new one watching the same directories. Changes while
+    /// the old one was failing are not replayed; the health listeners hear about the new one.
+    pub fn reestablish(&self) -> std::io::Result<()> {
+        {
+            let mut state = self.lock();
+            let generation = state.generation + 1;
+            let mut watcher = self.start_os_watcher(generation)?;
+            let roots = covering(state.watches.iter().map(|watch| watch.root.clone()));
+            Self::sync_os_watches(&mut watcher, &BTreeSet::new(), &roots).map_err(watch_error)?;
+            // The old watcher's thread sees the new generation once it stops
+            state.generation = generation;
+            state.watcher = Some(watcher);
+        }
+        self.health.update(|status| {
+            status.degraded = false;
+            status.error = None;
+            status.degraded_since = None;
+            status.restarts += 1;
+            true
+        });
+        Ok(())
+    }
+
+    /// Hand an event to the watches it concerns. Handlers run on the hub's thread, one
+    /// event at a time, without the hub locked (so they may add or remove watches).
+    pub fn dispatch(&self, event: &notify::Event) {
+        let deliveries: Vec<(Handler, WatchEvent)> = {
+            let state = self.lock();
+            let watches: Vec<(WatchId, &Path, &str)> = state
+                .watches
+                .iter()
+                .map(|watch| (watch.id, watch.root.as_path(), watch.topic.as_str()))
+                .collect();
+            route(&watches, event)
+                .into_iter()
+                .filter_map(|(id, event)| {
+                    let watch = state.watches.iter().find(|watch| watch.id == id)?;
+                    Some((watch.handler.clone(), event))
+                })
+                .collect()
+        };
+        for (handler, event) in deliveries {
+            (handler.lock().unwrap_or_else(|e| e.into_inner()))(&event);
+        }
+    }
+
+    /// Move the OS watches from the directories in `before` to those in `after`
+    fn sync_os_watches(
+        watcher: &mut RecommendedWatcher,
+        before: &BTreeSet<PathBuf>,
+        after: &BTreeSet<PathBuf>,
+    ) -> notify::Result<()> {
+        for root in after.difference(before) {
+            watcher.watch(root, RecursiveMode::Recursive)?;
+        }
+        for root in before.difference(after) {
+            // The directory may be gone already, which ends its OS watch anyway
+            let _ = watcher.unwatch(root);
+        }
+        Ok(())
+    }
+
+    /// Watch a directory recursively, handing its events to `handler` with `topic`
+    pub fn watch<P, F>(
+        &self,
+        root: P,
+        topic: impl Into<String>,
+        handler: F,
+    ) -> std::io::Result<WatchId>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&WatchEvent) + Send + 'static,
+    {
+        let root = root.as_ref().to_path_buf();
+        let mut state = self.lock();
+        let before = covering(state.watches.iter().map(|watch| watch.root.clone()));
+        let after = covering(
+            state
+                .watches
+                .iter()
+                .map(|watch| watch.root.clone())
+                .chain([root.clone()]),
+        );
+        let synced = match state.watcher.as_mut() {
+            Some(watcher) => Self::sync_os_watches(watcher, &before, &after),
+            None => Ok(()),
+        };
+        // Out of OS watches: keep the watch, so it works once the limit is raised
+        let limit_hit =
+            matches!(&synced, Err(e) if matches!(e.kind, notify::ErrorKind::MaxFilesWatch));
+        if !limit_hit {
+            synced.map_err(watch_error)?;
+        }
+        let id = state.next_id;
+        state.next_id += 1;
+        state.watches.push(Watch {
+            id,
+            root,
+            topic: topic.into(),
+            handler: Arc::new(Mutex::new(handler)),
+        });
+        let generation = state.generation;
+        drop(state);
+        if limit_hit {
+            self.degrade(
+                generation,
+                "The OS limit on watched directories is reached".to_string(),
+            );
+        }
+        Ok(id)
+    }
+
+    /// Stop a watch; `false` when there is none with this id
+    pub fn unwatch(&self, id: WatchId) -> bool {
+        let mut state = self.lock();
+        let before = covering(state.watches.iter().map(|watch| watch.root.clone()));
+        let count = state.watches.len();
+        state.watches.retain(|watch| watch.id != id);
+        if state.watches.len() == count {
+            return false;
+        }
+        let after = covering(state.watches.iter().map(|watch| watch.root.clone()));
+        if let Some(watcher) = state.watcher.as_mut()
+            && let Err(e) = Self::sync_os_watches(watcher, &before, &after)
+        {
+            eprintln!("Failed to update directory watches: {}", e);
+        }
+        true
+    }
+
+    /// Current watches: id, directory and topic
+    pub fn watches(&self) -> Vec<(WatchId, PathBuf, String)> {
+        self.lock()
+            .watches
+            .iter()
+            .map(|watch| (watch.id, watch.root.clone(), watch.topic.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WatcherHub, covering, route};
+    use notify::EventKind;
+    use notify::event::{CreateKind, Event};
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_covering() {
+        let roots = ["/notes/work", "/notes", "/other", "/notes"].map(PathBuf::from);
+        assert_eq!(
+            covering(roots).into_iter().collect::<Vec<_>>(),
+            ["/notes", "/other"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_route() {
+        let event = Event::new(EventKind::Create(CreateKind::File))
+            .add_path(PathBuf::from("/notes/work/a.md"))
+            .add_path(PathBuf::from("/notes/work/a.md"))
+            .add_path(PathBuf::from("/notes/b.md"));
+        let watches = [
+            (1, Path::new("/notes"), "root"),
+            (2, Path::new("/notes/work"), "work"),
+            (3, Path::new("/elsewhere"), "other"),
+        ];
+        let routed = route(&watches, &event);
+        assert_eq!(routed.len(), 2);
+        assert_eq!(routed[0].1.paths.len(), 2);
+        assert_eq!(routed[1].0, 2);
+        assert_eq!(routed[1].1.topic, "work");
+        assert_eq!(routed[1].1.paths, [PathBuf::from("/notes/work/a.md")]);
+    }
+
+    #[test]
+    fn test_hub_dispatch() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let sub = temp_dir.path().join("sub");
+        std::fs::create_dir(&sub).unwrap();
+        let hub = WatcherHub::new().unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let record = |seen: &Arc<Mutex<Vec<String>>>| {
+            let seen = seen.clone();
+            move |event: &super::WatchEvent| seen.lock().unwrap().push(event.topic.clone())
+        };
+        let root = hub.watch(temp_dir.path(), "root", record(&seen)).unwrap();
+        hub.watch(&sub, "sub", record(&seen)).unwrap();
+        assert_eq!(hub.watches().len(), 2);
+
+        let event = Event::new(EventKind::Create(CreateKind::File)).add_path(sub.join("a.md"));
+        hub.dispatch(&event);
+        assert_eq!(*seen.lock().unwrap(), ["root", "sub"]);
+
+        assert!(hub.unwatch(root));
+        assert!(!hub.unwatch(root));
+        hub.dispatch(&event);
+        assert_eq!(*seen.lock().unwrap(), ["root", "sub", "sub"]);
+    }
+
+    #[test]
+    fn test_hub_reestablish() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let hub = WatcherHub::new().unwrap();
+        let statuses = Arc::new(Mutex::new(Vec::new()));
+        let recorded = statuses.clone();
+        hub.health()
+            .on_change(move |status| recorded.lock().unwrap().push(status.clone()));
+        let (tx, rx) = std::sync::mpsc::channel();
+        hub.watch(temp_dir.path(), "root", move |event: &super::WatchEvent| {
+            let _ = tx.send(event.paths.clone());
+        })
+        .unwrap();
+        assert!(hub.health().status().running);
+        assert!(!hub.health().status().degraded);
+
+        hub.degrade(0, "queue overflow".to_string());
+        // Reported once however often it fails
+        hub.degrade(0, "again".to_string());
+        let status = hub.health().status();
+        assert!(status.degraded);
+        assert_eq!(status.error.as_deref(), Some("queue overflow"));
+        assert!(status.degraded_since.is_some());
+
+        hub.reestablish().unwrap();
+        let status = hub.health().status();
+        assert!(!status.degraded && status.error.is_none());
+        assert_eq!(status.restarts, 1);
+        assert_eq!(statuses.lock().unwrap().len(), 2);
+        // The replaced watcher stopping doesn't count as a failure
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!hub.health().status().degraded);
+
+        // The new watcher sees changes
+        let file = temp_dir.path().join("a.md");
+        std::fs::write(&file, "# A").unwrap();
+        let paths = rx.recv_timeout(std::time::Duration::from_secs(2)).unwrap();
+        assert!(paths.iter().any(|path| path.ends_with("a.md")));
+    }
+}
@@ -0,0 +1,198 @@
+//! Outbound webhooks: the watcher's change events POSTed as JSON to configured URLs.
+//!
+//! The body is the same message WebSocket clients receive. Requests carry
+//! `X-Fmemo-Event: <type>` and, with a secret, `X-Fmemo-Signature: sha256=<hex HMAC of the body>`.
+//! Events about encrypted memos carry only their type and paths.
+
+use std::path::Path;
+use std::sync::mpsc::{Sender, channel};
+use std::thread;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config::WebhookConfig;
+use crate::schema::WsServerMessage;
+
+/// `sha256=<hex>` HMAC-SHA256 signature of `body`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!(
+        "sha256={}",
+        crate::crypt::to_hex(&mac.finalize().into_bytes())
+    )
+}
+
+/// The webhook event for a message about the memo file `path`; for an encrypted memo only its
+/// type and paths, so decrypted memos never leave the machine
+pub fn file_event(path: &Path, message: &WsServerMessage) -> Option<serde_json::Value> {
+    let mut event = serde_json::to_value(message).ok()?;
+    if crate::crypt::is_encrypted(path)
+        && let Some(fields) = event.as_object_mut()
+    {
+        fields.retain(|key, _| matches!(key.as_str(), "type" | "file_path" | "path"));
+    }
+    Some(event)
+}
+
+/// Whether a webhook wants events of this type
+fn wants(webhook: &WebhookConfig, event_type: &str) -> bool {
+    webhook.events.is_empty() || webhook.events.iter().any(|event| event == event_type)
+}
+
+fn deliver(agent: &ureq::Agent, webhook: &WebhookConfig, event_type: &str, body: &str) {
+    let mut request = agent
+        .post(&webhook.url)
+        .set("Content-Type", "application/json")
+        .set("X-Fmemo-Event", event_type);
+    if let Some(secret) = &webhook.secret {
+        request = request.set("X-Fmemo-Signature", &sign(secret, body.as_bytes()));
+    }
+    if let Err(e) = request.send_string(body) {
+        eprintln!("Webhook {} failed: {}", webhook.url, e);
+    }
+}
+
+/// Sends events to the configured webhooks from a background thread, in order,
+/// so slow endpoints never hold up the watcher
+#[derive(Debug, Clone)]
+pub struct WebhookDispatcher {
+    sender: Sender<serde_json::Value>,
+}
+
+impl WebhookDispatcher {
+    /// Start the delivery thread; `None` without webhooks
+    pub fn start(webhooks: Vec<WebhookConfig>) -> Option<Self> {
+        if webhooks.is_empty() {
+            return None;
+        }
+        let (sender, receiver) = channel::<serde_json::Value>();
+        thread::spawn(move || {
+            let agent = ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(10))
+                .build();
+            for event in receiver {
+                let event_type = event["type"].as_str().unwrap_or_default();
+                let body = event.to_string();
+                for webhook in webhooks.iter().filter(|webhook| wants(webhook, event_type)) {
+                    deliver(&agent, webhook, event_type, &body);
+                }
+            }
+        });
+        Some(Self { sender })
+    }
+
+    /// Queue a watcher message (with a `type` field) for delivery
+    pub fn dispatch(&self, event: &serde_json::Value) {
+        // Only fails once the delivery thread is gone, and then there's nobody to tell
+        let _ = self.sender.send(event.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WebhookDispatcher, file_event, sign};
+    use crate::config::WebhookConfig;
+    use crate::schema::WsServerMessage;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            sign("It's a Secret to Everybody", b"Hello, World!"),
+            "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17"
+        );
+    }
+
+    #[test]
+    fn test_encrypted_file_events_carry_no_content() {
+        let message = |file_path: &str| WsServerMessage::FileUpdated {
+            file_path: file_path.to_string(),
+            path: Some(file_path.to_string()),
+            memos: crate::parser::parse_memo("# Secret plan\nbody"),
+            warnings: Vec::new(),
+            issues: Vec::new(),
+        };
+        let event = file_event(Path::new("/r/s.fmemox"), &message("/r/s.fmemox")).unwrap();
+        assert_eq!(
+            event,
+            serde_json::json!({"type": "file_updated", "file_path": "/r/s.fmemox", "path": "/r/s.fmemox"})
+        );
+        let event = file_event(Path::new("/r/a.fmemo"), &message("/r/a.fmemo")).unwrap();
+        assert_eq!(event["memos"][0]["title"], "Secret plan");
+    }
+
+    /// Accept one request and return its lowercased headers and body
+    fn receive(listener: &TcpListener) -> (Vec<String>, String) {
+        let (stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let line = line.trim_end().to_lowercase();
+            if line.is_empty() {
+                break;
+            }
+            headers.push(line);
+        }
+        let length: usize = headers
+            .iter()
+            .find_map(|header| header.strip_prefix("content-length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        (headers, String::from_utf8(body).unwrap())
+    }
+
+    #[test]
+    fn test_dispatch_posts_signed_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let dispatcher = WebhookDispatcher::start(vec![
+            WebhookConfig {
+                url: url.clone(),
+                secret: Some("secret".to_string()),
+                events: vec!["file_deleted".to_string()],
+            },
+            WebhookConfig {
+                url,
+                secret: None,
+                events: Vec::new(),
+            },
+        ])
+        .unwrap();
+        assert!(WebhookDispatcher::start(Vec::new()).is_none());
+
+        dispatcher.dispatch(&serde_json::json!({"type": "file_updated", "path": "a.fmemo"}));
+        dispatcher.dispatch(&serde_json::json!({"type": "file_deleted", "path": "a.fmemo"}));
+
+        // Only the unfiltered webhook gets file_updated
+        let (headers, body) = receive(&listener);
+        assert!(headers.contains(&"x-fmemo-event: file_updated".to_string()));
+        assert!(!headers.iter().any(|h| h.starts_with("x-fmemo-signature")));
+        assert_eq!(body, r#"{"path":"a.fmemo","type":"file_updated"}"#);
+
+        let (headers, body) = receive(&listener);
+        assert!(headers.contains(&"x-fmemo-event: file_deleted".to_string()));
+        let signature = format!("x-fmemo-signature: {}", sign("secret", body.as_bytes()));
+        assert!(headers.contains(&signature));
+        let (headers, _) = receive(&listener);
+        assert!(!headers.iter().any(|h| h.starts_with("x-fmemo-signature")));
+    }
+}
@@ -0,0 +1,233 @@
+//! Binary encodings of WebSocket messages.
+//!
+//! Clients connecting to `/ws?format=msgpack` or `/ws?format=cbor` get every message as a
+//! binary frame in MessagePack or CBOR instead of JSON text, and may send theirs the same way.
+//! The messages themselves are the same objects as in JSON (see `crate::schema::WsServerMessage`),
+//! serialized by `rmp-serde` and `ciborium`.
+//!
+//! `?compress=deflate` additionally compresses every message the server sends, in any format,
+//! into a binary frame holding a zlib stream. warp's WebSocket doesn't implement the
+//! permessage-deflate extension, so this is done by the application instead.
+
+use std::io;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// Nesting depth a decoded message may have; memo trees stay far below it
+const MAX_DEPTH: usize = 128;
+
+/// Encoding of a WebSocket connection's messages, serialized by its `?format=` name
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WsFormat {
+    /// JSON in text frames
+    #[default]
+    Json,
+    /// MessagePack in binary frames
+    #[serde(rename = "msgpack")]
+    MessagePack,
+    /// CBOR (RFC 8949) in binary frames
+    Cbor,
+}
+
+impl WsFormat {
+    /// The format named in `?format=...`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            "msgpack" | "messagepack" => Some(Self::MessagePack),
+            "cbor" => Some(Self::Cbor),
+            _ => None,
+        }
+    }
+
+    /// A message as the frame to send
+    pub fn encode<T: Serialize + ?Sized>(self, message: &T) -> io::Result<warp::ws::Message> {
+        match self {
+            Self::Json => serde_json::to_string(message)
+                .map(warp::ws::Message::text)
+                .map_err(io::Error::other),
+            // Structs as maps, so messages have the same shape as in JSON
+            Self::MessagePack => rmp_serde::to_vec_named(message)
+                .map(warp::ws::Message::binary)
+                .map_err(io::Error::other),
+            Self::Cbor => {
+                let mut out = Vec::new();
+                ciborium::into_writer(message, &mut out).map_err(io::Error::other)?;
+                Ok(warp::ws::Message::binary(out))
+            }
+        }
+    }
+
+    /// A frame received from the client; text frames are always JSON
+    pub fn decode<T: DeserializeOwned>(self, message: &warp::ws::Message) -> io::Result<T> {
+        if let Ok(text) = message.to_str() {
+            return serde_json::from_str(text).map_err(invalid);
+        }
+        if !message.is_binary() {
+            return Err(invalid("Not a data frame"));
+        }
+        let mut input = message.as_bytes();
+        let value = match self {
+            Self::Json => return Err(invalid("Binary frames need ?format=msgpack or cbor")),
+            Self::MessagePack => {
+                let mut deserializer = rmp_serde::Deserializer::new(&mut input);
+                deserializer.set_max_depth(MAX_DEPTH);
+                T::deserialize(&mut deserializer).map_err(invalid)?
+            }
+            Self::Cbor => ciborium::de::from_reader_with_recursion_limit(&mut input, MAX_DEPTH)
+                .map_err(invalid)?,
+        };
+        if !input.is_empty() {
+            return Err(invalid("Trailing bytes after the message"));
+        }
+        Ok(value)
+    }
+}
+
+/// Compression of the messages the server sends, serialized by its `?compress=` name
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WsCompression {
+    #[default]
+    None,
+    /// zlib (RFC 1950), as read by `DecompressionStream("deflate")` in browsers
+    Deflate,
+}
+
+impl WsCompression {
+    /// The compression named in `?compress=...`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "" | "none" => Some(Self::None),
+            "deflate" => Some(Self::Deflate),
+            _ => None,
+        }
+    }
+
+    /// Compress a frame from `WsFormat::encode`
+    pub fn compress(self, frame: warp::ws::Message) -> warp::ws::Message {
+        use std::io::Write;
+
+        match self {
+            Self::None => frame,
+            Self::Deflate => {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                match encoder
+                    .write_all(frame.as_bytes())
+                    .and_then(|_| encoder.finish())
+                {
+                    Ok(bytes) => warp::ws::Message::binary(bytes),
+                    Err(_) => frame,
+                }
+            }
+        }
+    }
+}
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WsCompression, WsFormat};
+    use crate::schema::{WsClientMessage, WsServerMessage};
+    use serde_json::{Value, json};
+
+    #[test]
+    fn test_round_trip() {
+        let value = json!({
+            "type": "file_updated",
+            "memos": [{"title": "x".repeat(40), "level": 2, "children": []}],
+            "numbers": [0, 127, 128, 300, 70000, 5_000_000_000u64, -1, -32, -33, -200, -40000, -3_000_000_000i64, 1.5],
+            "flags": [true, false, null],
+            "wide": (0..20).collect::<Vec<_>>(),
+        });
+        for format in [WsFormat::Json, WsFormat::MessagePack, WsFormat::Cbor] {
+            let message = format.encode(&value).unwrap();
+            assert_eq!(message.is_binary(), format != WsFormat::Json);
+            assert_eq!(
+                format.decode::<Value>(&message).unwrap(),
+                value,
+                "{:?}",
+                format
+            );
+
+            // Messages are encoded as they are, the same objects as in JSON
+            let server = WsServerMessage::FileUpdated {
+                file_path: "/r/a.fmemo".to_string(),
+                path: None,
+                memos: crate::parser::parse_memo("# A\n## B\ntext"),
+                warnings: Vec::new(),
+                issues: Vec::new(),
+            };
+            let message = format.encode(&server).unwrap();
+            assert_eq!(
+                format.decode::<Value>(&message).unwrap(),
+                serde_json::to_value(&server).unwrap(),
+                "{:?}",
+                format
+            );
+            let client = WsClientMessage::Subscribe {
+                dir: "projects".to_string(),
+            };
+            let message = format.encode(&client).unwrap();
+            assert_eq!(format.decode::<WsClientMessage>(&message).unwrap(), client);
+        }
+    }
+
+    #[test]
+    fn test_known_bytes() {
+        let value = json!({"a": [1, -2, "b"]});
+        let message = WsFormat::MessagePack.encode(&value).unwrap();
+        assert_eq!(
+            message.as_bytes(),
+            [0x81, 0xa1, b'a', 0x93, 0x01, 0xfe, 0xa1, b'b']
+        );
+        let message = WsFormat::Cbor.encode(&value).unwrap();
+        assert_eq!(
+            message.as_bytes(),
+            [0xa1, 0x61, b'a', 0x83, 0x01, 0x21, 0x61, b'b']
+        );
+        // Half-precision 1.5 from another encoder
+        let message = warp::ws::Message::binary(vec![0xf9, 0x3e, 0x00]);
+        assert_eq!(
+            WsFormat::Cbor.decode::<Value>(&message).unwrap(),
+            json!(1.5)
+        );
+
+        for bytes in [vec![0x92, 0x01], vec![0xc1], vec![0x01, 0x02]] {
+            let message = warp::ws::Message::binary(bytes);
+            assert!(WsFormat::MessagePack.decode::<Value>(&message).is_err());
+        }
+        // Deeply nested arrays are refused rather than overflowing the stack
+        let message = warp::ws::Message::binary(vec![0x91; 100_000]);
+        assert!(WsFormat::MessagePack.decode::<Value>(&message).is_err());
+        let message = warp::ws::Message::binary(vec![0x81; 100_000]);
+        assert!(WsFormat::Cbor.decode::<Value>(&message).is_err());
+        assert!(WsFormat::Json.decode::<Value>(&message).is_err());
+        assert_eq!(WsFormat::from_name("MsgPack"), Some(WsFormat::MessagePack));
+        assert_eq!(WsFormat::from_name("xml"), None);
+    }
+
+    #[test]
+    fn test_deflate() {
+        use std::io::Read;
+
+        let value = json!({"type": "file_updated", "content": "lorem ipsum ".repeat(500)});
+        let plain = WsFormat::Json.encode(&value).unwrap();
+        assert_eq!(WsCompression::None.compress(plain.clone()), plain);
+        let compressed = WsCompression::Deflate.compress(plain.clone());
+        assert!(compressed.is_binary());
+        assert!(compressed.as_bytes().len() < plain.as_bytes().len() / 10);
+        let mut text = String::new();
+        flate2::read::ZlibDecoder::new(compressed.as_bytes())
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text.as_bytes(), plain.as_bytes());
+        assert_eq!(WsCompression::from_name("gzip"), None);
+    }
+}